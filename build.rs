@@ -32,10 +32,11 @@ fn main() {
 /// `src/embedded_programs.rs` includes. This lets a deployed binary run bundled programs (e.g. the
 /// `netmap` discovery program) with no external .wasm file.
 ///
-/// Compilation uses zig, exactly like `scripts/compile-example-programs.py`. If zig is missing or a
-/// program fails to build we emit a `cargo:warning` and simply omit that entry (an empty/partial
-/// table), so a plain `cargo build` never hard-fails on account of embedding — callers just fall
-/// back to `--program` / the on-disk example.
+/// C programs compile with zig, exactly like `scripts/compile-example-programs.py`, and Rust ones
+/// (`<name>.rs`, built against the guest crate) with cargo. If a toolchain is missing or a program
+/// fails to build we emit a `cargo:warning` and simply omit that entry (an empty/partial table), so a
+/// plain `cargo build` never hard-fails on account of embedding — callers just fall back to
+/// `--program` / the on-disk example.
 fn embed_example_programs() {
     let manifest_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
     let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
@@ -49,17 +50,24 @@ fn embed_example_programs() {
 
     let mut entries: Vec<(String, PathBuf)> = Vec::new();
     for name in &names {
-        let source = examples_dir.join(format!("{name}.c"));
+        // A C program, or a Rust one written against the guest crate.
+        let c_source = examples_dir.join(format!("{name}.c"));
+        let rust_source = examples_dir.join(format!("{name}.rs"));
         // Rebuild whenever the source changes.
-        println!("cargo:rerun-if-changed={}", source.display());
-
-        if !source.exists() {
-            println!("cargo:warning=embedded.list names '{name}' but {} does not exist; skipping", source.display());
-            continue;
-        }
+        println!("cargo:rerun-if-changed={}", c_source.display());
+        println!("cargo:rerun-if-changed={}", rust_source.display());
 
         let out_wasm = out_dir.join(format!("{name}.wasm"));
-        match compile_one(&source, &out_wasm) {
+        let compiled = if c_source.exists() {
+            compile_one(&c_source, &out_wasm)
+        } else if rust_source.exists() {
+            println!("cargo:rerun-if-changed={}", manifest_dir.join("guest").display());
+            compile_rust_example(&manifest_dir, name, &out_wasm)
+        } else {
+            println!("cargo:warning=embedded.list names '{name}' but neither {} nor {} exists; skipping", c_source.display(), rust_source.display());
+            continue;
+        };
+        match compiled {
             Ok(()) => entries.push((name.clone(), out_wasm)),
             Err(e) => println!("cargo:warning=could not embed program '{name}': {e}. `netmap` will fall back to --program / the on-disk example."),
         }
@@ -116,6 +124,36 @@ fn compile_one(source: &Path, out_wasm: &Path) -> Result<(), String> {
     Ok(())
 }
 
+/// Compile the Rust example `name` (an `[[example]]` of guest/Cargo.toml) for wasm32-wasip1 with the
+/// workspace's `guest` profile, into its own target dir so it doesn't wait on the build running us.
+/// Needs the target installed (`rustup target add wasm32-wasip1`).
+fn compile_rust_example(manifest_dir: &Path, name: &str, out_wasm: &Path) -> Result<(), String> {
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let target_dir = out_wasm.with_file_name("guest-target");
+    let output = std::process::Command::new(&cargo)
+        .args(["build", "--quiet", "--example", name, "--target", "wasm32-wasip1", "--profile", "guest"])
+        .arg("--manifest-path").arg(manifest_dir.join("guest").join("Cargo.toml"))
+        .arg("--target-dir").arg(&target_dir)
+        // Flags and wrappers meant for the host build (e.g. clippy's) would apply to this one too.
+        .env_remove("CARGO_ENCODED_RUSTFLAGS")
+        .env_remove("RUSTFLAGS")
+        .env_remove("RUSTC_WRAPPER")
+        .env_remove("RUSTC_WORKSPACE_WRAPPER")
+        .output()
+        .map_err(|e| format!("failed to run '{cargo}': {e}"))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.contains("E0463") {
+            return Err("the wasm32-wasip1 target is not installed (rustup target add wasm32-wasip1)".to_string());
+        }
+        return Err(format!("cargo exited with {}: {}", output.status, stderr.trim().replace('\n', " ")));
+    }
+    let built = target_dir.join("wasm32-wasip1").join("guest").join("examples").join(format!("{name}.wasm"));
+    std::fs::copy(&built, out_wasm).map_err(|e| format!("{}: {e}", built.display()))?;
+    Ok(())
+}
+
 /// The default compile command, overridable by a `// COMPILE: ...` line in the source. Uses simple
 /// whitespace splitting (the example commands contain no quoted spaces).
 fn compile_command_for(source: &Path) -> Vec<String> {
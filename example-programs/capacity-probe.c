@@ -0,0 +1,123 @@
+// COMPILE: zig cc -Os -target wasm32-wasi -mexec-model=reactor THIS_FILE -o OUT_FILE
+
+// capacity-probe: how busy is this node? The first half of `weverywhere submit`.
+//
+// `submit` sends this program to every node it can reach directly, ranks them by the records they
+// return, and then sends the real job to the best ones. Like network-map, observation is just a
+// program: the daemon only exposes the raw figures through the host::load_* imports, and what a
+// probe measures (or how it weighs it) can change without a protocol change.
+//
+// Output is a CBOR map with small integer keys. Keys 1, 2 and 5 match network-map's record (see
+// crate::discovery::record_keys) so the client verifies and addresses the node the same way; 6-8
+// are crate::scheduler::capacity_keys and hold the CBOR items the load imports returned, nested as-is:
+//   1 => attestation (CBOR byte string)   2 => trusts_caller (uint 0/1)
+//   5 => node addr (text "ip:port")       6 => host::load_cpu map
+//   7 => host::load_memory map            8 => host::load_pids array
+//
+// Freestanding (no libc/stdio) so the module stays tiny; the record must fit one UDP datagram.
+
+// --- weverywhere host imports (module "host") -------------------------------------------------
+
+// 1 if the server running us trusts the caller's key, else 0.
+__attribute__((import_module("host"), import_name("trusts_me")))
+int host_trusts_me(void);
+
+// Write this node's signed CBOR attestation into [ptr, ptr+cap); returns bytes written, or -1 if
+// this node has no identity key.
+__attribute__((import_module("host"), import_name("signed_attestation")))
+int host_signed_attestation(char* ptr, int cap);
+
+// Write this node's OWN socket address ("ip:port", as the caller reached it) into [ptr, ptr+cap);
+// returns bytes written, or -1 if the node could not determine its address.
+__attribute__((import_module("host"), import_name("node_addr")))
+int host_node_addr(char* ptr, int cap);
+
+// Write a CBOR map {1: cores, 2: busy per-mille, 3: 1-minute load x100}; returns bytes written.
+// Samples for ~200ms.
+__attribute__((import_module("host"), import_name("load_cpu")))
+int host_load_cpu(char* ptr, int cap);
+
+// Write a CBOR map {1: total bytes, 2: available bytes}; returns bytes written.
+__attribute__((import_module("host"), import_name("load_memory")))
+int host_load_memory(char* ptr, int cap);
+
+// Write a CBOR array of the PIDs the executor is already running; returns bytes written.
+__attribute__((import_module("host"), import_name("load_pids")))
+int host_load_pids(char* ptr, int cap);
+
+// Hand our finished CBOR record to the daemon (sent to the caller as a BasicReturnMap).
+__attribute__((import_module("host"), import_name("return_map")))
+int host_return_map(const char* ptr, int len);
+
+// --- tiny freestanding CBOR builder -----------------------------------------------------------
+
+static unsigned char out[16384];
+static int out_len = 0;
+
+static void put_byte(unsigned char b) {
+  if (out_len < (int)sizeof(out)) out[out_len++] = b;
+}
+
+static void put_bytes(const unsigned char* src, int n) {
+  for (int i = 0; i < n && out_len < (int)sizeof(out); i++) out[out_len++] = src[i];
+}
+
+// Write a CBOR type header: major type (0=uint, 2=bytes, 3=text, 5=map) + argument value.
+static void cbor_head(int major, unsigned long long val) {
+  unsigned char mt = (unsigned char)(major << 5);
+  if (val < 24) {
+    put_byte(mt | (unsigned char)val);
+  } else if (val < 256ULL) {
+    put_byte(mt | 24); put_byte((unsigned char)val);
+  } else if (val < 65536ULL) {
+    put_byte(mt | 25); put_byte((unsigned char)(val >> 8)); put_byte((unsigned char)val);
+  } else if (val < 4294967296ULL) {
+    put_byte(mt | 26);
+    put_byte((unsigned char)(val >> 24)); put_byte((unsigned char)(val >> 16));
+    put_byte((unsigned char)(val >> 8));  put_byte((unsigned char)val);
+  } else {
+    put_byte(mt | 27);
+    for (int s = 56; s >= 0; s -= 8) put_byte((unsigned char)(val >> s));
+  }
+}
+
+static void cbor_uint(unsigned long long v) { cbor_head(0, v); }
+static void cbor_bytes(const unsigned char* b, int n) { cbor_head(2, (unsigned long long)n); put_bytes(b, n); }
+static void cbor_text(const unsigned char* b, int n) { cbor_head(3, (unsigned long long)n); put_bytes(b, n); }
+
+// Splice an already-encoded CBOR item in as a map value, or an empty map if the host gave nothing.
+static void cbor_item_or_empty_map(const unsigned char* b, int n) {
+  if (n > 0) put_bytes(b, n); else cbor_head(5, 0);
+}
+
+// Scratch buffers for host-provided bytes.
+static unsigned char attest[1024];
+static unsigned char node_addr[64];
+static unsigned char cpu[64];
+static unsigned char mem[64];
+static unsigned char pids[4096];
+
+__attribute__((export_name("_start")))
+void _start(void) {
+  // 1. Gather this node's identity and load.
+  int alen = host_signed_attestation((char*)attest, (int)sizeof(attest));
+  if (alen < 0) alen = 0;                       // no identity key: empty attestation
+  int trusts = host_trusts_me() ? 1 : 0;
+  int nlen = host_node_addr((char*)node_addr, (int)sizeof(node_addr));
+  if (nlen < 0) nlen = 0;                        // address unknown: empty text string
+  int clen = host_load_cpu((char*)cpu, (int)sizeof(cpu));
+  int mlen = host_load_memory((char*)mem, (int)sizeof(mem));
+  int plen = host_load_pids((char*)pids, (int)sizeof(pids));
+
+  // 2. Encode the CBOR capacity record: a 6-entry map with integer keys.
+  cbor_head(5, 6);                              // map(6)
+  cbor_uint(1); cbor_bytes(attest, alen);       // 1 => attestation
+  cbor_uint(2); cbor_uint((unsigned)trusts);    // 2 => trusts_caller
+  cbor_uint(5); cbor_text(node_addr, nlen);     // 5 => node addr ("ip:port")
+  cbor_uint(6); cbor_item_or_empty_map(cpu, clen);
+  cbor_uint(7); cbor_item_or_empty_map(mem, mlen);
+  cbor_uint(8);                                 // 8 => running PIDs (empty array if unknown)
+  if (plen > 0) put_bytes(pids, plen); else cbor_head(4, 0);
+
+  host_return_map((const char*)out, out_len);
+}
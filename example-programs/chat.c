@@ -14,7 +14,19 @@
 // ordinary chat: kind 0 (or absent) = chat, 1 = join, 2 = leave. On start we broadcast a join notice
 // and on quit a leave notice, so peers see who comes and goes; both are rendered as "*** name ...".
 // Received messages land in this node's message store (host verifies the signature first) and we read
-// them back with host::messages_read, decoding each stored payload's first list element to display.
+// them back with host::messages_read_room, decoding each stored payload's first list element to display.
+//
+// Rooms and direct messages: `/join <room>` moves the conversation to a named room (`/join` alone goes
+// back to the fabric-wide lobby, room ""), `/msg <name|short-id> <text>` sends a direct message sealed
+// to that identity's key, and `/who` lists who has been heard in the current room. Lobby messages go
+// out with host::messages_send, which every node understands; room messages and DMs use
+// host::messages_send_to, which carries the room / recipient inside the signed envelope. DMs show in
+// every room, marked "[dm]". Whoever we've heard from (in any room) can be messaged by name.
+//
+// Chat lines are sent reliably (host::messages_send_reliable in the lobby, messages_send_to_reliable in
+// a room or as a DM): receivers ack them and the host retransmits until the peers it knows of - for a
+// DM, its recipient - have all acked. Each of our own lines is marked from host::messages_status:
+// "·" pending, "✓" delivered, "!" expired before everyone acked.
 //
 // This program does no I/O itself; message passing, the terminal, and arguments are host primitives.
 // Freestanding (no libc) so it stays small.
@@ -26,8 +38,24 @@ int host_arg_map_get(const char* k, int kl, char* p, int cap);
 // nonce, signs it with our verified identity, and fans it out onto the fabric. Returns 0 on success.
 __attribute__((import_module("host"), import_name("messages_send")))
 int host_messages_send(const char* p, int n);
-__attribute__((import_module("host"), import_name("messages_read")))
-int host_messages_read(long long after_seq, char* p, int cap);
+// messages_send with acknowledgements: also writes the message's 16-byte id to `id`, and the host
+// retransmits it until the peers it knows of have acked (or it gives up). Returns 0 on success.
+__attribute__((import_module("host"), import_name("messages_send_reliable")))
+int host_messages_send_reliable(const char* p, int n, char* id);
+// Where a reliable send stands: 0 pending, 1 delivered, 2 expired, -1 unknown id. Writes how many
+// peers acked and how many were expected to (u16 little-endian each) to `out`.
+__attribute__((import_module("host"), import_name("messages_status")))
+int host_messages_status(const char* id, int id_len, char* out);
+// Same as messages_send, for a named room (room "" = the lobby) or - with a 32-byte identity pubkey as `to` - a
+// direct message sealed to that identity.
+__attribute__((import_module("host"), import_name("messages_send_to")))
+int host_messages_send_to(const char* room, int room_len, const char* to, int to_len, const char* p, int n);
+// messages_send_to with acknowledgements, as messages_send_reliable is for messages_send.
+__attribute__((import_module("host"), import_name("messages_send_to_reliable")))
+int host_messages_send_to_reliable(const char* room, int room_len, const char* to, int to_len, const char* p, int n, char* id);
+// Stored messages said in `room`, plus every direct message to or from us.
+__attribute__((import_module("host"), import_name("messages_read_room")))
+int host_messages_read_room(const char* room, int room_len, long long after_seq, char* p, int cap);
 
 __attribute__((import_module("host"), import_name("tty_available")))
 int host_tty_available(void);
@@ -50,11 +78,12 @@ int host_tty_flush(void);
 enum { EV_CHAR=1, EV_ENTER=2, EV_BACKSPACE=3, EV_LEFT=4, EV_RIGHT=5, EV_UP=6, EV_DOWN=7,
        EV_CTRL_C=8, EV_CTRL_D=9, EV_ESC=10, EV_RESIZE=11 };
 // Message record keys (mirror crate::executor::message_keys).
-enum { K_SEQ=1, K_NAME=2, K_PUBKEY=3, K_EPOCH=4, K_TEXT=5 };
+enum { K_SEQ=1, K_NAME=2, K_PUBKEY=3, K_EPOCH=4, K_TEXT=5, K_ROOM=6, K_TO=7, K_ID=8 };
 // Payload "kind" (the optional 2nd list element): a plain line vs. a join/leave system notice.
 enum { KIND_CHAT=0, KIND_JOIN=1, KIND_LEAVE=2 };
 
 static int slen(const char* s){ int n=0; while(s[n]) n++; return n; }
+static int seq_bytes(const char* a, const char* b, int n){ for(int i=0;i<n;i++) if(a[i]!=b[i]) return 0; return 1; }
 static void tprint(const char* s){ host_tty_print(s, slen(s)); }
 
 // ---- small helpers ----------------------------------------------------------------------------
@@ -82,9 +111,45 @@ static void cb_text(const char* s, int n){
   for(int i=0;i<n;i++) cb_b((unsigned char)s[i]);
 }
 
-// Broadcast a signed CBOR list [ "text" ] (chat) or [ "text", kind ] (join/leave). The host signs +
-// fans it out; we never transmit a copy of the program. A fresh dedup nonce is minted host-side.
-static void send_msg(const char* text, int tlen, int kind){
+// ---- delivery of our own chat lines ------------------------------------------------------------
+// Chat lines go out reliably. We keep their ids so the transcript can mark each of our lines pending
+// until the peers the host knows of have acked it, delivered, or expired.
+#define MAXSENT 32
+enum { SENT_PENDING=0, SENT_DELIVERED=1, SENT_EXPIRED=2 };
+static struct { char id[16]; int state; int acked; int expected; } sent[MAXSENT];
+static int sent_count = 0;                // total sent; storage index is (i % MAXSENT)
+
+// The total index of the sent line with this id, or -1 if it isn't among the last MAXSENT.
+static int sent_for(const unsigned char* id){
+  for(int i=sent_count-1;i>=0 && i>=sent_count-MAXSENT;i--)
+    if(seq_bytes(sent[i%MAXSENT].id,(const char*)id,16)) return i;
+  return -1;
+}
+// Ask the host how our pending lines are doing. Returns 1 if any changed.
+static int refresh_sent(void){
+  int changed=0;
+  for(int i=sent_count-1;i>=0 && i>=sent_count-MAXSENT;i--){
+    int slot=i%MAXSENT;
+    if(sent[slot].state!=SENT_PENDING) continue;
+    char out[4];
+    int st = host_messages_status(sent[slot].id, 16, out);
+    if(st<0) st=SENT_EXPIRED;             // forgotten by the host
+    int acked = (unsigned char)out[0] | ((unsigned char)out[1]<<8);
+    int expected = (unsigned char)out[2] | ((unsigned char)out[3]<<8);
+    if(st!=sent[slot].state || acked!=sent[slot].acked){ changed=1; }
+    sent[slot].state=st; sent[slot].acked=acked; sent[slot].expected=expected;
+  }
+  return changed;
+}
+
+// The room we're talking in ("" = the fabric-wide lobby).
+static char room[48];
+static int  room_len = 0;
+
+// Broadcast a signed CBOR list [ "text" ] (chat) or [ "text", kind ] (join/leave) to the current room,
+// or - with `to` a 32-byte pubkey - as a direct message to that identity. The host signs + fans it out;
+// we never transmit a copy of the program. A fresh dedup nonce is minted host-side.
+static void send_msg_to(const char* to, const char* text, int tlen, int kind){
   cbn=0;
   if(kind==KIND_CHAT){
     cb_b(0x81);            // array(1): [ text ] - keeps plain chat back-compatible
@@ -94,17 +159,75 @@ static void send_msg(const char* text, int tlen, int kind){
     cb_text(text,tlen);   // element 0: descriptive text (unused when a kind is present)
     cb_b((unsigned char)kind); // element 1: small uint kind (< 24, so a bare byte)
   }
-  host_messages_send((const char*)cb, cbn);
+  // The lobby keeps using messages_send(_reliable) so nodes that predate rooms still hear it. Only
+  // chat lines are sent reliably: join/leave notices aren't worth retransmitting, and the leave is
+  // sent as we exit, with nobody left to retransmit it.
+  if(kind==KIND_CHAT){
+    int slot=sent_count%MAXSENT;
+    int rc = (!to && room_len==0)
+      ? host_messages_send_reliable((const char*)cb, cbn, sent[slot].id)
+      : host_messages_send_to_reliable(room, room_len, to, to ? 32 : 0, (const char*)cb, cbn, sent[slot].id);
+    if(rc==0){
+      sent[slot].state=SENT_PENDING; sent[slot].acked=0; sent[slot].expected=0;
+      sent_count++;
+    }
+  }
+  else if(!to && room_len==0) host_messages_send((const char*)cb, cbn);
+  else host_messages_send_to(room, room_len, to, to ? 32 : 0, (const char*)cb, cbn);
 }
+static void send_msg(const char* text, int tlen, int kind){ send_msg_to(0, text, tlen, kind); }
 static void send_line(const char* text, int tlen){ send_msg(text, tlen, KIND_CHAT); }
 static void send_join(void){ send_msg("joined", 6, KIND_JOIN); }
 static void send_leave(void){ send_msg("left", 4, KIND_LEAVE); }
 
+// ---- who we've heard from -----------------------------------------------------------------------
+// Everyone whose messages we've read, in any room (so /msg can address them by name), and whether
+// they're in the current room: present after a join or a line there, gone after a leave.
+#define MAXPEOPLE 64
+static struct { char name[32]; int name_len; char pk[32]; int here; } people[MAXPEOPLE];
+static int people_count = 0;
+
+static int person_for(const unsigned char* pk){
+  for(int i=0;i<people_count;i++) if(seq_bytes(people[i].pk,(const char*)pk,32)) return i;
+  return -1;
+}
+static void note_person(const unsigned char* name, int name_len, const unsigned char* pk, int here){
+  int i = person_for(pk);
+  if(i<0){
+    if(people_count==MAXPEOPLE) return;
+    i = people_count++;
+    for(int k=0;k<32;k++) people[i].pk[k]=(char)pk[k];
+    people[i].here = 0;
+  }
+  if(name_len>(int)sizeof(people[i].name)) name_len=(int)sizeof(people[i].name);
+  for(int k=0;k<name_len;k++) people[i].name[k]=(char)name[k];
+  people[i].name_len=name_len;
+  if(here>=0) people[i].here=here;
+}
+// Find someone by exact name or by short id (a hex prefix of their pubkey). -1 if nobody matches.
+static int find_person(const char* who, int n){
+  for(int i=0;i<people_count;i++)
+    if(people[i].name_len==n && seq_bytes(people[i].name,who,n)) return i;
+  if(n==0 || n>64) return -1;
+  for(int i=0;i<people_count;i++){
+    int k=0;
+    for(;k<n;k++){
+      unsigned char b=(unsigned char)people[i].pk[k/2];
+      char h = HEX[(k%2==0) ? (b>>4) : (b&0xf)];
+      char c = who[k]; if(c>='A' && c<='F') c=(char)(c-'A'+'a');
+      if(c!=h) break;
+    }
+    if(k==n) return i;
+  }
+  return -1;
+}
+
 // ---- transcript state -------------------------------------------------------------------------
 #define MAXLINES 256
 #define LINEW 200
 static char lines[MAXLINES][LINEW];
 static int  line_len[MAXLINES];
+static int  line_sent[MAXLINES];           // 1 + the `sent` index of our own reliable line; 0 otherwise
 static int  line_count = 0;               // total appended; storage index is (i % MAXLINES)
 
 static void append_line(const char* s, int n){
@@ -112,6 +235,7 @@ static void append_line(const char* s, int n){
   int slot = line_count % MAXLINES;
   for(int i=0;i<n;i++) lines[slot][i]=s[i];
   line_len[slot]=n;
+  line_sent[slot]=0;
   line_count++;
 }
 
@@ -134,7 +258,7 @@ static void put_ident(char* comp, int* c, int nameP, int nameL, int pkP, int pkL
 
 // Read any new messages (seq > *after) and fold them into the transcript. Returns 1 if anything new.
 static int poll_messages(long long* after){
-  int n = host_messages_read(*after, (char*)rbuf, (int)sizeof(rbuf));
+  int n = host_messages_read_room(room, room_len, *after, (char*)rbuf, (int)sizeof(rbuf));
   if(n<=0) return 0;
   int pos=0, major; unsigned long long v;
   pos += chead(rbuf+pos,&major,&v);
@@ -143,7 +267,7 @@ static int poll_messages(long long* after){
   for(int i=0;i<count;i++){
     pos += chead(rbuf+pos,&major,&v);      // map header
     int pairs=(int)v;
-    long long seq=0; int nameP=-1,nameL=0,pkP=-1,pkL=0,textP=-1,textL=0;
+    long long seq=0; int nameP=-1,nameL=0,pkP=-1,pkL=0,textP=-1,textL=0,toP=-1,toL=0,idP=-1,idL=0;
     for(int j=0;j<pairs;j++){
       unsigned long long key; int km;
       pos += chead(rbuf+pos,&km,&key);
@@ -154,6 +278,8 @@ static int poll_messages(long long* after){
         if(key==K_NAME){ nameP=pos; nameL=(int)vv; }
         else if(key==K_PUBKEY){ pkP=pos; pkL=(int)vv; }
         else if(key==K_TEXT){ textP=pos; textL=(int)vv; }
+        else if(key==K_TO){ toP=pos; toL=(int)vv; }
+        else if(key==K_ID){ idP=pos; idL=(int)vv; }
         pos += (int)vv;
       }
     }
@@ -179,8 +305,14 @@ static int poll_messages(long long* after){
         }
       }
     }
+    // Keep track of who's around: anyone we read can be messaged; a join or a line (not a DM) puts
+    // them in this room, a leave takes them out.
+    if(nameP>=0 && pkP>=0 && pkL==32){
+      int here = (toP>=0) ? -1 : (kind==KIND_LEAVE ? 0 : 1);
+      note_person(rbuf+nameP, nameL, rbuf+pkP, here);
+    }
     // Compose the transcript line: a system notice "*** name (pk8) joined/left the chat" for join/leave,
-    // otherwise the ordinary "name (pk8): text".
+    // "[dm] name (pk8) -> name (pk8): text" for a direct message, otherwise "name (pk8): text".
     char comp[LINEW]; int c=0;
     if(kind==KIND_JOIN || kind==KIND_LEAVE){
       const char* pre="*** ";
@@ -189,11 +321,27 @@ static int poll_messages(long long* after){
       const char* verb=(kind==KIND_JOIN)?" joined the chat":" left the chat";
       for(int k=0;verb[k] && c<LINEW;k++) comp[c++]=verb[k];
     } else {
+      if(toP>=0){
+        const char* pre="[dm] ";
+        for(int k=0;pre[k] && c<LINEW;k++) comp[c++]=pre[k];
+      }
       put_ident(comp,&c,nameP,nameL,pkP,pkL);
+      if(toP>=0 && toL==32){
+        const char* arrow=" -> ";
+        for(int k=0;arrow[k] && c<LINEW;k++) comp[c++]=arrow[k];
+        int who = person_for(rbuf+toP);
+        for(int k=0;who>=0 && k<people[who].name_len && c<LINEW-2;k++) comp[c++]=people[who].name[k];
+        if(who>=0 && c<LINEW-2) comp[c++]=' ';
+        if(c<LINEW-2) comp[c++]='(';
+        for(int k=0;k<4 && c<LINEW-2;k++){ unsigned char b=rbuf[toP+k]; comp[c++]=HEX[b>>4]; comp[c++]=HEX[b&0xf]; }
+        if(c<LINEW-2) comp[c++]=')';
+      }
       if(c<LINEW-2){ comp[c++]=':'; comp[c++]=' '; }
       for(int k=0;k<dispL && c<LINEW;k++) comp[c++]=rbuf[dispP+k];
     }
     append_line(comp,c);
+    int mine = (idP>=0 && idL==16) ? sent_for(rbuf+idP) : -1;
+    if(mine>=0) line_sent[(line_count-1)%MAXLINES]=mine+1;
     if(seq>*after) *after=seq;
     got=1;
   }
@@ -203,6 +351,9 @@ static int poll_messages(long long* after){
 // ---- rendering --------------------------------------------------------------------------------
 static char input[1024];
 static int  input_len = 0;
+// The fabric this conversation is on (the host passes it as the "fabric" arg); shown in the title.
+static char fabric[64];
+static int  fabric_len = 0;
 
 static void redraw(void){
   char sz[4]; int cols=80, rows=24;
@@ -217,7 +368,17 @@ static void redraw(void){
   host_tty_style(11,-1,1);                 // bright yellow, bold
   tprint("weverywhere chat");
   host_tty_style(-1,-1,0);
-  tprint("  —  type a message, Enter to send, Ctrl-C to quit");
+  if(fabric_len>0){
+    tprint(" on ");
+    host_tty_style(14,-1,1);               // bright cyan, bold
+    host_tty_print(fabric, fabric_len);
+    host_tty_style(-1,-1,0);
+  }
+  host_tty_style(13,-1,1);                 // bright magenta, bold
+  tprint("  #");
+  if(room_len>0) host_tty_print(room, room_len); else tprint("lobby");
+  host_tty_style(-1,-1,0);
+  tprint("  —  Enter to send, /join /msg /who, Ctrl-C to quit");
 
   int visible = rows-3;                     // rows 1..rows-2 for transcript
   if(visible<1) visible=1;
@@ -226,9 +387,19 @@ static void redraw(void){
   int row=1;
   for(int i=start;i<line_count;i++){
     int slot=i%MAXLINES, n=line_len[slot];
+    int mine=line_sent[slot]-1;
+    if(mine>=0 && mine<sent_count-MAXSENT) mine=-1;   // too old to still be tracked
+    if(n>cols-2 && mine>=0) n=cols-2;
     if(n>cols) n=cols;
     host_tty_move(0,row++);
     host_tty_print(lines[slot], n);
+    // Our own lines: "·" while pending, "✓" once every known peer has it, "!" if it expired first.
+    if(mine>=0){
+      int st=sent[mine%MAXSENT].state;
+      host_tty_style(st==SENT_DELIVERED ? 10 : st==SENT_EXPIRED ? 9 : 8, -1, 0);
+      tprint(st==SENT_DELIVERED ? " ✓" : st==SENT_EXPIRED ? " !" : " ·");
+      host_tty_style(-1,-1,0);
+    }
   }
 
   host_tty_move(0,rows-1);
@@ -240,14 +411,81 @@ static void redraw(void){
   host_tty_flush();
 }
 
+// ---- commands ---------------------------------------------------------------------------------
+static void notice(const char* a, const char* b, int bn){
+  char comp[LINEW]; int c=0;
+  for(int k=0;a[k] && c<LINEW;k++) comp[c++]=a[k];
+  for(int k=0;k<bn && c<LINEW;k++) comp[c++]=b[k];
+  append_line(comp,c);
+}
+
+// Move to `name` (len n; "" or "lobby" = the lobby): leave the old room, then re-read the transcript
+// for the new one from the start of the store, which also works out who's in it.
+static void join_room(const char* name, int n, long long* after){
+  if(n==5 && seq_bytes(name,"lobby",5)) n=0;
+  if(n>(int)sizeof(room)) n=(int)sizeof(room);
+  send_leave();
+  for(int k=0;k<n;k++) room[k]=name[k];
+  room_len=n;
+  line_count=0;
+  for(int i=0;i<people_count;i++) people[i].here=0;
+  *after=0;
+  send_join();
+}
+
+static void who(void){
+  char comp[LINEW]; int c=0;
+  const char* pre="*** here: ";
+  for(int k=0;pre[k] && c<LINEW;k++) comp[c++]=pre[k];
+  int any=0;
+  for(int i=0;i<people_count;i++){
+    if(!people[i].here) continue;
+    if(any && c<LINEW-2){ comp[c++]=','; comp[c++]=' '; }
+    for(int k=0;k<people[i].name_len && c<LINEW;k++) comp[c++]=people[i].name[k];
+    if(c<LINEW-11){
+      comp[c++]=' '; comp[c++]='(';
+      for(int k=0;k<4;k++){ unsigned char b=(unsigned char)people[i].pk[k]; comp[c++]=HEX[b>>4]; comp[c++]=HEX[b&0xf]; }
+      comp[c++]=')';
+    }
+    any=1;
+  }
+  if(!any){ const char* none="nobody yet"; for(int k=0;none[k] && c<LINEW;k++) comp[c++]=none[k]; }
+  append_line(comp,c);
+}
+
+// Handle an entered line: a /command, or a chat line for the current room.
+static void submit_line(const char* s, int n, long long* after){
+  if(n==0) return;
+  if(s[0]!='/'){ send_line(s, n); return; }
+  int cmd_end=1; while(cmd_end<n && s[cmd_end]!=' ') cmd_end++;
+  int arg=cmd_end; while(arg<n && s[arg]==' ') arg++;
+  int cl=cmd_end-1;
+  if(cl==4 && seq_bytes(s+1,"join",4)){
+    int e=arg; while(e<n && s[e]!=' ') e++;
+    join_room(s+arg, e-arg, after);
+  } else if(cl==3 && seq_bytes(s+1,"who",3)){
+    who();
+  } else if(cl==3 && seq_bytes(s+1,"msg",3)){
+    int e=arg; while(e<n && s[e]!=' ') e++;
+    int t=e; while(t<n && s[t]==' ') t++;
+    int i = find_person(s+arg, e-arg);
+    if(e==arg || t>=n) notice("*** usage: /msg <name|short-id> <text>", "", 0);
+    else if(i<0) notice("*** nobody heard from yet goes by ", s+arg, e-arg);
+    else send_msg_to(people[i].pk, s+t, n-t, KIND_CHAT);
+  } else {
+    notice("*** unknown command ", s, cmd_end);
+  }
+}
+
 // ---- main loop --------------------------------------------------------------------------------
 static char evbuf[16];
 
 static void ui_loop(void){
-  long long after=0;
+  long long after=0;                        // highest seq read for the current room
   redraw();
   for(;;){
     int changed = poll_messages(&after);
+    changed |= refresh_sent();
     int e = host_tty_next_event(evbuf, (int)sizeof(evbuf), 120);
     if(e>0){
       unsigned char kind=evbuf[0];
@@ -258,7 +496,7 @@ static void ui_loop(void){
       }
       else if(kind==EV_BACKSPACE){ if(input_len>0){ input_len--; changed=1; } }
       else if(kind==EV_ENTER){
-        if(input_len>0){ send_line(input, input_len); input_len=0; changed=1; }
+        if(input_len>0){ submit_line(input, input_len, &after); input_len=0; changed=1; }
       }
       else if(kind==EV_RESIZE){ changed=1; }
     }
@@ -270,6 +508,9 @@ __attribute__((export_name("_start")))
 void _start(void){
   // Interactive role only: requires a terminal. (Delivery is now a host primitive, not a program.)
   if(!host_tty_available()) return;
+  fabric_len = host_arg_map_get("fabric", 6, fabric, (int)sizeof(fabric));
+  if(fabric_len<0) fabric_len=0;
+  if(fabric_len>(int)sizeof(fabric)) fabric_len=(int)sizeof(fabric);
   send_join();     // announce our arrival to the fabric
   ui_loop();
   send_leave();    // announce our departure before the host tears the send sink down
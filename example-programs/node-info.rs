@@ -0,0 +1,41 @@
+// node-info: the Rust example, written with the weverywhere-guest crate (./guest) instead of hand
+// declared imports and hand built CBOR. It prints what a program can learn about the node it runs
+// on - name, address, depth, trust, arguments, neighbours and load - and returns the node's signed
+// discovery record, so `run` shows both.
+//
+// build.rs compiles it like the C programs when it is named in embedded.list, with
+//   cargo build --manifest-path guest/Cargo.toml --example node-info --target wasm32-wasip1 --profile guest
+// (add the target once with `rustup target add wasm32-wasip1`).
+
+use std::fmt::Write;
+
+use weverywhere_guest::{args, discovery, load, peers};
+
+fn main() {
+  let mut out = String::new();
+  let addr = weverywhere_guest::node_addr().unwrap_or_else(|| "unknown address".to_string());
+  let _ = writeln!(out, "{} ({addr}), {} hops out", weverywhere_guest::hostname(), weverywhere_guest::depth());
+  let trust = if weverywhere_guest::trusts_me() { "trusts" } else { "does not trust" };
+  let _ = writeln!(out, "  {trust} whoever sent this program");
+
+  let args = args::Args::current();
+  if !args.list.is_empty() || !args.map.is_empty() {
+    let _ = writeln!(out, "  args: {:?} {:?}", args.list, args.map);
+  }
+
+  for peer in peers::all() {
+    let trusted = if peer.trusted { ", trusted" } else { "" };
+    let _ = writeln!(out, "  peer {} at {}{trusted}", peer.name, peer.addr);
+  }
+
+  if let (Some(cpu), Some(memory)) = (load::cpu(), load::memory()) {
+    let busy = cpu.busy_permille / 10;
+    let free_mib = memory.available_bytes >> 20;
+    let _ = writeln!(out, "  {} cores {busy}% busy, {free_mib} MiB free, {} other programs", cpu.cores, load::pids().len());
+  }
+  weverywhere_guest::print(&out);
+
+  if let Some(record) = discovery::Record::for_this_node() {
+    weverywhere_guest::return_map(&record.to_value());
+  }
+}
@@ -0,0 +1,66 @@
+//! The arguments a program runs with: a positional list and named key/value pairs, chosen by
+//! whoever launched it (`run ... -- a b`, `--arg key=value`) or replicated it.
+
+use crate::cbor::Value;
+use crate::{lossy, read_growing, sys};
+
+/// Keys of the map `host::replicate` takes.
+const LIST: i128 = 1;
+/// The named arguments, flattened to `[k0, v0, k1, v1, ...]`.
+const MAP: i128 = 2;
+
+/// A full set of arguments: this program's own ([`Args::current`]), or a replica's.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Args {
+  pub list: Vec<String>,
+  pub map: Vec<(String, String)>,
+}
+
+impl Args {
+  pub fn current() -> Args {
+    Args { list: list(), map: map() }
+  }
+
+  /// The named argument `key`.
+  pub fn get(&self, key: &str) -> Option<&str> {
+    self.map.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+  }
+
+  /// The CBOR `host::replicate` takes.
+  pub fn encode(&self) -> Vec<u8> {
+    let texts = |items: Vec<&String>| Value::Array(items.into_iter().map(|s| Value::Text(s.clone())).collect());
+    let flat = self.map.iter().flat_map(|(k, v)| [k, v]).collect();
+    Value::int_map([(LIST, texts(self.list.iter().collect())), (MAP, texts(flat))]).encode()
+  }
+}
+
+pub fn len() -> usize {
+  unsafe { sys::arg_len() }.max(0) as usize
+}
+
+/// Positional argument `index`.
+pub fn get(index: usize) -> Option<String> {
+  read_growing(256, |ptr, cap| unsafe { sys::arg_get(index as i32, ptr, cap) }).map(lossy)
+}
+
+/// Every positional argument, in order.
+pub fn list() -> Vec<String> {
+  (0..len()).filter_map(get).collect()
+}
+
+/// The named argument `key`.
+pub fn named(key: &str) -> Option<String> {
+  read_growing(256, |ptr, cap| unsafe { sys::arg_map_get(key.as_ptr(), key.len() as i32, ptr, cap) }).map(lossy)
+}
+
+/// Every named argument, in the order given.
+pub fn map() -> Vec<(String, String)> {
+  let count = unsafe { sys::arg_map_len() }.max(0);
+  (0..count)
+    .filter_map(|i| read_growing(64, |ptr, cap| unsafe { sys::arg_map_key(i, ptr, cap) }).map(lossy))
+    .filter_map(|key| {
+      let value = named(&key)?;
+      Some((key, value))
+    })
+    .collect()
+}
@@ -0,0 +1,295 @@
+//! Just enough CBOR (RFC 8949) for what the host hands programs and takes from them: integers, byte
+//! and text strings, arrays, maps, booleans, null and floats, all definite-length. Encoding picks
+//! the shortest head for each item, and the narrowest width that holds a float exactly, as the host's
+//! encoder does, so both sides produce the same bytes.
+
+/// One CBOR data item.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+  Integer(i128),
+  Bytes(Vec<u8>),
+  Text(String),
+  Array(Vec<Value>),
+  /// Entries in the order they were written.
+  Map(Vec<(Value, Value)>),
+  Bool(bool),
+  Null,
+  Float(f64),
+}
+
+/// How deeply arrays and maps may nest in decoded input.
+const MAX_DEPTH: usize = 64;
+
+impl Value {
+  /// A map keyed by small integers, the shape every record the host defines has.
+  pub fn int_map(entries: impl IntoIterator<Item = (i128, Value)>) -> Value {
+    Value::Map(entries.into_iter().map(|(k, v)| (Value::Integer(k), v)).collect())
+  }
+
+  pub fn encode(&self) -> Vec<u8> {
+    let mut out = Vec::new();
+    self.encode_into(&mut out);
+    out
+  }
+
+  /// Append this item's encoding to `out`. Integers outside CBOR's 64-bit range are clamped to it.
+  pub fn encode_into(&self, out: &mut Vec<u8>) {
+    match self {
+      Value::Integer(n) if *n >= 0 => head(out, 0, u64::try_from(*n).unwrap_or(u64::MAX)),
+      Value::Integer(n) => head(out, 1, u64::try_from(-1 - *n).unwrap_or(u64::MAX)),
+      Value::Bytes(b) => {
+        head(out, 2, b.len() as u64);
+        out.extend_from_slice(b);
+      }
+      Value::Text(s) => {
+        head(out, 3, s.len() as u64);
+        out.extend_from_slice(s.as_bytes());
+      }
+      Value::Array(items) => {
+        head(out, 4, items.len() as u64);
+        items.iter().for_each(|item| item.encode_into(out));
+      }
+      Value::Map(entries) => {
+        head(out, 5, entries.len() as u64);
+        for (k, v) in entries {
+          k.encode_into(out);
+          v.encode_into(out);
+        }
+      }
+      Value::Bool(b) => out.push(0xf4 | *b as u8),
+      Value::Null => out.push(0xf6),
+      // The narrowest width that holds the value exactly.
+      Value::Float(f) => match (f16_from_f64(*f), *f as f32) {
+        (Some(half), _) => {
+          out.push(0xf9);
+          out.extend_from_slice(&half.to_be_bytes());
+        }
+        (None, single) if single as f64 == *f => {
+          out.push(0xfa);
+          out.extend_from_slice(&single.to_be_bytes());
+        }
+        _ => {
+          out.push(0xfb);
+          out.extend_from_slice(&f.to_be_bytes());
+        }
+      },
+    }
+  }
+
+  /// Decode exactly one item filling all of `bytes`; `None` if they are anything else. Tags are
+  /// skipped (the tagged item is returned), and indefinite lengths aren't supported.
+  pub fn decode(bytes: &[u8]) -> Option<Value> {
+    let mut reader = Reader { bytes, pos: 0 };
+    let value = reader.item(0)?;
+    (reader.pos == bytes.len()).then_some(value)
+  }
+
+  /// The value under integer `key`, if this is a map that has one.
+  pub fn get(&self, key: i128) -> Option<&Value> {
+    match self {
+      Value::Map(entries) => entries.iter().find(|(k, _)| *k == Value::Integer(key)).map(|(_, v)| v),
+      _ => None,
+    }
+  }
+
+  pub fn as_integer(&self) -> Option<i128> {
+    match self {
+      Value::Integer(n) => Some(*n),
+      _ => None,
+    }
+  }
+
+  pub fn as_u64(&self) -> Option<u64> {
+    self.as_integer().and_then(|n| u64::try_from(n).ok())
+  }
+
+  pub fn as_bytes(&self) -> Option<&[u8]> {
+    match self {
+      Value::Bytes(b) => Some(b),
+      _ => None,
+    }
+  }
+
+  pub fn as_text(&self) -> Option<&str> {
+    match self {
+      Value::Text(s) => Some(s),
+      _ => None,
+    }
+  }
+
+  pub fn as_array(&self) -> Option<&[Value]> {
+    match self {
+      Value::Array(items) => Some(items),
+      _ => None,
+    }
+  }
+}
+
+impl From<i64> for Value {
+  fn from(n: i64) -> Value {
+    Value::Integer(n as i128)
+  }
+}
+
+impl From<u64> for Value {
+  fn from(n: u64) -> Value {
+    Value::Integer(n as i128)
+  }
+}
+
+impl From<bool> for Value {
+  fn from(b: bool) -> Value {
+    Value::Bool(b)
+  }
+}
+
+impl From<&str> for Value {
+  fn from(s: &str) -> Value {
+    Value::Text(s.to_string())
+  }
+}
+
+impl From<String> for Value {
+  fn from(s: String) -> Value {
+    Value::Text(s)
+  }
+}
+
+impl From<&[u8]> for Value {
+  fn from(b: &[u8]) -> Value {
+    Value::Bytes(b.to_vec())
+  }
+}
+
+impl From<Vec<u8>> for Value {
+  fn from(b: Vec<u8>) -> Value {
+    Value::Bytes(b)
+  }
+}
+
+impl From<Vec<Value>> for Value {
+  fn from(items: Vec<Value>) -> Value {
+    Value::Array(items)
+  }
+}
+
+/// Write a major type and its argument in the fewest bytes.
+fn head(out: &mut Vec<u8>, major: u8, n: u64) {
+  let major = major << 5;
+  match n {
+    0..=23 => out.push(major | n as u8),
+    24..=0xff => out.extend_from_slice(&[major | 24, n as u8]),
+    0x100..=0xffff => {
+      out.push(major | 25);
+      out.extend_from_slice(&(n as u16).to_be_bytes());
+    }
+    0x1_0000..=0xffff_ffff => {
+      out.push(major | 26);
+      out.extend_from_slice(&(n as u32).to_be_bytes());
+    }
+    _ => {
+      out.push(major | 27);
+      out.extend_from_slice(&n.to_be_bytes());
+    }
+  }
+}
+
+struct Reader<'a> {
+  bytes: &'a [u8],
+  pos: usize,
+}
+
+impl Reader<'_> {
+  fn take(&mut self, n: usize) -> Option<&[u8]> {
+    let end = self.pos.checked_add(n).filter(|end| *end <= self.bytes.len())?;
+    let taken = &self.bytes[self.pos..end];
+    self.pos = end;
+    Some(taken)
+  }
+
+  fn uint(&mut self, n: usize) -> Option<u64> {
+    Some(self.take(n)?.iter().fold(0u64, |acc, b| acc << 8 | *b as u64))
+  }
+
+  /// A head's major type, additional info and argument (the raw info for major 7 floats/simples).
+  fn head(&mut self) -> Option<(u8, u8, u64)> {
+    let first = *self.take(1)?.first()?;
+    let (major, info) = (first >> 5, first & 0x1f);
+    let arg = match info {
+      0..=23 => info as u64,
+      24 => self.uint(1)?,
+      25 => self.uint(2)?,
+      26 => self.uint(4)?,
+      27 => self.uint(8)?,
+      _ => return None,
+    };
+    Some((major, info, arg))
+  }
+
+  /// A length that can't claim more items than there are bytes left, so hostile input can't make us
+  /// allocate for them.
+  fn len(&self, n: u64) -> Option<usize> {
+    usize::try_from(n).ok().filter(|n| *n <= self.bytes.len() - self.pos)
+  }
+
+  fn item(&mut self, depth: usize) -> Option<Value> {
+    if depth > MAX_DEPTH {
+      return None;
+    }
+    let (major, info, arg) = self.head()?;
+    Some(match major {
+      0 => Value::Integer(arg as i128),
+      1 => Value::Integer(-1 - arg as i128),
+      2 => Value::Bytes(self.take(self.len(arg)?)?.to_vec()),
+      3 => Value::Text(String::from_utf8(self.take(self.len(arg)?)?.to_vec()).ok()?),
+      4 => Value::Array((0..self.len(arg)?).map(|_| self.item(depth + 1)).collect::<Option<_>>()?),
+      5 => Value::Map((0..self.len(arg)?).map(|_| Some((self.item(depth + 1)?, self.item(depth + 1)?))).collect::<Option<_>>()?),
+      6 => self.item(depth + 1)?,
+      _ => match (info, arg) {
+        (20, _) => Value::Bool(false),
+        (21, _) => Value::Bool(true),
+        (22, _) | (23, _) => Value::Null,
+        (25, half) => Value::Float(f16_to_f64(half as u16)),
+        (26, single) => Value::Float(f32::from_bits(single as u32) as f64),
+        (27, double) => Value::Float(f64::from_bits(double)),
+        _ => return None,
+      },
+    })
+  }
+}
+
+/// `f` as an IEEE half-precision float, if one holds it exactly (NaN becomes the quiet NaN).
+fn f16_from_f64(f: f64) -> Option<u16> {
+  let sign = if f.is_sign_negative() { 0x8000 } else { 0 };
+  if f.is_nan() {
+    return Some(0x7e00);
+  }
+  if f.is_infinite() || f == 0.0 {
+    return Some(sign | if f.is_infinite() { 0x7c00 } else { 0 });
+  }
+  // Scale the magnitude into a half's 10-bit mantissa: normal halves carry an implicit leading 1 and
+  // exponents -14..=15, subnormals are multiples of 2^-24.
+  let exponent = f.abs().log2().floor() as i32;
+  let half = if (-14..=15).contains(&exponent) {
+    let mantissa = (f.abs() / 2f64.powi(exponent) - 1.0) * 1024.0;
+    sign | ((exponent + 15) as u16) << 10 | mantissa as u16
+  } else if (-24..-14).contains(&exponent) {
+    sign | (f.abs() / 2f64.powi(-24)) as u16
+  } else {
+    return None;
+  };
+  (f16_to_f64(half) == f).then_some(half)
+}
+
+/// Widen an IEEE half-precision float.
+fn f16_to_f64(half: u16) -> f64 {
+  let exponent = (half >> 10) & 0x1f;
+  let mantissa = (half & 0x3ff) as f64;
+  let magnitude = match exponent {
+    0 => mantissa * 2f64.powi(-24),
+    0x1f if mantissa == 0.0 => f64::INFINITY,
+    0x1f => f64::NAN,
+    e => (1.0 + mantissa / 1024.0) * 2f64.powi(e as i32 - 15),
+  };
+  if half & 0x8000 != 0 { -magnitude } else { magnitude }
+}
@@ -0,0 +1,119 @@
+//! What discovery programs (like `netmap`'s) return: a record per node, carrying an attestation the
+//! node's identity key signed, so the client knows which node sent it.
+
+use crate::cbor::Value;
+use crate::{read_growing, sys};
+
+/// Keys of the attestation map `host::signed_attestation` writes.
+pub mod attest_keys {
+  pub const HOSTNAME: i128 = 1;
+  pub const PUBKEY: i128 = 2;
+  pub const EPOCH_S: i128 = 3;
+  pub const SIGNATURE: i128 = 4;
+}
+
+/// Keys of a node record.
+pub mod record_keys {
+  /// byte string: the CBOR attestation (see [`super::attest_keys`]).
+  pub const ATTESTATION: i128 = 1;
+  /// uint 0/1: whether the node trusts the caller that sent it the program.
+  pub const TRUSTS_CALLER: i128 = 2;
+  /// uint: hops from the origin.
+  pub const DEPTH: i128 = 3;
+  /// byte string: the identity pubkey of the node's caller (its parent in the tree).
+  pub const PARENT_PUBKEY: i128 = 4;
+  /// text: the node's own `ip:port`, empty when it doesn't know it.
+  pub const NODE_ADDR: i128 = 5;
+}
+
+/// A node's signed claim to its hostname and key at a moment.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Attestation {
+  pub hostname: String,
+  pub pubkey: Vec<u8>,
+  pub epoch_s: u64,
+  /// Ed25519, by `pubkey`, over the bytes the host builds from the other three fields.
+  pub signature: Vec<u8>,
+}
+
+impl Attestation {
+  pub fn decode(cbor: &[u8]) -> Option<Attestation> {
+    let map = Value::decode(cbor)?;
+    let bytes = |key| map.get(key).and_then(Value::as_bytes).map(<[u8]>::to_vec);
+    Some(Attestation {
+      hostname: map.get(attest_keys::HOSTNAME)?.as_text()?.to_string(),
+      pubkey: bytes(attest_keys::PUBKEY)?,
+      epoch_s: map.get(attest_keys::EPOCH_S)?.as_u64()?,
+      signature: bytes(attest_keys::SIGNATURE)?,
+    })
+  }
+
+  pub fn encode(&self) -> Vec<u8> {
+    Value::int_map([
+      (attest_keys::HOSTNAME, Value::from(self.hostname.as_str())),
+      (attest_keys::PUBKEY, Value::from(self.pubkey.as_slice())),
+      (attest_keys::EPOCH_S, Value::from(self.epoch_s)),
+      (attest_keys::SIGNATURE, Value::from(self.signature.as_slice())),
+    ])
+    .encode()
+  }
+}
+
+/// This node's attestation, freshly signed, as the CBOR a [`Record`] carries; `None` if the node
+/// has no identity key.
+pub fn signed_attestation() -> Option<Vec<u8>> {
+  read_growing(256, |ptr, cap| unsafe { sys::signed_attestation(ptr, cap) })
+}
+
+/// One node's answer to a discovery program.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Record {
+  /// The CBOR attestation (see [`Attestation::decode`]).
+  pub attestation: Vec<u8>,
+  pub trusts_caller: bool,
+  pub depth: Option<u64>,
+  /// The caller's identity pubkey (empty if not reported).
+  pub parent: Vec<u8>,
+  pub node_addr: Option<String>,
+}
+
+impl Record {
+  /// The record this node gives: its attestation, whether it trusts its caller, its depth, its
+  /// caller's key and its address. `None` without an identity key to attest with.
+  pub fn for_this_node() -> Option<Record> {
+    Some(Record {
+      attestation: signed_attestation()?,
+      trusts_caller: crate::trusts_me(),
+      depth: Some(crate::depth() as u64),
+      parent: crate::caller_pubkey(),
+      node_addr: crate::node_addr(),
+    })
+  }
+
+  /// Read a record map; `None` without an attestation.
+  pub fn from_value(map: &Value) -> Option<Record> {
+    let node_addr = match map.get(record_keys::NODE_ADDR) {
+      Some(Value::Text(addr)) if !addr.is_empty() => Some(addr.clone()),
+      Some(Value::Bytes(addr)) if !addr.is_empty() => Some(String::from_utf8_lossy(addr).into_owned()),
+      _ => None,
+    };
+    Some(Record {
+      attestation: map.get(record_keys::ATTESTATION)?.as_bytes()?.to_vec(),
+      trusts_caller: map.get(record_keys::TRUSTS_CALLER).and_then(Value::as_integer) == Some(1),
+      depth: map.get(record_keys::DEPTH).and_then(Value::as_u64),
+      parent: map.get(record_keys::PARENT_PUBKEY).and_then(Value::as_bytes).unwrap_or_default().to_vec(),
+      node_addr,
+    })
+  }
+
+  pub fn to_value(&self) -> Value {
+    let mut record = vec![
+      (record_keys::ATTESTATION, Value::from(self.attestation.as_slice())),
+      (record_keys::TRUSTS_CALLER, Value::from(self.trusts_caller as u64)),
+    ];
+    record.extend(self.depth.map(|depth| (record_keys::DEPTH, Value::from(depth))));
+    record.push((record_keys::PARENT_PUBKEY, Value::from(self.parent.as_slice())));
+    record.push((record_keys::NODE_ADDR, Value::from(self.node_addr.clone().unwrap_or_default())));
+    Value::int_map(record)
+  }
+}
@@ -0,0 +1,153 @@
+//! Write weverywhere programs in Rust: safe wrappers for every `host::*` import the executor links,
+//! and typed versions of the CBOR records it exchanges with programs.
+//!
+//! Build a program for `wasm32-wasip1`, where `fn main` becomes its `_start`:
+//!
+//! ```no_run
+//! weverywhere_guest::print(&format!("hello from {}\n", weverywhere_guest::hostname()));
+//! ```
+//!
+//! - [`args`]: the positional and named arguments a program was launched (or replicated) with.
+//! - [`peers`]: the neighbours this node has seen.
+//! - [`load`]: CPU, memory and running programs, for capacity probes.
+//! - [`messages`]: the node's message store, and signed fabric messages.
+//! - [`tty`]: drawing on, and reading from, an attached terminal.
+//! - [`discovery`]: signed attestations and the node records discovery programs return.
+//! - [`cbor`]: the encoding all of these share.
+//!
+//! The raw imports are in [`sys`]. Off wasm32 they panic, so a program's logic can still be built
+//! and unit tested natively.
+
+pub mod args;
+pub mod cbor;
+pub mod discovery;
+pub mod load;
+pub mod messages;
+pub mod peers;
+pub mod sys;
+pub mod tty;
+
+/// Why the host refused a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+  /// This node can't do it here: no terminal, no identity key, or nowhere to send (e.g. `run-local`).
+  Unavailable,
+  /// A message payload that isn't a CBOR array or map.
+  NotListOrMap,
+  /// The host couldn't encode or seal the message.
+  Encoding,
+  /// A direct message's recipient isn't a valid identity key.
+  BadRecipient,
+}
+
+impl std::fmt::Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str(match self {
+      Error::Unavailable => "not available on this node",
+      Error::NotListOrMap => "payload must be a CBOR array or map",
+      Error::Encoding => "the host could not encode the message",
+      Error::BadRecipient => "recipient is not an identity key",
+    })
+  }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+  /// The error a `messages_send*` or `replicate` import's negative return stands for.
+  fn from_code(code: i32) -> Error {
+    match code {
+      -2 => Error::NotListOrMap,
+      -3 => Error::Encoding,
+      -4 => Error::BadRecipient,
+      _ => Error::Unavailable,
+    }
+  }
+}
+
+/// 0 is success for the imports that return a status.
+fn status(code: i32) -> Result<(), Error> {
+  if code < 0 { Err(Error::from_code(code)) } else { Ok(()) }
+}
+
+/// The most any growing read asks the host for.
+const MAX_READ: usize = 1 << 20;
+
+/// Call an import that writes into a `(ptr, cap)` buffer and returns how much it wrote, doubling the
+/// buffer from `start` bytes while the result fills it: the host cuts off whatever doesn't fit
+/// without saying so. `None` if the import returns a negative value. Only for imports that can be
+/// called again without side effects.
+pub(crate) fn read_growing(start: usize, mut call: impl FnMut(*mut u8, i32) -> i32) -> Option<Vec<u8>> {
+  let mut buf = vec![0u8; start.max(1)];
+  loop {
+    let n = usize::try_from(call(buf.as_mut_ptr(), buf.len() as i32)).ok()?;
+    if n < buf.len() || buf.len() >= MAX_READ {
+      buf.truncate(n);
+      return Some(buf);
+    }
+    buf.resize(buf.len() * 2, 0);
+  }
+}
+
+pub(crate) fn lossy(bytes: Vec<u8>) -> String {
+  String::from_utf8(bytes).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+}
+
+/// Send text to the caller as this program's stdout.
+pub fn print(text: &str) {
+  unsafe { sys::print(text.as_ptr(), text.len() as i32) }
+}
+
+/// Whether this node trusts whoever sent it this program.
+pub fn trusts_me() -> bool {
+  unsafe { sys::trusts_me() == 1 }
+}
+
+/// Whether this node trusts the identity `pubkey` (e.g. a message's sender).
+pub fn trusts_key(pubkey: &[u8]) -> bool {
+  unsafe { sys::trusts_key(pubkey.as_ptr(), pubkey.len() as i32) == 1 }
+}
+
+/// The name of the machine this program is running on.
+pub fn hostname() -> String {
+  lossy(read_growing(256, |ptr, cap| unsafe { sys::hostname(ptr, cap) }).unwrap_or_default())
+}
+
+/// Fill `buf` from the operating system's secure random source; programs have no other.
+pub fn fill_random(buf: &mut [u8]) {
+  unsafe { sys::random(buf.as_mut_ptr(), buf.len() as i32) };
+}
+
+/// The identity pubkey of the caller that sent this program (this node's parent in a discovery tree).
+pub fn caller_pubkey() -> Vec<u8> {
+  read_growing(64, |ptr, cap| unsafe { sys::caller_pubkey(ptr, cap) }).unwrap_or_default()
+}
+
+/// Hops from the node the program was first launched from; its direct responders are at 1.
+pub fn depth() -> u32 {
+  unsafe { sys::depth() }.max(0) as u32
+}
+
+/// This node's own `ip:port` on the fabric, if it knows it (it doesn't under `run-local`).
+pub fn node_addr() -> Option<String> {
+  read_growing(64, |ptr, cap| unsafe { sys::node_addr(ptr, cap) }).map(lossy)
+}
+
+/// Send a copy of this program across the fabric, to run with `args` instead of its own.
+pub fn replicate(args: &args::Args) -> Result<(), Error> {
+  let cbor = args.encode();
+  status(unsafe { sys::replicate(0, cbor.as_ptr(), cbor.len() as i32) })
+}
+
+/// Hand `map` to the caller, which gets it as the program's returned map once it exits. A later
+/// call replaces an earlier one.
+pub fn return_map(map: &cbor::Value) {
+  let cbor = map.encode();
+  unsafe { sys::return_map(cbor.as_ptr(), cbor.len() as i32) };
+}
+
+/// The request id to put on requests this node forwards to its peers on the program's behalf;
+/// discovery programs pick a fresh random one per hop.
+pub fn set_forward_uuid(uuid: [u8; 16]) {
+  unsafe { sys::set_forward_uuid(uuid.as_ptr(), uuid.len() as i32) };
+}
@@ -0,0 +1,97 @@
+//! How busy this machine is, for capacity probes: `submit` sends one across the fabric and places the
+//! real job on the nodes with the most headroom.
+
+use crate::cbor::Value;
+use crate::{read_growing, sys};
+
+/// Keys of the `host::load_cpu` map.
+pub mod cpu_keys {
+  pub const CORES: i128 = 1;
+  pub const BUSY_PERMILLE: i128 = 2;
+  pub const LOAD1_CENTI: i128 = 3;
+}
+
+/// Keys of the `host::load_memory` map.
+pub mod memory_keys {
+  pub const TOTAL_BYTES: i128 = 1;
+  pub const AVAILABLE_BYTES: i128 = 2;
+}
+
+/// Keys a capacity probe's record adds to the discovery record's
+/// ([`crate::discovery::record_keys`]). Each holds what the matching import returned, as-is.
+pub mod capacity_keys {
+  /// A [`super::CpuLoad`] map.
+  pub const CPU: i128 = 6;
+  /// A [`super::MemoryLoad`] map.
+  pub const MEMORY: i128 = 7;
+  /// The array [`super::pids`] returns.
+  pub const PIDS: i128 = 8;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuLoad {
+  /// Logical CPUs.
+  pub cores: u32,
+  /// How busy all cores are together over a short sample, 0..=1000.
+  pub busy_permille: u32,
+  /// One-minute load average x100 (0 where the platform has none).
+  pub load1_centi: u32,
+}
+
+impl CpuLoad {
+  /// Read a [`cpu_keys`] map; missing figures are 0.
+  pub fn from_value(map: &Value) -> CpuLoad {
+    let field = |key| map.get(key).and_then(Value::as_u64).unwrap_or(0).min(u32::MAX as u64) as u32;
+    CpuLoad { cores: field(cpu_keys::CORES), busy_permille: field(cpu_keys::BUSY_PERMILLE), load1_centi: field(cpu_keys::LOAD1_CENTI) }
+  }
+
+  pub fn to_value(&self) -> Value {
+    Value::int_map([
+      (cpu_keys::CORES, Value::from(self.cores as u64)),
+      (cpu_keys::BUSY_PERMILLE, Value::from(self.busy_permille as u64)),
+      (cpu_keys::LOAD1_CENTI, Value::from(self.load1_centi as u64)),
+    ])
+  }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryLoad {
+  pub total_bytes: u64,
+  pub available_bytes: u64,
+}
+
+impl MemoryLoad {
+  /// Read a [`memory_keys`] map; missing figures are 0.
+  pub fn from_value(map: &Value) -> MemoryLoad {
+    let field = |key| map.get(key).and_then(Value::as_u64).unwrap_or(0);
+    MemoryLoad { total_bytes: field(memory_keys::TOTAL_BYTES), available_bytes: field(memory_keys::AVAILABLE_BYTES) }
+  }
+
+  pub fn to_value(&self) -> Value {
+    Value::int_map([
+      (memory_keys::TOTAL_BYTES, Value::from(self.total_bytes)),
+      (memory_keys::AVAILABLE_BYTES, Value::from(self.available_bytes)),
+    ])
+  }
+}
+
+/// This machine's CPU use. The host samples for ~200ms, so call it once.
+pub fn cpu() -> Option<CpuLoad> {
+  let mut buf = [0u8; 64];
+  let n = unsafe { sys::load_cpu(buf.as_mut_ptr(), buf.len() as i32) };
+  Some(CpuLoad::from_value(&Value::decode(buf.get(..usize::try_from(n).ok()?)?)?))
+}
+
+pub fn memory() -> Option<MemoryLoad> {
+  let cbor = read_growing(64, |ptr, cap| unsafe { sys::load_memory(ptr, cap) })?;
+  Some(MemoryLoad::from_value(&Value::decode(&cbor)?))
+}
+
+/// The pids of the other programs this node was running when this one started.
+pub fn pids() -> Vec<u64> {
+  let cbor = read_growing(256, |ptr, cap| unsafe { sys::load_pids(ptr, cap) }).unwrap_or_default();
+  match Value::decode(&cbor) {
+    Some(Value::Array(pids)) => pids.iter().filter_map(Value::as_u64).collect(),
+    _ => Vec::new(),
+  }
+}
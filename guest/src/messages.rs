@@ -0,0 +1,178 @@
+//! The node's message store, and messages sent across the fabric.
+//!
+//! Everything a node hears lands in its store as a [`Message`], stamped by the host with the
+//! sender's verified name and key; programs read it by sequence number. Sending signs a CBOR payload
+//! with this node's identity and broadcasts it, to the fabric-wide room, a named one, or sealed to
+//! one identity.
+
+use crate::cbor::Value;
+use crate::{sys, Error};
+
+/// Keys of the message records `host::messages_read` returns.
+pub mod message_keys {
+  /// uint: sequence number assigned by the receiving host.
+  pub const SEQ: i128 = 1;
+  /// text: the sender's verified human name.
+  pub const NAME: i128 = 2;
+  /// byte string: the sender's verified identity pubkey.
+  pub const PUBKEY: i128 = 3;
+  /// uint: seconds since the UTC epoch when the host recorded it.
+  pub const EPOCH_S: i128 = 4;
+  /// byte string: the body exactly as sent.
+  pub const TEXT: i128 = 5;
+  /// text: the room it was said in (`""` is the fabric-wide room).
+  pub const ROOM: i128 = 6;
+  /// byte string: the recipient's identity pubkey; only on direct messages.
+  pub const TO: i128 = 7;
+  /// byte string: the sender's message id; absent for messages pushed without one.
+  pub const ID: i128 = 8;
+}
+
+/// How much one read asks for. The host returns the oldest records that fit, so a reader that
+/// keeps passing on the highest seq it has seen gets the rest on its next read.
+const READ_BUFFER: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Message {
+  pub seq: u64,
+  pub name: String,
+  pub pubkey: Vec<u8>,
+  pub epoch_s: u64,
+  pub text: Vec<u8>,
+  pub room: String,
+  pub to: Option<Vec<u8>>,
+  pub id: Option<Vec<u8>>,
+}
+
+impl Message {
+  /// Read one record; `None` without a seq.
+  pub fn from_value(record: &Value) -> Option<Message> {
+    let bytes = |key| record.get(key).and_then(Value::as_bytes).map(<[u8]>::to_vec);
+    let text = |key| record.get(key).and_then(Value::as_text).unwrap_or_default().to_string();
+    Some(Message {
+      seq: record.get(message_keys::SEQ)?.as_u64()?,
+      name: text(message_keys::NAME),
+      pubkey: bytes(message_keys::PUBKEY).unwrap_or_default(),
+      epoch_s: record.get(message_keys::EPOCH_S).and_then(Value::as_u64).unwrap_or(0),
+      text: bytes(message_keys::TEXT).unwrap_or_default(),
+      room: text(message_keys::ROOM),
+      to: bytes(message_keys::TO),
+      id: bytes(message_keys::ID),
+    })
+  }
+
+  pub fn to_value(&self) -> Value {
+    let mut record = vec![
+      (message_keys::SEQ, Value::from(self.seq)),
+      (message_keys::NAME, Value::from(self.name.as_str())),
+      (message_keys::PUBKEY, Value::from(self.pubkey.as_slice())),
+      (message_keys::EPOCH_S, Value::from(self.epoch_s)),
+      (message_keys::TEXT, Value::from(self.text.as_slice())),
+      (message_keys::ROOM, Value::from(self.room.as_str())),
+    ];
+    record.extend(self.to.clone().map(|to| (message_keys::TO, Value::Bytes(to))));
+    record.extend(self.id.clone().map(|id| (message_keys::ID, Value::Bytes(id))));
+    Value::int_map(record)
+  }
+
+  /// Read the array of records a read returns, skipping any that don't parse.
+  pub fn decode_list(cbor: &[u8]) -> Vec<Message> {
+    match Value::decode(cbor) {
+      Some(Value::Array(records)) => records.iter().filter_map(Message::from_value).collect(),
+      _ => Vec::new(),
+    }
+  }
+}
+
+/// Where a [`send_reliable`] message stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryState {
+  /// Still being retransmitted.
+  Pending,
+  /// Every peer expected to hear it has acked.
+  Delivered,
+  /// The deadline passed first.
+  Expired,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Delivery {
+  pub state: DeliveryState,
+  /// How many identities have acked it.
+  pub acked: u16,
+  /// How many were expected to.
+  pub expected: u16,
+}
+
+/// Add a message to this node's store only, under the sender-chosen `id` (the store drops a second
+/// message with the same id; pass an empty one to keep every copy). Returns its seq, or `None` if it
+/// was dropped.
+pub fn push(id: &[u8], text: &[u8]) -> Option<u64> {
+  let seq = unsafe { sys::messages_push(id.as_ptr(), id.len() as i32, text.as_ptr(), text.len() as i32) };
+  u64::try_from(seq).ok().filter(|seq| *seq > 0)
+}
+
+/// Messages in the store with a seq above `after_seq`, oldest first, whatever room they were said in.
+pub fn read(after_seq: u64) -> Vec<Message> {
+  read_with(|ptr, cap| unsafe { sys::messages_read(after_seq as i64, ptr, cap) })
+}
+
+/// [`read`] for one room (`""` is the fabric-wide one), plus direct messages.
+pub fn read_room(room: &str, after_seq: u64) -> Vec<Message> {
+  read_with(|ptr, cap| unsafe { sys::messages_read_room(room.as_ptr(), room.len() as i32, after_seq as i64, ptr, cap) })
+}
+
+fn read_with(call: impl FnOnce(*mut u8, i32) -> i32) -> Vec<Message> {
+  let mut buf = vec![0u8; READ_BUFFER];
+  let n = call(buf.as_mut_ptr(), buf.len() as i32);
+  buf.truncate(n.max(0) as usize);
+  Message::decode_list(&buf)
+}
+
+/// Sign `payload` (an array or map) as this node and broadcast it to the fabric-wide room.
+pub fn send(payload: &Value) -> Result<(), Error> {
+  let cbor = payload.encode();
+  crate::status(unsafe { sys::messages_send(cbor.as_ptr(), cbor.len() as i32) })
+}
+
+/// [`send`], retransmitted until every peer heard from recently acks it or a deadline passes.
+/// Returns the message id to follow it with [`status`].
+pub fn send_reliable(payload: &Value) -> Result<[u8; 16], Error> {
+  let cbor = payload.encode();
+  let mut id = [0u8; 16];
+  crate::status(unsafe { sys::messages_send_reliable(cbor.as_ptr(), cbor.len() as i32, id.as_mut_ptr()) })?;
+  Ok(id)
+}
+
+/// How a [`send_reliable`] or [`send_to_reliable`] message is doing; `None` for an id this node
+/// isn't tracking.
+pub fn status(id: &[u8]) -> Option<Delivery> {
+  let mut counts = [0u8; 4];
+  let state = match unsafe { sys::messages_status(id.as_ptr(), id.len() as i32, counts.as_mut_ptr()) } {
+    0 => DeliveryState::Pending,
+    1 => DeliveryState::Delivered,
+    2 => DeliveryState::Expired,
+    _ => return None,
+  };
+  let acked = u16::from_le_bytes([counts[0], counts[1]]);
+  let expected = u16::from_le_bytes([counts[2], counts[3]]);
+  Some(Delivery { state, acked, expected })
+}
+
+/// [`send`] to `room`, or - given `to`, an identity pubkey - sealed so only that identity can read
+/// it. The message is also put in this node's own store.
+pub fn send_to(room: &str, to: Option<&[u8]>, payload: &Value) -> Result<(), Error> {
+  let cbor = payload.encode();
+  let to = to.unwrap_or_default();
+  crate::status(unsafe { sys::messages_send_to(room.as_ptr(), room.len() as i32, to.as_ptr(), to.len() as i32, cbor.as_ptr(), cbor.len() as i32) })
+}
+
+/// [`send_to`], retransmitted until acked - by every peer heard from recently, or for a direct
+/// message by `to` - or a deadline passes. Returns the message id to follow it with [`status`].
+pub fn send_to_reliable(room: &str, to: Option<&[u8]>, payload: &Value) -> Result<[u8; 16], Error> {
+  let cbor = payload.encode();
+  let to = to.unwrap_or_default();
+  let mut id = [0u8; 16];
+  crate::status(unsafe { sys::messages_send_to_reliable(room.as_ptr(), room.len() as i32, to.as_ptr(), to.len() as i32, cbor.as_ptr(), cbor.len() as i32, id.as_mut_ptr()) })?;
+  Ok(id)
+}
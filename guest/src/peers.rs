@@ -0,0 +1,44 @@
+//! The neighbours this node has heard from, as of when the program started.
+
+use crate::{lossy, read_growing, sys};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+  pub name: String,
+  /// Where it was last heard from (`ip:port`).
+  pub addr: String,
+  /// Whether this node trusts it.
+  pub trusted: bool,
+  /// Its identity pubkey.
+  pub pubkey: Vec<u8>,
+}
+
+impl Peer {
+  /// Parse the `name\taddr\ttrusted(0/1)\tpubkey_hex` line `host::peer_report` writes.
+  pub fn parse(line: &str) -> Option<Peer> {
+    let mut fields = line.split('\t');
+    let (name, addr, trusted, pubkey) = (fields.next()?, fields.next()?, fields.next()?, fields.next()?);
+    Some(Peer { name: name.to_string(), addr: addr.to_string(), trusted: trusted == "1", pubkey: from_hex(pubkey)? })
+  }
+}
+
+pub fn count() -> usize {
+  unsafe { sys::peer_count() }.max(0) as usize
+}
+
+/// Peer `index`, below [`count`].
+pub fn get(index: usize) -> Option<Peer> {
+  let line = read_growing(256, |ptr, cap| unsafe { sys::peer_report(index as i32, ptr, cap) })?;
+  Peer::parse(&lossy(line))
+}
+
+pub fn all() -> Vec<Peer> {
+  (0..count()).filter_map(get).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+  if !hex.len().is_multiple_of(2) {
+    return None;
+  }
+  (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
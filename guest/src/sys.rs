@@ -0,0 +1,108 @@
+//! The raw `host::*` imports, exactly as the executor links them (see `Executor::begin_exec` in the
+//! weverywhere crate for what each one does). Pointers are offsets into this program's memory, and
+//! an `(ptr, cap)` pair is a buffer the host writes at most `cap` bytes into, returning how many it
+//! wrote: a result that doesn't fit is cut short, not reported.
+//!
+//! Everything here is `unsafe` and untyped; the rest of the crate wraps it. Off wasm32 the imports
+//! don't exist, so each is a stub that panics - the crate still builds (and its CBOR types can be
+//! tested) on the machine writing the program.
+
+macro_rules! imports {
+  ($($(#[$doc:meta])* fn $name:ident($($arg:ident: $ty:ty),*) $(-> $ret:ty)?;)*) => {
+    #[cfg(target_arch = "wasm32")]
+    #[link(wasm_import_module = "host")]
+    unsafe extern "C" {
+      $($(#[$doc])* pub fn $name($($arg: $ty),*) $(-> $ret)?;)*
+    }
+
+    $(
+      $(#[$doc])*
+      ///
+      /// # Safety
+      /// Every pointer must be valid for the length or capacity passed with it.
+      #[cfg(not(target_arch = "wasm32"))]
+      #[allow(unused_variables)]
+      pub unsafe fn $name($($arg: $ty),*) $(-> $ret)? {
+        panic!(concat!("host::", stringify!($name), " is only available to programs running under weverywhere"))
+      }
+    )*
+  };
+}
+
+imports! {
+  /// Forward `len` bytes to the caller as stdout.
+  fn print(ptr: *const u8, len: i32);
+  /// 1 if this node trusts whoever sent it this program, else 0.
+  fn trusts_me() -> i32;
+  /// 1 if this node trusts the identity pubkey at `ptr`, else 0.
+  fn trusts_key(ptr: *const u8, len: i32) -> i32;
+  fn hostname(ptr: *mut u8, cap: i32) -> i32;
+  /// Fill `len` bytes with OS randomness.
+  fn random(ptr: *mut u8, len: i32) -> i32;
+  /// The identity pubkey of the caller that sent this program.
+  fn caller_pubkey(ptr: *mut u8, cap: i32) -> i32;
+  /// Hops from the origin.
+  fn depth() -> i32;
+  /// This node's own `ip:port`, or -1 if it doesn't know it.
+  fn node_addr(ptr: *mut u8, cap: i32) -> i32;
+
+  fn arg_len() -> i32;
+  /// -1 if `index` is out of range.
+  fn arg_get(index: i32, ptr: *mut u8, cap: i32) -> i32;
+  fn arg_map_len() -> i32;
+  /// -1 if `index` is out of range.
+  fn arg_map_key(index: i32, ptr: *mut u8, cap: i32) -> i32;
+  /// -1 if there is no such key.
+  fn arg_map_get(key_ptr: *const u8, key_len: i32, ptr: *mut u8, cap: i32) -> i32;
+
+  fn peer_count() -> i32;
+  /// One `name\taddr\ttrusted\tpubkey_hex` line; -1 if `index` is out of range.
+  fn peer_report(index: i32, ptr: *mut u8, cap: i32) -> i32;
+
+  /// A CBOR map keyed by `load::cpu_keys`; takes a ~200ms sample.
+  fn load_cpu(ptr: *mut u8, cap: i32) -> i32;
+  /// A CBOR map keyed by `load::memory_keys`.
+  fn load_memory(ptr: *mut u8, cap: i32) -> i32;
+  /// A CBOR array of the other programs' pids.
+  fn load_pids(ptr: *mut u8, cap: i32) -> i32;
+
+  /// Store a message locally; returns its seq, or 0 if `id` was seen before.
+  fn messages_push(id_ptr: *const u8, id_len: i32, text_ptr: *const u8, text_len: i32) -> i64;
+  /// A CBOR array of message records (keyed by `messages::message_keys`) with seq > `after_seq`.
+  fn messages_read(after_seq: i64, ptr: *mut u8, cap: i32) -> i32;
+  /// `messages_read` for one room, plus direct messages.
+  fn messages_read_room(room_ptr: *const u8, room_len: i32, after_seq: i64, ptr: *mut u8, cap: i32) -> i32;
+  /// Sign and broadcast a CBOR list or map.
+  fn messages_send(cbor_ptr: *const u8, cbor_len: i32) -> i32;
+  /// `messages_send`, retransmitted until acked; writes the 16-byte message id to `id_ptr`.
+  fn messages_send_reliable(cbor_ptr: *const u8, cbor_len: i32, id_ptr: *mut u8) -> i32;
+  /// Delivery state of a reliable message; writes acked and expected counts (u16 LE) to `out_ptr`.
+  fn messages_status(id_ptr: *const u8, id_len: i32, out_ptr: *mut u8) -> i32;
+  /// `messages_send` to a room, or sealed to the identity pubkey `to` when it isn't empty.
+  fn messages_send_to(room_ptr: *const u8, room_len: i32, to_ptr: *const u8, to_len: i32, cbor_ptr: *const u8, cbor_len: i32) -> i32;
+  /// `messages_send_to`, retransmitted until acked; writes the 16-byte message id to `id_ptr`.
+  fn messages_send_to_reliable(room_ptr: *const u8, room_len: i32, to_ptr: *const u8, to_len: i32, cbor_ptr: *const u8, cbor_len: i32, id_ptr: *mut u8) -> i32;
+
+  /// Send a copy of this program with the CBOR args `{1: [list], 2: [k, v, ...]}`.
+  fn replicate(scope: i32, args_ptr: *const u8, args_len: i32) -> i32;
+  /// A CBOR attestation keyed by `discovery::attest_keys`, or -1 without an identity key.
+  fn signed_attestation(ptr: *mut u8, cap: i32) -> i32;
+  /// Hand the caller a CBOR map once this program exits.
+  fn return_map(ptr: *const u8, len: i32) -> i32;
+  /// The 16-byte request UUID to stamp on requests forwarded from this node.
+  fn set_forward_uuid(ptr: *const u8, len: i32) -> i32;
+
+  fn tty_available() -> i32;
+  /// Writes cols and rows (u16 LE); -1 without a terminal.
+  fn tty_size(ptr: *mut u8) -> i32;
+  /// Ask for event encoding `version`; returns the one in effect.
+  fn tty_protocol(version: i32) -> i32;
+  fn tty_modes(modes: i32) -> i32;
+  /// One encoded event, 0 on timeout, -1 without a terminal.
+  fn tty_next_event(ptr: *mut u8, cap: i32, timeout_ms: i32) -> i32;
+  fn tty_print(ptr: *const u8, len: i32) -> i32;
+  fn tty_move(col: i32, row: i32) -> i32;
+  fn tty_clear() -> i32;
+  fn tty_style(fg: i32, bg: i32, attrs: i32) -> i32;
+  fn tty_flush() -> i32;
+}
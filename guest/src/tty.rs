@@ -0,0 +1,242 @@
+//! Drawing on, and reading from, the terminal of whoever ran the program (`chat`, `run --tty`,
+//! `run-local --tty`). Drawing is queued and shown at [`flush`]. Events are decoded from protocol
+//! 2, which this module asks the host for before the first read, so modified keys, function keys,
+//! pastes and the mouse all arrive.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{sys, Error};
+
+/// [`style`] attribute bits.
+pub mod attrs {
+  pub const BOLD: i32 = 1;
+  pub const UNDERLINE: i32 = 2;
+  pub const REVERSE: i32 = 4;
+  pub const ITALIC: i32 = 8;
+  pub const DIM: i32 = 16;
+  pub const STRIKETHROUGH: i32 = 32;
+  pub const BLINK: i32 = 64;
+}
+
+/// [`set_modes`] bits.
+pub mod modes {
+  /// Report clicks, drags and the scroll wheel as [`super::Event::Mouse`].
+  pub const MOUSE: u32 = 1;
+  /// Pasted text arrives as one [`super::Event::Paste`] instead of keystrokes.
+  pub const PASTE: u32 = 2;
+  /// Draw on the alternate screen. On when a program starts.
+  pub const ALTERNATE_SCREEN: u32 = 4;
+}
+
+/// Modifier bits on [`Event::Key`] and [`Mouse`].
+pub mod modifiers {
+  pub const SHIFT: u8 = 1;
+  pub const ALT: u8 = 2;
+  pub const CTRL: u8 = 4;
+}
+
+/// [`Mouse::action`] tags.
+pub mod mouse_action {
+  pub const DOWN: u8 = 1;
+  pub const UP: u8 = 2;
+  pub const DRAG: u8 = 3;
+  pub const MOVE: u8 = 4;
+  pub const SCROLL_UP: u8 = 5;
+  pub const SCROLL_DOWN: u8 = 6;
+  pub const SCROLL_LEFT: u8 = 7;
+  pub const SCROLL_RIGHT: u8 = 8;
+}
+
+/// [`Mouse::button`] tags.
+pub mod mouse_button {
+  pub const NONE: u8 = 0;
+  pub const LEFT: u8 = 1;
+  pub const RIGHT: u8 = 2;
+  pub const MIDDLE: u8 = 3;
+}
+
+/// The terminal's own colour, for [`style`].
+pub const DEFAULT_COLOR: i32 = -1;
+
+/// A 24-bit colour for [`style`]; 0-15 are the ANSI colours and 16-255 the rest of the palette.
+pub const fn rgb(r: u8, g: u8, b: u8) -> i32 {
+  0x0100_0000 | (r as i32) << 16 | (g as i32) << 8 | b as i32
+}
+
+/// The first byte of a protocol 2 event.
+const PROTOCOL_V2: u8 = 2;
+
+/// Event kind tags on the wire.
+mod kind {
+  pub const CHAR: u8 = 1;
+  pub const ENTER: u8 = 2;
+  pub const BACKSPACE: u8 = 3;
+  pub const LEFT: u8 = 4;
+  pub const RIGHT: u8 = 5;
+  pub const UP: u8 = 6;
+  pub const DOWN: u8 = 7;
+  pub const CTRL_C: u8 = 8;
+  pub const CTRL_D: u8 = 9;
+  pub const ESC: u8 = 10;
+  pub const RESIZE: u8 = 11;
+  pub const TAB: u8 = 12;
+  pub const BACK_TAB: u8 = 13;
+  pub const HOME: u8 = 14;
+  pub const END: u8 = 15;
+  pub const PAGE_UP: u8 = 16;
+  pub const PAGE_DOWN: u8 = 17;
+  pub const DELETE: u8 = 18;
+  pub const INSERT: u8 = 19;
+  pub const FUNCTION: u8 = 20;
+  pub const PASTE: u8 = 21;
+  pub const MOUSE: u8 = 22;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+  Char(char),
+  Enter,
+  Backspace,
+  Left,
+  Right,
+  Up,
+  Down,
+  CtrlC,
+  CtrlD,
+  Esc,
+  Tab,
+  /// Shift+Tab.
+  BackTab,
+  Home,
+  End,
+  PageUp,
+  PageDown,
+  Delete,
+  Insert,
+  /// F1-F12 (as 1-12).
+  F(u8),
+}
+
+/// Mouse activity at a 0-based (col, row).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mouse {
+  /// A [`mouse_action`] tag.
+  pub action: u8,
+  /// A [`mouse_button`] tag.
+  pub button: u8,
+  pub col: u16,
+  pub row: u16,
+  /// [`modifiers`] bits.
+  pub modifiers: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+  /// A key, with the [`modifiers`] held. Shift on a character is already in the character.
+  Key(Key, u8),
+  /// Text pasted while [`modes::PASTE`] is on. A long paste may be cut short.
+  Paste(String),
+  /// Only while [`modes::MOUSE`] is on.
+  Mouse(Mouse),
+  /// The terminal's new (cols, rows).
+  Resize(u16, u16),
+}
+
+impl Event {
+  /// Decode an event as `host::tty_next_event` writes it, in either protocol.
+  pub fn decode(bytes: &[u8]) -> Option<Event> {
+    match bytes {
+      [PROTOCOL_V2, kind, mods, payload @ ..] => decode_payload(*kind, *mods, payload),
+      [kind, payload @ ..] => decode_payload(*kind, 0, payload),
+      [] => None,
+    }
+  }
+}
+
+fn decode_payload(kind: u8, mods: u8, payload: &[u8]) -> Option<Event> {
+  let u16_at = |i: usize| Some(u16::from_le_bytes([*payload.get(i)?, *payload.get(i + 1)?]));
+  let key = match kind {
+    kind::CHAR => Key::Char(std::str::from_utf8(payload).ok()?.chars().next()?),
+    kind::ENTER => Key::Enter,
+    kind::BACKSPACE => Key::Backspace,
+    kind::LEFT => Key::Left,
+    kind::RIGHT => Key::Right,
+    kind::UP => Key::Up,
+    kind::DOWN => Key::Down,
+    kind::CTRL_C => Key::CtrlC,
+    kind::CTRL_D => Key::CtrlD,
+    kind::ESC => Key::Esc,
+    kind::TAB => Key::Tab,
+    kind::BACK_TAB => Key::BackTab,
+    kind::HOME => Key::Home,
+    kind::END => Key::End,
+    kind::PAGE_UP => Key::PageUp,
+    kind::PAGE_DOWN => Key::PageDown,
+    kind::DELETE => Key::Delete,
+    kind::INSERT => Key::Insert,
+    kind::FUNCTION => Key::F(*payload.first()?),
+    kind::RESIZE => return Some(Event::Resize(u16_at(0)?, u16_at(2)?)),
+    kind::PASTE => return Some(Event::Paste(String::from_utf8_lossy(payload).into_owned())),
+    kind::MOUSE => {
+      let (action, button) = (*payload.first()?, *payload.get(1)?);
+      return Some(Event::Mouse(Mouse { action, button, col: u16_at(2)?, row: u16_at(4)?, modifiers: mods }));
+    }
+    _ => return None,
+  };
+  Some(Event::Key(key, mods))
+}
+
+/// Whether a terminal is attached to this run.
+pub fn available() -> bool {
+  unsafe { sys::tty_available() == 1 }
+}
+
+/// (cols, rows).
+pub fn size() -> Option<(u16, u16)> {
+  let mut b = [0u8; 4];
+  (unsafe { sys::tty_size(b.as_mut_ptr()) } == 4).then(|| (u16::from_le_bytes([b[0], b[1]]), u16::from_le_bytes([b[2], b[3]])))
+}
+
+/// Turn [`modes`] on (the bits set) and off (the rest).
+pub fn set_modes(modes: u32) -> Result<(), Error> {
+  crate::status(unsafe { sys::tty_modes(modes as i32) })
+}
+
+static ASKED_FOR_V2: AtomicBool = AtomicBool::new(false);
+
+/// Wait up to `timeout` for input: `Ok(None)` if none came.
+pub fn next_event(timeout: std::time::Duration) -> Result<Option<Event>, Error> {
+  if !ASKED_FOR_V2.swap(true, Ordering::Relaxed) {
+    unsafe { sys::tty_protocol(PROTOCOL_V2 as i32) };
+  }
+  let mut buf = vec![0u8; 16 * 1024];
+  let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+  match unsafe { sys::tty_next_event(buf.as_mut_ptr(), buf.len() as i32, timeout_ms) } {
+    n if n < 0 => Err(Error::Unavailable),
+    n => Ok(buf.get(..n as usize).and_then(Event::decode)),
+  }
+}
+
+/// Queue `text` at the cursor. In raw mode `\n` only moves down; a new line is `\r\n`.
+pub fn print(text: &str) -> Result<(), Error> {
+  crate::status(unsafe { sys::tty_print(text.as_ptr(), text.len() as i32) })
+}
+
+/// Move the cursor to a 0-based (col, row).
+pub fn move_to(col: u16, row: u16) -> Result<(), Error> {
+  crate::status(unsafe { sys::tty_move(col as i32, row as i32) })
+}
+
+pub fn clear() -> Result<(), Error> {
+  crate::status(unsafe { sys::tty_clear() })
+}
+
+/// Colours ([`rgb`], a palette index, or [`DEFAULT_COLOR`]) and [`attrs`] for what's printed next.
+pub fn style(fg: i32, bg: i32, attrs: i32) -> Result<(), Error> {
+  crate::status(unsafe { sys::tty_style(fg, bg, attrs) })
+}
+
+/// Show everything queued since the last flush.
+pub fn flush() -> Result<(), Error> {
+  crate::status(unsafe { sys::tty_flush() })
+}
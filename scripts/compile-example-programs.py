@@ -12,7 +12,7 @@ import shutil
 import traceback
 import shlex
 
-REPO_DIR = os.path.dirname(os.path.dirname(__file__))
+REPO_DIR = os.path.dirname(os.path.dirname(os.path.abspath(__file__)))
 
 def check_req_bins():
   req_bins = [
@@ -61,6 +61,18 @@ def compile_example_program(example_path, out_dir):
     bare_cmd = read_compile_command_from_c_source(example_path)
     cmd = template_compile_command(bare_cmd, compile_cmd_dict)
     run_cmd(cmd)
+  elif example_path.endswith('.rs'):
+    # An [[example]] of the guest crate; same command build.rs uses. Needs `rustup target add wasm32-wasip1`.
+    run_cmd([
+      'cargo', 'build', '--example', example_path_stem,
+        '--target', 'wasm32-wasip1',
+        '--profile', 'guest',
+        '--manifest-path', os.path.join(REPO_DIR, 'guest', 'Cargo.toml'),
+    ])
+    shutil.copyfile(
+      os.path.join(REPO_DIR, 'target', 'wasm32-wasip1', 'guest', 'examples', f'{example_path_stem}.wasm'),
+      compile_cmd_dict['OUT_FILE'],
+    )
   else:
     raise Exception(f'[ compile_example_program ] We do not know how to compile the example program at {example_path}! Please add an implementation to compile_example_program().')
 
@@ -72,6 +84,8 @@ def main():
   os.makedirs(example_programs_out, exist_ok=True)
 
   for program_name in os.listdir(example_programs):
+    if program_name == 'embedded.list':
+      continue
     program_path = os.path.abspath(os.path.join(example_programs, program_name))
     compile_example_program(program_path, example_programs_out)
 
@@ -32,6 +32,17 @@ pub struct Args {
     #[arg(long)]
     pub log_file: Option<std::path::PathBuf>,
 
+    /// Act on this fabric instead of the config's `[network].fabric` (`""` = the shared default
+    /// fabric). A `[[fabric]]` entry of that name supplies its trust set and address.
+    #[arg(long, value_name = "NAME", global = true)]
+    pub fabric_name: Option<String>,
+
+    /// Set a config field after the config file and its includes are applied, such as
+    /// `--set identity.name=build-7` or `--set network.port=4000`. May be repeated; later flags win,
+    /// and every flag wins over a `WEVERYWHERE__SECTION__FIELD` environment variable.
+    #[arg(long = "set", value_name = "SETTING=VALUE", global = true)]
+    pub set: Vec<crate::config::ConfigOverride>,
+
 }
 
 /// The platform's default system-wide config location, used when --config is not given and no
@@ -82,6 +93,12 @@ pub enum Command {
 
     },
 
+    /// Check the configuration for problems, or explain where each setting came from
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
     /// Install weverywhere into a filesystem tree: extract the embedded etc/ config templates and
     /// copy this binary under INSTALL_ROOT (into etc/ and bin/ by default).
     InstallTo {
@@ -109,13 +126,65 @@ pub enum Command {
         #[arg(short, long, default_value_t = false)]
         fabric: bool,
 
+        /// Send to this one daemon instead of the local one: a configured [[peer]] hostname or
+        /// address, or any resolvable host / IP
+        #[arg(long, value_name = "HOST", conflicts_with = "fabric")]
+        peer: Option<String>,
+
+        /// Run on exactly one node, by identity: a full public key (hex or `ssh-ed25519 ...`), a
+        /// short-id / key prefix, or a hostname. Looked up in [[peer]] first, then by a quick
+        /// discovery pass; replies signed by any other identity are refused
+        #[arg(long, value_name = "PUBKEY|SHORT-ID|HOSTNAME", conflicts_with_all = ["fabric", "peer"])]
+        to: Option<String>,
+
+        /// Stream a file to the program's stdin, or our own stdin when given bare / as `-`
+        /// (e.g. `cat data | weverywhere run --peer node1 --stdin - grep.wasm`)
+        #[arg(long, value_name = "FILE", num_args = 0..=1, default_missing_value = "-", conflicts_with = "fabric")]
+        stdin: Option<std::path::PathBuf>,
+
+        /// Attach this terminal to the program: its `host::tty_*` drawing shows here and our keys,
+        /// mouse and resizes go to it, wherever it runs. Ctrl+] detaches
+        #[arg(long, default_value_t = false, conflicts_with_all = ["fabric", "stdin"])]
+        tty: bool,
+
+        /// With --tty: record the session as an asciicast, for `asciinema play`
+        #[arg(long, value_name = "FILE", requires = "tty")]
+        tty_record: Option<std::path::PathBuf>,
+
+        /// With --fabric: fail (non-zero exit) unless at least N selected nodes ran the program to
+        /// completion before the deadline
+        #[arg(long, value_name = "N", default_value_t = 0, requires = "fabric")]
+        min_responders: usize,
+
+        /// With --fabric: only count (print/collect) the first N selected nodes that accept the
+        /// program, and stop waiting once those N have exited
+        #[arg(long, value_name = "N", requires = "fabric")]
+        max_responders: Option<usize>,
+
+        /// With --fabric: wait at most this many seconds for replies. Without it the window starts
+        /// at a few seconds and grows while replies keep arriving
+        #[arg(long, value_name = "SECONDS", requires = "fabric")]
+        timeout: Option<f64>,
+
+        /// With --fabric: which responding nodes count - `trusted` (keys in our [[trusted]] list),
+        /// `untrusted`, or `pubkey:<hex>` (a full key or a short-id prefix)
+        #[arg(long, value_name = "trusted|untrusted|pubkey:<hex>", requires = "fabric")]
+        select: Option<ResponderSelect>,
+
+        /// With --fabric: print one aggregated document of every selected node's results (stdout,
+        /// returned maps/lists, exit) keyed by node identity, instead of streaming replies
+        #[arg(long, value_enum, requires = "fabric")]
+        collect: Option<CollectFormat>,
+
         /// UDP Multicast addresses to send to (only used with --fabric)
-        #[arg(short, long, default_value_t = default_multicast_groups() )]
-        multicast_groups: MulticastAddressVec,
+        /// [default: `[network].multicast_groups`]
+        #[arg(short, long)]
+        multicast_groups: Option<MulticastAddressVec>,
 
         /// UDP port the daemon listens on
-        #[arg(short, long, default_value_t = 2240)]
-        port: u16,
+        /// [default: `[network].port`]
+        #[arg(short, long)]
+        port: Option<u16>,
 
         /// Named program argument, repeatable: `--arg key=value`. Passed to the program as its
         /// arg_map and readable via host::arg_map_get.
@@ -128,6 +197,50 @@ pub enum Command {
         arg_list: Vec<String>,
     },
 
+    /// Place the given WASI file on the best node(s) of the fabric. First runs a small capacity probe
+    /// on every node reachable directly (multicast + [[peer]]), ranks the answers by trust and by free
+    /// CPU / memory, then sends the job to the best N - moving on to the next node if one rejects it
+    /// or never accepts it. An accepted job is waited for until it exits
+    Submit {
+        /// Path to the WASI file
+        file_path: std::path::PathBuf,
+
+        /// How many nodes to run the job on
+        #[arg(short = 'n', long, value_name = "N", default_value_t = 1)]
+        count: usize,
+
+        /// Capacity probe to run instead of the bundled `capacity-probe` program
+        #[arg(long, value_name = "FILE.wasm")]
+        probe: Option<std::path::PathBuf>,
+
+        /// How many seconds to collect capacity reports for
+        #[arg(long, value_name = "SECONDS", default_value_t = 3.0)]
+        probe_timeout: f64,
+
+        /// Give up on an accepted job that has not exited after this many seconds
+        /// [default: wait until it exits]
+        #[arg(long, value_name = "SECONDS")]
+        timeout: Option<f64>,
+
+        /// UDP Multicast addresses the probe is sent to
+        /// [default: `[network].multicast_groups`]
+        #[arg(short, long)]
+        multicast_groups: Option<MulticastAddressVec>,
+
+        /// UDP port the daemons listen on
+        /// [default: `[network].port`]
+        #[arg(short, long)]
+        port: Option<u16>,
+
+        /// Named program argument, repeatable: `--arg key=value` (see `run`).
+        #[arg(long = "arg", value_name = "KEY=VALUE")]
+        arg: Vec<String>,
+
+        /// Positional program argument, repeatable: `--arg-list value` (see `run`).
+        #[arg(long = "arg-list", value_name = "VALUE")]
+        arg_list: Vec<String>,
+    },
+
     /// Run the given WASI file locally, spinning up an executor as-if we had just become a server and recieved the program.
     // Primarially for debugging, local testing, etc. Reads the same --config file as "serve" does.
     RunLocal {
@@ -143,23 +256,54 @@ pub enum Command {
         arg_list: Vec<String>,
 
         /// Fabric multicast groups that host::replicate copies are broadcast to.
-        #[arg(short, long, default_value_t = default_multicast_groups() )]
-        multicast_groups: MulticastAddressVec,
+        /// [default: `[network].multicast_groups`]
+        #[arg(short, long)]
+        multicast_groups: Option<MulticastAddressVec>,
 
         /// UDP port that host::replicate copies are sent to.
-        #[arg(short, long, default_value_t = 2240)]
-        port: u16,
+        /// [default: `[network].port`]
+        #[arg(short, long)]
+        port: Option<u16>,
+
+        /// Attach this terminal to the program, for its `host::tty_*` imports
+        #[arg(long, default_value_t = false)]
+        tty: bool,
+
+        /// Give the program a headless terminal instead, typing the input in this script (one step
+        /// per line: `type TEXT`, `key ctrl+c`, `paste TEXT`, `resize 100x30`,
+        /// `mouse down left COL ROW`, `wait MS`)
+        #[arg(long, value_name = "FILE", conflicts_with = "tty")]
+        tty_script: Option<std::path::PathBuf>,
+
+        /// With --tty-script: write the final screen here instead of printing it
+        #[arg(long, value_name = "FILE", requires = "tty_script")]
+        tty_dump: Option<std::path::PathBuf>,
+
+        /// With --tty-script: the headless terminal's size
+        #[arg(long, value_name = "COLSxROWS", default_value = "80x24", requires = "tty_script")]
+        tty_size: TtySize,
+
+        /// With --tty-script: how long the program may keep running once the script is done
+        #[arg(long, value_name = "SECONDS", default_value_t = 10.0, requires = "tty_script")]
+        tty_timeout: f64,
+
+        /// Record the session (--tty or --tty-script) as an asciicast, for `asciinema play`
+        #[arg(long, value_name = "FILE")]
+        tty_record: Option<std::path::PathBuf>,
     },
 
-    /// Listen on the given socket for network messages and execute WASI programs sent to us
+    /// Listen on the given socket for network messages and execute WASI programs sent to us.
+    /// Reloads the config when it (or an included file) changes, or on SIGHUP.
     Serve {
         /// UDP Multicast addresses to listen on
-        #[arg(short, long, default_value_t = default_multicast_groups() )]
-        multicast_groups: MulticastAddressVec,
+        /// [default: `[network].multicast_groups`]
+        #[arg(short, long)]
+        multicast_groups: Option<MulticastAddressVec>,
 
-        /// UDP Multicast address to listen on
-        #[arg(short, long, default_value_t = 2240)]
-        port: u16,
+        /// UDP port to listen on
+        /// [default: `[network].port`]
+        #[arg(short, long)]
+        port: Option<u16>,
 
     },
 
@@ -189,31 +333,74 @@ pub enum Command {
         local: bool,
 
         /// UDP Multicast addresses to send the discovery program to
-        #[arg(short, long, default_value_t = default_multicast_groups() )]
-        multicast_groups: MulticastAddressVec,
+        /// [default: `[network].multicast_groups`]
+        #[arg(short, long)]
+        multicast_groups: Option<MulticastAddressVec>,
 
         /// UDP port the daemon listens on
-        #[arg(short, long, default_value_t = 2240)]
-        port: u16,
+        /// [default: `[network].port`]
+        #[arg(short, long)]
+        port: Option<u16>,
+
+        /// How to print the map: the annotated tree, or a JSON / Graphviz DOT / Mermaid document of
+        /// every verified node and edge for other tools to consume
+        #[arg(long, value_enum, default_value_t = NetmapFormat::Tree)]
+        format: NetmapFormat,
+
+        /// Keep running: repeat discovery every this many seconds and report nodes joining and
+        /// leaving, address, trust and key changes (a live view on a terminal, event lines otherwise)
+        #[arg(long, value_name = "SECONDS")]
+        watch: Option<f64>,
+
+        /// Stop listening after at most this many seconds (default 30). Relays stop forwarding and
+        /// relaying once it has passed
+        #[arg(long, value_name = "SECONDS")]
+        timeout: Option<f64>,
+
+        /// Stop once no node has answered for this many seconds (default 1.5)
+        #[arg(long, value_name = "SECONDS")]
+        idle: Option<f64>,
+
+        /// Stop shortly after this many nodes have answered, instead of after every known peer
+        /// (configured, recorded in known_peers or the peer registry) has. 0 never stops early
+        #[arg(long, value_name = "N")]
+        expect: Option<usize>,
     },
 
     /// Join an interactive fabric chat. Runs a self-contained host that listens on the fabric (like
     /// `serve`) AND attaches your terminal, then runs the bundled chat WASI program in `ui` mode: it
     /// draws the transcript, reads your keyboard, and fans each line out to every node by sending a
     /// copy of itself (in `deliver` mode) onto the fabric. Every node's chat shows the sender's signed
-    /// name + public key. Run this INSTEAD of `serve` on a machine (both bind the same port).
+    /// name + public key. `/join <room>`, `/msg <name> <text>` (encrypted to that identity) and `/who`
+    /// work inside it, and on start it backfills earlier messages from the nodes it can reach. Run this
+    /// INSTEAD of `serve` on a machine (both bind the same port).
     Chat {
         /// Path to the chat WASI program. Defaults to the bundled/embedded chat program.
         #[arg(long)]
         program: Option<std::path::PathBuf>,
 
         /// UDP Multicast addresses to chat over.
-        #[arg(short, long, default_value_t = default_multicast_groups() )]
-        multicast_groups: MulticastAddressVec,
+        /// [default: `[network].multicast_groups`]
+        #[arg(short, long)]
+        multicast_groups: Option<MulticastAddressVec>,
 
         /// UDP port to listen/chat on.
-        #[arg(short, long, default_value_t = 2240)]
-        port: u16,
+        /// [default: `[network].port`]
+        #[arg(short, long)]
+        port: Option<u16>,
+    },
+
+    /// Show, export, import, rotate or encrypt this node's identity key (`[identity] keyfile`)
+    Identity {
+        #[command(subcommand)]
+        action: IdentityAction,
+    },
+
+    /// List or edit the peers this node has heard from (the peer registry a daemon keeps under
+    /// `[state] dir`, so it remembers its neighbours across restarts)
+    Peers {
+        #[command(subcommand)]
+        action: PeersAction,
     },
 
     /// Manage weverywhere as a long-running background daemon (OS service) that runs `serve`.
@@ -226,6 +413,16 @@ pub enum Command {
 
 }
 
+#[derive(Debug, Clone, clap::Subcommand)]
+pub enum ConfigAction {
+    /// Report every problem in the config and its includes, and exit non-zero if there are any:
+    /// unparseable or duplicated keys, an unreadable or group/world-readable identity keyfile, includes
+    /// that match nothing or fail to load, peers that don't resolve and limits set to zero
+    Check,
+    /// Print each effective setting and the file (the main config or which include) that set it
+    Explain,
+}
+
 #[derive(Debug, Clone, clap::Subcommand)]
 pub enum DaemonAction {
     /// Register weverywhere as a boot-time daemon and start it now
@@ -242,6 +439,91 @@ pub enum DaemonAction {
     Status,
 }
 
+#[derive(Debug, Clone, clap::Subcommand)]
+pub enum PeersAction {
+    /// Print every remembered peer: short-id, name, last address, trust and when it was last seen
+    List,
+    /// Remove peers from the registry. A running daemon drops them too, until they contact it again
+    Forget {
+        /// A full public key (hex or `ssh-ed25519 ...`), a short-id / key prefix, or a peer name
+        query: String,
+
+        /// Forget every peer the query matches instead of requiring exactly one
+        #[arg(long, default_value_t = false)]
+        all: bool,
+    },
+    /// Print a [[peer]] block for one remembered peer, pinned to the key it used
+    Pin {
+        /// A full public key (hex or `ssh-ed25519 ...`), a short-id / key prefix, or a peer name
+        query: String,
+
+        /// Also append the block to the config file
+        #[arg(long, default_value_t = false)]
+        write: bool,
+    },
+}
+
+#[derive(Debug, Clone, clap::Subcommand)]
+pub enum IdentityAction {
+    /// Print the public key (the `ssh-ed25519 ...` form [[trusted]] takes), its short id and its
+    /// SHA-256 fingerprint
+    Show,
+    /// Print the private key (or with --public, the public key) in another format
+    Export {
+        #[arg(long, value_enum, default_value_t = KeyFormat::Ssh)]
+        format: KeyFormat,
+
+        /// Print the public key instead of the private key
+        #[arg(long, default_value_t = false)]
+        public: bool,
+    },
+    /// Make an existing OpenSSH ed25519 private key (such as ~/.ssh/id_ed25519) this node's identity.
+    /// The key it replaces is kept next to the keyfile as <keyfile>.<epoch_s>.old. It is encrypted if a
+    /// passphrase_file, passphrase_command or systemd credential is set up
+    Import {
+        /// The OpenSSH private key file; it must not be passphrase-protected
+        file: std::path::PathBuf,
+
+        /// Replace an existing identity key without asking
+        #[arg(long, default_value_t = false)]
+        force: bool,
+    },
+    /// Generate a new identity key and broadcast a statement, signed by the old key, naming it as
+    /// the successor: daemons that trusted the old key trust the new one from then on. The old key is
+    /// kept next to the keyfile as <keyfile>.<epoch_s>.old. Restart a local daemon to serve as the new key.
+    /// An encrypted keyfile's successor is encrypted under the same passphrase
+    Rotate {
+        /// UDP Multicast addresses to announce the rotation on
+        /// [default: `[network].multicast_groups`]
+        #[arg(short, long)]
+        multicast_groups: Option<MulticastAddressVec>,
+
+        /// UDP port the daemons listen on
+        /// [default: `[network].port`]
+        #[arg(short, long)]
+        port: Option<u16>,
+    },
+    /// Encrypt the keyfile under a new passphrase (asked for twice), or change the one it has. The key
+    /// itself stays the same. Daemons unlock it from `[identity] passphrase_file`, `passphrase_command`
+    /// or the systemd credential weverywhere-key-passphrase
+    Passphrase {
+        /// Store the key unencrypted again
+        #[arg(long, default_value_t = false)]
+        remove: bool,
+    },
+}
+
+/// Key format of `identity export --format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum KeyFormat {
+    /// OpenSSH: an `OPENSSH PRIVATE KEY` block, or the `ssh-ed25519 <base64>` public key line.
+    Ssh,
+    /// PKCS#8 PEM (the keyfile's own format), or a SubjectPublicKeyInfo `PUBLIC KEY` block.
+    Pem,
+    /// The raw 32-byte key as hex.
+    Hex,
+}
+
 /// Turn repeated `--arg key=value` CLI strings into an ordered arg_map. Each entry is split on the
 /// FIRST `=`; an entry with no `=` becomes `key -> ""`. Order is preserved.
 pub fn parse_arg_map(entries: &[String]) -> Vec<(String, String)> {
@@ -255,17 +537,7 @@ pub fn parse_arg_map(entries: &[String]) -> Vec<(String, String)> {
 }
 
 fn default_multicast_groups() -> MulticastAddressVec {
-    let mut groups = Vec::with_capacity(2);
-    groups.push(std::net::IpAddr::V4(std::net::Ipv4Addr::new(
-        // "Unassigned" per https://www.iana.org/assignments/multicast-addresses/multicast-addresses.xhtml
-        224, 0, 0, 3
-    )));
-    groups.push(std::net::IpAddr::V6(std::net::Ipv6Addr::new(
-        // "Unassigned" per https://www.iana.org/assignments/ipv6-multicast-addresses/ipv6-multicast-addresses.xhtml
-        0xFF02, 0x0000, 0x0000, 0x0000,
-        0x0000, 0x0000, 0x0000, 0x0003
-    )));
-    MulticastAddressVec(groups)
+    MulticastAddressVec(crate::config::MulticastGroups::default().0)
 }
 
 
@@ -301,10 +573,72 @@ impl Command {
     /// also write logs to that terminal, or the log lines bleed onto the program's frames (the TUI's
     /// stdout and the logger's stderr are the same PTY). Used to pick a silent log writer by default.
     pub fn owns_terminal(&self) -> bool {
-        matches!(self, Command::Chat { .. })
+        matches!(self, Command::Chat { .. } | Command::Run { tty: true, .. } | Command::RunLocal { tty: true, .. })
     }
 }
 
+/// A terminal size given as `COLSxROWS`, e.g. `80x24`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TtySize(pub u16, pub u16);
+
+impl std::str::FromStr for TtySize {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('x').map(|(c, r)| (c.parse::<u16>(), r.parse::<u16>())) {
+            Some((Ok(cols), Ok(rows))) if cols > 0 && rows > 0 => Ok(TtySize(cols, rows)),
+            _ => Err(format!("expected COLSxROWS, e.g. 80x24, got {s:?}")),
+        }
+    }
+}
+
+/// Which responders of a fan-out `run --fabric` count (`--select`). Trust here is OUR view of the
+/// node: whether its identity key is in our `[[trusted]]` list (or is our own key).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResponderSelect {
+    Trusted,
+    Untrusted,
+    /// Lowercase hex of a full public key, or a prefix of one (e.g. an 8-hex short id).
+    Pubkey(String),
+}
+
+impl std::str::FromStr for ResponderSelect {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "trusted" => Ok(ResponderSelect::Trusted),
+            "untrusted" => Ok(ResponderSelect::Untrusted),
+            _ => match s.strip_prefix("pubkey:") {
+                Some(hex) if !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+                    Ok(ResponderSelect::Pubkey(hex.to_ascii_lowercase()))
+                }
+                _ => Err(format!("expected trusted, untrusted, or pubkey:<hex>, got {s:?}")),
+            },
+        }
+    }
+}
+
+/// Output format of `run --collect`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum CollectFormat {
+    /// Pretty-printed JSON (CBOR byte strings become hex strings).
+    Json,
+    /// The same document as raw CBOR bytes.
+    Cbor,
+}
+
+/// Output format of `netmap --format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum NetmapFormat {
+    /// The trust-annotated ASCII tree.
+    Tree,
+    /// One JSON document: nodes, edges and warnings.
+    Json,
+    /// A Graphviz digraph.
+    Dot,
+    /// A Mermaid flowchart.
+    Mermaid,
+}
+
 #[derive(Debug, Clone)]
 pub struct MulticastAddressVec(Vec<std::net::IpAddr>);
 
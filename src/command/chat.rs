@@ -3,10 +3,19 @@ use super::*;
 /// The stem of the bundled chat program (see example-programs/embedded.list + chat.c).
 const EMBEDDED_CHAT_NAME: &str = "chat";
 
+/// How long a starting chat collects peers' HistoryReplies for.
+const HISTORY_WINDOW: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Ask for history from this long before our newest stored message, so messages that arrived
+/// elsewhere around the time we last stopped aren't missed.
+const HISTORY_OVERLAP_S: u64 = 300;
+
+
 /// `weverywhere chat`: a self-contained interactive chat host. One process that (1) listens on the
 /// fabric like `serve`, so inbound "deliver" copies of the chat program push messages into a shared
 /// store, and (2) attaches the terminal and runs the chat program in `ui` mode, which draws the
 /// transcript, reads the keyboard, and fans each typed line out to the fabric via `host::replicate`.
+/// Meanwhile it asks the fabric for what was said before it started (see [`backfill_history`]).
 ///
 /// The message-passing is entirely the WASI program + host primitives (args, tty, messages,
 /// replicate); this command is just the launcher that wires a terminal and a fabric listener to one
@@ -14,10 +23,11 @@ const EMBEDDED_CHAT_NAME: &str = "chat";
 pub async fn chat(
   args: &args::Args,
   program: Option<std::path::PathBuf>,
-  multicast_groups: args::MulticastAddressVec,
-  port: u16,
+  multicast_groups: Option<args::MulticastAddressVec>,
+  port: Option<u16>,
 ) -> DynResult<()> {
-  let local_config = config::Config::read_from_file(&args.config_path()).await.map_err(map_loc_err!())?;
+  let mut local_config = config::Config::read_from_file(&args.config_path()).await.map_err(map_loc_err!())?.fabric_view(args.fabric_name.as_deref());
+  local_config.network.apply_cli_overrides(multicast_groups.as_deref(), port);
   let source = config::IdentityData::generate_from_config(&local_config).await.map_err(map_loc_err!())?;
   let (wasm_bytes, program_label) = resolve_chat_program(program).await?;
   if crate::v_is_info() {
@@ -39,30 +49,53 @@ pub async fn chat(
 
   // Fabric listeners on the SAME executor: inbound `deliver` copies push into the executor's message
   // store, which the UI program reads. Left running in the background (we don't join the set).
-  let mut listeners = serve::spawn_listeners(executor.clone(), local_config_arc.clone(), multicast_groups.clone(), port);
+  let mut listeners = serve::spawn_listeners(executor.clone(), local_config_arc.clone());
 
   // host::messages_send sink: the UI deposits ready-to-transmit signed message bytes here; this task
   // fans each out onto the fabric (multicast every interface + peers). No program is shipped - the
   // message is a small signed SignedFabricMessage, not a whole copy of the chat wasm.
   let (fabric_send_tx, mut fabric_send_rx) = tokio::sync::mpsc::unbounded_channel::<Vec<u8>>();
   let mut drain = {
-    let groups: Vec<std::net::IpAddr> = multicast_groups.iter().copied().collect();
+    let network = local_config.network.clone();
     let peers = local_config.peer.clone();
     tokio::spawn(async move {
       while let Some(bytes) = fabric_send_rx.recv().await {
-        if let Err(e) = run::broadcast_bytes_to_fabric(&bytes, &groups, port, &peers).await {
+        let sent = match network.fabric_id().scope(bytes) {
+          Ok(bytes) => run::broadcast_bytes_to_fabric(&bytes, &network, &peers).await,
+          Err(e) => Err(e),
+        };
+        if let Err(e) = sent {
           tracing::warn!("[ chat ] message send failed: {:?}", e);
         }
       }
     })
   };
 
+  // Reliable sends (host::messages_send_reliable) are retransmitted by the listeners above until
+  // they're acked or expire, as they are for a daemon's own programs (see serve::serve_fabrics).
+
+  // Backfill what was said before we started from whoever holds it; replies trickle into the store
+  // while the UI is already up.
+  let backfill = {
+    let (executor, local_config, source) = (executor.clone(), local_config.clone(), source.clone());
+    tokio::spawn(async move {
+      match backfill_history(&executor, &local_config, &source).await {
+        Ok(n) => if crate::v_is_info() { tracing::info!("[ chat ] backfilled {} message(s) from peers", n) },
+        Err(e) => tracing::warn!("[ chat ] history backfill failed: {:?}", e),
+      }
+    })
+  };
+
   // Launch the long-lived UI instance: uncapped fuel (it loops), with the terminal + replication sink.
   let pd = executor::ProgramDataBuilder::new()
     .set_human_name(EMBEDDED_CHAT_NAME)
     .set_wasm_program_bytes(&wasm_bytes)
     .set_source(&source)
-    .set_args(Vec::new(), vec![("mode".to_string(), "ui".to_string())])
+    .set_args(Vec::new(), vec![
+      ("mode".to_string(), "ui".to_string()),
+      // Shown in the UI's title bar, so it's clear whose conversation this is.
+      ("fabric".to_string(), local_config.network.fabric_label().to_string()),
+    ])
     .build().map_err(map_loc_err!())?;
   let return_slot = std::sync::Arc::new(std::sync::Mutex::new(executor::ExecReturn::default()));
   let opts = executor::ExecOptions {
@@ -80,6 +113,7 @@ pub async fn chat(
   // restores the terminal.
   let _ = executor.wait_for_pid_exit(pid).await;
   listeners.abort_all();
+  backfill.abort();
   // Give the send sink a moment to fan out any final message (notably the leave notice the UI emits
   // as it exits) before we tear the drain task down.
   let _ = tokio::time::timeout(std::time::Duration::from_millis(300), &mut drain).await;
@@ -87,6 +121,60 @@ pub async fn chat(
   Ok(())
 }
 
+/// Fill `executor`'s message store from the fabric: send a signed HistoryRequest for every room (to
+/// the multicast groups and each [[peer]]) and record each message in the HistoryReplies that arrive
+/// within [`HISTORY_WINDOW`], after checking its sender's signature like live traffic. Returns how
+/// many messages were new to us.
+async fn backfill_history(executor: &executor::Executor, local_config: &config::Config, source: &config::IdentityData) -> DynResult<usize> {
+  let key = local_config.identity.read_private_key_ed25519_pem_file().await.map_err(map_loc_err!())?;
+  let (request_uuid, request) = history_request(executor, &key, source);
+  let fabric = local_config.network.fabric_id();
+  let request = fabric.encode(&request)?;
+
+  let (sock_v4, sock_v6) = netmap::bind_request_sockets(&local_config.network).await?;
+  let make_request = |_depth: u8| Ok(request.clone());
+  netmap::send_discovery_requests(&sock_v4, sock_v6.as_deref(), false, &local_config.network, &local_config.peer, &Default::default(), &make_request).await?;
+
+  let deadline = tokio::time::Instant::now() + HISTORY_WINDOW;
+  let endpoints = netmap::reply_endpoints(&sock_v4, sock_v6.as_ref(), fabric);
+  let (v4, v6) = tokio::join!(collect_history(executor, &endpoints[0], request_uuid, deadline), async {
+    match endpoints.get(1) {
+      Some(sock) => collect_history(executor, sock, request_uuid, deadline).await,
+      None => 0,
+    }
+  });
+  Ok(v4 + v6)
+}
+
+/// A signed HistoryRequest for every room, asking from a little before the newest message
+/// `executor` already holds (see [`HISTORY_OVERLAP_S`]). Returns it with its request id.
+pub(crate) fn history_request(executor: &executor::Executor, key: &ed25519_dalek::SigningKey, source: &config::IdentityData) -> ([u8; 16], messages::NetworkMessage) {
+  let since_epoch_s = executor.newest_message_epoch().map(|e| e.saturating_sub(HISTORY_OVERLAP_S)).unwrap_or(0);
+  let request_uuid = discovery::random_uuid16();
+  let epoch_s = sys_utils::epoch_seconds_now_utc0();
+  let payload = messages::history_request_payload(None, since_epoch_s, epoch_s);
+  let signature = config::IdentityData::sign_payload(key, &request_uuid, &payload).to_bytes().to_vec();
+  (request_uuid, messages::NetworkMessage::HistoryRequest { source: source.clone(), request_uuid, room: None, since_epoch_s, epoch_s, signature })
+}
+
+/// Record the messages in the HistoryReplies to `request_uuid` that arrive on `sock` before
+/// `deadline`, each checked like live traffic. Returns how many were new to `executor`.
+pub(crate) async fn collect_history(executor: &executor::Executor, sock: &transport::Endpoint, request_uuid: [u8; 16], deadline: tokio::time::Instant) -> usize {
+  let mut recorded = 0;
+  let mut buf = vec![0u8; 64 * 1024];
+  while let Ok(Ok((len, from))) = tokio::time::timeout_at(deadline, sock.recv_from(&mut buf)).await {
+    let Some(messages::NetworkMessage::HistoryReply { request_uuid: ru, entries }) = sock.fabric().decode(&buf[..len]) else { continue };
+    if ru != request_uuid { continue; }
+    for entry in entries {
+      let Ok(msg) = serde_bare::from_slice::<messages::NetworkMessage>(&entry.message) else { continue };
+      if serve::accept_signed_message(executor, "history", from, &msg, entry.epoch_s).is_some_and(|seq| seq > 0) {
+        recorded += 1;
+      }
+    }
+  }
+  recorded
+}
+
 /// Resolve which chat program bytes to run: explicit `--program`, else the embedded program, else the
 /// on-disk compiled example (dev fallback). Mirrors netmap's resolution so a moved binary is
 /// self-contained.
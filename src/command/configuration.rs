@@ -6,12 +6,18 @@ pub async fn configuration(args: &args::Args, style: ConfigStyle) -> DynResult<(
   match config::Config::read_from_file(&config_path).await {
     Ok(config_struct) => {
       tracing::info!("Configuration from {:?}", config_path);
+      for o in config::ConfigOverride::active() {
+        tracing::info!("Overridden by {}: {} = {}", o.origin, o.setting, o.value);
+      }
       tracing::info!("{:#?}", config_struct);
 
       if style == ConfigStyle::CreateMissingKeys {
         if !tokio::fs::metadata(&config_struct.identity.keyfile).await.is_ok() {
           tracing::warn!("The file {:?} does not exist and a new identity will be generated.", &config_struct.identity.keyfile);
-          crypto_utils::generate_private_key_ed25519_pem_file(&config_struct.identity.keyfile).await?;
+          // Encrypt it straight away when a passphrase is set up for the daemon to unlock it with.
+          let passphrase = keyfile::configured_passphrase(&config_struct.identity).await?;
+          let key = ed25519_dalek::SigningKey::generate(&mut rand::rngs::OsRng);
+          keyfile::write(&config_struct.identity.keyfile, &key, passphrase.as_deref()).await?;
         }
         else {
           tracing::warn!("The file {:?} already exists, refusing to overwrite with new key material!", &config_struct.identity.keyfile);
@@ -42,3 +48,50 @@ pub async fn configuration(args: &args::Args, style: ConfigStyle) -> DynResult<(
   }
   Ok(())
 }
+
+/// `weverywhere config check`: print every problem with the config, and fail if there are any.
+pub async fn check(args: &args::Args) -> DynResult<()> {
+  let config_path = args.config_path();
+  let loaded = config::LoadedConfig::load(&config_path).await.map_err(|e| format!("{}: {}", config_path.display(), e))?;
+  let problems = loaded.check().await;
+  for problem in problems.iter() {
+    println!("{problem}");
+  }
+  if !problems.is_empty() {
+    return Err(format!("{} problem(s) in {} and its includes", problems.len(), config_path.display()).into());
+  }
+  let includes = loaded.layers.iter().filter(|l| l.kind == config::LayerKind::Include).count();
+  println!("{} and {} include file(s): no problems found.", config_path.display(), includes);
+  Ok(())
+}
+
+/// `weverywhere config explain`: print each effective setting with the file that set it.
+pub async fn explain(args: &args::Args) -> DynResult<()> {
+  let config_path = args.config_path();
+  let loaded = config::LoadedConfig::load(&config_path).await.map_err(|e| format!("{}: {}", config_path.display(), e))?;
+  for layer in loaded.layers.iter() {
+    let kind = match layer.kind {
+      config::LayerKind::Main => "main    ",
+      config::LayerKind::Include => "include ",
+      config::LayerKind::Override => "override",
+    };
+    println!("# {} {}", kind, layer.path.display());
+  }
+  for problem in loaded.problems.iter() {
+    println!("# problem: {problem}");
+  }
+  let mut settings = loaded.sources();
+  // Successors trusted through a recorded key rotation, credited to the rotations file.
+  let rotations_path = key_rotation::path(&loaded.config.state);
+  for (i, rotation) in key_rotation::in_effect(&loaded.config).iter().enumerate() {
+    let key = format!("{} (from {})", crypto_utils::format_verifying_key(&rotation.new), crypto_utils::short_id(rotation.old.as_bytes()));
+    settings.push((format!("rotated[{i}]"), serde_json::Value::String(key), Some(rotations_path.as_path())));
+  }
+  let width = settings.iter().map(|(path, value, _)| path.len() + value.to_string().len()).max().unwrap_or(0);
+  for (path, value, source) in settings {
+    let setting = format!("{path} = {value}");
+    let source = source.map_or_else(|| "default".to_string(), |file| file.display().to_string());
+    println!("{setting:<pad$}  # {source}", pad = width + 3);
+  }
+  Ok(())
+}
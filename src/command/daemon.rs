@@ -12,10 +12,12 @@ pub async fn daemon(action: &DaemonAction, _args: &args::Args) -> DynResult<()>
   match action {
     DaemonAction::Install   => {
       backend::install().await?;
-      // The installed daemon runs `serve` (no --port), so open the host firewall for serve's
-      // default UDP port now, while we already hold the elevation `install` required. `serve` also
+      // The installed daemon runs `serve` (no --port), so open the host firewall for the UDP ports
+      // its config serves now, while we already hold the elevation `install` required. `serve` also
       // re-ensures this at startup; doing it here means the node is reachable the moment it starts.
-      crate::firewall::ensure_inbound_udp_allowed(DEFAULT_SERVE_PORT).await;
+      for port in served_ports().await {
+        crate::firewall::ensure_inbound_udp_allowed(port).await;
+      }
       Ok(())
     }
     DaemonAction::Uninstall => backend::uninstall().await,
@@ -26,10 +28,25 @@ pub async fn daemon(action: &DaemonAction, _args: &args::Args) -> DynResult<()>
   }
 }
 
-/// UDP port the installed daemon will `serve` on. Mirrors the `--port` default of the `Serve`
-/// subcommand in `args.rs`; `serve_args_vec()` deliberately passes no `--port`, so the daemon always
-/// uses this default - and this is therefore the port to open in the firewall at install time.
-const DEFAULT_SERVE_PORT: u16 = 2240;
+/// The UDP ports the installed daemon will `serve` on: `[network].port` and each served fabric's
+/// (derived) port, read from the config it will be started with (see `serve_args_vec()`, which
+/// passes no `--port`). A config that can't be read yet falls back to the default port.
+async fn served_ports() -> Vec<u16> {
+  let path = installed_config_path().unwrap_or_else(args::default_config_path);
+  match config::Config::read_from_file(&path).await {
+    Ok(config) => {
+      let mut ports: Vec<u16> = config.served_fabrics().iter().map(|view| view.network.port).collect();
+      ports.sort_unstable();
+      ports.dedup();
+      ports
+    }
+    Err(e) => {
+      let port = config::NetworkConfig::default().port;
+      tracing::warn!("Could not read {} ({:?}); opening the firewall for the default port {}", path.display(), e, port);
+      vec![port]
+    }
+  }
+}
 
 /// Absolute path to the currently running executable - this is what the daemon runs at boot.
 fn this_exe() -> DynResult<std::path::PathBuf> {
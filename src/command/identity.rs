@@ -0,0 +1,125 @@
+use super::*;
+
+/// How many times `identity rotate` sends its statement, and how far apart. It goes out over UDP like
+/// everything else; daemons that already took it ignore the repeats.
+const ROTATION_SENDS: usize = 3;
+const ROTATION_SEND_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// `weverywhere identity`: show, export, import, rotate or (un)encrypt the `[identity] keyfile`.
+pub async fn identity(args: &args::Args, action: &args::IdentityAction) -> DynResult<()> {
+  let local_config = config::Config::read_from_file(&args.config_path()).await.map_err(map_loc_err!())?.fabric_view(args.fabric_name.as_deref());
+  let keyfile = &local_config.identity.keyfile;
+  let missing = |e| format!("could not read the identity key {} ({e}); create one with `weverywhere generate-missing-keys` or `weverywhere identity import`", keyfile.display());
+
+  match action {
+    args::IdentityAction::Show => {
+      let key = local_config.identity.read_private_key_ed25519_pem_file().await.map_err(missing)?.verifying_key();
+      println!("{}", crypto_utils::format_verifying_key(&key));
+      println!("name:        {:?}", local_config.identity.name);
+      println!("short id:    {}", crypto_utils::short_id(key.as_bytes()));
+      println!("fingerprint: {}", crypto_utils::fingerprint(&key));
+      println!("keyfile:     {}", keyfile.display());
+      println!("encrypted:   {}", if keyfile::unlock(&local_config.identity).await?.passphrase.is_some() { "yes" } else { "no" });
+    }
+    args::IdentityAction::Export { format, public } => {
+      let key = local_config.identity.read_private_key_ed25519_pem_file().await.map_err(missing)?;
+      print!("{}", export(&key, *format, *public, &local_config.identity.name)?);
+    }
+    args::IdentityAction::Import { file, force } => {
+      let text = tokio::fs::read_to_string(file).await.map_err(|e| format!("could not read {}: {}", file.display(), e))?;
+      let key = crypto_utils::signing_key_from_openssh(&text).map_err(|e| format!("{}: {}", file.display(), e))?;
+      if tokio::fs::metadata(keyfile).await.is_ok() && !force
+        && !sys_utils::ask_user_proceed_yn(&format!("Replace the identity key in {}? Peers that trust it won't trust the imported key. (y/n) ", keyfile.display())).await {
+        return Err("not replacing the existing identity key (pass --force to skip this question)".into());
+      }
+      let passphrase = keyfile::configured_passphrase(&local_config.identity).await?;
+      let kept = replace_keyfile(keyfile, &key, passphrase.as_deref()).await?;
+      println!("Imported {} as {}.", crypto_utils::format_verifying_key(&key.verifying_key()), keyfile.display());
+      if let Some(kept) = kept {
+        println!("The previous key is kept in {}.", kept.display());
+      }
+    }
+    args::IdentityAction::Rotate { multicast_groups, port } => {
+      let old = keyfile::unlock(&local_config.identity).await.map_err(missing)?;
+      let old_key = old.key;
+      let new_key = ed25519_dalek::SigningKey::generate(&mut rand::rngs::OsRng);
+      let statement = key_rotation::statement(&local_config.identity.name, &old_key, &new_key);
+      let kept = replace_keyfile(keyfile, &new_key, old.passphrase.as_deref()).await?.unwrap_or_default();
+
+      let mut network = local_config.network.clone();
+      network.apply_cli_overrides(multicast_groups.as_deref(), *port);
+      let bytes = network.fabric_id().encode(&statement)?;
+      let local_daemon = std::net::SocketAddr::from((std::net::Ipv4Addr::LOCALHOST, network.port));
+      for i in 0..ROTATION_SENDS {
+        if i > 0 {
+          tokio::time::sleep(ROTATION_SEND_INTERVAL).await;
+        }
+        run::broadcast_bytes_to_fabric(&bytes, &network, &local_config.peer).await?;
+        if let Ok(sock) = tokio::net::UdpSocket::bind((std::net::Ipv4Addr::UNSPECIFIED, 0)).await {
+          let _ = sock.send_to(&bytes, local_daemon).await;
+        }
+      }
+      println!("Rotated {} -> {}.", crypto_utils::short_id(old_key.verifying_key().as_bytes()), crypto_utils::short_id(new_key.verifying_key().as_bytes()));
+      println!("New key:  {}", crypto_utils::format_verifying_key(&new_key.verifying_key()));
+      println!("Old key kept in {}.", kept.display());
+      println!("Daemons on fabric {} that trusted the old key and heard this trust the new one; restart a daemon running as this identity to use it.", network.fabric_label());
+    }
+    args::IdentityAction::Passphrase { remove } => {
+      let key = local_config.identity.read_private_key_ed25519_pem_file().await.map_err(missing)?;
+      if *remove {
+        keyfile::write(keyfile, &key, None).await?;
+        println!("{} is no longer encrypted.", keyfile.display());
+        return Ok(());
+      }
+      let ask = |prompt: &str| sys_utils::ask_user_secret(prompt.to_string());
+      let passphrase = ask("New passphrase: ").await.ok_or("a new passphrase can only be given on a terminal")?;
+      if passphrase.is_empty() {
+        return Err("the passphrase is empty; use --remove to store the key unencrypted".into());
+      }
+      if ask("Again: ").await.as_deref() != Some(passphrase.as_str()) {
+        return Err("the passphrases differ; the keyfile is unchanged".into());
+      }
+      keyfile::write(keyfile, &key, Some(&passphrase)).await?;
+      println!("{} is encrypted; daemons need it in [identity] passphrase_file, passphrase_command or the systemd credential {}.", keyfile.display(), keyfile::SYSTEMD_CREDENTIAL);
+    }
+  }
+  Ok(())
+}
+
+/// `key` in `format`: the private key, or with `public` its public key.
+pub fn export(key: &ed25519_dalek::SigningKey, format: args::KeyFormat, public: bool, comment: &str) -> DynResult<String> {
+  use pkcs8::{EncodePrivateKey, EncodePublicKey};
+  let vk = key.verifying_key();
+  Ok(match (format, public) {
+    (args::KeyFormat::Ssh, false) => crypto_utils::signing_key_to_openssh(key, comment)?,
+    (args::KeyFormat::Ssh, true) => format!("{}\n", crypto_utils::format_verifying_key(&vk)),
+    (args::KeyFormat::Pem, false) => key.to_pkcs8_pem(pkcs8::LineEnding::LF)?.to_string(),
+    (args::KeyFormat::Pem, true) => vk.to_public_key_pem(pkcs8::LineEnding::LF)?,
+    (args::KeyFormat::Hex, false) => format!("{}\n", crypto_utils::to_hex(key.as_bytes())),
+    (args::KeyFormat::Hex, true) => format!("{}\n", crypto_utils::to_hex(vk.as_bytes())),
+  })
+}
+
+/// Write `key` to `keyfile` (encrypted under `passphrase`, if given), first moving any key already
+/// there aside to `<keyfile>.<epoch_s>.old` (`<keyfile>.<epoch_s>-<n>.old` if that is taken, so no
+/// kept key is ever overwritten). Returns where the old key went.
+async fn replace_keyfile(keyfile: &std::path::Path, key: &ed25519_dalek::SigningKey, passphrase: Option<&str>) -> DynResult<Option<std::path::PathBuf>> {
+  let mut kept = None;
+  if tokio::fs::metadata(keyfile).await.is_ok() {
+    let epoch_s = sys_utils::epoch_seconds_now_utc0();
+    let mut n = 0;
+    let old = loop {
+      let mut name = keyfile.file_name().unwrap_or_default().to_os_string();
+      name.push(if n == 0 { format!(".{epoch_s}.old") } else { format!(".{epoch_s}-{n}.old") });
+      let candidate = keyfile.with_file_name(name);
+      if tokio::fs::symlink_metadata(&candidate).await.is_err() {
+        break candidate;
+      }
+      n += 1;
+    };
+    tokio::fs::rename(keyfile, &old).await.map_err(|e| format!("could not move the old key aside to {}: {}", old.display(), e))?;
+    kept = Some(old);
+  }
+  keyfile::write(keyfile, key, passphrase).await?;
+  Ok(kept)
+}
@@ -8,9 +8,12 @@ pub mod install_to;
 pub mod daemon;
 pub mod run;
 pub mod run_local;
+pub mod submit;
 pub mod serve;
 pub mod netmap;
 pub mod chat;
+pub mod peers;
+pub mod identity;
 pub mod extract_programs;
 
 #[derive(Debug, Eq, PartialEq)]
@@ -31,22 +34,67 @@ pub async fn run_command(cmd: &args::Command, args: &args::Args) -> DynResult<()
     Command::GenerateMissingKeys { } => {
       configuration::configuration(args, ConfigStyle::CreateMissingKeys).await.map_err(map_loc_err!())?;
     }
+    Command::Config { action: ConfigAction::Check } => {
+      configuration::check(args).await.map_err(map_loc_err!())?;
+    }
+    Command::Config { action: ConfigAction::Explain } => {
+      configuration::explain(args).await.map_err(map_loc_err!())?;
+    }
     Command::InstallTo { install_root, install_etc, install_bin } => {
       install_to::install_to(install_root, install_etc, install_bin).await.map_err(map_loc_err!())?;
     }
-    Command::Run { file_path, fabric, multicast_groups, port, arg, arg_list } => {
+    Command::Run { file_path, fabric, peer, to, stdin, tty, tty_record, min_responders, max_responders, timeout, select, collect, multicast_groups, port, arg, arg_list } => {
+      let arg_map = args::parse_arg_map(arg);
+      let fan_out = crate::fanout::FanOutPolicy {
+        min_responders: *min_responders,
+        max_responders: *max_responders,
+        timeout: match timeout {
+          Some(secs) => Some(std::time::Duration::try_from_secs_f64(*secs).map_err(|e| format!("--timeout {secs}: {e}"))?),
+          None => None,
+        },
+        select: select.clone(),
+        collect: *collect,
+      };
+      let opts = run::RunOptions { fabric: *fabric, peer: peer.clone(), to: to.clone(), stdin: stdin.clone(), tty: *tty, tty_record: tty_record.clone(), fan_out };
+      let exit_code = run::run(args, file_path, multicast_groups.clone(), *port, arg_list.clone(), arg_map, opts).await.map_err(map_loc_err!())?;
+      // Exit like the remote program did, so `run` composes in scripts.
+      if exit_code != 0 {
+        std::process::exit(exit_code as i32);
+      }
+    }
+    Command::Submit { file_path, count, probe, probe_timeout, timeout, multicast_groups, port, arg, arg_list } => {
       let arg_map = args::parse_arg_map(arg);
-      run::run(args, file_path, *fabric, multicast_groups.clone(), *port, arg_list.clone(), arg_map).await.map_err(map_loc_err!())?;
+      let probe_window = std::time::Duration::try_from_secs_f64(*probe_timeout).map_err(|e| format!("--probe-timeout {probe_timeout}: {e}"))?;
+      let exit_timeout = timeout.map(std::time::Duration::try_from_secs_f64).transpose().map_err(|e| format!("--timeout {timeout:?}: {e}"))?;
+      let exit_code = submit::submit(args, file_path, *count, probe.clone(), probe_window, exit_timeout, multicast_groups.clone(), *port, arg_list.clone(), arg_map).await.map_err(map_loc_err!())?;
+      if exit_code != 0 {
+        std::process::exit(exit_code as i32);
+      }
     }
-    Command::RunLocal { file_path, arg, arg_list, multicast_groups, port } => {
+    Command::RunLocal { file_path, arg, arg_list, multicast_groups, port, tty, tty_script, tty_dump, tty_size, tty_timeout, tty_record } => {
       let arg_map = args::parse_arg_map(arg);
-      run_local::run_local(file_path, args, arg_list.clone(), arg_map, multicast_groups.clone(), *port).await.map_err(map_loc_err!())?;
+      let tty = run_local::LocalTty {
+        attach: *tty,
+        script: tty_script.clone(),
+        dump: tty_dump.clone(),
+        size: (tty_size.0, tty_size.1),
+        timeout: std::time::Duration::try_from_secs_f64(*tty_timeout).map_err(|e| format!("--tty-timeout {tty_timeout}: {e}"))?,
+        record: tty_record.clone(),
+      };
+      run_local::run_local(file_path, args, arg_list.clone(), arg_map, multicast_groups.clone(), *port, tty).await.map_err(map_loc_err!())?;
     }
     Command::Serve { multicast_groups, port } => {
       serve::serve(args, multicast_groups.clone(), *port).await.map_err(map_loc_err!())?;
     }
-    Command::Netmap { program, local, multicast_groups, port } => {
-      netmap::netmap(args, program.clone(), *local, multicast_groups.clone(), *port).await.map_err(map_loc_err!())?;
+    Command::Netmap { program, local, multicast_groups, port, format, watch, timeout, idle, expect } => {
+      let mut policy = discovery::CollectPolicy::default();
+      if let Some(secs) = timeout {
+        policy.timeout = std::time::Duration::try_from_secs_f64(*secs).map_err(|e| format!("--timeout {secs}: {e}"))?;
+      }
+      if let Some(secs) = idle {
+        policy.idle = std::time::Duration::try_from_secs_f64(*secs).map_err(|e| format!("--idle {secs}: {e}"))?;
+      }
+      netmap::netmap(args, program.clone(), *local, multicast_groups.clone(), *port, *format, *watch, policy, *expect).await.map_err(map_loc_err!())?;
     }
     Command::Chat { program, multicast_groups, port } => {
       chat::chat(args, program.clone(), multicast_groups.clone(), *port).await.map_err(map_loc_err!())?;
@@ -54,6 +102,12 @@ pub async fn run_command(cmd: &args::Command, args: &args::Args) -> DynResult<()
     Command::ExtractPrograms { out_dir } => {
       extract_programs::extract_programs(out_dir).await.map_err(map_loc_err!())?;
     }
+    Command::Peers { action } => {
+      peers::peers(args, action).await.map_err(map_loc_err!())?;
+    }
+    Command::Identity { action } => {
+      identity::identity(args, action).await.map_err(map_loc_err!())?;
+    }
     Command::Daemon { action } => {
       daemon::daemon(action, args).await.map_err(map_loc_err!())?;
     }
@@ -1,27 +1,36 @@
 
 use super::*;
 
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
 use std::net::SocketAddr;
 
 /// `weverywhere netmap` entry point: send the discovery program onto the fabric (multicast + every
-/// configured [[peer]]), collect the signed per-node records that relay back for a fixed window, and
-/// print a trust-annotated tree. Each node returns a signed attestation binding its hostname to its
-/// identity key, so the caller can prove every entry represents only itself. See readme "Network
+/// configured [[peer]]), collect the signed per-node records that relay back until `policy` says
+/// everyone has answered (by default: every known peer, else a quiet period; `expect` overrides the
+/// known-peer count), and print the map in `format` (a trust-annotated tree by default; see [`topology::NetworkMap`]). Each
+/// node returns a signed attestation binding its hostname to its identity key, so the caller can
+/// prove every entry represents only itself. With `watch`, discovery repeats every that many seconds
+/// and the changes between rounds are reported instead (see [`watch_fabric`]). See readme "Network
 /// discovery" and `example-programs/network-map.c`.
+#[allow(clippy::too_many_arguments)]
 pub async fn netmap(
   args: &args::Args,
   program: Option<std::path::PathBuf>,
   local: bool,
-  multicast_groups: args::MulticastAddressVec,
-  port: u16,
+  multicast_groups: Option<args::MulticastAddressVec>,
+  port: Option<u16>,
+  format: args::NetmapFormat,
+  watch: Option<f64>,
+  mut policy: discovery::CollectPolicy,
+  expect: Option<usize>,
 ) -> DynResult<()> {
   let (wasm_bytes, program_label) = resolve_discovery_program(program).await?;
   if crate::v_is_info() {
     tracing::info!("[ netmap ] discovery program: {}", program_label);
   }
 
-  let local_config = config::Config::read_from_file(&args.config_path()).await.map_err(map_loc_err!())?;
+  let mut local_config = config::Config::read_from_file(&args.config_path()).await.map_err(map_loc_err!())?.fabric_view(args.fabric_name.as_deref());
+  local_config.network.apply_cli_overrides(multicast_groups.as_deref(), port);
   let source = config::IdentityData::generate_from_config(&local_config).await.map_err(map_loc_err!())?;
 
   // Our own identity pubkey: the tree root, and seeded into the visited-set so no node forwards back
@@ -40,89 +49,449 @@ pub async fn netmap(
     }
   }
 
-  // One request UUID for this whole invocation; every node's records relay back tagged with it.
-  let request_uuid = discovery::random_uuid16();
-  let visited_init: Vec<Vec<u8>> = if our_pubkey.is_empty() { Vec::new() } else { vec![our_pubkey.clone()] };
+  match expect {
+    Some(0) => {}
+    Some(n) => policy.expected_count = Some(n),
+    // Just the local daemon: one answer is all there is.
+    None if local => policy.expected_count = Some(1),
+    None => policy.expected_keys = known_peer_keys(&local_config).await,
+  }
 
-  // Collected records: raw (responder addr, cbor bytes) for replies carrying OUR request UUID.
-  let collected: std::sync::Arc<tokio::sync::Mutex<Vec<(SocketAddr, Vec<u8>)>>> =
-    std::sync::Arc::new(tokio::sync::Mutex::new(Vec::new()));
+  let probe = FabricProbe { wasm_bytes, source, our_pubkey, trusted, peers: local_config.peer.clone(), local, network: local_config.network.clone() };
+  if let Some(interval) = watch {
+    return watch_fabric(&probe, policy, expect.is_none() && !local, interval, format).await;
+  }
 
-  // Build a per-target execute request (the depth budget differs by trust), encode + send.
-  let make_request = |depth: u8| -> DynResult<Vec<u8>> {
-    let pd = executor::ProgramDataBuilder::new()
-      .set_human_name(EMBEDDED_DISCOVERY_NAME.to_string())
-      .set_wasm_program_bytes(&wasm_bytes)
-      .set_source(&source)
-      .set_request_context(request_uuid, depth, visited_init.clone())
-      .build().map_err(map_loc_err!())?;
-    Ok(serde_bare::to_vec(&messages::NetworkMessage::ExecuteRequest { program_data: pd })?)
+  let map = probe.collect(policy).await?;
+  for w in map.warnings.iter() {
+    eprintln!("[netmap] WARNING: {}", w);
+  }
+  match format {
+    args::NetmapFormat::Tree => print!("{}", map.render_tree()),
+    args::NetmapFormat::Json => println!("{}", serde_json::to_string_pretty(&map.render_json()).map_err(map_loc_err!())?),
+    args::NetmapFormat::Dot => print!("{}", map.render_dot()),
+    args::NetmapFormat::Mermaid => print!("{}", map.render_mermaid()),
+  }
+  Ok(())
+}
+
+/// Keys of the nodes we expect a discovery round to reach: the keys pinned on our [[peer]]s, the ones
+/// recorded in the known-peers file, and the unexpired entries of the peer registry.
+async fn known_peer_keys(config: &config::Config) -> HashSet<Vec<u8>> {
+  let mut keys: HashSet<Vec<u8>> = config.peer.iter()
+    .filter_map(|p| p.expected_key_str())
+    .filter_map(|k| crypto_utils::public_key_to_ed25519_vk(k).ok())
+    .map(|vk| vk.as_bytes().to_vec())
+    .collect();
+  if let Ok(known) = known_peers::load(&known_peers::path(&config.state)).await {
+    keys.extend(known.into_iter().map(|k| k.key.as_bytes().to_vec()));
+  }
+  if let Ok(registry) = peer_registry::load(&peer_registry::path(&config.state)).await {
+    let now = sys_utils::epoch_seconds_now_utc0();
+    keys.extend(registry.iter()
+      .filter(|r| !r.is_expired(now, config.state.peer_ttl_s))
+      .filter_map(|r| crypto_utils::from_hex(&r.pubkey)));
+  }
+  keys
+}
+
+/// Everything one discovery round needs, so `--watch` can repeat it.
+struct FabricProbe {
+  wasm_bytes: Vec<u8>,
+  source: config::IdentityData,
+  our_pubkey: Vec<u8>,
+  trusted: HashSet<Vec<u8>>,
+  peers: Vec<config::PeerMetadata>,
+  local: bool,
+  network: config::NetworkConfig,
+}
+
+impl FabricProbe {
+  /// Run one discovery round under a fresh request UUID: send the program (telling every hop how
+  /// long we'll listen), collect records until `policy` says we've heard everyone, and assemble the
+  /// verified map.
+  async fn collect(&self, policy: discovery::CollectPolicy) -> DynResult<topology::NetworkMap> {
+    // One request UUID for this whole round; every node's records relay back tagged with it.
+    let request_uuid = discovery::random_uuid16();
+    let visited_init: Vec<Vec<u8>> = if self.our_pubkey.is_empty() { Vec::new() } else { vec![self.our_pubkey.clone()] };
+
+    // Collected records: raw (responder addr, cbor bytes) for replies carrying OUR request UUID.
+    let collected: std::sync::Arc<tokio::sync::Mutex<Vec<(SocketAddr, Vec<u8>)>>> =
+      std::sync::Arc::new(tokio::sync::Mutex::new(Vec::new()));
+
+    // Build a per-target execute request (the depth budget differs by trust), encode + send.
+    let time_budget_ms = policy.time_budget_ms();
+    let make_request = |depth: u8| -> DynResult<Vec<u8>> {
+      let pd = executor::ProgramDataBuilder::new()
+        .set_human_name(EMBEDDED_DISCOVERY_NAME.to_string())
+        .set_wasm_program_bytes(&self.wasm_bytes)
+        .set_source(&self.source)
+        .set_request_context(request_uuid, depth, visited_init.clone())
+        .set_time_budget_ms(time_budget_ms)
+        .build().map_err(map_loc_err!())?;
+      self.network.fabric_id().encode(&messages::NetworkMessage::execute_request(pd))
+    };
+
+    // Sockets we both send from and collect replies on (nodes reply to the address they were contacted
+    // from). One per family; the v6 socket is best-effort, and skipped when [network] turns IPv6 off.
+    let (sock_v4, sock_v6) = bind_request_sockets(&self.network).await?;
+
+    // Start collectors before sending so nothing is missed.
+    let collection = std::sync::Arc::new(std::sync::Mutex::new(discovery::Collection::new(policy, std::time::Instant::now())));
+    let mut collectors = tokio::task::JoinSet::new();
+    for sock in reply_endpoints(&sock_v4, sock_v6.as_ref(), self.network.fabric_id()) {
+      let (collection, collected) = (collection.clone(), collected.clone());
+      collectors.spawn(async move { collect_until(&sock, request_uuid, collection, collected).await; });
+    }
+
+    send_discovery_requests(&sock_v4, sock_v6.as_deref(), self.local, &self.network, &self.peers, &self.trusted, &make_request).await?;
+
+    // Listen until the policy says everyone has answered.
+    collectors.join_all().await;
+
+    let replies = collected.lock().await;
+    let mut map = topology::NetworkMap::assemble(&self.source.human_name, &self.our_pubkey, &replies, sys_utils::epoch_seconds_now_utc0());
+    map.fabric = self.network.fabric.clone();
+    Ok(map)
+  }
+}
+
+/// `netmap --watch`: run a discovery round every `interval` seconds (each collecting under `policy`,
+/// cut to the interval, and with `learn_expected` also expecting every node the last round found)
+/// and report what changed, as a redrawn full-screen view when a terminal is attached
+/// or as one event line per change otherwise (JSON objects with `--format json`).
+async fn watch_fabric(probe: &FabricProbe, policy: discovery::CollectPolicy, learn_expected: bool, interval: f64, format: args::NetmapFormat) -> DynResult<()> {
+  use std::io::IsTerminal;
+  if !interval.is_finite() || interval < 1.0 {
+    return Err(format!("--watch needs an interval of at least 1 second, got {interval}").into());
+  }
+  if matches!(format, args::NetmapFormat::Dot | args::NetmapFormat::Mermaid) {
+    return Err("--watch reports changes as they happen; use --format tree (the default) or json".into());
+  }
+  let interval = std::time::Duration::from_secs_f64(interval);
+  let round_policy = |fabric: &topology::FabricWatch| {
+    let mut policy = policy.clone();
+    policy.timeout = policy.timeout.min(interval);
+    if learn_expected {
+      policy.expected_keys.extend(fabric.current.nodes.keys().cloned());
+    }
+    policy
   };
 
-  // Sockets we both send from and collect replies on (nodes reply to the address they were contacted
-  // from). One per family; the v6 socket is best-effort.
-  let sock_v4 = std::sync::Arc::new(tokio::net::UdpSocket::bind((std::net::Ipv4Addr::UNSPECIFIED, 0)).await.map_err(map_loc_err!())?);
-  let sock_v6 = tokio::net::UdpSocket::bind((std::net::Ipv6Addr::UNSPECIFIED, 0)).await.ok().map(std::sync::Arc::new);
+  // The full-screen view only makes sense for the tree format on a real terminal.
+  let tty = if format == args::NetmapFormat::Tree && std::io::stdout().is_terminal() {
+    crate::tty::attach().ok()
+  } else {
+    None
+  };
+  let started = tokio::time::Instant::now();
+  let mut fabric = topology::FabricWatch::default();
+  let mut log: std::collections::VecDeque<(u64, topology::MapChange)> = std::collections::VecDeque::new();
+  if let Some((tty, _guard)) = &tty {
+    draw_watch(tty, &fabric, &log, interval);
+  }
+  loop {
+    let round_start = tokio::time::Instant::now();
+    let map = match &tty {
+      Some((tty, _guard)) => tokio::select! {
+        map = probe.collect(round_policy(&fabric)) => map?,
+        _ = until_quit(tty, || draw_watch(tty, &fabric, &log, interval)) => return Ok(()),
+      },
+      None => probe.collect(round_policy(&fabric)).await?,
+    };
+    let changes = fabric.update(map);
+    match &tty {
+      Some((tty, _guard)) => {
+        for change in changes {
+          log.push_back((fabric.rounds, change));
+        }
+        while log.len() > WATCH_LOG_LEN {
+          log.pop_front();
+        }
+        draw_watch(tty, &fabric, &log, interval);
+      }
+      None => {
+        let elapsed = started.elapsed().as_secs();
+        for w in fabric.current.warnings.iter() {
+          eprintln!("[netmap] WARNING: {}", w);
+        }
+        for change in changes.iter() {
+          if format == args::NetmapFormat::Json {
+            let mut event = change.to_json();
+            event["round"] = serde_json::json!(fabric.rounds);
+            println!("{}", event);
+          } else {
+            println!("[netmap] round {} (+{}s) {}", fabric.rounds, elapsed, change.describe());
+          }
+        }
+      }
+    }
 
-  // Start collectors before sending so nothing is missed.
-  let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(12);
-  let mut collectors = tokio::task::JoinSet::new();
-  {
-    let sock = sock_v4.clone();
-    let collected = collected.clone();
-    collectors.spawn(async move { collect_until(&sock, request_uuid, deadline, collected).await; });
+    // Wait out the rest of the interval; on a terminal, q / Esc / Ctrl-C quits.
+    let next_round = round_start + interval;
+    match &tty {
+      Some((tty, _guard)) => tokio::select! {
+        _ = tokio::time::sleep_until(next_round) => {}
+        _ = until_quit(tty, || draw_watch(tty, &fabric, &log, interval)) => return Ok(()),
+      },
+      None => tokio::time::sleep_until(next_round).await,
+    }
+  }
+}
+
+/// Resolve once the user presses q, Esc or Ctrl-C, calling `redraw` whenever the terminal resizes.
+async fn until_quit(tty: &crate::tty::TtyHandle, redraw: impl Fn()) {
+  use crate::tty::TtyEvent;
+  loop {
+    match tty.next_event(std::time::Duration::from_secs(60)).await {
+      Some(TtyEvent::Char('q')) | Some(TtyEvent::Esc) | Some(TtyEvent::CtrlC) => return,
+      Some(TtyEvent::Resize(..)) => redraw(),
+      Some(_) => {}
+      // A timeout, or the input thread ended; don't spin on the latter.
+      None => tokio::time::sleep(std::time::Duration::from_millis(500)).await,
+    }
+  }
+}
+
+/// How many change events the full-screen watch view keeps.
+const WATCH_LOG_LEN: usize = 200;
+
+/// Redraw the full-screen watch view: a status line, the current tree, then the most recent changes
+/// (key changes in red) in whatever rows are left.
+fn draw_watch(tty: &crate::tty::TtyHandle, fabric: &topology::FabricWatch, log: &std::collections::VecDeque<(u64, topology::MapChange)>, interval: std::time::Duration) {
+  let (cols, rows) = tty.size();
+  let width = cols as usize;
+  let fit = |s: &str| s.chars().take(width).collect::<String>();
+  tty.clear();
+
+  let alarms = log.iter().filter(|(_, c)| c.is_alarming()).count();
+  tty.move_to(0, 0);
+  tty.style(-1, -1, 0b100);
+  tty.print(&fit(&format!(
+    "netmap --watch  round {}  every {}s  {} node(s)  (q to quit){:width$}",
+    fabric.rounds, interval.as_secs_f64(), fabric.current.nodes.len(), "", width = width
+  )));
+  tty.style(-1, -1, 0);
+  let mut row: u16 = 1;
+  if alarms > 0 {
+    tty.move_to(0, row);
+    tty.style(9, -1, 0b001);
+    tty.print(&fit(&format!("!! {alarms} key change(s) this session - possible impersonation, see below")));
+    tty.style(-1, -1, 0);
+    row += 1;
   }
-  if let Some(sock6) = &sock_v6 {
-    let sock = sock6.clone();
-    let collected = collected.clone();
-    collectors.spawn(async move { collect_until(&sock, request_uuid, deadline, collected).await; });
+
+  // Keep at least a third of the screen for the change log.
+  let log_rows = (rows / 3).max(4);
+  let tree_end = rows.saturating_sub(log_rows + 1);
+  let tree = if fabric.rounds == 0 { "\ncollecting the first round...\n".to_string() } else { fabric.current.render_tree() };
+  for line in tree.lines() {
+    if row >= tree_end {
+      tty.move_to(0, row);
+      tty.print(&fit("  ..."));
+      row += 1;
+      break;
+    }
+    tty.move_to(0, row);
+    tty.print(&fit(line));
+    row += 1;
   }
 
+  tty.move_to(0, row);
+  tty.style(-1, -1, 0b001);
+  tty.print(&fit("changes:"));
+  tty.style(-1, -1, 0);
+  row += 1;
+  let room = rows.saturating_sub(row) as usize;
+  let skip = log.len().saturating_sub(room);
+  for (round, change) in log.iter().skip(skip) {
+    tty.move_to(0, row);
+    if change.is_alarming() {
+      tty.style(9, -1, 0b001);
+    }
+    tty.print(&fit(&format!("  round {round}  {}", change.describe())));
+    tty.style(-1, -1, 0);
+    row += 1;
+  }
+  tty.flush();
+}
+
+/// The sockets a discovery pass sends from and collects replies on (nodes reply to the address they
+/// were contacted from): one per family, the v6 one best-effort and only when `[network]` enables it.
+pub(crate) async fn bind_request_sockets(network: &config::NetworkConfig) -> DynResult<(std::sync::Arc<tokio::net::UdpSocket>, Option<std::sync::Arc<tokio::net::UdpSocket>>)> {
+  let sock_v4 = std::sync::Arc::new(tokio::net::UdpSocket::bind((std::net::Ipv4Addr::UNSPECIFIED, 0)).await.map_err(map_loc_err!())?);
+  let sock_v6 = match network.ipv6 {
+    true => tokio::net::UdpSocket::bind((std::net::Ipv6Addr::UNSPECIFIED, 0)).await.ok().map(std::sync::Arc::new),
+    false => None,
+  };
+  Ok((sock_v4, sock_v6))
+}
+
+/// The [`bind_request_sockets`] pair as endpoints on `fabric`, for reading the replies through.
+pub(crate) fn reply_endpoints(sock_v4: &std::sync::Arc<tokio::net::UdpSocket>, sock_v6: Option<&std::sync::Arc<tokio::net::UdpSocket>>, fabric: messages::FabricId) -> Vec<transport::Endpoint> {
+  std::iter::once(sock_v4).chain(sock_v6)
+    .map(|sock| transport::Endpoint::new(sock.clone()).scoped(fabric))
+    .collect()
+}
+
+/// Send the discovery request: to the local daemon only (`local`), or multicast to every `[network]`
+/// group out every interface it allows plus a unicast to each configured [[peer]]. `make_request`
+/// builds the encoded request for a given depth budget, which depends on how far we trust the first hop.
+pub(crate) async fn send_discovery_requests(
+  sock_v4: &tokio::net::UdpSocket,
+  sock_v6: Option<&tokio::net::UdpSocket>,
+  local: bool,
+  network: &config::NetworkConfig,
+  peers: &[config::PeerMetadata],
+  trusted: &HashSet<Vec<u8>>,
+  make_request: &(dyn Fn(u8) -> DynResult<Vec<u8>> + Sync),
+) -> DynResult<()> {
+  let port = network.port;
   if local {
     // Only the local daemon: unicast to loopback with the untrusted budget (it's just us).
     let req = make_request(discovery::UNTRUSTED_FORWARD_DEPTH)?;
     let _ = sock_v4.send_to(&req, (std::net::Ipv4Addr::LOCALHOST, port)).await;
-  } else {
-    // Multicast to every group on every interface (untrusted budget - arbitrary hosts).
-    let mcast_req = make_request(discovery::UNTRUSTED_FORWARD_DEPTH)?;
-    for (_iface_idx, _iface_name, iface_addrs) in net_utils::get_interfaces().into_iter() {
-      if iface_addrs.is_empty() { continue; }
-      for group in multicast_groups.iter() {
-        match group {
-          std::net::IpAddr::V4(g) => {
-            let _ = sock_v4.set_multicast_ttl_v4(4);
-            let _ = sock_v4.send_to(&mcast_req, (*g, port)).await;
-          }
-          std::net::IpAddr::V6(g) => {
-            if let Some(sock6) = &sock_v6 {
-              let _ = sock6.send_to(&mcast_req, (*g, port)).await;
-            }
+    return Ok(());
+  }
+  // Multicast to every group on every interface (untrusted budget - arbitrary hosts). Egress is
+  // pinned to each interface in turn so the copies go out exactly the NICs [network] allows.
+  let mcast_req = make_request(discovery::UNTRUSTED_FORWARD_DEPTH)?;
+  for (iface_idx, _iface_name, iface_addrs) in net_utils::fabric_interfaces(network).into_iter() {
+    if iface_addrs.is_empty() { continue; }
+    for group in network.groups() {
+      match group {
+        std::net::IpAddr::V4(g) => {
+          let Some(iface_v4) = iface_addrs.iter().find_map(|a| match a { std::net::IpAddr::V4(v4) => Some(*v4), _ => None }) else { continue };
+          let sock = socket2::SockRef::from(sock_v4);
+          let _ = sock.set_multicast_if_v4(&iface_v4);
+          let _ = net_utils::set_multicast_hops(sock, &group, network);
+          let _ = sock_v4.send_to(&mcast_req, (g, port)).await;
+        }
+        std::net::IpAddr::V6(g) => {
+          if let Some(sock6) = sock_v6 {
+            let sock = socket2::SockRef::from(sock6);
+            let _ = sock.set_multicast_if_v6(iface_idx);
+            let _ = net_utils::set_multicast_hops(sock, &group, network);
+            let _ = sock6.send_to(&mcast_req, (g, port)).await;
           }
         }
       }
     }
-    // Unicast to every configured [[peer]] with a per-peer trust-based depth budget.
-    for peer in local_config.peer.iter() {
-      if let Some(addr) = net_utils::resolve_peer_addr(peer, port).await {
-        let peer_trusted = peer.expected_key_str()
-          .and_then(|s| crypto_utils::public_key_to_ed25519_vk(s).ok())
-          .map(|vk| trusted.contains(vk.as_bytes().as_slice()))
-          .unwrap_or(false);
-        let req = make_request(discovery::initial_depth_budget(peer_trusted))?;
-        match addr {
-          SocketAddr::V4(_) => { let _ = sock_v4.send_to(&req, addr).await; }
-          SocketAddr::V6(_) => { if let Some(sock6) = &sock_v6 { let _ = sock6.send_to(&req, addr).await; } }
-        }
+  }
+  // Unicast to every configured [[peer]] with a per-peer trust-based depth budget.
+  for peer in peers.iter() {
+    if let Some(addr) = net_utils::resolve_peer_addr(peer, port).await {
+      let peer_trusted = peer.expected_key_str()
+        .and_then(|s| crypto_utils::public_key_to_ed25519_vk(s).ok())
+        .map(|vk| trusted.contains(vk.as_bytes().as_slice()))
+        .unwrap_or(false);
+      let req = make_request(discovery::initial_depth_budget(peer_trusted))?;
+      match addr {
+        SocketAddr::V4(_) => { let _ = sock_v4.send_to(&req, addr).await; }
+        SocketAddr::V6(_) => { if let Some(sock6) = sock_v6 { let _ = sock6.send_to(&req, addr).await; } }
       }
     }
   }
+  Ok(())
+}
 
-  // Wait out the 12s collection window.
-  collectors.join_all().await;
+/// A node that answered a [`probe_neighbours`] pass with a valid, current attestation.
+#[derive(Debug, Clone)]
+pub(crate) struct DiscoveredNode {
+  pub hostname: String,
+  pub pubkey: Vec<u8>,
+  /// The node's self-reported serve address, or the address its reply came from.
+  pub addr: SocketAddr,
+  /// Whether the node trusts us (record key 2).
+  pub trusts_caller: bool,
+  /// The whole CBOR record the probe program returned, for programs that report more than
+  /// discovery does (e.g. the `submit` capacity probe).
+  pub record: Vec<u8>,
+}
 
-  let replies = collected.lock().await;
-  print_network_map(&source.human_name, &our_pubkey, &replies);
-  Ok(())
+/// A short, non-recursive discovery pass (depth 0: only nodes our multicast or [[peer]] list reaches
+/// directly) used to find a single node, e.g. for `run --to`. Collects for up to `window`, returning
+/// early once `done` is satisfied by the nodes seen so far. Records with a bad signature or a stale
+/// timestamp are dropped rather than warned about - the caller only wants nodes it can address.
+pub(crate) async fn discover_neighbours(
+  local_config: &config::Config,
+  source: &config::IdentityData,
+  window: std::time::Duration,
+  done: impl Fn(&[DiscoveredNode]) -> bool,
+) -> DynResult<Vec<DiscoveredNode>> {
+  let (wasm_bytes, _label) = resolve_discovery_program(None).await?;
+  probe_neighbours(local_config, source, &wasm_bytes, EMBEDDED_DISCOVERY_NAME, window, done).await
+}
+
+/// The pass behind [`discover_neighbours`], with any program that returns a discovery-style record
+/// (signed attestation under key 1, trusts_caller under 2, node address under 5).
+pub(crate) async fn probe_neighbours(
+  local_config: &config::Config,
+  source: &config::IdentityData,
+  wasm_bytes: &[u8],
+  human_name: &str,
+  window: std::time::Duration,
+  done: impl Fn(&[DiscoveredNode]) -> bool,
+) -> DynResult<Vec<DiscoveredNode>> {
+  let request_uuid = discovery::random_uuid16();
+  let make_request = |_depth: u8| -> DynResult<Vec<u8>> {
+    let pd = executor::ProgramDataBuilder::new()
+      .set_human_name(human_name)
+      .set_wasm_program_bytes(wasm_bytes)
+      .set_source(source)
+      .set_request_context(request_uuid, 0, Vec::new())
+      .build().map_err(map_loc_err!())?;
+    local_config.network.fabric_id().encode(&messages::NetworkMessage::execute_request(pd))
+  };
+
+  let (sock_v4, sock_v6) = bind_request_sockets(&local_config.network).await?;
+  let endpoints = reply_endpoints(&sock_v4, sock_v6.as_ref(), local_config.network.fabric_id());
+  let no_trust = HashSet::new();
+  let send = send_discovery_requests(&sock_v4, sock_v6.as_deref(), false, &local_config.network, &local_config.peer, &no_trust, &make_request);
+  gather_neighbours(&endpoints, request_uuid, window, done, send).await
+}
+
+/// The collecting half of [`probe_neighbours`]: read request `request_uuid`'s records off `endpoints`
+/// for up to `window`, starting before `send` puts the request out, and keep each node with a valid,
+/// current attestation once. Returns early once `done` is satisfied by the nodes seen so far.
+pub(crate) async fn gather_neighbours(
+  endpoints: &[transport::Endpoint],
+  request_uuid: [u8; 16],
+  window: std::time::Duration,
+  done: impl Fn(&[DiscoveredNode]) -> bool,
+  send: impl std::future::Future<Output = DynResult<()>>,
+) -> DynResult<Vec<DiscoveredNode>> {
+  let collected = std::sync::Arc::new(tokio::sync::Mutex::new(Vec::<(SocketAddr, Vec<u8>)>::new()));
+  let deadline = tokio::time::Instant::now() + window;
+  let collection = std::sync::Arc::new(std::sync::Mutex::new(discovery::Collection::new(discovery::CollectPolicy::fixed(window), std::time::Instant::now())));
+  let mut collectors = tokio::task::JoinSet::new();
+  for sock in endpoints {
+    let (sock, collection, collected) = (sock.clone(), collection.clone(), collected.clone());
+    collectors.spawn(async move { collect_until(&sock, request_uuid, collection, collected).await; });
+  }
+
+  send.await?;
+
+  let mut nodes: Vec<DiscoveredNode> = Vec::new();
+  loop {
+    let finished = tokio::time::Instant::now() >= deadline;
+    let now = sys_utils::epoch_seconds_now_utc0();
+    for (responder, cbor) in collected.lock().await.drain(..) {
+      let Some(record) = topology::parse_record(&cbor) else { continue };
+      let Ok(vn) = discovery::verify_attestation_cbor(&record.attestation) else { continue };
+      if !discovery::attestation_time_ok(vn.epoch_s, now) || nodes.iter().any(|n| n.pubkey == vn.pubkey) {
+        continue;
+      }
+      let addr = record.node_addr.and_then(|a| a.parse::<SocketAddr>().ok()).unwrap_or(responder);
+      nodes.push(DiscoveredNode { hostname: vn.hostname, pubkey: vn.pubkey, addr, trusts_caller: record.trusts_caller, record: cbor });
+    }
+    if finished || done(&nodes) {
+      break;
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+  }
+  collectors.abort_all();
+  Ok(nodes)
 }
 
 /// The stem of the bundled discovery program (see example-programs/embedded.list + network-map.c).
@@ -157,248 +526,38 @@ async fn resolve_discovery_program(program: Option<std::path::PathBuf>) -> DynRe
   }
 }
 
-/// Read `BasicReturnMap` replies carrying `request_uuid` off `sock` until `deadline`, appending each
-/// (responder addr, cbor bytes) to `collected`.
+/// Read the `BasicReturnMap` replies carrying `request_uuid` off `sock` (on its fabric) until
+/// `collection` says to stop, appending each (responder addr, cbor bytes) to `collected`. Several
+/// sockets share one `collection`, so each wakes up regularly to notice a deadline another one
+/// brought forward.
 async fn collect_until(
-  sock: &tokio::net::UdpSocket,
+  sock: &transport::Endpoint,
   request_uuid: [u8; 16],
-  deadline: tokio::time::Instant,
+  collection: std::sync::Arc<std::sync::Mutex<discovery::Collection>>,
   collected: std::sync::Arc<tokio::sync::Mutex<Vec<(SocketAddr, Vec<u8>)>>>,
 ) {
   let mut buf = [0u8; 64 * 1024];
+  let tick = std::time::Duration::from_millis(100);
   loop {
-    let now = tokio::time::Instant::now();
+    let now = std::time::Instant::now();
+    let Ok(deadline) = collection.lock().map(|c| c.deadline()) else { break };
     if now >= deadline { break; }
-    match tokio::time::timeout(deadline - now, sock.recv_from(&mut buf)).await {
+    match tokio::time::timeout((deadline - now).min(tick), sock.recv_from(&mut buf)).await {
       Ok(Ok((len, from))) => {
-        if let Ok(messages::NetworkMessage::BasicReturnMap { request_uuid: ru, cbor_data, .. }) =
-          serde_bare::from_slice::<messages::NetworkMessage>(&buf[..len])
-        {
+        if let Some(messages::NetworkMessage::BasicReturnMap { request_uuid: ru, cbor_data, .. }) = sock.fabric().decode(&buf[..len]) {
           if ru == request_uuid {
+            let pubkey = topology::parse_record(&cbor_data)
+              .and_then(|r| discovery::verify_attestation_cbor(&r.attestation).ok())
+              .map(|vn| vn.pubkey);
+            if let Ok(mut c) = collection.lock() {
+              c.note_record(pubkey.as_deref(), std::time::Instant::now());
+            }
             collected.lock().await.push((from, cbor_data));
           }
         }
       }
       Ok(Err(_)) => break,
-      Err(_) => break, // deadline reached
-    }
-  }
-}
-
-/// One node in the assembled map.
-struct Node {
-  pubkey: Vec<u8>,
-  hostname: String,
-  parent: Vec<u8>,
-  trusts_caller: bool,
-  /// The node's OWN address as it reported it (record key 5). Preferred for display over `responder`,
-  /// which for a relayed record is the intermediate daemon that forwarded it up, not the node itself.
-  node_addr: Option<String>,
-  responder: SocketAddr,
-  sig_valid: bool,
-  time_ok: bool,
-}
-
-/// Decode a per-node record CBOR map into its fields (attestation bytes + parent + trusts_caller +
-/// the node's self-reported address).
-fn parse_record(bytes: &[u8]) -> Option<(Vec<u8>, Vec<u8>, bool, Option<String>)> {
-  use serde_cbor::Value;
-  let map = match serde_cbor::from_slice::<Value>(bytes).ok()? {
-    Value::Map(m) => m,
-    _ => return None,
-  };
-  let attestation = match map.get(&Value::Integer(discovery::record_keys::ATTESTATION)) {
-    Some(Value::Bytes(b)) => b.clone(),
-    _ => return None,
-  };
-  let parent = match map.get(&Value::Integer(discovery::record_keys::PARENT_PUBKEY)) {
-    Some(Value::Bytes(b)) => b.clone(),
-    _ => Vec::new(),
-  };
-  let trusts_caller = matches!(
-    map.get(&Value::Integer(discovery::record_keys::TRUSTS_CALLER)),
-    Some(Value::Integer(1))
-  );
-  // Accept the address as either a text or byte string; an empty value means "unknown".
-  let node_addr = match map.get(&Value::Integer(discovery::record_keys::NODE_ADDR)) {
-    Some(Value::Text(s)) if !s.is_empty() => Some(s.clone()),
-    Some(Value::Bytes(b)) if !b.is_empty() => Some(String::from_utf8_lossy(b).into_owned()),
-    _ => None,
-  };
-  Some((attestation, parent, trusts_caller, node_addr))
-}
-
-/// Verify + assemble the collected records into a tree and print it. Verifies each node's signature
-/// (warning on failure) and that its timestamp is within the request window, dedups by identity
-/// pubkey (never printing a node twice), and links children to parents by pubkey.
-fn print_network_map(you: &str, our_pubkey: &[u8], replies: &[(SocketAddr, Vec<u8>)]) {
-  let now = sys_utils::epoch_seconds_now_utc0();
-  let mut nodes: HashMap<Vec<u8>, Node> = HashMap::new();
-
-  for (responder, cbor) in replies {
-    let (attestation, parent, trusts_caller, node_addr) = match parse_record(cbor) {
-      Some(v) => v,
-      None => continue,
-    };
-    match discovery::verify_attestation_cbor(&attestation) {
-      Ok(vn) => {
-        let time_ok = discovery::attestation_time_ok(vn.epoch_s, now);
-        if !time_ok {
-          eprintln!("[netmap] WARNING: {} ({}) attestation timestamp is outside the +/-30s window",
-                    vn.hostname, responder);
-        }
-        // Dedup by identity pubkey: never re-print the same node.
-        nodes.entry(vn.pubkey.clone()).or_insert(Node {
-          pubkey: vn.pubkey,
-          hostname: vn.hostname,
-          parent,
-          trusts_caller,
-          node_addr,
-          responder: *responder,
-          sig_valid: true,
-          time_ok,
-        });
-      }
-      Err(e) => {
-        eprintln!("[netmap] WARNING: invalid signature from {} - {} (node not trusted for identity)",
-                  responder, e);
-      }
-    }
-  }
-
-  println!();
-  println!("weverywhere network map");
-  println!("  you = {}", you);
-  println!("  legend:  <3 = node trusts you    x = node does NOT trust you    (!) = unverified/expired");
-  println!("  nodes show as  hostname (short-id) @ addr;  short-id matches the chat name and its full");
-  println!("  public key is listed below the tree (a short-id can collide - always verify the full key)");
-  println!();
-
-  if nodes.is_empty() {
-    println!("(no servers responded)");
-    println!("  - is a daemon running and reachable? try:  weverywhere netmap --local");
-    println!("  - are peers configured, and did the discovery program get built?");
-    println!();
-    return;
-  }
-
-  // children[parent_pubkey] = [child pubkeys], sorted for stable output.
-  let mut children: HashMap<Vec<u8>, Vec<Vec<u8>>> = HashMap::new();
-  for (pk, node) in nodes.iter() {
-    children.entry(node.parent.clone()).or_default().push(pk.clone());
-  }
-  for v in children.values_mut() {
-    v.sort_by(|a, b| {
-      let na = nodes.get(a).map(|n| n.hostname.as_str()).unwrap_or("");
-      let nb = nodes.get(b).map(|n| n.hostname.as_str()).unwrap_or("");
-      na.cmp(nb)
-    });
-  }
-
-  if our_pubkey.is_empty() {
-    println!("(you) {}", you);
-  } else {
-    println!("(you) {} ({})", you, crypto_utils::short_id(our_pubkey));
-  }
-  // Roots are nodes whose parent is us (or whose parent we never heard from).
-  let mut roots: Vec<Vec<u8>> = nodes.keys()
-    .filter(|pk| {
-      let parent = &nodes[*pk].parent;
-      parent == our_pubkey || !nodes.contains_key(parent)
-    })
-    .cloned()
-    .collect();
-  roots.sort_by(|a, b| nodes[a].hostname.cmp(&nodes[b].hostname));
-
-  // First pass: build every row's tree portion so we can align the trust column into a single column
-  // regardless of how deep (and therefore how wide) each branch is.
-  let mut printed: HashSet<Vec<u8>> = HashSet::new();
-  let mut rows: Vec<Row> = Vec::new();
-  let root_count = roots.len();
-  for (i, pk) in roots.iter().enumerate() {
-    collect_rows(pk, &nodes, &children, "", i + 1 == root_count, &mut printed, &mut rows);
-  }
-
-  // Second pass: pad each tree portion to the widest one, then print the aligned trust column.
-  let tree_width = rows.iter().map(|r| r.tree.chars().count()).max().unwrap_or(0);
-  for row in &rows {
-    let pad = tree_width - row.tree.chars().count();
-    println!("{}{:pad$}   [{}]{}", row.tree, "", row.trust_mark, row.warn, pad = pad);
-  }
-
-  // Full public keys, so a short-id (as shown in the tree and in chat) can be verified against the
-  // unforgeable full ed25519 key. A hostname or short-id can be claimed by anyone; only the full key
-  // is unique. Include ourselves so a user can confirm their own chat short-id too.
-  let mut ids: Vec<(Vec<u8>, String, bool)> = Vec::new();
-  if !our_pubkey.is_empty() {
-    ids.push((our_pubkey.to_vec(), you.to_string(), true));
-  }
-  for (pk, node) in nodes.iter() {
-    ids.push((pk.clone(), node.hostname.clone(), false));
-  }
-  ids.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
-
-  // Flag short-id collisions (distinct full keys sharing a short-id) - a hint of possible impersonation.
-  let mut short_counts: HashMap<String, usize> = HashMap::new();
-  for (pk, _, _) in &ids {
-    *short_counts.entry(crypto_utils::short_id(pk)).or_default() += 1;
-  }
-
-  println!();
-  println!("public keys (verify a chat/netmap short-id against the full key here):");
-  for (pk, hostname, is_you) in &ids {
-    let short = crypto_utils::short_id(pk);
-    let you_mark = if *is_you { " (you)" } else { "" };
-    let collision = if short_counts[&short] > 1 { "   (!) SHARED SHORT-ID - verify full key" } else { "" };
-    println!("  {}  {}  {}{}{}", short, crypto_utils::to_hex(pk), hostname, you_mark, collision);
-  }
-  println!();
-}
-
-/// One rendered tree line, split so the trust column can be aligned across the whole tree.
-struct Row {
-  /// The tree portion: prefix + branch + `hostname (short-id) @ addr`.
-  tree: String,
-  /// Trust indicator (`<3` / `x`) shown in the aligned column.
-  trust_mark: &'static str,
-  /// Trailing warning marker (` (!)`) for unverified/expired records, or empty.
-  warn: &'static str,
-}
-
-/// Recursively build one node's row and its children's rows with box-drawing indentation, guarding
-/// against cycles via `printed` (a node reached by two paths is only shown once). Rows are collected
-/// rather than printed so the caller can align the trust column across the whole tree.
-fn collect_rows(
-  pk: &[u8],
-  nodes: &HashMap<Vec<u8>, Node>,
-  children: &HashMap<Vec<u8>, Vec<Vec<u8>>>,
-  prefix: &str,
-  is_last: bool,
-  printed: &mut HashSet<Vec<u8>>,
-  rows: &mut Vec<Row>,
-) {
-  let node = match nodes.get(pk) { Some(n) => n, None => return };
-  if !printed.insert(pk.to_vec()) {
-    return; // already printed via another path
-  }
-  let branch = if is_last { "`--" } else { "|--" };
-  let trust_mark = if node.trusts_caller { "<3" } else { "x" };
-  let warn = if node.sig_valid && node.time_ok { "" } else { " (!)" };
-  // Prefer the node's self-reported address; fall back to the datagram source (which for a relayed
-  // record is the intermediate daemon, not this node).
-  let addr = node.node_addr.clone().unwrap_or_else(|| node.responder.to_string());
-  rows.push(Row {
-    tree: format!("{}{} {} ({}) @ {}", prefix, branch, node.hostname, crypto_utils::short_id(pk), addr),
-    trust_mark,
-    warn,
-  });
-
-  let child_prefix = format!("{}{}", prefix, if is_last { "    " } else { "|   " });
-  if let Some(kids) = children.get(pk) {
-    let kids: Vec<&Vec<u8>> = kids.iter().filter(|k| !printed.contains(*k)).collect();
-    let n = kids.len();
-    for (i, child) in kids.into_iter().enumerate() {
-      collect_rows(child, nodes, children, &child_prefix, i + 1 == n, printed, rows);
+      Err(_) => {} // a tick or the deadline; the loop head decides
     }
   }
 }
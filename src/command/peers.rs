@@ -0,0 +1,106 @@
+use super::*;
+
+/// `weverywhere peers`: list, forget or pin entries of the peer registry (see [`peer_registry`]).
+/// Works on the file directly, so it needs no running daemon; a running one picks up forgotten
+/// peers at its next flush.
+pub async fn peers(args: &args::Args, action: &args::PeersAction) -> DynResult<()> {
+  let local_config = config::Config::read_from_file(&args.config_path()).await.map_err(map_loc_err!())?.fabric_view(args.fabric_name.as_deref());
+  let path = peer_registry::path(&local_config.state);
+  let mut records = peer_registry::load(&path).await?;
+  records.sort_by_key(|r| std::cmp::Reverse(r.last_seen_epoch_s));
+  let now = sys_utils::epoch_seconds_now_utc0();
+
+  match action {
+    args::PeersAction::List => {
+      if records.is_empty() {
+        println!("No peers remembered in {}.", path.display());
+        return Ok(());
+      }
+      for r in records.iter() {
+        let key = crypto_utils::from_hex(&r.pubkey).unwrap_or_default();
+        println!(
+          "{}  {:<24} {:<40} {:<10} last seen {}{}",
+          crypto_utils::short_id(&key),
+          format!("{:?}", r.name),
+          r.addr,
+          if r.trusted { "trusted" } else { "untrusted" },
+          describe_age(now.saturating_sub(r.last_seen_epoch_s)),
+          if r.is_expired(now, local_config.state.peer_ttl_s) { " (expired)" } else { "" },
+        );
+        if args.v_is_info() {
+          println!("          {}", r.pubkey);
+        }
+      }
+    }
+    args::PeersAction::Forget { query, all } => {
+      let matched = matching(&records, query);
+      if matched.is_empty() {
+        return Err(format!("no remembered peer matches {query:?} (see `weverywhere peers list`)").into());
+      }
+      if matched.len() > 1 && !all {
+        return Err(format!("{query:?} matches {} peers ({}); use a longer key prefix or --all", matched.len(), labels(&records, &matched)).into());
+      }
+      let forgotten = labels(&records, &matched);
+      let keep: Vec<peer_registry::PeerRecord> = records.iter().enumerate()
+        .filter(|(i, _)| !matched.contains(i))
+        .map(|(_, r)| r.clone())
+        .collect();
+      peer_registry::save(&path, &keep).await?;
+      println!("Forgot {forgotten}.");
+    }
+    args::PeersAction::Pin { query, write } => {
+      let matched = matching(&records, query);
+      let i = match matched.as_slice() {
+        [i] => *i,
+        [] => return Err(format!("no remembered peer matches {query:?} (see `weverywhere peers list`)").into()),
+        _ => return Err(format!("{query:?} matches {} peers ({}); use a longer key prefix", matched.len(), labels(&records, &matched)).into()),
+      };
+      let record = &records[i];
+      let pubkey = crypto_utils::from_hex(&record.pubkey).unwrap_or_default();
+      let already = local_config.peer.iter().any(|p| {
+        p.expected_key_str()
+          .and_then(|k| crypto_utils::public_key_to_ed25519_vk(k).ok())
+          .is_some_and(|vk| vk.as_bytes().as_slice() == pubkey.as_slice())
+      });
+      if *write && already {
+        return Err(format!("{} already has a [[peer]] pinned to this key; not appending another", args.config_path().display()).into());
+      }
+      let block = record.to_pinned_toml()?;
+      print!("{block}");
+      if *write {
+        use tokio::io::AsyncWriteExt;
+        let mut f = tokio::fs::OpenOptions::new().append(true).open(args.config_path()).await.map_err(map_loc_err!())?;
+        f.write_all(format!("\n# Pinned from the peer registry ({})\n{block}", record.name).as_bytes()).await.map_err(map_loc_err!())?;
+        eprintln!("Appended to {}.", args.config_path().display());
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Indices of the records `query` names, by the same rules as `run --to` (full key, key prefix, or
+/// name).
+fn matching(records: &[peer_registry::PeerRecord], query: &str) -> Vec<usize> {
+  let query = discovery::TargetQuery::parse(query);
+  records.iter().enumerate()
+    .filter(|(_, r)| query.matches(Some(&r.name), crypto_utils::from_hex(&r.pubkey).as_deref()))
+    .map(|(i, _)| i)
+    .collect()
+}
+
+fn labels(records: &[peer_registry::PeerRecord], indices: &[usize]) -> String {
+  indices.iter()
+    .map(|i| format!("{:?} ({})", records[*i].name, crypto_utils::short_id(&crypto_utils::from_hex(&records[*i].pubkey).unwrap_or_default())))
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+/// "42s ago", "5m ago", "3h ago", "2d ago".
+fn describe_age(secs: u64) -> String {
+  match secs {
+    0..60 => format!("{secs}s ago"),
+    60..3600 => format!("{}m ago", secs / 60),
+    3600..86400 => format!("{}h ago", secs / 3600),
+    _ => format!("{}d ago", secs / 86400),
+  }
+}
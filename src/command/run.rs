@@ -1,17 +1,68 @@
-
 use super::*;
 
+/// Where and how `run` delivers its program, beyond the program itself and its arguments.
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+  /// Broadcast to the whole multicast fabric (and every `[[peer]]`) instead of a single daemon.
+  pub fabric: bool,
+  /// Send to this one daemon instead of the local one: a `[[peer]]` hostname/address from the
+  /// config (so its port-less address forms are reused), or any resolvable host or IP literal.
+  pub peer: Option<String>,
+  /// Send to the single node with this identity (`run --to`): a full key, short-id / key prefix, or
+  /// hostname, looked up in `[[peer]]` and then by a quick discovery pass.
+  pub to: Option<String>,
+  /// Stream this file to the program's WASI stdin as `ProgramStdin` chunks; `-` means our own
+  /// stdin. Needs a single unicast target, so it can't be combined with `fabric`.
+  pub stdin: Option<std::path::PathBuf>,
+  /// Attach our terminal to the program (`run --tty`): its `host::tty_*` drawing is shown here and
+  /// our input forwarded to it. Needs a single target, and can't be combined with `stdin`.
+  pub tty: bool,
+  /// With `tty`: record the session as an asciicast here (see [`crate::tty::cast`]).
+  pub tty_record: Option<std::path::PathBuf>,
+  /// With `fabric`: how many nodes to wait for, which ones count, and whether to aggregate results.
+  pub fan_out: fanout::FanOutPolicy,
+}
+
+/// What `run` exits with when the program's exit status never arrived (it was still running when we
+/// stopped waiting, the reply was lost, or `run --tty` detached first), so a script can't mistake
+/// that for success. Borrowed from timeout(1).
+pub const NO_EXIT_STATUS: u32 = 124;
 
-pub async fn run(args: &args::Args, file_path: &std::path::PathBuf, fabric: bool, multicast_groups: args::MulticastAddressVec, port: u16, arg_list: Vec<String>, arg_map: Vec<(String, String)>) -> DynResult<()> {
-  use tokio::net::ToSocketAddrs;
+pub async fn run(args: &args::Args, file_path: &std::path::PathBuf, multicast_groups: Option<args::MulticastAddressVec>, port: Option<u16>, arg_list: Vec<String>, arg_map: Vec<(String, String)>, opts: RunOptions) -> DynResult<u32> {
+  // Stdin chunks are addressed to one running program; a multicast run has many (or none).
+  if opts.fabric && opts.stdin.is_some() {
+    return Err("--stdin needs a single target; use the local daemon or --peer instead of --fabric".into());
+  }
+  if opts.tty && (opts.fabric || opts.stdin.is_some()) {
+    return Err("--tty needs a single target and the terminal to itself; it can't be combined with --fabric or --stdin".into());
+  }
 
   // Step 1: Read the executable material & form an exeute request object, sign it, and transmit.
   let wasm_bytes = tokio::fs::read(file_path).await.map_err(map_loc_err!())?;
 
-  let local_config = config::Config::read_from_file(&args.config_path()).await.map_err(map_loc_err!())?;
+  let mut local_config = config::Config::read_from_file(&args.config_path()).await.map_err(map_loc_err!())?.fabric_view(args.fabric_name.as_deref());
+  local_config.network.apply_cli_overrides(multicast_groups.as_deref(), port);
+  let port = local_config.network.port;
 
   let source = config::IdentityData::generate_from_config(&local_config).await.map_err(map_loc_err!())?;
 
+  // Resolve `--to` before building the request: the target's key goes into it, so any other node
+  // that receives a copy leaves it alone.
+  let target = match opts.to.as_deref() {
+    Some(to) => Some(resolve_target(&local_config, &source, to).await?),
+    None => None,
+  };
+
+  // Attach last thing before sending, once nothing else needs to prompt or print: the program draws
+  // at our size from its first frame.
+  let tty = if opts.tty {
+    Some(crate::tty::attach_recorded(opts.tty_record.as_deref()).map_err(|e| format!("--tty: {e}"))?)
+  } else {
+    None
+  };
+
+  // A fresh request id lets the executor tie our ProgramStdin chunks to this run before we learn its pid.
+  let request_uuid = discovery::random_uuid16();
   let pd = executor::ProgramDataBuilder::new()
     .set_human_name(
       file_path.file_name().map(|fn_osstr| fn_osstr.to_string_lossy().to_string() ).unwrap_or_else(|| "UNSET_NAME".to_string() )
@@ -19,38 +70,86 @@ pub async fn run(args: &args::Args, file_path: &std::path::PathBuf, fabric: bool
     .set_wasm_program_bytes(&wasm_bytes)
     .set_source(&source)
     .set_args(arg_list, arg_map)
+    .set_request_context(request_uuid, 0, Vec::new())
+    .set_wants_stdin(opts.stdin.is_some())
+    .set_target_pubkey(target.as_ref().and_then(|t| t.pubkey.clone()))
+    .set_tty_size(tty.as_ref().map(|(handle, _)| handle.size()))
     .build().map_err(map_loc_err!())?;
 
-  let execute_req = messages::NetworkMessage::ExecuteRequest {
-    program_data: pd.clone(),
-  };
-  let execute_req_encoded = serde_bare::to_vec(&execute_req)?;
+  let execute_req = messages::NetworkMessage::execute_request(pd.clone());
+  let fabric = local_config.network.fabric_id();
 
-  // Step 2a (default): talk to the local daemon on this machine. The daemon binds 0.0.0.0:port,
-  // so a unicast to the loopback address reaches it without touching the LAN. This is the
-  // client's default on every platform; --fabric opts into the multicast broadcast below.
-  if !fabric {
-    return send_to_local_daemon(&execute_req_encoded, port).await;
+  // Step 2a (default): talk to ONE daemon - the local one, or the --peer one. The daemon binds
+  // 0.0.0.0:port, so a unicast to the loopback address reaches it without touching the LAN. This is
+  // the client's default on every platform; --fabric opts into the multicast broadcast below.
+  if !opts.fabric {
+    let (addr, expect) = match (&target, opts.peer.as_deref()) {
+      (Some(target), _) => (target.addr, Some(target.clone())),
+      (None, Some(name)) => {
+        let peer = named_peer(&local_config, name);
+        let addr = match net_utils::resolve_peer_addr(&peer, port).await {
+          Some(t) => t,
+          None => return Err(format!("no resolvable address for peer [{}]", peer.label()).into()),
+        };
+        // A pinned (or, under TOFU, known) peer must prove who it is before its replies count.
+        let check = known_peers::PeerCheck::new(&local_config, &peer).await?;
+        (addr, Some(TargetNode::for_peer(addr, check)))
+      }
+      (None, None) => (std::net::SocketAddr::from((std::net::Ipv4Addr::LOCALHOST, port)), None),
+    };
+    let sock = transport::Endpoint::udp_ephemeral(addr).map_err(map_loc_err!())?.scoped(fabric);
+    let exit_code = match tty {
+      Some(tty) => run_tty_session(&sock, &execute_req, addr, request_uuid, expect.as_ref(), tty).await?,
+      None => run_unicast(&sock, &execute_req, addr, request_uuid, opts.stdin.as_deref(), expect.as_ref(), ExitWait::Idle).await?,
+    };
+    return Ok(match exit_code {
+      Some(code) => code,
+      None => {
+        tracing::warn!("[ run ] No exit status arrived from {} (timed out, or the reply was lost)", addr);
+        NO_EXIT_STATUS
+      }
+    });
   }
 
   // Step 2b (--fabric): transmit to all multicast groups on all interfaces, AND to
   // every statically-configured [[peer]] (unicast). Peers are treated just like the
   // multicast targets - sent the same request, replies collected the same way - so
   // nodes that multicast can't reach are still covered.
+  // Every socket's replies feed one collector, which attributes them to signed node identities
+  // and decides when the run is complete (see crate::fanout).
+  let our_pubkey = local_config.identity.read_public_key_ed25519_pem_file().await
+    .map(|vk| vk.as_bytes().to_vec())
+    .unwrap_or_default();
+  let mut trusted: std::collections::HashSet<Vec<u8>> = std::collections::HashSet::new();
+  if !our_pubkey.is_empty() { trusted.insert(our_pubkey); }
+  for t in local_config.trusted.iter() {
+    if let Ok(vk) = crypto_utils::public_key_to_ed25519_vk(&t.key) {
+      trusted.insert(vk.as_bytes().to_vec());
+    }
+  }
+  let fan_out = std::sync::Arc::new(fanout::FanOut::new(request_uuid, opts.fan_out, trusted));
+
   let mut tasks = tokio::task::JoinSet::new();
 
   for peer in local_config.peer.iter() {
-    let execute_req_encoded = execute_req_encoded.clone();
-    let peer = peer.clone();
+    let check = match known_peers::PeerCheck::new(&local_config, peer).await {
+      Ok(check) => check,
+      Err(e) => {
+        tracing::warn!("[ run ] Skipping peer [{}]: {}", peer.label(), e);
+        continue;
+      }
+    };
+    let execute_req = execute_req.clone();
+    let fan_out = fan_out.clone();
     tasks.spawn(async move {
-      if let Err(e) = run_one_peer(&execute_req_encoded, &peer, port).await {
-        tracing::warn!("[ run ] Error sending to peer [{}]: {:?}", peer.label(), e);
+      if let Err(e) = run_one_peer(&execute_req, fabric, request_uuid, &check, port, &fan_out).await {
+        tracing::warn!("[ run ] Error sending to peer [{}]: {:?}", check.peer.label(), e);
       }
     });
   }
 
-  for (iface_idx, iface_name, iface_addrs) in net_utils::get_interfaces().into_iter() {
-    for multicast_addr in multicast_groups.iter() {
+  for (iface_idx, iface_name, iface_addrs) in net_utils::fabric_interfaces(&local_config.network).into_iter() {
+    for multicast_addr in local_config.network.groups() {
       if iface_addrs.len() < 1 {
         // We assume 0 addresses means no network connection, so we skip the interface entirely.
         continue;
@@ -60,11 +159,11 @@ pub async fn run(args: &args::Args, file_path: &std::path::PathBuf, fabric: bool
       let iface_idx = iface_idx.clone();
       let iface_name = iface_name.clone();
       let iface_addrs = iface_addrs.clone();
-      let multicast_addr = multicast_addr.clone();
-      let pd = pd.clone();
-      let execute_req_encoded = execute_req_encoded.clone();
+      let network = local_config.network.clone();
+      let fan_out = fan_out.clone();
+      let execute_req = execute_req.clone();
       tasks.spawn(async move {
-        if let Err(e) = run_one_iface(&execute_req_encoded, &pd, iface_idx, &iface_name, &iface_addrs, &multicast_addr, port).await {
+        if let Err(e) = run_one_iface(&execute_req, &fan_out, iface_idx, &iface_name, &iface_addrs, &multicast_addr, &network).await {
           tracing::warn!("[ serve_iface ] Error serving {:?} addr {:?} port {}: {:?}", iface_name, multicast_addr, port, e);
         }
       });
@@ -73,7 +172,8 @@ pub async fn run(args: &args::Args, file_path: &std::path::PathBuf, fabric: bool
 
   tasks.join_all().await;
 
-  Ok(())
+  // Many programs ran; the run fails if any selected one did (first non-zero code wins).
+  fan_out.finish()
 }
 
 /// Fire-and-forget broadcast of a program onto the whole fabric (multicast on every interface +
@@ -83,13 +183,13 @@ pub async fn run(args: &args::Args, file_path: &std::path::PathBuf, fabric: bool
 /// given args and an empty discovery context (depth 0 / no visited), so recipients run it but don't
 /// themselves recurse unless their own logic calls replicate again.
 /// A UDP socket whose multicast egress interface is pinned to the NIC that owns `iface_addr` (via
-/// IP_MULTICAST_IF). Bound to an ephemeral port; TTL 4 and loopback on so a co-located listener still
-/// gets our own copy. tokio's UdpSocket can't set IP_MULTICAST_IF, so we go through socket2.
-fn new_multicast_sender_v4(iface_addr: std::net::Ipv4Addr) -> DynResult<tokio::net::UdpSocket> {
+/// IP_MULTICAST_IF). Bound to an ephemeral port; TTL `ttl` and loopback on so a co-located listener
+/// still gets our own copy. tokio's UdpSocket can't set IP_MULTICAST_IF, so we go through socket2.
+fn new_multicast_sender_v4(iface_addr: std::net::Ipv4Addr, ttl: u32) -> DynResult<tokio::net::UdpSocket> {
   use socket2::{Domain, Protocol, Socket, Type};
   let sock = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).map_err(map_loc_err!())?;
   sock.set_multicast_if_v4(&iface_addr).map_err(map_loc_err!())?;
-  sock.set_multicast_ttl_v4(4).map_err(map_loc_err!())?;
+  sock.set_multicast_ttl_v4(ttl).map_err(map_loc_err!())?;
   let _ = sock.set_multicast_loop_v4(true);
   sock.set_nonblocking(true).map_err(map_loc_err!())?;
   sock.bind(&std::net::SocketAddr::from((std::net::Ipv4Addr::UNSPECIFIED, 0)).into()).map_err(map_loc_err!())?;
@@ -97,10 +197,11 @@ fn new_multicast_sender_v4(iface_addr: std::net::Ipv4Addr) -> DynResult<tokio::n
 }
 
 /// IPv6 counterpart of [`new_multicast_sender_v4`], pinning the egress interface by index.
-fn new_multicast_sender_v6(iface_idx: u32) -> DynResult<tokio::net::UdpSocket> {
+fn new_multicast_sender_v6(iface_idx: u32, hops: u32) -> DynResult<tokio::net::UdpSocket> {
   use socket2::{Domain, Protocol, Socket, Type};
   let sock = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP)).map_err(map_loc_err!())?;
   sock.set_multicast_if_v6(iface_idx).map_err(map_loc_err!())?;
+  sock.set_multicast_hops_v6(hops).map_err(map_loc_err!())?;
   let _ = sock.set_multicast_loop_v6(true);
   sock.set_nonblocking(true).map_err(map_loc_err!())?;
   sock.bind(&std::net::SocketAddr::from((std::net::Ipv6Addr::UNSPECIFIED, 0)).into()).map_err(map_loc_err!())?;
@@ -113,8 +214,7 @@ pub async fn broadcast_program_to_fabric(
   human_name: &str,
   arg_list: Vec<String>,
   arg_map: Vec<(String, String)>,
-  multicast_groups: &[std::net::IpAddr],
-  port: u16,
+  network: &config::NetworkConfig,
   peers: &[config::PeerMetadata],
 ) -> DynResult<()> {
   let pd = executor::ProgramDataBuilder::new()
@@ -123,25 +223,26 @@ pub async fn broadcast_program_to_fabric(
     .set_source(source)
     .set_args(arg_list, arg_map)
     .build()?;
-  let bytes = serde_bare::to_vec(&messages::NetworkMessage::ExecuteRequest { program_data: pd })?;
-  broadcast_bytes_to_fabric(&bytes, multicast_groups, port, peers).await
+  let bytes = network.fabric_id().encode(&messages::NetworkMessage::execute_request(pd))?;
+  broadcast_bytes_to_fabric(&bytes, network, peers).await
 }
 
-/// Fan an already-serialized `NetworkMessage` out onto the fabric: multicast each group out EVERY
-/// interface (egress pinned via IP_MULTICAST_IF) and unicast it to every configured peer. Shared by the
+/// Fan an already-serialized `NetworkMessage` out onto the fabric: multicast each `[network]` group
+/// out every interface `[network]` allows (egress pinned via IP_MULTICAST_IF) and unicast it to every configured peer. Shared by the
 /// program-shipping path ([`broadcast_program_to_fabric`]) and the lightweight signed-message path
 /// (`host::messages_send`), so both reach every segment identically. Receivers collapse the overlapping
 /// copies via the `(pubkey, id)` dedup; losing a copy is acceptable - senders/operators retry.
 pub async fn broadcast_bytes_to_fabric(
   bytes: &[u8],
-  multicast_groups: &[std::net::IpAddr],
-  port: u16,
+  network: &config::NetworkConfig,
   peers: &[config::PeerMetadata],
 ) -> DynResult<()> {
+  let multicast_groups = network.groups();
+  let port = network.port;
   // A plain 0.0.0.0:0 send picks only the default-route interface, so hosts that straddle several
   // segments (a physical LAN plus a VM bridge, say) would deliver multicast to just one of them.
   // Sending per interface, pinned to that NIC, reaches all segments.
-  let interfaces = net_utils::get_interfaces();
+  let interfaces = net_utils::fabric_interfaces(network);
   let mut sent_any = false;
   for group in multicast_groups.iter() {
     for (idx, name, addrs) in interfaces.iter() {
@@ -151,12 +252,12 @@ pub async fn broadcast_bytes_to_fabric(
       // but never abort the fan-out - reaching the other interfaces is what matters.
       let sock = match group {
         std::net::IpAddr::V4(_) => match addrs.iter().find_map(|a| match a { std::net::IpAddr::V4(v4) => Some(*v4), _ => None }) {
-          Some(iface_v4) => new_multicast_sender_v4(iface_v4),
+          Some(iface_v4) => new_multicast_sender_v4(iface_v4, network.hops_for(group)),
           None => continue,
         },
         std::net::IpAddr::V6(_) => {
           if !addrs.iter().any(|a| a.is_ipv6()) { continue; }
-          new_multicast_sender_v6(*idx)
+          new_multicast_sender_v6(*idx, network.hops_for(group))
         }
       };
       let sock = match sock {
@@ -188,10 +289,11 @@ pub async fn broadcast_bytes_to_fabric(
   Ok(())
 }
 
-pub async fn run_one_iface(ex_req_bytes: &[u8], pd: &executor::ProgramData, iface_idx: u32, iface_name: &str, iface_addrs: &Vec<std::net::IpAddr>, multicast_group: &std::net::IpAddr, port: u16) -> DynResult<()> {
+pub async fn run_one_iface(request: &messages::NetworkMessage, fan_out: &fanout::FanOut, iface_idx: u32, iface_name: &str, iface_addrs: &Vec<std::net::IpAddr>, multicast_group: &std::net::IpAddr, network: &config::NetworkConfig) -> DynResult<()> {
+  let port = network.port;
 
   if crate::v_is_info() {
-    tracing::warn!("Sending {} bytes to {:?} port {} on iface {} ({:?})", ex_req_bytes.len(), multicast_group, port, iface_name, iface_addrs);
+    tracing::warn!("Sending to {:?} port {} on iface {} ({:?})", multicast_group, port, iface_name, iface_addrs);
   }
 
   let empty_bind_addr_port = if multicast_group.is_ipv4() {
@@ -205,11 +307,12 @@ pub async fn run_one_iface(ex_req_bytes: &[u8], pd: &executor::ProgramData, ifac
 
   if multicast_group.is_ipv4() {
     sock.set_multicast_loop_v4(true).map_err(map_loc_err!())?;
-    sock.set_multicast_ttl_v4(4).map_err(map_loc_err!())?; // How many hops multicast can live for - default is just the immediate LAN we are attached to. TODO configure me from /etc/weveryware.toml l8ter
   }
   else {
     sock.set_multicast_loop_v6(true).map_err(map_loc_err!())?;
   }
+  // How many routers the request may cross: [network].multicast_ttl / multicast_hops_v6.
+  net_utils::set_multicast_hops(socket2::SockRef::from(&sock), multicast_group, network).map_err(map_loc_err!())?;
 
   match multicast_group {
     std::net::IpAddr::V4(multicast_group) => {
@@ -225,125 +328,618 @@ pub async fn run_one_iface(ex_req_bytes: &[u8], pd: &executor::ProgramData, ifac
   }
 
   // sock.connect( (*multicast_group, port) ).await.map_err(map_loc_err!())?;
-  // Sized to a full UDP datagram so large forwarded stdout payloads aren't truncated.
-  let mut buf = [0; 64*1024];
+  let sock = transport::Endpoint::udp(sock).scoped(network.fabric_id());
+  fan_out_via(&sock, request, std::net::SocketAddr::new(*multicast_group, port), fan_out, None).await
+}
 
-  let len = sock.send_to(&ex_req_bytes, (*multicast_group, port)).await.map_err(map_loc_err!())?;
-  tracing::warn!("{:?} bytes sent", len);
+/// Send `request` from `sock` to `to` (a multicast group or one node) and feed the replies into the
+/// shared fan-out collector until it says the run is complete. The shared half of
+/// [`run_one_iface`] and [`run_one_peer`].
+pub(crate) async fn fan_out_via(sock: &transport::Endpoint, request: &messages::NetworkMessage, to: std::net::SocketAddr, fan_out: &fanout::FanOut, gate: Option<IdentityGate<'_>>) -> DynResult<()> {
+  let len = sock.send_to(&serde_bare::to_vec(request)?, to).await.map_err(map_loc_err!())?;
+  tracing::warn!("{:?} bytes sent to {}", len, to);
+  collect_fan_out_replies(sock, fan_out, gate).await;
+  Ok(())
+}
 
+/// Feed every reply arriving on `sock` into the shared fan-out collector until it says the run is
+/// complete. Polls in 100ms steps so a quiet socket still notices the deadline. Replies sent on any
+/// fabric but the socket's are ignored; with a `gate`, only the replies it admits are fed in.
+async fn collect_fan_out_replies(sock: &transport::Endpoint, fan_out: &fanout::FanOut, mut gate: Option<IdentityGate<'_>>) {
+  // Sized to a full UDP datagram so large forwarded stdout payloads aren't truncated.
+  let mut buf = [0; 64*1024];
   let td = tokio::time::Duration::from_millis(100);
-
-  let mut remaining_100ms_checks: usize = 24;
-
-  while remaining_100ms_checks > 0 {
-    remaining_100ms_checks -= 1;
-    // Only wait up to 100ms for replies;
-    match tokio::time::timeout(td, sock.recv(&mut buf)).await {
-      Ok(Ok(len)) => {
+  while !fan_out.is_done() {
+    match tokio::time::timeout(td, sock.recv_from(&mut buf)).await {
+      Ok(Ok((len, from))) => {
         if crate::v_is_everything() {
-          tracing::warn!("{:?} bytes received from {:?} => {:?}", len, multicast_group, &buf[0..len]);
+          tracing::warn!("{:?} bytes received from {:?} => {:?}", len, from, &buf[0..len]);
         }
-        #[allow(unreachable_patterns)]
-        match serde_bare::from_slice::<messages::NetworkMessage>(&buf[..len]) {
-          Ok(network_message) => {
-            remaining_100ms_checks += 10; // if we rx a message, allow another second of waiting.
-            match network_message {
-              messages::NetworkMessage::BasicInsecureProgramStdout { from_pid, stdout_data } => {
-                if let Ok(stdout_string) = str::from_utf8(&stdout_data) {
-                  tracing::warn!("[{}] {}", from_pid, stdout_string);
-                }
-                else {
-                  tracing::warn!("[{}:binary] {:?}", from_pid, stdout_data);
-                }
-              }
-              messages::NetworkMessage::BasicInsecureProgramExit { from_pid, exit_code } => {
-                tracing::warn!("pid {} exited with code {}", from_pid, exit_code);
-              }
-              unused => {
-                tracing::warn!("Got unexpected network message: {:?}", unused);
-              }
+        match messages::decode(&buf[..len]) {
+          Ok((sent_on, network_message)) if sent_on == sock.fabric() => {
+            let admitted = match gate.as_mut() {
+              Some(gate) => gate.admit(from, network_message).await,
+              None => vec![network_message],
+            };
+            for msg in admitted {
+              fan_out.handle(from, msg);
             }
           }
-          Err(e) => {
-            tracing::warn!("Parsing NetworkMessage error: {e}");
-          }
+          Ok(_) => {}
+          Err(e) => tracing::warn!("Parsing NetworkMessage error: {e}"),
         }
       }
       Ok(Err(e)) => {
         // The socket operation itself failed
         tracing::warn!("Socket error: {e}");
       }
-      Err(_) => {
-        // The timeout expired (no data within 100ms)
-        // tracing::warn!("Timed out");
-      }
+      Err(_) => { /* 100ms timeout, no data */ }
     }
   }
-
-  Ok(())
+  if let Some(gate) = gate
+    && gate.never_confirmed()
+    && (gate.refused > 0 || !gate.held.is_empty()) {
+    tracing::warn!("[ run ] {} never identified itself with the expected key; dropped {} reply(s)", gate.target.label, gate.refused + gate.held.len());
+  }
 }
 
-/// Unicast an encoded execute request to one configured `[[peer]]` and print its replies, exactly
-/// like the local-daemon path. The peer's address is chosen in preference order (hostname, then
-/// ipv6, then ipv4); the reply socket is bound to the matching address family.
-pub async fn run_one_peer(ex_req_bytes: &[u8], peer: &config::PeerMetadata, port: u16) -> DynResult<()> {
+/// Unicast an encoded execute request to one configured `[[peer]]` as part of a fan-out run, feeding
+/// its replies to the shared collector like the multicast sockets do. The peer's address is chosen in
+/// preference order (hostname, then ipv6, then ipv4); the reply socket is bound to the matching
+/// address family. A pinned (or TOFU-checked) peer's replies only reach the collector once it has
+/// identified itself with the right key.
+pub async fn run_one_peer(request: &messages::NetworkMessage, fabric: messages::FabricId, request_uuid: [u8; 16], check: &known_peers::PeerCheck, port: u16, fan_out: &fanout::FanOut) -> DynResult<()> {
+  let peer = &check.peer;
   let target = match net_utils::resolve_peer_addr(peer, port).await {
     Some(t) => t,
     None => return Err(format!("no resolvable address for peer [{}]", peer.label()).into()),
   };
-
-  let bind_addr = if target.is_ipv4() {
-    (std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED), 0)
-  } else {
-    (std::net::IpAddr::V6(std::net::Ipv6Addr::UNSPECIFIED), 0)
-  };
-  let sock = tokio::net::UdpSocket::bind(bind_addr).await.map_err(map_loc_err!())?;
-
-  let len = sock.send_to(ex_req_bytes, target).await.map_err(map_loc_err!())?;
   if crate::v_is_info() {
-    tracing::warn!("Sent {} bytes to peer [{}] at {}", len, peer.label(), target);
+    tracing::warn!("Sending to peer [{}] at {}", peer.label(), target);
   }
 
-  read_daemon_replies(&sock).await
+  let sock = transport::Endpoint::udp_ephemeral(target).map_err(map_loc_err!())?.scoped(fabric);
+  let node = TargetNode::for_peer(target, check.clone());
+  fan_out_via(&sock, request, target, fan_out, Some(IdentityGate::new(&node, request_uuid))).await
+}
+
+/// Look `name` up among the configured `[[peer]]` entries (by hostname or by either address, as
+/// written in the config), falling back to an ad-hoc peer that just resolves `name` as a host.
+fn named_peer(local_config: &config::Config, name: &str) -> config::PeerMetadata {
+  let matches = |p: &&config::PeerMetadata| {
+    p.hostname.as_deref() == Some(name)
+      || p.ipv4.map(|a| a.to_string()).as_deref() == Some(name)
+      || p.ipv6.map(|a| a.to_string()).as_deref() == Some(name)
+  };
+  match local_config.peer.iter().find(matches) {
+    Some(p) => p.clone(),
+    None => config::PeerMetadata { hostname: Some(name.to_string()), ipv6: None, ipv4: None, expected_key: None },
+  }
 }
 
 /// Default client path: send an encoded execute request to the local daemon over loopback, then
 /// print replies for a short window. The daemon binds 0.0.0.0:port, so a unicast to 127.0.0.1
 /// reaches it on every platform without going out to the LAN.
-pub async fn send_to_local_daemon(ex_req_bytes: &[u8], port: u16) -> DynResult<()> {
-  let sock = tokio::net::UdpSocket::bind((std::net::Ipv4Addr::UNSPECIFIED, 0)).await.map_err(map_loc_err!())?;
-  let len = sock.send_to(ex_req_bytes, (std::net::Ipv4Addr::LOCALHOST, port)).await.map_err(map_loc_err!())?;
-  tracing::warn!("{} bytes sent to local daemon 127.0.0.1:{}", len, port);
-  read_daemon_replies(&sock).await
+pub async fn send_to_local_daemon(request: &messages::NetworkMessage, fabric: messages::FabricId, port: u16) -> DynResult<()> {
+  let target = std::net::SocketAddr::from((std::net::Ipv4Addr::LOCALHOST, port));
+  let sock = transport::Endpoint::udp_ephemeral(target).map_err(map_loc_err!())?.scoped(fabric);
+  run_unicast(&sock, request, target, [0u8; 16], None, None, ExitWait::Idle).await?;
+  Ok(())
+}
+
+/// Unicast an execute request from `sock` (on the fabric it sends on) to a single daemon and print its
+/// replies. With `stdin` set,
+/// that file (`-` = our stdin) is streamed to the program as `ProgramStdin` chunks while replies are
+/// read, and the program's stdout is written raw to our stdout so `run` works inside a pipeline.
+/// With `expect` set, only replies from that node's verified identity are shown, and it is an error
+/// if the node never confirms it is running the program. `exit_wait` says how long to wait once the
+/// node has accepted it. Returns the program's exit code, if its exit message arrived.
+pub(crate) async fn run_unicast(sock: &transport::Endpoint, request: &messages::NetworkMessage, target: std::net::SocketAddr, request_uuid: [u8; 16], stdin: Option<&std::path::Path>, expect: Option<&TargetNode>, exit_wait: ExitWait) -> DynResult<Option<u32>> {
+  let len = sock.send_to(&serde_bare::to_vec(request)?, target).await.map_err(map_loc_err!())?;
+  if crate::v_is_info() {
+    tracing::info!("{} bytes sent to {}", len, target);
+  }
+
+  let stream = match stdin {
+    Some(path) => {
+      let stream = StdinStream::default();
+      tokio::spawn(stream_stdin(sock.clone(), target, request_uuid, path.to_path_buf(), stream.clone()));
+      Some(stream)
+    }
+    None => None,
+  };
+  let gate = expect.map(|node| IdentityGate::new(node, request_uuid));
+  read_daemon_replies(sock, target, request_uuid, stream.as_ref(), gate, exit_wait).await
 }
 
-/// Read and print daemon replies (forwarded stdout + exit codes) for up to a short window.
-async fn read_daemon_replies(sock: &tokio::net::UdpSocket) -> DynResult<()> {
+/// How long the reply reader keeps going once the daemon has accepted the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ExitWait {
+  /// Stop after the usual idle window, like any quiet run.
+  Idle,
+  /// Wait for the accepted program's exit however quiet it is, for at most this long
+  /// (`None` = as long as it runs). Ctrl-C falls back to the idle window.
+  UntilExit(Option<std::time::Duration>),
+}
+
+/// How long `run --tty` waits for the program to start before giving up.
+const TTY_CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How long `run --tty` waits for the full frame it asked for before asking again.
+const TTY_REDRAW_RETRY: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Run a program with our terminal attached (`run --tty`): draw its `TtyDraw` batches on `tty`, send
+/// it our input as `TtyInput`, and end when it exits or the user detaches with Ctrl+]. What it
+/// writes to stdout / stderr meanwhile is held and printed once the terminal is restored. After a
+/// missed batch it asks for the whole screen with `TtyRedraw` (see [`crate::tty::DrawSequence`]).
+/// With a `gate`'s target, as in [`run_unicast`], only the verified node's replies are drawn.
+async fn run_tty_session(sock: &transport::Endpoint, request: &messages::NetworkMessage, target: std::net::SocketAddr, request_uuid: [u8; 16], expect: Option<&TargetNode>, tty: (std::sync::Arc<crate::tty::TtyHandle>, crate::tty::TtyGuard)) -> DynResult<Option<u32>> {
+  let (tty, guard) = tty;
+  sock.send_to(&serde_bare::to_vec(request)?, target).await.map_err(map_loc_err!())?;
+
+  let mut gate = expect.map(|node| IdentityGate::new(node, request_uuid));
+  let started = tokio::time::Instant::now();
+  let mut answered = false;
+  let mut pid = 0;
+  let mut draws = crate::tty::DrawSequence::default();
+  let mut redraw_asked: Option<tokio::time::Instant> = None;
+  let mut held_output: Vec<(bool, Vec<u8>)> = Vec::new();
+  let mut exit: Option<messages::NetworkMessage> = None;
+  let mut buf = vec![0u8; 64 * 1024];
+  let send_input = |pid: u64, events: Vec<Vec<u8>>, hangup: bool| {
+    let msg = messages::NetworkMessage::TtyInput { request_id: request_uuid, pid, events, hangup };
+    serde_bare::to_vec(&msg)
+  };
+  'session: loop {
+    tokio::select! {
+      received = sock.recv_from(&mut buf) => {
+        let Ok((len, from)) = received else { continue };
+        let Some(network_message) = sock.fabric().decode(&buf[..len]) else { continue };
+        let admitted = match gate.as_mut() {
+          Some(gate) => gate.admit(from, network_message).await,
+          None => vec![network_message],
+        };
+        for msg in admitted {
+          answered = true;
+          match msg {
+            messages::NetworkMessage::ProgramAccepted { from_pid, .. } => pid = from_pid,
+            messages::NetworkMessage::TtyDraw { seq, ops, .. } if draws.admit(seq, &ops) => {
+              crate::tty::apply_draw_ops(&ops, &**tty);
+            }
+            messages::NetworkMessage::BasicInsecureProgramStdout { stdout_data, .. } => held_output.push((false, stdout_data)),
+            messages::NetworkMessage::ProgramStderr { stderr_data, .. } => held_output.push((true, stderr_data)),
+            msg @ (messages::NetworkMessage::BasicInsecureProgramExit { .. } | messages::NetworkMessage::ProgramExit { .. }) => {
+              exit = Some(msg);
+              break 'session;
+            }
+            _ => {}
+          }
+        }
+      }
+      ev = tty.next_event(std::time::Duration::from_millis(100)) => {
+        match ev {
+          Some(ev) if is_detach_key(&ev) => {
+            if let Ok(enc) = send_input(pid, Vec::new(), true) {
+              let _ = sock.send_to(&enc, target).await;
+            }
+            break 'session;
+          }
+          Some(ev) => {
+            if let Ok(enc) = send_input(pid, vec![crate::tty::encode_event_v2(&ev)], false) {
+              let _ = sock.send_to(&enc, target).await;
+            }
+          }
+          None => {}
+        }
+        if !answered && started.elapsed() > TTY_CONNECT_TIMEOUT {
+          break 'session;
+        }
+      }
+    }
+    if !draws.awaiting_frame() {
+      redraw_asked = None;
+    } else if redraw_asked.is_none_or(|at| at.elapsed() >= TTY_REDRAW_RETRY) {
+      if let Ok(enc) = serde_bare::to_vec(&messages::NetworkMessage::TtyRedraw { request_id: request_uuid, pid }) {
+        let _ = sock.send_to(&enc, target).await;
+      }
+      redraw_asked = Some(tokio::time::Instant::now());
+    }
+  }
+  drop(guard);
+
+  if let Some(gate) = gate
+    && gate.never_confirmed() {
+    let refused = if gate.refused > 0 { format!(" ({} reply(s) from other identities refused)", gate.refused) } else { String::new() };
+    return Err(format!("{} never confirmed it was running the program{}", gate.target.label, refused).into());
+  }
+  if !answered {
+    return Err(format!("{} did not answer", target).into());
+  }
+  for (is_stderr, data) in held_output {
+    if is_stderr {
+      write_remote_stderr(&data);
+    } else {
+      use std::io::Write;
+      let mut out = std::io::stdout().lock();
+      let _ = out.write_all(&data);
+      let _ = out.flush();
+    }
+  }
+  Ok(match exit {
+    Some(messages::NetworkMessage::ProgramExit { from_pid, exit_code, reason, fuel_consumed, backtrace }) => {
+      print_program_exit(from_pid, exit_code, &reason, fuel_consumed, &backtrace);
+      Some(exit_code)
+    }
+    Some(messages::NetworkMessage::BasicInsecureProgramExit { from_pid, exit_code }) => {
+      tracing::warn!("pid {} exited with code {}", from_pid, exit_code);
+      Some(exit_code)
+    }
+    _ => None,
+  })
+}
+
+/// Ctrl+], which ends a `run --tty` session from our side. Terminals send it as the same byte as
+/// Ctrl+5, which is how crossterm reports it on Unix.
+fn is_detach_key(ev: &crate::tty::TtyEvent) -> bool {
+  matches!(ev, crate::tty::TtyEvent::Modified(crate::tty::modifiers::CTRL, key) if matches!(**key, crate::tty::TtyEvent::Char(']' | '5')))
+}
+
+/// How long `run --to` listens for discovery replies when the target isn't a configured [[peer]].
+const TARGET_DISCOVERY_WINDOW: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// The one node a `run --to` resolved to (or `submit` placed a job on).
+#[derive(Debug, Clone)]
+pub(crate) struct TargetNode {
+  pub addr: std::net::SocketAddr,
+  /// The key its replies must be signed with. `None` only for a [[peer]] with no `expected_key`,
+  /// where the first validly-signed identity to answer is taken (and reported).
+  pub pubkey: Option<Vec<u8>>,
+  /// How the node is named in messages.
+  pub label: String,
+  /// The [[peer]] entry it was picked from, which does the key check (and TOFU recording).
+  pub peer: Option<known_peers::PeerCheck>,
+}
+
+impl TargetNode {
+  /// The node behind a configured [[peer]], expected to answer with whatever key `check` holds.
+  pub fn for_peer(addr: std::net::SocketAddr, check: known_peers::PeerCheck) -> TargetNode {
+    TargetNode {
+      addr,
+      pubkey: check.key.key().map(<[u8]>::to_vec),
+      label: format!("peer [{}]", check.peer.label()),
+      peer: Some(check),
+    }
+  }
+}
+
+/// Find the node `run --to <to>` names. A [[peer]] entry wins when its hostname / address is `to`
+/// or its pinned key matches; otherwise a short discovery pass must turn up exactly one match.
+async fn resolve_target(local_config: &config::Config, source: &config::IdentityData, to: &str) -> DynResult<TargetNode> {
+  let query = discovery::TargetQuery::parse(to);
+  for peer in local_config.peer.iter() {
+    let check = known_peers::PeerCheck::new(local_config, peer).await?;
+    let by_addr = peer.connect_hosts().iter().any(|h| h.eq_ignore_ascii_case(&query.text));
+    if !by_addr && !query.matches(None, check.key.key()) {
+      continue;
+    }
+    let Some(addr) = net_utils::resolve_peer_addr(peer, local_config.network.port).await else {
+      return Err(format!("--to {}: no resolvable address for peer [{}]", query.text, peer.label()).into());
+    };
+    return Ok(TargetNode::for_peer(addr, check));
+  }
+
+  // Not a configured peer: ask the nodes we can reach directly who they are. A full key can stop
+  // the pass at its first sighting; a hostname or prefix waits out the window to catch duplicates.
+  let nodes = netmap::discover_neighbours(local_config, source, TARGET_DISCOVERY_WINDOW, |seen| {
+    query.pubkey.is_some() && seen.iter().any(|n| query.matches(Some(&n.hostname), Some(&n.pubkey)))
+  }).await?;
+  let mut found: Vec<netmap::DiscoveredNode> = nodes.into_iter()
+    .filter(|n| query.matches(Some(&n.hostname), Some(&n.pubkey)))
+    .collect();
+  match found.len() {
+    0 => Err(format!("--to {}: no [[peer]] matches and no node answering discovery does either", query.text).into()),
+    1 => {
+      let node = found.remove(0);
+      Ok(TargetNode {
+        addr: node.addr,
+        label: format!("{} ({})", node.hostname, crypto_utils::short_id(&node.pubkey)),
+        pubkey: Some(node.pubkey),
+        peer: None,
+      })
+    }
+    _ => {
+      let candidates: Vec<String> = found.iter()
+        .map(|n| format!("  {} @ {}  {}", n.hostname, n.addr, crypto_utils::to_hex(&n.pubkey)))
+        .collect();
+      Err(format!("--to {} matches {} nodes; name one by its full key:\n{}", query.text, found.len(), candidates.join("\n")).into())
+    }
+  }
+}
+
+/// `run --to`'s (and a pinned [[peer]]'s) check on who is answering. Nothing is shown until a
+/// `ProgramAccepted` for our request verifies as signed by the target's key; replies that raced ahead
+/// of it are held until then, and afterwards only the accepted program's replies count, and only
+/// from the address that accepted it. For a [[peer]] whose key isn't enforced (see
+/// [`known_peers::PeerCheck::is_enforced`]) every reply passes straight through, and the
+/// `ProgramAccepted` only serves to report the key it answered with.
+pub(crate) struct IdentityGate<'a> {
+  target: &'a TargetNode,
+  request_uuid: [u8; 16],
+  enforced: bool,
+  /// The accepted program's pid, and the address its acceptance came from.
+  accepted: Option<(u64, std::net::SocketAddr)>,
+  held: Vec<(std::net::SocketAddr, messages::NetworkMessage)>,
+  pub(crate) refused: usize,
+}
+
+impl<'a> IdentityGate<'a> {
+  pub(crate) fn new(target: &'a TargetNode, request_uuid: [u8; 16]) -> Self {
+    let enforced = target.peer.as_ref().is_none_or(known_peers::PeerCheck::is_enforced);
+    IdentityGate { target, request_uuid, enforced, accepted: None, held: Vec::new(), refused: 0 }
+  }
+
+  /// The target never proved it was running the program, though its key is enforced.
+  pub(crate) fn never_confirmed(&self) -> bool {
+    self.enforced && self.accepted.is_none()
+  }
+
+  /// Take in one reply from `addr`; returns the replies that may now be shown, in order.
+  pub(crate) async fn admit(&mut self, addr: std::net::SocketAddr, msg: messages::NetworkMessage) -> Vec<messages::NetworkMessage> {
+    if let messages::NetworkMessage::ProgramAccepted { request_uuid, from_pid, node, trusts_caller, signature } = &msg {
+      if self.accepted.is_some() || *request_uuid != self.request_uuid {
+        return Vec::new();
+      }
+      let payload = messages::program_accepted_payload(*from_pid, *trusts_caller);
+      if let Err(e) = node.check_self_signature().and_then(|_| node.verify_payload(request_uuid, &payload, signature)) {
+        tracing::warn!("[ run ] Refusing reply from {}: bad ProgramAccepted signature: {}", addr, e);
+        self.refused += 1;
+        return Vec::new();
+      }
+      match (&self.target.peer, &self.target.pubkey) {
+        (Some(check), _) => {
+          if !check.admit("run-reply", addr, node).await {
+            self.refused += 1;
+            return Vec::new();
+          }
+        }
+        (None, Some(want)) if *want != node.encoded_public_key => {
+          tracing::warn!("[ run ] Refusing reply from {}: signed by {} ({}), not {}", addr, node.human_name, crypto_utils::short_id(&node.encoded_public_key), self.target.label);
+          self.refused += 1;
+          return Vec::new();
+        }
+        (None, Some(_)) => {}
+        (None, None) => {
+          tracing::warn!("[ run ] {} has no expected_key; accepting {} ({}) as {}", self.target.label, node.human_name, crypto_utils::short_id(&node.encoded_public_key), crypto_utils::to_hex(&node.encoded_public_key));
+        }
+      }
+      let pid = *from_pid;
+      self.accepted = Some((pid, addr));
+      let mut admitted = vec![msg];
+      admitted.extend(std::mem::take(&mut self.held).into_iter().filter(|(from, m)| *from == addr && reply_pid(m) == Some(pid)).map(|(_, m)| m));
+      return admitted;
+    }
+    match self.accepted {
+      _ if !self.enforced => vec![msg],
+      None => {
+        self.held.push((addr, msg));
+        Vec::new()
+      }
+      // The pid alone proves nothing: anyone who saw it could send replies carrying it.
+      Some((pid, from)) if from == addr && reply_pid(&msg) == Some(pid) => vec![msg],
+      Some(_) => Vec::new(),
+    }
+  }
+}
+
+/// The remote pid a per-program reply is about, if it is one.
+fn reply_pid(msg: &messages::NetworkMessage) -> Option<u64> {
+  use messages::NetworkMessage as M;
+  match msg {
+    M::BasicInsecureProgramStdout { from_pid, .. }
+    | M::BasicInsecureProgramExit { from_pid, .. }
+    | M::BasicReturnMap { from_pid, .. }
+    | M::BasicReturnList { from_pid, .. }
+    | M::ProgramStderr { from_pid, .. }
+    | M::ProgramExit { from_pid, .. }
+    | M::TtyDraw { from_pid, .. }
+    | M::ProgramAccepted { from_pid, .. }
+    | M::ProgramStdinAck { from_pid, .. } => Some(*from_pid),
+    _ => None,
+  }
+}
+
+/// Shared state between a `--stdin` streamer and the reply reader: the remote pid once the first
+/// reply names it (0 until then), what the daemon has acknowledged, and whether the stream is still
+/// sending.
+#[derive(Debug, Clone, Default)]
+struct StdinStream {
+  pid: std::sync::Arc<std::sync::atomic::AtomicU64>,
+  /// `next_seq` of the latest `ProgramStdinAck`: every chunk before it reached the program.
+  acked: std::sync::Arc<std::sync::atomic::AtomicU64>,
+  /// How many acks have arrived, so the streamer can tell a slow reader from a silent daemon.
+  acks: std::sync::Arc<std::sync::atomic::AtomicU64>,
+  done: std::sync::Arc<std::sync::atomic::AtomicBool>,
+  /// Set when the daemon stopped acknowledging before the whole stream got through.
+  cut_short: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+impl StdinStream {
+  fn ack(&self, next_seq: u64) {
+    self.acked.store(next_seq, std::sync::atomic::Ordering::Relaxed);
+    self.acks.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+  }
+}
+
+/// Largest stdin chunk per `ProgramStdin` datagram; small enough to never fragment badly.
+const STDIN_CHUNK_BYTES: usize = 8 * 1024;
+
+/// Most stdin chunks in flight (sent but not yet acknowledged) at once.
+const STDIN_WINDOW_CHUNKS: usize = 16;
+
+/// How long unacknowledged stdin chunks wait before they are sent again.
+const STDIN_RESEND_AFTER: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// How long the daemon may go without acknowledging any stdin before the stream is given up on.
+const STDIN_SILENCE_LIMIT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Read `path` (`-` = our stdin) and send it to `target` as numbered `ProgramStdin` chunks, ending with
+/// an `eof` chunk. At most [`STDIN_WINDOW_CHUNKS`] are unacknowledged at once, and those are sent again
+/// until a `ProgramStdinAck` covers them, so a lost chunk is resent rather than skipped and a program
+/// that reads slowly holds the stream back. If the daemon goes silent for [`STDIN_SILENCE_LIMIT`] the
+/// stream stops and is marked `cut_short`.
+async fn stream_stdin(sock: transport::Endpoint, target: std::net::SocketAddr, request_id: [u8; 16], path: std::path::PathBuf, stream: StdinStream) {
+  use tokio::io::AsyncReadExt;
+  use std::sync::atomic::Ordering::Relaxed;
+  let mut reader: Box<dyn tokio::io::AsyncRead + Unpin + Send> = if path.as_os_str() == "-" {
+    Box::new(tokio::io::stdin())
+  } else {
+    match tokio::fs::File::open(&path).await {
+      Ok(f) => Box::new(f),
+      Err(e) => {
+        tracing::warn!("[ run ] Cannot open --stdin file {:?}: {:?}", path, e);
+        Box::new(tokio::io::empty())
+      }
+    }
+  };
+  let send = async |seq: u64, data: &[u8], eof: bool| {
+    let pid = stream.pid.load(Relaxed);
+    let msg = messages::NetworkMessage::ProgramStdin { request_id, pid, seq, data: data.to_vec(), eof };
+    if let Ok(enc) = serde_bare::to_vec(&msg)
+      && let Err(e) = sock.send_to(&enc, target).await {
+      tracing::warn!("[ run ] Sending stdin to {} failed: {:?}", target, e);
+    }
+  };
+  let mut buf = vec![0u8; STDIN_CHUNK_BYTES];
+  // Sent but not yet acknowledged, oldest first: (seq, data, eof).
+  let mut unacked: std::collections::VecDeque<(u64, Vec<u8>, bool)> = std::collections::VecDeque::new();
+  let mut next_seq = 0;
+  let mut read_all = false;
+  let mut acks_seen = 0;
+  let mut heard_at = tokio::time::Instant::now();
+  let mut sent_at = tokio::time::Instant::now();
+  loop {
+    let acked = stream.acked.load(Relaxed);
+    while unacked.front().is_some_and(|(seq, ..)| *seq < acked) {
+      unacked.pop_front();
+    }
+    if read_all && unacked.is_empty() {
+      break;
+    }
+    let acks = stream.acks.load(Relaxed);
+    if acks != acks_seen {
+      acks_seen = acks;
+      heard_at = tokio::time::Instant::now();
+    } else if !unacked.is_empty() && heard_at.elapsed() >= STDIN_SILENCE_LIMIT {
+      tracing::warn!("[ run ] {} stopped acknowledging stdin; the program got only the first {} of {} chunk(s)", target, acked, next_seq);
+      stream.cut_short.store(true, Relaxed);
+      break;
+    }
+    if !unacked.is_empty() && sent_at.elapsed() >= STDIN_RESEND_AFTER {
+      for (seq, data, eof) in unacked.iter() {
+        send(*seq, data, *eof).await;
+      }
+      sent_at = tokio::time::Instant::now();
+    }
+    if read_all || unacked.len() >= STDIN_WINDOW_CHUNKS {
+      tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+      continue;
+    }
+    // Reading is cancel safe: if the wait ends first, nothing was read.
+    let read = tokio::select! {
+      read = reader.read(&mut buf) => read,
+      _ = tokio::time::sleep(STDIN_RESEND_AFTER) => continue,
+    };
+    let (data, eof) = match read {
+      Ok(0) => (Vec::new(), true),
+      Ok(n) => (buf[..n].to_vec(), false),
+      Err(e) => {
+        tracing::warn!("[ run ] Reading --stdin failed, closing remote stdin: {:?}", e);
+        (Vec::new(), true)
+      }
+    };
+    if unacked.is_empty() {
+      // Nothing was outstanding, so the daemon had no reason to speak: start its silence clock now.
+      heard_at = tokio::time::Instant::now();
+    }
+    send(next_seq, &data, eof).await;
+    sent_at = tokio::time::Instant::now();
+    unacked.push_back((next_seq, data, eof));
+    next_seq += 1;
+    read_all = eof;
+    tokio::time::sleep(tokio::time::Duration::from_millis(1)).await;
+  }
+  stream.done.store(true, Relaxed);
+}
+
+/// Read and print daemon replies (forwarded stdout + exit codes) for up to a short window. While a
+/// `stream` is still sending stdin the window never runs out (the program may be waiting on it);
+/// in stream mode stdout goes raw to our stdout and the first exit ends the run. Remote stderr always
+/// goes raw to our stderr. With a `gate` (`run --to`) only the verified target's replies are shown,
+/// and a target that never verifies is an error, as is a stdin stream the daemon stopped
+/// acknowledging. Ctrl-C asks `target` to stop request `request_id`'s program (`ProgramKill`) and
+/// keeps reading for its exit; a second Ctrl-C gives up at once. With [`ExitWait::UntilExit`] an
+/// accepted program is waited for until it exits (or the limit passes) instead of for the idle
+/// window. Returns the last exit code seen.
+async fn read_daemon_replies(sock: &transport::Endpoint, target: std::net::SocketAddr, request_id: [u8; 16], stream: Option<&StdinStream>, mut gate: Option<IdentityGate<'_>>, exit_wait: ExitWait) -> DynResult<Option<u32>> {
   let td = tokio::time::Duration::from_millis(100);
-  let mut buf = [0u8; 16 * 1024];
+  let mut buf = [0u8; 64 * 1024];
   let mut remaining_100ms_checks: usize = 24;
-  while remaining_100ms_checks > 0 {
-    remaining_100ms_checks -= 1;
-    match tokio::time::timeout(td, sock.recv(&mut buf)).await {
-      Ok(Ok(len)) => {
-        #[allow(unreachable_patterns)]
-        match serde_bare::from_slice::<messages::NetworkMessage>(&buf[..len]) {
-          Ok(network_message) => {
+  let mut last_code: Option<u32> = None;
+  let mut pid = 0;
+  let mut interrupted = false;
+  let mut accepted_at: Option<tokio::time::Instant> = None;
+  'replies: while remaining_100ms_checks > 0 {
+    let awaiting_exit = match (exit_wait, accepted_at) {
+      (ExitWait::UntilExit(limit), Some(at)) if last_code.is_none() && !interrupted => {
+        if limit.is_some_and(|limit| at.elapsed() >= limit) {
+          tracing::warn!("[ run ] {} accepted the program but it has not exited after {:?}; giving up on it", target, at.elapsed());
+          break 'replies;
+        }
+        true
+      }
+      _ => false,
+    };
+    if !awaiting_exit && stream.is_none_or(|s| s.done.load(std::sync::atomic::Ordering::Relaxed)) {
+      remaining_100ms_checks -= 1;
+    }
+    let received = tokio::select! {
+      received = tokio::time::timeout(td, sock.recv_from(&mut buf)) => received,
+      _ = tokio::signal::ctrl_c() => {
+        if interrupted {
+          std::process::exit(130);
+        }
+        interrupted = true;
+        tracing::warn!("[ run ] Interrupted; asking {} to stop the program (Ctrl-C again to stop waiting)", target);
+        if let Ok(enc) = serde_bare::to_vec(&messages::NetworkMessage::ProgramKill { request_id, pid }) {
+          let _ = sock.send_to(&enc, target).await;
+        }
+        continue;
+      }
+    };
+    match received {
+      Ok(Ok((len, from))) => {
+        match messages::decode(&buf[..len]) {
+          Ok((sent_on, _)) if sent_on != sock.fabric() => {}
+          Ok((_, network_message)) => {
             remaining_100ms_checks += 10; // got a reply: allow another ~second of waiting
-            match network_message {
-              messages::NetworkMessage::BasicInsecureProgramStdout { from_pid, stdout_data } => {
-                if let Ok(stdout_string) = str::from_utf8(&stdout_data) {
-                  tracing::warn!("[{}] {}", from_pid, stdout_string);
-                }
-                else {
-                  tracing::warn!("[{}:binary] {:?}", from_pid, stdout_data);
+            let admitted = match gate.as_mut() {
+              Some(gate) => gate.admit(from, network_message).await,
+              None => vec![network_message],
+            };
+            for msg in admitted {
+              pid = reply_pid(&msg).unwrap_or(pid);
+              if let messages::NetworkMessage::ProgramStdinAck { request_id: acked_request, next_seq, .. } = msg {
+                if let Some(stream) = stream
+                  && acked_request == request_id {
+                  stream.ack(next_seq);
                 }
+                continue;
               }
-              messages::NetworkMessage::BasicInsecureProgramExit { from_pid, exit_code } => {
-                tracing::warn!("pid {} exited with code {}", from_pid, exit_code);
+              if matches!(msg, messages::NetworkMessage::ProgramAccepted { .. }) {
+                accepted_at.get_or_insert_with(tokio::time::Instant::now);
               }
-              unused => {
-                tracing::warn!("Got unexpected network message: {:?}", unused);
+              if show_daemon_reply(msg, stream, &mut last_code) {
+                break 'replies;
               }
             }
           }
@@ -358,5 +954,82 @@ async fn read_daemon_replies(sock: &tokio::net::UdpSocket) -> DynResult<()> {
       Err(_) => { /* 100ms timeout, no data */ }
     }
   }
-  Ok(())
+  if let Some(gate) = gate
+    && gate.never_confirmed() {
+    let refused = if gate.refused > 0 { format!(" ({} reply(s) from other identities refused)", gate.refused) } else { String::new() };
+    return Err(format!("{} never confirmed it was running the program{}", gate.target.label, refused).into());
+  }
+  if stream.is_some_and(|s| s.cut_short.load(std::sync::atomic::Ordering::Relaxed)) {
+    return Err(format!("{} stopped taking stdin before the end of it; the program's output may be incomplete", target).into());
+  }
+  Ok(last_code)
+}
+
+/// Print one daemon reply, recording any exit code in `last_code`. Returns true when the run is over
+/// (in stream mode, the program's first exit).
+fn show_daemon_reply(msg: messages::NetworkMessage, stream: Option<&StdinStream>, last_code: &mut Option<u32>) -> bool {
+  use std::io::Write;
+  #[allow(unreachable_patterns)]
+  match msg {
+    messages::NetworkMessage::BasicInsecureProgramStdout { from_pid, stdout_data } => {
+      if let Some(stream) = stream {
+        stream.pid.store(from_pid, std::sync::atomic::Ordering::Relaxed);
+        let mut out = std::io::stdout().lock();
+        let _ = out.write_all(&stdout_data);
+        let _ = out.flush();
+      }
+      else if let Ok(stdout_string) = str::from_utf8(&stdout_data) {
+        tracing::warn!("[{}] {}", from_pid, stdout_string);
+      }
+      else {
+        tracing::warn!("[{}:binary] {:?}", from_pid, stdout_data);
+      }
+    }
+    messages::NetworkMessage::ProgramAccepted { from_pid, .. } => {
+      if let Some(stream) = stream {
+        stream.pid.store(from_pid, std::sync::atomic::Ordering::Relaxed);
+      }
+    }
+    messages::NetworkMessage::ProgramStderr { from_pid, stderr_data } => {
+      if let Some(stream) = stream {
+        stream.pid.store(from_pid, std::sync::atomic::Ordering::Relaxed);
+      }
+      write_remote_stderr(&stderr_data);
+    }
+    messages::NetworkMessage::BasicInsecureProgramExit { from_pid, exit_code } => {
+      tracing::warn!("pid {} exited with code {}", from_pid, exit_code);
+      *last_code = Some(exit_code);
+      return stream.is_some();
+    }
+    messages::NetworkMessage::ProgramExit { from_pid, exit_code, reason, fuel_consumed, backtrace } => {
+      print_program_exit(from_pid, exit_code, &reason, fuel_consumed, &backtrace);
+      *last_code = Some(exit_code);
+      return stream.is_some();
+    }
+    unused => {
+      tracing::warn!("Got unexpected network message: {:?}", unused);
+    }
+  }
+  false
+}
+
+/// Copy a remote program's stderr bytes to our own stderr, unprefixed, so diagnostics read the same
+/// as a local run's.
+pub(crate) fn write_remote_stderr(data: &[u8]) {
+  use std::io::Write;
+  let mut err = std::io::stderr().lock();
+  let _ = err.write_all(data);
+  let _ = err.flush();
+}
+
+/// Report a `ProgramExit`: the code and why, fuel burned if metered, and any backtrace the executor
+/// chose to share with us.
+pub(crate) fn print_program_exit(from_pid: u64, exit_code: u32, reason: &messages::ExitReason, fuel_consumed: Option<u64>, backtrace: &[String]) {
+  match fuel_consumed {
+    Some(fuel) => tracing::warn!("pid {} exited with code {} ({}, {} fuel)", from_pid, exit_code, reason.describe(), fuel),
+    None => tracing::warn!("pid {} exited with code {} ({})", from_pid, exit_code, reason.describe()),
+  }
+  for (i, frame) in backtrace.iter().enumerate() {
+    tracing::warn!("  #{} {}", i, frame);
+  }
 }
@@ -3,10 +3,28 @@ use super::*;
 
 use wasmtime::*;
 
+/// The terminal `run-local` gives its program, if any.
+#[derive(Debug, Clone, Default)]
+pub struct LocalTty {
+  /// Attach the real terminal (`--tty`).
+  pub attach: bool,
+  /// Give it a headless terminal typed into by this script instead (`--tty-script`, see
+  /// [`crate::tty::headless`]).
+  pub script: Option<std::path::PathBuf>,
+  /// Where the headless screen goes when the run ends; `None` prints it.
+  pub dump: Option<std::path::PathBuf>,
+  /// The headless terminal's (cols, rows).
+  pub size: (u16, u16),
+  /// How long the program may keep running once the script is done.
+  pub timeout: std::time::Duration,
+  /// Record the session as an asciicast here.
+  pub record: Option<std::path::PathBuf>,
+}
 
-pub async fn run_local(file_path: &std::path::PathBuf, args: &args::Args, arg_list: Vec<String>, arg_map: Vec<(String, String)>, multicast_groups: args::MulticastAddressVec, port: u16) -> DynResult<()> {
+pub async fn run_local(file_path: &std::path::PathBuf, args: &args::Args, arg_list: Vec<String>, arg_map: Vec<(String, String)>, multicast_groups: Option<args::MulticastAddressVec>, port: Option<u16>, tty: LocalTty) -> DynResult<()> {
 
-  let local_config = config::Config::read_from_file(&args.config_path()).await.map_err(map_loc_err!())?;
+  let mut local_config = config::Config::read_from_file(&args.config_path()).await.map_err(map_loc_err!())?.fabric_view(args.fabric_name.as_deref());
+  local_config.network.apply_cli_overrides(multicast_groups.as_deref(), port);
 
   let wasm_bytes = tokio::fs::read(file_path).await.map_err(map_loc_err!())?;
 
@@ -32,18 +50,58 @@ pub async fn run_local(file_path: &std::path::PathBuf, args: &args::Args, arg_li
   // time. (The interactive `chat` launcher drains concurrently instead - see that command.)
   let (replicate_tx, mut replicate_rx) = tokio::sync::mpsc::unbounded_channel::<executor::ReplicateRequest>();
 
+  // A scripted run reads its whole script up front, so a mistake in it fails before the program starts.
+  let script = match &tty.script {
+    Some(path) => {
+      let text = tokio::fs::read_to_string(path).await.map_err(|e| format!("--tty-script {}: {e}", path.display()))?;
+      Some(crate::tty::headless::parse_script(&text).map_err(|e| format!("--tty-script {}: {e}", path.display()))?)
+    }
+    None => None,
+  };
+  let (tty_handle, tty_guard, headless) = if script.is_some() {
+    let (handle, input, view) = crate::tty::headless::headless(tty.size, tty.record.as_deref())?;
+    (Some(handle), None, Some((input, view)))
+  } else if tty.attach {
+    let (handle, guard) = crate::tty::attach_recorded(tty.record.as_deref()).map_err(|e| format!("--tty: {e}"))?;
+    (Some(handle), Some(guard), None)
+  } else {
+    (None, None, None)
+  };
+
   let return_slot = std::sync::Arc::new(std::sync::Mutex::new(executor::ExecReturn::default()));
   // Purely local run: no fabric caller, so there's no meaningful "own address facing the caller".
-  let exec_opts = executor::ExecOptions { replicate_tx: Some(replicate_tx), ..Default::default() };
+  // A program with a terminal is interactive and runs as long as the user keeps it going, like chat.
+  let exec_opts = executor::ExecOptions {
+    replicate_tx: Some(replicate_tx),
+    uncapped_fuel: tty_handle.is_some(),
+    tty: tty_handle,
+    ..Default::default()
+  };
   match executor.begin_exec(&pd, executor::wasi_adapters::WasiStdioSimpleForwarder::new_nop(), exec_opts, return_slot ).await {
     Ok(running_pid) => {
       if crate::v_is_info() {
         tracing::info!("Spawned PID {}", running_pid);
       }
       // TODO stdio stuff here?
-      let exit_code = executor.wait_for_pid_exit(running_pid).await?;
-      if crate::v_is_info() {
-        tracing::info!("Exited with code {}", exit_code);
+      let exit = executor.wait_for_pid_exit_status(running_pid);
+      let status = match (script, &headless) {
+        (Some(steps), Some((input, _))) => {
+          let played = async {
+            crate::tty::headless::play(steps, input).await;
+            tokio::time::sleep(tty.timeout).await;
+          };
+          tokio::select! {
+            status = exit => Some(status),
+            _ = played => None,
+          }
+        }
+        _ => Some(exit.await),
+      };
+      drop(tty_guard);
+      match status {
+        Some(status) if crate::v_is_info() => tracing::info!("Exited with code {} ({})", status.exit_code, status.reason.describe()),
+        Some(_) => {}
+        None => tracing::warn!("[ run-local ] {} still running {:?} after its --tty-script ended; stopping", pd.human_name, tty.timeout),
       }
     }
     Err(e) => {
@@ -51,12 +109,19 @@ pub async fn run_local(file_path: &std::path::PathBuf, args: &args::Args, arg_li
     }
   }
 
-  let groups: Vec<std::net::IpAddr> = multicast_groups.iter().copied().collect();
+  if let Some((_, view)) = headless {
+    let screen = view.screen().text();
+    match &tty.dump {
+      Some(path) => tokio::fs::write(path, screen).await.map_err(|e| format!("--tty-dump {}: {e}", path.display()))?,
+      None => print!("{screen}"),
+    }
+  }
+
   while let Ok(req) = replicate_rx.try_recv() {
     match req.scope {
       executor::ReplicateScope::Fabric => {
         if let Err(e) = run::broadcast_program_to_fabric(
-          &wasm_bytes, &source, &pd.human_name, req.arg_list, req.arg_map, &groups, port, &local_config.peer,
+          &wasm_bytes, &source, &pd.human_name, req.arg_list, req.arg_map, &local_config.network, &local_config.peer,
         ).await {
           tracing::warn!("[ run-local ] replicate failed: {:?}", e);
         }
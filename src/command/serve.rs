@@ -21,49 +21,197 @@ fn log_sig_event(kind: &str, ok: bool, addr: std::net::SocketAddr, source: &conf
   }
 }
 
+/// The most messages one node replays in answer to a HistoryRequest.
+const HISTORY_REPLY_MAX_ENTRIES: usize = 512;
+
+/// Keep each HistoryReply datagram well under the UDP limit.
+const HISTORY_REPLY_MAX_BYTES: usize = 48 * 1024;
+
+/// Check a SignedFabricMessage, ReliableFabricMessage or RoomMessage that came from `addr` - the sender's identity
+/// self-signature, then its signature over the payload - log the verdict as a `kind` security event
+/// and record it in `executor`'s message store. None if a signature failed; otherwise the assigned
+/// sequence number (0 for a duplicate, or a DM between two other identities).
+pub(crate) fn accept_signed_message(executor: &executor::Executor, kind: &str, addr: std::net::SocketAddr, msg: &messages::NetworkMessage, epoch_s: u64) -> Option<u64> {
+  let (source, id, payload, signature) = match msg {
+    messages::NetworkMessage::SignedFabricMessage { source, id, cbor_data, signature } => (source, id, messages::fabric_message_payload(cbor_data, false), signature),
+    messages::NetworkMessage::ReliableFabricMessage { source, id, cbor_data, signature } => (source, id, messages::fabric_message_payload(cbor_data, true), signature),
+    messages::NetworkMessage::RoomMessage { source, id, envelope, signature } => (source, id, envelope.clone(), signature),
+    _ => return None,
+  };
+  if let Err(e) = source.check_self_signature() {
+    // Identity self-signature failed: the claimed name/pubkey are unverified (a forged identity).
+    // Log the CLAIMED key anyway so imposters using it are tracked.
+    log_sig_event(kind, false, addr, source, &format!(" error=bad-identity-sig detail={e:?}"));
+    return None;
+  }
+  if let Err(e) = source.verify_payload(id, &payload, signature) {
+    // Identity is genuine but the payload signature doesn't match: tampering or replay under this key.
+    log_sig_event(kind, false, addr, source, &format!(" error=bad-payload-sig detail={e:?}"));
+    return None;
+  }
+  log_sig_event(kind, true, addr, source, "");
+  Some(executor.record_signed_message(msg, epoch_s))
+}
+
 #[allow(unreachable_code)]
-pub async fn serve(args: &args::Args, multicast_group: args::MulticastAddressVec, port: u16) -> DynResult<()> {
+pub async fn serve(args: &args::Args, multicast_groups: Option<args::MulticastAddressVec>, port: Option<u16>) -> DynResult<()> {
+
+  let config_path = args.config_path();
+  let mut local_config = config::Config::read_from_file(&config_path).await.map_err(map_loc_err!())?;
+  let mut fabrics = Vec::new();
+  for view in served_views(args, &local_config, multicast_groups.as_deref(), port) {
+    fabrics.push(start_fabric(view).await);
+  }
+  let mut tasks = spawn_fabric_listeners(fabrics.clone());
+
+  // Pick up edits to the config (and its includes) and SIGHUPs while running; see reload_config.
+  let mut watcher = config_watch::ConfigWatcher::new(&config_path, &local_config).await;
+  loop {
+    tokio::select! {
+      done = tasks.join_next() => if done.is_none() { break },
+      reason = watcher.changed() => {
+        tracing::info!("[ reload ] {}, re-reading {}", reason, config_path.display());
+        if let Some(restart) = reload_config(args, &config_path, multicast_groups.as_deref(), port, &mut local_config, &mut fabrics).await {
+          watcher.set_config(&local_config);
+          if restart {
+            // New addresses (or fabrics) need new sockets. Programs already running keep theirs, so
+            // they finish and reply as before.
+            tracing::info!("[ reload ] network settings changed; restarting the listeners");
+            tasks.abort_all();
+            while tasks.join_next().await.is_some() {}
+            tasks = spawn_fabric_listeners(fabrics.clone());
+          }
+        }
+      }
+    }
+  }
 
-  let local_config = config::Config::read_from_file(&args.config_path()).await.map_err(map_loc_err!())?;
-  let executor = executor::Executor::new(&local_config).await;
+  Ok(())
+}
 
+/// Every fabric `config` has us serve (or just the one `--fabric-name` names), each a view of its
+/// own with the `--multicast-groups` / `--port` flags applied.
+fn served_views(args: &args::Args, config: &config::Config, multicast_groups: Option<&[std::net::IpAddr]>, port: Option<u16>) -> Vec<config::Config> {
+  let mut views = match args.fabric_name.as_deref() {
+    Some(name) => vec![config.fabric_view(Some(name))],
+    None => config.served_fabrics(),
+  };
+  for view in views.iter_mut() {
+    // The flags move the fabrics on [network]'s address; a derived one keeps its own.
+    if !view.network.derive_address || view.network.fabric.is_empty() {
+      view.network.apply_cli_overrides(multicast_groups, port);
+    }
+  }
+  views
+}
+
+/// An executor - and so a trust set, message store and peer registry of its own - for one fabric view.
+async fn start_fabric(view: config::Config) -> ServedFabric {
+  tracing::info!("[ serve ] fabric {} on port {}, groups {:?}", view.network.fabric_label(), view.network.port, view.network.groups());
   // Make sure the host firewall actually lets us receive on this UDP port (unicast + multicast)
   // before we start listening. Best-effort and never fatal - see firewall::ensure_inbound_udp_allowed.
-  firewall::ensure_inbound_udp_allowed(port).await;
+  firewall::ensure_inbound_udp_allowed(view.network.port).await;
+  let executor = executor::Executor::new(&view).await;
+  ServedFabric { executor, config: std::sync::Arc::new(view) }
+}
 
-  // Shared, read-only view of our config for the discovery relay (the [[peer]] forwarding targets).
-  let local_config = std::sync::Arc::new(local_config);
+/// Re-read the config at `path` and switch the running daemon over to it: each fabric still served
+/// keeps its executor (and running programs) and takes the new trust set, peers and limits (see
+/// [`executor::Executor::apply_config`]); new fabrics get an executor, dropped ones are let go. What
+/// changed is logged. A file that doesn't parse or validate leaves everything as it was, and None is
+/// returned; otherwise whether the listeners must restart because the network settings changed.
+async fn reload_config(
+  args: &args::Args,
+  path: &std::path::Path,
+  multicast_groups: Option<&[std::net::IpAddr]>,
+  port: Option<u16>,
+  current: &mut config::Config,
+  fabrics: &mut Vec<ServedFabric>,
+) -> Option<bool> {
+  let new = match config::Config::read_from_file(path).await.and_then(|c| c.validate().map(|_| c)) {
+    Ok(new) => new,
+    Err(e) => {
+      tracing::warn!("[ reload ] {}: {}; keeping the running config", path.display(), e);
+      return None;
+    }
+  };
+  let changes = current.diff(&new);
+  if changes.is_empty() {
+    tracing::info!("[ reload ] nothing changed");
+    return Some(false);
+  }
+  for change in changes.iter() {
+    tracing::info!("[ reload ] {}", change);
+  }
+  if changes.iter().any(|c| c.starts_with("identity.") || c.starts_with("state.")) {
+    tracing::warn!("[ reload ] [identity] and [state] changes take effect after a restart");
+  }
 
-  let tasks = spawn_listeners(executor, local_config, multicast_group, port);
-  tasks.join_all().await;
+  let address = |f: &ServedFabric| serde_json::to_value(&f.config.network).ok();
+  let old_addresses: Vec<_> = fabrics.iter().map(address).collect();
+  let mut next = Vec::new();
+  for view in served_views(args, &new, multicast_groups, port) {
+    match fabrics.iter().find(|f| f.config.network.fabric == view.network.fabric) {
+      Some(running) => {
+        running.executor.apply_config(&view);
+        next.push(ServedFabric { executor: running.executor.clone(), config: std::sync::Arc::new(view) });
+      }
+      None => next.push(start_fabric(view).await),
+    }
+  }
+  for dropped in fabrics.iter().filter(|f| !next.iter().any(|n| n.config.network.fabric == f.config.network.fabric)) {
+    tracing::info!("[ reload ] no longer serving fabric {}", dropped.config.network.fabric_label());
+  }
+  let restart = next.iter().map(address).collect::<Vec<_>>() != old_addresses;
+  *fabrics = next;
+  *current = new;
+  Some(restart)
+}
 
-  Ok(())
+/// One fabric a serve loop answers on: datagrams carrying its [`messages::FabricId`] (taken from
+/// `config.network.fabric`) run on its executor, under the executor's current config. `config` is
+/// what the listener was started with, which decides the address it listens on.
+#[derive(Clone)]
+pub struct ServedFabric {
+  pub executor: std::sync::Arc<executor::Executor>,
+  pub config: std::sync::Arc<config::Config>,
 }
 
-/// Spawn one UDP listener task per (interface x multicast group) on the given executor, returning the
+/// Spawn one UDP listener task per `[network]` multicast group on the given executor, returning the
 /// JoinSet. Both `serve` and the self-contained `chat` host use this so a single executor (and its
 /// shared message store) backs all inbound fabric traffic. The caller decides whether to join the set
 /// (block, like `serve`) or let it run in the background (like `chat`, which also drives a UI).
 pub fn spawn_listeners(
   executor: std::sync::Arc<executor::Executor>,
   local_config: std::sync::Arc<config::Config>,
-  multicast_group: args::MulticastAddressVec,
-  port: u16,
 ) -> tokio::task::JoinSet<()> {
+  spawn_fabric_listeners(vec![ServedFabric { executor, config: local_config }])
+}
+
+/// [`spawn_listeners`] for several fabrics at once. Fabrics on the same group and port share one
+/// listener, which hands each datagram to the fabric it was sent on.
+pub fn spawn_fabric_listeners(fabrics: Vec<ServedFabric>) -> tokio::task::JoinSet<()> {
   let mut tasks = tokio::task::JoinSet::new();
+  let mut by_address: Vec<((std::net::IpAddr, u16), Vec<ServedFabric>)> = Vec::new();
+  for fabric in fabrics.iter() {
+    for group in fabric.config.network.groups() {
+      let key = (group, fabric.config.network.port);
+      match by_address.iter_mut().find(|(k, _)| *k == key) {
+        Some((_, sharing)) => sharing.push(fabric.clone()),
+        None => by_address.push((key, vec![fabric.clone()])),
+      }
+    }
+  }
   // One listener per multicast group (NOT per interface). A single reuse-bound socket joins the group
   // on EVERY interface that has an address of the right family, so we receive multicast no matter which
   // NIC it arrives on. The old per-(interface x group) design bound the same 0.0.0.0:port without
   // SO_REUSEADDR, so every interface after the first failed with AddrInUse (silently), leaving the
   // group joined on just one - usually the wrong - interface on multi-NIC / bridge+tap hosts.
-  let interfaces = std::sync::Arc::new(net_utils::get_interfaces());
-  for multicast_addr in multicast_group.iter() {
-    let multicast_addr = multicast_addr.clone();
+  let interfaces = std::sync::Arc::new(fabrics.first().map(|f| net_utils::fabric_interfaces(&f.config.network)).unwrap_or_default());
+  for ((multicast_addr, port), sharing) in by_address {
     let interfaces = interfaces.clone();
-    let executor = executor.clone();
-    let local_config = local_config.clone();
     tasks.spawn(async move {
-      if let Err(e) = serve_group(&interfaces, &multicast_addr, port, executor, local_config).await {
+      if let Err(e) = serve_group(&interfaces, &multicast_addr, port, sharing).await {
         tracing::warn!("[ serve_group ] Error serving group {} port {}: {:?}", multicast_addr, port, e);
       }
     });
@@ -92,11 +240,11 @@ fn bind_reuse_udp(is_v4: bool, port: u16) -> DynResult<tokio::net::UdpSocket> {
 }
 
 #[allow(unreachable_code)]
-pub async fn serve_group(interfaces: &[(u32, String, Vec<std::net::IpAddr>)], multicast_addr: &std::net::IpAddr, port: u16, executor: std::sync::Arc<executor::Executor>, local_config: std::sync::Arc<config::Config>) -> DynResult<()> {
+pub async fn serve_group(interfaces: &[(u32, String, Vec<std::net::IpAddr>)], multicast_addr: &std::net::IpAddr, port: u16, fabrics: Vec<ServedFabric>) -> DynResult<()> {
   use tokio::net::ToSocketAddrs;
+  let network = fabrics.first().map(|f| f.config.network.clone()).unwrap_or_default();
 
   let sock = bind_reuse_udp(multicast_addr.is_ipv4(), port)?;
-  let sock = std::sync::Arc::new(sock); // Allow us to send socket to many threads - we wrap in UdpSocketSender as recieving is racy from other threads!
 
   if crate::v_is_info() {
     tracing::warn!("Successfully bound group {} on port {}", multicast_addr, port);
@@ -110,7 +258,6 @@ pub async fn serve_group(interfaces: &[(u32, String, Vec<std::net::IpAddr>)], mu
   match multicast_addr {
     std::net::IpAddr::V4(group) => {
       sock.set_multicast_loop_v4(true).map_err(map_loc_err!())?;
-      sock.set_multicast_ttl_v4(4).map_err(map_loc_err!())?; // How many hops multicast can live for - default is just the immediate LAN we are attached to. TODO configure me from /etc/weveryware.toml l8ter
       for (_iface_idx, iface_name, iface_addrs) in interfaces.iter() {
         for iface_addr in iface_addrs.iter() {
           if let std::net::IpAddr::V4(iface_addr_v4) = iface_addr {
@@ -121,8 +268,9 @@ pub async fn serve_group(interfaces: &[(u32, String, Vec<std::net::IpAddr>)], mu
           }
         }
       }
-      if !joined_any {
+      if !joined_any && network.interfaces.is_empty() && network.exclude_interfaces.is_empty() {
         // No usable per-interface address; fall back to the default-route interface so we still receive.
+        // Not when [network] names the interfaces to use: the default route may be one it left out.
         sock.join_multicast_v4(*group, core::net::Ipv4Addr::UNSPECIFIED).map_err(map_loc_err!())?;
         joined_any = true;
       }
@@ -138,14 +286,43 @@ pub async fn serve_group(interfaces: &[(u32, String, Vec<std::net::IpAddr>)], mu
       }
     }
   }
+  // Replies and relayed requests leave on this socket too: [network].multicast_ttl / multicast_hops_v6.
+  net_utils::set_multicast_hops(socket2::SockRef::from(&sock), multicast_addr, &network).map_err(map_loc_err!())?;
   if !joined_any {
     tracing::warn!("[ serve_group ] joined group {} on NO interfaces - will not receive multicast", multicast_addr);
   }
 
+  // Endpoint clones share the socket across tasks; only the serve loop receives on it (receiving is racy from other threads!).
+  serve_fabrics(transport::Endpoint::udp(sock), port, fabrics).await
+}
+
+/// The daemon's receive loop on one socket: run inbound ExecuteRequests, feed ProgramStdin and
+/// TtyInput to their programs, store verified signed messages and answer HistoryRequests from that
+/// store. `serve_group` runs it on a multicast-joined UDP socket; the in-process fabric simulator in
+/// the tests runs it on simulated endpoints.
+pub async fn serve_endpoint(sock: transport::Endpoint, port: u16, executor: std::sync::Arc<executor::Executor>, local_config: std::sync::Arc<config::Config>) -> DynResult<()> {
+  serve_fabrics(sock, port, vec![ServedFabric { executor, config: local_config }]).await
+}
+
+/// [`serve_endpoint`] for every fabric in `fabrics`: each datagram goes to the fabric it was sent on,
+/// and everything sent in answer goes back out on that fabric. Datagrams for any other fabric are
+/// dropped unread.
+#[allow(unreachable_code)]
+pub async fn serve_fabrics(sock: transport::Endpoint, port: u16, fabrics: Vec<ServedFabric>) -> DynResult<()> {
+  let fabrics: Vec<(messages::FabricId, ServedFabric)> = fabrics.into_iter().map(|f| (f.config.network.fabric_id(), f)).collect();
+  // Each fabric's sink for the signed messages its programs send (host::messages_send*), also
+  // retransmitting its reliable sends. The tasks stop with this loop: dropping the set aborts them.
+  let mut senders = tokio::task::JoinSet::new();
+  let sinks: Vec<tokio::sync::mpsc::UnboundedSender<Vec<u8>>> = fabrics.iter().map(|(fabric_id, fabric)| {
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    senders.spawn(send_to_fabric(fabric.executor.clone(), sock.scoped(*fabric_id), port, rx));
+    tx
+  }).collect();
     // A whole program arrives in one datagram, so this must be large enough to hold the biggest
     // ExecuteRequest we expect. UDP tops out near 64KiB; size to that so we don't silently truncate
     // (a too-small buffer clips the request and serde_bare fails with UnexpectedEof).
     let mut buf = [0; 64*1024];
+    let mut history_guard = history_guard::HistoryGuard::new();
     loop {
         let (len, addr) = sock.recv_from(&mut buf).await.map_err(map_loc_err!())?;
         //tracing::warn!("{:?} bytes received from {:?} => {:?}", len, addr, &buf[..len]);
@@ -157,103 +334,203 @@ pub async fn serve_group(interfaces: &[(u32, String, Vec<std::net::IpAddr>)], mu
         }
 
         #[allow(unreachable_patterns)]
-        match serde_bare::from_slice::<messages::NetworkMessage>(&buf[..len]) {
-          Ok(network_message) => {
+        match messages::decode(&buf[..len]) {
+          Ok((fabric_id, network_message)) => {
+            let Some(index) = fabrics.iter().position(|(id, _)| *id == fabric_id) else {
+              if crate::v_is_info() {
+                tracing::info!("Dropped a message from {} sent on a fabric we're not on", addr);
+              }
+              continue;
+            };
+            let fabric = &fabrics[index].1;
+            // The executor's config, rather than the one the listener started with: a reload swaps in
+            // new peers and limits without restarting the listener.
+            let executor = &fabric.executor;
+            let local_config = &executor.config();
+            let sock = sock.scoped(fabric_id);
             match network_message {
-              messages::NetworkMessage::ExecuteRequest { program_data } => {
+              request @ (messages::NetworkMessage::ExecuteRequest { .. } | messages::NetworkMessage::ExtendedExecuteRequest { .. }) => {
+                let Some(program_data) = request.into_program_data() else { continue };
+                // A discovery request's time budget counts from here.
+                let received_at = tokio::time::Instant::now();
                 // Security audit: record who asked us to run a program, by full public key, and whether
                 // their identity self-signature checks out. (Whether the program is actually allowed to
                 // DO anything is enforced later via host::trusts_me against our trusted-keys set.)
                 match program_data.source.check_self_signature() {
-                  Ok(()) => log_sig_event("execute-req", true, addr, &program_data.source,
-                    &format!(" program={:?}", program_data.human_name)),
+                  Ok(()) => {
+                    log_sig_event("execute-req", true, addr, &program_data.source,
+                      &format!(" program={:?}", program_data.human_name));
+                    // Passively remember whoever just contacted us as a fabric neighbour. This is not a
+                    // discovery protocol: it just lets later discovery *programs* enumerate our peers.
+                    executor.note_peer(addr, &program_data.source);
+                  }
                   Err(e) => log_sig_event("execute-req", false, addr, &program_data.source,
                     &format!(" program={:?} error=bad-identity-sig detail={e:?}", program_data.human_name)),
                 }
                 if crate::v_is_info() {
                   tracing::warn!("Recieved ExecuteRequest: {:?}", &program_data.human_name );
                 }
-                // Passively remember whoever just contacted us as a fabric neighbour. This is not a
-                // discovery protocol: it just lets later discovery *programs* enumerate our peers.
-                executor.note_peer(addr, &program_data.source);
+                // `run --to` addresses a request to one node; a copy that reached us anyway (multicast,
+                // a misrouted unicast) is left for its target.
+                if !executor.is_addressed_to_us(&program_data) {
+                  if crate::v_is_info() {
+                    let target = program_data.options.target_pubkey.as_deref().unwrap_or_default();
+                    tracing::info!("Ignoring ExecuteRequest {:?} from {}: addressed to {}", program_data.human_name, addr, crypto_utils::short_id(target));
+                  }
+                  continue;
+                }
                 let return_slot = std::sync::Arc::new(std::sync::Mutex::new(executor::ExecReturn::default()));
                 let stdio_fwd = executor::wasi_adapters::WasiStdioSimpleForwarder::new_udp(addr, UdpSocketSender::new(&sock) );
                 // Our OWN address as this caller reaches us: the local interface address on the same
                 // network as `addr`, paired with our serve port. Discovery reports this so the origin
                 // sees each node's real address instead of the relay it was reached through.
-                let node_addr = net_utils::local_addr_facing(addr.ip())
+                let node_addr = sock.local_ip_facing(addr.ip())
                   .map(|ip| std::net::SocketAddr::new(ip, port).to_string());
-                let exec_opts = executor::ExecOptions { node_addr, ..Default::default() };
-                match executor.begin_exec(&program_data, stdio_fwd, exec_opts, return_slot.clone()).await { // TODO async off to a thread pool
+                // A client streaming stdin (`run --stdin`) gets a pipe only it may feed, keyed to this
+                // request; everyone else's program keeps a closed stdin.
+                let stdin = if program_data.options.wants_stdin {
+                  Some(executor::StdinPipeOptions { owner: Some(addr) })
+                } else {
+                  None
+                };
+                // A client attaching its terminal (`run --tty`) gets one drawn back to it over the network
+                // and fed only by it. The session stays metered: the caller's identity is only a replayable
+                // self-signature, so it can't vouch for an unmetered run.
+                let (tty, tty_input, tty_draws) = match program_data.options.tty_size {
+                  Some(size) => {
+                    let (handle, input, draws) = crate::tty::remote(size);
+                    (Some(handle), Some(executor::TtyInputOptions { owner: Some(addr), input }), Some(draws))
+                  }
+                  None => (None, None, None),
+                };
+                let trusted = executor.trusts_pubkey(&program_data.source.encoded_public_key);
+                // Only a trusted caller's program may send signed messages as this node.
+                let fabric_send_tx = Some(sinks[index].clone()).filter(|_| trusted);
+                let exec_opts = executor::ExecOptions { node_addr, stdin, tty, tty_input, fabric_send_tx, owner: Some(addr), ..Default::default() };
+                // Launch inline so the program (and its stdin pipe) is registered before we read the
+                // next datagram - a ProgramStdin chunk right behind the request must find it - then wait
+                // for the exit on a task of its own so this loop keeps receiving (stdin, other work).
+                match executor.begin_exec(&program_data, stdio_fwd, exec_opts, return_slot.clone()).await {
                   Ok(running_pid) => {
                     if crate::v_is_info() {
                       tracing::info!("Spawned PID {}", running_pid);
                     }
-                    // TODO stdio stuff here?
-                    let exit_code = executor.wait_for_pid_exit(running_pid).await?;
-                    if crate::v_is_info() {
-                      tracing::info!("Exited with code {}", exit_code);
-                    }
-
-                    // If the program returned a structured CBOR record (discovery), send it to the
-                    // caller as a BasicReturnMap, then recurse: forward the program to our peers and
-                    // relay their replies back up (rewriting the UUID to the caller's).
-                    let exec_return = return_slot.lock().map(|g| g.clone()).unwrap_or_default();
-                    if let Some(cbor) = exec_return.map {
-                      let node_msg = messages::NetworkMessage::BasicReturnMap {
-                        from_pid: running_pid,
-                        request_uuid: program_data.request_uuid,
-                        cbor_data: cbor,
-                      };
-                      if let Ok(enc) = serde_bare::to_vec(&node_msg) {
-                        let _ = sock.send_to(&enc, addr).await;
-                      }
-                    }
-                    if program_data.depth_budget > 0 {
-                      tokio::spawn(discovery_forward(
-                        executor.clone(), local_config.clone(), program_data.clone(),
-                        exec_return.forward_uuid, addr, sock.clone(), port,
-                      ));
-                    }
-
-                    let program_exit_msg = messages::NetworkMessage::BasicInsecureProgramExit {
-                      from_pid: running_pid,
-                      exit_code: exit_code,
-                    };
-                    match serde_bare::to_vec(&program_exit_msg) {
-                      Ok(program_exit_msg_encoded) => {
-                        let len = sock.send_to(&program_exit_msg_encoded, addr).await.map_err(map_loc_err!())?;
-                        tracing::warn!("{:?} bytes sent to {:?}", len, addr);
-                      }
-                      Err(e) => {
-                        tracing::info!("e = {:?}", e);
-                      }
+                    // Tell the caller who is running its program (and under which PID) before any
+                    // output, so fan-out clients can attribute every reply to a signed identity.
+                    if let Some(accepted) = executor.accepted_message(&program_data, running_pid)
+                      && let Ok(enc) = serde_bare::to_vec(&accepted) {
+                      let _ = sock.send_to(&enc, addr).await;
                     }
-
+                    tokio::spawn(finish_execute_request(
+                      executor.clone(), local_config.clone(), program_data, received_at, running_pid, return_slot, tty_draws, addr, sock.clone(), port,
+                    ));
                   }
                   Err(e) => {
                     tracing::info!("e = {:?}", e);
                   }
                 }
               }
-              messages::NetworkMessage::SignedFabricMessage { source, id, cbor_data, signature } => {
-                // A lightweight signed message (no program shipped). Verify BOTH the sender's identity
-                // self-signature AND the payload signature before trusting the name/pubkey, then append
-                // to our store for the local UI to read. Bad signatures are dropped, not executed.
-                if let Err(e) = source.check_self_signature() {
-                  // Identity self-signature failed: the claimed name/pubkey are unverified (a forged
-                  // identity). Log the CLAIMED key anyway so imposters using it are tracked.
-                  log_sig_event("fabric-msg", false, addr, &source, &format!(" error=bad-identity-sig detail={e:?}"));
-                } else if let Err(e) = source.verify_payload(&id, &cbor_data, &signature) {
-                  // Identity is genuine but the payload signature doesn't match: tampering or replay
-                  // under this key.
-                  log_sig_event("fabric-msg", false, addr, &source, &format!(" error=bad-payload-sig detail={e:?}"));
-                } else {
-                  log_sig_event("fabric-msg", true, addr, &source, "");
-                  executor.note_peer(addr, &source);
-                  let epoch = sys_utils::epoch_seconds_now_utc0();
-                  let seq = executor.record_fabric_message(source.human_name.clone(), source.encoded_public_key.clone(), &id, cbor_data, epoch);
-                  if crate::v_is_info() { tracing::info!("SignedFabricMessage from {:?} stored as seq {}", source.human_name, seq); }
+              messages::NetworkMessage::TtyInput { request_id, pid, events, hangup } => {
+                // Checked like ProgramStdin; events that don't decode are dropped one by one.
+                let events = events.iter().filter_map(|ev| crate::tty::decode_event_v2(ev)).collect();
+                if !executor.feed_tty(request_id, pid, Some(addr), events, hangup) && crate::v_is_info() {
+                  tracing::info!("Dropped TtyInput from {} (pid {}): no matching terminal", addr, pid);
+                }
+              }
+              messages::NetworkMessage::TtyRedraw { request_id, pid } => {
+                if !executor.redraw_tty(request_id, pid, Some(addr)) && crate::v_is_info() {
+                  tracing::info!("Dropped TtyRedraw from {} (pid {}): no matching terminal", addr, pid);
+                }
+              }
+              messages::NetworkMessage::ProgramKill { request_id, pid } => {
+                // Only the client that launched the program may stop it.
+                if !executor.kill_program(request_id, pid, Some(addr)) && crate::v_is_info() {
+                  tracing::info!("Dropped ProgramKill from {} (pid {}): no matching program", addr, pid);
+                }
+              }
+              messages::NetworkMessage::ProgramStdin { request_id, pid, seq, data, eof } => {
+                // Only the client that launched the program may feed it; feed_stdin checks the request id
+                // and sender address, so a stray or spoofed chunk is simply dropped. Every chunk that
+                // matched is acknowledged, so the client resends whatever the pipe did not take.
+                match executor.feed_stdin(request_id, pid, Some(addr), seq, data, eof) {
+                  Some((from_pid, next_seq)) => {
+                    let ack = messages::NetworkMessage::ProgramStdinAck { request_id, from_pid, next_seq };
+                    if let Ok(enc) = serde_bare::to_vec(&ack) {
+                      let _ = sock.send_to(&enc, addr).await;
+                    }
+                  }
+                  None => if crate::v_is_info() {
+                    tracing::info!("Dropped ProgramStdin from {} (pid {}): no matching stdin pipe", addr, pid);
+                  },
+                }
+              }
+              msg @ (messages::NetworkMessage::SignedFabricMessage { .. } | messages::NetworkMessage::ReliableFabricMessage { .. } | messages::NetworkMessage::RoomMessage { .. }) => {
+                // A lightweight signed message (no program shipped): verified, then appended to our
+                // store for the local UI to read. Bad signatures are dropped, not executed.
+                let epoch = sys_utils::epoch_seconds_now_utc0();
+                if let Some(seq) = accept_signed_message(executor, "fabric-msg", addr, &msg, epoch)
+                  && let messages::NetworkMessage::SignedFabricMessage { source, .. } | messages::NetworkMessage::ReliableFabricMessage { source, .. } | messages::NetworkMessage::RoomMessage { source, .. } = &msg {
+                  executor.note_peer(addr, source);
+                  if crate::v_is_info() { tracing::info!("Signed message from {:?} stored as seq {}", source.human_name, seq); }
+                  // Ack every copy of a reliable send, duplicates included: a copy arriving again is a
+                  // retransmit whose sender missed our last ack. Acks go to the sender host's serve
+                  // port, where its listener is; the socket it sent from may already be gone.
+                  if let Some(ack) = executor.message_ack(&msg)
+                    && let Ok(enc) = serde_bare::to_vec(&ack) {
+                    let _ = sock.send_to(&enc, std::net::SocketAddr::new(addr.ip(), port)).await;
+                  }
+                }
+              }
+              messages::NetworkMessage::MessageAck { node, source_pubkey, id, signature } => {
+                if let Err(e) = node.check_self_signature().and_then(|_| node.verify_payload(&id, &source_pubkey, &signature)) {
+                  log_sig_event("msg-ack", false, addr, &node, &format!(" error=bad-sig detail={e:?}"));
+                  continue;
+                }
+                if executor.note_message_ack(&source_pubkey, &id, &node.encoded_public_key) && crate::v_is_info() {
+                  tracing::info!("{:?} acked message {}", node.human_name, crypto_utils::to_hex(&id));
+                }
+                executor.note_peer(addr, &node);
+              }
+              messages::NetworkMessage::HistoryRequest { source, request_uuid, room, since_epoch_s, epoch_s, signature } => {
+                // Replay the signed messages we hold; each carries its sender's own signature, so the
+                // requester checks them itself. Only DMs it sent or received go back to it.
+                let payload = messages::history_request_payload(room.as_deref(), since_epoch_s, epoch_s);
+                if let Err(e) = source.check_self_signature().and_then(|_| source.verify_payload(&request_uuid, &payload, &signature)) {
+                  log_sig_event("history-req", false, addr, &source, &format!(" error=bad-sig detail={e:?}"));
+                  continue;
+                }
+                // A reply is far bigger than the request, so only a fresh one, once, and not too often
+                // from one address: neither a replay nor a spoofed source turns us into an amplifier.
+                let now_epoch_s = sys_utils::epoch_seconds_now_utc0();
+                if let Err(refusal) = history_guard.admit(addr.ip(), request_uuid, epoch_s, now_epoch_s, std::time::Instant::now()) {
+                  log_sig_event("history-req", true, addr, &source, &format!(" room={room:?} refused={refusal:?}"));
+                  continue;
+                }
+                log_sig_event("history-req", true, addr, &source, &format!(" room={room:?}"));
+                let entries = executor.message_history_for(room.as_deref(), since_epoch_s, &source.encoded_public_key, HISTORY_REPLY_MAX_ENTRIES);
+                // Sent from a task of its own, so a long history doesn't hold up this loop.
+                let replies = messages::history_replies(request_uuid, entries, HISTORY_REPLY_MAX_BYTES);
+                tokio::spawn(async move {
+                  for reply in replies {
+                    if let Ok(enc) = serde_bare::to_vec(&reply) {
+                      let _ = sock.send_to(&enc, addr).await;
+                    }
+                  }
+                });
+              }
+              msg @ messages::NetworkMessage::KeyRotation { .. } => {
+                let messages::NetworkMessage::KeyRotation { old, new, .. } = &msg else { continue };
+                let rotation = match key_rotation::verify(&msg, sys_utils::epoch_seconds_now_utc0()) {
+                  Ok(rotation) => rotation,
+                  Err(e) => {
+                    log_sig_event("key-rotation", false, addr, old, &format!(" error=bad-sig detail={e:?}"));
+                    continue;
+                  }
+                };
+                let successor = crypto_utils::to_hex(&new.encoded_public_key);
+                log_sig_event("key-rotation", true, addr, old, &format!(" successor={successor}"));
+                if executor.accept_key_rotation(&rotation).await {
+                  tracing::warn!("[ key_rotations ] {:?} ({}) rotated to {}; trusting the new key", old.human_name, crypto_utils::short_id(&old.encoded_public_key), crypto_utils::short_id(&new.encoded_public_key));
                 }
               }
               unused => {
@@ -277,28 +554,176 @@ pub async fn serve_group(interfaces: &[(u32, String, Vec<std::net::IpAddr>)], mu
 }
 
 
-/// Stop a subtree relay after this long with no new data (the subtree has gone quiet).
+/// Put the signed messages `executor`'s programs send (arriving on `outbox`) on its fabric through
+/// `sock`, to each of the fabric's multicast groups on `port` and each `[[peer]]`, and resend its
+/// reliable sends as they fall due (see [`delivery`]) the same way. Runs until aborted. A socket of
+/// one family can't reach the other's groups or peers; those sends fail quietly.
+async fn send_to_fabric(executor: std::sync::Arc<executor::Executor>, sock: transport::Endpoint, port: u16, mut outbox: tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>) {
+  let mut tick = tokio::time::interval(delivery::RETRANSMIT_TICK);
+  loop {
+    let batch = tokio::select! {
+      Some(bytes) = outbox.recv() => vec![bytes],
+      _ = tick.tick() => executor.due_retransmits(),
+    };
+    if batch.is_empty() {
+      continue;
+    }
+    let config = executor.config();
+    let mut targets: Vec<std::net::SocketAddr> = config.network.groups().into_iter().map(|group| std::net::SocketAddr::new(group, port)).collect();
+    for peer in config.peer.iter() {
+      if let Some(addr) = net_utils::resolve_peer_addr(peer, port).await {
+        targets.push(addr);
+      }
+    }
+    for bytes in batch.iter() {
+      for addr in targets.iter() {
+        let _ = sock.send_to(bytes, *addr).await;
+      }
+    }
+  }
+}
+
+/// Send a `run --tty` program's draw op batches back to the client at `addr` as `TtyDraw` messages
+/// until `exit` completes, and return its status. Whatever the program flushed is queued by the time
+/// it exits, so that all goes out first and the client draws the last frame before it sees the exit.
+async fn relay_tty_draws(mut draws: tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>, pid: u64, addr: std::net::SocketAddr, sock: &transport::Endpoint, exit: impl std::future::Future<Output = executor::ExitStatus>) -> executor::ExitStatus {
+  let mut seq = 0;
+  let send = |ops: Vec<u8>, seq: u64| async move {
+    if let Ok(enc) = serde_bare::to_vec(&messages::NetworkMessage::TtyDraw { from_pid: pid, seq, ops }) {
+      let _ = sock.send_to(&enc, addr).await;
+    }
+  };
+  tokio::pin!(exit);
+  loop {
+    tokio::select! {
+      biased;
+      Some(ops) = draws.recv() => {
+        send(ops, seq).await;
+        seq += 1;
+      }
+      status = &mut exit => {
+        while let Ok(ops) = draws.try_recv() {
+          send(ops, seq).await;
+          seq += 1;
+        }
+        return status;
+      }
+    }
+  }
+}
+
+/// Wait for a program launched from an ExecuteRequest to exit, then report back to its caller: the
+/// structured CBOR record if it returned one (discovery), the onward discovery fan-out if it still
+/// has depth budget, a `run --tty` program's drawing, and finally the structured exit
+/// (code, reason, fuel). Runs as its own task so the serve loop keeps receiving while the
+/// program runs.
+#[allow(clippy::too_many_arguments)]
+async fn finish_execute_request(
+  executor: std::sync::Arc<executor::Executor>,
+  local_config: std::sync::Arc<config::Config>,
+  program_data: executor::ProgramData,
+  received_at: tokio::time::Instant,
+  running_pid: u64,
+  return_slot: std::sync::Arc<std::sync::Mutex<executor::ExecReturn>>,
+  tty_draws: Option<tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>>,
+  addr: std::net::SocketAddr,
+  sock: transport::Endpoint,
+  port: u16,
+) {
+  let exit = executor.wait_for_pid_exit_status(running_pid);
+  let status = match tty_draws {
+    Some(draws) => relay_tty_draws(draws, running_pid, addr, &sock, exit).await,
+    None => exit.await,
+  };
+  if crate::v_is_info() {
+    tracing::info!("Exited with code {} ({})", status.exit_code, status.reason.describe());
+  }
+
+  // If the program returned a structured CBOR record (discovery), send it to the
+  // caller as a BasicReturnMap, then recurse: forward the program to our peers and
+  // relay their replies back up (rewriting the UUID to the caller's).
+  let exec_return = return_slot.lock().map(|g| g.clone()).unwrap_or_default();
+  if let Some(cbor) = exec_return.map {
+    let node_msg = messages::NetworkMessage::BasicReturnMap {
+      from_pid: running_pid,
+      request_uuid: program_data.request_uuid,
+      cbor_data: cbor,
+    };
+    if let Ok(enc) = serde_bare::to_vec(&node_msg) {
+      let _ = sock.send_to(&enc, addr).await;
+    }
+  }
+  if program_data.depth_budget > 0 {
+    tokio::spawn(discovery_forward(
+      executor.clone(), local_config.clone(), program_data.clone(), received_at,
+      exec_return.forward_uuid, addr, sock.clone(), port,
+    ));
+  }
+
+  let program_exit_msg = messages::NetworkMessage::ProgramExit {
+    from_pid: running_pid,
+    exit_code: status.exit_code,
+    reason: status.reason,
+    fuel_consumed: status.fuel_consumed,
+    backtrace: status.backtrace,
+  };
+  match serde_bare::to_vec(&program_exit_msg) {
+    Ok(program_exit_msg_encoded) => {
+      match sock.send_to(&program_exit_msg_encoded, addr).await {
+        Ok(len) => tracing::warn!("{:?} bytes sent to {:?}", len, addr),
+        Err(e) => tracing::info!("e = {:?}", e),
+      }
+    }
+    Err(e) => {
+      tracing::info!("e = {:?}", e);
+    }
+  }
+}
+
+/// For a request whose origin set no time budget (`time_budget_ms` of 0): stop a subtree relay after
+/// this long with no new data (the subtree has gone quiet)...
 const RELAY_QUIET_TIMEOUT_MS: u64 = 1500;
-/// Absolute cap for one hop's relay window (the origin client waits ~12s overall).
+/// ...or after this long in all.
 const RELAY_MAX_MS: u64 = 9000;
 
 /// Forward the discovery program to each eligible peer and relay their `BasicReturn*` replies back to
 /// our caller, rewriting each reply's UUID to the caller's. Runs as its own task so the serve recv
 /// loop keeps going. Applies the trust-based depth budget and the visited-set (keyed by identity
-/// public key) so the fan-out always terminates and never loops back on itself.
+/// public key) so the fan-out always terminates and never loops back on itself, and the origin's
+/// time budget (counted from `received_at`) so it stops once the origin has stopped listening.
+#[allow(clippy::too_many_arguments)]
 async fn discovery_forward(
   executor: std::sync::Arc<executor::Executor>,
   local_config: std::sync::Arc<config::Config>,
   incoming: executor::ProgramData,
+  received_at: tokio::time::Instant,
   forward_uuid: Option<[u8; 16]>,
   caller_addr: std::net::SocketAddr,
-  sock: std::sync::Arc<tokio::net::UdpSocket>,
+  sock: transport::Endpoint,
   port: u16,
 ) {
   // We can only forward if we have a signed identity to sign the sub-request `source` with.
   let our_identity = match executor.identity_data() { Some(id) => id, None => return };
   let our_pubkey = executor.identity_pubkey();
 
+  // With a time budget, our children get what is left of ours minus a hop margin, and we don't
+  // forward at all once too little is left for their records to make it back to the origin.
+  let (deadline, child_budget_ms) = match incoming.options.time_budget_ms {
+    0 => (None, 0),
+    budget => {
+      let deadline = received_at + std::time::Duration::from_millis(u64::from(budget));
+      match discovery::child_time_budget_ms(deadline.saturating_duration_since(tokio::time::Instant::now())) {
+        Some(child_budget) => (Some(deadline), child_budget),
+        None => {
+          if crate::v_is_info() {
+            tracing::info!("[ discovery ] Not forwarding {:?}: the origin's deadline has passed", incoming.human_name);
+          }
+          return;
+        }
+      }
+    }
+  };
+
   // UUID for our onward sub-requests: prefer the program-generated one (host::set_forward_uuid,
   // seeded from host::random in network-map.c); fall back to a fresh random one.
   let child_uuid = forward_uuid.unwrap_or_else(discovery::random_uuid16);
@@ -307,24 +732,30 @@ async fn discovery_forward(
   let mut child_visited = incoming.visited.clone();
   child_visited.push(our_pubkey.clone());
 
-  // De-duplicated forwarding targets: configured [[peer]]s + passively-observed neighbours.
-  let mut targets: Vec<(std::net::SocketAddr, Option<Vec<u8>>)> = Vec::new();
+  // De-duplicated forwarding targets: configured [[peer]]s (with the key check their replies must
+  // pass) + passively-observed neighbours.
+  let mut targets: Vec<(std::net::SocketAddr, Option<Vec<u8>>, Option<known_peers::PeerCheck>)> = Vec::new();
   let mut seen_addrs: std::collections::HashSet<std::net::SocketAddr> = std::collections::HashSet::new();
   for peer in local_config.peer.iter() {
     if let Some(addr) = net_utils::resolve_peer_addr(peer, port).await {
-      let pubkey = peer.expected_key_str()
-        .and_then(|s| crypto_utils::public_key_to_ed25519_vk(s).ok())
-        .map(|vk| vk.as_bytes().to_vec());
-      if seen_addrs.insert(addr) { targets.push((addr, pubkey)); }
+      let check = match known_peers::PeerCheck::new(&local_config, peer).await {
+        Ok(check) => check,
+        Err(e) => {
+          tracing::warn!("[ discovery ] Not forwarding to peer [{}]: {}", peer.label(), e);
+          continue;
+        }
+      };
+      let pubkey = check.key.key().map(<[u8]>::to_vec);
+      if seen_addrs.insert(addr) { targets.push((addr, pubkey, Some(check))); }
     }
   }
   for (addr, pubkey) in executor.observed_targets() {
     if seen_addrs.insert(addr) {
-      targets.push((addr, if pubkey.is_empty() { None } else { Some(pubkey) }));
+      targets.push((addr, if pubkey.is_empty() { None } else { Some(pubkey) }, None));
     }
   }
 
-  for (peer_addr, peer_pubkey) in targets {
+  for (peer_addr, peer_pubkey, check) in targets {
     // Loop prevention: skip ourselves and any peer already on the path (by identity pubkey).
     if let Some(pk) = &peer_pubkey {
       if *pk == our_pubkey { continue; }
@@ -338,44 +769,84 @@ async fn discovery_forward(
       .set_wasm_program_bytes(&incoming.wasm_program_bytes)
       .set_source(&our_identity)
       .set_request_context(child_uuid, child_depth, child_visited.clone())
+      .set_time_budget_ms(child_budget_ms)
       .build()
     {
       Ok(pd) => pd,
       Err(_) => continue,
     };
-    let req = messages::NetworkMessage::ExecuteRequest { program_data: sub };
+    let req = messages::NetworkMessage::execute_request(sub);
     let sub_bytes = match serde_bare::to_vec(&req) { Ok(b) => b, Err(_) => continue };
 
-    tokio::spawn(relay_one_peer(sub_bytes, peer_addr, caller_addr, incoming.request_uuid, sock.clone()));
+    let check = check.filter(known_peers::PeerCheck::is_enforced);
+    tokio::spawn(relay_one_peer(sub_bytes, peer_addr, child_uuid, check, caller_addr, incoming.request_uuid, deadline, sock.clone()));
   }
 }
 
 /// Send one forwarded discovery sub-request to `peer_addr`, then relay every `BasicReturn*` reply back
-/// to `caller_addr` on `sock`, rewriting the reply's UUID to `caller_uuid`. Ends when the subtree goes
-/// quiet or the per-hop cap elapses.
+/// to `caller_addr` on `sock`, rewriting the reply's UUID to `caller_uuid`. Ends at the origin's
+/// `deadline`, or - for a request that didn't carry one - when the subtree goes quiet or the per-hop
+/// cap elapses. With a `check` (a pinned or TOFU-checked [[peer]]) nothing is
+/// relayed until the peer's signed `ProgramAccepted` for `child_uuid` passes it, only datagrams from
+/// `peer_addr` count, and a peer answering with the wrong key gets its whole subtree dropped.
+#[allow(clippy::too_many_arguments)]
 async fn relay_one_peer(
   sub_bytes: Vec<u8>,
   peer_addr: std::net::SocketAddr,
+  child_uuid: [u8; 16],
+  check: Option<known_peers::PeerCheck>,
   caller_addr: std::net::SocketAddr,
   caller_uuid: [u8; 16],
-  sock: std::sync::Arc<tokio::net::UdpSocket>,
+  deadline: Option<tokio::time::Instant>,
+  sock: transport::Endpoint,
 ) {
-  let bind: (std::net::IpAddr, u16) = if peer_addr.is_ipv4() {
-    (std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED), 0)
-  } else {
-    (std::net::IpAddr::V6(std::net::Ipv6Addr::UNSPECIFIED), 0)
-  };
-  let relay = match tokio::net::UdpSocket::bind(bind).await { Ok(s) => s, Err(_) => return };
+  let relay = match sock.open_ephemeral(peer_addr) { Ok(s) => s, Err(_) => return };
   if relay.send_to(&sub_bytes, peer_addr).await.is_err() { return; }
 
   let mut buf = [0u8; 64 * 1024];
-  let start = std::time::Instant::now();
-  let quiet = std::time::Duration::from_millis(RELAY_QUIET_TIMEOUT_MS);
+  // Without a deadline from the origin, fall back to the fixed cap plus a quiet timeout. With one,
+  // the subtree may be quiet for a while (a deep hop compiling the program) and still be heard.
+  let (deadline, quiet) = match deadline {
+    Some(deadline) => (deadline, None),
+    None => (
+      tokio::time::Instant::now() + std::time::Duration::from_millis(RELAY_MAX_MS),
+      Some(std::time::Duration::from_millis(RELAY_QUIET_TIMEOUT_MS)),
+    ),
+  };
+  // Records that raced ahead of the peer's ProgramAccepted, while a `check` is still pending.
+  let mut verified = check.is_none();
+  let mut held: Vec<messages::NetworkMessage> = Vec::new();
   loop {
-    if start.elapsed() >= std::time::Duration::from_millis(RELAY_MAX_MS) { break; }
-    match tokio::time::timeout(quiet, relay.recv_from(&mut buf)).await {
-      Ok(Ok((len, _from))) => {
-        if let Ok(msg) = serde_bare::from_slice::<messages::NetworkMessage>(&buf[..len]) {
+    let left = deadline.saturating_duration_since(tokio::time::Instant::now());
+    if left.is_zero() { break; }
+    match tokio::time::timeout(quiet.map_or(left, |q| q.min(left)), relay.recv_from(&mut buf)).await {
+      Ok(Ok((len, from))) => {
+        let Some(msg) = relay.fabric().decode(&buf[..len]) else { continue };
+        if check.is_some() && from != peer_addr {
+          continue;
+        }
+        let to_relay = match &check {
+          Some(check) if !verified => match msg {
+            messages::NetworkMessage::ProgramAccepted { request_uuid, from_pid, node, trusts_caller, signature } if request_uuid == child_uuid => {
+              let payload = messages::program_accepted_payload(from_pid, trusts_caller);
+              if let Err(e) = node.check_self_signature().and_then(|_| node.verify_payload(&request_uuid, &payload, &signature)) {
+                log_sig_event("discovery-relay", false, from, &node, &format!(" error=bad-accepted-sig detail={e:?}"));
+                continue;
+              }
+              if !check.admit("discovery-relay", from, &node).await {
+                return;
+              }
+              verified = true;
+              std::mem::take(&mut held)
+            }
+            other => {
+              held.push(other);
+              continue;
+            }
+          },
+          _ => vec![msg],
+        };
+        for msg in to_relay {
           let rewritten = match msg {
             messages::NetworkMessage::BasicReturnMap { from_pid, cbor_data, .. } =>
               Some(messages::NetworkMessage::BasicReturnMap { from_pid, request_uuid: caller_uuid, cbor_data }),
@@ -391,18 +862,18 @@ async fn relay_one_peer(
         }
       }
       Ok(Err(_)) => break, // socket error
-      Err(_) => break,     // quiet timeout: subtree has gone silent
+      Err(_) => break,     // quiet timeout (subtree has gone silent) or the deadline
     }
   }
 }
 
 #[derive(Clone)]
 pub struct UdpSocketSender {
-    socket: std::sync::Arc<tokio::net::UdpSocket>,
+    socket: transport::Endpoint,
 }
 
 impl UdpSocketSender {
-  pub fn new(socket: &std::sync::Arc<tokio::net::UdpSocket>) -> UdpSocketSender {
+  pub fn new(socket: &transport::Endpoint) -> UdpSocketSender {
     UdpSocketSender {
       socket: socket.clone()
     }
@@ -0,0 +1,162 @@
+use super::*;
+
+/// The stem of the bundled capacity probe (see example-programs/embedded.list + capacity-probe.c).
+const EMBEDDED_PROBE_NAME: &str = "capacity-probe";
+
+/// `weverywhere submit` entry point: probe the capacity of every node we reach directly, rank them
+/// (see [`scheduler::rank`]), and run the job on the best `count` of them. Each placement is a
+/// `run --to`-style unicast: the request names the node's key and only its signed replies count.
+/// A node that rejects the job or never accepts it is skipped for the next one in the ranking; once a
+/// node has accepted it, its job is waited for until it exits (or `exit_timeout` passes) and is never
+/// placed again. Returns the first non-zero exit code among the placed jobs, like `run --fabric`.
+#[allow(clippy::too_many_arguments)]
+pub async fn submit(
+  args: &args::Args,
+  file_path: &std::path::PathBuf,
+  count: usize,
+  probe: Option<std::path::PathBuf>,
+  probe_window: std::time::Duration,
+  exit_timeout: Option<std::time::Duration>,
+  multicast_groups: Option<args::MulticastAddressVec>,
+  port: Option<u16>,
+  arg_list: Vec<String>,
+  arg_map: Vec<(String, String)>,
+) -> DynResult<u32> {
+  if count == 0 {
+    return Err("--count must be at least 1".into());
+  }
+  let wasm_bytes = tokio::fs::read(file_path).await.map_err(map_loc_err!())?;
+  let (probe_bytes, probe_label) = resolve_probe_program(probe).await?;
+  if crate::v_is_info() {
+    tracing::info!("[ submit ] capacity probe: {}", probe_label);
+  }
+
+  let mut local_config = config::Config::read_from_file(&args.config_path()).await.map_err(map_loc_err!())?.fabric_view(args.fabric_name.as_deref());
+  local_config.network.apply_cli_overrides(multicast_groups.as_deref(), port);
+  let source = config::IdentityData::generate_from_config(&local_config).await.map_err(map_loc_err!())?;
+
+  let our_pubkey = local_config.identity.read_public_key_ed25519_pem_file().await
+    .map(|vk| vk.as_bytes().to_vec())
+    .unwrap_or_default();
+  let mut trusted: std::collections::HashSet<Vec<u8>> = std::collections::HashSet::new();
+  if !our_pubkey.is_empty() { trusted.insert(our_pubkey); }
+  for t in local_config.trusted.iter() {
+    if let Ok(vk) = crypto_utils::public_key_to_ed25519_vk(&t.key) {
+      trusted.insert(vk.as_bytes().to_vec());
+    }
+  }
+
+  // Step 1: observe. Every node that answers with a verified record becomes a candidate.
+  let nodes = netmap::probe_neighbours(&local_config, &source, &probe_bytes, EMBEDDED_PROBE_NAME, probe_window, |_| false).await?;
+  let candidates: Vec<scheduler::Candidate> = nodes.into_iter().filter_map(|n| {
+    let capacity = scheduler::Capacity::from_record(&n.record)?;
+    Some(scheduler::Candidate {
+      we_trust: trusted.contains(&n.pubkey),
+      trusts_us: n.trusts_caller,
+      hostname: n.hostname,
+      pubkey: n.pubkey,
+      addr: n.addr,
+      capacity,
+    })
+  }).collect();
+  let ranked = scheduler::rank(candidates);
+  if ranked.is_empty() {
+    return Err("no node answered the capacity probe; is a daemon reachable (try `weverywhere netmap`)?".into());
+  }
+  for (i, c) in ranked.iter().enumerate() {
+    tracing::warn!(
+      "[ submit ] #{} {} ({}) @ {}  trusts us: {}  we trust: {}  free cpu: {:.2} of {} cores  free mem: {} MiB  running: {}",
+      i + 1, c.hostname, crypto_utils::short_id(&c.pubkey), c.addr,
+      if c.trusts_us { "yes" } else { "no" }, if c.we_trust { "yes" } else { "no" },
+      c.capacity.free_millicores() as f64 / 1000.0, c.capacity.cores,
+      c.capacity.mem_available_bytes / (1024 * 1024), c.capacity.running,
+    );
+  }
+  if ranked.len() < count {
+    tracing::warn!("[ submit ] only {} node(s) answered for {} job(s)", ranked.len(), count);
+  }
+
+  // Step 2: place. `count` workers take nodes off the ranking best-first; one that fails to place
+  // its job simply takes the next node, so a rejection costs one node, not the job.
+  let queue = std::sync::Arc::new(std::sync::Mutex::new(std::collections::VecDeque::from(ranked)));
+  let human_name = file_path.file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_else(|| "UNSET_NAME".to_string());
+  let fabric = local_config.network.fabric_id();
+  let mut workers = tokio::task::JoinSet::new();
+  for _ in 0..count {
+    let queue = queue.clone();
+    let wasm_bytes = wasm_bytes.clone();
+    let source = source.clone();
+    let human_name = human_name.clone();
+    let arg_list = arg_list.clone();
+    let arg_map = arg_map.clone();
+    workers.spawn(async move {
+      loop {
+        let node = queue.lock().ok().and_then(|mut q| q.pop_front())?;
+        let target = run::TargetNode {
+          addr: node.addr,
+          label: format!("{} ({})", node.hostname, crypto_utils::short_id(&node.pubkey)),
+          pubkey: Some(node.pubkey),
+          peer: None,
+        };
+        match place_job(&wasm_bytes, fabric, &source, &human_name, arg_list.clone(), arg_map.clone(), &target, exit_timeout).await {
+          Ok(code) => return Some(code),
+          Err(e) => tracing::warn!("[ submit ] could not place the job on {}: {}; trying the next node", target.label, e),
+        }
+      }
+    });
+  }
+  let results: Vec<Option<u32>> = workers.join_all().await;
+  let placed: Vec<u32> = results.into_iter().flatten().collect();
+  if placed.len() < count {
+    return Err(format!("placed only {} of {} job(s); every other candidate refused or never answered", placed.len(), count).into());
+  }
+  Ok(placed.into_iter().find(|c| *c != 0).unwrap_or(0))
+}
+
+/// Send one copy of the job to `target` and print its replies. Err only if the node never confirmed
+/// (by its signed acceptance) that it is running the job, so the next node may take it. Once it has,
+/// the job counts as placed: Ok with its exit code when it finishes, or with
+/// [`run::NO_EXIT_STATUS`] if `exit_timeout` passes first (or its exit reply was lost).
+#[allow(clippy::too_many_arguments)]
+async fn place_job(wasm_bytes: &[u8], fabric: messages::FabricId, source: &config::IdentityData, human_name: &str, arg_list: Vec<String>, arg_map: Vec<(String, String)>, target: &run::TargetNode, exit_timeout: Option<std::time::Duration>) -> DynResult<u32> {
+  let request_uuid = discovery::random_uuid16();
+  let pd = executor::ProgramDataBuilder::new()
+    .set_human_name(human_name)
+    .set_wasm_program_bytes(wasm_bytes)
+    .set_source(source)
+    .set_args(arg_list, arg_map)
+    .set_request_context(request_uuid, 0, Vec::new())
+    .set_target_pubkey(target.pubkey.clone())
+    .build().map_err(map_loc_err!())?;
+  let sock = transport::Endpoint::udp_ephemeral(target.addr).map_err(map_loc_err!())?.scoped(fabric);
+  let request = messages::NetworkMessage::execute_request(pd);
+  let exit = run::run_unicast(&sock, &request, target.addr, request_uuid, None, Some(target), run::ExitWait::UntilExit(exit_timeout)).await?;
+  Ok(exit.unwrap_or_else(|| {
+    tracing::warn!("[ submit ] {} accepted the job but no exit status arrived (--timeout, or the reply was lost)", target.label);
+    run::NO_EXIT_STATUS
+  }))
+}
+
+/// Resolve which capacity probe to send: explicit `--probe`, else the embedded program, else the
+/// on-disk compiled example. Returns the bytes plus a short label describing the source.
+async fn resolve_probe_program(probe: Option<std::path::PathBuf>) -> DynResult<(Vec<u8>, String)> {
+  if let Some(path) = probe {
+    match tokio::fs::read(&path).await {
+      Ok(bytes) => return Ok((bytes, format!("override file {}", path.display()))),
+      Err(e) => return Err(format!("Could not read --probe {:?} ({})", path, e).into()),
+    }
+  }
+  if let Some(bytes) = crate::embedded_programs::get(EMBEDDED_PROBE_NAME) {
+    return Ok((bytes.to_vec(), format!("embedded '{EMBEDDED_PROBE_NAME}'")));
+  }
+  let fallback = std::path::PathBuf::from("target").join("example-programs").join(format!("{EMBEDDED_PROBE_NAME}.wasm"));
+  match tokio::fs::read(&fallback).await {
+    Ok(bytes) => Ok((bytes, format!("on-disk {}", fallback.display()))),
+    Err(e) => Err(format!(
+      "No capacity probe available: this binary has no embedded '{EMBEDDED_PROBE_NAME}' and \
+       {:?} could not be read ({}). Build with zig to embed it, run \
+       `uv run scripts/compile-example-programs.py`, or pass `--probe <FILE.wasm>`.",
+      fallback, e
+    ).into()),
+  }
+}
@@ -27,27 +27,340 @@ pub struct Config {
 
   #[serde(default)]
   pub limits: Limits,
+
+  #[serde(default)]
+  pub state: StateConfig,
+
+  #[serde(default)]
+  pub network: NetworkConfig,
+
+  /// Further fabrics the daemon serves besides `[network].fabric`, each with its own trust set. See
+  /// [`FabricMembership`].
+  #[serde(default)]
+  pub fabric: Vec<FabricMembership>,
+
+  /// Keys a file takes back out of everything loaded before it: `[[trusted]]` and every `[[fabric]]`
+  /// trust set. With the other `*_remove` lists, this lets an include revoke what an earlier file
+  /// granted. Consumed by loading, so a loaded config's are always empty.
+  #[serde(default, skip_serializing)]
+  pub trusted_remove: Vec<SingleTrustedKey>,
+
+  /// `[[peer]]`s a file takes back out of everything loaded before it. See [`PeerRemoval`].
+  #[serde(default, skip_serializing)]
+  pub peer_remove: Vec<PeerRemoval>,
+
+  /// `[[fabric]]`s, by name, a file takes back out of everything loaded before it.
+  #[serde(default, skip_serializing)]
+  pub fabric_remove: Vec<FabricRemoval>,
 }
 
 #[derive(Default, Debug, Clone, serde::Serialize, serde::Deserialize, optionable::Optionable)]
 #[optionable(derive(Default, Debug, Clone, serde::Serialize, serde::Deserialize))]
 pub struct Limits {
   #[serde(default)]
-  trusted: Limit,
+  pub trusted: Limit,
   #[serde(default)]
-  untrusted: Limit,
+  pub untrusted: Limit,
 }
 
 #[derive(Default, Debug, Clone, serde::Serialize, serde::Deserialize, optionable::Optionable)]
 #[optionable(derive(Default, Debug, Clone, serde::Serialize, serde::Deserialize))]
 pub struct Limit {
+  /// Fuel (roughly instructions) a metered program starts with; it traps once that runs out. 0 = the
+  /// built-in 128,000.
+  #[serde(default)]
+  pub max_cpu_instructions: u64,
+
+  /// The most bytes any one of a program's linear memories may grow to. 0 = no cap.
+  #[serde(default)]
+  pub max_memory_bytes: u64,
+
+  /// Milliseconds a program may run, by the wall clock, before it is stopped as out of time. 0 = no
+  /// limit.
+  #[serde(default)]
+  pub max_wall_clock_ms: u64,
+}
+
+/// `[state]`: what a node learns at run time and keeps across restarts (see [`crate::peer_registry`]).
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, optionable::Optionable)]
+#[optionable(derive(Default, Debug, Clone, serde::Serialize, serde::Deserialize))]
+pub struct StateConfig {
+  /// Directory the state files live in. Defaults to [`default_state_dir`].
+  #[serde(default = "default_state_dir")]
+  pub dir: std::path::PathBuf,
+
+  /// Forget an observed peer once we haven't heard from it for this many seconds. 0 keeps peers
+  /// until they are removed with `weverywhere peers forget`.
+  #[serde(default = "default_peer_ttl_s")]
+  pub peer_ttl_s: u64,
+
+  /// What to do with a `[[peer]]` that has no `expected_key` (see [`UnpinnedPeers`]).
+  #[serde(default)]
+  pub unpinned_peers: UnpinnedPeers,
+
+  /// Keep the signed chat messages this node receives on disk (see [`crate::message_history`]), so
+  /// a restarted node still has them to show and to replay to peers asking for history.
+  #[serde(default)]
+  pub message_history: bool,
+}
+
+impl Default for StateConfig {
+  fn default() -> StateConfig {
+    StateConfig { dir: default_state_dir(), peer_ttl_s: default_peer_ttl_s(), unpinned_peers: UnpinnedPeers::default(), message_history: false }
+  }
+}
+
+/// `[state].unpinned_peers`: how replies from a `[[peer]]` without an `expected_key` are checked.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnpinnedPeers {
+  /// Accept whichever validly-signed key answers, logging it as a ready-to-paste `[[peer]]` block
+  /// (see [`PeerMetadata::unpinned_key_warning`]).
+  #[default]
+  Warn,
+  /// Trust on first use: record the first key that answers in the known-peers file and hold the
+  /// peer to it from then on, like SSH's known_hosts (see [`crate::known_peers`]).
+  Tofu,
+}
+
+// A plain setting with nothing to merge inside it; an include that sets it replaces it (same as
+// `PeerMetadata` below).
+impl optionable::Optionable for UnpinnedPeers {
+  type Optioned = Self;
+}
+impl optionable::OptionableConvert for UnpinnedPeers {
+  fn into_optioned(self) -> Self::Optioned { self }
+  fn try_from_optioned(value: Self::Optioned) -> Result<Self, optionable::Error> { Ok(value) }
+  fn merge(&mut self, other: Self::Optioned) -> Result<(), optionable::Error> {
+    *self = other;
+    Ok(())
+  }
+}
+
+/// `[network]`: how this node reaches the fabric. Every multicast send and receive path (serve, run,
+/// netmap, chat, replication) reads it; `--port` / `--multicast-groups` override it for one command.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, optionable::Optionable)]
+#[optionable(derive(Default, Debug, Clone, serde::Serialize, serde::Deserialize))]
+pub struct NetworkConfig {
+  /// The fabric this node is on: nodes only hear programs, discovery and messages from their own
+  /// fabric (see [`crate::messages::FabricId`]). Empty = the shared default fabric.
+  #[serde(default)]
+  pub fabric: String,
+
+  /// Run the fabric on a multicast group and port derived from its name instead of
+  /// `multicast_groups` / `port`, so fabrics don't even share datagrams. Ignored on the default fabric.
+  #[serde(default)]
+  pub derive_address: bool,
+
+  /// UDP port daemons listen on, and clients and relays send to (also for every `[[peer]]`).
+  #[serde(default = "default_port")]
+  pub port: u16,
+
+  /// Multicast groups the fabric runs on.
+  #[serde(default)]
+  pub multicast_groups: MulticastGroups,
+
+  /// IPv4 multicast TTL: how many routers a multicast datagram may cross.
+  #[serde(default = "default_multicast_hops")]
+  pub multicast_ttl: u32,
+
+  /// IPv6 multicast hop limit, the IPv6 counterpart of `multicast_ttl`. The group address's own
+  /// scope still applies on top: an `ff02::` group never leaves the link whatever this says.
+  #[serde(default = "default_multicast_hops")]
+  pub multicast_hops_v6: u32,
+
+  /// Only use interfaces whose name matches one of these globs (e.g. `"eth*"`). Empty = all.
+  #[serde(default)]
+  pub interfaces: Vec<String>,
+
+  /// Never use interfaces whose name matches one of these globs (e.g. `"docker*"`, `"tun*"`), even
+  /// if `interfaces` lets them in.
+  #[serde(default)]
+  pub exclude_interfaces: Vec<String>,
+
+  /// Use IPv4 multicast groups and interface addresses.
+  #[serde(default = "default_true")]
+  pub ipv4: bool,
+
+  /// Use IPv6 multicast groups and interface addresses.
+  #[serde(default = "default_true")]
+  pub ipv6: bool,
+}
+
+impl Default for NetworkConfig {
+  fn default() -> NetworkConfig {
+    NetworkConfig {
+      fabric: String::new(),
+      derive_address: false,
+      port: default_port(),
+      multicast_groups: MulticastGroups::default(),
+      multicast_ttl: default_multicast_hops(),
+      multicast_hops_v6: default_multicast_hops(),
+      interfaces: Vec::new(),
+      exclude_interfaces: Vec::new(),
+      ipv4: true,
+      ipv6: true,
+    }
+  }
+}
+
+impl NetworkConfig {
+  pub fn fabric_id(&self) -> messages::FabricId {
+    messages::FabricId::named(&self.fabric)
+  }
+
+  /// `fabric`, for people: the default fabric has no name of its own.
+  pub fn fabric_label(&self) -> &str {
+    if self.fabric.is_empty() { "(default)" } else { &self.fabric }
+  }
+
+  /// The multicast groups and port `derive_address` puts the fabric called `name` on: an
+  /// administratively-scoped IPv4 group (239.255.0.0/16), a link-local IPv6 group and a port above
+  /// the default one, all picked by a hash of the name.
+  pub fn derived_address(name: &str) -> (Vec<std::net::IpAddr>, u16) {
+    use sha2::Digest;
+    let h = sha2::Sha256::new().chain_update(b"weverywhere-fabric-address\0").chain_update(name.as_bytes()).finalize();
+    let groups = vec![
+      std::net::IpAddr::V4(std::net::Ipv4Addr::new(239, 255, h[0], h[1])),
+      std::net::IpAddr::V6(std::net::Ipv6Addr::new(0xFF02, 0, 0, 0, 0, 0, 0x7765, u16::from_be_bytes([h[0], h[1]]))),
+    ];
+    (groups, default_port() + 1 + u16::from_be_bytes([h[2], h[3]]) % 8000)
+  }
+
+  /// Whether addresses and groups of `addr`'s family are in use.
+  pub fn allows_family(&self, addr: &std::net::IpAddr) -> bool {
+    if addr.is_ipv4() { self.ipv4 } else { self.ipv6 }
+  }
+
+  /// Whether the interface called `name` may carry fabric traffic. A pattern that isn't a valid
+  /// glob only matches the name literally.
+  pub fn allows_interface(&self, name: &str) -> bool {
+    let matches = |p: &String| glob::Pattern::new(p).map(|g| g.matches(name)).unwrap_or(p == name);
+    (self.interfaces.is_empty() || self.interfaces.iter().any(matches)) && !self.exclude_interfaces.iter().any(matches)
+  }
+
+  /// Apply a command's `--multicast-groups` / `--port` flags over this section, for that command only.
+  pub fn apply_cli_overrides(&mut self, multicast_groups: Option<&[std::net::IpAddr]>, port: Option<u16>) {
+    if let Some(groups) = multicast_groups {
+      self.multicast_groups = MulticastGroups(groups.to_vec());
+    }
+    if let Some(port) = port {
+      self.port = port;
+    }
+  }
+
+  /// The multicast groups to send to and listen on, less any of a disabled family.
+  pub fn groups(&self) -> Vec<std::net::IpAddr> {
+    self.multicast_groups.0.iter().copied().filter(|g| self.allows_family(g)).collect()
+  }
+
+  /// The multicast TTL (v4) or hop limit (v6) for sends to `group`.
+  pub fn hops_for(&self, group: &std::net::IpAddr) -> u32 {
+    if group.is_ipv4() { self.multicast_ttl } else { self.multicast_hops_v6 }
+  }
+}
+
+/// `[[fabric]]`: another fabric for the daemon to serve. Requests on it run under its own trust set,
+/// and what the daemon learns there (remembered peers, TOFU keys) is kept apart from its other fabrics.
+/// Naming `[network].fabric` here just gives that fabric its trust set.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, optionable::Optionable)]
+#[optionable(derive(Default, Debug, Clone, serde::Serialize, serde::Deserialize))]
+pub struct FabricMembership {
+  pub name: String,
+
+  /// The keys trusted on this fabric, in place of the top-level `[[trusted]]` list.
   #[serde(default)]
-  max_cpu_instructions: u64,
+  pub trusted: Vec<SingleTrustedKey>,
 
+  /// Serve it on its own derived group and port (see [`NetworkConfig::derive_address`]).
   #[serde(default)]
-  max_memory_bytes: u64,
+  pub derive_address: bool,
+}
+
+/// A `[[peer_remove]]` entry: it removes every `[[peer]]` matching all the fields it sets.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, optionable::Optionable)]
+#[optionable(derive(Default, Debug, Clone, serde::Serialize, serde::Deserialize))]
+pub struct PeerRemoval {
+  /// One of the peer's `hostname`, `ipv4` or `ipv6`, as written.
+  #[serde(default)]
+  pub host: Option<String>,
+  /// The key the peer is pinned to with `expected_key`.
+  #[serde(default)]
+  pub key: Option<String>,
+}
+
+/// A `[[fabric_remove]]` entry.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, optionable::Optionable)]
+#[optionable(derive(Default, Debug, Clone, serde::Serialize, serde::Deserialize))]
+pub struct FabricRemoval {
+  pub name: String,
+}
+
+/// `[network].multicast_groups`, as written: `["224.0.0.3", "ff02::3"]`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct MulticastGroups(pub Vec<std::net::IpAddr>);
+
+impl Default for MulticastGroups {
+  fn default() -> MulticastGroups {
+    MulticastGroups(vec![
+      // "Unassigned" per https://www.iana.org/assignments/multicast-addresses/multicast-addresses.xhtml
+      std::net::IpAddr::V4(std::net::Ipv4Addr::new(224, 0, 0, 3)),
+      // "Unassigned" per https://www.iana.org/assignments/ipv6-multicast-addresses/ipv6-multicast-addresses.xhtml
+      std::net::IpAddr::V6(std::net::Ipv6Addr::new(0xFF02, 0, 0, 0, 0, 0, 0, 3)),
+    ])
+  }
+}
+
+// One list: an include that sets it replaces it rather than appending to it.
+impl optionable::Optionable for MulticastGroups {
+  type Optioned = Self;
+}
+impl optionable::OptionableConvert for MulticastGroups {
+  fn into_optioned(self) -> Self::Optioned { self }
+  fn try_from_optioned(value: Self::Optioned) -> Result<Self, optionable::Error> { Ok(value) }
+  fn merge(&mut self, other: Self::Optioned) -> Result<(), optionable::Error> {
+    *self = other;
+    Ok(())
+  }
+}
+
+fn default_port() -> u16 {
+  2240
+}
+
+/// Enough for a few routed hops; the link-local default groups stay on the link regardless.
+fn default_multicast_hops() -> u32 {
+  4
+}
+
+fn default_true() -> bool {
+  true
+}
+
+/// Default `[state].dir`: the platform's place for a service's persistent state.
+pub fn default_state_dir() -> std::path::PathBuf {
+  #[cfg(target_os = "windows")]
+  {
+    let base = std::env::var_os("ProgramData")
+      .map(std::path::PathBuf::from)
+      .unwrap_or_else(|| std::path::PathBuf::from(r"C:\ProgramData"));
+    return base.join("weverywhere").join("state");
+  }
+  #[cfg(target_os = "macos")]
+  {
+    return std::path::PathBuf::from("/Library/Application Support/weverywhere/state");
+  }
+  #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+  {
+    std::path::PathBuf::from("/var/lib/weverywhere")
+  }
 }
 
+/// One week.
+fn default_peer_ttl_s() -> u64 {
+  7 * 24 * 60 * 60
+}
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, optionable::Optionable)]
 #[optionable(derive(Default, Debug, Clone, serde::Serialize, serde::Deserialize))]
@@ -77,10 +390,13 @@ pub struct SingleStartupProgram {
 /// When we actually connect we try the addresses in a fixed preference order -
 /// `hostname`, then `ipv6`, then `ipv4` (see [`PeerMetadata::connect_hosts`]).
 ///
-/// `expected_key` optionally pins the server's advertised public key. When it is
-/// unset, the first time we reach the peer we log the key it actually advertised
-/// as a ready-to-paste TOML block (see [`PeerMetadata::unpinned_key_warning`]) so
-/// an operator can pin it.
+/// `expected_key` optionally pins the server's advertised public key: every
+/// unicast reply from the peer must then be signed with it, and a reply signed by
+/// any other key is refused. When it is unset, the first time we reach the peer we
+/// either log the key it actually advertised as a ready-to-paste TOML block (see
+/// [`PeerMetadata::unpinned_key_warning`]) so an operator can pin it, or, with
+/// `[state] unpinned_peers = "tofu"`, record it in the known-peers file (see
+/// [`crate::known_peers`]).
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(try_from = "PeerMetadataToml")]
 pub struct PeerMetadata {
@@ -262,6 +578,14 @@ pub struct IdentityConfig {
   /// still get a stable, per-machine identity that `generate-missing-keys` will populate.
   #[serde(default = "default_identity_keyfile")]
   pub keyfile: std::path::PathBuf,
+  /// File holding the passphrase of an encrypted keyfile (see [`crate::keyfile`]); one trailing
+  /// newline is ignored.
+  #[serde(default)]
+  pub passphrase_file: Option<std::path::PathBuf>,
+  /// Command printing the passphrase of an encrypted keyfile on stdout, e.g. a lookup in the OS
+  /// keyring. Program first, then its arguments; empty runs nothing.
+  #[serde(default)]
+  pub passphrase_command: Vec<String>,
 }
 
 /// Default identity keyfile: `identity.pem` beside the platform's default `weverywhere.toml` (see
@@ -300,27 +624,31 @@ pub struct IdentityData {
 
 impl IdentityData {
   pub async fn generate_from_config(config: &Config) -> DynResult<IdentityData> {
-    let human_name = config.identity.name.clone();
+    let our_private_key = config.identity.read_private_key_ed25519_pem_file().await.map_err(map_loc_err!())?;
+    Ok(IdentityData::from_signing_key(&config.identity.name, &our_private_key))
+  }
+
+  /// A freshly self-signed identity for `our_private_key` under `human_name`.
+  pub fn from_signing_key(human_name: &str, our_private_key: &ed25519_dalek::SigningKey) -> IdentityData {
+    let human_name = human_name.to_string();
     let validity_s = u16::MAX;
     let encoded_public_key_fmt = "ed25519".to_string(); // TODO dynamic keys once we support more than one format
-    let encoded_public_key = config.identity.read_public_key_ed25519_pem_file().await.map_err(map_loc_err!())?.as_bytes().to_vec();
+    let encoded_public_key = our_private_key.verifying_key().as_bytes().to_vec();
     let generated_at_utc0_epoch_s = sys_utils::epoch_seconds_now_utc0();
 
-    let our_private_key = config.identity.read_private_key_ed25519_pem_file().await.map_err(map_loc_err!())?;
-
     let signature = IdentityData::sign_identity_data(
-      &our_private_key,
+      our_private_key,
       &human_name, &generated_at_utc0_epoch_s, &validity_s, &encoded_public_key_fmt, &encoded_public_key
     );
 
-    Ok(IdentityData {
+    IdentityData {
       human_name: human_name,
       generated_at_utc0_epoch_s: generated_at_utc0_epoch_s,
       validity_s: validity_s,
       encoded_public_key_fmt: encoded_public_key_fmt,
       encoded_public_key: encoded_public_key,
       signature: signature.to_vec(),
-    })
+    }
   }
 
   pub fn sign_identity_data(signing_key: &ed25519_dalek::SigningKey,
@@ -429,43 +757,609 @@ impl IdentityData {
 
 
 impl Config {
+  /// This config as a node on the fabric called `name` sees it (`None`: `[network].fabric`): that
+  /// fabric's trust set and address if a `[[fabric]]` entry gives them, and for a fabric other than
+  /// `[network].fabric`, a state directory of its own.
+  pub fn fabric_view(&self, name: Option<&str>) -> Config {
+    let mut view = self.clone();
+    let name = name.unwrap_or(&self.network.fabric).to_string();
+    if name != self.network.fabric {
+      let dir_name: String = name.chars().map(|c| if c.is_ascii_alphanumeric() || "-_.".contains(c) { c } else { '_' }).collect();
+      view.state.dir = self.state.dir.join("fabrics").join(dir_name);
+    }
+    if let Some(membership) = self.fabric.iter().find(|m| m.name == name) {
+      view.trusted = membership.trusted.clone();
+      view.network.derive_address = membership.derive_address;
+    }
+    if view.network.derive_address && !name.is_empty() {
+      let (groups, port) = NetworkConfig::derived_address(&name);
+      view.network.multicast_groups = MulticastGroups(groups);
+      view.network.port = port;
+    }
+    view.network.fabric = name;
+    view
+  }
+
+  /// Views of every fabric the daemon serves: `[network].fabric` first, then each other `[[fabric]]`.
+  pub fn served_fabrics(&self) -> Vec<Config> {
+    let mut views = vec![self.fabric_view(None)];
+    for membership in self.fabric.iter() {
+      if !views.iter().any(|v| v.network.fabric == membership.name) {
+        views.push(self.fabric_view(Some(&membership.name)));
+      }
+    }
+    views
+  }
+
+  /// Check what parsing alone doesn't: every `[[trusted]]` key (top-level and per `[[fabric]]`) and
+  /// every `[[peer]]` `expected_key` must be a valid ed25519 key, and `[[fabric]]` names must be set
+  /// and distinct. `serve` refuses to reload a config that fails this.
+  pub fn validate(&self) -> DynResult<()> {
+    match self.problems().into_iter().next() {
+      Some(problem) => Err(problem.into()),
+      None => Ok(()),
+    }
+  }
+
+  /// Everything [`Config::validate`] rejects, all of it rather than the first.
+  pub fn problems(&self) -> Vec<String> {
+    let mut problems = Vec::new();
+    for trusted in self.trusted.iter() {
+      if let Err(e) = crypto_utils::public_key_to_ed25519_vk(&trusted.key) {
+        problems.push(format!("[[trusted]] key {:?}: {}", trusted.key, e));
+      }
+    }
+    for peer in self.peer.iter() {
+      if let Some(key) = peer.expected_key_str() && let Err(e) = crypto_utils::public_key_to_ed25519_vk(key) {
+        problems.push(format!("[[peer]] {} expected_key {:?}: {}", peer.label(), key, e));
+      }
+    }
+    let mut names = std::collections::HashSet::new();
+    for membership in self.fabric.iter() {
+      if membership.name.is_empty() {
+        problems.push("a [[fabric]] has no name".to_string());
+      }
+      else if !names.insert(membership.name.as_str()) {
+        problems.push(format!("[[fabric]] {:?} is listed twice", membership.name));
+      }
+      for trusted in membership.trusted.iter() {
+        if let Err(e) = crypto_utils::public_key_to_ed25519_vk(&trusted.key) {
+          problems.push(format!("[[fabric]] {:?} trusted key {:?}: {}", membership.name, trusted.key, e));
+        }
+      }
+    }
+    problems
+  }
+
+  /// Keys listed more than once where once is meant: the same key twice in `[[trusted]]` (or in one
+  /// `[[fabric]]`'s trust set), or two `[[peer]]`s pinned to the same key. Keys are compared parsed, so
+  /// one written as hex and again as `ssh-ed25519 ...` still counts; unparseable keys are left to
+  /// [`Config::problems`].
+  pub fn duplicated_keys(&self) -> Vec<String> {
+    fn repeats<'a>(what: &str, keys: impl Iterator<Item = &'a str>, out: &mut Vec<String>) {
+      let mut seen = std::collections::HashSet::new();
+      for key in keys {
+        if let Ok(vk) = crypto_utils::public_key_to_ed25519_vk(key) && !seen.insert(vk.to_bytes()) {
+          out.push(format!("{what} lists the key {key:?} more than once"));
+        }
+      }
+    }
+    let mut out = Vec::new();
+    repeats("[[trusted]]", self.trusted.iter().map(|t| t.key.as_str()), &mut out);
+    for membership in self.fabric.iter() {
+      repeats(&format!("[[fabric]] {:?} trusted", membership.name), membership.trusted.iter().map(|t| t.key.as_str()), &mut out);
+    }
+    repeats("[[peer]] expected_key", self.peer.iter().filter_map(|p| p.expected_key_str()), &mut out);
+    out
+  }
+
+  /// What changed from `self` to `new`, one line per setting: `network.port: 2240 -> 2241`, or for
+  /// the lists (`[[trusted]]`, `[[peer]]`, ...) each entry added or removed. Empty if nothing did.
+  pub fn diff(&self, new: &Config) -> Vec<String> {
+    let (Ok(old), Ok(new)) = (serde_json::to_value(self), serde_json::to_value(new)) else { return Vec::new() };
+    let mut lines = Vec::new();
+    diff_values("", &old, &new, &mut lines);
+    lines
+  }
+
   pub async fn read_from_file(file: &std::path::Path) -> DynResult<Config> {
+    let loaded = LoadedConfig::load(file).await?;
+    for problem in loaded.problems.iter() {
+      tracing::warn!("{}", problem);
+    }
+    Ok( loaded.config )
+  }
+}
+
+/// One file that went into a config: the main file, an include or a [`ConfigOverride`], as written,
+/// and the config as it stood once that file was applied.
+#[derive(Debug, Clone)]
+pub struct ConfigLayer {
+  /// The file, or for an override where it came from (its [`ConfigOverride::origin`]).
+  pub path: std::path::PathBuf,
+  pub kind: LayerKind,
+  pub raw: serde_json::Value,
+  pub config: Config,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerKind {
+  Main,
+  Include,
+  Override,
+}
+
+/// Settings from `--set` flags, in the order given. Stored once at startup, like `GLOBAL_VERBOSITY`,
+/// so every config read applies them without plumbing `args::Args` down to it.
+pub static CLI_OVERRIDES: std::sync::OnceLock<Vec<ConfigOverride>> = std::sync::OnceLock::new();
+
+/// Environment variables starting with this set a config field: `WEVERYWHERE__NETWORK__PORT=4000`.
+pub const ENV_OVERRIDE_PREFIX: &str = "WEVERYWHERE__";
+
+/// One setting given outside the TOML files, by a `WEVERYWHERE__SECTION__FIELD` environment variable
+/// or a `--set section.field=value` flag. Overrides apply after the includes, through the same merge
+/// an include goes through, so a list setting such as `peer` adds entries rather than replacing them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigOverride {
+  /// Where it came from: the variable's name, or `--set <setting>`.
+  pub origin: String,
+  /// The dotted path of the setting, such as `identity.name`.
+  pub setting: String,
+  /// The value as written. It is read as TOML (`4000`, `true`, `["ff02::3"]`, `[{ ipv4 = "10.0.0.2" }]`)
+  /// unless the setting holds a string or it isn't valid TOML, in which case it is the string itself.
+  pub value: String,
+}
+
+impl std::str::FromStr for ConfigOverride {
+  type Err = String;
+
+  /// A `--set` flag's `SETTING=VALUE`.
+  fn from_str(s: &str) -> Result<ConfigOverride, String> {
+    let Some((setting, value)) = s.split_once('=') else {
+      return Err(format!("expected SETTING=VALUE, such as identity.name=foo, not {s:?}"));
+    };
+    let setting = setting.trim();
+    if setting.split('.').any(str::is_empty) {
+      return Err(format!("{setting:?} is not a setting; expected a dotted path such as network.port"));
+    }
+    Ok(ConfigOverride { origin: format!("--set {setting}"), setting: setting.to_string(), value: value.to_string() })
+  }
+}
+
+impl ConfigOverride {
+  /// The overrides among environment `vars`, ordered by name. `__` separates the path's parts, which
+  /// are lowercased: `WEVERYWHERE__NETWORK__MULTICAST_TTL` sets `network.multicast_ttl`.
+  pub fn from_env_vars(vars: impl IntoIterator<Item = (String, String)>) -> Vec<ConfigOverride> {
+    let mut overrides: Vec<ConfigOverride> = vars.into_iter()
+      .filter_map(|(name, value)| {
+        let setting = name.strip_prefix(ENV_OVERRIDE_PREFIX)?.split("__").map(str::to_ascii_lowercase).collect::<Vec<_>>().join(".");
+        Some(ConfigOverride { origin: name, setting, value })
+      })
+      .collect();
+    overrides.sort_by(|a, b| a.origin.cmp(&b.origin));
+    overrides
+  }
+
+  /// Every override in effect for this process: the environment's, then the `--set` flags, so a flag
+  /// wins over a variable.
+  pub fn active() -> Vec<ConfigOverride> {
+    let mut overrides = ConfigOverride::from_env_vars(std::env::vars());
+    overrides.extend(CLI_OVERRIDES.get().cloned().unwrap_or_default());
+    overrides
+  }
+
+  /// This override as the TOML document an include setting it would hold. The setting is looked up in
+  /// `config` first, so a misspelt one is an error rather than silently ignored.
+  fn to_toml(&self, config: &Config) -> DynResult<toml::Value> {
+    use serde_json::Value;
+    let parts: Vec<&str> = self.setting.split('.').collect();
+    if parts.iter().any(|p| p.is_empty()) {
+      return Err(format!("{:?} is not a setting", self.setting).into());
+    }
+    if parts[0] == "includes" {
+      return Err("includes can only be set in a config file".into());
+    }
+    let current = serde_json::to_value(config)?;
+    let mut at = Some(&current);
+    for (i, part) in parts.iter().enumerate() {
+      at = match at {
+        Some(Value::Object(fields)) => match fields.get(*part) {
+          Some(value) => Some(value),
+          None if i == 0 && ["trusted_remove", "peer_remove", "fabric_remove"].contains(part) => None,
+          None => return Err(format!("there is no setting {:?}", parts[..=i].join(".")).into()),
+        },
+        // An unset optional section; there's nothing to check the rest against.
+        Some(Value::Null) | None => None,
+        Some(_) => return Err(format!("{} is not a section", parts[..i].join(".")).into()),
+      };
+    }
+    let as_toml = || toml::from_str::<toml::Table>(&format!("value = {}", self.value)).ok().and_then(|mut t| t.remove("value"));
+    let mut doc = match at {
+      Some(Value::String(_)) => None,
+      _ => as_toml(),
+    }.unwrap_or_else(|| toml::Value::String(self.value.clone()));
+    for part in parts.iter().rev() {
+      doc = toml::Value::Table(toml::Table::from_iter([(part.to_string(), doc)]));
+    }
+    Ok(doc)
+  }
+}
+
+/// A config as [`Config::read_from_file`] loads it, keeping what that only logs: every file and
+/// override applied, in order, and what went wrong applying the includes.
+#[derive(Debug, Clone)]
+pub struct LoadedConfig {
+  pub config: Config,
+  /// The main file first, then each include file that applied, then each override.
+  pub layers: Vec<ConfigLayer>,
+  /// Include files that couldn't be read or parsed, and globs that were invalid or failed to walk.
+  pub problems: Vec<String>,
+  /// `[[includes]]` paths that matched no file.
+  pub unmatched_includes: Vec<String>,
+}
+
+impl LoadedConfig {
+  /// Read `file` and apply its includes over it. An include's own `[[includes]]` are applied right
+  /// after it, before the next file of the include that named it; the files a glob matches are taken
+  /// in lexical order. A file already applied is skipped, and a file that would include itself, or
+  /// one of the files that led to it, is reported as a cycle. Only the main file failing to read or
+  /// parse is an error; trouble with an include is recorded and the include skipped. The
+  /// [`ConfigOverride::active`] overrides apply last.
+  pub async fn load(file: &std::path::Path) -> DynResult<LoadedConfig> {
+    LoadedConfig::load_with_overrides(file, &ConfigOverride::active()).await
+  }
+
+  /// [`LoadedConfig::load`], applying `overrides` after the includes in place of the process's own.
+  /// An override naming no setting, or whose value doesn't fit it, is an error.
+  pub async fn load_with_overrides(file: &std::path::Path, overrides: &[ConfigOverride]) -> DynResult<LoadedConfig> {
     let contents = tokio::fs::read_to_string(file).await?;
     let mut config: Config = toml::from_str(&contents)?;
-    // Process all included files, applying them over the original file's data
-    for include_struct in config.includes.clone().iter() {
-      match glob::glob(&include_struct.path) {
-        Ok(paths) => {
-          for entry in paths {
-            match entry {
-              Ok(path) => {
-                match process_config_override_file(&config, &path).await {
-                  Ok(new_config) => {
-                    config = new_config;
-                  }
-                  Err(e) => {
-                    tracing::warn!("Error applying override file {:?} - {}", &path, e);
-                  }
-                }
-              }
-              Err(ref e) => tracing::warn!("Glob error when processing {:?} - {}", entry, e),
+    let raw: toml::Value = toml::from_str(&contents)?;
+    let mut loaded = LoadedConfig { config: config.clone(), layers: Vec::new(), problems: Vec::new(), unmatched_includes: Vec::new() };
+    // Removals in the main file have nothing loaded before them to remove.
+    let removals = Removals::take(&mut config);
+    let mut nothing = Config { trusted: Vec::new(), peer: Vec::new(), fabric: Vec::new(), ..config.clone() };
+    loaded.problems.extend(removals.apply(&mut nothing, file));
+    loaded.layers.push(ConfigLayer { path: file.to_path_buf(), kind: LayerKind::Main, raw: serde_json::to_value(raw)?, config: config.clone() });
+
+    let main = std::fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+    let mut applied = std::collections::HashSet::from([main.clone()]);
+    // Files still to apply, the next one last, each with the chain of files that included it.
+    let mut pending: Vec<(std::path::PathBuf, Vec<std::path::PathBuf>)> = Vec::new();
+    loaded.push_includes(&config.includes, vec![main], &mut pending);
+    while let Some((path, chain)) = pending.pop() {
+      let canonical = std::fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+      if chain.contains(&canonical) {
+        let cycle: Vec<String> = chain.iter().chain(std::iter::once(&canonical)).map(|p| p.display().to_string()).collect();
+        loaded.problems.push(format!("Include cycle skipped: {}", cycle.join(" -> ")));
+        continue;
+      }
+      if !applied.insert(canonical.clone()) {
+        continue;
+      }
+      match process_config_override_file(&config, &path).await {
+        Ok(applied) => {
+          config = applied.config;
+          loaded.problems.extend(applied.problems);
+          loaded.layers.push(ConfigLayer { path, kind: LayerKind::Include, raw: applied.raw, config: config.clone() });
+          let mut chain = chain;
+          chain.push(canonical);
+          loaded.push_includes(&applied.includes, chain, &mut pending);
+        }
+        Err(e) => {
+          loaded.problems.push(format!("Error applying override file {:?} - {}", &path, e));
+        }
+      }
+    }
+
+    for o in overrides.iter() {
+      let origin = std::path::PathBuf::from(&o.origin);
+      let applied = o.to_toml(&config)
+        .and_then(|raw| apply_config_override(&config, raw, &origin))
+        .map_err(|e| format!("{}: {}", o.origin, e))?;
+      config = applied.config;
+      loaded.problems.extend(applied.problems);
+      loaded.layers.push(ConfigLayer { path: origin, kind: LayerKind::Override, raw: applied.raw, config: config.clone() });
+    }
+    loaded.config = config;
+    Ok( loaded )
+  }
+
+  /// Queue the files `includes` match so they are applied next, in order, each recorded as included
+  /// through `chain`.
+  fn push_includes(&mut self, includes: &[SingleInclude], chain: Vec<std::path::PathBuf>, pending: &mut Vec<(std::path::PathBuf, Vec<std::path::PathBuf>)>) {
+    let mut files = Vec::new();
+    for include_struct in includes.iter() {
+      match glob_sorted(&include_struct.path) {
+        Ok((paths, errors)) => {
+          if paths.is_empty() && errors.is_empty() {
+            self.unmatched_includes.push(include_struct.path.clone());
+          }
+          for e in errors {
+            self.problems.push(format!("Glob error when processing {:?} - {}", include_struct.path, e));
+          }
+          files.extend(paths);
+        }
+        Err(e) => self.problems.push(format!("Invalid glob pattern {:?} - {}", include_struct.path, e)),
+      }
+    }
+    pending.extend(files.into_iter().rev().map(|path| (path, chain.clone())));
+  }
+
+  /// Everything `weverywhere config check` fails on: the include trouble loading skips over, what
+  /// [`Config::problems`] and [`Config::duplicated_keys`] find, an identity keyfile that can't be
+  /// read or that others can read, `[[peer]]`s none of whose addresses resolve, and limits a file
+  /// sets to zero (which are ignored, leaving the built-in limit).
+  pub async fn check(&self) -> Vec<String> {
+    let config = &self.config;
+    let mut problems = self.problems.clone();
+    for pattern in self.unmatched_includes.iter() {
+      problems.push(format!("[[includes]] path {pattern:?} matches no files"));
+    }
+    problems.extend(config.problems());
+    problems.extend(config.duplicated_keys());
+    if let Err(e) = config.identity.read_private_key_ed25519_pem_file().await {
+      problems.push(format!("[identity] keyfile {:?} can't be read: {}", config.identity.keyfile, e));
+    }
+    if let Some(problem) = crypto_utils::keyfile_permission_problem(&config.identity.keyfile) {
+      problems.push(format!("[identity] keyfile {problem}"));
+    }
+    for peer in config.peer.iter() {
+      if net_utils::resolve_peer_addr(peer, config.network.port).await.is_none() {
+        problems.push(format!("[[peer]] {} doesn't resolve to an address", peer.label()));
+      }
+    }
+    for (path, value, source) in self.sources() {
+      if path.starts_with("limits.") && value == 0 && let Some(file) = source {
+        problems.push(format!("{path} is 0 in {file:?}, so the built-in limit applies"));
+      }
+    }
+    problems
+  }
+
+  /// Every effective setting as a dotted path (`network.port`, `peer[1].hostname`) with its value and
+  /// the file that set it last (an override's [`ConfigOverride::origin`] standing in for a file), or
+  /// None where nothing did and the default stands. Unset optional
+  /// settings are left out. An include's `[[peer]]` and other list entries are appended after those
+  /// already loaded (and not removed), so each entry is credited to the file it came from.
+  pub fn sources(&self) -> Vec<(String, serde_json::Value, Option<&std::path::Path>)> {
+    use serde_json::Value;
+    let is_list = |v: &Value| v.as_array().is_some_and(|items| items.iter().any(|v| v.is_object()));
+    // Per file: the settings it wrote, and the list entries it added as they were once it applied.
+    let mut wrote: Vec<(&std::path::Path, std::collections::HashSet<String>, Value)> = Vec::new();
+    for layer in self.layers.iter() {
+      let after = serde_json::to_value(&layer.config).unwrap_or_default();
+      let mut paths = std::collections::HashSet::new();
+      let mut added = serde_json::Map::new();
+      for (key, value) in layer.raw.as_object().into_iter().flatten() {
+        if is_list(value) {
+          // Its entries are the last ones once it has been applied.
+          let all = after.get(key).and_then(|v| v.as_array()).cloned().unwrap_or_default();
+          let count = value.as_array().map_or(0, |v| v.len());
+          added.insert(key.clone(), Value::Array(all[all.len().saturating_sub(count)..].to_vec()));
+        }
+        else {
+          flatten_value(key, value, &mut |path, _| { paths.insert(path); });
+        }
+      }
+      wrote.push((layer.path.as_path(), paths, Value::Object(added)));
+    }
+
+    let mut out: Vec<(String, Value, Option<&std::path::Path>)> = Vec::new();
+    let effective = serde_json::to_value(&self.config).unwrap_or_default();
+    for (key, value) in effective.as_object().into_iter().flatten() {
+      let mut emit = |path: String, value: &Value, source| {
+        if !value.is_null() {
+          out.push((path, value.clone(), source));
+        }
+      };
+      if is_list(value) {
+        // Removals shift entries, so an entry is credited by what it is rather than where it is: the
+        // last entry to the last file that added one like it, and so on back.
+        let entries = value.as_array().cloned().unwrap_or_default();
+        let mut sources = vec![None; entries.len()];
+        for (i, entry) in entries.iter().enumerate().rev() {
+          for (file, _, added) in wrote.iter_mut().rev() {
+            let added = added.get_mut(key).and_then(|v| v.as_array_mut());
+            if let Some(added) = added && let Some(at) = added.iter().position(|a| a == entry) {
+              added.remove(at);
+              sources[i] = Some(*file);
+              break;
             }
           }
         }
-        Err(e) => tracing::warn!("Invalid glob pattern while parsing {:?} - {}", file, e),
+        for (i, (entry, source)) in entries.iter().zip(sources).enumerate() {
+          flatten_value(&format!("{key}[{i}]"), entry, &mut |path, value| emit(path, value, source));
+        }
+      }
+      else {
+        flatten_value(key, value, &mut |path, value| {
+          let source = wrote.iter().rev()
+            .find(|(_, paths, _)| setting_ancestors(&path).any(|p| paths.contains(p)))
+            .map(|(file, _, _)| *file);
+          emit(path, value, source);
+        });
       }
     }
-    Ok( config )
+    out
   }
 }
 
-async fn process_config_override_file(config: &Config, override_file_path: &std::path::Path) -> DynResult<Config> {
+/// Call `f` with each leaf under `value` and its dotted path below `prefix`. Lists of tables descend
+/// per entry (`peer[0].hostname`); any other list is one leaf.
+fn flatten_value(prefix: &str, value: &serde_json::Value, f: &mut dyn FnMut(String, &serde_json::Value)) {
+  use serde_json::Value;
+  match value {
+    Value::Object(map) if !map.is_empty() => {
+      for (key, v) in map.iter() {
+        let path = if prefix.is_empty() { key.clone() } else { format!("{prefix}.{key}") };
+        flatten_value(&path, v, f);
+      }
+    }
+    Value::Array(items) if items.iter().any(|v| v.is_object()) => {
+      for (i, v) in items.iter().enumerate() {
+        flatten_value(&format!("{prefix}[{i}]"), v, f);
+      }
+    }
+    _ => f(prefix.to_string(), value),
+  }
+}
+
+/// `path` and each setting containing it: `peer[1].expected_key.key`, `peer[1].expected_key`, `peer[1]`, `peer`.
+fn setting_ancestors(path: &str) -> impl Iterator<Item = &str> {
+  std::iter::once(path).chain(path.char_indices().rev().filter(|(_, c)| *c == '.' || *c == '[').map(move |(i, _)| &path[..i]))
+}
+
+/// The files `includes` globs currently resolve to, each glob's matches in lexical order. Includes
+/// named inside included files are part of a loaded config's `includes` too, so this covers them.
+pub fn resolve_includes(includes: &[SingleInclude]) -> Vec<std::path::PathBuf> {
+  includes.iter()
+    .filter_map(|include| glob_sorted(&include.path).ok())
+    .flat_map(|(paths, _)| paths)
+    .collect()
+}
+
+/// The files `pattern` matches, in lexical order, and any errors walking to them.
+fn glob_sorted(pattern: &str) -> Result<(Vec<std::path::PathBuf>, Vec<glob::GlobError>), glob::PatternError> {
+  let (mut paths, mut errors) = (Vec::new(), Vec::new());
+  for entry in glob::glob(pattern)? {
+    match entry {
+      Ok(path) => paths.push(path),
+      Err(e) => errors.push(e),
+    }
+  }
+  paths.sort();
+  Ok((paths, errors))
+}
+
+/// One file's `[[trusted_remove]]`, `[[peer_remove]]` and `[[fabric_remove]]` entries.
+struct Removals {
+  trusted: Vec<SingleTrustedKey>,
+  peer: Vec<PeerRemoval>,
+  fabric: Vec<FabricRemoval>,
+}
+
+impl Removals {
+  fn take(config: &mut Config) -> Removals {
+    Removals {
+      trusted: std::mem::take(&mut config.trusted_remove),
+      peer: std::mem::take(&mut config.peer_remove),
+      fabric: std::mem::take(&mut config.fabric_remove),
+    }
+  }
+
+  /// Remove these entries from `config`, as listed in `file`. Returns a problem for each entry that is
+  /// malformed or removed nothing, since that is usually a typo in a revocation.
+  fn apply(self, config: &mut Config, file: &std::path::Path) -> Vec<String> {
+    let mut problems = Vec::new();
+    for removal in self.trusted {
+      let Ok(vk) = crypto_utils::public_key_to_ed25519_vk(&removal.key) else {
+        problems.push(format!("{}: [[trusted_remove]] key {:?} can't be parsed", file.display(), removal.key));
+        continue;
+      };
+      let is_other = |t: &SingleTrustedKey| crypto_utils::public_key_to_ed25519_vk(&t.key).map_or(true, |k| k != vk);
+      let before = config.trusted.len() + config.fabric.iter().map(|m| m.trusted.len()).sum::<usize>();
+      config.trusted.retain(is_other);
+      for membership in config.fabric.iter_mut() {
+        membership.trusted.retain(is_other);
+      }
+      if before == config.trusted.len() + config.fabric.iter().map(|m| m.trusted.len()).sum::<usize>() {
+        problems.push(format!("{}: [[trusted_remove]] key {:?} matches no key loaded before it", file.display(), removal.key));
+      }
+    }
+    for removal in self.peer {
+      let key = removal.key.as_deref().map(crypto_utils::public_key_to_ed25519_vk);
+      if removal.host.is_none() && key.is_none() {
+        problems.push(format!("{}: a [[peer_remove]] sets neither `host` nor `key`", file.display()));
+        continue;
+      }
+      if let Some(Err(e)) = key {
+        problems.push(format!("{}: [[peer_remove]] key {:?}: {}", file.display(), removal.key.unwrap_or_default(), e));
+        continue;
+      }
+      let key = key.and_then(Result::ok);
+      let before = config.peer.len();
+      config.peer.retain(|peer| {
+        let host_matches = removal.host.as_ref().is_none_or(|host| peer.connect_hosts().contains(host));
+        let key_matches = key.is_none_or(|key| peer.expected_key_str().and_then(|k| crypto_utils::public_key_to_ed25519_vk(k).ok()) == Some(key));
+        !(host_matches && key_matches)
+      });
+      if before == config.peer.len() {
+        let fields: Vec<String> = [removal.host.as_ref().map(|h| format!("host {h:?}")), removal.key.as_ref().map(|k| format!("key {k:?}"))].into_iter().flatten().collect();
+        problems.push(format!("{}: [[peer_remove]] {} matches no [[peer]] loaded before it", file.display(), fields.join(", ")));
+      }
+    }
+    for removal in self.fabric {
+      let before = config.fabric.len();
+      config.fabric.retain(|m| m.name != removal.name);
+      if before == config.fabric.len() {
+        problems.push(format!("{}: [[fabric_remove]] {:?} matches no [[fabric]] loaded before it", file.display(), removal.name));
+      }
+    }
+    problems
+  }
+}
+
+fn diff_values(path: &str, old: &serde_json::Value, new: &serde_json::Value, lines: &mut Vec<String>) {
+  use serde_json::Value;
+  let child = |key: &str| if path.is_empty() { key.to_string() } else { format!("{path}.{key}") };
+  match (old, new) {
+    (Value::Object(o), Value::Object(n)) => {
+      for (key, ov) in o.iter() {
+        diff_values(&child(key), ov, n.get(key).unwrap_or(&Value::Null), lines);
+      }
+      for (key, nv) in n.iter().filter(|(key, _)| !o.contains_key(*key)) {
+        diff_values(&child(key), &Value::Null, nv, lines);
+      }
+    }
+    (Value::Array(o), Value::Array(n)) if o.iter().chain(n.iter()).any(|v| v.is_object()) => {
+      for removed in o.iter().filter(|v| !n.contains(v)) {
+        lines.push(format!("{path}: removed {removed}"));
+      }
+      for added in n.iter().filter(|v| !o.contains(v)) {
+        lines.push(format!("{path}: added {added}"));
+      }
+    }
+    (o, n) if o != n => lines.push(format!("{path}: {o} -> {n}")),
+    _ => {}
+  }
+}
+
+/// One include file applied over a config.
+struct AppliedInclude {
+  config: Config,
+  /// The file as written.
+  raw: serde_json::Value,
+  /// The file's own `[[includes]]`, to apply next.
+  includes: Vec<SingleInclude>,
+  /// Its removals that were malformed or removed nothing.
+  problems: Vec<String>,
+}
+
+/// `config` with the file at `override_file_path` applied over it; see [`apply_config_override`].
+async fn process_config_override_file(config: &Config, override_file_path: &std::path::Path) -> DynResult<AppliedInclude> {
   let contents = tokio::fs::read_to_string(override_file_path).await?;
+  apply_config_override(config, toml::from_str(&contents)?, override_file_path)
+}
 
-  let mut override_data: ConfigOpt = toml::from_str(&contents)?;
-  override_data.includes = None; // I don't care, we're not recursively including other things -_-
+/// `config` with `raw`, an include's contents from `override_file_path`, applied over it: first its
+/// `*_remove` entries take out what they match, then what it sets replaces or (for lists) adds to what
+/// `config` has.
+fn apply_config_override(config: &Config, raw: toml::Value, override_file_path: &std::path::Path) -> DynResult<AppliedInclude> {
+  let override_data: ConfigOpt = raw.clone().try_into()?;
+  let includes = override_data.includes.clone().unwrap_or_default().into_iter()
+    .map(SingleInclude::try_from_optioned)
+    .collect::<Result<Vec<_>, _>>()?;
+
+  let mut config = config.clone();
+  let removals = Removals {
+    trusted: override_data.trusted_remove.clone().unwrap_or_default().into_iter().map(SingleTrustedKey::try_from_optioned).collect::<Result<_, _>>()?,
+    peer: override_data.peer_remove.clone().unwrap_or_default().into_iter().map(PeerRemoval::try_from_optioned).collect::<Result<_, _>>()?,
+    fabric: override_data.fabric_remove.clone().unwrap_or_default().into_iter().map(FabricRemoval::try_from_optioned).collect::<Result<_, _>>()?,
+  };
+  let problems = removals.apply(&mut config, override_file_path);
 
-  let config_o: ConfigOpt = config.clone().into_optioned();
+  let config_o: ConfigOpt = config.into_optioned();
 
   // This does not work - omerge does not descend to children, so we will need to do all minus the lowest level outselves. Ugh -_-
   // I had hoped to avoid this via the set of optionable::Optionable derives upstairs
@@ -481,12 +1375,19 @@ async fn process_config_override_file(config: &Config, override_file_path: &std:
       trusted: Some(fancy_omerge(config_o.limits.clone().unwrap_or_else(|| Default::default()).trusted, override_data.limits.clone().unwrap_or_else(|| Default::default()).trusted)?.unwrap_or_else(|| Default::default())),
       untrusted: Some(fancy_omerge(config_o.limits.clone().unwrap_or_else(|| Default::default()).untrusted, override_data.limits.clone().unwrap_or_else(|| Default::default()).untrusted)?.unwrap_or_else(|| Default::default())),
     }),
+    state: fancy_omerge(config_o.state, override_data.state)?,
+    network: fancy_omerge(config_o.network, override_data.network)?,
+    fabric: fancy_omerge_vec(config_o.fabric, override_data.fabric)?,
+    // Consumed above, so they don't carry over into the next file.
+    trusted_remove: Some(Vec::new()),
+    peer_remove: Some(Vec::new()),
+    fabric_remove: Some(Vec::new()),
 
     // TODO other top-level fields here
   };
 
   // We know this is safe, as the original Config had all values and serde_merge::omerge promises not to overwrite None values.
-  Ok( Config::try_from_optioned(joined_o)? )
+  Ok( AppliedInclude { config: Config::try_from_optioned(joined_o)?, raw: serde_json::to_value(raw)?, includes, problems } )
 }
 
 fn fancy_omerge<T>(f1: Option<T>, f2: Option<T>) -> DynResult<Option<T>>
@@ -531,10 +1432,11 @@ where T: serde::Serialize + serde::de::DeserializeOwned
 }
 
 impl IdentityConfig {
+  /// The identity key, unlocking an encrypted keyfile with its passphrase (see [`crate::keyfile`]).
   pub async fn read_private_key_ed25519_pem_file(&self) -> DynResult<ed25519_dalek::SigningKey> {
-    crypto_utils::read_private_key_ed25519_pem_file(&self.keyfile).await
+    Ok(keyfile::unlock(self).await?.key)
   }
   pub async fn read_public_key_ed25519_pem_file(&self) -> DynResult<ed25519_dalek::VerifyingKey> {
-    crypto_utils::read_public_key_ed25519_pem_file(&self.keyfile).await
+    Ok(self.read_private_key_ed25519_pem_file().await?.verifying_key())
   }
 }
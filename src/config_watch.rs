@@ -0,0 +1,89 @@
+//! Noticing when `serve` should re-read its config: a change to the config file or to any file its
+//! `[[includes]]` globs resolve to (including a file newly dropped into an include directory), or a
+//! SIGHUP. Files are polled every [`POLL_INTERVAL`] by modification time and size, which works the
+//! same everywhere, network filesystems included.
+
+use crate::*;
+
+/// How often the watched files are checked.
+pub const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Each watched file with its modification time and size; None for a file that doesn't exist.
+type Fingerprint = Vec<(std::path::PathBuf, Option<(std::time::SystemTime, u64)>)>;
+
+/// Watches one config file and its includes.
+pub struct ConfigWatcher {
+  path: std::path::PathBuf,
+  /// The `[[includes]]` of the last config that loaded; a broken edit keeps watching these.
+  includes: Vec<config::SingleInclude>,
+  last: Fingerprint,
+  #[cfg(unix)]
+  hangup: Option<tokio::signal::unix::Signal>,
+}
+
+impl ConfigWatcher {
+  /// Watch `path`, which currently reads as `config`.
+  pub async fn new(path: &std::path::Path, config: &config::Config) -> ConfigWatcher {
+    let mut watcher = ConfigWatcher {
+      path: path.to_path_buf(),
+      includes: config.includes.clone(),
+      last: Vec::new(),
+      #[cfg(unix)]
+      hangup: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        .inspect_err(|e| tracing::warn!("[ reload ] can't listen for SIGHUP ({e}); watching the config files only"))
+        .ok(),
+    };
+    watcher.last = watcher.fingerprint().await;
+    watcher
+  }
+
+  /// Follow the `[[includes]]` of `config`, a newly loaded version of the file.
+  pub fn set_config(&mut self, config: &config::Config) {
+    self.includes = config.includes.clone();
+  }
+
+  /// True if a watched file has changed, appeared or disappeared since the last check.
+  pub async fn poll(&mut self) -> bool {
+    let now = self.fingerprint().await;
+    now != std::mem::replace(&mut self.last, now.clone())
+  }
+
+  /// Wait for the next reason to reload, and return it for the log.
+  pub async fn changed(&mut self) -> &'static str {
+    let mut tick = tokio::time::interval(POLL_INTERVAL);
+    tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+      tokio::select! {
+        _ = self.hangup() => {
+          self.last = self.fingerprint().await;
+          return "SIGHUP";
+        }
+        _ = tick.tick() => {}
+      }
+      if self.poll().await {
+        return "a config file changed";
+      }
+    }
+  }
+
+  /// Resolves on the next SIGHUP; never, where there are no signals to listen for.
+  async fn hangup(&mut self) {
+    #[cfg(unix)]
+    if let Some(hangup) = self.hangup.as_mut() {
+      hangup.recv().await;
+      return;
+    }
+    std::future::pending::<()>().await
+  }
+
+  async fn fingerprint(&self) -> Fingerprint {
+    let mut files = vec![self.path.clone()];
+    files.extend(config::resolve_includes(&self.includes));
+    let mut out = Vec::with_capacity(files.len());
+    for file in files {
+      let stamp = tokio::fs::metadata(&file).await.ok().and_then(|m| Some((m.modified().ok()?, m.len())));
+      out.push((file, stamp));
+    }
+    out
+  }
+}
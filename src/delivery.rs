@@ -0,0 +1,178 @@
+//! Acknowledged delivery for the signed fabric messages a node sends with
+//! `host::messages_send_reliable` and `host::messages_send_to_reliable`.
+//!
+//! Multicast loses datagrams, and a lost chat line is simply gone, so a reliable send is remembered
+//! here along with the peers expected to hear it: those heard from within [`PEER_WINDOW_S`], or for
+//! a direct message just its recipient. The message is signed as wanting acks, and every node that
+//! accepts it answers each copy with a signed [`messages::NetworkMessage::MessageAck`]. Until every
+//! expected peer has acked, the sender retransmits the same signed bytes with exponential backoff,
+//! and gives up at [`DEADLINE`].
+//! Retransmits are harmless because receivers dedup on (sender pubkey, id) (see
+//! [`executor::MessageStore`]).
+//!
+//! Times are passed in rather than read, so the policy can be tested without waiting on it.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::*;
+
+/// The first retransmit comes this long after the send. Each later one waits twice as long as the
+/// one before, up to [`MAX_RETRY`].
+pub const FIRST_RETRY: std::time::Duration = std::time::Duration::from_millis(500);
+pub const MAX_RETRY: std::time::Duration = std::time::Duration::from_secs(4);
+
+/// Stop retransmitting a message this long after it was sent. It then counts as expired unless
+/// everyone expected has acked it.
+pub const DEADLINE: std::time::Duration = std::time::Duration::from_secs(20);
+
+/// A send expects acks from the peers heard from within this many seconds of it.
+pub const PEER_WINDOW_S: u64 = 15 * 60;
+
+/// How often the senders check for reliable sends due a retransmit.
+pub const RETRANSMIT_TICK: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// How many sends are remembered for `host::messages_status`; the oldest are forgotten first.
+pub const MAX_TRACKED: usize = 1024;
+
+/// Where a reliable send stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryState {
+  /// Still waiting on acks, and still retransmitting.
+  Pending,
+  /// Every expected peer has acked. With no peers expected, at least one node has acked.
+  Delivered,
+  /// The deadline passed first.
+  Expired,
+}
+
+impl DeliveryState {
+  /// The code `host::messages_status` returns for this state.
+  pub fn code(self) -> i32 {
+    match self {
+      DeliveryState::Pending => 0,
+      DeliveryState::Delivered => 1,
+      DeliveryState::Expired => 2,
+    }
+  }
+}
+
+/// A reliable send's state, and how many of the peers expected to ack it have done so.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeliveryStatus {
+  pub state: DeliveryState,
+  /// Every identity that acked, including any we didn't expect.
+  pub acked: usize,
+  pub expected: usize,
+}
+
+#[derive(Debug)]
+struct Delivery {
+  /// The signed message as first sent; every retransmit sends exactly these bytes.
+  bytes: Vec<u8>,
+  expected: HashSet<Vec<u8>>,
+  acked: HashSet<Vec<u8>>,
+  deadline: std::time::Instant,
+  next_retry: std::time::Instant,
+  backoff: std::time::Duration,
+}
+
+impl Delivery {
+  fn state(&self, now: std::time::Instant) -> DeliveryState {
+    let all_acked = if self.expected.is_empty() { !self.acked.is_empty() } else { self.expected.is_subset(&self.acked) };
+    if all_acked {
+      DeliveryState::Delivered
+    } else if now >= self.deadline {
+      DeliveryState::Expired
+    } else {
+      DeliveryState::Pending
+    }
+  }
+}
+
+/// The reliable sends a node has made and the acks they've collected. Lives on the [`executor::Executor`],
+/// shared like its message store, so a program's `host::messages_*` calls and the serve loop see the
+/// same state.
+#[derive(Debug, Default)]
+pub struct DeliveryTracker {
+  deliveries: HashMap<Vec<u8>, Delivery>,
+  /// Tracked message ids, oldest first, for evicting past [`MAX_TRACKED`].
+  order: VecDeque<Vec<u8>>,
+  /// Peers' identity pubkeys -> when we last heard from them.
+  peers: HashMap<Vec<u8>, u64>,
+  /// Our own pubkey, which never has to ack our messages.
+  our_pubkey: Vec<u8>,
+}
+
+impl DeliveryTracker {
+  pub fn new(our_pubkey: &[u8]) -> DeliveryTracker {
+    DeliveryTracker { our_pubkey: our_pubkey.to_vec(), ..Default::default() }
+  }
+
+  /// We heard from the identity `pubkey` at `epoch_s`.
+  pub fn note_peer(&mut self, pubkey: &[u8], epoch_s: u64) {
+    if pubkey != self.our_pubkey.as_slice() {
+      let seen = self.peers.entry(pubkey.to_vec()).or_default();
+      *seen = (*seen).max(epoch_s);
+    }
+  }
+
+  /// Start tracking the message `id`, just sent as `bytes`. It expects acks from every peer heard
+  /// from within [`PEER_WINDOW_S`] of `now_epoch_s`. Returns how many peers that is.
+  pub fn track(&mut self, id: &[u8], bytes: Vec<u8>, now: std::time::Instant, now_epoch_s: u64) -> usize {
+    let expected: HashSet<Vec<u8>> = self.peers.iter()
+      .filter(|(_, seen)| now_epoch_s.saturating_sub(**seen) <= PEER_WINDOW_S)
+      .map(|(pubkey, _)| pubkey.clone())
+      .collect();
+    self.insert(id, bytes, expected, now)
+  }
+
+  /// Start tracking the direct message `id` to the identity `to`, just sent as `bytes`: only `to` is
+  /// expected to ack it, heard from lately or not. Returns 1.
+  pub fn track_to(&mut self, id: &[u8], bytes: Vec<u8>, to: &[u8], now: std::time::Instant) -> usize {
+    self.insert(id, bytes, HashSet::from([to.to_vec()]), now)
+  }
+
+  fn insert(&mut self, id: &[u8], bytes: Vec<u8>, expected: HashSet<Vec<u8>>, now: std::time::Instant) -> usize {
+    let count = expected.len();
+    let delivery = Delivery {
+      bytes, expected, acked: HashSet::new(),
+      deadline: now + DEADLINE, next_retry: now + FIRST_RETRY, backoff: FIRST_RETRY,
+    };
+    if self.deliveries.insert(id.to_vec(), delivery).is_none() {
+      self.order.push_back(id.to_vec());
+    }
+    while self.order.len() > MAX_TRACKED {
+      if let Some(old) = self.order.pop_front() { self.deliveries.remove(&old); }
+    }
+    count
+  }
+
+  /// The identity `from` acked the message `id`. Returns true if that was news for a message we're
+  /// tracking.
+  pub fn ack(&mut self, id: &[u8], from: &[u8]) -> bool {
+    match self.deliveries.get_mut(id) {
+      Some(d) if from != self.our_pubkey.as_slice() => d.acked.insert(from.to_vec()),
+      _ => false,
+    }
+  }
+
+  /// Where the message `id` stands; None if it isn't tracked (never sent reliably, or long forgotten).
+  pub fn status(&self, id: &[u8], now: std::time::Instant) -> Option<DeliveryStatus> {
+    self.deliveries.get(id).map(|d| DeliveryStatus { state: d.state(now), acked: d.acked.len(), expected: d.expected.len() })
+  }
+
+  /// The messages due a retransmit at `now`. Each one's next retransmit is scheduled after its
+  /// backoff, which then doubles.
+  pub fn due(&mut self, now: std::time::Instant) -> Vec<Vec<u8>> {
+    let mut out = Vec::new();
+    for d in self.deliveries.values_mut() {
+      if d.next_retry > now || d.state(now) != DeliveryState::Pending {
+        continue;
+      }
+      out.push(d.bytes.clone());
+      d.backoff = (d.backoff * 2).min(MAX_RETRY);
+      d.next_retry = now + d.backoff;
+    }
+    out
+  }
+}
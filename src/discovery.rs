@@ -52,6 +52,128 @@ fn trust_cap(trusted: bool) -> u8 {
 }
 
 
+// ============================================================================================
+// Collection window: how long the origin listens for records, and how far each hop may relay.
+//
+// The origin stops at whichever comes first: its hard `timeout`, `idle` without a new record, or a
+// short settle once every node it expected (its known peers) has answered - so a small LAN is done
+// in well under a second while a deep tree keeps the window open as long as records keep arriving.
+// The origin also sends how long it will listen as `ProgramData::time_budget_ms`; each relay hands
+// its children what is left minus [`HOP_DEADLINE_MARGIN`], and stops forwarding and relaying once
+// its share has run out, since nobody upstream is listening any more. The budget is relative (ms
+// from receipt), so it doesn't depend on the nodes' clocks agreeing.
+// ============================================================================================
+
+/// Default hard cap on one collection (`netmap --timeout`).
+pub const DEFAULT_COLLECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Default quiet period after the latest record (`netmap --idle`). Long enough for a relay to
+/// compile and run the program one hop further down.
+pub const DEFAULT_COLLECT_IDLE: std::time::Duration = std::time::Duration::from_millis(1500);
+
+/// How long to wait for the very first record: a cold daemon compiles the program before it answers.
+pub const FIRST_RECORD_WAIT: std::time::Duration = std::time::Duration::from_secs(4);
+
+/// How long stragglers (nodes we didn't know about, deeper hops) get once every expected node has
+/// answered.
+pub const EXPECTED_SETTLE: std::time::Duration = std::time::Duration::from_millis(300);
+
+/// What a relay keeps back from its own deadline when handing its children theirs, so their records
+/// still have time to travel back up through it.
+pub const HOP_DEADLINE_MARGIN: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// When a discovery client stops listening for records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollectPolicy {
+  /// Hard cap on the whole collection; also sent on as the request's time budget.
+  pub timeout: std::time::Duration,
+  /// Stop once no record has arrived for this long (after the first one).
+  pub idle: std::time::Duration,
+  /// Keys of the nodes we expect to answer; once all of them have, only [`EXPECTED_SETTLE`] more.
+  pub expected_keys: std::collections::HashSet<Vec<u8>>,
+  /// Alternatively (or as well), a number of distinct nodes that must answer first.
+  pub expected_count: Option<usize>,
+}
+
+impl Default for CollectPolicy {
+  fn default() -> CollectPolicy {
+    CollectPolicy {
+      timeout: DEFAULT_COLLECT_TIMEOUT,
+      idle: DEFAULT_COLLECT_IDLE,
+      expected_keys: std::collections::HashSet::new(),
+      expected_count: None,
+    }
+  }
+}
+
+impl CollectPolicy {
+  /// Listen for exactly `window`, however quiet it gets.
+  pub fn fixed(window: std::time::Duration) -> CollectPolicy {
+    CollectPolicy { timeout: window, idle: window, ..Default::default() }
+  }
+
+  /// The budget to send with the request: the timeout in ms, at least 1 (0 means "no deadline").
+  pub fn time_budget_ms(&self) -> u32 {
+    u32::try_from(self.timeout.as_millis()).unwrap_or(u32::MAX).max(1)
+  }
+}
+
+/// One collection in progress: feed it every record as it arrives, and stop at [`Self::deadline`].
+#[derive(Debug, Clone)]
+pub struct Collection {
+  policy: CollectPolicy,
+  start: std::time::Instant,
+  last_record: Option<std::time::Instant>,
+  answered: std::collections::HashSet<Vec<u8>>,
+}
+
+impl Collection {
+  pub fn new(policy: CollectPolicy, start: std::time::Instant) -> Collection {
+    Collection { policy, start, last_record: None, answered: std::collections::HashSet::new() }
+  }
+
+  /// Note a record arriving at `now`; `pubkey` is its node's verified key, if it had one.
+  pub fn note_record(&mut self, pubkey: Option<&[u8]>, now: std::time::Instant) {
+    self.last_record = Some(now);
+    if let Some(pk) = pubkey {
+      self.answered.insert(pk.to_vec());
+    }
+  }
+
+  /// Whether everyone we were waiting for has answered (false if we weren't waiting for anyone).
+  pub fn expected_answered(&self) -> bool {
+    let p = &self.policy;
+    if p.expected_keys.is_empty() && p.expected_count.is_none() {
+      return false;
+    }
+    p.expected_keys.iter().all(|k| self.answered.contains(k)) && self.answered.len() >= p.expected_count.unwrap_or(0)
+  }
+
+  /// When to stop if nothing else arrives.
+  pub fn deadline(&self) -> std::time::Instant {
+    let hard = self.start + self.policy.timeout;
+    let quiet = match self.last_record {
+      None => self.start + FIRST_RECORD_WAIT.max(self.policy.idle),
+      Some(last) if self.expected_answered() => last + EXPECTED_SETTLE.min(self.policy.idle),
+      Some(last) => last + self.policy.idle,
+    };
+    hard.min(quiet)
+  }
+
+  pub fn is_done(&self, now: std::time::Instant) -> bool {
+    now >= self.deadline()
+  }
+}
+
+/// The time budget a relay hands its children when what is left of its own is `remaining`, or
+/// `None` if too little is left to be worth forwarding.
+pub fn child_time_budget_ms(remaining: std::time::Duration) -> Option<u32> {
+  let left = remaining.checked_sub(HOP_DEADLINE_MARGIN)?;
+  let ms = u32::try_from(left.as_millis()).unwrap_or(u32::MAX);
+  (ms > 0).then_some(ms)
+}
+
+
 // ============================================================================================
 // Node attestation: a per-node signed statement binding (hostname, identity pubkey, timestamp).
 //
@@ -173,3 +295,50 @@ pub fn verify_attestation_cbor(bytes: &[u8]) -> Result<VerifiedNode, String> {
 
   Ok(VerifiedNode { hostname, pubkey, epoch_s })
 }
+
+
+// ============================================================================================
+// Target matching for `run --to`: one user-typed string names a node by hostname, by full identity
+// key, or by a short-id / key prefix. [[peer]] entries and discovery replies are both checked with
+// the same rules, so `--to` means the same thing whichever way the node is found.
+// ============================================================================================
+
+/// Shortest hex prefix `--to` treats as naming a key - the 8-hex short id shown by chat and netmap.
+pub const MIN_KEY_PREFIX_HEX: usize = 8;
+
+/// A parsed `run --to` argument.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetQuery {
+  /// The argument as given, trimmed.
+  pub text: String,
+  /// Set when the argument is a complete key: 64 hex chars or an OpenSSH `ssh-ed25519 ...` line.
+  pub pubkey: Option<Vec<u8>>,
+}
+
+impl TargetQuery {
+  pub fn parse(text: &str) -> TargetQuery {
+    let text = text.trim().to_string();
+    let pubkey = if text.starts_with("ssh-ed25519") {
+      crate::crypto_utils::public_key_to_ed25519_vk(&text).ok().map(|vk| vk.as_bytes().to_vec())
+    } else if text.len() == 64 && text.chars().all(|c| c.is_ascii_hexdigit()) {
+      (0..32).map(|i| u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).ok()).collect()
+    } else {
+      None
+    };
+    TargetQuery { text, pubkey }
+  }
+
+  /// Whether a node with this (claimed) hostname and identity key is the one asked for. A full key
+  /// only matches that key; anything else matches a hostname (case-insensitively) or, when it is
+  /// at least [`MIN_KEY_PREFIX_HEX`] hex chars, a key starting with it.
+  pub fn matches(&self, hostname: Option<&str>, pubkey: Option<&[u8]>) -> bool {
+    if let Some(want) = &self.pubkey {
+      return pubkey == Some(want.as_slice());
+    }
+    if hostname.is_some_and(|h| h.eq_ignore_ascii_case(&self.text)) {
+      return true;
+    }
+    let is_prefix = self.text.len() >= MIN_KEY_PREFIX_HEX && self.text.chars().all(|c| c.is_ascii_hexdigit());
+    is_prefix && pubkey.is_some_and(|pk| crate::crypto_utils::to_hex(pk).starts_with(&self.text.to_ascii_lowercase()))
+  }
+}
@@ -14,19 +14,27 @@ pub struct Executor {
   /// We need to keep a thread-safe copy of ourselves for use in passed-off threads -_-
   self_weakref: std::sync::Weak<Executor>,
 
-  /// Stores host-set configuration such as which PKI identities are trusted
-  ///
-  config: config::Config,
+  /// Stores host-set configuration such as which PKI identities are trusted. Swapped whole by
+  /// [`Executor::apply_config`] when `serve` reloads its config file.
+  config: std::sync::RwLock<std::sync::Arc<config::Config>>,
 
   next_pid: std::sync::atomic::AtomicU64,
 
-  untrusted_allowed_instructions: std::sync::atomic::AtomicU64,
-
-  trusted_allowed_instructions: std::sync::atomic::AtomicU64,
-
   /// Every program submited will get a unique number (PID) and RunningProgram entry here.
   running_programs: dashmap::DashMap<u64, std::sync::Arc<tokio::sync::RwLock<RunningProgram>> >,
-  pid_last_exit_code: dashmap::DashMap<u64, u32>,
+  pid_last_exit_status: dashmap::DashMap<u64, ExitStatus>,
+
+  /// Stdin pipes of running programs launched with [`ExecOptions::stdin`], keyed by PID. Removed when
+  /// the pipe reaches EOF or the program exits.
+  stdin_pipes: dashmap::DashMap<u64, StdinPipe>,
+
+  /// Input sides of running programs' remote terminals ([`ExecOptions::tty_input`]), keyed by PID.
+  /// Removed on hang-up or when the program exits.
+  tty_inputs: dashmap::DashMap<u64, TtyInput>,
+
+  /// How to stop each running program early (see [`Executor::terminate_running_pid`]), keyed by PID.
+  /// Removed when the program exits.
+  kill_switches: dashmap::DashMap<u64, KillSwitch>,
 
   trusted_keys: dashmap::DashMap<String, ed25519_dalek::VerifyingKey>,
 
@@ -40,13 +48,30 @@ pub struct Executor {
   /// a discovery program can report this node's neighbours. See readme "Network discovery".
   peers: dashmap::DashMap<String, PeerInfo>,
 
+  /// `peers` is persisted here (see [`peer_registry`]) and reloaded by the next `Executor::new`.
+  peer_registry_path: std::path::PathBuf,
+  /// Drop peers not heard from for this long (`[state].peer_ttl_s`; 0 = never).
+  peer_ttl_s: u64,
+  /// Set by `note_peer`; cleared by the next flush.
+  peers_dirty: std::sync::atomic::AtomicBool,
+  /// When we last loaded/wrote the registry file, and the keys it held then. A key that has since
+  /// vanished from the file was removed by `weverywhere peers forget`, and is dropped from `peers`
+  /// too unless that peer has contacted us again in the meantime.
+  peers_persisted: std::sync::Mutex<(u64, std::collections::HashSet<String>)>,
+  /// Only the first failed registry write is logged at warn (e.g. an unwritable state dir).
+  peers_save_warned: std::sync::atomic::AtomicBool,
+
   /// This node's bounded log of messages received on the fabric (see [`MessageStore`]). Shared into
   /// every program execution so a "deliver" program can push what a "ui" program later reads. Wrapped
   /// in a std Mutex because the wasmtime host callbacks lock it only briefly.
   messages: std::sync::Arc<std::sync::Mutex<MessageStore>>,
 
+  /// The messages this node sent with `host::messages_send_reliable` and the acks they've collected
+  /// (see [`delivery`]). Shared into every program execution like `messages`.
+  deliveries: std::sync::Arc<std::sync::Mutex<delivery::DeliveryTracker>>,
+
   /// Efficient OS primitive to wake up a ton of .await-ers.
-  /// This one is fired every time a PID exits. The exit code may be found in pid_last_exit_code until a new process
+  /// This one is fired every time a PID exits. The exit status may be found in pid_last_exit_status until a new process
   /// with the same PID is launched, at which point the code will be 0 until the process exits.
   pid_exit_signal: tokio::sync::Notify,
   running_programs_insert_signal: tokio::sync::Notify,
@@ -78,6 +103,70 @@ pub struct ExecReturn {
   pub forward_uuid: Option<[u8; 16]>,
 }
 
+/// How a program ended, kept per PID after it exits (see [`Executor::wait_for_pid_exit_status`]) and
+/// sent to the caller as [`messages::NetworkMessage::ProgramExit`].
+#[derive(Debug, Clone)]
+pub struct ExitStatus {
+  pub exit_code: u32,
+  pub reason: messages::ExitReason,
+  /// Fuel burned; None when the program ran unmetered (`ExecOptions::uncapped_fuel`).
+  pub fuel_consumed: Option<u64>,
+  /// Short wasm backtrace of a trap, innermost first; only captured for trusted programs.
+  pub backtrace: Vec<String>,
+}
+
+impl Default for ExitStatus {
+  fn default() -> Self {
+    ExitStatus { exit_code: 0, reason: messages::ExitReason::Exited, fuel_consumed: None, backtrace: Vec::new() }
+  }
+}
+
+impl ExitStatus {
+  /// Classify the result of calling `_start`. `proc_exit(n)` surfaces from wasmtime as an error but
+  /// is a normal exit with code n; every trap exits 1 with its kind. The original error is handed
+  /// back for anything that isn't a normal exit so the caller can still log/store it.
+  /// A program the host stopped (`killed`) exits 1 with that reason unless it had already exited.
+  fn from_call_result(res: wasmtime::Result<()>, trusted: bool, fuel_consumed: Option<u64>, killed: Option<messages::ExitReason>) -> (ExitStatus, Option<wasmtime::Error>) {
+    let e = match res {
+      Ok(()) => return (ExitStatus { fuel_consumed, ..Default::default() }, None),
+      Err(e) => e,
+    };
+    if let Some(exit) = e.downcast_ref::<wasmtime_wasi::I32Exit>() {
+      return (ExitStatus { exit_code: exit.0 as u32, fuel_consumed, ..Default::default() }, None);
+    }
+    if let Some(reason) = killed {
+      return (ExitStatus { exit_code: 1, reason, fuel_consumed, backtrace: Vec::new() }, None);
+    }
+    let reason = match e.downcast_ref::<wasmtime::Trap>() {
+      Some(wasmtime::Trap::OutOfFuel) => messages::ExitReason::OutOfFuel,
+      Some(wasmtime::Trap::UnreachableCodeReached) => messages::ExitReason::Unreachable,
+      Some(trap) => messages::ExitReason::Trap(trap.to_string()),
+      None if e.downcast_ref::<MemoryLimitExceeded>().is_some() => messages::ExitReason::OutOfMemory,
+      // Not a wasm trap: a host import failed the call.
+      None => messages::ExitReason::Trap(e.to_string()),
+    };
+    let backtrace = if trusted { short_backtrace(&e) } else { Vec::new() };
+    (ExitStatus { exit_code: 1, reason, fuel_consumed, backtrace }, Some(e))
+  }
+
+  fn start_failed(e: &wasmtime::Error) -> ExitStatus {
+    ExitStatus { exit_code: 1, reason: messages::ExitReason::StartFailed(e.to_string()), ..Default::default() }
+  }
+}
+
+/// Render up to [`BACKTRACE_MAX_FRAMES`] frames of the wasm backtrace wasmtime attaches to a trap,
+/// as `name+0xoffset` (or `func[index]` for unnamed functions).
+fn short_backtrace(e: &wasmtime::Error) -> Vec<String> {
+  let Some(bt) = e.downcast_ref::<wasmtime::WasmBacktrace>() else { return Vec::new() };
+  bt.frames().iter().take(BACKTRACE_MAX_FRAMES).map(|f| {
+    let name = f.func_name().map(str::to_string).unwrap_or_else(|| format!("func[{}]", f.func_index()));
+    match f.func_offset() {
+      Some(off) => format!("{name}+{off:#x}"),
+      None => name,
+    }
+  }).collect()
+}
+
 /// CBOR integer keys inside a single message record returned by `host::messages_read`. Kept small so
 /// the C chat program can hand-decode them; mirror any change in example-programs/chat.c.
 pub mod message_keys {
@@ -91,17 +180,39 @@ pub mod message_keys {
   pub const EPOCH_S: i128 = 4;
   /// byte string: the message body exactly as the sending program supplied it.
   pub const TEXT: i128 = 5;
+  /// text: the room the message was said in (`""` = the fabric-wide room).
+  pub const ROOM: i128 = 6;
+  /// byte string: the recipient's identity pubkey; only present on direct messages.
+  pub const TO: i128 = 7;
+  /// byte string: the sender-chosen message id (what `host::messages_status` takes); absent for
+  /// messages a program pushed without one.
+  pub const ID: i128 = 8;
 }
 
+/// How many messages a node's [`MessageStore`] (and its on-disk history) keeps.
+pub const MESSAGE_STORE_CAP: usize = 4096;
+
 /// One message recorded in a node's [`MessageStore`]. The identity fields are stamped by the host
 /// from the request's already-verified `source`, so a program can never forge a sender's name/pubkey.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct StoredMessage {
   pub seq: u64,
+  /// The sender-chosen per-message nonce it was deduplicated by (empty if pushed without one).
+  pub id: Vec<u8>,
   pub from_name: String,
   pub from_pubkey: Vec<u8>,
   pub text: Vec<u8>,
   pub epoch_s: u64,
+  /// The room it was said in; `""` for the fabric-wide room (and every SignedFabricMessage).
+  pub room: String,
+  /// The recipient of a direct message (already decrypted into `text`); None for a room message.
+  pub to: Option<Vec<u8>>,
+  /// The serde_bare signed message as it arrived, replayed to peers asking for history; empty for
+  /// messages a program pushed locally, which have no signature to replay.
+  pub signed: Vec<u8>,
+  /// A direct message between two other identities: unreadable here (`text` is empty) and never
+  /// handed to programs, only kept to replay to its sender or recipient.
+  pub opaque: bool,
 }
 
 /// A node's bounded, in-memory log of chat/messages received on the fabric. Lives on the [`Executor`]
@@ -121,6 +232,8 @@ pub struct MessageStore {
   seen: std::collections::HashSet<Vec<u8>>,
   seen_order: std::collections::VecDeque<Vec<u8>>,
   seen_cap: usize,
+  /// Where signed messages are also appended when `[state] message_history` is on.
+  history: Option<message_history::HistoryLog>,
 }
 
 impl MessageStore {
@@ -132,15 +245,21 @@ impl MessageStore {
       seen: std::collections::HashSet::new(),
       seen_order: std::collections::VecDeque::new(),
       seen_cap: 4096,
+      history: None,
     }
   }
   /// Append a message with the host-stamped verified identity, deduplicated by `(from_pubkey, id)`.
   /// Returns the assigned sequence number, or 0 if this was a duplicate that was dropped. `id` is the
   /// sender-chosen per-message nonce; pass an empty slice to disable dedup for this push.
   pub fn push(&mut self, from_name: String, from_pubkey: Vec<u8>, id: &[u8], text: Vec<u8>, epoch_s: u64) -> u64 {
+    self.insert(id, StoredMessage { from_name, from_pubkey, text, epoch_s, ..Default::default() })
+  }
+  /// [`MessageStore::push`] for a full record (room, recipient, signed original); `msg.seq` is
+  /// assigned here. A signed message is also appended to the on-disk history, if there is one.
+  pub fn insert(&mut self, id: &[u8], mut msg: StoredMessage) -> u64 {
     if !id.is_empty() {
-      let mut key = Vec::with_capacity(from_pubkey.len() + id.len());
-      key.extend_from_slice(&from_pubkey);
+      let mut key = Vec::with_capacity(msg.from_pubkey.len() + id.len());
+      key.extend_from_slice(&msg.from_pubkey);
       key.extend_from_slice(id);
       if !self.seen.insert(key.clone()) {
         return 0; // already seen this exact (sender, message) - a duplicate delivery
@@ -150,18 +269,124 @@ impl MessageStore {
         if let Some(old) = self.seen_order.pop_front() { self.seen.remove(&old); }
       }
     }
-    let seq = self.next_seq;
+    if let Some(history) = self.history.as_mut().filter(|_| !msg.signed.is_empty()) {
+      history.append(&messages::HistoryEntry { epoch_s: msg.epoch_s, message: msg.signed.clone() });
+    }
+    msg.id = id.to_vec();
+    msg.seq = self.next_seq;
     self.next_seq += 1;
-    self.msgs.push_back(StoredMessage { seq, from_name, from_pubkey, text, epoch_s });
+    let seq = msg.seq;
+    self.msgs.push_back(msg);
     while self.msgs.len() > self.cap { self.msgs.pop_front(); }
     seq
   }
+  /// Append every signed message recorded from now on to `history`.
+  pub fn set_history(&mut self, history: message_history::HistoryLog) {
+    self.history = Some(history);
+  }
   /// Messages with `seq > after_seq`, oldest first (clones, so the lock is released quickly).
   pub fn read_after(&self, after_seq: u64) -> Vec<StoredMessage> {
-    self.msgs.iter().filter(|m| m.seq > after_seq).cloned().collect()
+    self.msgs.iter().filter(|m| m.seq > after_seq && !m.opaque).cloned().collect()
+  }
+  /// [`MessageStore::read_after`] for one room. Direct messages are personal rather than said in a
+  /// room, so they show up whichever room is asked for.
+  pub fn read_room_after(&self, room: &str, after_seq: u64) -> Vec<StoredMessage> {
+    self.msgs.iter().filter(|m| m.seq > after_seq && !m.opaque && (m.room == room || m.to.is_some())).cloned().collect()
+  }
+  /// The newest `max` signed messages recorded at or after `since_epoch_s` to replay to `requester`:
+  /// room messages (of `room`, if given) and the direct messages it sent or received.
+  pub fn history_for(&self, room: Option<&str>, since_epoch_s: u64, requester: &[u8], max: usize) -> Vec<messages::HistoryEntry> {
+    let wanted = |m: &&StoredMessage| match &m.to {
+      None => room.is_none_or(|r| r == m.room),
+      Some(to) => to == requester || m.from_pubkey == requester,
+    };
+    let mut out: Vec<messages::HistoryEntry> = self.msgs.iter().rev()
+      .filter(|m| !m.signed.is_empty() && m.epoch_s >= since_epoch_s)
+      .filter(wanted)
+      .take(max)
+      .map(|m| messages::HistoryEntry { epoch_s: m.epoch_s, message: m.signed.clone() })
+      .collect();
+    out.reverse();
+    out
+  }
+  /// When the newest signed message was recorded, if there is one.
+  pub fn newest_signed_epoch(&self) -> Option<u64> {
+    self.msgs.iter().rev().find(|m| !m.signed.is_empty()).map(|m| m.epoch_s)
   }
 }
 
+/// Record a SignedFabricMessage, ReliableFabricMessage or RoomMessage whose signatures the caller has already checked into
+/// `store`, as this node can read it: a direct message is decrypted with `key` when it was sent to or
+/// by us, and kept [`StoredMessage::opaque`] otherwise. Anything that doesn't decode is dropped.
+/// Returns the assigned sequence number, or 0 if nothing was recorded.
+pub fn store_signed_message(store: &mut MessageStore, msg: &messages::NetworkMessage, epoch_s: u64, key: Option<&ed25519_dalek::SigningKey>) -> u64 {
+  let (source, id, room, to, text, opaque) = match msg {
+    messages::NetworkMessage::SignedFabricMessage { source, id, cbor_data, .. } | messages::NetworkMessage::ReliableFabricMessage { source, id, cbor_data, .. } => (source, id, String::new(), None, cbor_data.clone(), false),
+    messages::NetworkMessage::RoomMessage { source, id, envelope, .. } => {
+      let Ok(envelope) = serde_bare::from_slice::<messages::MessageEnvelope>(envelope) else { return 0 };
+      match envelope.body {
+        messages::MessageBody::Clear(cbor) => (source, id, envelope.room, None, cbor, false),
+        messages::MessageBody::Sealed { to, ephemeral_key, ciphertext, sender_ciphertext } => {
+          let ours = key.map(|k| k.verifying_key().as_bytes().to_vec()).unwrap_or_default();
+          let sealed = if to == ours {
+            Some(ciphertext)
+          } else if source.encoded_public_key == ours {
+            Some(sender_ciphertext)
+          } else {
+            None // between two other identities
+          };
+          match (sealed, key) {
+            (Some(sealed), Some(key)) => match crypto_utils::open_sealed(key, &ephemeral_key, &sealed) {
+              Ok(text) => (source, id, envelope.room, Some(to), text, false),
+              Err(_) => return 0,
+            },
+            _ => (source, id, envelope.room, Some(to), Vec::new(), true),
+          }
+        }
+      }
+    }
+    _ => return 0,
+  };
+  let Ok(signed) = serde_bare::to_vec(msg) else { return 0 };
+  store.insert(id, StoredMessage {
+    seq: 0,
+    id: Vec::new(),
+    from_name: source.human_name.clone(),
+    from_pubkey: source.encoded_public_key.clone(),
+    text,
+    epoch_s,
+    room,
+    to,
+    signed,
+    opaque,
+  })
+}
+
+/// A fresh [`MessageStore`] for `config`, refilled from (and then appending to) the on-disk history
+/// when `[state] message_history` is on.
+async fn load_message_store(config: &config::Config, key: Option<&ed25519_dalek::SigningKey>) -> MessageStore {
+  let mut store = MessageStore::new(MESSAGE_STORE_CAP);
+  if !config.state.message_history {
+    return store;
+  }
+  let path = message_history::path(&config.state);
+  match message_history::load(&path, MESSAGE_STORE_CAP).await {
+    Ok(entries) => {
+      for entry in entries {
+        if let Ok(msg) = serde_bare::from_slice::<messages::NetworkMessage>(&entry.message) {
+          store_signed_message(&mut store, &msg, entry.epoch_s, key);
+        }
+      }
+    }
+    Err(e) => tracing::warn!("Could not load the message history: {}", e),
+  }
+  match message_history::HistoryLog::open(&path) {
+    Ok(history) => store.set_history(history),
+    Err(e) => tracing::warn!("Could not open the message history {} (set [state] dir to a writable directory): {}", path.display(), e),
+  }
+  store
+}
+
 /// Per-execution wiring the launching context supplies to [`Executor::begin_exec`]. Bundled into one
 /// struct (rather than a growing parameter list) as the host surface expands. All fields are optional
 /// so a plain batch run can pass `ExecOptions::default()`.
@@ -181,6 +406,59 @@ pub struct ExecOptions {
   /// Run without the instruction (fuel) cap. Required for long-lived interactive programs, which
   /// would otherwise trap once the fuel budget is exhausted. Only set this for trusted local launches.
   pub uncapped_fuel: bool,
+  /// Attach a host-fed pipe as the guest's WASI stdin, fed via [`Executor::feed_stdin`]. `None`
+  /// leaves stdin closed (the guest reads EOF immediately).
+  pub stdin: Option<StdinPipeOptions>,
+  /// Where input for a terminal on another node comes in (see [`crate::tty::remote`]); registered so
+  /// [`Executor::feed_tty`] can reach it. Set alongside a `tty` from that same call.
+  pub tty_input: Option<TtyInputOptions>,
+  /// The caller that sent the ExecuteRequest: the only address a `ProgramKill` is taken from (see
+  /// [`Executor::kill_program`]). `None` = no one can stop the program over the network.
+  pub owner: Option<std::net::SocketAddr>,
+}
+
+/// The input side of a program's remote terminal (see [`ExecOptions::tty_input`]).
+#[derive(Clone)]
+pub struct TtyInputOptions {
+  /// The only address allowed to send input - the caller that sent the ExecuteRequest. `None` accepts
+  /// any sender.
+  pub owner: Option<std::net::SocketAddr>,
+  pub input: crate::tty::RemoteInput,
+}
+
+/// A running program's way out, kept on the [`Executor`] keyed by PID: the reason it is being stopped,
+/// which its runner task waits on.
+struct KillSwitch {
+  request_id: [u8; 16],
+  owner: Option<std::net::SocketAddr>,
+  reason: tokio::sync::watch::Sender<Option<messages::ExitReason>>,
+}
+
+/// A running program's remote terminal input, kept on the [`Executor`] keyed by PID.
+struct TtyInput {
+  request_id: [u8; 16],
+  owner: Option<std::net::SocketAddr>,
+  input: crate::tty::RemoteInput,
+}
+
+/// How a launcher wants a program's stdin pipe wired up (see [`ExecOptions::stdin`]).
+#[derive(Debug, Clone, Default)]
+pub struct StdinPipeOptions {
+  /// The only address allowed to feed this pipe - the caller that sent the ExecuteRequest. `None`
+  /// accepts any feeder (local launches that feed the pipe themselves).
+  pub owner: Option<std::net::SocketAddr>,
+}
+
+/// The write side of one running program's stdin pipe, kept on the [`Executor`] keyed by PID until
+/// the program exits. Chunks are queued for a pump task that writes them into the guest-facing pipe
+/// in order; dropping `tx` closes the pipe (EOF) once the queue drains.
+struct StdinPipe {
+  request_id: [u8; 16],
+  owner: Option<std::net::SocketAddr>,
+  /// The `seq` of the next chunk the pipe takes.
+  next_seq: u64,
+  /// `None` once the EOF chunk has been taken; the pipe stays to acknowledge repeats of it.
+  tx: Option<tokio::sync::mpsc::Sender<Vec<u8>>>,
 }
 
 /// Where a `host::replicate` copy should be sent. Kept as an enum so we can grow targets (a specific
@@ -247,12 +525,43 @@ pub struct ProgramData {
   /// role from `arg_map["mode"]`, for example. Defaults to empty for a plain `run`.
   #[serde(default)]
   pub arg_map: Vec<(String, String)>,
+
+  /// Request options newer than this struct's wire layout. They travel beside it in an
+  /// `ExtendedExecuteRequest` (see [`messages::NetworkMessage::execute_request`]), never inside it,
+  /// so a program that sets none of them is still a plain `ExecuteRequest` any node decodes.
+  #[serde(skip)]
+  pub options: ProgramOptions,
 }
 
 impl ProgramData {
 
 }
 
+/// Per-request options carried by [`messages::ProgramExtension`]. All default to off, and a request
+/// that leaves them so is sent as a plain `ExecuteRequest`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ProgramOptions {
+  /// The client will stream this program's WASI stdin over the network as `ProgramStdin` messages
+  /// (see `run --stdin`). When false the guest's stdin stays closed, exactly as before, so a program
+  /// that reads stdin can never hang waiting on a client that isn't sending.
+  pub wants_stdin: bool,
+
+  /// Identity public key of the one node meant to run this program (`run --to`). Any other executor
+  /// that receives it - over multicast, say - ignores it. Not a security boundary: the client still
+  /// checks the signed `ProgramAccepted` it gets back. `None` lets anyone run it.
+  pub target_pubkey: Option<Vec<u8>>,
+
+  /// How much longer (ms, counted from when a node receives this) the origin is still listening for
+  /// records. A relay forwards with what is left minus a hop margin and stops relaying once it runs
+  /// out (see `crate::discovery::child_time_budget_ms`). 0 = no deadline (each hop's own cap applies).
+  pub time_budget_ms: u32,
+
+  /// The client's terminal size (cols, rows) when it attaches its terminal to this program (`run
+  /// --tty`): the `host::tty_*` imports then draw there, via `TtyDraw` messages, and read the keys it
+  /// forwards as `TtyInput`. `None` runs the program with no terminal.
+  pub tty_size: Option<(u16, u16)>,
+}
+
 /// A single passively-observed neighbour on the fabric. Populated by [`Executor::note_peer`] from
 /// the signed identity carried on inbound requests, and surfaced to WASI programs (one formatted
 /// line per peer) through the `host::peer_report` import.
@@ -280,6 +589,7 @@ pub struct ProgramDataBuilder {
   visited: Vec<Vec<u8>>,
   arg_list: Vec<String>,
   arg_map: Vec<(String, String)>,
+  options: ProgramOptions,
 }
 
 impl ProgramDataBuilder {
@@ -294,6 +604,7 @@ impl ProgramDataBuilder {
       visited: Vec::new(),
       arg_list: Vec::new(),
       arg_map: Vec::new(),
+      options: ProgramOptions::default(),
     }
   }
   /// Set the program's positional (`arg_list`) and named (`arg_map`) arguments in one call.
@@ -310,6 +621,26 @@ impl ProgramDataBuilder {
     self.visited = visited;
     self
   }
+  /// Tell every hop how long (ms) the origin keeps listening; 0 (the default) sets no deadline.
+  pub fn set_time_budget_ms(mut self, time_budget_ms: u32) -> Self {
+    self.options.time_budget_ms = time_budget_ms;
+    self
+  }
+  /// Ask the executor to attach a network-fed stdin pipe (fed by `ProgramStdin` messages).
+  pub fn set_wants_stdin(mut self, wants_stdin: bool) -> Self {
+    self.options.wants_stdin = wants_stdin;
+    self
+  }
+  /// Address the program to the single node with this identity public key (see `run --to`).
+  pub fn set_target_pubkey(mut self, target_pubkey: Option<Vec<u8>>) -> Self {
+    self.options.target_pubkey = target_pubkey;
+    self
+  }
+  /// Ask the executor to attach a network-backed terminal of this size (see `run --tty`).
+  pub fn set_tty_size(mut self, tty_size: Option<(u16, u16)>) -> Self {
+    self.options.tty_size = tty_size;
+    self
+  }
   pub fn set_source(mut self, source: &config::IdentityData) -> Self {
     self.source = Some(source.clone());
     self
@@ -340,6 +671,7 @@ impl ProgramDataBuilder {
         visited: self.visited,
         arg_list: self.arg_list,
         arg_map: self.arg_map,
+        options: self.options,
       })
     }
     else {
@@ -367,11 +699,48 @@ pub struct RunningProgram {
   pub spawn_error: tokio::sync::RwLock<Option<Box<dyn std::error::Error + Send + Sync>>>,
 }
 
+/// The store's resource limiter: no linear memory may grow past `max_bytes` (`None` = no cap).
+/// Growing past it traps with [`MemoryLimitExceeded`] rather than making `memory.grow` return -1, so
+/// the program stops as [`messages::ExitReason::OutOfMemory`] instead of running on short of memory.
+pub struct MemoryLimit {
+  pub max_bytes: Option<usize>,
+}
+
+impl wasmtime::ResourceLimiter for MemoryLimit {
+  fn memory_growing(&mut self, _current: usize, desired: usize, _maximum: Option<usize>) -> wasmtime::Result<bool> {
+    match self.max_bytes {
+      Some(max_bytes) if desired > max_bytes => Err(MemoryLimitExceeded { desired, max_bytes }.into()),
+      _ => Ok(true),
+    }
+  }
+
+  fn table_growing(&mut self, _current: usize, _desired: usize, _maximum: Option<usize>) -> wasmtime::Result<bool> {
+    Ok(true)
+  }
+}
+
+/// The trap [`MemoryLimit`] raises: a memory tried to grow to `desired` bytes past the `max_bytes` cap.
+#[derive(Debug)]
+pub struct MemoryLimitExceeded {
+  pub desired: usize,
+  pub max_bytes: usize,
+}
+
+impl std::fmt::Display for MemoryLimitExceeded {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "memory of {} bytes is over the {} byte limit", self.desired, self.max_bytes)
+  }
+}
+
+impl std::error::Error for MemoryLimitExceeded {}
+
 /// This structure participates in wasmtime function callbacks et al
 pub struct RPStoreData {
   pub rp: std::sync::Arc<tokio::sync::RwLock<RunningProgram>>, // MUST point to the RunningProgram struct which holds the related Store<RPStoreData>
   pub instruction_count: std::sync::Arc<std::sync::atomic::AtomicU64>,
   pub max_instructions: u64,
+  /// Caps the program's linear memory at its caller's `max_memory_bytes` (see [`Executor::limits_for`]).
+  pub limits: MemoryLimit,
   //pub wasi_p1_ctx: std::sync::Arc<tokio::sync::RwLock<wasmtime_wasi::p1::WasiP1Ctx>>,
   pub wasi_p1_ctx: wasmtime_wasi::p1::WasiP1Ctx,
 
@@ -381,6 +750,8 @@ pub struct RPStoreData {
   /// `name\taddr\ttrusted(0/1)\tpubkey_hex`. Snapshotting avoids sharing the live [`Executor`] into
   /// wasmtime callbacks and gives the program a stable view for the duration of its run.
   pub peer_reports: Vec<String>,
+  /// PIDs of the OTHER programs this executor was running when this one started, for `host::load_pids`.
+  pub running_pids: Vec<u64>,
 
   // ---- Discovery per-exec context (snapshotted from the inbound ProgramData) ----
   /// This node's own signing key (for `host::signed_attestation`); None if we have no identity key.
@@ -394,6 +765,8 @@ pub struct RPStoreData {
   pub caller_name: String,
   /// This node's message store, shared from the [`Executor`] for `host::messages_push`/`messages_read`.
   pub messages: std::sync::Arc<std::sync::Mutex<MessageStore>>,
+  /// This node's reliable sends, shared from the [`Executor`] for `host::messages_send_reliable`/`messages_status`.
+  pub deliveries: std::sync::Arc<std::sync::Mutex<delivery::DeliveryTracker>>,
   /// Snapshot of this node's trusted identity pubkeys, for the general `host::trusts_key` query.
   pub trusted_pubkeys: Vec<Vec<u8>>,
   /// Where `host::replicate` deposits requests to send a copy of this program onward. The launcher
@@ -409,6 +782,9 @@ pub struct RPStoreData {
   /// An attached interactive terminal for the `host::tty_*` imports; `None` when not running in a UI
   /// context (the tty imports then report "no terminal").
   pub tty: Option<std::sync::Arc<crate::tty::TtyHandle>>,
+  /// The event encoding `host::tty_next_event` uses for this program ([`crate::tty::PROTOCOL_V1`]
+  /// until it asks for another with `host::tty_protocol`).
+  pub tty_protocol: u8,
   /// Hop distance from the origin (== inbound visited length; origin's direct responders are 1).
   pub depth: u32,
   /// This node's OWN socket address (`ip:port`) as it believes it is reachable by the caller, or
@@ -435,12 +811,39 @@ impl Executor {
     let hostname = get_hostname().await;
     // Load our identity key material once, up front, for signing node attestations and for signing
     // the `source` on forwarded discovery requests. Missing keyfile => None (node still serves).
-    let identity_signing_key = crypto_utils::read_private_key_ed25519_pem_file(&config.identity.keyfile).await.ok();
+    let identity_signing_key = match config.identity.read_private_key_ed25519_pem_file().await {
+      Ok(key) => Some(key),
+      Err(e) => {
+        if tokio::fs::metadata(&config.identity.keyfile).await.is_ok() {
+          tracing::warn!("Serving without an identity: {}", e);
+        }
+        None
+      }
+    };
     let identity_pubkey = identity_signing_key
       .as_ref()
       .map(|k| k.verifying_key().as_bytes().to_vec())
       .unwrap_or_default();
     let identity_data = config::IdentityData::generate_from_config(&config).await.ok();
+    // Reload the neighbours we knew before the restart, minus any that have since expired.
+    let peer_registry_path = peer_registry::path(&config.state);
+    let peer_ttl_s = config.state.peer_ttl_s;
+    let now = sys_utils::epoch_seconds_now_utc0();
+    let known_peers: Vec<PeerInfo> = match peer_registry::load(&peer_registry_path).await {
+      Ok(records) => records.iter().filter(|r| !r.is_expired(now, peer_ttl_s)).filter_map(|r| r.to_info()).collect(),
+      Err(e) => {
+        tracing::warn!("Could not load the peer registry: {}", e);
+        Vec::new()
+      }
+    };
+    let peers = dashmap::DashMap::with_capacity_and_shard_amount(256, 8);
+    let mut deliveries = delivery::DeliveryTracker::new(&identity_pubkey);
+    for info in known_peers {
+      deliveries.note_peer(&info.pubkey, info.last_seen_epoch_s);
+      peers.insert(to_hex(&info.pubkey), info);
+    }
+    let persisted_keys = peers.iter().map(|kv| kv.key().clone()).collect();
+    let messages = load_message_store(&config, identity_signing_key.as_ref()).await;
     std::sync::Arc::new_cyclic(move |weak_ref| {
         // Upgrade inside the task
         let event_loop_weak_ref = weak_ref.clone();
@@ -466,33 +869,51 @@ impl Executor {
         // when we may not trust ourselves, so tasks being performed quickly should confirm that there is at least 1 trusted key
         // before assuming the trust store has been filled
         let initialization_work_weak_ref = weak_ref.clone();
+        let our_identity = config.identity.clone();
         let our_identity_keyfile = config.identity.keyfile.clone();
+        let configured_trust = configured_trust(&config);
         let startup_handle = tokio::spawn(async move {
-          match crypto_utils::read_public_key_ed25519_pem_file(&our_identity_keyfile).await {
-            Ok(our_pub_key) => {
-              for _ in 0..10000 { // 5ms pauses, so in an error state where weak_ref is never populated we run for a max of 50s
-                match initialization_work_weak_ref.upgrade() {
-                  Some(arc) => {
-                    let arc: std::sync::Arc<Executor> = arc; // Compiler forgot what type we were -_-
-                    arc.add_trusted_key(
-                      our_identity_keyfile.file_name().map(|fn_osstr| fn_osstr.to_string_lossy().to_string() ).unwrap_or_else(|| "SELF".to_string() ),
-                      &our_pub_key
-                    );
-                    break;
-                  }
-                  None => {
-                    if crate::v_is_everything() {
-                      tracing::info!("initialization_work_weak_ref.upgrade() is None");
-                    }
-                    tokio::time::sleep(std::time::Duration::from_millis(5)).await; // Wait until we are constructed
-                  }
-                }
-              }
-            }
+          // The [[trusted]] keys are trusted whether or not our own key loads.
+          let our_pub_key = match our_identity.read_public_key_ed25519_pem_file().await {
+            Ok(our_pub_key) => Some(our_pub_key),
             Err(e) => {
               if crate::v_is_info() {
                   tracing::info!("Error reading our own public key: {}", e );
               }
+              None
+            }
+          };
+          for _ in 0..10000 { // 5ms pauses, so in an error state where weak_ref is never populated we run for a max of 50s
+            match initialization_work_weak_ref.upgrade() {
+              Some(arc) => {
+                let arc: std::sync::Arc<Executor> = arc; // Compiler forgot what type we were -_-
+                if let Some(our_pub_key) = our_pub_key.as_ref() {
+                  arc.add_trusted_key(self_trust_name(&our_identity_keyfile), our_pub_key);
+                }
+                for (name, key) in configured_trust.iter() {
+                  arc.add_trusted_key(name, key);
+                }
+                break;
+              }
+              None => {
+                if crate::v_is_everything() {
+                  tracing::info!("initialization_work_weak_ref.upgrade() is None");
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await; // Wait until we are constructed
+              }
+            }
+          }
+        });
+
+        // Write the peer table back periodically so the next start knows who we knew. Ends once the
+        // executor is dropped.
+        let flush_weak_ref = weak_ref.clone();
+        tokio::spawn(async move {
+          loop {
+            tokio::time::sleep(peer_registry::FLUSH_INTERVAL).await;
+            match flush_weak_ref.upgrade() {
+              Some(arc) => arc.flush_peer_registry().await,
+              None => break,
             }
           }
         });
@@ -501,18 +922,18 @@ impl Executor {
         Executor {
             self_weakref: weak_ref.clone(),
 
-            config: config.clone(),
+            config: std::sync::RwLock::new(std::sync::Arc::new(config.clone())),
 
             next_pid: std::sync::atomic::AtomicU64::new(0),
 
-            untrusted_allowed_instructions: std::sync::atomic::AtomicU64::new(16 * 1024),
-
-            trusted_allowed_instructions: std::sync::atomic::AtomicU64::new(u64::MAX),
-
             // We use a high shard count (128) here on the expectation that many processes will be running in parallel,
             // and we want to enable lots of write capacity. This is a similar reason as why we have a large capacity up-front.
             running_programs: dashmap::DashMap::with_capacity_and_shard_amount(16 * 1024, 128),
-            pid_last_exit_code: dashmap::DashMap::with_capacity_and_shard_amount(16 * 1024, 128),
+            pid_last_exit_status: dashmap::DashMap::with_capacity_and_shard_amount(16 * 1024, 128),
+            // Only programs whose client streams stdin get a pipe, so this stays small.
+            stdin_pipes: dashmap::DashMap::with_capacity_and_shard_amount(64, 8),
+            tty_inputs: dashmap::DashMap::with_capacity_and_shard_amount(16, 8),
+            kill_switches: dashmap::DashMap::with_capacity_and_shard_amount(16, 8),
 
             // We expect fewer writes to these during run-time, so we lower the shard amount to reduce overhead
             trusted_keys: dashmap::DashMap::with_capacity_and_shard_amount(256, 8),
@@ -520,10 +941,16 @@ impl Executor {
             hostname: hostname,
             // Peers accumulate slowly (one entry per distinct identity we hear from), so a small
             // shard count is plenty.
-            peers: dashmap::DashMap::with_capacity_and_shard_amount(256, 8),
+            peers,
+            peer_registry_path,
+            peer_ttl_s,
+            peers_dirty: std::sync::atomic::AtomicBool::new(false),
+            peers_persisted: std::sync::Mutex::new((now, persisted_keys)),
+            peers_save_warned: std::sync::atomic::AtomicBool::new(false),
 
             // A few thousand messages is plenty for an interactive session; oldest are dropped.
-            messages: std::sync::Arc::new(std::sync::Mutex::new(MessageStore::new(4096))),
+            messages: std::sync::Arc::new(std::sync::Mutex::new(messages)),
+            deliveries: std::sync::Arc::new(std::sync::Mutex::new(deliveries)),
 
             pid_exit_signal: tokio::sync::Notify::new(),
             running_programs_insert_signal: tokio::sync::Notify::new(),
@@ -564,12 +991,74 @@ impl Executor {
     self.trusted_keys.insert(name.as_ref().into(), key.clone());
   }
 
+  /// The config this executor currently runs under.
+  pub fn config(&self) -> std::sync::Arc<config::Config> {
+    self.config.read().map(|c| c.clone()).unwrap_or_else(|e| e.into_inner().clone())
+  }
+
+  /// The `[limits]` a program from a trusted (or untrusted) caller starts under: its fuel budget and
+  /// memory cap, each 0 for the built-in default (see [`config::Limit`]).
+  pub fn limits_for(&self, trusted: bool) -> config::Limit {
+    let config = self.config();
+    if trusted { config.limits.trusted.clone() } else { config.limits.untrusted.clone() }
+  }
+
+  /// Switch to `config` (a validated re-read of the file we were started with) without touching
+  /// running programs: the trust set becomes our own key plus its `[[trusted]]` keys, its limits apply
+  /// to programs started from now on, and [`Executor::config`] returns it. New keys are added before
+  /// dropped ones are removed, so a key in both sets is never briefly untrusted. The identity and
+  /// `[state]` stay as they were; those take a restart.
+  pub fn apply_config(&self, config: &config::Config) {
+    let mut trusted = configured_trust(config);
+    if let Some(key) = self.identity_signing_key.as_ref() {
+      trusted.push((self_trust_name(&config.identity.keyfile), key.verifying_key()));
+    }
+    for (name, key) in trusted.iter() {
+      self.add_trusted_key(name, key);
+    }
+    self.trusted_keys.retain(|name, _| trusted.iter().any(|(n, _)| n == name));
+    match self.config.write() {
+      Ok(mut c) => *c = std::sync::Arc::new(config.clone()),
+      Err(e) => *e.into_inner() = std::sync::Arc::new(config.clone()),
+    }
+  }
+
+  /// Take on a verified key rotation (see [`crate::key_rotation`]): if we trust its old key, trust the
+  /// new one too and record the rotation so it outlasts a restart. True if the successor was new to us.
+  pub async fn accept_key_rotation(&self, rotation: &key_rotation::Rotation) -> bool {
+    if !self.trusts_pubkey(rotation.old.as_bytes()) || self.trusts_pubkey(rotation.new.as_bytes()) {
+      return false;
+    }
+    self.add_trusted_key(rotation.trust_name(), &rotation.new);
+    let path = key_rotation::path(&self.config().state);
+    if let Err(e) = key_rotation::record(&path, rotation).await {
+      tracing::warn!("[ key_rotations ] Could not record the rotation in {}: {}; the successor is trusted until we restart", path.display(), e);
+    }
+    true
+  }
+
   /// True if `pubkey` (raw ed25519 bytes) is in our trusted-keys set. Used to pick the trusted vs
   /// untrusted forwarding depth for a peer.
   pub fn trusts_pubkey(&self, pubkey: &[u8]) -> bool {
     self.trusted_keys.iter().any(|kv| kv.value().as_bytes() == pubkey)
   }
 
+  /// A signed [`messages::NetworkMessage::ProgramAccepted`] announcing that this node launched
+  /// `program` as `pid`. None if this node has no signing key (it can't vouch for itself).
+  pub fn accepted_message(&self, program: &ProgramData, pid: u64) -> Option<messages::NetworkMessage> {
+    let (key, node) = (self.identity_signing_key.as_ref()?, self.identity_data.clone()?);
+    let trusts_caller = self.trusts_pubkey(&program.source.encoded_public_key);
+    let payload = messages::program_accepted_payload(pid, trusts_caller);
+    let signature = config::IdentityData::sign_payload(key, &program.request_uuid, &payload);
+    Some(messages::NetworkMessage::ProgramAccepted {
+      request_uuid: program.request_uuid,
+      from_pid: pid,
+      node,
+      trusts_caller,
+      signature: signature.to_bytes().to_vec(),
+    })
+  }
+
   /// This node's identity public key bytes (empty if no keyfile). The discovery visited-set key.
   pub fn identity_pubkey(&self) -> Vec<u8> {
     self.identity_pubkey.clone()
@@ -580,6 +1069,15 @@ impl Executor {
     self.identity_data.clone()
   }
 
+  /// False when `program` was addressed (`target_pubkey`) to some other node, so we must not run it.
+  /// A node without an identity key can never be the target of an addressed request.
+  pub fn is_addressed_to_us(&self, program: &ProgramData) -> bool {
+    match &program.options.target_pubkey {
+      Some(target) => !self.identity_pubkey.is_empty() && *target == self.identity_pubkey,
+      None => true,
+    }
+  }
+
   /// Snapshot of passively-observed neighbours as (last address, identity pubkey) - forwarding
   /// targets for recursive discovery, alongside the statically-configured `[[peer]]` list.
   pub fn observed_targets(&self) -> Vec<(std::net::SocketAddr, Vec<u8>)> {
@@ -589,30 +1087,217 @@ impl Executor {
   /// Record (or refresh) a neighbour we just heard from on the fabric. This is intentionally
   /// passive observation, NOT a discovery protocol: we simply remember the signed identity that
   /// arrived on an inbound request so that later discovery *programs* can enumerate our neighbours
-  /// via the `host::peer_*` imports. Keyed by hex(pubkey) so repeated contact updates in place.
+  /// via the `host::peer_*` imports. Keyed by hex(pubkey) so repeated contact updates in place. An
+  /// identity whose self-signature doesn't check out is ignored: it would otherwise be persisted,
+  /// listed, pinnable and chased for delivery acks.
   pub fn note_peer(&self, addr: std::net::SocketAddr, source: &config::IdentityData) {
+    if !source.check_self_signature_b() {
+      return;
+    }
     let trusted = self.trusted_keys.iter().any(|kv| source.encoded_public_key == kv.value().as_bytes());
+    let now = sys_utils::epoch_seconds_now_utc0();
     self.peers.insert(to_hex(&source.encoded_public_key), PeerInfo {
       human_name: source.human_name.clone(),
       pubkey: source.encoded_public_key.clone(),
       last_addr: addr,
       trusted: trusted,
-      last_seen_epoch_s: sys_utils::epoch_seconds_now_utc0(),
+      last_seen_epoch_s: now,
     });
+    if let Ok(mut d) = self.deliveries.lock() {
+      d.note_peer(&source.encoded_public_key, now);
+    }
+    self.peers_dirty.store(true, std::sync::atomic::Ordering::Relaxed);
+  }
+
+  /// Bring the peer registry file and our peer table in line: forget peers removed from the file
+  /// since our last flush (`weverywhere peers forget`), expire peers past `[state].peer_ttl_s`, and
+  /// write the table back if anything changed. Runs every [`peer_registry::FLUSH_INTERVAL`].
+  pub async fn flush_peer_registry(&self) {
+    let now = sys_utils::epoch_seconds_now_utc0();
+    let mut changed = self.peers_dirty.swap(false, std::sync::atomic::Ordering::Relaxed);
+
+    let (last_flush, persisted) = self.peers_persisted.lock().map(|g| g.clone()).unwrap_or_default();
+    match peer_registry::load(&self.peer_registry_path).await {
+      Ok(on_disk) => {
+        let on_disk: std::collections::HashSet<String> = on_disk.into_iter().map(|r| r.pubkey).collect();
+        for key in persisted.difference(&on_disk) {
+          if self.peers.remove_if(key, |_, p| p.last_seen_epoch_s <= last_flush).is_some() {
+            changed = true;
+          }
+        }
+      }
+      Err(e) => if crate::v_is_info() { tracing::info!("Could not re-read the peer registry: {}", e); },
+    }
+    let before = self.peers.len();
+    self.peers.retain(|_, p| !peer_registry::PeerRecord::from_info(p).is_expired(now, self.peer_ttl_s));
+    changed |= self.peers.len() != before;
+    if !changed {
+      return;
+    }
+
+    let records: Vec<peer_registry::PeerRecord> = self.peers.iter().map(|kv| peer_registry::PeerRecord::from_info(kv.value())).collect();
+    match peer_registry::save(&self.peer_registry_path, &records).await {
+      Ok(()) => {
+        if let Ok(mut g) = self.peers_persisted.lock() {
+          *g = (now, records.into_iter().map(|r| r.pubkey).collect());
+        }
+      }
+      Err(e) => {
+        // Keep the changes pending so the next flush retries.
+        self.peers_dirty.store(true, std::sync::atomic::Ordering::Relaxed);
+        if !self.peers_save_warned.swap(true, std::sync::atomic::Ordering::Relaxed) {
+          tracing::warn!("Could not save the peer registry to {} (set [state] dir to a writable directory): {}", self.peer_registry_path.display(), e);
+        } else if crate::v_is_info() {
+          tracing::info!("Could not save the peer registry: {}", e);
+        }
+      }
+    }
   }
 
-  /// Append a signed fabric message (see [`messages::NetworkMessage::SignedFabricMessage`]) to this
-  /// node's message store after the caller has verified both the sender's self-signature and the
-  /// payload signature. The verified `from_name`/`from_pubkey` are stamped by the host (never guest
-  /// data); `payload` is the opaque CBOR body the sender broadcast. Deduplicated by `(pubkey, id)`;
-  /// returns the assigned sequence number, or 0 if it was a duplicate that was dropped.
-  pub fn record_fabric_message(&self, from_name: String, from_pubkey: Vec<u8>, id: &[u8], payload: Vec<u8>, epoch_s: u64) -> u64 {
+  /// Append a signed fabric message (a [`messages::NetworkMessage::SignedFabricMessage`] or
+  /// [`messages::NetworkMessage::RoomMessage`]) to this node's message store after the caller has
+  /// verified both the sender's self-signature and the payload signature (see
+  /// [`store_signed_message`]). Deduplicated by `(pubkey, id)`; returns the assigned sequence number,
+  /// or 0 if it was a duplicate or isn't readable here.
+  pub fn record_signed_message(&self, msg: &messages::NetworkMessage, epoch_s: u64) -> u64 {
     match self.messages.lock() {
-      Ok(mut s) => s.push(from_name, from_pubkey, id, payload, epoch_s),
+      Ok(mut s) => store_signed_message(&mut s, msg, epoch_s, self.identity_signing_key.as_ref()),
       Err(_) => 0,
     }
   }
 
+  /// The signed messages to answer a [`messages::NetworkMessage::HistoryRequest`] with (see
+  /// [`MessageStore::history_for`]).
+  pub fn message_history_for(&self, room: Option<&str>, since_epoch_s: u64, requester: &[u8], max: usize) -> Vec<messages::HistoryEntry> {
+    self.messages.lock().map(|s| s.history_for(room, since_epoch_s, requester, max)).unwrap_or_default()
+  }
+
+  /// Our signed [`messages::NetworkMessage::MessageAck`] for `msg`, a ReliableFabricMessage or
+  /// RoomMessage someone else sent asking for acks (see [`messages::wants_ack`]); None for anything
+  /// else, or when we have no identity to sign with.
+  pub fn message_ack(&self, msg: &messages::NetworkMessage) -> Option<messages::NetworkMessage> {
+    let (messages::NetworkMessage::ReliableFabricMessage { source, id, .. } | messages::NetworkMessage::RoomMessage { source, id, .. }) = msg else { return None };
+    if !messages::wants_ack(msg) {
+      return None;
+    }
+    let (node, key) = (self.identity_data.as_ref()?, self.identity_signing_key.as_ref()?);
+    if source.encoded_public_key == self.identity_pubkey {
+      return None;
+    }
+    let signature = config::IdentityData::sign_payload(key, id, &source.encoded_public_key).to_bytes().to_vec();
+    Some(messages::NetworkMessage::MessageAck { node: node.clone(), source_pubkey: source.encoded_public_key.clone(), id: id.clone(), signature })
+  }
+
+  /// Record that the (already verified) identity `from` acked the message `id` sent by
+  /// `source_pubkey`. Returns true if that was news for a reliable send of ours we're tracking.
+  pub fn note_message_ack(&self, source_pubkey: &[u8], id: &[u8], from: &[u8]) -> bool {
+    source_pubkey == self.identity_pubkey && self.deliveries.lock().map(|mut d| d.ack(id, from)).unwrap_or(false)
+  }
+
+  /// Track the signed message `id`, just sent as `bytes`, for acks and retransmits (what
+  /// `host::messages_send_reliable` does). Returns how many peers are expected to ack it.
+  pub fn track_delivery(&self, id: &[u8], bytes: Vec<u8>) -> usize {
+    let now = (std::time::Instant::now(), sys_utils::epoch_seconds_now_utc0());
+    self.deliveries.lock().map(|mut d| d.track(id, bytes, now.0, now.1)).unwrap_or(0)
+  }
+
+  /// [`Executor::track_delivery`] for a direct message to the identity `to`, the one peer expected
+  /// to ack it (what `host::messages_send_to_reliable` does for a DM).
+  pub fn track_delivery_to(&self, id: &[u8], bytes: Vec<u8>, to: &[u8]) -> usize {
+    self.deliveries.lock().map(|mut d| d.track_to(id, bytes, to, std::time::Instant::now())).unwrap_or(0)
+  }
+
+  /// Where our reliable send `id` stands (see [`delivery::DeliveryTracker::status`]).
+  pub fn delivery_status(&self, id: &[u8]) -> Option<delivery::DeliveryStatus> {
+    self.deliveries.lock().ok().and_then(|d| d.status(id, std::time::Instant::now()))
+  }
+
+  /// The signed messages due a retransmit now (see [`delivery::DeliveryTracker::due`]).
+  pub fn due_retransmits(&self) -> Vec<Vec<u8>> {
+    self.deliveries.lock().map(|mut d| d.due(std::time::Instant::now())).unwrap_or_default()
+  }
+
+  /// When the newest signed message in our store was recorded (where a history backfill can start).
+  pub fn newest_message_epoch(&self) -> Option<u64> {
+    self.messages.lock().ok().and_then(|s| s.newest_signed_epoch())
+  }
+
+  /// Stored fabric messages with `seq > after_seq`, oldest first - what `host::messages_read` sees.
+  pub fn messages_after(&self, after_seq: u64) -> Vec<StoredMessage> {
+    self.messages.lock().map(|s| s.read_after(after_seq)).unwrap_or_default()
+  }
+
+  /// Append `ProgramStdin` chunk `seq` to a running program's stdin pipe. The pipe is looked up by
+  /// `pid` when the sender knows it, else by `request_id`; either way the pipe's request id must match
+  /// and, if the pipe has an owner, `from` must be that owner. `eof` closes the pipe after `data`. The
+  /// pipe only takes the chunk it expects next: a repeat, one past a gap, or one that finds the queue
+  /// full ([`STDIN_QUEUE_CHUNKS`]) is dropped for the sender to resend. Returns the pipe's pid and the
+  /// `seq` it expects next - what the sender is acknowledged with - or None when no matching pipe
+  /// exists (unknown/exited program, wrong request or sender).
+  pub fn feed_stdin(&self, request_id: [u8; 16], pid: u64, from: Option<std::net::SocketAddr>, seq: u64, data: Vec<u8>, eof: bool) -> Option<(u64, u64)> {
+    let pid = if pid != 0 && self.stdin_pipes.contains_key(&pid) {
+      pid
+    } else {
+      *self.stdin_pipes.iter().find(|kv| kv.value().request_id == request_id)?.key()
+    };
+    let mut pipe = self.stdin_pipes.get_mut(&pid)?;
+    if pipe.request_id != request_id { return None; }
+    if pipe.owner.is_some() && pipe.owner != from { return None; }
+    if seq == pipe.next_seq
+      && let Some(tx) = &pipe.tx {
+      let queued = data.is_empty() || match tx.try_send(data) {
+        Ok(()) => true,
+        Err(tokio::sync::mpsc::error::TrySendError::Full(_)) => false,
+        Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) => return None, // guest side gone
+      };
+      if queued {
+        pipe.next_seq += 1;
+        if eof { pipe.tx = None; }
+      }
+    }
+    Some((pid, pipe.next_seq))
+  }
+
+  /// Deliver `TtyInput` events to a running program's remote terminal, found and checked like
+  /// [`Self::feed_stdin`]'s pipe. `hangup` (the user detached) delivers Ctrl+D after `events` - the
+  /// closest thing to a hang-up a tty program already handles - and stops taking input. Returns false
+  /// when no matching terminal took the events.
+  pub fn feed_tty(&self, request_id: [u8; 16], pid: u64, from: Option<std::net::SocketAddr>, events: Vec<crate::tty::TtyEvent>, hangup: bool) -> bool {
+    let Some(pid) = self.tty_pid(request_id, pid) else { return false };
+    {
+      let tty = match self.tty_inputs.get(&pid) { Some(t) => t, None => return false };
+      if tty.request_id != request_id { return false; }
+      if tty.owner.is_some() && tty.owner != from { return false; }
+      for ev in events {
+        tty.input.feed(ev);
+      }
+      if hangup { tty.input.feed(crate::tty::TtyEvent::CtrlD); }
+    }
+    if hangup {
+      self.tty_inputs.remove(&pid);
+    }
+    true
+  }
+
+  /// Answer a `TtyRedraw`: have a running program's remote terminal, found and checked like
+  /// [`Self::feed_tty`]'s, send the whole screen again. Returns false when no matching terminal did.
+  pub fn redraw_tty(&self, request_id: [u8; 16], pid: u64, from: Option<std::net::SocketAddr>) -> bool {
+    let Some(pid) = self.tty_pid(request_id, pid) else { return false };
+    let tty = match self.tty_inputs.get(&pid) { Some(t) => t, None => return false };
+    if tty.request_id != request_id { return false; }
+    if tty.owner.is_some() && tty.owner != from { return false; }
+    tty.input.redraw();
+    true
+  }
+
+  /// The PID of request `request_id`'s remote terminal: `pid` if it has one, else looked up by request.
+  fn tty_pid(&self, request_id: [u8; 16], pid: u64) -> Option<u64> {
+    if pid != 0 && self.tty_inputs.contains_key(&pid) {
+      return Some(pid);
+    }
+    self.tty_inputs.iter().find(|kv| kv.value().request_id == request_id).map(|kv| *kv.key())
+  }
+
   pub async fn begin_exec(&self, program: &ProgramData, stdio_forwarder: executor::wasi_adapters::WasiStdioSimpleForwarder, opts: ExecOptions, return_slot: std::sync::Arc<std::sync::Mutex<ExecReturn>>) -> DynResult<u64> {
     // Check 1: Is the program signature valid, given the identity it claims to have been signed by?
     match program.source.check_self_signature() {
@@ -639,18 +1324,45 @@ impl Executor {
     self.next_pid.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
   }
 
-  async fn terminate_running_pid(&self, pid: u64) -> DynResult<()> {
-    if let Some(entry) = self.running_programs.get(&pid) {
-      tracing::info!("TODO implement {}:{}", file!(), line!());
+  /// Stop a running program early: it exits with code 1 and `reason` (the first one given wins). It is
+  /// dropped where it next waits: in a host call (stdin, its terminal, a sleep), or at its next fuel
+  /// yield if it is busy computing. False if `pid` isn't running.
+  pub fn terminate_running_pid(&self, pid: u64, reason: messages::ExitReason) -> bool {
+    let Some(switch) = self.kill_switches.get(&pid) else { return false };
+    switch.reason.send_if_modified(|current| match current {
+      Some(_) => false,
+      None => {
+        *current = Some(reason);
+        true
+      }
+    });
+    true
+  }
+
+  /// Stop request `request_id`'s program for a `ProgramKill` from `from` (see [`ExecOptions::owner`]):
+  /// PID `pid`, or the request's program when `pid` is 0 (the client hasn't heard it yet). It exits
+  /// as [`messages::ExitReason::HostKilled`]. False if there's no such program or `from` isn't its owner.
+  pub fn kill_program(&self, request_id: [u8; 16], pid: u64, from: Option<std::net::SocketAddr>) -> bool {
+    let pid = if pid != 0 && self.kill_switches.contains_key(&pid) {
+      pid
+    } else {
+      match self.kill_switches.iter().find(|kv| kv.value().request_id == request_id) {
+        Some(kv) => *kv.key(),
+        None => return false,
+      }
+    };
+    match self.kill_switches.get(&pid) {
+      Some(switch) if switch.request_id == request_id && switch.owner.is_some() && switch.owner == from => {}
+      _ => return false,
     }
-    Ok(())
+    self.terminate_running_pid(pid, messages::ExitReason::HostKilled)
   }
 
   async fn create_pid(&self, program: &ProgramData, program_is_trusted: bool, mut stdio_forwarder: executor::wasi_adapters::WasiStdioSimpleForwarder, opts: ExecOptions, return_slot: std::sync::Arc<std::sync::Mutex<ExecReturn>>) -> DynResult<u64> {
     // Allocate space in our PIDs; TODO check for wraparound and/or pre-existing stuff, terminate old when new PID is issued?
     let this_program_pid = self.create_next_pid();
 
-    self.terminate_running_pid(this_program_pid).await?;
+    self.terminate_running_pid(this_program_pid, messages::ExitReason::HostKilled);
 
     stdio_forwarder.set_pid(this_program_pid); // Claim this PID - todo look at timeout stuff, we should not allow these to alias new processes
 
@@ -660,12 +1372,23 @@ impl Executor {
       let p = kv.value();
       format!("{}\t{}\t{}\t{}", p.human_name, p.last_addr, if p.trusted { 1 } else { 0 }, to_hex(&p.pubkey))
     }).collect();
+    let running_pids_snapshot: Vec<u64> = self.running_programs.iter()
+      .map(|kv| *kv.key())
+      .filter(|pid| *pid != this_program_pid)
+      .collect();
+
+    // The caller's limits as configured now, so a reload applies from the next program on.
+    let limit = self.limits_for(program_is_trusted);
+    let fuel = match limit.max_cpu_instructions {
+      0 => INITIAL_FUEL,
+      max => max,
+    };
 
     let mut config = wasmtime::Config::new();
-    // Long-lived interactive programs (e.g. the chat UI) must run without the instruction cap or they
-    // trap once fuel runs out; batch/fabric programs keep the cap. Fuel tracking is only enabled when
-    // we intend to cap, since store.set_fuel requires it.
-    config.consume_fuel(!opts.uncapped_fuel);
+    // Fuel is always tracked: it is what makes a busy program yield (see FUEL_YIELD_INTERVAL) so it can
+    // be stopped. Long-lived interactive programs (e.g. the chat UI) get a tank that never runs dry;
+    // batch/fabric programs keep the cap.
+    config.consume_fuel(true);
     config.async_support(true); // Affects APIs available
 
     let engine = wasmtime::Engine::new(&config).map_err(map_loc_err!())?;
@@ -683,36 +1406,77 @@ impl Executor {
       spawn_error: tokio::sync::RwLock::new(None),
     }));
 
-    let wasi_ctx = wasmtime_wasi::WasiCtxBuilder::new()
+    let mut wasi_builder = wasmtime_wasi::WasiCtxBuilder::new();
+    wasi_builder
       //.inherit_stdout()   // allow fd_write to stdout
       //.inherit_stderr()   // allow fd_write to stderr
-      // NOTE: do NOT call inherit_stdin()
+      // NOTE: do NOT call inherit_stdin() - stdin is only ever the network-fed pipe below
       // NOTE: do NOT call preopen_dir()
       // NOTE: do NOT call inherit_args() unless you want argv
       // NOTE: do NOT call inherit_env() unless you want env vars
       //.build();
       .stdout( stdio_forwarder.clone() )
-      .stderr( stdio_forwarder.clone() )
-      .build_p1();
+      .stderr( stdio_forwarder.for_stderr() );
+
+    if let Some(stdin_opts) = &opts.stdin {
+      // The guest reads one half of an in-memory pipe; a pump task writes incoming chunks into the
+      // other half in order and drops it on EOF. The bounded queue in front keeps feed_stdin
+      // non-blocking for the serve loop: when the guest reads slower than input arrives, chunks are
+      // refused and the sender resends them later.
+      let (guest_half, mut host_half) = tokio::io::duplex(STDIN_PIPE_BYTES);
+      let (tx, mut rx) = tokio::sync::mpsc::channel::<Vec<u8>>(STDIN_QUEUE_CHUNKS);
+      tokio::spawn(async move {
+        while let Some(chunk) = rx.recv().await {
+          if host_half.write_all(&chunk).await.is_err() {
+            break; // guest side gone (program exited)
+          }
+        }
+      });
+      self.stdin_pipes.insert(this_program_pid, StdinPipe {
+        request_id: program.request_uuid,
+        owner: stdin_opts.owner,
+        next_seq: 0,
+        tx: Some(tx),
+      });
+      wasi_builder.stdin(wasmtime_wasi::cli::AsyncStdinStream::new(guest_half));
+    }
+    if let Some(tty_input) = opts.tty_input {
+      self.tty_inputs.insert(this_program_pid, TtyInput {
+        request_id: program.request_uuid,
+        owner: tty_input.owner,
+        input: tty_input.input,
+      });
+    }
+
+    let wasi_ctx = wasi_builder.build_p1();
 
     let rps_store_data = RPStoreData {
       rp: arc_rp_data.clone(),
       instruction_count: std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0)),
       max_instructions: 16 * 1024, // todo
+      limits: MemoryLimit {
+        max_bytes: match limit.max_memory_bytes {
+          0 => None,
+          bytes => Some(usize::try_from(bytes).unwrap_or(usize::MAX)),
+        },
+      },
       //wasi_p1_ctx: std::sync::Arc::new(tokio::sync::RwLock::new(wasi_ctx)),
       wasi_p1_ctx: wasi_ctx,
       hostname: hostname_snapshot,
       peer_reports: peer_reports_snapshot,
+      running_pids: running_pids_snapshot,
       signing_key: self.identity_signing_key.clone(),
       our_pubkey: self.identity_pubkey.clone(),
       caller_pubkey: program.source.encoded_public_key.clone(),
       caller_name: program.source.human_name.clone(),
       messages: self.messages.clone(),
+      deliveries: self.deliveries.clone(),
       trusted_pubkeys: self.trusted_keys.iter().map(|kv| kv.value().as_bytes().to_vec()).collect(),
       replicate_tx: opts.replicate_tx,
       fabric_send_tx: opts.fabric_send_tx,
       identity_data: self.identity_data.clone(),
       tty: opts.tty,
+      tty_protocol: crate::tty::PROTOCOL_V1,
       depth: program.visited.len() as u32,
       node_addr: opts.node_addr,
       arg_list: program.arg_list.clone(),
@@ -724,11 +1488,12 @@ impl Executor {
       let write_lock = arc_rp_data.read().await;
       let engine_read_lock = write_lock.engine.read().await;
       let mut store = wasmtime::Store::new(&engine_read_lock, rps_store_data);
-      // Set initial fuel (roughly corresponds to instruction count) only when the engine is tracking
-      // fuel; an uncapped interactive program has consume_fuel disabled and would reject set_fuel.
-      if !opts.uncapped_fuel {
-        store.set_fuel(128_000).map_err(map_loc_err!())?;
-      }
+      store.limiter(|data| &mut data.limits);
+      // Set initial fuel (roughly corresponds to instruction count).
+      store.set_fuel(if opts.uncapped_fuel { u64::MAX } else { fuel }).map_err(map_loc_err!())?;
+      // Give the runtime back its thread now and then, so a busy program doesn't starve the rest of
+      // the node and a kill or wall-clock timeout can reach it (see terminate_running_pid).
+      store.fuel_async_yield_interval(Some(FUEL_YIELD_INTERVAL)).map_err(map_loc_err!())?;
 
       *write_lock.store.write().await = Some(store);
     }
@@ -875,6 +1640,54 @@ impl Executor {
           },
       ).map_err(map_loc_err!())?;
 
+      // host::load_cpu(ptr, cap) -> bytes_written. Writes a CBOR map of this machine's CPU use (keys per
+      // crate::scheduler::cpu_keys: cores, busy per-mille, 1-minute load x100). Takes a ~200ms sample,
+      // so capacity probes (see `submit`) should call it once.
+      linker.func_wrap_async(
+          "host",
+          "load_cpu",
+          move |mut caller: wasmtime::Caller<'_, RPStoreData>, (ptr, cap): (i32, i32)| {
+            Box::new(async move {
+              let load = sys_utils::cpu_load().await;
+              match crate::scheduler::encode_cpu_load(&load) {
+                Ok(bytes) => write_guest_bytes(&mut caller, ptr, cap, &bytes),
+                Err(_) => Ok(-1i32),
+              }
+            })
+          },
+      ).map_err(map_loc_err!())?;
+
+      // host::load_memory(ptr, cap) -> bytes_written. Writes a CBOR map of this machine's memory (keys
+      // per crate::scheduler::memory_keys: total and available bytes).
+      linker.func_wrap_async(
+          "host",
+          "load_memory",
+          move |mut caller: wasmtime::Caller<'_, RPStoreData>, (ptr, cap): (i32, i32)| {
+            Box::new(async move {
+              let load = sys_utils::memory_load();
+              match crate::scheduler::encode_memory_load(&load) {
+                Ok(bytes) => write_guest_bytes(&mut caller, ptr, cap, &bytes),
+                Err(_) => Ok(-1i32),
+              }
+            })
+          },
+      ).map_err(map_loc_err!())?;
+
+      // host::load_pids(ptr, cap) -> bytes_written. Writes a CBOR array of the PIDs of the other
+      // programs this executor is running (snapshotted when this one started).
+      linker.func_wrap_async(
+          "host",
+          "load_pids",
+          move |mut caller: wasmtime::Caller<'_, RPStoreData>, (ptr, cap): (i32, i32)| {
+            Box::new(async move {
+              match crate::scheduler::encode_pids(&caller.data().running_pids) {
+                Ok(bytes) => write_guest_bytes(&mut caller, ptr, cap, &bytes),
+                Err(_) => Ok(-1i32),
+              }
+            })
+          },
+      ).map_err(map_loc_err!())?;
+
       // host::caller_pubkey(ptr, cap) -> n. Writes the identity pubkey of the caller that sent us
       // this program (this node's parent in the discovery tree) into guest memory.
       linker.func_wrap_async(
@@ -996,7 +1809,7 @@ impl Executor {
 
       // host::messages_read(after_seq, ptr, cap) -> bytes_written. Writes a CBOR array of message
       // records (keys per crate::executor::message_keys) with seq > after_seq into guest memory, newest
-      // last. Not trust-gated: chat is public. If the full set won't fit in `cap`, the oldest matching
+      // last, whatever room they were said in (see host::messages_read_room to pick one). Not trust-gated: chat is public. If the full set won't fit in `cap`, the oldest matching
       // messages that fit are returned; the reader advances its high-water seq and gets the rest next
       // poll. Returns bytes written (0 when nothing new).
       linker.func_wrap_async(
@@ -1067,29 +1880,140 @@ impl Executor {
           move |mut caller: wasmtime::Caller<'_, RPStoreData>, (cbor_ptr, cbor_len): (i32, i32)| {
             Box::new(async move {
               let payload = read_guest_bytes(&mut caller, cbor_ptr, cbor_len)?;
-              // Enforce "no bare strings": the top-level CBOR item must be an array (major 4) or map
-              // (major 5). Anything else (incl. text/byte strings and integers) is rejected.
-              match payload.first().map(|b| b >> 5) {
-                Some(4) | Some(5) => {}
-                _ => return Ok(-2i32),
+              let Some(tx) = caller.data().fabric_send_tx.clone() else { return Ok(-1i32) };
+              match sign_fabric_message(caller.data(), payload, false) {
+                Ok((_, _, bytes)) => Ok(if tx.send(bytes).is_ok() { 0i32 } else { -1i32 }),
+                Err(code) => Ok(code),
               }
-              let (source, signing_key, tx) = {
-                let d = caller.data();
-                match (&d.identity_data, &d.signing_key, &d.fabric_send_tx) {
-                  (Some(src), Some(key), Some(tx)) => (src.clone(), key.clone(), tx.clone()),
-                  _ => return Ok(-1i32),
-                }
+            })
+          },
+      ).map_err(map_loc_err!())?;
+
+      // host::messages_send_reliable(cbor_ptr, cbor_len, id_ptr) -> 0 (queued) | negative on error.
+      // host::messages_send with acknowledgements: writes the message's 16-byte id to id_ptr, records
+      // the message in this node's own store, and has the host retransmit it with backoff until every
+      // peer heard from recently has acked it or the deadline passes (see crate::delivery). Pass the
+      // id to host::messages_status to follow it. Errors as for messages_send.
+      linker.func_wrap_async(
+          "host",
+          "messages_send_reliable",
+          move |mut caller: wasmtime::Caller<'_, RPStoreData>, (cbor_ptr, cbor_len, id_ptr): (i32, i32, i32)| {
+            Box::new(async move {
+              let payload = read_guest_bytes(&mut caller, cbor_ptr, cbor_len)?;
+              let Some(tx) = caller.data().fabric_send_tx.clone() else { return Ok(-1i32) };
+              let (id, msg, bytes) = match sign_fabric_message(caller.data(), payload, true) {
+                Ok(signed) => signed,
+                Err(code) => return Ok(code),
               };
-              let mut id = [0u8; 16];
-              { use rand::RngCore; rand::rngs::OsRng.fill_bytes(&mut id); }
-              let signature = config::IdentityData::sign_payload(&signing_key, &id, &payload).to_bytes().to_vec();
-              let msg = messages::NetworkMessage::SignedFabricMessage {
-                source, id: id.to_vec(), cbor_data: payload, signature,
+              write_guest_bytes(&mut caller, id_ptr, id.len() as i32, &id)?;
+              let d = caller.data();
+              if let Ok(mut s) = d.messages.lock() {
+                store_signed_message(&mut s, &msg, sys_utils::epoch_seconds_now_utc0(), d.signing_key.as_ref());
+              }
+              if let Ok(mut deliveries) = d.deliveries.lock() {
+                deliveries.track(&id, bytes.clone(), std::time::Instant::now(), sys_utils::epoch_seconds_now_utc0());
+              }
+              Ok(if tx.send(bytes).is_ok() { 0i32 } else { -1i32 })
+            })
+          },
+      ).map_err(map_loc_err!())?;
+
+      // host::messages_status(id_ptr, id_len, out_ptr) -> state | -1.
+      // Where a host::messages_send_reliable message stands: 0 pending (still retransmitting), 1
+      // delivered (every expected peer acked), 2 expired (the deadline passed first), or -1 for an id
+      // this node isn't tracking. Writes 4 bytes to out_ptr: how many identities acked, then how many
+      // were expected to, each u16 little-endian.
+      linker.func_wrap_async(
+          "host",
+          "messages_status",
+          move |mut caller: wasmtime::Caller<'_, RPStoreData>, (id_ptr, id_len, out_ptr): (i32, i32, i32)| {
+            Box::new(async move {
+              let id = read_guest_bytes(&mut caller, id_ptr, id_len)?;
+              let status = caller.data().deliveries.lock().ok().and_then(|d| d.status(&id, std::time::Instant::now()));
+              let Some(status) = status else { return Ok(-1i32) };
+              let mut out = [0u8; 4];
+              out[..2].copy_from_slice(&(status.acked.min(u16::MAX as usize) as u16).to_le_bytes());
+              out[2..].copy_from_slice(&(status.expected.min(u16::MAX as usize) as u16).to_le_bytes());
+              write_guest_bytes(&mut caller, out_ptr, 4, &out)?;
+              Ok(status.state.code())
+            })
+          },
+      ).map_err(map_loc_err!())?;
+
+      // host::messages_send_to(room_ptr, room_len, to_ptr, to_len, cbor_ptr, cbor_len) -> 0 (queued) |
+      // negative on error. host::messages_send for a named room (`""` is the fabric-wide room), or -
+      // when `to` is a 32-byte identity pubkey rather than empty - a direct message only that identity
+      // (and we) can read: the payload is sealed to its key (crypto_utils::seal_to) inside the signed
+      // envelope of a NetworkMessage::RoomMessage. The message is also recorded in this node's own
+      // store straight away, so the sender sees what it said even where multicast doesn't loop back.
+      // Errors as for messages_send, plus -4 for a `to` that isn't a valid identity key.
+      linker.func_wrap_async(
+          "host",
+          "messages_send_to",
+          move |mut caller: wasmtime::Caller<'_, RPStoreData>, (room_ptr, room_len, to_ptr, to_len, cbor_ptr, cbor_len): (i32, i32, i32, i32, i32, i32)| {
+            Box::new(async move {
+              let room = String::from_utf8_lossy(&read_guest_bytes(&mut caller, room_ptr, room_len)?).into_owned();
+              let to = read_guest_bytes(&mut caller, to_ptr, to_len)?;
+              let payload = read_guest_bytes(&mut caller, cbor_ptr, cbor_len)?;
+              let Some(tx) = caller.data().fabric_send_tx.clone() else { return Ok(-1i32) };
+              match send_room_message(caller.data(), &room, &to, payload, false) {
+                Ok((_, bytes)) => Ok(if tx.send(bytes).is_ok() { 0i32 } else { -1i32 }),
+                Err(code) => Ok(code),
+              }
+            })
+          },
+      ).map_err(map_loc_err!())?;
+
+      // host::messages_send_to_reliable(room_ptr, room_len, to_ptr, to_len, cbor_ptr, cbor_len, id_ptr)
+      // -> 0 (queued) | negative on error. host::messages_send_to with acknowledgements, as
+      // host::messages_send_reliable is for host::messages_send: writes the message's 16-byte id to
+      // id_ptr and retransmits it until it's acked or the deadline passes. A room message waits on
+      // every peer heard from recently; a direct message only on its recipient. Errors as for
+      // messages_send_to.
+      linker.func_wrap_async(
+          "host",
+          "messages_send_to_reliable",
+          move |mut caller: wasmtime::Caller<'_, RPStoreData>, (room_ptr, room_len, to_ptr, to_len, cbor_ptr, cbor_len, id_ptr): (i32, i32, i32, i32, i32, i32, i32)| {
+            Box::new(async move {
+              let room = String::from_utf8_lossy(&read_guest_bytes(&mut caller, room_ptr, room_len)?).into_owned();
+              let to = read_guest_bytes(&mut caller, to_ptr, to_len)?;
+              let payload = read_guest_bytes(&mut caller, cbor_ptr, cbor_len)?;
+              let Some(tx) = caller.data().fabric_send_tx.clone() else { return Ok(-1i32) };
+              let (id, bytes) = match send_room_message(caller.data(), &room, &to, payload, true) {
+                Ok(sent) => sent,
+                Err(code) => return Ok(code),
               };
-              match serde_bare::to_vec(&msg) {
-                Ok(bytes) => Ok(if tx.send(bytes).is_ok() { 0i32 } else { -1i32 }),
-                Err(_) => Ok(-3i32),
+              write_guest_bytes(&mut caller, id_ptr, id.len() as i32, &id)?;
+              if let Ok(mut deliveries) = caller.data().deliveries.lock() {
+                let now = std::time::Instant::now();
+                if to.is_empty() {
+                  deliveries.track(&id, bytes.clone(), now, sys_utils::epoch_seconds_now_utc0());
+                } else {
+                  deliveries.track_to(&id, bytes.clone(), &to, now);
+                }
               }
+              Ok(if tx.send(bytes).is_ok() { 0i32 } else { -1i32 })
+            })
+          },
+      ).map_err(map_loc_err!())?;
+
+      // host::messages_read_room(room_ptr, room_len, after_seq, ptr, cap) -> bytes_written.
+      // host::messages_read for one room (`""` = the fabric-wide room): the records said in `room`,
+      // plus every direct message, which belongs to no room. Same record format and paging.
+      linker.func_wrap_async(
+          "host",
+          "messages_read_room",
+          move |mut caller: wasmtime::Caller<'_, RPStoreData>, (room_ptr, room_len, after_seq, ptr, cap): (i32, i32, i64, i32, i32)| {
+            Box::new(async move {
+              let room = String::from_utf8_lossy(&read_guest_bytes(&mut caller, room_ptr, room_len)?).into_owned();
+              let after = if after_seq < 0 { 0u64 } else { after_seq as u64 };
+              let msgs = match caller.data().messages.lock() {
+                Ok(s) => s.read_room_after(&room, after),
+                Err(_) => Vec::new(),
+              };
+              let cap = cap.max(0) as usize;
+              let bytes = encode_messages_cbor(&msgs, cap);
+              write_guest_bytes(&mut caller, ptr, cap as i32, &bytes)
             })
           },
       ).map_err(map_loc_err!())?;
@@ -1119,9 +2043,38 @@ impl Executor {
           },
       ).map_err(map_loc_err!())?;
 
-      // host::tty_next_event(ptr, cap, timeout_ms) -> bytes_written (encoded event, see crate::tty),
-      // 0 if the timeout elapsed with no input, or -1 if there is no terminal. Blocking a whole thread
-      // is avoided: this awaits, so other tasks (inbound messages, replication) keep running.
+      // host::tty_protocol(version) -> the event encoding now in effect for this program: the newest
+      // this host speaks that is no newer than `version` (see crate::tty::PROTOCOL_*). Programs that
+      // never call it get protocol 1, the original encoding.
+      linker.func_wrap_async(
+          "host",
+          "tty_protocol",
+          move |mut caller: wasmtime::Caller<'_, RPStoreData>, (version,): (i32,)| {
+            Box::new(async move {
+              let protocol = version.clamp(crate::tty::PROTOCOL_V1 as i32, crate::tty::PROTOCOL_LATEST as i32) as u8;
+              caller.data_mut().tty_protocol = protocol;
+              Ok(protocol as i32)
+            })
+          },
+      ).map_err(map_loc_err!())?;
+
+      // host::tty_modes(modes) -> 0/-1. Turn mouse reporting (bit0), bracketed paste (bit1) and the
+      // alternate screen (bit2) on or off; see crate::tty::modes. Mouse and paste events only reach
+      // programs using protocol 2.
+      linker.func_wrap_async(
+          "host",
+          "tty_modes",
+          move |caller: wasmtime::Caller<'_, RPStoreData>, (modes,): (i32,)| {
+            Box::new(async move {
+              match &caller.data().tty { Some(tty) => { tty.set_modes(modes as u32); Ok(0i32) } None => Ok(-1i32) }
+            })
+          },
+      ).map_err(map_loc_err!())?;
+
+      // host::tty_next_event(ptr, cap, timeout_ms) -> bytes_written (encoded event in the program's
+      // protocol, see crate::tty), 0 if the timeout elapsed with no input (or with only events its
+      // protocol can't express), or -1 if there is no terminal. Blocking a whole thread is avoided:
+      // this awaits, so other tasks (inbound messages, replication) keep running.
       linker.func_wrap_async(
           "host",
           "tty_next_event",
@@ -1131,7 +2084,10 @@ impl Executor {
               let dur = std::time::Duration::from_millis(timeout_ms.max(0) as u64);
               match tty.next_event(dur).await {
                 Some(ev) => {
-                  let bytes = crate::tty::encode_event(&ev);
+                  let bytes = crate::tty::encode_event_for(&ev, caller.data().tty_protocol, cap.max(0) as usize);
+                  if bytes.is_empty() {
+                    return Ok(0i32);
+                  }
                   write_guest_bytes(&mut caller, ptr, cap, &bytes)
                 }
                 None => Ok(0i32),
@@ -1181,8 +2137,9 @@ impl Executor {
           },
       ).map_err(map_loc_err!())?;
 
-      // host::tty_style(fg, bg, attrs) -> 0/-1. Set colour (ANSI 0-15, -1 = default) and attributes
-      // (bit0 bold, bit1 underline, bit2 reverse; 0 resets).
+      // host::tty_style(fg, bg, attrs) -> 0/-1. Set colour (ANSI 0-15, palette 16-255,
+      // 0x01RRGGBB for RGB, -1 = default) and attributes, all the bits set (bit0 bold, bit1 underline,
+      // bit2 reverse, bit3 italic, bit4 dim, bit5 strikethrough, bit6 blink; 0 = plain).
       linker.func_wrap_async(
           "host",
           "tty_style",
@@ -1300,9 +2257,31 @@ impl Executor {
       *write_lock.module.write().await = Some(module);
     }
 
+    // Registered before the task starts: a program that exits at once must find its entry to remove,
+    // or it would look like it runs forever.
+    self.running_programs.insert(this_program_pid, arc_rp_data.clone());
+    self.running_programs_insert_signal.notify_waiters();
+    let (kill_tx, mut kill_rx) = tokio::sync::watch::channel(None);
+    self.kill_switches.insert(this_program_pid, KillSwitch {
+      request_id: program.request_uuid,
+      owner: opts.owner,
+      reason: kill_tx,
+    });
+    if limit.max_wall_clock_ms > 0 {
+      let budget = std::time::Duration::from_millis(limit.max_wall_clock_ms);
+      let timer_self_weakref = self.self_weakref.clone();
+      tokio::spawn(async move {
+        tokio::time::sleep(budget).await;
+        if let Some(executor) = timer_self_weakref.upgrade() {
+          executor.terminate_running_pid(this_program_pid, messages::ExitReason::WallClockTimeout);
+        }
+      });
+    }
+
     // For now we'll just spawn main off in a new tokio task
     let running_arc_rp_data = arc_rp_data.clone();
     let runner_t_self_weakref = self.self_weakref.clone();
+    let metered = !opts.uncapped_fuel;
     tokio::spawn(async move {
 
 
@@ -1316,81 +2295,67 @@ impl Executor {
         linker_lock.as_mut().unwrap().instantiate_async(
           &mut write_lock_store.as_mut().unwrap(),
           &write_lock_module.as_ref().unwrap()
-        ).await.map_err(map_loc_err!())
+        ).await
       };
 
-      match instance_res {
+      // Classify the outcome while we still hold the raw wasmtime error (trap kind, proc_exit code,
+      // backtrace); only then is it flattened into spawn_error for wait_for_pid_exit.
+      let (status, error) = match instance_res {
         Ok(instance) => {
           let store_rw = {
             let wg = running_arc_rp_data.write().await;
             wg.store.clone()
           };
           let mut write_lock_store = store_rw.write().await;
-          match instance.get_typed_func::<(), ()>(&mut write_lock_store.as_mut().unwrap(), "_start").map_err(map_loc_err!()) {
+          let store = write_lock_store.as_mut().unwrap();
+          match instance.get_typed_func::<(), ()>(&mut *store, "_start") {
             Ok(main_func) => {
-              match main_func.call_async(&mut write_lock_store.as_mut().unwrap(), ()).await.map_err(map_loc_err!()) {
-                Ok(result) => {
-                  // Set exit code
-                  if let Some(self_arc) = runner_t_self_weakref.upgrade() {
-                    self_arc.running_programs.remove(&this_program_pid);
-                    self_arc.pid_last_exit_code.insert(this_program_pid, 0);
-                    self_arc.pid_exit_signal.notify_waiters();
-                  }
-                  else {
-                    if crate::v_is_everything() {
-                      tracing::info!("runner_t_self_weakref.upgrade() was None! ({}:{})", file!(), line!());
-                    }
-                    // We can't remove the PID and we can't notify anyone. This is bad, TODO add resiliancy or something.
-                  }
-                }
-                Err(e) => {
-                  tracing::info!("{}", e);
-                  {
-                    *running_arc_rp_data.write().await.spawn_error.write().await = Some(e.into());
-                  }
-                  if let Some(self_arc) = runner_t_self_weakref.upgrade() {
-                    self_arc.running_programs.remove(&this_program_pid);
-                    self_arc.pid_last_exit_code.insert(this_program_pid, 1);
-                    self_arc.pid_exit_signal.notify_waiters();
-                  }
-                }
-              }
-            }
-            Err(e) => {
-              tracing::info!("{}", e);
-              {
-                *running_arc_rp_data.write().await.spawn_error.write().await = Some(e.into());
-              }
-              if let Some(self_arc) = runner_t_self_weakref.upgrade() {
-                  self_arc.running_programs.remove(&this_program_pid);
-                  self_arc.pid_last_exit_code.insert(this_program_pid, 1);
-                  self_arc.pid_exit_signal.notify_waiters();
-                }
+              let call_res = tokio::select! {
+                res = main_func.call_async(&mut *store, ()) => res,
+                _ = kill_rx.wait_for(Option::is_some) => Err(wasmtime::Error::msg("stopped by the host")),
+              };
+              let fuel_consumed = if metered {
+                store.get_fuel().ok().map(|left| fuel.saturating_sub(left))
+              } else {
+                None
+              };
+              let killed = kill_rx.borrow().clone();
+              ExitStatus::from_call_result(call_res, program_is_trusted, fuel_consumed, killed)
             }
+            Err(e) => (ExitStatus::start_failed(&e), Some(e)),
           }
         }
-        Err(e) => {
-          tracing::info!("{}", e);
-          {
-            *running_arc_rp_data.write().await.spawn_error.write().await = Some(e.into());
-          }
-          if let Some(self_arc) = runner_t_self_weakref.upgrade() {
-            self_arc.running_programs.remove(&this_program_pid);
-            self_arc.pid_last_exit_code.insert(this_program_pid, 1);
-            self_arc.pid_exit_signal.notify_waiters();
-          }
-        }
+        Err(e) => (ExitStatus::start_failed(&e), Some(e)),
+      };
+
+      if let Some(e) = error {
+        tracing::info!("{}", e);
+        *running_arc_rp_data.write().await.spawn_error.write().await = Some(e.into());
+      }
+      if let Some(self_arc) = runner_t_self_weakref.upgrade() {
+        self_arc.record_exit(this_program_pid, status);
+      }
+      else if crate::v_is_everything() {
+        // We can't remove the PID and we can't notify anyone. This is bad, TODO add resiliancy or something.
+        tracing::info!("runner_t_self_weakref.upgrade() was None! ({}:{})", file!(), line!());
       }
 
     });
 
-    self.running_programs.insert(this_program_pid, arc_rp_data);
-
-    self.running_programs_insert_signal.notify_waiters();
-
     Ok(this_program_pid)
   }
 
+  /// Forget a finished program and wake everyone waiting on an exit. Its status stays readable in
+  /// `pid_last_exit_status` for [`Self::wait_for_pid_exit`] / [`Self::wait_for_pid_exit_status`].
+  fn record_exit(&self, pid: u64, status: ExitStatus) {
+    self.running_programs.remove(&pid);
+    self.stdin_pipes.remove(&pid);
+    self.tty_inputs.remove(&pid);
+    self.kill_switches.remove(&pid);
+    self.pid_last_exit_status.insert(pid, status);
+    self.pid_exit_signal.notify_waiters();
+  }
+
   pub async fn wait_for_pid_exit(&self, pid: u64) -> DynResult<u32> {
     loop {
       let pid_exit_notified = self.pid_exit_signal.notified();
@@ -1410,11 +2375,40 @@ impl Executor {
 
       pid_exit_notified.await;
     }
-    Ok( self.pid_last_exit_code.get(&pid).map(|r| *r.value() ).unwrap_or(0) )
+    Ok( self.pid_last_exit_status.get(&pid).map(|r| r.value().exit_code ).unwrap_or(0) )
+  }
+
+  /// Like [`Self::wait_for_pid_exit`], but returns the full [`ExitStatus`] (reason, fuel, backtrace)
+  /// instead of surfacing start-up failures as an `Err` - they are an [`messages::ExitReason`] here.
+  pub async fn wait_for_pid_exit_status(&self, pid: u64) -> ExitStatus {
+    loop {
+      let pid_exit_notified = self.pid_exit_signal.notified();
+      if !self.running_programs.contains_key(&pid) {
+        break;
+      }
+      pid_exit_notified.await;
+    }
+    self.pid_last_exit_status.get(&pid).map(|r| r.value().clone()).unwrap_or_default()
   }
 
 }
 
+/// Fuel a running program burns between yields back to the runtime (see `fuel_async_yield_interval`).
+const FUEL_YIELD_INTERVAL: u64 = 100_000;
+
+/// Fuel (roughly instructions) a metered program starts with when its `[limits]` don't say.
+const INITIAL_FUEL: u64 = 128_000;
+
+/// Most wasm frames kept in an [`ExitStatus`] backtrace; the innermost ones locate a trap.
+const BACKTRACE_MAX_FRAMES: usize = 8;
+
+/// In-memory buffer between a stdin pipe's pump task and the guest: how far the pump runs ahead of a
+/// slow reader.
+const STDIN_PIPE_BYTES: usize = 64 * 1024;
+
+/// Chunks a stdin pipe queues in front of its pump; more are refused until the guest catches up.
+const STDIN_QUEUE_CHUNKS: usize = 32;
+
 /// Copy `src` (clamped to `cap` bytes) into a running program's linear memory at `ptr`, returning
 /// the number of bytes written. Shared by the `host::hostname` / `host::peer_report` imports. Traps
 /// if the module has no `memory` export or the destination range is out of bounds.
@@ -1434,6 +2428,53 @@ fn write_guest_bytes(caller: &mut wasmtime::Caller<'_, RPStoreData>, ptr: i32, c
   Ok(n as i32)
 }
 
+/// Sign `payload` (a CBOR list or map) as a SignedFabricMessage (or, `wants_ack`, a
+/// ReliableFabricMessage) from this node's identity under a fresh random id, for `host::messages_send`
+/// and `messages_send_reliable`. Returns the id, the message and its serde_bare bytes, or the
+/// import's error code: -1 no identity key, -2 payload not a list/map, -3 serialization failed.
+fn sign_fabric_message(d: &RPStoreData, payload: Vec<u8>, wants_ack: bool) -> Result<([u8; 16], messages::NetworkMessage, Vec<u8>), i32> {
+  // Enforce "no bare strings": the top-level CBOR item must be an array (major 4) or map (major 5).
+  // Anything else (incl. text/byte strings and integers) is rejected.
+  match payload.first().map(|b| b >> 5) {
+    Some(4) | Some(5) => {}
+    _ => return Err(-2),
+  }
+  let (Some(source), Some(signing_key)) = (&d.identity_data, &d.signing_key) else { return Err(-1) };
+  let mut id = [0u8; 16];
+  { use rand::RngCore; rand::rngs::OsRng.fill_bytes(&mut id); }
+  let signature = config::IdentityData::sign_payload(signing_key, &id, &messages::fabric_message_payload(&payload, wants_ack)).to_bytes().to_vec();
+  let (source, id_bytes) = (source.clone(), id.to_vec());
+  let msg = if wants_ack {
+    messages::NetworkMessage::ReliableFabricMessage { source, id: id_bytes, cbor_data: payload, signature }
+  } else {
+    messages::NetworkMessage::SignedFabricMessage { source, id: id_bytes, cbor_data: payload, signature }
+  };
+  let bytes = serde_bare::to_vec(&msg).map_err(|_| -3)?;
+  Ok((id, msg, bytes))
+}
+
+/// Sign `payload` (a CBOR list or map) as a RoomMessage from this node's identity, said in `room` or,
+/// when `to` isn't empty, sent to that identity alone, for `host::messages_send_to` and
+/// (`wants_ack`) `messages_send_to_reliable`. Records it in this node's own store straight away, so
+/// the sender sees what it said even where multicast doesn't loop back. Returns the id and the
+/// message's serde_bare bytes, or the import's error code: -1 no identity key, -2 payload not a
+/// list/map, -3 serialization failed, -4 a `to` that isn't a valid identity key.
+fn send_room_message(d: &RPStoreData, room: &str, to: &[u8], payload: Vec<u8>, wants_ack: bool) -> Result<(Vec<u8>, Vec<u8>), i32> {
+  match payload.first().map(|b| b >> 5) {
+    Some(4) | Some(5) => {}
+    _ => return Err(-2),
+  }
+  let (Some(source), Some(signing_key)) = (&d.identity_data, &d.signing_key) else { return Err(-1) };
+  let msg = messages::room_message(source, signing_key, room, Some(to).filter(|t| !t.is_empty()), payload, wants_ack)
+    .map_err(|_| if to.is_empty() { -3 } else { -4 })?;
+  let bytes = serde_bare::to_vec(&msg).map_err(|_| -3)?;
+  if let Ok(mut s) = d.messages.lock() {
+    store_signed_message(&mut s, &msg, sys_utils::epoch_seconds_now_utc0(), Some(signing_key));
+  }
+  let messages::NetworkMessage::RoomMessage { id, .. } = msg else { return Err(-3) };
+  Ok((id, bytes))
+}
+
 /// Read `len` bytes from a running program's linear memory at `ptr`. Shared by the `host::return_map`
 /// / `host::set_forward_uuid` imports. Traps if the module has no `memory` export or the range is out
 /// of bounds.
@@ -1465,8 +2506,11 @@ fn encode_messages_cbor(msgs: &[StoredMessage], cap: usize) -> Vec<u8> {
         (Value::Integer(message_keys::PUBKEY), Value::Bytes(m.from_pubkey.clone())),
         (Value::Integer(message_keys::EPOCH_S), Value::Integer(m.epoch_s as i128)),
         (Value::Integer(message_keys::TEXT), Value::Bytes(m.text.clone())),
+        (Value::Integer(message_keys::ROOM), Value::Text(m.room.clone())),
       ]
       .into_iter()
+      .chain(m.to.clone().map(|to| (Value::Integer(message_keys::TO), Value::Bytes(to))))
+      .chain(Some(&m.id).filter(|id| !id.is_empty()).map(|id| (Value::Integer(message_keys::ID), Value::Bytes(id.clone()))))
       .collect(),
     )
   };
@@ -1511,6 +2555,30 @@ fn decode_replicate_args(bytes: &[u8]) -> (Vec<String>, Vec<(String, String)>) {
   (arg_list, arg_map)
 }
 
+/// The trusted-keys name for our own key: the keyfile's name.
+fn self_trust_name(keyfile: &std::path::Path) -> String {
+  keyfile.file_name().map(|fn_osstr| fn_osstr.to_string_lossy().to_string() ).unwrap_or_else(|| "SELF".to_string() )
+}
+
+/// `config`'s `[[trusted]]` keys, named by their position and the key's comment (`trusted[0] alice`),
+/// then the successors its `[state]`'s key rotations file hands their trust to (see
+/// [`key_rotation::in_effect`]). A key that doesn't parse is logged and left out.
+fn configured_trust(config: &config::Config) -> Vec<(String, ed25519_dalek::VerifyingKey)> {
+  let rotated = key_rotation::in_effect(config).into_iter().map(|r| (r.trust_name(), r.new));
+  config.trusted.iter().enumerate().filter_map(|(i, trusted)| {
+    match crypto_utils::public_key_to_ed25519_vk(&trusted.key) {
+      Ok(key) => {
+        let comment = trusted.key.split_whitespace().skip(2).collect::<Vec<_>>().join(" ");
+        Some((format!("trusted[{i}] {comment}").trim_end().to_string(), key))
+      }
+      Err(e) => {
+        tracing::warn!("Ignoring [[trusted]] key {:?}: {}", trusted.key, e);
+        None
+      }
+    }
+  }).chain(rotated).collect()
+}
+
 /// Lowercase hex encoding, used to key peers by their public key and to render keys for programs.
 // Shared with netmap + the daemon's security logs so identity hex is rendered identically everywhere.
 use crate::crypto_utils::to_hex;
@@ -12,6 +12,8 @@ pub struct WasiStdioSimpleForwarder {
   our_pid: u64,
   reply_to: Option<std::net::SocketAddr>,
   reply_from: Option<command::serve::UdpSocketSender>,
+  /// Frame writes as ProgramStderr instead of BasicInsecureProgramStdout (see [`Self::for_stderr`]).
+  is_stderr: bool,
 
   // Polled state
   current_encoded_msg: Option<Vec<u8>>,
@@ -24,6 +26,7 @@ impl std::fmt::Debug for WasiStdioSimpleForwarder {
       f.debug_struct("WasiStdioSimpleForwarder")
           .field("our_pid", &self.our_pid)
           .field("reply_to", &self.reply_to)
+          .field("is_stderr", &self.is_stderr)
           // reply_from does not impl Debug
           .finish()
   }
@@ -36,6 +39,7 @@ impl WasiStdioSimpleForwarder {
       our_pid: 0,
       reply_to: None,
       reply_from: None,
+      is_stderr: false,
       current_encoded_msg: None,
       current_encoded_sent: 0
     }
@@ -45,6 +49,7 @@ impl WasiStdioSimpleForwarder {
       our_pid: 0,
       reply_to: reply_to,
       reply_from: reply_from,
+      is_stderr: false,
       current_encoded_msg: None,
       current_encoded_sent: 0
     }
@@ -54,6 +59,7 @@ impl WasiStdioSimpleForwarder {
       our_pid: 0,
       reply_to: Some(reply_to),
       reply_from: Some(reply_from),
+      is_stderr: false,
       current_encoded_msg: None,
       current_encoded_sent: 0
     }
@@ -61,6 +67,13 @@ impl WasiStdioSimpleForwarder {
   pub fn set_pid(&mut self, pid: u64) {
     self.our_pid = pid;
   }
+  /// A copy of this forwarder (same destination and PID) that sends what it is given as
+  /// ProgramStderr, for wiring up the guest's stderr alongside its stdout.
+  pub fn for_stderr(&self) -> WasiStdioSimpleForwarder {
+    let mut fwd = self.clone();
+    fwd.is_stderr = true;
+    fwd
+  }
 }
 
 
@@ -72,9 +85,16 @@ impl tokio::io::AsyncWrite for WasiStdioSimpleForwarder {
   ) -> Poll<Result<usize, std::io::Error>> {
     if let (Some(reply_to), Some(reply_from)) = (self.reply_to, self.reply_from.clone()) {
       if self.current_encoded_msg.is_none() {
-        let msg = messages::NetworkMessage::BasicInsecureProgramStdout {
-          from_pid: self.our_pid,
-          stdout_data: buf.to_vec(),
+        let msg = if self.is_stderr {
+          messages::NetworkMessage::ProgramStderr {
+            from_pid: self.our_pid,
+            stderr_data: buf.to_vec(),
+          }
+        } else {
+          messages::NetworkMessage::BasicInsecureProgramStdout {
+            from_pid: self.our_pid,
+            stdout_data: buf.to_vec(),
+          }
         };
         match serde_bare::to_vec(&msg) {
           Ok(msg_encoded) => {
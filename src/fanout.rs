@@ -0,0 +1,383 @@
+//! Client-side bookkeeping for a fan-out run (`run --fabric`): one program broadcast to many
+//! executors, with replies from all of them arriving on several sockets at once.
+//!
+//! Replies only carry a per-node PID, so each executor first sends a signed
+//! [`messages::NetworkMessage::ProgramAccepted`] tying `(address, pid)` to its identity. [`FanOut`]
+//! verifies that binding, applies the responder policy (`--select`, `--max-responders`), prints or
+//! collects the selected nodes' output, and tells the receive loops when the run is complete.
+//! Replies that arrive before their node has identified itself are held until it does.
+
+use std::collections::HashSet;
+
+use crate::*;
+
+/// What a fan-out run waits for and which nodes it listens to (the `run --fabric` flags).
+#[derive(Debug, Clone, Default)]
+pub struct FanOutPolicy {
+  /// Fail the run unless at least this many selected nodes exited before the deadline.
+  pub min_responders: usize,
+  /// Count only the first N selected nodes, and finish as soon as all N have exited.
+  pub max_responders: Option<usize>,
+  /// Hard deadline for the whole run; `None` keeps the adaptive window (see [`DEFAULT_WINDOW`]).
+  pub timeout: Option<std::time::Duration>,
+  /// Which responders count; `None` = every node that identifies itself.
+  pub select: Option<args::ResponderSelect>,
+  /// Gather everything into one document printed at the end instead of streaming replies.
+  pub collect: Option<args::CollectFormat>,
+}
+
+/// Reply window without `--timeout`, and how long it stays open after the latest reply. Close to
+/// the original per-socket `run --fabric` window (2.4s, +1s per message), but shared by every socket
+/// so duplicate copies arriving over several interfaces don't stack up extra waiting.
+const DEFAULT_WINDOW: std::time::Duration = std::time::Duration::from_millis(2400);
+const WINDOW_EXTENSION: std::time::Duration = std::time::Duration::from_millis(1000);
+
+/// A verified executor identity, from its `ProgramAccepted`.
+#[derive(Debug, Clone)]
+struct Node {
+  name: String,
+  pubkey: Vec<u8>,
+  /// We trust its key (our `[[trusted]]` list or our own key).
+  we_trust: bool,
+  /// It trusts us (it ran the program under trusted limits).
+  trusts_us: bool,
+}
+
+/// One program instance on one executor, keyed by the address it replies from and its PID there.
+#[derive(Debug)]
+struct Responder {
+  addr: std::net::SocketAddr,
+  pid: u64,
+  node: Option<Node>,
+  /// `None` until the node identifies itself; then whether its replies count.
+  selected: Option<bool>,
+  /// Replies received before the node identified itself.
+  pending: Vec<messages::NetworkMessage>,
+  stdout: Vec<u8>,
+  stderr: Vec<u8>,
+  results: Vec<serde_cbor::Value>,
+  exit: Option<(u32, messages::ExitReason)>,
+}
+
+impl Responder {
+  /// Name used in streamed output: the node's signed name, else its address.
+  fn label(&self) -> String {
+    match &self.node {
+      Some(n) => format!("{}:{}", n.name, self.pid),
+      None => format!("{}:{}", self.addr, self.pid),
+    }
+  }
+
+  /// Key of this responder in a `--collect` document: the node's full hex public key, or its
+  /// address for a node that never identified itself (an older daemon).
+  fn doc_key(&self) -> String {
+    match &self.node {
+      Some(n) => crypto_utils::to_hex(&n.pubkey),
+      None => format!("addr:{}", self.addr),
+    }
+  }
+}
+
+#[derive(Debug)]
+struct State {
+  responders: Vec<Responder>,
+  selected_count: usize,
+  deadline: std::time::Instant,
+}
+
+/// Shared, thread-safe collector for one fan-out run. Every receive loop feeds it via
+/// [`FanOut::handle`] and polls [`FanOut::is_done`]; the caller ends with [`FanOut::finish`].
+#[derive(Debug)]
+pub struct FanOut {
+  request_uuid: [u8; 16],
+  policy: FanOutPolicy,
+  trusted: HashSet<Vec<u8>>,
+  state: std::sync::Mutex<State>,
+}
+
+impl FanOut {
+  /// `trusted` is the set of raw public keys we trust (for `--select trusted|untrusted`).
+  pub fn new(request_uuid: [u8; 16], policy: FanOutPolicy, trusted: HashSet<Vec<u8>>) -> FanOut {
+    let deadline = std::time::Instant::now() + policy.timeout.unwrap_or(DEFAULT_WINDOW);
+    FanOut {
+      request_uuid,
+      policy,
+      trusted,
+      state: std::sync::Mutex::new(State { responders: Vec::new(), selected_count: 0, deadline }),
+    }
+  }
+
+  /// Take in one reply received from `addr`.
+  pub fn handle(&self, addr: std::net::SocketAddr, msg: messages::NetworkMessage) {
+    use messages::NetworkMessage as M;
+    let Ok(mut state) = self.state.lock() else { return };
+    if self.policy.timeout.is_none() {
+      state.deadline = state.deadline.max(std::time::Instant::now() + WINDOW_EXTENSION);
+    }
+    match msg {
+      M::ProgramAccepted { request_uuid, from_pid, node, trusts_caller, signature } => {
+        if request_uuid != self.request_uuid {
+          return;
+        }
+        let payload = messages::program_accepted_payload(from_pid, trusts_caller);
+        if let Err(e) = node.check_self_signature().and_then(|_| node.verify_payload(&request_uuid, &payload, &signature)) {
+          tracing::warn!("[ run ] Ignoring ProgramAccepted from {} with a bad signature: {}", addr, e);
+          return;
+        }
+        let node = Node {
+          name: node.human_name.clone(),
+          we_trust: self.trusted.contains(&node.encoded_public_key),
+          pubkey: node.encoded_public_key,
+          trusts_us: trusts_caller,
+        };
+        self.accept(&mut state, addr, from_pid, node);
+      }
+      M::BasicInsecureProgramStdout { from_pid, .. }
+      | M::ProgramStderr { from_pid, .. }
+      | M::BasicInsecureProgramExit { from_pid, .. }
+      | M::ProgramExit { from_pid, .. } => {
+        self.route(&mut state, addr, from_pid, msg);
+      }
+      M::BasicReturnMap { from_pid, request_uuid, .. } | M::BasicReturnList { from_pid, request_uuid, .. } => {
+        if request_uuid == self.request_uuid {
+          self.route(&mut state, addr, from_pid, msg);
+        }
+      }
+      unused => {
+        tracing::warn!("Got unexpected network message: {:?}", unused);
+      }
+    }
+  }
+
+  /// True once the run is over: the deadline passed, or `--max-responders` nodes were selected and
+  /// all of them have exited.
+  pub fn is_done(&self) -> bool {
+    let Ok(state) = self.state.lock() else { return true };
+    if std::time::Instant::now() >= state.deadline {
+      return true;
+    }
+    match self.policy.max_responders {
+      Some(max) => {
+        state.selected_count >= max
+          && state.responders.iter().filter(|r| r.selected == Some(true)).all(|r| r.exit.is_some())
+      }
+      None => false,
+    }
+  }
+
+  /// Wrap up: release replies from nodes that never identified themselves (when no policy needs an
+  /// identity), print the `--collect` document, and enforce `--min-responders`. Returns the run's
+  /// exit code - the first non-zero code among selected nodes, else 0.
+  pub fn finish(&self) -> DynResult<u32> {
+    let Ok(mut state) = self.state.lock() else { return Err("fan-out state poisoned".into()) };
+    if self.policy.select.is_none() && self.policy.max_responders.is_none() {
+      for r in state.responders.iter_mut().filter(|r| r.selected.is_none()) {
+        r.selected = Some(true);
+        for msg in std::mem::take(&mut r.pending) {
+          self.apply(r, msg);
+        }
+      }
+    }
+
+    let selected: Vec<&Responder> = state.responders.iter().filter(|r| r.selected == Some(true)).collect();
+    if let Some(format) = self.policy.collect {
+      write_collected(&selected, format)?;
+    }
+
+    let completed = selected.iter().filter(|r| r.exit.is_some()).count();
+    if completed < self.policy.min_responders {
+      return Err(format!("only {} of the required {} responders completed", completed, self.policy.min_responders).into());
+    }
+    Ok(selected.iter().filter_map(|r| r.exit.as_ref().map(|(code, _)| *code)).find(|c| *c != 0).unwrap_or(0))
+  }
+
+  /// Record a verified identity for `(addr, pid)`, decide whether it counts, and release whatever it
+  /// sent before identifying itself.
+  fn accept(&self, state: &mut State, addr: std::net::SocketAddr, pid: u64, node: Node) {
+    let idx = responder_index(state, addr, pid);
+    if state.responders[idx].node.is_some() {
+      return; // a repeated ProgramAccepted
+    }
+    // A node reached over several interfaces/groups runs one copy per delivery; only its first
+    // accepted copy counts, so each identity appears once.
+    let duplicate = state.responders.iter().any(|r| r.selected == Some(true) && r.node.as_ref().is_some_and(|n| n.pubkey == node.pubkey));
+    let wanted = !duplicate
+      && self.matches_select(&node)
+      && self.policy.max_responders.is_none_or(|max| state.selected_count < max);
+    if wanted {
+      state.selected_count += 1;
+    }
+    let r = &mut state.responders[idx];
+    r.node = Some(node);
+    r.selected = Some(wanted);
+    let pending = std::mem::take(&mut r.pending);
+    if wanted {
+      for msg in pending {
+        self.apply(r, msg);
+      }
+    }
+  }
+
+  fn matches_select(&self, node: &Node) -> bool {
+    match &self.policy.select {
+      None => true,
+      Some(args::ResponderSelect::Trusted) => node.we_trust,
+      Some(args::ResponderSelect::Untrusted) => !node.we_trust,
+      Some(args::ResponderSelect::Pubkey(prefix)) => crypto_utils::to_hex(&node.pubkey).starts_with(prefix.as_str()),
+    }
+  }
+
+  /// Apply a reply to its responder now, hold it until the node identifies itself, or drop it if the
+  /// node doesn't count.
+  fn route(&self, state: &mut State, addr: std::net::SocketAddr, pid: u64, msg: messages::NetworkMessage) {
+    let idx = responder_index(state, addr, pid);
+    let r = &mut state.responders[idx];
+    match r.selected {
+      Some(true) => self.apply(r, msg),
+      Some(false) => {}
+      None => r.pending.push(msg),
+    }
+  }
+
+  /// Take in a reply from a selected responder: print it (streaming) or keep it for the document.
+  fn apply(&self, r: &mut Responder, msg: messages::NetworkMessage) {
+    use messages::NetworkMessage as M;
+    let streaming = self.policy.collect.is_none();
+    match msg {
+      M::BasicInsecureProgramStdout { stdout_data, .. } => {
+        if streaming {
+          match str::from_utf8(&stdout_data) {
+            Ok(text) => tracing::warn!("[{}] {}", r.label(), text),
+            Err(_) => tracing::warn!("[{}:binary] {:?}", r.label(), stdout_data),
+          }
+        }
+        r.stdout.extend_from_slice(&stdout_data);
+      }
+      M::ProgramStderr { stderr_data, .. } => {
+        if streaming {
+          command::run::write_remote_stderr(&stderr_data);
+        }
+        r.stderr.extend_from_slice(&stderr_data);
+      }
+      M::BasicInsecureProgramExit { from_pid, exit_code } => {
+        if streaming {
+          tracing::warn!("[{}] pid {} exited with code {}", r.label(), from_pid, exit_code);
+        }
+        r.exit = Some((exit_code, messages::ExitReason::Exited));
+      }
+      M::ProgramExit { from_pid, exit_code, reason, fuel_consumed, backtrace } => {
+        if streaming {
+          command::run::print_program_exit(from_pid, exit_code, &reason, fuel_consumed, &backtrace);
+        }
+        r.exit = Some((exit_code, reason));
+      }
+      M::BasicReturnMap { cbor_data, .. } | M::BasicReturnList { cbor_data, .. } => {
+        match serde_cbor::from_slice::<serde_cbor::Value>(&cbor_data) {
+          Ok(value) => {
+            if streaming {
+              tracing::warn!("[{}] returned {}", r.label(), cbor_to_json(&value));
+            }
+            r.results.push(value);
+          }
+          Err(e) => tracing::warn!("[{}] returned undecodable CBOR: {}", r.label(), e),
+        }
+      }
+      _ => {}
+    }
+  }
+}
+
+fn responder_index(state: &mut State, addr: std::net::SocketAddr, pid: u64) -> usize {
+  match state.responders.iter().position(|r| r.addr == addr && r.pid == pid) {
+    Some(idx) => idx,
+    None => {
+      state.responders.push(Responder {
+        addr,
+        pid,
+        node: None,
+        selected: None,
+        pending: Vec::new(),
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+        results: Vec::new(),
+        exit: None,
+      });
+      state.responders.len() - 1
+    }
+  }
+}
+
+/// Build the `--collect` document (a map keyed by node identity) and write it to stdout.
+fn write_collected(selected: &[&Responder], format: args::CollectFormat) -> DynResult<()> {
+  use serde_cbor::Value;
+  use std::io::Write;
+  let text = |k: &str| Value::Text(k.to_string());
+  let mut doc = std::collections::BTreeMap::new();
+  for r in selected {
+    let mut entry = std::collections::BTreeMap::new();
+    if let Some(n) = &r.node {
+      entry.insert(text("name"), text(&n.name));
+      entry.insert(text("short_id"), text(&crypto_utils::short_id(&n.pubkey)));
+      entry.insert(text("trusted"), Value::Bool(n.we_trust));
+      entry.insert(text("trusts_us"), Value::Bool(n.trusts_us));
+    }
+    entry.insert(text("address"), text(&r.addr.to_string()));
+    entry.insert(text("pid"), Value::Integer(r.pid as i128));
+    let (code, reason) = match &r.exit {
+      Some((code, reason)) => (Value::Integer(*code as i128), text(&reason.describe())),
+      None => (Value::Null, Value::Null),
+    };
+    entry.insert(text("exit_code"), code);
+    entry.insert(text("exit_reason"), reason);
+    entry.insert(text("stdout"), text(&String::from_utf8_lossy(&r.stdout)));
+    entry.insert(text("stderr"), text(&String::from_utf8_lossy(&r.stderr)));
+    entry.insert(text("results"), Value::Array(r.results.clone()));
+    doc.insert(text(&r.doc_key()), Value::Map(entry));
+  }
+  let doc = Value::Map(doc);
+  let mut out = std::io::stdout().lock();
+  match format {
+    args::CollectFormat::Json => {
+      let json = serde_json::to_string_pretty(&cbor_to_json(&doc)).map_err(map_loc_err!())?;
+      writeln!(out, "{}", json).map_err(map_loc_err!())?;
+    }
+    args::CollectFormat::Cbor => {
+      out.write_all(&serde_cbor::to_vec(&doc).map_err(map_loc_err!())?).map_err(map_loc_err!())?;
+    }
+  }
+  out.flush().map_err(map_loc_err!())?;
+  Ok(())
+}
+
+/// Convert a CBOR value to JSON for display. Byte strings become lowercase hex, non-text map keys
+/// their JSON rendering, and integers outside the i64/u64 range decimal strings.
+pub fn cbor_to_json(value: &serde_cbor::Value) -> serde_json::Value {
+  use serde_cbor::Value as C;
+  use serde_json::Value as J;
+  match value {
+    C::Null => J::Null,
+    C::Bool(b) => J::Bool(*b),
+    C::Integer(i) => {
+      if let Ok(v) = i64::try_from(*i) {
+        J::from(v)
+      } else if let Ok(v) = u64::try_from(*i) {
+        J::from(v)
+      } else {
+        J::String(i.to_string())
+      }
+    }
+    C::Float(f) => serde_json::Number::from_f64(*f).map(J::Number).unwrap_or(J::Null),
+    C::Bytes(b) => J::String(crypto_utils::to_hex(b)),
+    C::Text(t) => J::String(t.clone()),
+    C::Array(items) => J::Array(items.iter().map(cbor_to_json).collect()),
+    C::Map(entries) => J::Object(entries.iter().map(|(k, v)| {
+      let key = match k {
+        C::Text(t) => t.clone(),
+        other => cbor_to_json(other).to_string(),
+      };
+      (key, cbor_to_json(v))
+    }).collect()),
+    C::Tag(_, inner) => cbor_to_json(inner),
+    _ => J::Null,
+  }
+}
@@ -0,0 +1,71 @@
+//! What a node checks before answering a [`messages::NetworkMessage::HistoryRequest`].
+//!
+//! One small request can pull up to a few hundred stored messages back in large datagrams, so an
+//! answer is only sent for a request that is fresh (its signed `epoch_s` within
+//! [`REQUEST_WINDOW_HALF_S`] of our clock), that we haven't answered before (its `request_uuid`
+//! isn't one seen within the window), and whose source address hasn't already asked
+//! [`MAX_PER_SOURCE`] times in the last [`SOURCE_WINDOW`]. A captured request can't be replayed at
+//! us, and neither a replay nor a spoofed source address makes us flood a third party.
+//!
+//! Times are passed in rather than read, so the policy can be tested without waiting on it.
+
+use std::collections::{HashMap, VecDeque};
+
+/// How far (seconds) a request's `epoch_s` may be from our clock, either way.
+pub const REQUEST_WINDOW_HALF_S: u64 = 30;
+
+/// The most requests answered for one source address within [`SOURCE_WINDOW`].
+pub const MAX_PER_SOURCE: usize = 4;
+pub const SOURCE_WINDOW: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Why a request goes unanswered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+  /// Its `epoch_s` is outside the window.
+  Stale,
+  /// Its `request_uuid` was answered already.
+  Replayed,
+  /// Its source address has asked too often lately.
+  RateLimited,
+}
+
+/// The request uuids answered within the window and each source's recent requests.
+#[derive(Debug, Default)]
+pub struct HistoryGuard {
+  /// request_uuid -> its `epoch_s`; forgotten once that falls out of the window (the request would
+  /// be refused as stale by then anyway).
+  seen: HashMap<[u8; 16], u64>,
+  /// Source address -> when it was answered, oldest first.
+  by_source: HashMap<std::net::IpAddr, VecDeque<std::time::Instant>>,
+}
+
+impl HistoryGuard {
+  pub fn new() -> HistoryGuard {
+    HistoryGuard::default()
+  }
+
+  /// Whether to answer the (signature-checked) request `request_uuid` made at `epoch_s` from `from`,
+  /// at `now` / `now_epoch_s`. An admitted request is remembered, so the same one is refused next time.
+  pub fn admit(&mut self, from: std::net::IpAddr, request_uuid: [u8; 16], epoch_s: u64, now_epoch_s: u64, now: std::time::Instant) -> Result<(), Refusal> {
+    self.seen.retain(|_, at| at.abs_diff(now_epoch_s) <= REQUEST_WINDOW_HALF_S);
+    self.by_source.retain(|_, times| {
+      while times.front().is_some_and(|t| now.saturating_duration_since(*t) >= SOURCE_WINDOW) {
+        times.pop_front();
+      }
+      !times.is_empty()
+    });
+    if epoch_s.abs_diff(now_epoch_s) > REQUEST_WINDOW_HALF_S {
+      return Err(Refusal::Stale);
+    }
+    if self.seen.contains_key(&request_uuid) {
+      return Err(Refusal::Replayed);
+    }
+    let times = self.by_source.entry(from).or_default();
+    if times.len() >= MAX_PER_SOURCE {
+      return Err(Refusal::RateLimited);
+    }
+    times.push_back(now);
+    self.seen.insert(request_uuid, epoch_s);
+    Ok(())
+  }
+}
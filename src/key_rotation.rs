@@ -0,0 +1,154 @@
+//! Handing trust from an identity key to its successor.
+//!
+//! `weverywhere identity rotate` makes a new key and broadcasts a [`messages::NetworkMessage::KeyRotation`]
+//! naming it, signed by both the old and the new key. A daemon that trusts the old key when the
+//! statement arrives records it in `<[state].dir>/key_rotations` and trusts the new key from then on,
+//! across restarts and config reloads, for as long as the old key is trusted: a successor is only
+//! taken on from a `[[trusted]]` key or from another successor so taken on (see [`chained`]), so
+//! dropping a key from the config (or `[[trusted_remove]]`-ing it) drops its whole line of successors.
+//! List the new key in `[[trusted]]` to keep it past that. The file is one
+//! `<epoch_s> <old ssh-ed25519 key> <new ssh-ed25519 key>` line per rotation, safe to edit by hand:
+//! deleting a line stops trusting that successor.
+
+use crate::*;
+
+pub const FILE_NAME: &str = "key_rotations";
+
+/// Where the rotations file lives for this config.
+pub fn path(state: &config::StateConfig) -> std::path::PathBuf {
+  state.dir.join(FILE_NAME)
+}
+
+/// One accepted rotation: `new` succeeds `old`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rotation {
+  pub old: ed25519_dalek::VerifyingKey,
+  pub new: ed25519_dalek::VerifyingKey,
+  pub epoch_s: u64,
+}
+
+impl Rotation {
+  pub fn to_line(&self) -> String {
+    format!("{} {} {}", self.epoch_s, crypto_utils::format_verifying_key(&self.old), crypto_utils::format_verifying_key(&self.new))
+  }
+
+  /// Parse one `<epoch_s> ssh-ed25519 <base64> ssh-ed25519 <base64>` line; `None` for blanks,
+  /// comments and lines that don't parse.
+  pub fn parse_line(line: &str) -> Option<Rotation> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      return None;
+    }
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let [epoch_s, old_type, old, new_type, new] = fields.as_slice() else {
+      return None;
+    };
+    Some(Rotation {
+      old: crypto_utils::public_key_to_ed25519_vk(&format!("{old_type} {old}")).ok()?,
+      new: crypto_utils::public_key_to_ed25519_vk(&format!("{new_type} {new}")).ok()?,
+      epoch_s: epoch_s.parse().ok()?,
+    })
+  }
+
+  /// The trusted-keys name the successor is held under.
+  pub fn trust_name(&self) -> String {
+    format!("rotated {} (from {})", crypto_utils::short_id(self.new.as_bytes()), crypto_utils::short_id(self.old.as_bytes()))
+  }
+}
+
+/// Read the rotations file at `path`. A missing file holds no rotations; lines that don't parse are
+/// skipped with a warning. Blocking, so the executor can fold it into its trust set wherever it
+/// rebuilds that set; the file stays small.
+pub fn load(path: &std::path::Path) -> Vec<Rotation> {
+  let text = match std::fs::read_to_string(path) {
+    Ok(text) => text,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+    Err(e) => {
+      tracing::warn!("[ key_rotations ] could not read {}: {}", path.display(), e);
+      return Vec::new();
+    }
+  };
+  let mut rotations = Vec::new();
+  for (n, line) in text.lines().enumerate() {
+    match Rotation::parse_line(line) {
+      Some(r) => rotations.push(r),
+      None if line.trim().is_empty() || line.trim_start().starts_with('#') => {}
+      None => tracing::warn!("[ key_rotations ] {}:{}: not an `<epoch_s> <old key> <new key>` line; ignoring it", path.display(), n + 1),
+    }
+  }
+  rotations
+}
+
+/// The `rotations` that hand on trust from one of `trusted`: those whose old key is trusted, then
+/// those whose old key is a successor so taken on, and so on. A rotation from any other key carries
+/// nothing, however it was recorded.
+pub fn chained(trusted: &[ed25519_dalek::VerifyingKey], rotations: Vec<Rotation>) -> Vec<Rotation> {
+  let mut keys = trusted.to_vec();
+  let (mut chained, mut pending) = (Vec::new(), rotations);
+  loop {
+    let (taken, rest): (Vec<Rotation>, Vec<Rotation>) = pending.into_iter().partition(|r| keys.contains(&r.old));
+    if taken.is_empty() {
+      return chained;
+    }
+    keys.extend(taken.iter().map(|r| r.new));
+    chained.extend(taken);
+    pending = rest;
+  }
+}
+
+/// The recorded rotations `config` takes on: those [`chained`] from its `[[trusted]]` keys.
+pub fn in_effect(config: &config::Config) -> Vec<Rotation> {
+  let trusted: Vec<_> = config.trusted.iter().filter_map(|t| crypto_utils::public_key_to_ed25519_vk(&t.key).ok()).collect();
+  chained(&trusted, load(&path(&config.state)))
+}
+
+/// Append `rotation` to the rotations file, creating the file (and directory) if needed.
+pub async fn record(path: &std::path::Path, rotation: &Rotation) -> DynResult<()> {
+  use tokio::io::AsyncWriteExt;
+  if let Some(dir) = path.parent() {
+    tokio::fs::create_dir_all(dir).await.map_err(map_loc_err!())?;
+  }
+  let mut f = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await.map_err(map_loc_err!())?;
+  f.write_all(format!("{}\n", rotation.to_line()).as_bytes()).await.map_err(map_loc_err!())?;
+  // tokio hands writes to a background thread; wait for this one so a reload right after sees it.
+  f.flush().await.map_err(map_loc_err!())?;
+  Ok(())
+}
+
+/// The [`messages::NetworkMessage::KeyRotation`] handing `old_key`'s trust to `new_key`, both under
+/// `human_name`.
+pub fn statement(human_name: &str, old_key: &ed25519_dalek::SigningKey, new_key: &ed25519_dalek::SigningKey) -> messages::NetworkMessage {
+  let epoch_s = sys_utils::epoch_seconds_now_utc0();
+  let payload = messages::key_rotation_payload(old_key.verifying_key().as_bytes(), new_key.verifying_key().as_bytes());
+  messages::NetworkMessage::KeyRotation {
+    old: config::IdentityData::from_signing_key(human_name, old_key),
+    new: config::IdentityData::from_signing_key(human_name, new_key),
+    epoch_s,
+    old_signature: config::IdentityData::sign_payload(old_key, &epoch_s.to_le_bytes(), &payload).to_bytes().to_vec(),
+    new_signature: config::IdentityData::sign_payload(new_key, &epoch_s.to_le_bytes(), &payload).to_bytes().to_vec(),
+  }
+}
+
+/// Check a [`messages::NetworkMessage::KeyRotation`]: both identities' self-signatures, both
+/// signatures over the rotation, and that it wasn't made in the future. Whether the old key is one to
+/// take a successor from is up to the receiver.
+pub fn verify(msg: &messages::NetworkMessage, now_epoch_s: u64) -> DynResult<Rotation> {
+  let messages::NetworkMessage::KeyRotation { old, new, epoch_s, old_signature, new_signature } = msg else {
+    return Err("not a KeyRotation".into());
+  };
+  old.check_self_signature()?;
+  new.check_self_signature()?;
+  if old.encoded_public_key == new.encoded_public_key {
+    return Err("the old and new keys are the same".into());
+  }
+  let payload = messages::key_rotation_payload(&old.encoded_public_key, &new.encoded_public_key);
+  old.verify_payload(&epoch_s.to_le_bytes(), &payload, old_signature).map_err(|e| format!("old key's signature: {e}"))?;
+  new.verify_payload(&epoch_s.to_le_bytes(), &payload, new_signature).map_err(|e| format!("new key's signature: {e}"))?;
+  if *epoch_s > now_epoch_s && !discovery::attestation_time_ok(*epoch_s, now_epoch_s) {
+    return Err(format!("made in the future ({epoch_s}, now {now_epoch_s})").into());
+  }
+  let key = |bytes: &[u8]| -> DynResult<ed25519_dalek::VerifyingKey> {
+    Ok(ed25519_dalek::VerifyingKey::from_bytes(bytes.try_into().map_err(map_loc_err!())?)?)
+  };
+  Ok(Rotation { old: key(&old.encoded_public_key)?, new: key(&new.encoded_public_key)?, epoch_s: *epoch_s })
+}
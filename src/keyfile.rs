@@ -0,0 +1,113 @@
+//! Reading and writing the `[identity] keyfile`.
+//!
+//! A keyfile is a PKCS#8 PEM, either plain (`PRIVATE KEY`) or encrypted under a passphrase
+//! (`ENCRYPTED PRIVATE KEY`, see [`crypto_utils::encrypt_private_key_pem`]). The passphrase of an
+//! encrypted one is taken from the first of these that is set:
+//!
+//! 1. `[identity] passphrase_file`,
+//! 2. the systemd credential [`SYSTEMD_CREDENTIAL`] (`LoadCredential=` / `LoadCredentialEncrypted=`
+//!    in the unit, found under `$CREDENTIALS_DIRECTORY`),
+//! 3. the output of `[identity] passphrase_command` (e.g. a lookup in the OS keyring),
+//! 4. a prompt, when stdin is a terminal.
+//!
+//! A keyfile is unlocked once per process; later reads of the same file get the cached key. Reading
+//! a keyfile that its group or everyone else can read logs a warning.
+
+use crate::*;
+
+/// Name of the systemd credential holding the keyfile passphrase.
+pub const SYSTEMD_CREDENTIAL: &str = "weverywhere-key-passphrase";
+
+/// An unlocked keyfile.
+#[derive(Clone)]
+pub struct Unlocked {
+  pub key: ed25519_dalek::SigningKey,
+  /// The passphrase it was encrypted under; `None` for a plain keyfile.
+  pub passphrase: Option<String>,
+}
+
+/// Keyfiles already unlocked, with the contents they were unlocked from so an edited or replaced
+/// file is read afresh.
+static UNLOCKED: std::sync::Mutex<std::collections::BTreeMap<std::path::PathBuf, (String, Unlocked)>> = std::sync::Mutex::new(std::collections::BTreeMap::new());
+
+/// Read `identity`'s keyfile, decrypting it if it is encrypted.
+pub async fn unlock(identity: &config::IdentityConfig) -> DynResult<Unlocked> {
+  let path = &identity.keyfile;
+  let contents = tokio::fs::read_to_string(path).await.map_err(map_loc_err!())?;
+  if let Some((seen, unlocked)) = UNLOCKED.lock().unwrap_or_else(|e| e.into_inner()).get(path)
+    && *seen == contents {
+    return Ok(unlocked.clone());
+  }
+  if let Some(problem) = crypto_utils::keyfile_permission_problem(path) {
+    tracing::warn!("!!! [identity] keyfile {} - anyone who can read it can act as this node !!!", problem);
+  }
+
+  let unlocked = if crypto_utils::is_encrypted_private_key_pem(&contents) {
+    let passphrase = match configured_passphrase(identity).await? {
+      Some(passphrase) => passphrase,
+      None => sys_utils::ask_user_secret(format!("Passphrase for {}: ", path.display())).await
+        .ok_or_else(|| format!("{} is encrypted and no passphrase is available; set [identity] passphrase_file or passphrase_command, provide the systemd credential {SYSTEMD_CREDENTIAL}, or run from a terminal", path.display()))?,
+    };
+    let key = crypto_utils::decrypt_private_key_pem(&contents, &passphrase).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Unlocked { key, passphrase: Some(passphrase) }
+  }
+  else {
+    Unlocked { key: crypto_utils::private_key_from_pem(&contents)?, passphrase: None }
+  };
+  UNLOCKED.lock().unwrap_or_else(|e| e.into_inner()).insert(path.clone(), (contents, unlocked.clone()));
+  Ok(unlocked)
+}
+
+/// The keyfile passphrase from the non-interactive sources (file, systemd credential, command), if
+/// any is set up.
+pub async fn configured_passphrase(identity: &config::IdentityConfig) -> DynResult<Option<String>> {
+  if let Some(file) = identity.passphrase_file.as_ref() {
+    return read_passphrase_file(file).await.map(Some);
+  }
+  if let Some(dir) = std::env::var_os("CREDENTIALS_DIRECTORY") {
+    let credential = std::path::Path::new(&dir).join(SYSTEMD_CREDENTIAL);
+    if tokio::fs::metadata(&credential).await.is_ok() {
+      return read_passphrase_file(&credential).await.map(Some);
+    }
+  }
+  if let Some((program, args)) = identity.passphrase_command.split_first() {
+    let out = tokio::process::Command::new(program).args(args).stdin(std::process::Stdio::null()).output().await
+      .map_err(|e| format!("[identity] passphrase_command {program:?} could not be run: {e}"))?;
+    if !out.status.success() {
+      return Err(format!("[identity] passphrase_command {program:?} failed ({}): {}", out.status, String::from_utf8_lossy(&out.stderr).trim()).into());
+    }
+    let passphrase = String::from_utf8(out.stdout).map_err(|_| format!("[identity] passphrase_command {program:?} printed something that isn't UTF-8"))?;
+    return non_empty(strip_newline(passphrase), &format!("[identity] passphrase_command {program:?}")).map(Some);
+  }
+  Ok(None)
+}
+
+/// Write `key` to `path`, encrypted under `passphrase` if one is given.
+pub async fn write(path: &std::path::Path, key: &ed25519_dalek::SigningKey, passphrase: Option<&str>) -> DynResult<()> {
+  match passphrase {
+    Some(passphrase) => crypto_utils::write_encrypted_private_key_ed25519_pem_file(key, passphrase, path).await,
+    None => crypto_utils::write_private_key_ed25519_pem_file(key, path).await,
+  }
+}
+
+async fn read_passphrase_file(path: &std::path::Path) -> DynResult<String> {
+  let text = tokio::fs::read_to_string(path).await.map_err(|e| format!("could not read the passphrase file {}: {}", path.display(), e))?;
+  non_empty(strip_newline(text), &path.display().to_string())
+}
+
+fn strip_newline(mut s: String) -> String {
+  if s.ends_with('\n') {
+    s.pop();
+    if s.ends_with('\r') {
+      s.pop();
+    }
+  }
+  s
+}
+
+fn non_empty(passphrase: String, from: &str) -> DynResult<String> {
+  if passphrase.is_empty() {
+    return Err(format!("the passphrase from {from} is empty").into());
+  }
+  Ok(passphrase)
+}
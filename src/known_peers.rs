@@ -0,0 +1,183 @@
+//! Which key a configured `[[peer]]` must answer with, and the trust-on-first-use record behind it.
+//!
+//! A `[[peer]]` with an `expected_key` is pinned: every unicast reply we act on (a `run`, a fan-out
+//! run, a forwarded discovery request) must come with a signed `ProgramAccepted` from that key, and
+//! one signed by anything else is refused with a `[security]` line. A peer with no `expected_key` is
+//! either accepted as-is with a warning, or - under `[state] unpinned_peers = "tofu"` - held to the
+//! first key it answered with, which is kept in `<[state].dir>/known_peers` in the spirit of SSH's
+//! known_hosts: one `host[,host...] ssh-ed25519 <base64>` line per peer, safe to edit by hand. A
+//! peer that legitimately changed its key is fixed by deleting its line (or pinning the new key).
+
+use crate::*;
+
+pub const FILE_NAME: &str = "known_peers";
+
+/// Where the known-peers file lives for this config.
+pub fn path(state: &config::StateConfig) -> std::path::PathBuf {
+  state.dir.join(FILE_NAME)
+}
+
+/// The key a peer's replies are checked against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerKey {
+  /// The `expected_key` of its `[[peer]]` entry.
+  Pinned(Vec<u8>),
+  /// Recorded in the known-peers file the first time we reached it.
+  Known(Vec<u8>),
+  /// Nothing to check against (yet).
+  Unpinned,
+}
+
+impl PeerKey {
+  /// The raw ed25519 key replies must be signed with, if there is one.
+  pub fn key(&self) -> Option<&[u8]> {
+    match self {
+      PeerKey::Pinned(k) | PeerKey::Known(k) => Some(k),
+      PeerKey::Unpinned => None,
+    }
+  }
+
+  /// Where the key came from, for log lines.
+  pub fn origin(&self) -> &'static str {
+    match self {
+      PeerKey::Pinned(_) => "expected_key",
+      PeerKey::Known(_) => "known_peers",
+      PeerKey::Unpinned => "unpinned",
+    }
+  }
+}
+
+/// One line of the known-peers file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KnownPeer {
+  /// The peer's hostname / addresses as written in its `[[peer]]` entry.
+  pub hosts: Vec<String>,
+  pub key: ed25519_dalek::VerifyingKey,
+}
+
+impl KnownPeer {
+  /// True if this line is about `peer` (any of its configured hosts appears on it).
+  pub fn is_for(&self, peer: &config::PeerMetadata) -> bool {
+    peer.connect_hosts().iter().any(|h| self.hosts.iter().any(|k| k.eq_ignore_ascii_case(h)))
+  }
+
+  pub fn to_line(&self) -> String {
+    format!("{} {}", self.hosts.join(","), crypto_utils::format_verifying_key(&self.key))
+  }
+
+  /// Parse one `host[,host...] ssh-ed25519 <base64>` line; `None` for blanks, comments and lines
+  /// that don't parse.
+  pub fn parse_line(line: &str) -> Option<KnownPeer> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      return None;
+    }
+    let (hosts, key) = line.split_once(char::is_whitespace)?;
+    let key = crypto_utils::public_key_to_ed25519_vk(key.trim()).ok()?;
+    Some(KnownPeer { hosts: hosts.split(',').filter(|h| !h.is_empty()).map(str::to_string).collect(), key })
+  }
+}
+
+/// Read the known-peers file at `path`. A missing file knows nobody; lines that don't parse are
+/// skipped with a warning rather than failing every peer.
+pub async fn load(path: &std::path::Path) -> DynResult<Vec<KnownPeer>> {
+  let text = match tokio::fs::read_to_string(path).await {
+    Ok(text) => text,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+    Err(e) => return Err(format!("could not read {}: {}", path.display(), e).into()),
+  };
+  let mut known = Vec::new();
+  for (n, line) in text.lines().enumerate() {
+    match KnownPeer::parse_line(line) {
+      Some(k) => known.push(k),
+      None if line.trim().is_empty() || line.trim_start().starts_with('#') => {}
+      None => tracing::warn!("[ known_peers ] {}:{}: not a `host ssh-ed25519 <key>` line; ignoring it", path.display(), n + 1),
+    }
+  }
+  Ok(known)
+}
+
+/// Append `peer`'s key to the known-peers file, creating the file (and directory) if needed.
+pub async fn record(path: &std::path::Path, peer: &config::PeerMetadata, key: &ed25519_dalek::VerifyingKey) -> DynResult<()> {
+  use tokio::io::AsyncWriteExt;
+  if let Some(dir) = path.parent() {
+    tokio::fs::create_dir_all(dir).await.map_err(map_loc_err!())?;
+  }
+  let line = KnownPeer { hosts: peer.connect_hosts(), key: *key }.to_line();
+  let mut f = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await.map_err(map_loc_err!())?;
+  f.write_all(format!("{line}\n").as_bytes()).await.map_err(map_loc_err!())?;
+  Ok(())
+}
+
+/// One `[[peer]]` together with the key its replies are checked against (see [`PeerKey`]).
+#[derive(Debug, Clone)]
+pub struct PeerCheck {
+  pub peer: config::PeerMetadata,
+  pub key: PeerKey,
+  state: config::StateConfig,
+}
+
+impl PeerCheck {
+  /// Look up what `peer`'s replies must carry: its `expected_key`, else (under TOFU) the key
+  /// recorded for it, else nothing. An `expected_key` that doesn't parse is an error rather than
+  /// "unpinned", so a typo can't quietly switch the check off.
+  pub async fn new(config: &config::Config, peer: &config::PeerMetadata) -> DynResult<PeerCheck> {
+    let tofu = config.state.unpinned_peers == config::UnpinnedPeers::Tofu;
+    let key = if let Some(pinned) = peer.expected_key_str() {
+      let vk = crypto_utils::public_key_to_ed25519_vk(pinned)
+        .map_err(|e| format!("peer [{}] has an unusable expected_key: {}", peer.label(), e))?;
+      PeerKey::Pinned(vk.as_bytes().to_vec())
+    } else if tofu && let Some(k) = load(&path(&config.state)).await?.into_iter().find(|k| k.is_for(peer)) {
+      PeerKey::Known(k.key.as_bytes().to_vec())
+    } else {
+      PeerKey::Unpinned
+    };
+    Ok(PeerCheck { peer: peer.clone(), key, state: config.state.clone() })
+  }
+
+  /// Whether replies must identify themselves with a signed `ProgramAccepted` before they count:
+  /// always for a pinned or known peer, and under TOFU so the first key can be recorded.
+  pub fn is_enforced(&self) -> bool {
+    self.key.key().is_some() || self.state.unpinned_peers == config::UnpinnedPeers::Tofu
+  }
+
+  /// Check the (already signature-verified) identity `node` that answered from `addr`. A mismatch is
+  /// logged as a `[security]` event and refused. An unpinned peer is accepted: recorded in the
+  /// known-peers file under TOFU, otherwise reported with a ready-to-paste `[[peer]]` block.
+  pub async fn admit(&self, kind: &str, addr: std::net::SocketAddr, node: &config::IdentityData) -> bool {
+    if let Some(want) = self.key.key() {
+      if want == node.encoded_public_key.as_slice() {
+        return true;
+      }
+      log_key_mismatch(kind, addr, &self.peer, &self.key, node);
+      return false;
+    }
+    let Some(vk) = <[u8; 32]>::try_from(node.encoded_public_key.as_slice()).ok()
+      .and_then(|k| ed25519_dalek::VerifyingKey::from_bytes(&k).ok()) else {
+      return false;
+    };
+    if self.state.unpinned_peers == config::UnpinnedPeers::Tofu {
+      let path = path(&self.state);
+      match record(&path, &self.peer, &vk).await {
+        Ok(()) => tracing::warn!("[ known_peers ] First contact with peer [{}]: recorded {} ({}) in {}", self.peer.label(), node.human_name, crypto_utils::short_id(&node.encoded_public_key), path.display()),
+        Err(e) => tracing::warn!("[ known_peers ] Could not record peer [{}] in {}: {}", self.peer.label(), path.display(), e),
+      }
+    } else {
+      tracing::warn!("{}", self.peer.unpinned_key_warning(&crypto_utils::format_verifying_key(&vk)));
+    }
+    true
+  }
+}
+
+/// The `[security]` line for a reply signed by the wrong key, in the same shape as the daemon's
+/// signature events so one grep finds both.
+pub fn log_key_mismatch(kind: &str, addr: std::net::SocketAddr, peer: &config::PeerMetadata, want: &PeerKey, node: &config::IdentityData) {
+  let short = crypto_utils::short_id(&node.encoded_public_key);
+  let pubkey = crypto_utils::to_hex(&node.encoded_public_key);
+  let expected = crypto_utils::to_hex(want.key().unwrap_or_default());
+  let origin = want.origin();
+  let name = &node.human_name;
+  let label = peer.label();
+  tracing::warn!(target: "weverywhere::security",
+    "[security] key=MISMATCH kind={kind} addr={addr} peer=[{label}] short={short} pubkey={pubkey} name={name:?} expected={expected} pinned_by={origin}");
+}
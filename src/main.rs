@@ -6,17 +6,29 @@ type DynResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
 mod args;
 mod config;
+mod config_watch;
 mod comm;
 mod command;
 mod embedded_programs;
 mod executor;
 mod universal_serde;
 mod net_utils;
+mod transport;
 mod tty;
 mod firewall;
 mod discovery;
+mod fanout;
+mod topology;
+mod scheduler;
+mod peer_registry;
+mod message_history;
+mod history_guard;
+mod delivery;
+mod known_peers;
+mod key_rotation;
 mod messages;
 mod crypto_utils;
+mod keyfile;
 mod fs_utils;
 mod sys_utils;
 mod err_utils;
@@ -29,6 +41,7 @@ fn main() {
     let mut args = args::Args::parse();
 
     GLOBAL_VERBOSITY.set(args.verbosity.into()).expect("Failed to assign GLOBAL_VERBOSITY");
+    config::CLI_OVERRIDES.set(args.set.clone()).expect("Failed to assign CLI_OVERRIDES");
     let log_guard = init_logging(&args);
 
     if args.v_is_debug() {
@@ -60,17 +73,19 @@ async fn async_main(args: &mut args::Args) -> DynResult<()> {
 }
 
 async fn handle_friendly_ux_warnings(args: &mut args::Args) {
-    if let args::Command::GenerateMissingKeys {} = args.command {
+    // `config` and `identity` report a missing key themselves rather than offering to make one.
+    if matches!(args.command, args::Command::GenerateMissingKeys {} | args::Command::Config { .. } | args::Command::Identity { .. }) {
         return;
     }
     let config_path = args.config_path();
     match config::Config::read_from_file(&config_path).await.map_err(map_loc_err!()) {
         Ok(local_config) => {
-            // Check if a key exists; if not offer to run "Command::GenerateMissingKeys"
-            match local_config.identity.read_public_key_ed25519_pem_file().await {
+            // Check if a key exists; if not offer to run "Command::GenerateMissingKeys". Only whether
+            // it's there: reading it may mean asking for its passphrase, which the command does if it needs the key.
+            match tokio::fs::metadata(&local_config.identity.keyfile).await {
                 Ok(_) => { }
                 Err(e) => {
-                    tracing::info!("WARNING: {}", e);
+                    tracing::info!("WARNING: {:?}: {}", local_config.identity.keyfile, e);
                     if sys_utils::ask_user_proceed_yn("Would you like to generate a private keypair now? (y/n) ").await {
                         if let Err(e) = command::run_command(&args::Command::GenerateMissingKeys {}, &args).await.map_err(map_loc_err!()) {
                             tracing::info!("ERROR: {}", e);
@@ -0,0 +1,85 @@
+//! The on-disk copy of a node's message store ([`executor::MessageStore`]), kept when
+//! `[state] message_history` is on.
+//!
+//! Every signed message the store records is appended to `<[state].dir>/messages.jsonl` exactly as
+//! its sender signed it, one JSON line each, so a restarted node can still show - and replay to peers
+//! asking for history - what was said before. The executor reloads the newest
+//! [`executor::MESSAGE_STORE_CAP`] lines on start and rewrites the file once it holds well over that.
+
+use crate::*;
+
+pub const FILE_NAME: &str = "messages.jsonl";
+
+/// One message as stored on disk; the signed message is hex so the file stays line-oriented text.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct HistoryLine {
+  epoch_s: u64,
+  message: String,
+}
+
+/// Where the history lives for this config.
+pub fn path(state: &config::StateConfig) -> std::path::PathBuf {
+  state.dir.join(FILE_NAME)
+}
+
+/// The newest `keep` entries of the history at `path`, oldest first. A missing file is an empty
+/// history, and a line that doesn't parse (say, one cut short by a crash) is skipped. A file holding
+/// more than twice `keep` lines is rewritten with just the ones returned.
+pub async fn load(path: &std::path::Path, keep: usize) -> DynResult<Vec<messages::HistoryEntry>> {
+  let text = match tokio::fs::read_to_string(path).await {
+    Ok(text) => text,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+    Err(e) => return Err(format!("could not read {}: {}", path.display(), e).into()),
+  };
+  let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+  let entries: Vec<messages::HistoryEntry> = lines[lines.len().saturating_sub(keep)..]
+    .iter()
+    .filter_map(|l| serde_json::from_str::<HistoryLine>(l).ok())
+    .filter_map(|l| Some(messages::HistoryEntry { epoch_s: l.epoch_s, message: crypto_utils::from_hex(&l.message)? }))
+    .collect();
+  if lines.len() > keep.saturating_mul(2) {
+    let mut out = String::new();
+    for entry in entries.iter() {
+      out.push_str(&to_line(entry)?);
+    }
+    let tmp = path.with_extension("jsonl.tmp");
+    tokio::fs::write(&tmp, out).await.map_err(map_loc_err!())?;
+    tokio::fs::rename(&tmp, path).await.map_err(map_loc_err!())?;
+  }
+  Ok(entries)
+}
+
+fn to_line(entry: &messages::HistoryEntry) -> DynResult<String> {
+  let line = HistoryLine { epoch_s: entry.epoch_s, message: crypto_utils::to_hex(&entry.message) };
+  Ok(serde_json::to_string(&line).map_err(map_loc_err!())? + "\n")
+}
+
+/// The history file, open for appending as messages arrive.
+#[derive(Debug)]
+pub struct HistoryLog {
+  path: std::path::PathBuf,
+  file: std::fs::File,
+  /// Only the first failed write is logged at warn (e.g. a full disk).
+  write_warned: bool,
+}
+
+impl HistoryLog {
+  /// Open (creating it and its directory if needed) the history at `path`.
+  pub fn open(path: &std::path::Path) -> DynResult<HistoryLog> {
+    if let Some(dir) = path.parent() {
+      std::fs::create_dir_all(dir).map_err(map_loc_err!())?;
+    }
+    let file = std::fs::OpenOptions::new().create(true).append(true).open(path).map_err(map_loc_err!())?;
+    Ok(HistoryLog { path: path.to_path_buf(), file, write_warned: false })
+  }
+
+  /// Append one message. The store calls this with its lock held, so it's a single small write.
+  pub fn append(&mut self, entry: &messages::HistoryEntry) {
+    use std::io::Write;
+    let written = to_line(entry).and_then(|line| Ok(self.file.write_all(line.as_bytes())?));
+    if let Err(e) = written
+      && !std::mem::replace(&mut self.write_warned, true) {
+      tracing::warn!("Could not append to the message history {}: {}", self.path.display(), e);
+    }
+  }
+}
@@ -54,6 +54,495 @@ pub enum NetworkMessage {
     cbor_data: Vec<u8>,
     signature: Vec<u8>,
   },
+
+  /// A chunk of WASI stdin for a program the sender previously launched with `wants_stdin` set (see
+  /// [`crate::executor::ProgramData`]). Sent by `run --stdin` to the same executor it sent the
+  /// ExecuteRequest to, and appended to that program's stdin pipe in arrival order.
+  ///
+  /// * `request_id` - the launching request's `request_uuid`; the executor only feeds a pipe whose
+  ///   request matches, so a stray datagram can't write into someone else's program.
+  /// * `pid`        - the executor-side PID if the sender has learned it from a reply, else 0 (the
+  ///   executor then routes by `request_id` alone - a filter may need input before it prints
+  ///   anything).
+  /// * `seq`        - this chunk's position in the stream, from 0. The executor takes chunks strictly
+  ///   in order and acknowledges each with a [`NetworkMessage::ProgramStdinAck`]; the sender resends
+  ///   whatever has not been acknowledged, so a lost chunk is sent again rather than skipped.
+  /// * `data`       - raw stdin bytes (may be empty on the final chunk).
+  /// * `eof`        - true on the last chunk; the guest sees end-of-file once it drains the pipe.
+  ProgramStdin {
+    request_id: [u8; 16],
+    pid: u64,
+    seq: u64,
+    data: Vec<u8>,
+    eof: bool,
+  },
+
+  /// Bytes a program wrote to its WASI stderr - the stderr counterpart of BasicInsecureProgramStdout,
+  /// kept on its own channel so clients can route diagnostics separately from output.
+  ProgramStderr {
+    from_pid: u64,
+    stderr_data: Vec<u8>,
+  },
+
+  /// The structured successor to BasicInsecureProgramExit: why a program stopped, not just its code.
+  ///
+  /// * `exit_code`     - the WASI exit code (`proc_exit`, or 0 when `_start` returns); 1 for any trap.
+  /// * `reason`        - what ended the program (see [`ExitReason`]).
+  /// * `fuel_consumed` - fuel (roughly instructions) the program burned; None when it ran unmetered.
+  /// * `backtrace`     - a short wasm backtrace of a trap, innermost frame first. Only filled in for
+  ///   callers the executor trusts; empty otherwise (it can leak program internals).
+  ProgramExit {
+    from_pid: u64,
+    exit_code: u32,
+    reason: ExitReason,
+    fuel_consumed: Option<u64>,
+    backtrace: Vec<String>,
+  },
+
+  /// Sent by an executor as soon as it launches a program from an ExecuteRequest, binding the PID
+  /// its later replies carry to its signed identity. A client collecting replies from many nodes
+  /// (`run --fabric`) uses it to tell who each `from_pid` belongs to.
+  ///
+  /// * `request_uuid`  - the ExecuteRequest's `request_uuid` (fresh per run).
+  /// * `from_pid`      - the PID this node's stdout/stderr/exit messages for the run carry.
+  /// * `node`          - the executor's self-signed identity.
+  /// * `trusts_caller` - whether the executor trusts the request's signer (it runs under trusted limits).
+  /// * `signature`     - `node`'s [`config::IdentityData::sign_payload`] over `request_uuid` and
+  ///   [`program_accepted_payload`], so the binding can't be forged or replayed into another run.
+  ProgramAccepted {
+    request_uuid: [u8; 16],
+    from_pid: u64,
+    node: config::IdentityData,
+    trusts_caller: bool,
+    signature: Vec<u8>,
+  },
+
+  /// Any other message, sent on a named fabric (see [`FabricId`]). Every message a node on a named
+  /// fabric sends travels inside one of these; receivers on another fabric (or on the default one)
+  /// drop it unread. The default fabric sends its messages bare, so older nodes keep working on it.
+  ///
+  /// * `fabric_id` - [`FabricId::named`] of the sender's fabric.
+  /// * `message`   - the serde_bare encoding of the wrapped message (never itself a FabricScoped).
+  FabricScoped {
+    fabric_id: [u8; 16],
+    message: Vec<u8>,
+  },
+
+  /// A signed message for one chat room, or for one recipient alone (a direct message). Emitted by
+  /// `host::messages_send_to`; like SignedFabricMessage it is stored by every receiver that can read
+  /// it, but the room and recipient travel inside the signed envelope, so neither can be altered.
+  ///
+  /// * `source`    - the sender's self-signed identity (as for SignedFabricMessage).
+  /// * `id`        - a random per-send nonce, the dedup key together with the sender's pubkey.
+  /// * `envelope`  - a serde_bare [`MessageEnvelope`].
+  /// * `signature` - ed25519 over `SHA-256(id ++ envelope)` by the source key.
+  RoomMessage {
+    source: config::IdentityData,
+    id: Vec<u8>,
+    envelope: Vec<u8>,
+    signature: Vec<u8>,
+  },
+
+  /// Ask the nodes that hear it for the signed messages they hold, so a freshly started chat can show
+  /// what was said before it joined. Answered with [`NetworkMessage::HistoryReply`]s to the sender.
+  ///
+  /// * `source`        - the requester's self-signed identity (DMs are only replayed to their two ends).
+  /// * `request_uuid`  - fresh per request, echoed in every reply.
+  /// * `room`          - only this room's messages; None for every room.
+  /// * `since_epoch_s` - only messages recorded at or after this time.
+  /// * `epoch_s`       - when the request was made; a node only answers fresh requests it hasn't
+  ///   answered before (see [`crate::history_guard`]).
+  /// * `signature`     - `source`'s signature over `request_uuid` and [`history_request_payload`].
+  HistoryRequest {
+    source: config::IdentityData,
+    request_uuid: [u8; 16],
+    room: Option<String>,
+    since_epoch_s: u64,
+    epoch_s: u64,
+    signature: Vec<u8>,
+  },
+
+  /// Part of a node's answer to a [`NetworkMessage::HistoryRequest`]; a long history spans several.
+  /// Each entry is a message exactly as its sender signed it, so the requester verifies every one
+  /// itself and needn't trust the node replaying them.
+  HistoryReply {
+    request_uuid: [u8; 16],
+    entries: Vec<HistoryEntry>,
+  },
+
+  /// A receipt for a [`NetworkMessage::SignedFabricMessage`] or [`NetworkMessage::RoomMessage`] whose
+  /// sender asked for acks (see [`wants_ack`]). Every node that accepts one sends this back to the
+  /// serve port of the host it came from, once per copy, so a sender retransmitting a reliable send
+  /// (see [`crate::delivery`]) knows who has it.
+  ///
+  /// * `node`          - the acking node's self-signed identity.
+  /// * `source_pubkey` - the pubkey of the acked message's sender.
+  /// * `id`            - the acked message's `id`.
+  /// * `signature`     - `node`'s signature over `id` and `source_pubkey`.
+  MessageAck {
+    node: config::IdentityData,
+    source_pubkey: Vec<u8>,
+    id: Vec<u8>,
+    signature: Vec<u8>,
+  },
+
+  /// Hands trust from one identity key to its successor (see [`crate::key_rotation`]). Broadcast by
+  /// `weverywhere identity rotate`; a node that trusts `old` trusts `new` from then on.
+  ///
+  /// * `old`, `new`      - the retiring and the successor identity, each self-signed.
+  /// * `epoch_s`         - when the rotation was made.
+  /// * `old_signature`   - `old`'s signature over `epoch_s` and [`key_rotation_payload`]: only the
+  ///   holder of the old key can name a successor.
+  /// * `new_signature`   - `new`'s signature over the same, so no one can be named successor to a key
+  ///   without holding it.
+  KeyRotation {
+    old: config::IdentityData,
+    new: config::IdentityData,
+    epoch_s: u64,
+    old_signature: Vec<u8>,
+    new_signature: Vec<u8>,
+  },
+
+  /// Drawing by a program run with `run --tty` (see [`crate::executor::ProgramData::tty_size`]), sent
+  /// back to the client that launched it each time the program flushes its terminal.
+  ///
+  /// * `from_pid` - the executor-side PID.
+  /// * `seq`      - counts up from 0 per program. The client draws batches in sequence; after a gap
+  ///   it draws nothing more until a batch starting with [`crate::tty::draw_op::FRAME`], asking for
+  ///   one with [`NetworkMessage::TtyRedraw`].
+  /// * `ops`      - draw ops in the compact encoding of [`crate::tty::draw_op`].
+  TtyDraw {
+    from_pid: u64,
+    seq: u64,
+    ops: Vec<u8>,
+  },
+
+  /// Input from the client's terminal for a program it launched with `run --tty`, sent to the same
+  /// executor and checked like [`NetworkMessage::ProgramStdin`].
+  ///
+  /// * `request_id` - the launching request's `request_uuid`.
+  /// * `pid`        - the executor-side PID if known from a reply, else 0.
+  /// * `events`     - key, mouse, paste and resize events, each encoded by
+  ///   [`crate::tty::encode_event_v2`].
+  /// * `hangup`     - the user detached; the program gets Ctrl+D and no further input.
+  TtyInput {
+    request_id: [u8; 16],
+    pid: u64,
+    events: Vec<Vec<u8>>,
+    hangup: bool,
+  },
+
+  /// A `run --tty` client missed a [`NetworkMessage::TtyDraw`] batch and asks for the whole screen
+  /// again, checked like [`NetworkMessage::TtyInput`].
+  ///
+  /// * `request_id` - the launching request's `request_uuid`.
+  /// * `pid`        - the executor-side PID if known from a reply, else 0.
+  TtyRedraw {
+    request_id: [u8; 16],
+    pid: u64,
+  },
+
+  /// A [`NetworkMessage::SignedFabricMessage`] whose sender wants acks, sent by
+  /// `host::messages_send_reliable`: every node that accepts a copy answers it with a
+  /// [`NetworkMessage::MessageAck`]. A variant of its own, so SignedFabricMessage stays as older
+  /// nodes decode it.
+  ///
+  /// * `source`, `id`, `cbor_data` - as for SignedFabricMessage.
+  /// * `signature` - ed25519 by the source key over `id` and [`fabric_message_payload`]`(cbor_data,
+  ///   true)`, so neither kind of message passes for the other.
+  ReliableFabricMessage {
+    source: config::IdentityData,
+    id: Vec<u8>,
+    cbor_data: Vec<u8>,
+    signature: Vec<u8>,
+  },
+
+  /// An [`NetworkMessage::ExecuteRequest`] for a program that sets [`executor::ProgramOptions`]
+  /// (stdin and the like), which ProgramData's own wire layout predates. Built by
+  /// [`NetworkMessage::execute_request`] and unpacked by [`NetworkMessage::into_program_data`].
+  ///
+  /// * `program_data` - as for ExecuteRequest.
+  /// * `extension`    - the options, tagged by version.
+  ExtendedExecuteRequest {
+    program_data: executor::ProgramData,
+    extension: ProgramExtension,
+  },
+
+  /// Sent by the client that launched a program to stop it (`run`, on Ctrl-C). The node only takes it
+  /// from the address the ExecuteRequest came from; the program then exits as
+  /// [`ExitReason::HostKilled`] and its ProgramExit goes back as usual.
+  ///
+  /// * `request_id` - the launching request's `request_uuid`.
+  /// * `pid`        - the executor-side PID if known from a reply, else 0.
+  ProgramKill {
+    request_id: [u8; 16],
+    pid: u64,
+  },
+
+  /// An executor's answer to every ProgramStdin it matched to a pipe, sent back to its sender: the
+  /// `seq` of the next chunk it will take, so every chunk before it has reached the program. A chunk
+  /// that was lost, arrived out of order or found the pipe's queue full leaves `next_seq` where it was,
+  /// and the sender resends from there.
+  ///
+  /// * `request_id` - the launching request's `request_uuid`.
+  /// * `from_pid`   - the executor-side PID of the program being fed.
+  /// * `next_seq`   - the first chunk the pipe has not taken yet.
+  ProgramStdinAck {
+    request_id: [u8; 16],
+    from_pid: u64,
+    next_seq: u64,
+  },
+}
+
+impl NetworkMessage {
+  /// The request that asks an executor to run `program_data`: a plain ExecuteRequest unless it sets
+  /// any [`executor::ProgramOptions`], so a plain `run` still reaches nodes that predate them.
+  pub fn execute_request(program_data: executor::ProgramData) -> NetworkMessage {
+    if program_data.options == executor::ProgramOptions::default() {
+      NetworkMessage::ExecuteRequest { program_data }
+    } else {
+      let extension = ProgramExtension::V1(program_data.options.clone());
+      NetworkMessage::ExtendedExecuteRequest { program_data, extension }
+    }
+  }
+
+  /// The program an ExecuteRequest or ExtendedExecuteRequest carries, with its options filled in;
+  /// `None` for any other message.
+  pub fn into_program_data(self) -> Option<executor::ProgramData> {
+    match self {
+      NetworkMessage::ExecuteRequest { program_data } => Some(program_data),
+      NetworkMessage::ExtendedExecuteRequest { mut program_data, extension } => {
+        program_data.options = match extension {
+          ProgramExtension::V1(options) => options,
+        };
+        Some(program_data)
+      }
+      _ => None,
+    }
+  }
+}
+
+/// The options an [`NetworkMessage::ExtendedExecuteRequest`] carries. Options added later go in a new
+/// variant appended here, so every version stays decodable by the nodes that know it.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum ProgramExtension {
+  V1(executor::ProgramOptions),
+}
+
+/// What a [`NetworkMessage::RoomMessage`] carries under its signature.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct MessageEnvelope {
+  /// The room the message was said in; `""` is the fabric-wide room SignedFabricMessages belong to.
+  pub room: String,
+  pub body: MessageBody,
+  /// Sent with `host::messages_send_to_reliable`: receivers ack each copy (see [`wants_ack`]).
+  pub wants_ack: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum MessageBody {
+  /// A CBOR list or map anyone in the room may read.
+  Clear(Vec<u8>),
+  /// A direct message: the CBOR payload sealed to `to` (see [`crypto_utils::seal_to`]), and sealed
+  /// again to the sender so its own history can still show what it said.
+  Sealed {
+    to: Vec<u8>,
+    ephemeral_key: Vec<u8>,
+    ciphertext: Vec<u8>,
+    sender_ciphertext: Vec<u8>,
+  },
+}
+
+/// One replayed message in a [`NetworkMessage::HistoryReply`].
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct HistoryEntry {
+  /// When the replaying node recorded the message (the sender's signature doesn't cover a time).
+  pub epoch_s: u64,
+  /// The serde_bare SignedFabricMessage or RoomMessage.
+  pub message: Vec<u8>,
+}
+
+/// Which fabric a message belongs to: several fabrics can share a LAN (and even a multicast group and
+/// port) without seeing each other's programs, discovery or chat. This is isolation, not access
+/// control - who may do what on a fabric is still decided by that fabric's trusted keys.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FabricId(Option<[u8; 16]>);
+
+impl FabricId {
+  /// The ID of the fabric called `name`: the first 16 bytes of SHA-256 over a fixed prefix and the
+  /// name. The empty name is the default fabric, which has no ID.
+  pub fn named(name: &str) -> FabricId {
+    if name.is_empty() {
+      return FabricId(None);
+    }
+    use sha2::Digest;
+    let digest = sha2::Sha256::new().chain_update(b"weverywhere-fabric\0").chain_update(name.as_bytes()).finalize();
+    let mut id = [0u8; 16];
+    id.copy_from_slice(&digest[..16]);
+    FabricId(Some(id))
+  }
+
+  pub fn is_default(&self) -> bool {
+    self.0.is_none()
+  }
+
+  /// Put already-encoded message bytes on this fabric (a no-op for the default fabric).
+  pub fn scope(&self, encoded: Vec<u8>) -> DynResult<Vec<u8>> {
+    match self.0 {
+      None => Ok(encoded),
+      Some(fabric_id) => Ok(serde_bare::to_vec(&NetworkMessage::FabricScoped { fabric_id, message: encoded })?),
+    }
+  }
+
+  /// Encode `msg` for this fabric.
+  pub fn encode(&self, msg: &NetworkMessage) -> DynResult<Vec<u8>> {
+    self.scope(serde_bare::to_vec(msg)?)
+  }
+
+  /// Decode a datagram, returning the message only if it was sent on this fabric.
+  pub fn decode(&self, datagram: &[u8]) -> Option<NetworkMessage> {
+    match decode(datagram) {
+      Ok((fabric, msg)) if fabric == *self => Some(msg),
+      _ => None,
+    }
+  }
+}
+
+/// Decode a datagram and say which fabric it was sent on.
+pub fn decode(datagram: &[u8]) -> DynResult<(FabricId, NetworkMessage)> {
+  match serde_bare::from_slice::<NetworkMessage>(datagram)? {
+    NetworkMessage::FabricScoped { fabric_id, message } => match serde_bare::from_slice::<NetworkMessage>(&message)? {
+      NetworkMessage::FabricScoped { .. } => Err("nested FabricScoped message".into()),
+      inner => Ok((FabricId(Some(fabric_id)), inner)),
+    },
+    msg => Ok((FabricId(None), msg)),
+  }
+}
+
+/// The bytes a [`NetworkMessage::ProgramAccepted`] signature covers (after its `request_uuid`): the
+/// PID little-endian, then the trust flag as one byte.
+pub fn program_accepted_payload(from_pid: u64, trusts_caller: bool) -> Vec<u8> {
+  let mut payload = from_pid.to_le_bytes().to_vec();
+  payload.push(trusts_caller as u8);
+  payload
+}
+
+/// The bytes both [`NetworkMessage::KeyRotation`] signatures cover (after `epoch_s`, little-endian):
+/// a fixed label, so no other signed message can pass for one, then the old and the new public key.
+pub fn key_rotation_payload(old_pubkey: &[u8], new_pubkey: &[u8]) -> Vec<u8> {
+  [b"weverywhere-key-rotation\0".as_slice(), old_pubkey, new_pubkey].concat()
+}
+
+/// The bytes a [`NetworkMessage::SignedFabricMessage`] signature covers (after its `id`): the payload,
+/// behind a fixed label for a [`NetworkMessage::ReliableFabricMessage`] (`wants_ack`), so only the
+/// sender's key can ask for acks.
+pub fn fabric_message_payload(cbor_data: &[u8], wants_ack: bool) -> Vec<u8> {
+  if wants_ack {
+    [b"weverywhere-wants-ack\0".as_slice(), cbor_data].concat()
+  } else {
+    cbor_data.to_vec()
+  }
+}
+
+/// Whether `msg`'s sender asked for a [`NetworkMessage::MessageAck`] of each copy: a
+/// ReliableFabricMessage, or a RoomMessage whose envelope says so. False for anything else.
+pub fn wants_ack(msg: &NetworkMessage) -> bool {
+  match msg {
+    NetworkMessage::ReliableFabricMessage { .. } => true,
+    NetworkMessage::RoomMessage { envelope, .. } => serde_bare::from_slice::<MessageEnvelope>(envelope).is_ok_and(|e| e.wants_ack),
+    _ => false,
+  }
+}
+
+/// A [`NetworkMessage::RoomMessage`] from `source`, signed with its key `key`: `payload` (a CBOR list or
+/// map) said in `room`, or - given `to` - a direct message sealed to that identity pubkey and again to
+/// the sender. With `wants_ack` receivers ack it. Fails for a `to` that isn't an ed25519 key.
+pub fn room_message(source: &config::IdentityData, key: &ed25519_dalek::SigningKey, room: &str, to: Option<&[u8]>, payload: Vec<u8>, wants_ack: bool) -> DynResult<NetworkMessage> {
+  let body = match to {
+    None => MessageBody::Clear(payload),
+    Some(to) => {
+      let ephemeral = ed25519_dalek::SigningKey::generate(&mut rand::rngs::OsRng);
+      MessageBody::Sealed {
+        to: to.to_vec(),
+        ephemeral_key: ephemeral.verifying_key().as_bytes().to_vec(),
+        ciphertext: crypto_utils::seal_to(&ephemeral, to, &payload)?,
+        sender_ciphertext: crypto_utils::seal_to(&ephemeral, &source.encoded_public_key, &payload)?,
+      }
+    }
+  };
+  let envelope = serde_bare::to_vec(&MessageEnvelope { room: room.to_string(), body, wants_ack })?;
+  let mut id = [0u8; 16];
+  { use rand::RngCore; rand::rngs::OsRng.fill_bytes(&mut id); }
+  let signature = config::IdentityData::sign_payload(key, &id, &envelope).to_bytes().to_vec();
+  Ok(NetworkMessage::RoomMessage { source: source.clone(), id: id.to_vec(), envelope, signature })
+}
+
+/// The bytes a [`NetworkMessage::HistoryRequest`] signature covers (after its `request_uuid`): the
+/// request time and the start time little-endian, a room-filter flag byte, then the room name.
+pub fn history_request_payload(room: Option<&str>, since_epoch_s: u64, epoch_s: u64) -> Vec<u8> {
+  let mut payload = epoch_s.to_le_bytes().to_vec();
+  payload.extend_from_slice(&since_epoch_s.to_le_bytes());
+  payload.push(room.is_some() as u8);
+  payload.extend_from_slice(room.unwrap_or_default().as_bytes());
+  payload
+}
+
+/// Pack `entries` into as few [`NetworkMessage::HistoryReply`]s as keep each one's entries under
+/// `max_bytes` (an entry bigger than that still goes, alone). No entries, no replies.
+pub fn history_replies(request_uuid: [u8; 16], entries: Vec<HistoryEntry>, max_bytes: usize) -> Vec<NetworkMessage> {
+  let mut replies = Vec::new();
+  let mut chunk: Vec<HistoryEntry> = Vec::new();
+  let mut chunk_bytes = 0;
+  for entry in entries {
+    if !chunk.is_empty() && chunk_bytes + entry.message.len() > max_bytes {
+      replies.push(NetworkMessage::HistoryReply { request_uuid, entries: std::mem::take(&mut chunk) });
+      chunk_bytes = 0;
+    }
+    chunk_bytes += entry.message.len();
+    chunk.push(entry);
+  }
+  if !chunk.is_empty() {
+    replies.push(NetworkMessage::HistoryReply { request_uuid, entries: chunk });
+  }
+  replies
+}
+
+/// Why a program stopped running, carried by [`NetworkMessage::ProgramExit`]. Like NetworkMessage,
+/// new variants MUST be appended so older clients keep decoding the ones they know.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum ExitReason {
+  /// `_start` returned or the program called `proc_exit`; see `exit_code`.
+  Exited,
+  /// The program used up its fuel (instruction) budget.
+  OutOfFuel,
+  /// The program tried to grow its memory past the host's `max_memory_bytes` cap.
+  OutOfMemory,
+  /// The program executed a wasm `unreachable` instruction (typically a panic or abort).
+  Unreachable,
+  /// Any other trap (out-of-bounds access, stack overflow, ...), with the runtime's description.
+  Trap(String),
+  /// The program never got to run: instantiation failed or it has no usable `_start`.
+  StartFailed(String),
+  /// The host stopped it: its caller sent a [`NetworkMessage::ProgramKill`].
+  HostKilled,
+  /// It ran past its caller's `max_wall_clock_ms`.
+  WallClockTimeout,
+}
+
+impl ExitReason {
+  /// Short human-readable form for logs and the `run` client.
+  pub fn describe(&self) -> String {
+    match self {
+      ExitReason::Exited => "exited".to_string(),
+      ExitReason::OutOfFuel => "out of fuel".to_string(),
+      ExitReason::OutOfMemory => "out of memory".to_string(),
+      ExitReason::Unreachable => "unreachable executed".to_string(),
+      ExitReason::Trap(msg) => format!("trap: {msg}"),
+      ExitReason::StartFailed(msg) => format!("failed to start: {msg}"),
+      ExitReason::HostKilled => "killed by the host".to_string(),
+      ExitReason::WallClockTimeout => "out of time".to_string(),
+    }
+  }
 }
 
 
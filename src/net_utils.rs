@@ -10,6 +10,24 @@ pub fn get_interfaces() -> Vec<(u32, String, Vec<std::net::IpAddr>)> {
         .collect()
 }
 
+/// The interfaces fabric traffic may use under `[network]`: those its `interfaces` /
+/// `exclude_interfaces` globs allow, each keeping only addresses of an enabled family.
+pub fn fabric_interfaces(network: &crate::config::NetworkConfig) -> Vec<(u32, String, Vec<std::net::IpAddr>)> {
+    get_interfaces()
+        .into_iter()
+        .filter(|(_idx, name, _addrs)| network.allows_interface(name))
+        .map(|(idx, name, addrs)| (idx, name, addrs.into_iter().filter(|a| network.allows_family(a)).collect()))
+        .collect()
+}
+
+/// Set the `[network]` multicast TTL (v4) or hop limit (v6) for sends to `group` on `sock`.
+pub fn set_multicast_hops(sock: socket2::SockRef<'_>, group: &std::net::IpAddr, network: &crate::config::NetworkConfig) -> std::io::Result<()> {
+    match group {
+        std::net::IpAddr::V4(_) => sock.set_multicast_ttl_v4(network.hops_for(group)),
+        std::net::IpAddr::V6(_) => sock.set_multicast_hops_v6(network.hops_for(group)),
+    }
+}
+
 /// Resolve a statically-configured `[[peer]]` to a single socket address to send to,
 /// honouring its preference order: hostname, then ipv6, then ipv4 (see
 /// [`crate::config::PeerMetadata::connect_hosts`]). Each candidate is resolved via the
@@ -0,0 +1,106 @@
+//! The on-disk copy of the executor's peer table ([`executor::PeerInfo`]).
+//!
+//! A node only learns its neighbours passively (`Executor::note_peer`), so without this a restart
+//! forgets everyone until they talk to us again - and recursive discovery has nobody to forward to
+//! in the meantime. The executor reloads `<[state].dir>/peers.json` on start, drops entries not heard
+//! from within `[state].peer_ttl_s`, and flushes its table back every [`FLUSH_INTERVAL`].
+//! `weverywhere peers` lists and edits the same file.
+
+use crate::*;
+
+pub const FILE_NAME: &str = "peers.json";
+
+/// How often a running executor writes its peer table back (only when something changed).
+pub const FLUSH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// One peer as stored on disk. The key is hex so the file stays readable and hand-editable.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PeerRecord {
+  /// Untrusted self-declared name from the peer's identity.
+  pub name: String,
+  /// Hex of the raw ed25519 public key.
+  pub pubkey: String,
+  /// The address we last heard from it on.
+  pub addr: std::net::SocketAddr,
+  /// Whether we trusted its key when we last heard from it.
+  pub trusted: bool,
+  pub last_seen_epoch_s: u64,
+}
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+struct RegistryFile {
+  #[serde(default)]
+  peers: Vec<PeerRecord>,
+}
+
+/// Where the registry lives for this config.
+pub fn path(state: &config::StateConfig) -> std::path::PathBuf {
+  state.dir.join(FILE_NAME)
+}
+
+impl PeerRecord {
+  pub fn from_info(info: &executor::PeerInfo) -> PeerRecord {
+    PeerRecord {
+      name: info.human_name.clone(),
+      pubkey: crypto_utils::to_hex(&info.pubkey),
+      addr: info.last_addr,
+      trusted: info.trusted,
+      last_seen_epoch_s: info.last_seen_epoch_s,
+    }
+  }
+
+  /// The executor's form of this record; `None` if the stored key isn't valid hex.
+  pub fn to_info(&self) -> Option<executor::PeerInfo> {
+    Some(executor::PeerInfo {
+      human_name: self.name.clone(),
+      pubkey: crypto_utils::from_hex(&self.pubkey)?,
+      last_addr: self.addr,
+      trusted: self.trusted,
+      last_seen_epoch_s: self.last_seen_epoch_s,
+    })
+  }
+
+  /// Not heard from within `ttl_s` seconds of `now_epoch_s`. A TTL of 0 never expires.
+  pub fn is_expired(&self, now_epoch_s: u64, ttl_s: u64) -> bool {
+    ttl_s != 0 && now_epoch_s.saturating_sub(self.last_seen_epoch_s) > ttl_s
+  }
+
+  /// A `[[peer]]` block for the address we last heard this peer on, pinning the key it used (see
+  /// [`config::PeerMetadata::to_pinned_toml`]).
+  pub fn to_pinned_toml(&self) -> DynResult<String> {
+    let key: [u8; 32] = crypto_utils::from_hex(&self.pubkey)
+      .and_then(|k| k.try_into().ok())
+      .ok_or_else(|| format!("peer {:?} has a malformed key {:?}", self.name, self.pubkey))?;
+    let vk = ed25519_dalek::VerifyingKey::from_bytes(&key).map_err(map_loc_err!())?;
+    let (ipv4, ipv6) = match self.addr.ip() {
+      std::net::IpAddr::V4(v4) => (Some(v4), None),
+      std::net::IpAddr::V6(v6) => (None, Some(v6)),
+    };
+    let peer = config::PeerMetadata { hostname: None, ipv6, ipv4, expected_key: None };
+    Ok(peer.to_pinned_toml(&crypto_utils::format_verifying_key(&vk)))
+  }
+}
+
+/// Read the registry at `path`. A missing file is an empty registry.
+pub async fn load(path: &std::path::Path) -> DynResult<Vec<PeerRecord>> {
+  match tokio::fs::read(path).await {
+    Ok(bytes) => Ok(serde_json::from_slice::<RegistryFile>(&bytes).map_err(map_loc_err!())?.peers),
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+    Err(e) => Err(format!("could not read {}: {}", path.display(), e).into()),
+  }
+}
+
+/// Replace the registry at `path` with `peers`, creating the directory if needed. Written to a
+/// temporary file first and renamed into place, so a reader never sees half a file.
+pub async fn save(path: &std::path::Path, peers: &[PeerRecord]) -> DynResult<()> {
+  if let Some(dir) = path.parent() {
+    tokio::fs::create_dir_all(dir).await.map_err(map_loc_err!())?;
+  }
+  let mut peers = peers.to_vec();
+  peers.sort_by(|a, b| a.pubkey.cmp(&b.pubkey));
+  let bytes = serde_json::to_vec_pretty(&RegistryFile { peers }).map_err(map_loc_err!())?;
+  let tmp = path.with_extension("json.tmp");
+  tokio::fs::write(&tmp, bytes).await.map_err(map_loc_err!())?;
+  tokio::fs::rename(&tmp, path).await.map_err(map_loc_err!())?;
+  Ok(())
+}
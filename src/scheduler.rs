@@ -0,0 +1,146 @@
+//! Capacity-based placement for `weverywhere submit`.
+//!
+//! Like discovery, observation is a program: `submit` first sends a small capacity probe
+//! (`example-programs/capacity-probe.c`) across the fabric. Each node runs it and returns a CBOR
+//! record built from the `host::load_*` imports. The client then ranks the nodes and sends the real
+//! job to the best ones. This module is shared by the daemon side (encoding the load figures for the
+//! imports) and the client side (decoding records and ranking).
+
+use crate::*;
+
+/// CBOR integer keys a capacity record adds to the discovery record keys
+/// ([`crate::discovery::record_keys`]: 1 attestation, 2 trusts_caller, 5 node addr). Each value is the
+/// CBOR item the matching `host::load_*` import returned, nested as-is.
+pub mod capacity_keys {
+  /// `host::load_cpu` map (see [`super::cpu_keys`]).
+  pub const CPU: i128 = 6;
+  /// `host::load_memory` map (see [`super::memory_keys`]).
+  pub const MEMORY: i128 = 7;
+  /// `host::load_pids` array of the PIDs running on the node.
+  pub const PIDS: i128 = 8;
+}
+
+/// CBOR integer keys in the `host::load_cpu` map.
+pub mod cpu_keys {
+  pub const CORES: i128 = 1;
+  pub const BUSY_PERMILLE: i128 = 2;
+  pub const LOAD1_CENTI: i128 = 3;
+}
+
+/// CBOR integer keys in the `host::load_memory` map.
+pub mod memory_keys {
+  pub const TOTAL_BYTES: i128 = 1;
+  pub const AVAILABLE_BYTES: i128 = 2;
+}
+
+/// Encode a CPU snapshot for `host::load_cpu`.
+pub fn encode_cpu_load(load: &sys_utils::CpuLoad) -> DynResult<Vec<u8>> {
+  use serde_cbor::Value;
+  let map = Value::Map(
+    [
+      (Value::Integer(cpu_keys::CORES), Value::Integer(load.cores as i128)),
+      (Value::Integer(cpu_keys::BUSY_PERMILLE), Value::Integer(load.busy_permille as i128)),
+      (Value::Integer(cpu_keys::LOAD1_CENTI), Value::Integer(load.load1_centi as i128)),
+    ]
+    .into_iter()
+    .collect(),
+  );
+  Ok(serde_cbor::to_vec(&map)?)
+}
+
+/// Encode a memory snapshot for `host::load_memory`.
+pub fn encode_memory_load(load: &sys_utils::MemoryLoad) -> DynResult<Vec<u8>> {
+  use serde_cbor::Value;
+  let map = Value::Map(
+    [
+      (Value::Integer(memory_keys::TOTAL_BYTES), Value::Integer(load.total_bytes as i128)),
+      (Value::Integer(memory_keys::AVAILABLE_BYTES), Value::Integer(load.available_bytes as i128)),
+    ]
+    .into_iter()
+    .collect(),
+  );
+  Ok(serde_cbor::to_vec(&map)?)
+}
+
+/// Encode the running PIDs for `host::load_pids`.
+pub fn encode_pids(pids: &[u64]) -> DynResult<Vec<u8>> {
+  use serde_cbor::Value;
+  Ok(serde_cbor::to_vec(&Value::Array(pids.iter().map(|p| Value::Integer(*p as i128)).collect()))?)
+}
+
+/// What a node's capacity record says about its headroom. Missing figures read as zero, so a node
+/// that can't measure something sorts below one that can.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capacity {
+  pub cores: u32,
+  pub busy_permille: u32,
+  pub mem_total_bytes: u64,
+  pub mem_available_bytes: u64,
+  /// How many weverywhere programs the node was already running.
+  pub running: usize,
+}
+
+impl Capacity {
+  /// Read the load figures out of a capacity record (the whole CBOR map the probe returned).
+  /// `None` if the bytes aren't a CBOR map; individual missing entries default to zero.
+  pub fn from_record(bytes: &[u8]) -> Option<Capacity> {
+    use serde_cbor::Value;
+    let map = match serde_cbor::from_slice::<Value>(bytes).ok()? {
+      Value::Map(m) => m,
+      _ => return None,
+    };
+    let sub = |key: i128, sub_key: i128| -> u64 {
+      match map.get(&Value::Integer(key)) {
+        Some(Value::Map(m)) => match m.get(&Value::Integer(sub_key)) {
+          Some(Value::Integer(i)) if *i >= 0 => (*i).min(u64::MAX as i128) as u64,
+          _ => 0,
+        },
+        _ => 0,
+      }
+    };
+    let running = match map.get(&Value::Integer(capacity_keys::PIDS)) {
+      Some(Value::Array(pids)) => pids.len(),
+      _ => 0,
+    };
+    Some(Capacity {
+      cores: sub(capacity_keys::CPU, cpu_keys::CORES) as u32,
+      busy_permille: sub(capacity_keys::CPU, cpu_keys::BUSY_PERMILLE).min(1000) as u32,
+      mem_total_bytes: sub(capacity_keys::MEMORY, memory_keys::TOTAL_BYTES),
+      mem_available_bytes: sub(capacity_keys::MEMORY, memory_keys::AVAILABLE_BYTES),
+      running,
+    })
+  }
+
+  /// Idle CPU across all cores, in thousandths of a core.
+  pub fn free_millicores(&self) -> u64 {
+    self.cores as u64 * (1000 - self.busy_permille.min(1000)) as u64
+  }
+}
+
+/// A node that answered the capacity probe, as seen by the submitting client.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+  pub hostname: String,
+  pub pubkey: Vec<u8>,
+  pub addr: std::net::SocketAddr,
+  /// The node trusts our key, so the job runs under its trusted limits.
+  pub trusts_us: bool,
+  /// Our own `[[trusted]]` list (or our own key) includes the node.
+  pub we_trust: bool,
+  pub capacity: Capacity,
+}
+
+/// Order candidates best-first: nodes that trust us (the job gets trusted limits) before those that
+/// don't, then nodes we trust, then the most idle CPU, the most available memory and the fewest
+/// running programs. Ties fall back to the key so the order is stable.
+pub fn rank(mut candidates: Vec<Candidate>) -> Vec<Candidate> {
+  candidates.sort_by(|a, b| {
+    b.trusts_us.cmp(&a.trusts_us)
+      .then_with(|| b.we_trust.cmp(&a.we_trust))
+      .then_with(|| b.capacity.free_millicores().cmp(&a.capacity.free_millicores()))
+      .then_with(|| b.capacity.mem_available_bytes.cmp(&a.capacity.mem_available_bytes))
+      .then_with(|| a.capacity.running.cmp(&b.capacity.running))
+      .then_with(|| a.pubkey.cmp(&b.pubkey))
+  });
+  candidates
+}
@@ -32,5 +32,81 @@ pub async fn ask_user_proceed_yn<T: AsRef<str>>(msg: T) -> bool {
     input.contains("y") || input.contains("Y") || input.contains("1")
 }
 
+/// Ask for a secret on the terminal without echoing it. `None` when stdin isn't a terminal or the
+/// user gives up (Ctrl-C / Esc).
+pub async fn ask_user_secret<T: AsRef<str>>(msg: T) -> Option<String> {
+    use std::io::{IsTerminal, Write};
+    if !std::io::stdin().is_terminal() {
+        return None;
+    }
+    let msg = msg.as_ref().to_string();
+    tokio::task::spawn_blocking(move || {
+        use crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers};
+        let mut stderr = std::io::stderr();
+        let _ = stderr.write_all(msg.as_bytes());
+        let _ = stderr.flush();
+        crossterm::terminal::enable_raw_mode().ok()?;
+        let mut secret = String::new();
+        let answer = loop {
+            match crossterm::event::read() {
+                Ok(Event::Key(k)) if k.kind != KeyEventKind::Release => match k.code {
+                    KeyCode::Enter => break Some(secret),
+                    KeyCode::Esc => break None,
+                    KeyCode::Char('c') if k.modifiers.contains(KeyModifiers::CONTROL) => break None,
+                    KeyCode::Backspace => { secret.pop(); }
+                    KeyCode::Char(c) => secret.push(c),
+                    _ => {}
+                },
+                Ok(_) => {}
+                Err(_) => break None,
+            }
+        };
+        let _ = crossterm::terminal::disable_raw_mode();
+        let _ = stderr.write_all(b"\n");
+        answer
+    }).await.ok().flatten()
+}
+
+
+
+/// A snapshot of this machine's CPU use, as reported by `host::load_cpu`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CpuLoad {
+    /// Logical CPUs.
+    pub cores: u32,
+    /// How busy all cores are together over a short sample, 0..=1000.
+    pub busy_permille: u32,
+    /// One-minute load average x100 (0 where the platform has none, e.g. Windows).
+    pub load1_centi: u32,
+}
 
+/// A snapshot of this machine's memory, as reported by `host::load_memory`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryLoad {
+    pub total_bytes: u64,
+    pub available_bytes: u64,
+}
+
+/// Measure CPU use. Usage is a difference between two readings, so this waits out sysinfo's
+/// minimum sampling interval (~200ms) between them.
+pub async fn cpu_load() -> CpuLoad {
+    let mut sys = sysinfo::System::new();
+    sys.refresh_cpu_usage();
+    tokio::time::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL).await;
+    sys.refresh_cpu_usage();
+    let busy_percent = sys.global_cpu_usage().clamp(0.0, 100.0);
+    CpuLoad {
+        cores: sys.cpus().len() as u32,
+        busy_permille: (busy_percent * 10.0).round() as u32,
+        load1_centi: (sysinfo::System::load_average().one.max(0.0) * 100.0).round() as u32,
+    }
+}
 
+pub fn memory_load() -> MemoryLoad {
+    let mut sys = sysinfo::System::new();
+    sys.refresh_memory();
+    MemoryLoad {
+        total_bytes: sys.total_memory(),
+        available_bytes: sys.available_memory(),
+    }
+}
@@ -1,4 +1,4 @@
-use crate::config::{Config, PeerMetadata, default_identity_keyfile};
+use crate::config::{Config, NetworkConfig, PeerMetadata, default_identity_keyfile};
 
 #[test]
 fn identity_keyfile_is_optional_and_defaults() {
@@ -130,3 +130,290 @@ fn signed_message_payload_roundtrips_and_detects_tampering() {
   let other_sig = IdentityData::sign_payload(&other, &id, payload).to_bytes().to_vec();
   assert!(identity.verify_payload(&id, payload, &other_sig).is_err());
 }
+
+#[test]
+fn network_section_defaults_match_the_old_cli_defaults() {
+  let cfg: Config = toml::from_str("[identity]\nname = \"t\"\n").unwrap();
+  let net = &cfg.network;
+  assert_eq!(net.port, 2240);
+  assert_eq!(net.groups(), vec!["224.0.0.3".parse::<std::net::IpAddr>().unwrap(), "ff02::3".parse().unwrap()]);
+  assert_eq!((net.multicast_ttl, net.multicast_hops_v6), (4, 4));
+  assert!(net.allows_interface("eth0") && net.allows_interface("docker0"));
+}
+
+#[test]
+fn network_interface_globs_families_and_cli_overrides() {
+  let mut net: NetworkConfig = toml::from_str(
+    r#"
+      port = 4000
+      multicast_groups = ["239.1.2.3", "ff05::77"]
+      multicast_ttl = 1
+      interfaces = ["eth*", "wlan0", "br[0"]
+      exclude_interfaces = ["eth9"]
+      ipv6 = false
+    "#,
+  )
+  .unwrap();
+  assert!(net.allows_interface("eth0") && net.allows_interface("wlan0"));
+  assert!(!net.allows_interface("eth9"), "excludes win over includes");
+  assert!(!net.allows_interface("docker0") && !net.allows_interface("wlan1"));
+  // Not a valid glob: only the literal name matches.
+  assert!(net.allows_interface("br[0") && !net.allows_interface("br0"));
+
+  // IPv6 is off, so its group is dropped even though it's configured.
+  assert_eq!(net.groups(), vec!["239.1.2.3".parse::<std::net::IpAddr>().unwrap()]);
+  assert_eq!(net.hops_for(&"239.1.2.3".parse().unwrap()), 1);
+  assert_eq!(net.hops_for(&"ff05::77".parse().unwrap()), 4);
+
+  // Flags replace the configured values for one command; absent flags leave them alone.
+  net.apply_cli_overrides(None, None);
+  assert_eq!(net.port, 4000);
+  net.apply_cli_overrides(Some(&["224.0.0.9".parse().unwrap()]), Some(5000));
+  assert_eq!((net.groups(), net.port), (vec!["224.0.0.9".parse::<std::net::IpAddr>().unwrap()], 5000));
+}
+
+#[tokio::test]
+async fn included_network_settings_override_only_what_they_set() {
+  let dir = std::env::temp_dir().join(format!("weverywhere-network-include-{}", std::process::id()));
+  std::fs::create_dir_all(&dir).unwrap();
+  let include = dir.join("site.toml");
+  std::fs::write(&include, "[network]\nmulticast_groups = [\"239.9.9.9\"]\nexclude_interfaces = [\"docker*\"]\n").unwrap();
+  let main = dir.join("weverywhere.toml");
+  std::fs::write(&main, format!(
+    "[identity]\nname = \"t\"\n\n[[includes]]\npath = {:?}\n\n[network]\nport = 4100\n",
+    include.to_string_lossy()
+  )).unwrap();
+
+  let cfg = Config::read_from_file(&main).await.unwrap();
+  assert_eq!(cfg.network.port, 4100);
+  assert_eq!(cfg.network.groups(), vec!["239.9.9.9".parse::<std::net::IpAddr>().unwrap()]);
+  assert!(!cfg.network.allows_interface("docker0"));
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn fabric_views_swap_trust_state_dir_and_derived_address() {
+  let cfg: Config = toml::from_str(
+    r#"
+[identity]
+name = "t"
+
+[state]
+dir = "/var/lib/weverywhere"
+
+[[trusted]]
+key = "main-key"
+
+[[fabric]]
+name = "staging"
+trusted = [{ key = "staging-key" }]
+
+[[fabric]]
+name = "lab"
+derive_address = true
+"#,
+  ).unwrap();
+
+  // The primary fabric is the default one, with the top-level trust set and state dir.
+  let views = cfg.served_fabrics();
+  let names: Vec<&str> = views.iter().map(|v| v.network.fabric.as_str()).collect();
+  assert_eq!(names, vec!["", "staging", "lab"]);
+  assert!(views[0].network.fabric_id().is_default());
+  assert_eq!(views[0].trusted[0].key, "main-key");
+  assert_eq!(views[0].state.dir, std::path::PathBuf::from("/var/lib/weverywhere"));
+
+  // A membership brings its own trusted keys and state, but keeps the shared group and port.
+  let staging = cfg.fabric_view(Some("staging"));
+  assert_eq!(staging.trusted.iter().map(|k| k.key.as_str()).collect::<Vec<_>>(), vec!["staging-key"]);
+  assert_eq!(staging.state.dir, std::path::PathBuf::from("/var/lib/weverywhere/fabrics/staging"));
+  assert_eq!(staging.network.port, cfg.network.port);
+  assert!(!staging.network.fabric_id().is_default());
+
+  // derive_address moves the fabric to a group and port of its own, the same on every node.
+  let lab = cfg.fabric_view(Some("lab"));
+  let (groups, port) = NetworkConfig::derived_address("lab");
+  assert_eq!(lab.network.groups(), groups);
+  assert_eq!(lab.network.port, port);
+  assert_ne!(port, cfg.network.port);
+
+  // `--fabric-name` with a name that has no [[fabric]] entry keeps the top-level trust set.
+  let adhoc = cfg.fabric_view(Some("ad hoc/x"));
+  assert_eq!(adhoc.trusted[0].key, "main-key");
+  assert_eq!(adhoc.state.dir, std::path::PathBuf::from("/var/lib/weverywhere/fabrics/ad_hoc_x"));
+}
+
+#[test]
+fn reload_diffs_name_each_changed_setting_and_validation_rejects_bad_keys() {
+  let key = {
+    let signing = ed25519_dalek::SigningKey::generate(&mut rand::rngs::OsRng);
+    crate::crypto_utils::format_verifying_key(&signing.verifying_key())
+  };
+  let old: Config = toml::from_str(&format!(
+    "[identity]\nname = \"t\"\n\n[network]\nport = 2240\n\n[[trusted]]\nkey = \"{key}\"\n\n[[peer]]\nipv4 = \"10.0.0.1\"\n"
+  )).unwrap();
+  let new: Config = toml::from_str(&format!(
+    "[identity]\nname = \"t\"\n\n[network]\nport = 2241\n\n[[trusted]]\nkey = \"{key}\"\n\n[[peer]]\nipv4 = \"10.0.0.2\"\n"
+  )).unwrap();
+  assert!(old.diff(&old.clone()).is_empty());
+  let changes = old.diff(&new);
+  assert_eq!(changes.len(), 3, "{changes:?}");
+  assert!(changes.contains(&"network.port: 2240 -> 2241".to_string()));
+  assert!(changes.iter().any(|c| c.starts_with("peer: removed") && c.contains("10.0.0.1")));
+  assert!(changes.iter().any(|c| c.starts_with("peer: added") && c.contains("10.0.0.2")));
+
+  assert!(new.validate().is_ok());
+  let mut bad = new.clone();
+  bad.trusted[0].key = "ssh-ed25519 not-a-key".into();
+  assert!(bad.validate().is_err());
+  let twice: Config = toml::from_str("[identity]\nname = \"t\"\n\n[[fabric]]\nname = \"a\"\n\n[[fabric]]\nname = \"a\"\n").unwrap();
+  assert!(twice.validate().is_err());
+}
+
+#[tokio::test]
+async fn config_watcher_notices_edits_to_the_file_and_its_includes() {
+  let dir = std::env::temp_dir().join(format!("weverywhere-config-watch-{}", std::process::id()));
+  let include_dir = dir.join("conf.d");
+  std::fs::create_dir_all(&include_dir).unwrap();
+  let main = dir.join("weverywhere.toml");
+  let text = format!("[identity]\nname = \"t\"\n\n[[includes]]\npath = \"{}/*.toml\"\n", include_dir.to_string_lossy());
+  std::fs::write(&main, &text).unwrap();
+  std::fs::write(include_dir.join("a.toml"), "[network]\nport = 1\n").unwrap();
+  let config = Config::read_from_file(&main).await.unwrap();
+  let mut watcher = crate::config_watch::ConfigWatcher::new(&main, &config).await;
+
+  assert!(!watcher.poll().await, "nothing changed yet");
+  std::fs::write(include_dir.join("a.toml"), "[network]\nport = 12\n").unwrap();
+  assert!(watcher.poll().await, "an included file was edited");
+  assert!(!watcher.poll().await);
+  std::fs::write(include_dir.join("b.toml"), "").unwrap();
+  assert!(watcher.poll().await, "a new file matches an include glob");
+  std::fs::write(&main, format!("{text}\n")).unwrap();
+  assert!(watcher.poll().await, "the main file was edited");
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn explain_credits_each_setting_to_its_file_and_check_collects_every_problem() {
+  let dir = std::env::temp_dir().join(format!("weverywhere-config-check-{}", std::process::id()));
+  let include_dir = dir.join("conf.d");
+  std::fs::create_dir_all(&include_dir).unwrap();
+  let keyfile = dir.join("identity.pem");
+  crate::crypto_utils::generate_private_key_ed25519_pem_file(&keyfile).await.unwrap();
+  let key = crate::crypto_utils::format_public_key(&crate::crypto_utils::read_private_key_ed25519_pem_file(&keyfile).await.unwrap());
+  let main = dir.join("weverywhere.toml");
+  std::fs::write(&main, format!(
+    "[identity]\nkeyfile = {keyfile:?}\n\n[limits.untrusted]\nmax_cpu_instructions = 0\n\n[[trusted]]\nkey = {key:?}\n\n\
+     [[peer]]\nipv4 = \"127.0.0.1\"\n\n[[includes]]\npath = \"{}/*.toml\"\n\n[[includes]]\npath = \"{}/nothing-*.toml\"\n",
+    include_dir.to_string_lossy(), dir.to_string_lossy())).unwrap();
+  std::fs::write(include_dir.join("a.toml"), format!("[network]\nport = 2299\n\n[[trusted]]\nkey = {key:?}\n\n[[peer]]\nipv6 = \"::1\"\n")).unwrap();
+  std::fs::write(include_dir.join("b.toml"), "[network\n").unwrap();
+
+  let loaded = crate::config::LoadedConfig::load(&main).await.unwrap();
+  assert_eq!(loaded.layers.len(), 2, "the unparseable include is skipped");
+  let sources = loaded.sources();
+  let source = |path: &str| sources.iter().find(|(p, _, _)| p == path).map(|(_, _, file)| file.map(|f| f.to_path_buf())).unwrap();
+  assert_eq!(source("network.port"), Some(include_dir.join("a.toml")));
+  assert_eq!(source("network.ipv4"), None, "untouched settings are defaults");
+  assert_eq!(source("limits.untrusted.max_cpu_instructions"), Some(main.clone()));
+  assert_eq!(source("trusted[0].key"), Some(main.clone()));
+  assert_eq!(source("trusted[1].key"), Some(include_dir.join("a.toml")), "appended entries keep the file they came from");
+  assert_eq!(source("peer[1].ipv6"), Some(include_dir.join("a.toml")));
+
+  let problems = loaded.check().await;
+  assert_eq!(problems.len(), 4, "{problems:#?}");
+  assert!(problems.iter().any(|p| p.contains("b.toml")));
+  assert!(problems.iter().any(|p| p.contains("nothing-*.toml") && p.contains("matches no files")));
+  assert!(problems.iter().any(|p| p.starts_with("[[trusted]] lists the key")));
+  assert!(problems.iter().any(|p| p.starts_with("limits.untrusted.max_cpu_instructions is 0")));
+
+  std::fs::remove_file(&keyfile).unwrap();
+  std::fs::remove_file(include_dir.join("b.toml")).unwrap();
+  std::fs::write(include_dir.join("a.toml"), "[network]\nport = 2299\n").unwrap();
+  std::fs::write(&main, format!("[identity]\nkeyfile = {keyfile:?}\n\n[[trusted]]\nkey = \"nonsense\"\n")).unwrap();
+  let problems = crate::config::LoadedConfig::load(&main).await.unwrap().check().await;
+  assert_eq!(problems.len(), 2, "{problems:#?}");
+  assert!(problems[0].starts_with("[[trusted]] key \"nonsense\""));
+  assert!(problems[1].starts_with("[identity] keyfile"));
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn includes_nest_in_lexical_order_skip_cycles_and_remove_what_came_before() {
+  let dir = std::env::temp_dir().join(format!("weverywhere-nested-includes-{}", std::process::id()));
+  let include_dir = dir.join("conf.d");
+  std::fs::create_dir_all(&include_dir).unwrap();
+  let key = |_| crate::crypto_utils::format_public_key(&ed25519_dalek::SigningKey::generate(&mut rand::rngs::OsRng));
+  let (kept, revoked) = (key(0), key(1));
+  let main = dir.join("weverywhere.toml");
+  std::fs::write(&main, format!(
+    "[identity]\nname = \"t\"\n\n[[trusted]]\nkey = {kept:?}\n\n[[trusted]]\nkey = {revoked:?}\n\n\
+     [[peer]]\nhostname = \"old.example\"\n\n[[fabric]]\nname = \"staging\"\n\n[[includes]]\npath = \"{}/*.toml\"\n",
+    include_dir.to_string_lossy())).unwrap();
+  // Written out of order: b.toml must still apply after a.toml, and a.toml's own include before b.toml.
+  std::fs::write(include_dir.join("b.toml"), format!(
+    "[network]\nport = 2302\n\n[[trusted_remove]]\nkey = {revoked:?}\n\n[[peer_remove]]\nhost = \"old.example\"\n\n\
+     [[fabric_remove]]\nname = \"staging\"\n\n[[fabric_remove]]\nname = \"missing\"\n")).unwrap();
+  std::fs::write(include_dir.join("a.toml"), format!(
+    "[network]\nport = 2300\n\n[[includes]]\npath = {:?}\n", dir.join("nested.toml").to_string_lossy())).unwrap();
+  std::fs::write(dir.join("nested.toml"), format!(
+    "[network]\nport = 2301\n\n[[peer]]\nipv4 = \"127.0.0.1\"\n\n[[includes]]\npath = {:?}\n", main.to_string_lossy())).unwrap();
+
+  let loaded = crate::config::LoadedConfig::load(&main).await.unwrap();
+  let order: Vec<_> = loaded.layers.iter().map(|l| l.path.file_name().unwrap().to_string_lossy().to_string()).collect();
+  assert_eq!(order, ["weverywhere.toml", "a.toml", "nested.toml", "b.toml"]);
+  assert_eq!(loaded.config.network.port, 2302);
+  assert_eq!(loaded.config.trusted.iter().map(|t| t.key.as_str()).collect::<Vec<_>>(), [kept.as_str()]);
+  assert_eq!(loaded.config.peer.len(), 1);
+  assert_eq!(loaded.config.peer[0].ipv4, Some("127.0.0.1".parse().unwrap()));
+  assert!(loaded.config.fabric.is_empty());
+  assert_eq!(loaded.problems.len(), 2, "{:#?}", loaded.problems);
+  assert!(loaded.problems.iter().any(|p| p.starts_with("Include cycle skipped") && p.contains("nested.toml")));
+  assert!(loaded.problems.iter().any(|p| p.contains("[[fabric_remove]] \"missing\" matches no [[fabric]]")));
+
+  let sources = loaded.sources();
+  let source = |path: &str| sources.iter().find(|(p, _, _)| p == path).map(|(_, _, file)| file.map(|f| f.to_path_buf())).unwrap();
+  assert_eq!(source("trusted[0].key"), Some(main.clone()));
+  assert_eq!(source("peer[0].ipv4"), Some(dir.join("nested.toml")), "entries after a removal keep their file");
+  assert_eq!(source("network.port"), Some(include_dir.join("b.toml")));
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn env_and_set_overrides_apply_after_includes_and_are_credited() {
+  use crate::config::ConfigOverride;
+  let dir = std::env::temp_dir().join(format!("weverywhere-config-overrides-{}", std::process::id()));
+  std::fs::create_dir_all(&dir).unwrap();
+  let include = dir.join("site.toml");
+  std::fs::write(&include, "[network]\nport = 2400\nmulticast_ttl = 4\n").unwrap();
+  let main = dir.join("weverywhere.toml");
+  std::fs::write(&main, format!(
+    "[identity]\nname = \"from-file\"\n\n[[peer]]\nipv4 = \"10.0.0.1\"\n\n[[includes]]\npath = {:?}\n", include.to_string_lossy())).unwrap();
+
+  let env = ConfigOverride::from_env_vars([
+    ("WEVERYWHERE__NETWORK__PORT".to_string(), "2500".to_string()),
+    ("WEVERYWHERE__IDENTITY__NAME".to_string(), "from-env".to_string()),
+    ("PATH".to_string(), "/bin".to_string()),
+  ]);
+  assert_eq!(env.iter().map(|o| o.setting.as_str()).collect::<Vec<_>>(), ["identity.name", "network.port"]);
+  let set: Vec<ConfigOverride> = ["identity.name=1234", "peer=[{ ipv4 = \"10.0.0.2\" }]"].iter().map(|s| s.parse().unwrap()).collect();
+  assert!("identity.name".parse::<ConfigOverride>().is_err());
+  assert!("network..port=1".parse::<ConfigOverride>().is_err());
+
+  let overrides: Vec<ConfigOverride> = env.into_iter().chain(set).collect();
+  let loaded = crate::config::LoadedConfig::load_with_overrides(&main, &overrides).await.unwrap();
+  assert_eq!(loaded.config.network.port, 2500, "the environment beats includes");
+  assert_eq!(loaded.config.identity.name, "1234", "--set beats the environment, and a string stays a string");
+  assert_eq!(loaded.config.peer.len(), 2, "list settings add entries like an include's do");
+  let sources = loaded.sources();
+  let source = |path: &str| sources.iter().find(|(p, _, _)| p == path).map(|(_, _, file)| file.map(|f| f.to_path_buf())).unwrap();
+  assert_eq!(source("network.port"), Some("WEVERYWHERE__NETWORK__PORT".into()));
+  assert_eq!(source("identity.name"), Some("--set identity.name".into()));
+  assert_eq!(source("network.multicast_ttl"), Some(include.clone()));
+  assert_eq!(source("peer[1].ipv4"), Some("--set peer".into()));
+
+  for bad in ["netwrk.port=1", "network.port.x=1", "network.port=many", "includes=[]"] {
+    let e = crate::config::LoadedConfig::load_with_overrides(&main, &[bad.parse().unwrap()]).await.unwrap_err();
+    assert!(e.to_string().starts_with("--set "), "{bad}: {e}");
+  }
+  std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+
+use crate::delivery::{DeliveryState, DeliveryTracker, DEADLINE, FIRST_RETRY, MAX_RETRY, PEER_WINDOW_S};
+
+const NOW_S: u64 = 1_000_000;
+
+fn tracker_with_peers(peers: &[(&[u8], u64)]) -> DeliveryTracker {
+  let mut tracker = DeliveryTracker::new(b"me");
+  for (pubkey, seen) in peers {
+    tracker.note_peer(pubkey, *seen);
+  }
+  tracker
+}
+
+#[test]
+fn retransmits_back_off_and_stop_once_every_expected_peer_acks() {
+  let mut tracker = tracker_with_peers(&[(b"a", NOW_S), (b"b", NOW_S - 60), (b"me", NOW_S)]);
+  let t0 = Instant::now();
+  assert_eq!(tracker.track(b"id", b"bytes".to_vec(), t0, NOW_S), 2, "we never wait on ourselves");
+
+  assert!(tracker.due(t0).is_empty(), "nothing is resent before the first retry");
+  let mut sent_at = Vec::new();
+  let mut t = t0;
+  while t < t0 + Duration::from_secs(12) {
+    if !tracker.due(t).is_empty() {
+      sent_at.push(t - t0);
+    }
+    t += Duration::from_millis(50);
+  }
+  let gaps: Vec<Duration> = sent_at.windows(2).map(|w| w[1] - w[0]).collect();
+  assert_eq!(sent_at[0], FIRST_RETRY);
+  assert_eq!(gaps[..3], [FIRST_RETRY * 2, FIRST_RETRY * 4, MAX_RETRY]);
+  assert!(gaps.iter().all(|g| *g <= MAX_RETRY));
+
+  assert!(tracker.ack(b"id", b"a"));
+  assert!(!tracker.ack(b"id", b"a"), "a repeated ack is no news");
+  assert!(!tracker.ack(b"id", b"me"));
+  assert!(!tracker.ack(b"other", b"a"), "an ack for something we didn't send reliably");
+  let status = tracker.status(b"id", t).unwrap();
+  assert_eq!((status.state, status.acked, status.expected), (DeliveryState::Pending, 1, 2));
+
+  tracker.ack(b"id", b"b");
+  assert_eq!(tracker.status(b"id", t).unwrap().state, DeliveryState::Delivered);
+  assert!(tracker.due(t + MAX_RETRY).is_empty());
+}
+
+#[test]
+fn sends_expire_at_the_deadline_and_only_wait_on_recent_peers() {
+  let mut tracker = tracker_with_peers(&[(b"a", NOW_S), (b"gone", NOW_S - PEER_WINDOW_S - 1)]);
+  let t0 = Instant::now();
+  assert_eq!(tracker.track(b"id", b"bytes".to_vec(), t0, NOW_S), 1, "a peer not heard from for too long isn't waited on");
+  assert_eq!(tracker.status(b"id", t0 + DEADLINE - Duration::from_millis(1)).unwrap().state, DeliveryState::Pending);
+  assert_eq!(tracker.status(b"id", t0 + DEADLINE).unwrap().state, DeliveryState::Expired);
+  assert!(tracker.due(t0 + DEADLINE).is_empty(), "an expired send isn't retransmitted");
+  assert!(tracker.status(b"unknown", t0).is_none());
+
+  // With nobody known, the first ack from anyone counts as delivered.
+  let mut alone = DeliveryTracker::new(b"me");
+  alone.track(b"id", Vec::new(), t0, NOW_S);
+  assert_eq!(alone.status(b"id", t0).unwrap().state, DeliveryState::Pending);
+  alone.ack(b"id", b"stranger");
+  let status = alone.status(b"id", t0).unwrap();
+  assert_eq!((status.state, status.acked, status.expected), (DeliveryState::Delivered, 1, 0));
+}
+
+#[test]
+fn a_direct_message_waits_only_on_its_recipient() {
+  let mut tracker = tracker_with_peers(&[(b"a", NOW_S), (b"b", NOW_S)]);
+  let t0 = Instant::now();
+  assert_eq!(tracker.track_to(b"id", b"bytes".to_vec(), b"c", t0), 1, "the recipient, heard from lately or not");
+  assert!(tracker.ack(b"id", b"a"));
+  assert_eq!(tracker.status(b"id", t0).unwrap().state, DeliveryState::Pending, "other nodes' acks don't deliver it");
+  assert!(tracker.ack(b"id", b"c"));
+  let status = tracker.status(b"id", t0).unwrap();
+  assert_eq!((status.state, status.acked, status.expected), (DeliveryState::Delivered, 2, 1));
+}
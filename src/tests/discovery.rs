@@ -76,3 +76,78 @@ fn attestation_signs_and_verifies_and_binds_identity() {
   let tampered_bytes = serde_cbor::to_vec(&tampered).unwrap();
   assert!(verify_attestation_cbor(&tampered_bytes).is_err());
 }
+
+#[test]
+fn target_query_matches_hostname_prefix_or_full_key() {
+  let key: Vec<u8> = (0u8..32).map(|i| i.wrapping_mul(7).wrapping_add(0x39)).collect();
+  let hex = crate::crypto_utils::to_hex(&key);
+
+  // A hostname matches case-insensitively, and never a key.
+  let by_name = TargetQuery::parse(" BuildBox ");
+  assert_eq!(by_name.pubkey, None);
+  assert!(by_name.matches(Some("buildbox"), Some(&key)));
+  assert!(!by_name.matches(Some("other"), Some(&key)));
+
+  // A short-id (or longer prefix) matches the key, in either case.
+  let short = TargetQuery::parse(&hex[..8].to_ascii_uppercase());
+  assert!(short.matches(Some("whatever"), Some(&key)));
+  assert!(!short.matches(Some("whatever"), Some(&[0u8; 32])));
+  // ...but fewer than 8 hex chars is only ever a hostname.
+  assert!(!TargetQuery::parse(&hex[..6]).matches(None, Some(&key)));
+
+  // A full key parses to bytes and matches that key alone, whatever the node calls itself.
+  let full = TargetQuery::parse(&hex);
+  assert_eq!(full.pubkey.as_deref(), Some(key.as_slice()));
+  assert!(full.matches(Some("anything"), Some(&key)));
+  let mut other = key.clone();
+  other[31] ^= 1;
+  assert!(!full.matches(Some(&hex), Some(&other)));
+}
+
+#[test]
+fn collection_stops_on_idle_expected_nodes_or_the_hard_cap() {
+  use std::time::{Duration, Instant};
+  let ms = Duration::from_millis;
+  let t0 = Instant::now();
+  let (a, b) = (vec![1u8; 32], vec![2u8; 32]);
+
+  // Nobody answers: give a cold daemon the first-record allowance, not the whole timeout.
+  let c = Collection::new(CollectPolicy::default(), t0);
+  assert_eq!(c.deadline(), t0 + FIRST_RECORD_WAIT);
+
+  // Records keep the window open by `idle` each, up to the hard cap.
+  let policy = CollectPolicy { timeout: Duration::from_secs(5), idle: ms(1000), ..Default::default() };
+  let mut c = Collection::new(policy.clone(), t0);
+  c.note_record(Some(&a), t0 + ms(300));
+  assert_eq!(c.deadline(), t0 + ms(1300));
+  c.note_record(None, t0 + ms(4500));
+  assert_eq!(c.deadline(), t0 + Duration::from_secs(5), "the hard cap wins");
+  assert!(c.is_done(t0 + Duration::from_secs(5)));
+
+  // Once every known peer has answered, only a short settle is left.
+  let expecting = CollectPolicy { expected_keys: [a.clone(), b.clone()].into_iter().collect(), ..policy.clone() };
+  let mut c = Collection::new(expecting, t0);
+  c.note_record(Some(&a), t0 + ms(100));
+  assert!(!c.expected_answered());
+  c.note_record(Some(&b), t0 + ms(200));
+  assert!(c.expected_answered());
+  assert_eq!(c.deadline(), t0 + ms(200) + EXPECTED_SETTLE);
+
+  let counting = CollectPolicy { expected_count: Some(2), ..policy };
+  let mut c = Collection::new(counting, t0);
+  c.note_record(Some(&a), t0 + ms(100));
+  c.note_record(Some(&a), t0 + ms(150));
+  assert!(!c.expected_answered(), "the same node twice is one answer");
+
+  let fixed = Collection::new(CollectPolicy::fixed(ms(700)), t0);
+  assert_eq!(fixed.deadline(), t0 + ms(700));
+}
+
+#[test]
+fn relays_hand_children_what_is_left_minus_a_margin() {
+  use std::time::Duration;
+  assert_eq!(CollectPolicy::default().time_budget_ms(), 30_000);
+  assert_eq!(child_time_budget_ms(Duration::from_millis(2000)), Some(2000 - HOP_DEADLINE_MARGIN.as_millis() as u32));
+  assert_eq!(child_time_budget_ms(HOP_DEADLINE_MARGIN), None, "no time left for records to come back");
+  assert_eq!(child_time_budget_ms(Duration::ZERO), None);
+}
@@ -1,4 +1,7 @@
-use crate::executor::MessageStore;
+use crate::config::IdentityData;
+use crate::executor::{store_signed_message, MessageStore};
+use crate::messages::{room_message, NetworkMessage};
+use crate::tests::identity;
 
 #[test]
 fn message_store_assigns_monotonic_seqs_and_filters_by_after() {
@@ -47,3 +50,363 @@ fn message_store_evicts_oldest_past_capacity() {
   assert_eq!(all.iter().map(|m| m.seq).collect::<Vec<_>>(), vec![3, 4, 5]);
   assert_eq!(all[0].text, vec![2u8]); // the 3rd message pushed (0-indexed 2)
 }
+
+#[test]
+fn direct_messages_are_read_by_their_two_ends_and_held_opaque_by_others() {
+  let (alice_key, alice) = identity("alice");
+  let (bob_key, bob) = identity("bob");
+  let (carol_key, _) = identity("carol");
+  let payload = b"\x81\x64psst".to_vec();
+  let dm = room_message(&alice, &alice_key, "ops", Some(&bob.encoded_public_key), payload.clone(), false).unwrap();
+
+  for key in [&bob_key, &alice_key] {
+    let mut store = MessageStore::new(16);
+    assert_eq!(store_signed_message(&mut store, &dm, 7, Some(key)), 1);
+    let read = store.read_after(0);
+    assert_eq!(read[0].text, payload);
+    assert_eq!(read[0].to.as_deref(), Some(bob.encoded_public_key.as_slice()));
+    assert_eq!((read[0].room.as_str(), read[0].from_name.as_str()), ("ops", "alice"));
+  }
+
+  // A third node keeps it only to replay to alice or bob; it never reaches its programs.
+  let mut store = MessageStore::new(16);
+  assert_eq!(store_signed_message(&mut store, &dm, 7, Some(&carol_key)), 1);
+  assert!(store.read_after(0).is_empty());
+  assert!(store.read_room_after("ops", 0).is_empty());
+  assert_eq!(store.history_for(None, 0, &bob.encoded_public_key, 10).len(), 1);
+  assert!(store.history_for(None, 0, carol_key.verifying_key().as_bytes(), 10).is_empty());
+}
+
+#[test]
+fn room_reads_and_history_replays_filter_by_room_time_and_requester() {
+  let (alice_key, alice) = identity("alice");
+  let (bob_key, bob) = identity("bob");
+  let (_, carol) = identity("carol");
+  let lobby = NetworkMessage::SignedFabricMessage {
+    source: alice.clone(),
+    id: b"lobby-1".to_vec(),
+    cbor_data: b"\x81\x62hi".to_vec(),
+    signature: IdentityData::sign_payload(&alice_key, b"lobby-1", b"\x81\x62hi").to_bytes().to_vec(),
+  };
+  let ops = room_message(&alice, &alice_key, "ops", None, b"\x81\x63ops".to_vec(), false).unwrap();
+  let dev = room_message(&alice, &alice_key, "dev", None, b"\x81\x63dev".to_vec(), false).unwrap();
+  let dm = room_message(&alice, &alice_key, "dev", Some(&bob.encoded_public_key), b"\x81\x62dm".to_vec(), false).unwrap();
+
+  let mut store = MessageStore::new(16);
+  for (msg, epoch) in [(&lobby, 10), (&ops, 20), (&dev, 30), (&dm, 40)] {
+    assert!(store_signed_message(&mut store, msg, epoch, Some(&bob_key)) > 0);
+  }
+  // Pushed locally by a program: readable, but there is no signature to replay.
+  store.push("local".into(), vec![], b"", b"x".to_vec(), 50);
+  // The same signed message arriving again (a history replay of something we already have) is a duplicate.
+  assert_eq!(store_signed_message(&mut store, &ops, 99, Some(&bob_key)), 0);
+
+  let rooms = |msgs: Vec<crate::executor::StoredMessage>| msgs.iter().map(|m| m.text.clone()).collect::<Vec<_>>();
+  assert_eq!(rooms(store.read_room_after("ops", 0)), vec![b"\x81\x63ops".to_vec(), b"\x81\x62dm".to_vec()]);
+  assert_eq!(rooms(store.read_room_after("", 0)), vec![b"\x81\x62hi".to_vec(), b"\x81\x62dm".to_vec(), b"x".to_vec()]);
+  assert_eq!(store.read_after(0).len(), 5);
+
+  let epochs = |entries: Vec<crate::messages::HistoryEntry>| entries.iter().map(|e| e.epoch_s).collect::<Vec<_>>();
+  assert_eq!(epochs(store.history_for(None, 0, &carol.encoded_public_key, 10)), vec![10, 20, 30]);
+  assert_eq!(epochs(store.history_for(None, 0, &bob.encoded_public_key, 10)), vec![10, 20, 30, 40]);
+  assert_eq!(epochs(store.history_for(Some("dev"), 25, &bob.encoded_public_key, 10)), vec![30, 40]);
+  assert_eq!(epochs(store.history_for(None, 0, &bob.encoded_public_key, 2)), vec![30, 40], "the newest `max`");
+  assert_eq!(store.newest_signed_epoch(), Some(40));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn message_history_survives_an_executor_restart_when_enabled() {
+  let mut config = crate::tests::temp_config("message-history");
+  config.state.message_history = true;
+  let dir = config.state.dir.clone();
+  let (alice_key, alice) = identity("alice");
+  let said = room_message(&alice, &alice_key, "ops", None, b"\x81\x62hi".to_vec(), false).unwrap();
+
+  let executor = crate::executor::Executor::new(&config).await;
+  assert_eq!(executor.record_signed_message(&said, 77), 1);
+  drop(executor);
+
+  let restarted = crate::executor::Executor::new(&config).await;
+  let stored = restarted.messages_after(0);
+  assert_eq!(stored.len(), 1);
+  assert_eq!((stored[0].room.as_str(), stored[0].epoch_s), ("ops", 77));
+  // Reloading doesn't append the same messages to the file a second time.
+  let text = std::fs::read_to_string(dir.join(crate::message_history::FILE_NAME)).unwrap();
+  assert_eq!(text.lines().count(), 1);
+
+  let mut off = config.clone();
+  off.state.message_history = false;
+  assert!(crate::executor::Executor::new(&off).await.messages_after(0).is_empty());
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn applying_a_reloaded_config_swaps_the_trust_set_and_peers() {
+  let dir = std::env::temp_dir().join(format!("weverywhere-apply-config-{}", std::process::id()));
+  std::fs::create_dir_all(&dir).unwrap();
+  let keyfile = dir.join("identity.pem");
+  crate::crypto_utils::generate_private_key_ed25519_pem_file(&keyfile).await.unwrap();
+  let own = crate::crypto_utils::read_private_key_ed25519_pem_file(&keyfile).await.unwrap().verifying_key();
+  let (alice, _) = identity("alice");
+  let (bob, _) = identity("bob");
+  let config_trusting = |key: &ed25519_dalek::SigningKey, peer: &str| -> crate::config::Config {
+    toml::from_str(&format!(
+      "[identity]\nname = \"t\"\nkeyfile = {:?}\n\n[state]\ndir = {:?}\n\n[[trusted]]\nkey = \"{} {}\"\n\n[[peer]]\nipv4 = \"{}\"\n",
+      keyfile.to_string_lossy(), dir.to_string_lossy(),
+      crate::crypto_utils::format_verifying_key(&key.verifying_key()), "alice@laptop", peer,
+    )).unwrap()
+  };
+
+  let executor = crate::executor::Executor::new(&config_trusting(&alice, "10.0.0.1")).await;
+  let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(3);
+  while !executor.trusts_pubkey(alice.verifying_key().as_bytes()) && tokio::time::Instant::now() < deadline {
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+  }
+  assert!(executor.trusts_pubkey(alice.verifying_key().as_bytes()), "[[trusted]] keys are trusted from the start");
+  assert!(executor.trusts_pubkey(own.as_bytes()));
+
+  executor.apply_config(&config_trusting(&bob, "10.0.0.2"));
+  assert!(executor.trusts_pubkey(bob.verifying_key().as_bytes()));
+  assert!(!executor.trusts_pubkey(alice.verifying_key().as_bytes()), "a key dropped from the file is no longer trusted");
+  assert!(executor.trusts_pubkey(own.as_bytes()), "we always trust ourselves");
+  assert_eq!(executor.config().peer[0].label(), "ipv4=10.0.0.2");
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Reads its stdin to EOF and returns it as the map {1: bytes}, the length always written as a
+/// two-byte CBOR header.
+const STDIN_TO_MAP_WAT: &str = r#"(module
+  (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+  (import "host" "return_map" (func $return_map (param i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 1000) "\a1\01\59")
+  (func (export "_start")
+    (local $n i32)
+    (block $done
+      (loop $more
+        (i32.store (i32.const 0) (i32.add (i32.const 1005) (local.get $n)))
+        (i32.store (i32.const 4) (i32.sub (i32.const 4096) (local.get $n)))
+        (br_if $done (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+        (br_if $done (i32.eqz (i32.load (i32.const 8))))
+        (local.set $n (i32.add (local.get $n) (i32.load (i32.const 8))))
+        (br $more)))
+    (i32.store8 (i32.const 1003) (i32.shr_u (local.get $n) (i32.const 8)))
+    (i32.store8 (i32.const 1004) (local.get $n))
+    (drop (call $return_map (i32.const 1000) (i32.add (local.get $n) (i32.const 5))))))"#;
+
+/// An executor with no identity key (so it trusts nobody), its state in a temp dir named for `test`.
+async fn keyless_executor(test: &str) -> (std::sync::Arc<crate::executor::Executor>, std::path::PathBuf) {
+  let config = crate::tests::temp_config(test);
+  (crate::executor::Executor::new(&config).await, config.state.dir)
+}
+
+/// Start `wat` on `executor` as request `uuid`; returns its pid and where its returned map lands.
+async fn launch(executor: &crate::executor::Executor, wat: &str, uuid: [u8; 16], opts: crate::executor::ExecOptions) -> (u64, std::sync::Arc<std::sync::Mutex<crate::executor::ExecReturn>>) {
+  let (_, source) = identity("client");
+  let program = crate::executor::ProgramDataBuilder::new()
+    .set_human_name("test-program")
+    .set_wasm_program_bytes(wat.as_bytes())
+    .set_source(&source)
+    .set_request_context(uuid, 0, Vec::new())
+    .build()
+    .unwrap();
+  let slot = std::sync::Arc::new(std::sync::Mutex::new(crate::executor::ExecReturn::default()));
+  let forwarder = crate::executor::wasi_adapters::WasiStdioSimpleForwarder::new_nop();
+  (executor.begin_exec(&program, forwarder, opts, slot.clone()).await.unwrap(), slot)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stdin_chunks_reach_a_running_program_in_order_and_gaps_wait_for_a_resend() {
+  let (executor, dir) = keyless_executor("feed-stdin").await;
+  let owner: std::net::SocketAddr = "10.0.0.1:4000".parse().unwrap();
+
+  // Each chunk is (seq, data, eof); returns what the program read and the `next_seq` each chunk was
+  // acknowledged with.
+  let run = async |uuid: [u8; 16], chunks: &[(u64, &[u8], bool)]| -> (Vec<u8>, Vec<u64>) {
+    let opts = crate::executor::ExecOptions { stdin: Some(crate::executor::StdinPipeOptions { owner: Some(owner) }), ..Default::default() };
+    let (pid, slot) = launch(&executor, STDIN_TO_MAP_WAT, uuid, opts).await;
+    let acks = chunks.iter().map(|(seq, data, eof)| {
+      let (acked_pid, next_seq) = executor.feed_stdin(uuid, pid, Some(owner), *seq, data.to_vec(), *eof).expect("the pipe matched");
+      assert_eq!(acked_pid, pid);
+      next_seq
+    }).collect();
+    assert!(executor.feed_stdin(uuid, pid, Some("10.0.0.2:4000".parse().unwrap()), 0, b"x".to_vec(), false).is_none(), "only its caller feeds it");
+    assert_eq!(executor.wait_for_pid_exit_status(pid).await.exit_code, 0);
+    let map = slot.lock().unwrap().map.clone().expect("the program returned its input");
+    (map[5..].to_vec(), acks)
+  };
+
+  let (read, acks) = run([1; 16], &[(0, b"hello ", false), (0, b"hello ", false), (1, b"world", false), (2, b"", true), (2, b"", true)]).await;
+  assert_eq!(read, b"hello world");
+  assert_eq!(acks, [1, 1, 2, 3, 3], "repeats are dropped but acknowledged again");
+
+  // Chunk 1 is lost the first time: what follows waits for it instead of ending the input there.
+  let (read, acks) = run([2; 16], &[(0, b"abc", false), (2, b"ghi", false), (3, b"", true), (1, b"def", false), (2, b"ghi", false), (3, b"", true)]).await;
+  assert_eq!(read, b"abcdefghi");
+  assert_eq!(acks, [1, 1, 1, 2, 3, 4]);
+  let _ = std::fs::remove_dir_all(&dir);
+}
+
+// A program that isn't reading its stdin holds the stream back: past the pipe's bounded queue, chunks
+// are refused (their seq not acknowledged) until it catches up.
+#[tokio::test(flavor = "multi_thread")]
+async fn stdin_past_the_queue_is_refused_while_the_program_does_not_read() {
+  let mut config = crate::tests::temp_config("stdin-queue");
+  config.limits.untrusted = crate::config::Limit { max_cpu_instructions: u64::MAX / 2, ..Default::default() };
+  let executor = crate::executor::Executor::new(&config).await;
+  let owner: std::net::SocketAddr = "10.0.0.1:4000".parse().unwrap();
+  let spin = r#"(module (memory (export "memory") 1) (func (export "_start") (loop $spin (br $spin))))"#;
+  let opts = crate::executor::ExecOptions { owner: Some(owner), stdin: Some(crate::executor::StdinPipeOptions { owner: Some(owner) }), ..Default::default() };
+  let uuid = [3; 16];
+  let (pid, _) = launch(&executor, spin, uuid, opts).await;
+
+  let chunk = vec![b'x'; 8 * 1024];
+  let refused_at = (0..1000).find(|seq| executor.feed_stdin(uuid, pid, Some(owner), *seq, chunk.clone(), false) == Some((pid, *seq)));
+  let refused_at = refused_at.expect("the queue filled up");
+  assert!(refused_at >= 32, "a full queue's worth is taken first (refused at {refused_at})");
+  assert_eq!(executor.feed_stdin(uuid, pid, Some(owner), refused_at, chunk.clone(), false), Some((pid, refused_at)), "and stays refused");
+
+  assert!(executor.kill_program(uuid, pid, Some(owner)));
+  executor.wait_for_pid_exit_status(pid).await;
+  assert!(executor.feed_stdin(uuid, pid, Some(owner), refused_at, chunk, false).is_none(), "its pipe went with it");
+  let _ = std::fs::remove_dir_all(&config.state.dir);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn exit_statuses_say_why_a_program_stopped() {
+  use crate::messages::ExitReason;
+  let (executor, dir) = keyless_executor("exit-status").await;
+  let with_start = |body: &str| format!(
+    r#"(module
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+  (memory (export "memory") 1)
+  (func (export "_start") {body}))"#
+  );
+  let cases = [
+    (with_start("nop"), 0, ExitReason::Exited),
+    (with_start("(call $proc_exit (i32.const 3))"), 3, ExitReason::Exited),
+    (with_start("(loop $spin (br $spin))"), 1, ExitReason::OutOfFuel),
+    (with_start("unreachable"), 1, ExitReason::Unreachable),
+    (with_start("(drop (i32.load (i32.const 70000)))"), 1, ExitReason::Trap("wasm trap: out of bounds memory access".into())),
+  ];
+  for (i, (wat, exit_code, reason)) in cases.into_iter().enumerate() {
+    let (pid, _) = launch(&executor, &wat, [i as u8; 16], Default::default()).await;
+    let status = executor.wait_for_pid_exit_status(pid).await;
+    assert_eq!((status.exit_code, &status.reason), (exit_code, &reason), "case {i}");
+    assert!(status.fuel_consumed.is_some(), "case {i} is metered");
+    assert!(status.backtrace.is_empty(), "case {i}: only trusted callers get a backtrace");
+  }
+
+  let (pid, _) = launch(&executor, "(module (memory (export \"memory\") 1))", [9; 16], Default::default()).await;
+  let status = executor.wait_for_pid_exit_status(pid).await;
+  assert_eq!(status.exit_code, 1);
+  assert!(matches!(status.reason, ExitReason::StartFailed(_)), "{:?}", status.reason);
+  let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn configured_keys_are_trusted_even_without_our_own_key() {
+  let mut config = crate::tests::temp_config("keyless-trust");
+  let (alice, _) = identity("alice");
+  config.trusted.push(crate::config::SingleTrustedKey { key: format!("{} alice@laptop", crate::crypto_utils::format_verifying_key(&alice.verifying_key())) });
+  let executor = crate::executor::Executor::new(&config).await;
+  let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(3);
+  while !executor.trusts_pubkey(alice.verifying_key().as_bytes()) && tokio::time::Instant::now() < deadline {
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+  }
+  assert!(executor.trusts_pubkey(alice.verifying_key().as_bytes()), "our keyfile is missing, yet [[trusted]] keys still count");
+  let _ = std::fs::remove_dir_all(&config.state.dir);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_reloaded_limit_sets_the_next_programs_budget() {
+  use crate::messages::ExitReason;
+  let mut config = crate::tests::temp_config("reload-limits");
+  config.limits.untrusted = crate::config::Limit { max_cpu_instructions: 10_000_000, max_memory_bytes: 4 * 65536, ..Default::default() };
+  let executor = crate::executor::Executor::new(&config).await;
+  let run = async |wat: &str, uuid: [u8; 16]| {
+    let (pid, _) = launch(&executor, wat, uuid, Default::default()).await;
+    executor.wait_for_pid_exit_status(pid).await
+  };
+  // Counts to 100,000, well past the built-in 128,000 fuel but inside 10,000,000.
+  let counter = r#"(module
+  (memory (export "memory") 1)
+  (func (export "_start")
+    (local $i i32)
+    (loop $more
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br_if $more (i32.lt_u (local.get $i) (i32.const 100000))))))"#;
+  // Grows its memory to 17 pages, then exits with the old size plus one (2).
+  let grower = r#"(module
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+  (memory (export "memory") 1)
+  (func (export "_start") (call $proc_exit (i32.add (memory.grow (i32.const 16)) (i32.const 1)))))"#;
+
+  let status = run(counter, [1; 16]).await;
+  assert_eq!((status.exit_code, &status.reason), (0, &ExitReason::Exited));
+  assert!(status.fuel_consumed.is_some_and(|fuel| fuel > 128_000), "{:?}", status.fuel_consumed);
+  let status = run(grower, [2; 16]).await;
+  assert_eq!((status.exit_code, &status.reason), (1, &ExitReason::OutOfMemory), "4 pages is the cap");
+
+  config.limits.untrusted = crate::config::Limit { max_cpu_instructions: 1000, max_memory_bytes: 0, ..Default::default() };
+  executor.apply_config(&config);
+  assert_eq!(run(counter, [3; 16]).await.reason, ExitReason::OutOfFuel, "the new budget applies to the next program");
+  assert_eq!(run(grower, [4; 16]).await.exit_code, 2, "0 lifts the memory cap");
+  let _ = std::fs::remove_dir_all(&config.state.dir);
+}
+
+// The host can stop a program whether it is computing or waiting on its stdin: its caller may kill
+// it, and it is stopped once it runs past its wall-clock limit.
+#[tokio::test(flavor = "multi_thread")]
+async fn programs_stop_when_killed_or_out_of_time() {
+  use crate::messages::ExitReason;
+  let mut config = crate::tests::temp_config("kill");
+  config.limits.untrusted = crate::config::Limit { max_cpu_instructions: u64::MAX / 2, ..Default::default() };
+  let executor = crate::executor::Executor::new(&config).await;
+  let (owner, stranger): (std::net::SocketAddr, std::net::SocketAddr) = ("10.0.0.1:4000".parse().unwrap(), "10.0.0.2:4000".parse().unwrap());
+  let spin = r#"(module (memory (export "memory") 1) (func (export "_start") (loop $spin (br $spin))))"#;
+  let owned = |stdin: bool| crate::executor::ExecOptions {
+    owner: Some(owner),
+    stdin: stdin.then_some(crate::executor::StdinPipeOptions { owner: Some(owner) }),
+    ..Default::default()
+  };
+
+  for (i, (wat, stdin)) in [(spin, false), (STDIN_TO_MAP_WAT, true)].into_iter().enumerate() {
+    let uuid = [i as u8 + 1; 16];
+    let (pid, _) = launch(&executor, wat, uuid, owned(stdin)).await;
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    assert!(!executor.kill_program(uuid, pid, Some(stranger)), "case {i}: only the caller may stop it");
+    assert!(!executor.kill_program([9; 16], pid, Some(owner)), "case {i}: nor for another request");
+    assert!(executor.kill_program(uuid, 0, Some(owner)), "case {i}: found by its request");
+    let status = executor.wait_for_pid_exit_status(pid).await;
+    assert_eq!((status.exit_code, &status.reason), (1, &ExitReason::HostKilled), "case {i}");
+    assert!(!executor.kill_program(uuid, pid, Some(owner)), "case {i}: it's gone");
+  }
+
+  config.limits.untrusted.max_wall_clock_ms = 200;
+  executor.apply_config(&config);
+  for (i, (wat, stdin)) in [(spin, false), (STDIN_TO_MAP_WAT, true)].into_iter().enumerate() {
+    let started = std::time::Instant::now();
+    let (pid, _) = launch(&executor, wat, [i as u8 + 10; 16], owned(stdin)).await;
+    let status = executor.wait_for_pid_exit_status(pid).await;
+    assert_eq!((status.exit_code, &status.reason), (1, &ExitReason::WallClockTimeout), "case {i}");
+    assert!(started.elapsed() >= std::time::Duration::from_millis(200), "case {i}");
+  }
+  let _ = std::fs::remove_dir_all(&config.state.dir);
+}
+
+// A forged identity - one whose self-signature doesn't cover what it claims - never becomes a peer,
+// so it can't be persisted, listed, pinned or chased for acks.
+#[tokio::test]
+async fn only_self_signed_identities_are_noted_as_peers() {
+  let (executor, dir) = keyless_executor("forged-peer").await;
+  let (_, honest) = identity("honest");
+  let (_, mut forged) = identity("forged");
+  forged.human_name = "someone-else".into();
+  executor.note_peer("10.0.0.1:4000".parse().unwrap(), &honest);
+  executor.note_peer("10.0.0.2:4000".parse().unwrap(), &forged);
+  let targets = executor.observed_targets();
+  assert_eq!(targets.len(), 1);
+  assert_eq!(targets[0].1, honest.encoded_public_key);
+  let _ = std::fs::remove_dir_all(dir);
+}
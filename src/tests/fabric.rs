@@ -0,0 +1,645 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use crate::command::run::{self, ExitWait, TargetNode};
+use crate::discovery::random_uuid16;
+use crate::fanout::{FanOut, FanOutPolicy};
+use crate::messages::NetworkMessage;
+use crate::tests::fabric_sim::{Fabric, Link, SimClient, SimNode, GROUP};
+
+/// Prints a line and exits 0.
+const HELLO_WAT: &str = r#"(module
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 100) "hi\n")
+  (func (export "_start")
+    (i32.store (i32.const 0) (i32.const 100))
+    (i32.store (i32.const 4) (i32.const 3))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 20)))))"#;
+
+/// A minimal discovery program: returns the record {1: this node's signed attestation}, with the
+/// byte-string length always written as a two-byte CBOR header.
+const ATTEST_WAT: &str = r#"(module
+  (import "host" "signed_attestation" (func $attest (param i32 i32) (result i32)))
+  (import "host" "return_map" (func $return_map (param i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 1000) "\a1\01\59")
+  (func (export "_start")
+    (local $n i32)
+    (local.set $n (call $attest (i32.const 1005) (i32.const 4096)))
+    (i32.store8 (i32.const 1003) (i32.shr_u (local.get $n) (i32.const 8)))
+    (i32.store8 (i32.const 1004) (local.get $n))
+    (drop (call $return_map (i32.const 1000) (i32.add (local.get $n) (i32.const 5))))))"#;
+
+/// Draws "ready" on its terminal, then echoes the first key it is sent and exits.
+const TTY_ECHO_WAT: &str = r#"(module
+  (import "host" "tty_print" (func $print (param i32 i32) (result i32)))
+  (import "host" "tty_flush" (func $flush (result i32)))
+  (import "host" "tty_next_event" (func $next (param i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 100) "ready")
+  (func (export "_start")
+    (local $n i32)
+    (drop (call $print (i32.const 100) (i32.const 5)))
+    (drop (call $flush))
+    ;; A protocol 1 key is [CHAR][its UTF-8]; print the UTF-8.
+    (local.set $n (call $next (i32.const 200) (i32.const 64) (i32.const 10000)))
+    (drop (call $print (i32.const 201) (i32.sub (local.get $n) (i32.const 1))))
+    (drop (call $flush))))"#;
+
+/// Sleeps for 5 s without a word, then exits 7.
+const QUIET_WAT: &str = r#"(module
+  (import "wasi_snapshot_preview1" "poll_oneoff" (func $poll (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))
+  (memory (export "memory") 1)
+  (func (export "_start")
+    ;; One relative monotonic-clock subscription at 0 (tag 0 at 8, clock id at 16, timeout at 24).
+    (i32.store (i32.const 16) (i32.const 1))
+    (i64.store (i32.const 24) (i64.const 5000000000))
+    (drop (call $poll (i32.const 0) (i32.const 128) (i32.const 1) (i32.const 256)))
+    (call $exit (i32.const 7))))"#;
+
+/// Reads stdin to the end and exits with how many KiB it read.
+const COUNT_STDIN_WAT: &str = r#"(module
+  (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))
+  (memory (export "memory") 1)
+  (func (export "_start")
+    (local $total i32)
+    (i32.store (i32.const 0) (i32.const 1024))
+    (i32.store (i32.const 4) (i32.const 32768))
+    (block $done
+      (loop $more
+        (br_if $done (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+        (br_if $done (i32.eqz (i32.load (i32.const 8))))
+        (local.set $total (i32.add (local.get $total) (i32.load (i32.const 8))))
+        (br $more)))
+    (call $exit (i32.shr_u (local.get $total) (i32.const 10)))))"#;
+
+fn execute(program_data: crate::executor::ProgramData) -> NetworkMessage {
+  NetworkMessage::execute_request(program_data)
+}
+
+/// `run --to node`'s view of `node`: its address, and the key its replies must carry.
+fn target(node: &SimNode) -> TargetNode {
+  TargetNode { addr: node.addr, pubkey: Some(node.pubkey.clone()), label: node.name.clone(), peer: None }
+}
+
+/// The identities that signed a ProgramAccepted for `uuid` among `replies`.
+fn accepted_by(replies: &[(std::net::SocketAddr, NetworkMessage)], uuid: [u8; 16]) -> Vec<Vec<u8>> {
+  replies.iter().filter_map(|(_, m)| match m {
+    NetworkMessage::ProgramAccepted { request_uuid, node, .. } if *request_uuid == uuid => Some(node.encoded_public_key.clone()),
+    _ => None,
+  }).collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fabric_run_reaches_every_node_and_addressed_run_only_its_target() {
+  let mut fabric = Fabric::new();
+  let a = fabric.add_node("a", &[]).await;
+  let b = fabric.add_node("b", &[]).await;
+  let c = fabric.add_node("c", &[]).await;
+  let client = fabric.add_client("origin").await;
+
+  // `run --fabric`: one multicast copy; all three nodes identify themselves, run it and exit 0.
+  let uuid = random_uuid16();
+  let policy = FanOutPolicy { min_responders: 3, max_responders: Some(3), timeout: Some(Duration::from_secs(10)), ..Default::default() };
+  let fan_out = FanOut::new(uuid, policy, HashSet::new());
+  run::fan_out_via(&client.endpoint, &execute(client.program(HELLO_WAT, uuid, 0, None)), GROUP, &fan_out, None).await.unwrap();
+  assert_eq!(fan_out.finish().expect("all three nodes respond"), 0);
+
+  // An addressed multicast: a and c leave it alone.
+  let uuid = random_uuid16();
+  client.send(&execute(client.program(HELLO_WAT, uuid, 0, Some(b.pubkey.clone()))), GROUP).await;
+  let accepted = accepted_by(&client.recv_all(Duration::from_millis(1500)).await, uuid);
+  assert_eq!(accepted, vec![b.pubkey.clone()]);
+  assert!(!accepted.contains(&a.pubkey) && !accepted.contains(&c.pubkey));
+
+  // `run --to b`: b confirms and its exit is the run's; sent to a instead, nobody confirms.
+  let uuid = random_uuid16();
+  let request = execute(client.program(HELLO_WAT, uuid, 0, Some(b.pubkey.clone())));
+  assert_eq!(run::run_unicast(&client.endpoint, &request, b.addr, uuid, None, Some(&target(&b)), ExitWait::Idle).await.unwrap(), Some(0));
+  let uuid = random_uuid16();
+  let request = execute(client.program(HELLO_WAT, uuid, 0, Some(b.pubkey.clone())));
+  let wrong_node = TargetNode { addr: a.addr, ..target(&b) };
+  assert!(run::run_unicast(&client.endpoint, &request, a.addr, uuid, None, Some(&wrong_node), ExitWait::Idle).await.is_err());
+}
+
+// What `submit` relies on: once the node has accepted a job, its exit is waited for however quiet the
+// job is, rather than the job counting as lost after the usual idle window.
+#[tokio::test(flavor = "multi_thread")]
+async fn accepted_jobs_are_waited_for_until_they_exit() {
+  let mut fabric = Fabric::new();
+  let node = fabric.add_node("a", &[]).await;
+  let (patient, hasty, idle) = (fabric.add_client("patient").await, fabric.add_client("hasty").await, fabric.add_client("idle").await);
+  let place = async |client: &SimClient, exit_wait: ExitWait| {
+    let uuid = random_uuid16();
+    let request = execute(client.program(QUIET_WAT, uuid, 0, Some(node.pubkey.clone())));
+    let started = tokio::time::Instant::now();
+    let exit = run::run_unicast(&client.endpoint, &request, node.addr, uuid, None, Some(&target(&node)), exit_wait).await.expect("the node accepts");
+    (exit, started.elapsed())
+  };
+  let (until_exit, timed_out, idle_window) = tokio::join!(
+    place(&patient, ExitWait::UntilExit(None)),
+    place(&hasty, ExitWait::UntilExit(Some(Duration::from_secs(1)))),
+    place(&idle, ExitWait::Idle),
+  );
+  assert_eq!(until_exit.0, Some(7));
+  assert!(until_exit.1 >= Duration::from_secs(5));
+  assert_eq!(timed_out.0, None);
+  assert!(timed_out.1 < Duration::from_secs(3), "--timeout cuts the wait short ({:?})", timed_out.1);
+  assert_eq!(idle_window.0, None, "a plain run gives up on a quiet program");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_stdin_arrives_whole_despite_lost_chunks_and_a_stall_is_an_error() {
+  let mut fabric = Fabric::new();
+  let node = fabric.add_node("a", &[]).await;
+  let client = fabric.add_client("origin").await;
+  let input = std::env::temp_dir().join(format!("weverywhere-stdin-{}", crate::crypto_utils::to_hex(&random_uuid16()[..8])));
+  std::fs::write(&input, vec![b'x'; 100 * 1024]).unwrap();
+  let run_with_stdin = async || {
+    let uuid = random_uuid16();
+    let mut program = client.program(COUNT_STDIN_WAT, uuid, 0, None);
+    program.options.wants_stdin = true;
+    run::run_unicast(&client.endpoint, &execute(program), node.addr, uuid, Some(&input), None, ExitWait::Idle).await
+  };
+
+  // The first copies of chunks 1 and 4 are lost; they are sent again and the program reads it all.
+  let mut lost = HashSet::new();
+  fabric.drop_when(client.ip(), node.ip(), move |msg| matches!(msg, NetworkMessage::ProgramStdin { seq: seq @ (1 | 4), .. } if lost.insert(*seq)));
+  assert_eq!(run_with_stdin().await.unwrap(), Some(100));
+
+  // Past chunk 2 nothing gets through: rather than a clean exit on short input, the run fails.
+  fabric.drop_when(client.ip(), node.ip(), |msg| matches!(msg, NetworkMessage::ProgramStdin { seq: 2.., .. }));
+  let err = run_with_stdin().await.expect_err("a stalled stdin fails the run");
+  assert!(err.to_string().contains("stopped taking stdin"), "{err}");
+  let _ = std::fs::remove_file(&input);
+}
+
+// netmap / submit / `run --to` discovery: the records of every node the probe reaches are verified
+// and kept once each.
+#[tokio::test(flavor = "multi_thread")]
+async fn neighbour_probes_collect_every_node_the_client_reaches() {
+  let mut fabric = Fabric::new();
+  let a = fabric.add_node("a", &[]).await;
+  let b = fabric.add_node("b", &[]).await;
+  let c = fabric.add_node("c", &[]).await;
+  let client = fabric.add_client("origin").await;
+  fabric.partition(client.ip(), c.ip());
+
+  let uuid = random_uuid16();
+  let request = serde_bare::to_vec(&execute(client.program(ATTEST_WAT, uuid, 0, None))).unwrap();
+  let send = async {
+    client.endpoint.send_to(&request, GROUP).await?;
+    Ok(())
+  };
+  let nodes = crate::command::netmap::gather_neighbours(std::slice::from_ref(&client.endpoint), uuid, Duration::from_secs(2), |_| false, send).await.unwrap();
+  let found: HashSet<Vec<u8>> = nodes.iter().map(|n| n.pubkey.clone()).collect();
+  assert_eq!(found, [a.pubkey.clone(), b.pubkey.clone()].into_iter().collect());
+  assert_eq!(nodes.len(), 2);
+  for n in nodes.iter() {
+    assert!(n.addr == a.addr || n.addr == b.addr, "{} is reached at the address it answered from", n.hostname);
+  }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn discovery_relays_records_across_hops_the_origin_cannot_reach() {
+  let mut fabric = Fabric::new();
+  fabric.set_default_link(Link { loss: 0.0, latency: Duration::from_millis(20) });
+  // A chain a -> b -> c by [[peer]]; the origin only reaches a, and a only reaches c through b.
+  let c = fabric.add_node("c", &[]).await;
+  let b = fabric.add_node("b", &[&c]).await;
+  let a = fabric.add_node("a", &[&b]).await;
+  let client = fabric.add_client("origin").await;
+  fabric.partition(client.ip(), b.ip());
+  fabric.partition(client.ip(), c.ip());
+  fabric.partition(a.ip(), c.ip());
+
+  let found = discover_through(&client, &a, 3, 0, Duration::from_secs(10)).await;
+  let expected: HashSet<Vec<u8>> = [a.pubkey.clone(), b.pubkey.clone(), c.pubkey.clone()].into_iter().collect();
+  assert_eq!(found, expected);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn discovery_relay_drops_a_pinned_peer_answering_with_another_key() {
+  let mut fabric = Fabric::new();
+  let b = fabric.add_node("b", &[]).await;
+  let imposter = fabric.add_node("imposter", &[]).await;
+  // `good` pins b's real key; `fooled` pins the imposter's key at b's address.
+  let good = fabric.add_node_with("good", &b.peer_toml(Some(&b.pubkey))).await;
+  let fooled = fabric.add_node_with("fooled", &b.peer_toml(Some(&imposter.pubkey))).await;
+  let client = fabric.add_client("origin").await;
+  fabric.partition(client.ip(), b.ip());
+
+  let found = discover_through(&client, &good, 2, 0, Duration::from_secs(10)).await;
+  let expected: HashSet<Vec<u8>> = [good.pubkey.clone(), b.pubkey.clone()].into_iter().collect();
+  assert_eq!(found, expected);
+
+  // b answers `fooled` with its own key, so nothing from b's side is relayed.
+  let found = discover_through(&client, &fooled, 2, 0, Duration::from_secs(4)).await;
+  assert_eq!(found, [fooled.pubkey.clone()].into_iter().collect::<HashSet<_>>());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn relays_stop_forwarding_once_the_origins_time_budget_is_spent() {
+  let mut fabric = Fabric::new();
+  let b = fabric.add_node("b", &[]).await;
+  let a = fabric.add_node("a", &[&b]).await;
+  let client = fabric.add_client("origin").await;
+  fabric.partition(client.ip(), b.ip());
+
+  // A budget that runs out while `a` is still running the program: `a` answers, but forwards nowhere.
+  let found = discover_through(&client, &a, 2, 1, Duration::from_secs(3)).await;
+  assert_eq!(found, [a.pubkey.clone()].into_iter().collect::<HashSet<_>>());
+
+  // With time to spare, the same request reaches b through a.
+  let found = discover_through(&client, &a, 2, 10_000, Duration::from_secs(10)).await;
+  assert_eq!(found, [a.pubkey.clone(), b.pubkey.clone()].into_iter().collect::<HashSet<_>>());
+}
+
+/// Send the discovery program (with `time_budget_ms`, 0 for none) to `first_hop` and collect the identities whose records come back
+/// (all relayed through `first_hop`), until `want` of them or `wait` elapses.
+async fn discover_through(client: &SimClient, first_hop: &SimNode, want: usize, time_budget_ms: u32, wait: Duration) -> HashSet<Vec<u8>> {
+  let uuid = random_uuid16();
+  let depth = crate::discovery::initial_depth_budget(false);
+  let mut program = client.program(ATTEST_WAT, uuid, depth, None);
+  program.options.time_budget_ms = time_budget_ms;
+  client.send(&execute(program), first_hop.addr).await;
+
+  let mut found: HashSet<Vec<u8>> = HashSet::new();
+  let deadline = tokio::time::Instant::now() + wait;
+  while found.len() < want {
+    let Some((from, msg)) = client.recv(deadline.saturating_duration_since(tokio::time::Instant::now())).await else { break };
+    if let NetworkMessage::BasicReturnMap { request_uuid, cbor_data, .. } = msg {
+      assert_eq!(request_uuid, uuid, "relayed replies carry the origin's request id");
+      assert_eq!(from, first_hop.addr, "every record arrives through the first hop");
+      let record: serde_cbor::Value = serde_cbor::from_slice(&cbor_data).expect("CBOR record");
+      let serde_cbor::Value::Map(record) = record else { panic!("record is not a map") };
+      let Some(serde_cbor::Value::Bytes(attestation)) = record.get(&serde_cbor::Value::Integer(1)) else { panic!("no attestation") };
+      let node = crate::discovery::verify_attestation_cbor(attestation).expect("attestation verifies");
+      found.insert(node.pubkey);
+    }
+  }
+  found
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn partitions_and_lossy_links_drop_traffic_until_healed() {
+  let mut fabric = Fabric::new();
+  let a = fabric.add_node("a", &[]).await;
+  let client = fabric.add_client("origin").await;
+
+  fabric.partition(client.ip(), a.ip());
+  let uuid = random_uuid16();
+  client.send(&execute(client.program(HELLO_WAT, uuid, 0, None)), a.addr).await;
+  assert!(client.recv(Duration::from_millis(500)).await.is_none(), "a partitioned node hears nothing");
+
+  fabric.heal(client.ip(), a.ip());
+  fabric.set_link(client.ip(), a.ip(), Link { loss: 1.0, latency: Duration::ZERO });
+  client.send(&execute(client.program(HELLO_WAT, uuid, 0, None)), a.addr).await;
+  assert!(client.recv(Duration::from_millis(500)).await.is_none(), "a fully lossy link delivers nothing");
+
+  fabric.set_link(client.ip(), a.ip(), Link::default());
+  let uuid = random_uuid16();
+  client.send(&execute(client.program(HELLO_WAT, uuid, 0, None)), a.addr).await;
+  assert_eq!(accepted_by(&client.recv_all(Duration::from_millis(1500)).await, uuid), vec![a.pubkey.clone()]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn signed_fabric_messages_reach_every_listener_and_forgeries_do_not() {
+  let mut fabric = Fabric::new();
+  let a = fabric.add_node("a", &[]).await;
+  let b = fabric.add_node("b", &[]).await;
+  let client = fabric.add_client("origin").await;
+
+  let sign = |id: &[u8], payload: &[u8]| {
+    crate::config::IdentityData::sign_payload(&client.signing_key, id, payload).to_bytes().to_vec()
+  };
+  let genuine = NetworkMessage::SignedFabricMessage {
+    source: client.identity.clone(),
+    id: b"msg-1".to_vec(),
+    cbor_data: b"\x62hi".to_vec(),
+    signature: sign(b"msg-1", b"\x62hi"),
+  };
+  let forged = NetworkMessage::SignedFabricMessage {
+    source: client.identity.clone(),
+    id: b"msg-2".to_vec(),
+    cbor_data: b"\x64evil".to_vec(),
+    signature: sign(b"msg-2", b"\x62hi"),
+  };
+  client.send(&forged, GROUP).await;
+  client.send(&genuine, GROUP).await;
+
+  for node in [&a, &b] {
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(3);
+    while node.executor.messages_after(0).is_empty() && tokio::time::Instant::now() < deadline {
+      tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let stored = node.executor.messages_after(0);
+    assert_eq!(stored.len(), 1, "{} stores the genuine message only", node.name);
+    assert_eq!(stored[0].text, b"\x62hi");
+    assert_eq!(stored[0].from_pubkey, client.identity.encoded_public_key);
+  }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn named_fabrics_sharing_a_group_do_not_hear_each_other() {
+  let mut fabric = Fabric::new();
+  let plain = fabric.add_node("plain", &[]).await;
+  let staging = fabric.add_node_with("staging", "[network]\nfabric = \"staging\"\n").await;
+  let client = fabric.add_client("origin").await;
+
+  // The same multicast group carries both fabrics; each run is answered on its own fabric only.
+  let uuid = random_uuid16();
+  client.send(&execute(client.program(HELLO_WAT, uuid, 0, None)), GROUP).await;
+  assert_eq!(accepted_by(&client.recv_all(Duration::from_millis(1500)).await, uuid), vec![plain.pubkey.clone()]);
+
+  let on_staging = client.on_fabric("staging");
+  let uuid = random_uuid16();
+  on_staging.send(&execute(on_staging.program(HELLO_WAT, uuid, 0, None)), GROUP).await;
+  assert_eq!(accepted_by(&on_staging.recv_all(Duration::from_millis(1500)).await, uuid), vec![staging.pubkey.clone()]);
+
+  // A fabric nobody serves gets no answer at all.
+  let on_prod = client.on_fabric("prod");
+  let uuid = random_uuid16();
+  on_prod.send(&execute(on_prod.program(HELLO_WAT, uuid, 0, None)), GROUP).await;
+  assert!(accepted_by(&on_prod.recv_all(Duration::from_millis(1000)).await, uuid).is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn history_requests_replay_room_messages_and_only_the_requesters_dms() {
+  use crate::config::IdentityData;
+  use crate::messages::{history_request_payload, room_message};
+
+  let mut fabric = Fabric::new();
+  let a = fabric.add_node("a", &[]).await;
+  let alice = fabric.add_client("alice").await;
+  let bob = fabric.add_client("bob").await;
+  let carol = fabric.add_client("carol").await;
+
+  // Alice says something in #ops and DMs bob while neither bob nor carol is listening; `a` keeps both.
+  let said = room_message(&alice.identity, &alice.signing_key, "ops", None, b"\x81\x62hi".to_vec(), false).unwrap();
+  let dm = room_message(&alice.identity, &alice.signing_key, "ops", Some(&bob.identity.encoded_public_key), b"\x81\x62dm".to_vec(), false).unwrap();
+  alice.send(&said, a.addr).await;
+  alice.send(&dm, a.addr).await;
+  let deadline = tokio::time::Instant::now() + Duration::from_secs(3);
+  while a.executor.message_history_for(None, 0, &bob.identity.encoded_public_key, 10).len() < 2 && tokio::time::Instant::now() < deadline {
+    tokio::time::sleep(Duration::from_millis(20)).await;
+  }
+  assert_eq!(a.executor.messages_after(0).len(), 1, "a can't read the DM, so its programs never see it");
+
+  let ask_at = |client: &SimClient, since_epoch_s: u64, signed_since: u64, epoch_s: u64| {
+    let request_uuid = random_uuid16();
+    let payload = history_request_payload(None, signed_since, epoch_s);
+    let signature = IdentityData::sign_payload(&client.signing_key, &request_uuid, &payload).to_bytes().to_vec();
+    (request_uuid, NetworkMessage::HistoryRequest { source: client.identity.clone(), request_uuid, room: None, since_epoch_s, epoch_s, signature })
+  };
+  let ask = |client: &SimClient, since_epoch_s: u64, signed_since: u64| ask_at(client, since_epoch_s, signed_since, crate::sys_utils::epoch_seconds_now_utc0());
+  let replayed = |replies: Vec<(std::net::SocketAddr, NetworkMessage)>, uuid: [u8; 16]| -> Vec<NetworkMessage> {
+    replies.into_iter().filter_map(|(_, m)| match m {
+      NetworkMessage::HistoryReply { request_uuid, entries } if request_uuid == uuid => Some(entries),
+      _ => None,
+    }).flatten().map(|e| serde_bare::from_slice::<NetworkMessage>(&e.message).expect("a signed message")).collect()
+  };
+
+  let mut answered = Vec::new();
+  for (client, want) in [(&bob, 2), (&carol, 1)] {
+    let (uuid, request) = ask(client, 0, 0);
+    client.send(&request, a.addr).await;
+    let messages = replayed(client.recv_all(Duration::from_millis(1000)).await, uuid);
+    assert_eq!(messages.len(), want, "{} gets the room message, and the DM only if it's theirs", client.identity.human_name);
+    for msg in messages {
+      let NetworkMessage::RoomMessage { source, id, envelope, signature } = msg else { panic!("not a RoomMessage") };
+      source.verify_payload(&id, &envelope, &signature).expect("replayed exactly as alice signed it");
+    }
+    answered.push((uuid, request));
+  }
+
+  // The same request again (a replay) gets nothing, nor does one signed too long ago.
+  let (uuid, replay) = answered.remove(0);
+  bob.send(&replay, a.addr).await;
+  assert!(replayed(bob.recv_all(Duration::from_millis(800)).await, uuid).is_empty());
+  let (uuid, stale) = ask_at(&bob, 0, 0, crate::sys_utils::epoch_seconds_now_utc0() - 120);
+  bob.send(&stale, a.addr).await;
+  assert!(replayed(bob.recv_all(Duration::from_millis(800)).await, uuid).is_empty());
+
+  // A request whose signature doesn't cover what it asks for is ignored.
+  let (uuid, forged) = ask(&carol, 0, 12345);
+  carol.send(&forged, a.addr).await;
+  assert!(replayed(carol.recv_all(Duration::from_millis(800)).await, uuid).is_empty());
+}
+
+// `chat` starting up asks the fabric for what was said before it started, and stores it as if it had
+// heard it live.
+#[tokio::test(flavor = "multi_thread")]
+async fn chat_backfills_room_history_from_the_fabric() {
+  let mut fabric = Fabric::new();
+  let a = fabric.add_node("a", &[]).await;
+  let chatter = fabric.add_node("chatter", &[]).await;
+  let alice = fabric.add_client("alice").await;
+
+  let said = crate::messages::room_message(&alice.identity, &alice.signing_key, "ops", None, b"\x81\x62hi".to_vec(), false).unwrap();
+  alice.send(&said, a.addr).await;
+  eventually(Duration::from_secs(3), || a.executor.messages_after(0).len() == 1).await;
+  assert!(chatter.executor.messages_after(0).is_empty(), "chatter wasn't listening");
+
+  let out = fabric.client_on(&chatter);
+  let (uuid, request) = crate::command::chat::history_request(&chatter.executor, &chatter.signing_key, &chatter.identity);
+  out.send(&request, GROUP).await;
+  let deadline = tokio::time::Instant::now() + Duration::from_millis(1500);
+  assert_eq!(crate::command::chat::collect_history(&chatter.executor, &out.endpoint, uuid, deadline).await, 1);
+  let stored = chatter.executor.messages_after(0);
+  assert_eq!(stored.len(), 1);
+  assert_eq!(stored[0].from_pubkey, alice.identity.encoded_public_key);
+}
+
+/// Sends the CBOR list `["hi"]` with `host::messages_send_reliable`.
+const SEND_RELIABLE_WAT: &str = r#"(module
+  (import "host" "messages_send_reliable" (func $send (param i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 100) "\81\62hi")
+  (func (export "_start")
+    (drop (call $send (i32.const 100) (i32.const 4) (i32.const 200)))))"#;
+
+/// A SignedFabricMessage (a ReliableFabricMessage when `wants_ack`) of `cbor_data` from `node`, under a
+/// fresh id.
+fn signed_by(node: &SimNode, cbor_data: &[u8], wants_ack: bool) -> (Vec<u8>, NetworkMessage) {
+  let id = random_uuid16().to_vec();
+  let payload = crate::messages::fabric_message_payload(cbor_data, wants_ack);
+  let signature = crate::config::IdentityData::sign_payload(&node.signing_key, &id, &payload).to_bytes().to_vec();
+  let (source, cbor_data) = (node.identity.clone(), cbor_data.to_vec());
+  let msg = if wants_ack {
+    NetworkMessage::ReliableFabricMessage { source, id: id.clone(), cbor_data, signature }
+  } else {
+    NetworkMessage::SignedFabricMessage { source, id: id.clone(), cbor_data, signature }
+  };
+  (id, msg)
+}
+
+/// Wait (up to `wait`) until `done`.
+async fn eventually(wait: Duration, done: impl Fn() -> bool) {
+  let deadline = tokio::time::Instant::now() + wait;
+  while !done() && tokio::time::Instant::now() < deadline {
+    tokio::time::sleep(Duration::from_millis(20)).await;
+  }
+}
+
+#[tokio::test]
+async fn reliable_sends_retransmit_until_every_known_peer_acks() {
+  use crate::config::IdentityData;
+  use crate::delivery::DeliveryState;
+
+  let mut fabric = Fabric::new();
+  let sender = fabric.add_node("sender", &[]).await;
+  let a = fabric.add_node("a", &[]).await;
+  let b = fabric.add_node("b", &[]).await;
+  let carol = fabric.add_client("carol").await;
+  sender.executor.note_peer(a.addr, &a.identity);
+  sender.executor.note_peer(b.addr, &b.identity);
+  let out = fabric.client_on(&sender);
+
+  // A message not signed as wanting acks gets none, however often it goes out.
+  let (id, plain) = signed_by(&sender, b"\x81\x62hi", false);
+  sender.executor.track_delivery(&id, serde_bare::to_vec(&plain).unwrap());
+  out.send(&plain, GROUP).await;
+  eventually(Duration::from_millis(800), || a.executor.messages_after(0).len() == 1 && b.executor.messages_after(0).len() == 1).await;
+  tokio::time::sleep(Duration::from_millis(300)).await;
+  assert_eq!(sender.executor.delivery_status(&id).unwrap().acked, 0);
+
+  let (id, msg) = signed_by(&sender, b"\x81\x62yo", true);
+  assert_eq!(sender.executor.track_delivery(&id, serde_bare::to_vec(&msg).unwrap()), 2, "a and b were heard from just now");
+
+  // b can't hear the first copy, or the sender's retransmits, so only a acks.
+  fabric.partition(sender.ip(), b.ip());
+  out.send(&msg, GROUP).await;
+  eventually(Duration::from_secs(3), || sender.executor.delivery_status(&id).is_some_and(|s| s.acked >= 1)).await;
+  let status = sender.executor.delivery_status(&id).unwrap();
+  assert_eq!((status.state, status.acked, status.expected), (DeliveryState::Pending, 1, 2));
+
+  // An ack whose signature doesn't cover this message counts for nothing.
+  let forged = IdentityData::sign_payload(&carol.signing_key, b"some other id", &sender.pubkey).to_bytes().to_vec();
+  carol.send(&NetworkMessage::MessageAck { node: carol.identity.clone(), source_pubkey: sender.pubkey.clone(), id: id.clone(), signature: forged }, sender.addr).await;
+
+  // Once the link heals, the sender's serve loop retransmits to b, and b's ack completes the delivery.
+  fabric.heal(sender.ip(), b.ip());
+  eventually(Duration::from_secs(5), || sender.executor.delivery_status(&id).is_some_and(|s| s.state != DeliveryState::Pending)).await;
+  let status = sender.executor.delivery_status(&id).unwrap();
+  assert_eq!((status.state, status.acked, status.expected), (DeliveryState::Delivered, 2, 2));
+  assert!(sender.executor.due_retransmits().is_empty(), "a delivered message is never sent again");
+  for node in [&a, &b] {
+    assert_eq!(node.executor.messages_after(0).len(), 2, "{} stored each message once", node.name);
+  }
+}
+
+#[tokio::test]
+async fn reliable_direct_messages_wait_only_on_their_recipient() {
+  use crate::delivery::DeliveryState;
+
+  let mut fabric = Fabric::new();
+  let sender = fabric.add_node("sender", &[]).await;
+  let a = fabric.add_node("a", &[]).await;
+  let b = fabric.add_node("b", &[]).await;
+  sender.executor.note_peer(a.addr, &a.identity);
+  sender.executor.note_peer(b.addr, &b.identity);
+  let out = fabric.client_on(&sender);
+
+  let dm = crate::messages::room_message(&sender.identity, &sender.signing_key, "ops", Some(&a.pubkey), b"\x81\x62dm".to_vec(), true).unwrap();
+  let NetworkMessage::RoomMessage { id, .. } = &dm else { unreachable!() };
+  assert_eq!(sender.executor.track_delivery_to(id, serde_bare::to_vec(&dm).unwrap(), &a.pubkey), 1);
+
+  // b is cut off, but the DM isn't for b: a's ack alone delivers it.
+  fabric.partition(sender.ip(), b.ip());
+  out.send(&dm, GROUP).await;
+  eventually(Duration::from_secs(3), || sender.executor.delivery_status(id).is_some_and(|s| s.state != DeliveryState::Pending)).await;
+  let status = sender.executor.delivery_status(id).unwrap();
+  assert_eq!((status.state, status.acked, status.expected), (DeliveryState::Delivered, 1, 1));
+}
+
+#[tokio::test]
+async fn a_trusted_callers_program_sends_reliably_through_the_daemon() {
+  let mut fabric = Fabric::new();
+  let sender = fabric.add_node("sender", &[]).await;
+  let a = fabric.add_node("a", &[]).await;
+  let client = fabric.add_client("origin").await;
+  let stranger = fabric.add_client("stranger").await;
+  sender.executor.note_peer(a.addr, &a.identity);
+  sender.trust("origin", &client.identity.encoded_public_key);
+
+  // An untrusted caller's program can't speak as the node.
+  stranger.send(&execute(stranger.program(SEND_RELIABLE_WAT, random_uuid16(), 0, None)), sender.addr).await;
+  tokio::time::sleep(Duration::from_millis(800)).await;
+  assert!(a.executor.messages_after(0).is_empty());
+
+  // A trusted one's goes out on the fabric, signed by the node, and a's ack reaches the node.
+  client.send(&execute(client.program(SEND_RELIABLE_WAT, random_uuid16(), 0, None)), sender.addr).await;
+  eventually(Duration::from_secs(3), || !a.executor.messages_after(0).is_empty()).await;
+  let stored = a.executor.messages_after(0);
+  assert_eq!(stored.len(), 1);
+  assert_eq!(stored[0].from_pubkey, sender.pubkey);
+  let id = stored[0].id.clone();
+  eventually(Duration::from_secs(3), || sender.executor.delivery_status(&id).is_some_and(|s| s.acked == 1)).await;
+  assert_eq!(sender.executor.delivery_status(&id).unwrap().acked, 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tty_sessions_draw_on_the_client_and_take_only_its_input() {
+  use crate::tests::tty::Recorder;
+  let mut fabric = Fabric::new();
+  let node = fabric.add_node("a", &[]).await;
+  let client = fabric.add_client("origin").await;
+  let mallory = fabric.add_client("mallory").await;
+
+  let uuid = random_uuid16();
+  let mut program = client.program(TTY_ECHO_WAT, uuid, 0, None);
+  program.options.tty_size = Some((100, 30));
+  client.send(&execute(program), node.addr).await;
+  let shown = Recorder::default();
+  let drawn = |text: &str| shown.calls.lock().unwrap().iter().any(|c| c.strip_prefix("print ") == Some(text));
+  let mut pid = None;
+  while pid.is_none() || !drawn("ready") {
+    match client.recv(Duration::from_secs(5)).await.expect("the program starts and draws") {
+      (_, NetworkMessage::ProgramAccepted { request_uuid, from_pid, .. }) if request_uuid == uuid => pid = Some(from_pid),
+      (_, NetworkMessage::TtyDraw { ops, .. }) => assert!(crate::tty::apply_draw_ops(&ops, &shown)),
+      _ => {}
+    }
+  }
+
+  // A redraw goes only to the client that launched it, as one frame of the whole screen.
+  let pid = pid.unwrap();
+  let is_frame = |msg: &NetworkMessage| matches!(msg, NetworkMessage::TtyDraw { ops, .. } if ops.first() == Some(&crate::tty::draw_op::FRAME));
+  mallory.send(&NetworkMessage::TtyRedraw { request_id: uuid, pid }, node.addr).await;
+  tokio::time::sleep(Duration::from_millis(200)).await;
+  assert!(!client.recv_all(Duration::from_millis(200)).await.iter().any(|(_, msg)| is_frame(msg)), "only the owner can ask");
+  client.send(&NetworkMessage::TtyRedraw { request_id: uuid, pid }, node.addr).await;
+  let repainted = Recorder::default();
+  loop {
+    match client.recv(Duration::from_secs(5)).await.expect("a frame comes back") {
+      (_, msg @ NetworkMessage::TtyDraw { .. }) if is_frame(&msg) => {
+        let NetworkMessage::TtyDraw { ops, .. } = msg else { unreachable!() };
+        assert!(crate::tty::apply_draw_ops(&ops, &repainted));
+        break;
+      }
+      _ => {}
+    }
+  }
+  assert!(repainted.calls.lock().unwrap().iter().any(|c| c.starts_with("print ") && c.contains("ready")));
+
+  // Keys from anyone but the client that launched it are dropped.
+  let key = |c: char| NetworkMessage::TtyInput { request_id: uuid, pid, events: vec![crate::tty::encode_event_v2(&crate::tty::TtyEvent::Char(c))], hangup: false };
+  mallory.send(&key('m'), node.addr).await;
+  tokio::time::sleep(Duration::from_millis(200)).await;
+  client.send(&key('x'), node.addr).await;
+  loop {
+    match client.recv(Duration::from_secs(5)).await.expect("the program echoes and exits") {
+      (_, NetworkMessage::TtyDraw { ops, .. }) => assert!(crate::tty::apply_draw_ops(&ops, &shown)),
+      (_, NetworkMessage::ProgramExit { exit_code, .. } | NetworkMessage::BasicInsecureProgramExit { exit_code, .. }) => {
+        assert_eq!(exit_code, 0);
+        break;
+      }
+      _ => {}
+    }
+  }
+  assert!(drawn("x"));
+  assert!(!drawn("m"));
+}
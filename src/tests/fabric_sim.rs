@@ -0,0 +1,379 @@
+//! An in-process fabric for tests: N daemons, each a real [`Executor`] behind the real serve loop
+//! ([`serve::serve_endpoint`]), joined by an in-memory datagram network instead of UDP. Links can
+//! drop packets, delay them, or be cut entirely, so multi-node behaviour (fan-out runs, addressed
+//! runs, recursive discovery relays, fabric message delivery) is testable with `cargo test`.
+//!
+//! Every node gets its own keyfile, `Config` and trust set, and its own address `10.77.0.N:PORT`;
+//! [`Fabric::add_client`] makes the origin side, an identity with an endpoint of its own. Tests hand
+//! that endpoint to the client commands' reply readers (`run`, `netmap`, `chat`) or speak the wire
+//! protocol on it directly. A datagram to [`GROUP`] reaches every node, like multicast.
+
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use crate::command::serve;
+use crate::config::{Config, IdentityData};
+use crate::executor::{Executor, ProgramData, ProgramDataBuilder};
+use crate::messages::NetworkMessage;
+use crate::transport::{Datagram, Endpoint};
+
+/// The serve port every simulated node listens on.
+pub const PORT: u16 = 2200;
+
+/// The simulated multicast group; every node is a member.
+pub const GROUP: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(239, 77, 0, 1)), PORT);
+
+/// How one link treats its traffic. Applies in both directions.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Link {
+  /// Chance (0.0..=1.0) that any one datagram is dropped.
+  pub loss: f64,
+  /// Delay before each datagram is delivered.
+  pub latency: std::time::Duration,
+}
+
+type Inbox = tokio::sync::mpsc::UnboundedSender<(Vec<u8>, SocketAddr)>;
+
+/// Decides, per message, whether a datagram on one directed host pair is dropped.
+type Dropper = Box<dyn FnMut(&NetworkMessage) -> bool + Send>;
+
+/// The shared network state: who is bound where, group memberships and link conditions.
+#[derive(Default)]
+struct Net {
+  endpoints: HashMap<SocketAddr, Inbox>,
+  groups: HashMap<SocketAddr, Vec<SocketAddr>>,
+  default_link: Link,
+  /// Per host pair (see [`host_pair`]), overriding `default_link`.
+  links: HashMap<(IpAddr, IpAddr), Link>,
+  partitions: HashSet<(IpAddr, IpAddr)>,
+  /// Per (from, to) host, see [`Fabric::drop_when`].
+  droppers: HashMap<(IpAddr, IpAddr), Dropper>,
+  next_ephemeral_port: u16,
+}
+
+fn host_pair(a: IpAddr, b: IpAddr) -> (IpAddr, IpAddr) {
+  if a <= b { (a, b) } else { (b, a) }
+}
+
+impl Net {
+  fn bind(net: &Arc<Mutex<Net>>, addr: SocketAddr) -> Endpoint {
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    net.lock().unwrap().endpoints.insert(addr, tx);
+    Endpoint::new(Arc::new(SimSocket { addr, net: net.clone(), inbox: Mutex::new(rx) }))
+  }
+
+  fn bind_ephemeral(net: &Arc<Mutex<Net>>, ip: IpAddr) -> (Endpoint, SocketAddr) {
+    let port = {
+      let mut n = net.lock().unwrap();
+      n.next_ephemeral_port = n.next_ephemeral_port.max(40000) + 1;
+      n.next_ephemeral_port
+    };
+    let addr = SocketAddr::new(ip, port);
+    (Net::bind(net, addr), addr)
+  }
+
+  /// Hand `data` to every endpoint `to` names (one address, or every member of a group), subject to
+  /// partitions, droppers, loss and latency. Undeliverable datagrams vanish silently, as with UDP.
+  fn deliver(&mut self, from: SocketAddr, to: SocketAddr, data: &[u8]) {
+    let targets = match self.groups.get(&to) {
+      Some(members) => members.clone(),
+      None => vec![to],
+    };
+    for target in targets {
+      let link = if target.ip() == from.ip() {
+        Link::default()
+      } else {
+        let pair = host_pair(from.ip(), target.ip());
+        if self.partitions.contains(&pair) { continue; }
+        if let Some(drop) = self.droppers.get_mut(&(from.ip(), target.ip()))
+          && crate::messages::decode(data).is_ok_and(|(_, msg)| drop(&msg)) {
+          continue;
+        }
+        self.links.get(&pair).copied().unwrap_or(self.default_link)
+      };
+      if link.loss > 0.0 && rand::random::<f64>() < link.loss { continue; }
+      let Some(inbox) = self.endpoints.get(&target).cloned() else { continue };
+      let datagram = (data.to_vec(), from);
+      if link.latency.is_zero() {
+        let _ = inbox.send(datagram);
+      } else {
+        tokio::spawn(async move {
+          tokio::time::sleep(link.latency).await;
+          let _ = inbox.send(datagram);
+        });
+      }
+    }
+  }
+}
+
+/// One bound address on the simulated network.
+struct SimSocket {
+  addr: SocketAddr,
+  net: Arc<Mutex<Net>>,
+  inbox: Mutex<tokio::sync::mpsc::UnboundedReceiver<(Vec<u8>, SocketAddr)>>,
+}
+
+impl Datagram for SimSocket {
+  fn poll_send_to(
+    &self,
+    _cx: &mut core::task::Context<'_>,
+    buf: &[u8],
+    addr: SocketAddr,
+  ) -> core::task::Poll<std::io::Result<usize>> {
+    self.net.lock().unwrap().deliver(self.addr, addr, buf);
+    core::task::Poll::Ready(Ok(buf.len()))
+  }
+
+  fn poll_recv_from(
+    &self,
+    cx: &mut core::task::Context<'_>,
+    buf: &mut tokio::io::ReadBuf<'_>,
+  ) -> core::task::Poll<std::io::Result<SocketAddr>> {
+    let mut inbox = self.inbox.lock().unwrap();
+    match inbox.poll_recv(cx) {
+      core::task::Poll::Ready(Some((data, from))) => {
+        // Like UDP, a datagram larger than the buffer is truncated.
+        let len = data.len().min(buf.remaining());
+        buf.put_slice(&data[..len]);
+        core::task::Poll::Ready(Ok(from))
+      }
+      core::task::Poll::Ready(None) => core::task::Poll::Ready(Err(std::io::ErrorKind::NotConnected.into())),
+      core::task::Poll::Pending => core::task::Poll::Pending,
+    }
+  }
+
+  fn local_ip_facing(&self, _peer: IpAddr) -> Option<IpAddr> {
+    Some(self.addr.ip())
+  }
+
+  fn open_ephemeral(&self, _peer: SocketAddr) -> std::io::Result<Endpoint> {
+    Ok(Net::bind_ephemeral(&self.net, self.addr.ip()).0)
+  }
+}
+
+impl Drop for SimSocket {
+  fn drop(&mut self) {
+    if let Ok(mut net) = self.net.lock() {
+      net.endpoints.remove(&self.addr);
+      for members in net.groups.values_mut() {
+        members.retain(|m| *m != self.addr);
+      }
+    }
+  }
+}
+
+/// A running daemon on the simulated fabric.
+pub struct SimNode {
+  pub name: String,
+  pub addr: SocketAddr,
+  pub pubkey: Vec<u8>,
+  pub signing_key: ed25519_dalek::SigningKey,
+  pub identity: IdentityData,
+  pub executor: Arc<Executor>,
+}
+
+impl SimNode {
+  pub fn ip(&self) -> IpAddr {
+    self.addr.ip()
+  }
+
+  /// Add `pubkey` to this node's trusted-keys set.
+  pub fn trust(&self, name: &str, pubkey: &[u8]) {
+    let key: [u8; 32] = pubkey.try_into().expect("ed25519 public key");
+    self.executor.add_trusted_key(name, &ed25519_dalek::VerifyingKey::from_bytes(&key).expect("valid ed25519 key"));
+  }
+
+  /// A `[[peer]]` block for this node, optionally pinning `expected_key` (which need not be ours).
+  pub fn peer_toml(&self, expected_key: Option<&[u8]>) -> String {
+    let mut toml = format!("[[peer]]\nipv4 = \"{}\"\n", self.ip());
+    if let Some(key) = expected_key {
+      let key: [u8; 32] = key.try_into().expect("ed25519 public key");
+      let vk = ed25519_dalek::VerifyingKey::from_bytes(&key).expect("valid ed25519 key");
+      toml.push_str(&format!("expected_key = {{ key = \"{}\" }}\n", crate::crypto_utils::format_verifying_key(&vk)));
+    }
+    toml
+  }
+}
+
+/// The origin side of a test: an identity plus an endpoint the client code (or the test itself)
+/// talks to the fabric through.
+pub struct SimClient {
+  pub addr: SocketAddr,
+  pub endpoint: Endpoint,
+  pub signing_key: ed25519_dalek::SigningKey,
+  pub identity: IdentityData,
+}
+
+impl SimClient {
+  pub fn ip(&self) -> IpAddr {
+    self.addr.ip()
+  }
+
+  /// A program request from this client. `wat` ships as text; the executor compiles WAT as readily
+  /// as a binary module.
+  pub fn program(&self, wat: &str, request_uuid: [u8; 16], depth_budget: u8, target_pubkey: Option<Vec<u8>>) -> ProgramData {
+    ProgramDataBuilder::new()
+      .set_human_name("sim-program")
+      .set_wasm_program_bytes(wat.as_bytes())
+      .set_source(&self.identity)
+      .set_request_context(request_uuid, depth_budget, Vec::new())
+      .set_target_pubkey(target_pubkey)
+      .build()
+      .expect("program data")
+  }
+
+  /// The same client speaking on the fabric called `name`: what it sends is scoped to that fabric,
+  /// and it only hears replies sent on it.
+  pub fn on_fabric(&self, name: &str) -> SimClient {
+    SimClient {
+      addr: self.addr,
+      endpoint: self.endpoint.scoped(crate::messages::FabricId::named(name)),
+      signing_key: self.signing_key.clone(),
+      identity: self.identity.clone(),
+    }
+  }
+
+  pub async fn send(&self, msg: &NetworkMessage, to: SocketAddr) {
+    let bytes = serde_bare::to_vec(msg).expect("encodable message");
+    self.endpoint.send_to(&bytes, to).await.expect("simulated send");
+  }
+
+  /// The next message addressed to us, or None if nothing arrives within `wait`.
+  pub async fn recv(&self, wait: std::time::Duration) -> Option<(SocketAddr, NetworkMessage)> {
+    let mut buf = vec![0u8; 64 * 1024];
+    let deadline = tokio::time::Instant::now() + wait;
+    loop {
+      let (len, from) = tokio::time::timeout_at(deadline, self.endpoint.recv_from(&mut buf)).await.ok()?.ok()?;
+      if let Some(msg) = self.endpoint.fabric().decode(&buf[..len]) {
+        return Some((from, msg));
+      }
+    }
+  }
+
+  /// Every message that arrives within `wait`.
+  pub async fn recv_all(&self, wait: std::time::Duration) -> Vec<(SocketAddr, NetworkMessage)> {
+    let deadline = tokio::time::Instant::now() + wait;
+    let mut out = Vec::new();
+    while let Some(m) = self.recv(deadline.saturating_duration_since(tokio::time::Instant::now())).await {
+      out.push(m);
+    }
+    out
+  }
+}
+
+/// The simulated network plus the daemons on it. Dropping it stops the daemons and removes their
+/// keyfiles.
+pub struct Fabric {
+  net: Arc<Mutex<Net>>,
+  next_host: u8,
+  dirs: Vec<std::path::PathBuf>,
+  listeners: tokio::task::JoinSet<()>,
+}
+
+impl Fabric {
+  pub fn new() -> Fabric {
+    Fabric { net: Arc::new(Mutex::new(Net::default())), next_host: 1, dirs: Vec::new(), listeners: tokio::task::JoinSet::new() }
+  }
+
+  /// Conditions for every link without its own [`Fabric::set_link`].
+  pub fn set_default_link(&self, link: Link) {
+    self.net.lock().unwrap().default_link = link;
+  }
+
+  pub fn set_link(&self, a: IpAddr, b: IpAddr, link: Link) {
+    self.net.lock().unwrap().links.insert(host_pair(a, b), link);
+  }
+
+  /// Cut all traffic between hosts `a` and `b`.
+  pub fn partition(&self, a: IpAddr, b: IpAddr) {
+    self.net.lock().unwrap().partitions.insert(host_pair(a, b));
+  }
+
+  pub fn heal(&self, a: IpAddr, b: IpAddr) {
+    self.net.lock().unwrap().partitions.remove(&host_pair(a, b));
+  }
+
+  /// Drop the datagrams from host `from` to host `to` whose message `drop` picks - a loss pattern
+  /// a test can count on, unlike [`Link::loss`].
+  pub fn drop_when(&self, from: IpAddr, to: IpAddr, drop: impl FnMut(&NetworkMessage) -> bool + Send + 'static) {
+    self.net.lock().unwrap().droppers.insert((from, to), Box::new(drop));
+  }
+
+  /// Start a daemon named `name` whose `[[peer]]` list is `peers`.
+  pub async fn add_node(&mut self, name: &str, peers: &[&SimNode]) -> SimNode {
+    let peer_toml: String = peers.iter().map(|p| p.peer_toml(None)).collect();
+    self.add_node_with(name, &peer_toml).await
+  }
+
+  /// Start a daemon named `name` with `extra_toml` in its config (e.g. pinned `[[peer]]`s, see
+  /// [`SimNode::peer_toml`]).
+  pub async fn add_node_with(&mut self, name: &str, extra_toml: &str) -> SimNode {
+    let ip = self.next_ip();
+    let (config, key, identity) = self.new_identity(name, extra_toml).await;
+    let executor = Executor::new(&config).await;
+
+    let addr = SocketAddr::new(ip, PORT);
+    let endpoint = Net::bind(&self.net, addr);
+    self.net.lock().unwrap().groups.entry(GROUP).or_default().push(addr);
+    let (serving, config) = (executor.clone(), Arc::new(config));
+    self.listeners.spawn(async move {
+      let _ = serve::serve_endpoint(endpoint, PORT, serving, config).await;
+    });
+
+    SimNode { name: name.to_string(), addr, pubkey: key.verifying_key().as_bytes().to_vec(), signing_key: key, identity, executor }
+  }
+
+  /// A client identity on a host of its own, bound to an ephemeral port.
+  pub async fn add_client(&mut self, name: &str) -> SimClient {
+    let ip = self.next_ip();
+    let (_, signing_key, identity) = self.new_identity(name, "").await;
+    let (endpoint, addr) = Net::bind_ephemeral(&self.net, ip);
+    SimClient { addr, endpoint, signing_key, identity }
+  }
+
+  /// A client on `node`'s host speaking as `node`, bound to an ephemeral port - like a `weverywhere
+  /// chat` sending through its own executor.
+  pub fn client_on(&self, node: &SimNode) -> SimClient {
+    let (endpoint, addr) = Net::bind_ephemeral(&self.net, node.ip());
+    SimClient { addr, endpoint, signing_key: node.signing_key.clone(), identity: node.identity.clone() }
+  }
+
+  fn next_ip(&mut self) -> IpAddr {
+    let ip = IpAddr::V4(Ipv4Addr::new(10, 77, 0, self.next_host));
+    self.next_host += 1;
+    ip
+  }
+
+  /// A fresh keyfile and state dir in a temp dir of its own, and the config + signed identity that
+  /// use them.
+  async fn new_identity(&mut self, name: &str, extra_toml: &str) -> (Config, ed25519_dalek::SigningKey, IdentityData) {
+    let dir = std::env::temp_dir().join(format!(
+      "weverywhere-fabric-sim-{}-{}",
+      name,
+      crate::crypto_utils::to_hex(&crate::discovery::random_uuid16()[..8])
+    ));
+    std::fs::create_dir_all(&dir).expect("temp dir");
+    self.dirs.push(dir.clone());
+    let keyfile = dir.join("identity.pem");
+    crate::crypto_utils::generate_private_key_ed25519_pem_file(&keyfile).await.expect("keyfile");
+
+    let mut config: Config = toml::from_str(&format!("[identity]\nname = \"{name}\"\n\n{extra_toml}")).expect("sim config");
+    config.identity.keyfile = keyfile;
+    config.state.dir = dir;
+    // What a node sends to its fabric itself (signed messages, retransmits) goes to the simulated group.
+    config.network.apply_cli_overrides(Some(&[GROUP.ip()]), None);
+    let key = config.identity.read_private_key_ed25519_pem_file().await.expect("read keyfile");
+    let identity = IdentityData::generate_from_config(&config).await.expect("identity");
+    (config, key, identity)
+  }
+}
+
+impl Drop for Fabric {
+  fn drop(&mut self) {
+    self.listeners.abort_all();
+    for dir in self.dirs.iter() {
+      let _ = std::fs::remove_dir_all(dir);
+    }
+  }
+}
@@ -0,0 +1,68 @@
+use crate::args::ResponderSelect;
+use crate::config::IdentityData;
+use crate::fanout::{FanOut, FanOutPolicy};
+use crate::messages::{program_accepted_payload, ExitReason, NetworkMessage};
+use crate::tests::identity;
+
+fn accepted(key: &ed25519_dalek::SigningKey, node: &IdentityData, uuid: [u8; 16], pid: u64) -> NetworkMessage {
+  let signature = IdentityData::sign_payload(key, &uuid, &program_accepted_payload(pid, false));
+  NetworkMessage::ProgramAccepted { request_uuid: uuid, from_pid: pid, node: node.clone(), trusts_caller: false, signature: signature.to_vec() }
+}
+
+fn exit(pid: u64, code: u32) -> NetworkMessage {
+  NetworkMessage::ProgramExit { from_pid: pid, exit_code: code, reason: ExitReason::Exited, fuel_consumed: None, backtrace: Vec::new() }
+}
+
+#[test]
+fn responder_select_parses_cli_forms() {
+  assert_eq!("trusted".parse::<ResponderSelect>(), Ok(ResponderSelect::Trusted));
+  assert_eq!("untrusted".parse::<ResponderSelect>(), Ok(ResponderSelect::Untrusted));
+  assert_eq!("pubkey:39AD4176".parse::<ResponderSelect>(), Ok(ResponderSelect::Pubkey("39ad4176".into())));
+  assert!("pubkey:".parse::<ResponderSelect>().is_err());
+  assert!("pubkey:xyz".parse::<ResponderSelect>().is_err());
+  assert!("everyone".parse::<ResponderSelect>().is_err());
+}
+
+#[test]
+fn replies_wait_for_identity_and_each_node_counts_once() {
+  let uuid = [9u8; 16];
+  let (key, node) = identity("node1");
+  let policy = FanOutPolicy { min_responders: 1, max_responders: Some(1), ..Default::default() };
+  let fan_out = FanOut::new(uuid, policy, Default::default());
+  let a: std::net::SocketAddr = "10.0.0.1:2240".parse().unwrap();
+
+  // The exit arrives before the node identified itself: held, so the run isn't complete yet.
+  fan_out.handle(a, exit(0, 3));
+  assert!(!fan_out.is_done());
+  fan_out.handle(a, accepted(&key, &node, uuid, 0));
+  assert!(fan_out.is_done(), "the one wanted responder has exited");
+
+  // A second copy of the run on the same node (another interface) is not a second responder.
+  fan_out.handle(a, accepted(&key, &node, uuid, 1));
+  fan_out.handle(a, exit(1, 0));
+  assert_eq!(fan_out.finish().unwrap(), 3);
+}
+
+#[test]
+fn forged_or_foreign_acceptance_is_ignored() {
+  let uuid = [1u8; 16];
+  let (key, node) = identity("node1");
+  let (other_key, _) = identity("mallory");
+  let policy = FanOutPolicy { min_responders: 1, ..Default::default() };
+  let fan_out = FanOut::new(uuid, policy, Default::default());
+  let a: std::net::SocketAddr = "10.0.0.1:2240".parse().unwrap();
+
+  fan_out.handle(a, accepted(&other_key, &node, uuid, 0)); // signed by the wrong key
+  fan_out.handle(a, accepted(&key, &node, [2u8; 16], 0)); // another run's acceptance
+  fan_out.handle(a, exit(0, 0));
+  // Never identified: without --select/--max-responders it is still released at finish, like an
+  // older daemon that doesn't send ProgramAccepted...
+  assert!(fan_out.finish().is_ok());
+
+  let policy = FanOutPolicy { min_responders: 1, select: Some(ResponderSelect::Untrusted), ..Default::default() };
+  let fan_out = FanOut::new(uuid, policy, Default::default());
+  fan_out.handle(a, accepted(&other_key, &node, uuid, 0));
+  fan_out.handle(a, exit(0, 0));
+  // ...but it can never satisfy a --select.
+  assert!(fan_out.finish().is_err(), "an unverified node can't satisfy --select");
+}
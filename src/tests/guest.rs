@@ -0,0 +1,216 @@
+//! The guest crate (guest/) is what Rust programs see of the host, so everything it encodes must
+//! decode with the host's own code and vice versa. Its imports only exist under the executor; these
+//! tests cover the encodings.
+
+use ed25519_dalek::Signer;
+use serde_cbor::Value as HostValue;
+use weverywhere_guest::cbor::Value;
+
+use crate::tty::{encode_event, encode_event_v2, modifiers, mouse_action, mouse_button, MouseEvent, TtyEvent};
+
+// The same items encode to the same bytes as serde_cbor (maps here have their keys in the order
+// serde_cbor sorts them), and decode back from them.
+#[test]
+fn cbor_encodes_like_the_host() {
+  let pairs = [
+    (Value::Integer(0), HostValue::Integer(0)),
+    (Value::Integer(23), HostValue::Integer(23)),
+    (Value::Integer(24), HostValue::Integer(24)),
+    (Value::Integer(-1), HostValue::Integer(-1)),
+    (Value::Integer(-1000), HostValue::Integer(-1000)),
+    (Value::Integer(70_000), HostValue::Integer(70_000)),
+    (Value::Integer(u64::MAX as i128), HostValue::Integer(u64::MAX as i128)),
+    (Value::Integer(i64::MIN as i128), HostValue::Integer(i64::MIN as i128)),
+    (Value::Text("héllo".into()), HostValue::Text("héllo".into())),
+    (Value::Bytes(vec![7; 300]), HostValue::Bytes(vec![7; 300])),
+    (Value::Bool(true), HostValue::Bool(true)),
+    (Value::Null, HostValue::Null),
+    (Value::Float(1.5), HostValue::Float(1.5)),
+    (Value::Float(-0.1), HostValue::Float(-0.1)),
+    (Value::Float(100_000.0), HostValue::Float(100_000.0)),
+    (Value::Float(6e-8), HostValue::Float(6e-8)),
+    (Value::Float(f64::NEG_INFINITY), HostValue::Float(f64::NEG_INFINITY)),
+    (
+      Value::Array(vec![Value::Integer(1), Value::Text("two".into()), Value::Array(Vec::new())]),
+      HostValue::Array(vec![HostValue::Integer(1), HostValue::Text("two".into()), HostValue::Array(Vec::new())]),
+    ),
+    (
+      Value::int_map([(1, Value::Bytes(vec![1, 2])), (2, Value::Integer(-5))]),
+      HostValue::Map([(HostValue::Integer(1), HostValue::Bytes(vec![1, 2])), (HostValue::Integer(2), HostValue::Integer(-5))].into_iter().collect()),
+    ),
+  ];
+  for (ours, host) in pairs {
+    let bytes = serde_cbor::to_vec(&host).unwrap();
+    assert_eq!(ours.encode(), bytes, "{ours:?}");
+    assert_eq!(Value::decode(&bytes), Some(ours));
+  }
+}
+
+#[test]
+fn cbor_refuses_bad_input() {
+  let bytes = Value::Array(vec![Value::Text("x".into())]).encode();
+  assert_eq!(Value::decode(&bytes[..bytes.len() - 1]), None, "cut short");
+  assert_eq!(Value::decode(&[bytes.as_slice(), &[0]].concat()), None, "trailing bytes");
+  // An array claiming 2^32 items in a few bytes is refused before anything is allocated for it.
+  assert_eq!(Value::decode(&[0x9a, 0xff, 0xff, 0xff, 0xff, 0x00]), None);
+  assert_eq!(Value::decode(&[0x81; 100]), None, "nested too deep");
+  assert_eq!(Value::decode(&[0x62, 0xff, 0xfe]), None, "text that isn't UTF-8");
+  // Floats come in three widths; tags are looked through.
+  assert_eq!(Value::decode(&[0xf9, 0x3e, 0x00]), Some(Value::Float(1.5)));
+  assert_eq!(Value::decode(&[0xc1, 0x01]), Some(Value::Integer(1)));
+}
+
+#[test]
+fn keys_match_the_host() {
+  use weverywhere_guest::{discovery, load, messages};
+  let pairs = [
+    (discovery::attest_keys::HOSTNAME, crate::discovery::attest_keys::HOSTNAME),
+    (discovery::attest_keys::PUBKEY, crate::discovery::attest_keys::PUBKEY),
+    (discovery::attest_keys::EPOCH_S, crate::discovery::attest_keys::EPOCH_S),
+    (discovery::attest_keys::SIGNATURE, crate::discovery::attest_keys::SIGNATURE),
+    (discovery::record_keys::ATTESTATION, crate::discovery::record_keys::ATTESTATION),
+    (discovery::record_keys::TRUSTS_CALLER, crate::discovery::record_keys::TRUSTS_CALLER),
+    (discovery::record_keys::DEPTH, crate::discovery::record_keys::DEPTH),
+    (discovery::record_keys::PARENT_PUBKEY, crate::discovery::record_keys::PARENT_PUBKEY),
+    (discovery::record_keys::NODE_ADDR, crate::discovery::record_keys::NODE_ADDR),
+    (messages::message_keys::SEQ, crate::executor::message_keys::SEQ),
+    (messages::message_keys::NAME, crate::executor::message_keys::NAME),
+    (messages::message_keys::PUBKEY, crate::executor::message_keys::PUBKEY),
+    (messages::message_keys::EPOCH_S, crate::executor::message_keys::EPOCH_S),
+    (messages::message_keys::TEXT, crate::executor::message_keys::TEXT),
+    (messages::message_keys::ROOM, crate::executor::message_keys::ROOM),
+    (messages::message_keys::TO, crate::executor::message_keys::TO),
+    (messages::message_keys::ID, crate::executor::message_keys::ID),
+    (load::cpu_keys::CORES, crate::scheduler::cpu_keys::CORES),
+    (load::cpu_keys::BUSY_PERMILLE, crate::scheduler::cpu_keys::BUSY_PERMILLE),
+    (load::cpu_keys::LOAD1_CENTI, crate::scheduler::cpu_keys::LOAD1_CENTI),
+    (load::memory_keys::TOTAL_BYTES, crate::scheduler::memory_keys::TOTAL_BYTES),
+    (load::memory_keys::AVAILABLE_BYTES, crate::scheduler::memory_keys::AVAILABLE_BYTES),
+    (load::capacity_keys::CPU, crate::scheduler::capacity_keys::CPU),
+    (load::capacity_keys::MEMORY, crate::scheduler::capacity_keys::MEMORY),
+    (load::capacity_keys::PIDS, crate::scheduler::capacity_keys::PIDS),
+  ];
+  for (i, (ours, host)) in pairs.into_iter().enumerate() {
+    assert_eq!(ours, host, "pair {i}");
+  }
+  use weverywhere_guest::tty;
+  assert_eq!(tty::rgb(0x10, 0x20, 0x30), crate::tty::RGB_FLAG | 0x102030);
+  assert_eq!(tty::attrs::BLINK, crate::tty::attrs::BLINK);
+  assert_eq!(tty::modes::ALTERNATE_SCREEN, crate::tty::modes::ALTERNATE_SCREEN);
+}
+
+// A record built in a program is what netmap's client reads, and its attestation verifies.
+#[test]
+fn records_and_attestations_pass_the_host_checks() {
+  use weverywhere_guest::discovery::{Attestation, Record};
+  let key = ed25519_dalek::SigningKey::generate(&mut rand::rngs::OsRng);
+  let pubkey = key.verifying_key().as_bytes().to_vec();
+  let epoch_s = crate::sys_utils::epoch_seconds_now_utc0();
+  let signature = key.sign(&crate::discovery::attestation_signing_bytes("node1", &pubkey, epoch_s)).to_bytes().to_vec();
+
+  // What host::signed_attestation writes decodes, and re-encodes to something that still verifies.
+  let host_cbor = crate::discovery::build_attestation_cbor("node1", &pubkey, epoch_s, &signature).unwrap();
+  let attestation = Attestation::decode(&host_cbor).unwrap();
+  assert_eq!(attestation, Attestation { hostname: "node1".into(), pubkey: pubkey.clone(), epoch_s, signature });
+  let node = crate::discovery::verify_attestation_cbor(&attestation.encode()).unwrap();
+  assert_eq!((node.hostname.as_str(), node.pubkey.as_slice()), ("node1", pubkey.as_slice()));
+
+  let record = Record { attestation: host_cbor.clone(), trusts_caller: true, depth: Some(2), parent: vec![9; 32], node_addr: Some("10.0.0.1:2240".into()) };
+  let parsed = crate::topology::parse_record(&record.to_value().encode()).unwrap();
+  assert_eq!(parsed, crate::topology::Record { attestation: host_cbor, parent: vec![9; 32], trusts_caller: true, depth: Some(2), node_addr: Some("10.0.0.1:2240".into()) });
+  assert_eq!(Record::from_value(&record.to_value()), Some(record));
+}
+
+// A capacity probe written in Rust: the discovery record plus the load maps, read by `submit`.
+#[test]
+fn capacity_records_read_back_in_submit() {
+  use weverywhere_guest::load::{capacity_keys, CpuLoad, MemoryLoad};
+  let cpu = crate::sys_utils::CpuLoad { cores: 8, busy_permille: 250, load1_centi: 120 };
+  let memory = crate::sys_utils::MemoryLoad { total_bytes: 16 << 30, available_bytes: 9 << 30 };
+  let ours_cpu = CpuLoad::from_value(&Value::decode(&crate::scheduler::encode_cpu_load(&cpu).unwrap()).unwrap());
+  let ours_memory = MemoryLoad::from_value(&Value::decode(&crate::scheduler::encode_memory_load(&memory).unwrap()).unwrap());
+  assert_eq!(ours_cpu, CpuLoad { cores: 8, busy_permille: 250, load1_centi: 120 });
+  assert_eq!(ours_memory, MemoryLoad { total_bytes: 16 << 30, available_bytes: 9 << 30 });
+
+  let record = Value::int_map([
+    (capacity_keys::CPU, ours_cpu.to_value()),
+    (capacity_keys::MEMORY, ours_memory.to_value()),
+    (capacity_keys::PIDS, Value::Array(vec![Value::Integer(4), Value::Integer(5)])),
+  ]);
+  let capacity = crate::scheduler::Capacity::from_record(&record.encode()).unwrap();
+  assert_eq!(capacity, crate::scheduler::Capacity { cores: 8, busy_permille: 250, mem_total_bytes: 16 << 30, mem_available_bytes: 9 << 30, running: 2 });
+}
+
+#[test]
+fn message_records_decode() {
+  use crate::executor::message_keys as keys;
+  use weverywhere_guest::messages::Message;
+  let record = |seq: i128, to: Option<Vec<u8>>| {
+    let mut map: std::collections::BTreeMap<HostValue, HostValue> = [
+      (HostValue::Integer(keys::SEQ), HostValue::Integer(seq)),
+      (HostValue::Integer(keys::NAME), HostValue::Text("alice".into())),
+      (HostValue::Integer(keys::PUBKEY), HostValue::Bytes(vec![1; 32])),
+      (HostValue::Integer(keys::EPOCH_S), HostValue::Integer(1_760_000_000)),
+      (HostValue::Integer(keys::TEXT), HostValue::Bytes(b"hi".to_vec())),
+      (HostValue::Integer(keys::ROOM), HostValue::Text("ops".into())),
+    ]
+    .into_iter()
+    .collect();
+    if let Some(to) = to {
+      map.insert(HostValue::Integer(keys::TO), HostValue::Bytes(to));
+      map.insert(HostValue::Integer(keys::ID), HostValue::Bytes(vec![3; 16]));
+    }
+    HostValue::Map(map)
+  };
+  let cbor = serde_cbor::to_vec(&HostValue::Array(vec![record(1, None), record(2, Some(vec![2; 32])), HostValue::Text("junk".into())])).unwrap();
+  let messages = Message::decode_list(&cbor);
+  let plain = Message { seq: 1, name: "alice".into(), pubkey: vec![1; 32], epoch_s: 1_760_000_000, text: b"hi".to_vec(), room: "ops".into(), to: None, id: None };
+  let direct = Message { seq: 2, to: Some(vec![2; 32]), id: Some(vec![3; 16]), ..plain.clone() };
+  assert_eq!(messages, [plain.clone(), direct.clone()]);
+  assert_eq!(Message::from_value(&direct.to_value()), Some(direct));
+}
+
+// host::replicate reads `{1: [list], 2: [k0, v0, ...]}`.
+#[test]
+fn replicate_args_have_the_shape_the_host_reads() {
+  let args = weverywhere_guest::args::Args { list: vec!["a".into(), "b".into()], map: vec![("mode".into(), "fast".into())] };
+  let texts = |items: &[&str]| HostValue::Array(items.iter().map(|s| HostValue::Text(s.to_string())).collect());
+  let expected = HostValue::Map([(HostValue::Integer(1), texts(&["a", "b"])), (HostValue::Integer(2), texts(&["mode", "fast"]))].into_iter().collect());
+  assert_eq!(serde_cbor::from_slice::<HostValue>(&args.encode()).unwrap(), expected);
+  assert_eq!(args.get("mode"), Some("fast"));
+}
+
+#[test]
+fn tty_events_decode_in_both_protocols() {
+  use weverywhere_guest::tty::{Event, Key, Mouse};
+  let v2 = [
+    (TtyEvent::Char('é'), Event::Key(Key::Char('é'), 0)),
+    (TtyEvent::CtrlC, Event::Key(Key::CtrlC, modifiers::CTRL)),
+    (TtyEvent::Modified(modifiers::ALT | modifiers::SHIFT, Box::new(TtyEvent::Left)), Event::Key(Key::Left, modifiers::ALT | modifiers::SHIFT)),
+    (TtyEvent::F(11), Event::Key(Key::F(11), 0)),
+    (TtyEvent::BackTab, Event::Key(Key::BackTab, 0)),
+    (TtyEvent::Resize(300, 40), Event::Resize(300, 40)),
+    (TtyEvent::Paste("two\nlines".into()), Event::Paste("two\nlines".into())),
+    (
+      TtyEvent::Mouse(MouseEvent { action: mouse_action::DRAG, button: mouse_button::RIGHT, col: 7, row: 513, modifiers: modifiers::CTRL }),
+      Event::Mouse(Mouse { action: mouse_action::DRAG, button: mouse_button::RIGHT, col: 7, row: 513, modifiers: modifiers::CTRL }),
+    ),
+  ];
+  for (host, ours) in v2 {
+    assert_eq!(Event::decode(&encode_event_v2(&host)), Some(ours), "{host:?}");
+  }
+  // Protocol 1's enter is a lone byte 2, which a protocol 2 event never is.
+  assert_eq!(Event::decode(&encode_event(&TtyEvent::Enter)), Some(Event::Key(Key::Enter, 0)));
+  assert_eq!(Event::decode(&encode_event(&TtyEvent::Char('x'))), Some(Event::Key(Key::Char('x'), 0)));
+  assert_eq!(Event::decode(&encode_event(&TtyEvent::Resize(80, 24))), Some(Event::Resize(80, 24)));
+  assert_eq!(Event::decode(&[]), None);
+}
+
+#[test]
+fn peer_reports_parse() {
+  use weverywhere_guest::peers::Peer;
+  let line = format!("box\t10.0.0.2:2240\t1\t{}", crate::crypto_utils::to_hex(&[0xab; 32]));
+  assert_eq!(Peer::parse(&line), Some(Peer { name: "box".into(), addr: "10.0.0.2:2240".into(), trusted: true, pubkey: vec![0xab; 32] }));
+  assert_eq!(Peer::parse("box\t10.0.0.2:2240\t0"), None);
+  assert_eq!(Peer::parse("box\t10.0.0.2:2240\t0\txyz"), None);
+}
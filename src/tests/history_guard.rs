@@ -0,0 +1,37 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{Duration, Instant};
+
+use crate::history_guard::{HistoryGuard, Refusal, MAX_PER_SOURCE, REQUEST_WINDOW_HALF_S, SOURCE_WINDOW};
+
+const NOW_S: u64 = 1_000_000;
+const HOST_A: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+const HOST_B: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+fn uuid(n: u8) -> [u8; 16] {
+  [n; 16]
+}
+
+#[test]
+fn only_fresh_requests_are_answered_and_each_only_once() {
+  let mut guard = HistoryGuard::new();
+  let t0 = Instant::now();
+  assert_eq!(guard.admit(HOST_A, uuid(1), NOW_S - REQUEST_WINDOW_HALF_S - 1, NOW_S, t0), Err(Refusal::Stale));
+  assert_eq!(guard.admit(HOST_A, uuid(2), NOW_S + REQUEST_WINDOW_HALF_S + 1, NOW_S, t0), Err(Refusal::Stale), "from the future");
+  assert_eq!(guard.admit(HOST_A, uuid(3), NOW_S - REQUEST_WINDOW_HALF_S, NOW_S, t0), Ok(()));
+  assert_eq!(guard.admit(HOST_B, uuid(3), NOW_S - REQUEST_WINDOW_HALF_S, NOW_S, t0), Err(Refusal::Replayed), "whichever address replays it");
+  // Once the request has aged out of the window it is stale, so forgetting its uuid reopens nothing.
+  let later = NOW_S + REQUEST_WINDOW_HALF_S;
+  assert_eq!(guard.admit(HOST_B, uuid(3), NOW_S - REQUEST_WINDOW_HALF_S, later, t0 + SOURCE_WINDOW), Err(Refusal::Stale));
+}
+
+#[test]
+fn each_source_address_is_answered_a_few_times_per_window() {
+  let mut guard = HistoryGuard::new();
+  let t0 = Instant::now();
+  for n in 0..MAX_PER_SOURCE as u8 {
+    assert_eq!(guard.admit(HOST_A, uuid(n), NOW_S, NOW_S, t0), Ok(()));
+  }
+  assert_eq!(guard.admit(HOST_A, uuid(100), NOW_S, NOW_S, t0 + Duration::from_secs(1)), Err(Refusal::RateLimited));
+  assert_eq!(guard.admit(HOST_B, uuid(101), NOW_S, NOW_S, t0 + Duration::from_secs(1)), Ok(()), "other addresses aren't held up");
+  assert_eq!(guard.admit(HOST_A, uuid(102), NOW_S, NOW_S, t0 + SOURCE_WINDOW), Ok(()), "the window has moved on");
+}
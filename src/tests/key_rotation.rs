@@ -0,0 +1,88 @@
+use crate::key_rotation::{Rotation, statement, verify};
+
+fn key() -> ed25519_dalek::SigningKey {
+  ed25519_dalek::SigningKey::generate(&mut rand::rngs::OsRng)
+}
+
+#[test]
+fn rotation_statements_verify_and_reject_tampering() {
+  let (old, new, other) = (key(), key(), key());
+  let now = crate::sys_utils::epoch_seconds_now_utc0();
+  let msg = statement("node", &old, &new);
+  let rotation = verify(&msg, now).unwrap();
+  assert_eq!((rotation.old, rotation.new), (old.verifying_key(), new.verifying_key()));
+
+  // Someone else can't name themselves the successor, even re-signing as the new key.
+  let crate::messages::NetworkMessage::KeyRotation { old: old_id, epoch_s, old_signature, .. } = msg.clone() else { unreachable!() };
+  let forged = statement("node", &other, &other);
+  let crate::messages::NetworkMessage::KeyRotation { new: other_id, new_signature, .. } = statement("node", &old, &other) else { unreachable!() };
+  let swapped = crate::messages::NetworkMessage::KeyRotation { old: old_id.clone(), new: other_id, epoch_s, old_signature: old_signature.clone(), new_signature };
+  assert!(verify(&swapped, now).is_err(), "the old key's signature names the successor");
+  assert!(verify(&forged, now).is_err(), "a key can't succeed itself");
+  assert!(verify(&msg, now - 3600).is_err(), "a statement from the future is refused");
+
+  let line = rotation.to_line();
+  assert_eq!(Rotation::parse_line(&line), Some(rotation));
+  assert_eq!(Rotation::parse_line("# comment"), None);
+  assert_eq!(Rotation::parse_line("12 ssh-ed25519 nonsense"), None);
+}
+
+#[tokio::test]
+async fn a_trusted_keys_successors_are_trusted_for_as_long_as_it_is() {
+  let dir = std::env::temp_dir().join(format!("weverywhere-key-rotation-{}", std::process::id()));
+  std::fs::create_dir_all(&dir).unwrap();
+  let keyfile = dir.join("identity.pem");
+  crate::crypto_utils::generate_private_key_ed25519_pem_file(&keyfile).await.unwrap();
+  let (alice, alice_next, alice_third, mallory, mallory_next) = (key(), key(), key(), key(), key());
+  let config_trusting = |trusted: &str| -> crate::config::Config {
+    toml::from_str(&format!(
+      "[identity]\nname = \"t\"\nkeyfile = {:?}\n\n[state]\ndir = {:?}\n{trusted}",
+      keyfile.to_string_lossy(), dir.to_string_lossy(),
+    )).unwrap()
+  };
+  let alice_trusted = format!("\n[[trusted]]\nkey = {:?}\n", crate::crypto_utils::format_public_key(&alice));
+
+  let executor = crate::executor::Executor::new(&config_trusting(&alice_trusted)).await;
+  let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(3);
+  while !executor.trusts_pubkey(alice.verifying_key().as_bytes()) && tokio::time::Instant::now() < deadline {
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+  }
+  let now = crate::sys_utils::epoch_seconds_now_utc0();
+  let untrusted = verify(&statement("mallory", &mallory, &mallory_next), now).unwrap();
+  assert!(!executor.accept_key_rotation(&untrusted).await, "only a trusted key's successor is taken on");
+  assert!(!executor.trusts_pubkey(mallory_next.verifying_key().as_bytes()));
+
+  let rotation = verify(&statement("alice", &alice, &alice_next), now).unwrap();
+  assert!(executor.accept_key_rotation(&rotation).await);
+  assert!(!executor.accept_key_rotation(&rotation).await, "a repeat changes nothing");
+  assert!(executor.trusts_pubkey(alice_next.verifying_key().as_bytes()));
+
+  let next_rotation = verify(&statement("alice", &alice_next, &alice_third), now).unwrap();
+  assert!(executor.accept_key_rotation(&next_rotation).await, "a successor's own successor is taken on too");
+
+  // Recorded, so the chain outlasts a reload that keeps the old key...
+  executor.apply_config(&config_trusting(&alice_trusted));
+  assert!(executor.trusts_pubkey(alice_next.verifying_key().as_bytes()));
+  assert!(executor.trusts_pubkey(alice_third.verifying_key().as_bytes()));
+  let recorded = crate::key_rotation::load(&crate::key_rotation::path(&executor.config().state));
+  assert_eq!(recorded, vec![rotation, next_rotation]);
+
+  // ...but not one that drops it: revoking a key revokes everything it handed its trust to.
+  executor.apply_config(&config_trusting(""));
+  for key in [&alice, &alice_next, &alice_third] {
+    assert!(!executor.trusts_pubkey(key.verifying_key().as_bytes()));
+  }
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn rotations_chain_only_from_trusted_keys() {
+  use crate::key_rotation::{chained, Rotation};
+  let (a, b, c, x, y) = (key().verifying_key(), key().verifying_key(), key().verifying_key(), key().verifying_key(), key().verifying_key());
+  let rotate = |old, new| Rotation { old, new, epoch_s: 1 };
+  // Recorded out of order: B -> C is only taken on once A -> B has made B trusted.
+  let recorded = vec![rotate(b, c), rotate(x, y), rotate(a, b)];
+  assert_eq!(chained(&[a], recorded.clone()), vec![rotate(a, b), rotate(b, c)]);
+  assert_eq!(chained(&[b], recorded.clone()), vec![rotate(b, c)]);
+  assert!(chained(&[], recorded).is_empty());
+}
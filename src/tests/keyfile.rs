@@ -0,0 +1,63 @@
+use crate::config::IdentityConfig;
+
+fn identity(dir: &std::path::Path, passphrase_file: Option<std::path::PathBuf>, passphrase_command: &[&str]) -> IdentityConfig {
+  IdentityConfig {
+    name: "t".to_string(),
+    keyfile: dir.join("identity.pem"),
+    passphrase_file,
+    passphrase_command: passphrase_command.iter().map(|s| s.to_string()).collect(),
+  }
+}
+
+// A keyfile encrypted under the passphrase in `passphrase_file` (or printed by `passphrase_command`)
+// unlocks without a prompt, is written owner-only, and a wrong passphrase is an error, not a new key.
+#[tokio::test]
+async fn encrypted_keyfiles_unlock_from_the_configured_passphrase() {
+  let dir = std::env::temp_dir().join(format!("weverywhere-keyfile-{}", std::process::id()));
+  std::fs::create_dir_all(&dir).unwrap();
+  let passphrase_file = dir.join("passphrase");
+  std::fs::write(&passphrase_file, "s3cret\n").unwrap();
+  let from_file = identity(&dir, Some(passphrase_file.clone()), &[]);
+  let key = ed25519_dalek::SigningKey::generate(&mut rand::rngs::OsRng);
+
+  let passphrase = crate::keyfile::configured_passphrase(&from_file).await.unwrap();
+  assert_eq!(passphrase.as_deref(), Some("s3cret"));
+  crate::keyfile::write(&from_file.keyfile, &key, passphrase.as_deref()).await.unwrap();
+  assert!(crate::crypto_utils::is_encrypted_private_key_pem(&std::fs::read_to_string(&from_file.keyfile).unwrap()));
+  let unlocked = crate::keyfile::unlock(&from_file).await.unwrap();
+  assert_eq!(unlocked.key.as_bytes(), key.as_bytes());
+  assert_eq!(unlocked.passphrase.as_deref(), Some("s3cret"));
+
+  #[cfg(unix)]
+  {
+    let from_command = identity(&dir, None, &["echo", "s3cret"]);
+    assert_eq!(from_command.read_public_key_ed25519_pem_file().await.unwrap(), key.verifying_key());
+  }
+
+  // Rewriting the file under another passphrase drops the cached key, so the old passphrase fails.
+  crate::keyfile::write(&from_file.keyfile, &key, Some("other")).await.unwrap();
+  assert!(crate::keyfile::unlock(&from_file).await.is_err());
+
+  std::fs::write(&passphrase_file, "").unwrap();
+  assert!(crate::keyfile::configured_passphrase(&from_file).await.is_err());
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// Keyfiles are created 0600, and one that others can read is called out.
+#[cfg(unix)]
+#[tokio::test]
+async fn keyfiles_are_owner_only_and_wider_permissions_are_reported() {
+  use std::os::unix::fs::PermissionsExt;
+  let dir = std::env::temp_dir().join(format!("weverywhere-keyfile-mode-{}", std::process::id()));
+  std::fs::create_dir_all(&dir).unwrap();
+  let keyfile = dir.join("identity.pem");
+  std::fs::write(&keyfile, "old").unwrap();
+  std::fs::set_permissions(&keyfile, std::fs::Permissions::from_mode(0o644)).unwrap();
+  let problem = crate::crypto_utils::keyfile_permission_problem(&keyfile).expect("0644 is a problem");
+  assert!(problem.contains("its group and everyone else") && problem.contains("644"), "got: {problem}");
+
+  crate::crypto_utils::generate_private_key_ed25519_pem_file(&keyfile).await.unwrap();
+  assert_eq!(std::fs::metadata(&keyfile).unwrap().permissions().mode() & 0o777, 0o600);
+  assert_eq!(crate::crypto_utils::keyfile_permission_problem(&keyfile), None);
+  std::fs::remove_dir_all(&dir).unwrap();
+}
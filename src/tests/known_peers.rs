@@ -0,0 +1,82 @@
+use crate::config::{IdentityData, PeerMetadata, UnpinnedPeers};
+use crate::known_peers::{self, KnownPeer, PeerCheck, PeerKey};
+use crate::tests::{identity, temp_config};
+
+fn ssh_key(node: &IdentityData) -> String {
+  let key: [u8; 32] = node.encoded_public_key.as_slice().try_into().unwrap();
+  crate::crypto_utils::format_verifying_key(&ed25519_dalek::VerifyingKey::from_bytes(&key).unwrap())
+}
+
+fn peer(toml_text: &str) -> PeerMetadata {
+  toml::from_str(toml_text).unwrap()
+}
+
+const ADDR: &str = "10.0.0.9:2240";
+
+#[test]
+fn known_peer_lines_round_trip_and_match_any_configured_host() {
+  let (_, node) = identity("node1");
+  let line = format!("node1.lan,10.0.0.9 {}", ssh_key(&node));
+  let known = KnownPeer::parse_line(&line).expect("a valid line");
+  assert_eq!(known.hosts, vec!["node1.lan".to_string(), "10.0.0.9".to_string()]);
+  assert_eq!(known.to_line(), line);
+  assert!(known.is_for(&peer("ipv4 = \"10.0.0.9\"")));
+  assert!(known.is_for(&peer("hostname = \"NODE1.lan\"")));
+  assert!(!known.is_for(&peer("ipv4 = \"10.0.0.10\"")));
+  assert_eq!(KnownPeer::parse_line("# node1.lan ssh-ed25519 AAAA"), None);
+  assert_eq!(KnownPeer::parse_line("node1.lan not-a-key"), None);
+}
+
+#[tokio::test]
+async fn pinned_peer_refuses_any_other_key() {
+  let ((_, real), (_, imposter)) = (identity("node1"), identity("node1"));
+  let mut config = temp_config("known-peers-pinned");
+  config.state.unpinned_peers = UnpinnedPeers::Warn;
+  let pinned = peer(&format!("ipv4 = \"10.0.0.9\"\nexpected_key = {{ key = \"{}\" }}", ssh_key(&real)));
+  let check = PeerCheck::new(&config, &pinned).await.unwrap();
+  assert_eq!(check.key, PeerKey::Pinned(real.encoded_public_key.clone()));
+  assert!(check.is_enforced());
+  assert!(check.admit("test", ADDR.parse().unwrap(), &real).await);
+  assert!(!check.admit("test", ADDR.parse().unwrap(), &imposter).await);
+
+  let typo = peer("ipv4 = \"10.0.0.9\"\nexpected_key = { key = \"ssh-ed25519 AAAA\" }");
+  assert!(PeerCheck::new(&config, &typo).await.is_err(), "an unparseable pin must not read as unpinned");
+}
+
+#[tokio::test]
+async fn tofu_records_the_first_key_and_holds_the_peer_to_it() {
+  let ((_, first), (_, later)) = (identity("node1"), identity("node1"));
+  let mut config = temp_config("known-peers-tofu");
+  config.state.unpinned_peers = UnpinnedPeers::Tofu;
+  let unpinned = peer("hostname = \"node1.lan\"\nipv4 = \"10.0.0.9\"");
+
+  let check = PeerCheck::new(&config, &unpinned).await.unwrap();
+  assert_eq!(check.key, PeerKey::Unpinned);
+  assert!(check.is_enforced(), "TOFU needs a signed reply to learn the key from");
+  assert!(check.admit("test", ADDR.parse().unwrap(), &first).await);
+
+  let path = known_peers::path(&config.state);
+  let known = known_peers::load(&path).await.unwrap();
+  assert_eq!(known.len(), 1);
+  assert_eq!(known[0].hosts, vec!["node1.lan".to_string(), "10.0.0.9".to_string()]);
+
+  // The next run looks the peer up (by either of its hosts) and refuses a different key.
+  let check = PeerCheck::new(&config, &peer("ipv4 = \"10.0.0.9\"")).await.unwrap();
+  assert_eq!(check.key, PeerKey::Known(first.encoded_public_key.clone()));
+  assert!(!check.admit("test", ADDR.parse().unwrap(), &later).await);
+  assert!(check.admit("test", ADDR.parse().unwrap(), &first).await);
+  assert_eq!(known_peers::load(&path).await.unwrap().len(), 1, "a known peer isn't recorded again");
+
+  let _ = std::fs::remove_dir_all(&config.state.dir);
+}
+
+#[tokio::test]
+async fn warn_mode_leaves_unpinned_peers_unchecked() {
+  let mut config = temp_config("known-peers-warn");
+  config.state.unpinned_peers = UnpinnedPeers::Warn;
+  let check = PeerCheck::new(&config, &peer("ipv4 = \"10.0.0.9\"")).await.unwrap();
+  assert_eq!(check.key, PeerKey::Unpinned);
+  assert!(!check.is_enforced());
+  assert!(check.admit("test", ADDR.parse().unwrap(), &identity("anyone").1).await);
+  assert!(!known_peers::path(&config.state).exists(), "nothing is recorded outside TOFU mode");
+}
@@ -38,3 +38,150 @@ fn cbor_map_and_list_round_trip() {
   let enc = serde_cbor::to_vec(&list).expect("cbor list encode");
   assert_eq!(serde_cbor::from_slice::<Value>(&enc).expect("cbor list decode"), list);
 }
+
+#[test]
+fn program_stdin_round_trips_over_bare() {
+  // ProgramStdin is appended after the existing variants; make sure it decodes intact, including an
+  // empty eof-only chunk (how a client closes a remote program's stdin).
+  let id = [3u8; 16];
+  for (seq, data, eof) in [(0, b"line one\n".to_vec(), false), (1, Vec::new(), true)] {
+    let msg = NetworkMessage::ProgramStdin { request_id: id, pid: 9, seq, data: data.clone(), eof };
+    let bytes = serde_bare::to_vec(&msg).expect("encode");
+    match serde_bare::from_slice::<NetworkMessage>(&bytes).expect("decode") {
+      NetworkMessage::ProgramStdin { request_id, pid, seq: got_seq, data: got, eof: got_eof } => {
+        assert_eq!(request_id, id);
+        assert_eq!(pid, 9);
+        assert_eq!(got_seq, seq);
+        assert_eq!(got, data);
+        assert_eq!(got_eof, eof);
+      }
+      other => panic!("wrong variant: {other:?}"),
+    }
+  }
+}
+
+#[test]
+fn program_exit_round_trips_with_reason() {
+  // ExitReason rides inside ProgramExit as a nested bare enum; check a data-carrying variant and
+  // the optional fuel / backtrace fields survive.
+  use crate::messages::ExitReason;
+  let msg = NetworkMessage::ProgramExit {
+    from_pid: 5,
+    exit_code: 1,
+    reason: ExitReason::Trap("wasm trap: out of bounds memory access".into()),
+    fuel_consumed: Some(1234),
+    backtrace: vec!["inner+0x1".into(), "func[1]+0x1".into()],
+  };
+  let bytes = serde_bare::to_vec(&msg).expect("encode");
+  match serde_bare::from_slice::<NetworkMessage>(&bytes).expect("decode") {
+    NetworkMessage::ProgramExit { from_pid, exit_code, reason, fuel_consumed, backtrace } => {
+      assert_eq!((from_pid, exit_code), (5, 1));
+      assert_eq!(reason, ExitReason::Trap("wasm trap: out of bounds memory access".into()));
+      assert_eq!(fuel_consumed, Some(1234));
+      assert_eq!(backtrace.len(), 2);
+    }
+    other => panic!("wrong variant: {other:?}"),
+  }
+}
+
+#[test]
+fn fabric_scoped_messages_only_decode_on_their_own_fabric() {
+  use crate::messages::{decode, FabricId};
+
+  assert!(FabricId::named("").is_default());
+  assert_eq!(FabricId::named("staging"), FabricId::named("staging"));
+  assert_ne!(FabricId::named("staging"), FabricId::named("prod"));
+
+  let msg = NetworkMessage::ProgramStdin { request_id: [3u8; 16], pid: 9, seq: 0, data: b"hi".to_vec(), eof: false };
+  // The default fabric sends messages bare, exactly as before fabrics existed.
+  assert_eq!(FabricId::default().encode(&msg).unwrap(), serde_bare::to_vec(&msg).unwrap());
+
+  let staging = FabricId::named("staging");
+  let bytes = staging.encode(&msg).unwrap();
+  let (fabric, inner) = decode(&bytes).expect("decode");
+  assert_eq!(fabric, staging);
+  assert!(matches!(inner, NetworkMessage::ProgramStdin { request_id, pid: 9, .. } if request_id == [3u8; 16]));
+  assert!(staging.decode(&bytes).is_some());
+  assert!(FabricId::default().decode(&bytes).is_none());
+  assert!(FabricId::named("prod").decode(&bytes).is_none());
+
+  // An envelope inside an envelope is never valid.
+  let nested = FabricId::named("prod").scope(bytes).unwrap();
+  assert!(decode(&nested).is_err());
+}
+
+#[test]
+fn history_replies_split_entries_to_fit_each_datagram() {
+  use crate::messages::{history_replies, HistoryEntry};
+  let entry = |n: usize| HistoryEntry { epoch_s: n as u64, message: vec![0u8; n] };
+  let sizes = |replies: &[NetworkMessage]| -> Vec<Vec<u64>> {
+    replies.iter().map(|r| match r {
+      NetworkMessage::HistoryReply { request_uuid, entries } => {
+        assert_eq!(*request_uuid, [9u8; 16]);
+        entries.iter().map(|e| e.epoch_s).collect()
+      }
+      other => panic!("wrong variant: {other:?}"),
+    }).collect()
+  };
+
+  assert!(history_replies([9u8; 16], Vec::new(), 100).is_empty());
+  let replies = history_replies([9u8; 16], vec![entry(40), entry(50), entry(30), entry(90)], 100);
+  assert_eq!(sizes(&replies), vec![vec![40, 50], vec![30], vec![90]]);
+  // An entry bigger than the budget still goes, on its own.
+  let replies = history_replies([9u8; 16], vec![entry(10), entry(500), entry(10)], 100);
+  assert_eq!(sizes(&replies), vec![vec![10], vec![500], vec![10]]);
+}
+
+// SignedFabricMessage is laid out as older nodes decode it, field for field; asking for acks takes a
+// variant of its own, whose signature doesn't pass for a plain message's.
+#[test]
+fn signed_fabric_messages_keep_their_wire_layout() {
+  use crate::messages::fabric_message_payload;
+  let (key, source) = crate::tests::identity("sender");
+  let (id, cbor_data) = (vec![7u8; 16], vec![0x81, 0x01]);
+  let signature = crate::config::IdentityData::sign_payload(&key, &id, &fabric_message_payload(&cbor_data, false)).to_bytes().to_vec();
+  let msg = NetworkMessage::SignedFabricMessage { source: source.clone(), id: id.clone(), cbor_data: cbor_data.clone(), signature: signature.clone() };
+  let fields = serde_bare::to_vec(&(source.clone(), id.clone(), cbor_data.clone(), signature)).unwrap();
+  assert_eq!(serde_bare::to_vec(&msg).unwrap(), [vec![5], fields].concat(), "variant 5, then the four original fields");
+
+  let reliable = crate::config::IdentityData::sign_payload(&key, &id, &fabric_message_payload(&cbor_data, true)).to_bytes().to_vec();
+  assert!(source.verify_payload(&id, &fabric_message_payload(&cbor_data, true), &reliable).is_ok());
+  assert!(source.verify_payload(&id, &fabric_message_payload(&cbor_data, false), &reliable).is_err(), "a reliable send can't be replayed as a plain one");
+  let msg = NetworkMessage::ReliableFabricMessage { source, id, cbor_data, signature: reliable };
+  assert!(crate::messages::wants_ack(&msg));
+}
+
+// Options newer than ProgramData's wire layout ride in an ExtendedExecuteRequest; a program that sets
+// none of them is still the plain ExecuteRequest older nodes decode.
+#[test]
+fn program_options_travel_in_an_extended_request() {
+  let (_, source) = crate::tests::identity("client");
+  let program = || crate::executor::ProgramDataBuilder::new().set_source(&source).set_wasm_program_bytes([0, 97, 115, 109]);
+  let round_trip = |msg: &NetworkMessage| serde_bare::from_slice::<NetworkMessage>(&serde_bare::to_vec(msg).unwrap()).unwrap();
+
+  let plain = NetworkMessage::execute_request(program().build().unwrap());
+  assert!(matches!(plain, NetworkMessage::ExecuteRequest { .. }));
+  let NetworkMessage::ExecuteRequest { program_data: ref pd } = plain else { unreachable!() };
+  let fields = serde_bare::to_vec(&(&pd.source, &pd.human_name, &pd.wasm_program_bytes, &pd.signature,
+    pd.request_uuid, pd.depth_budget, &pd.visited, &pd.arg_list, &pd.arg_map)).unwrap();
+  assert_eq!(serde_bare::to_vec(&plain).unwrap(), [vec![0], fields].concat(), "variant 0, then ProgramData's original fields");
+
+  let piped = NetworkMessage::execute_request(program().set_wants_stdin(true).build().unwrap());
+  let decoded = round_trip(&piped);
+  assert!(matches!(decoded, NetworkMessage::ExtendedExecuteRequest { .. }));
+  let program_data = decoded.into_program_data().expect("a program");
+  assert!(program_data.options.wants_stdin);
+  assert_eq!(program_data.options.target_pubkey, None);
+
+  let addressed = NetworkMessage::execute_request(program().set_target_pubkey(Some(vec![3; 32])).build().unwrap());
+  let program_data = round_trip(&addressed).into_program_data().expect("a program");
+  assert_eq!(program_data.options.target_pubkey, Some(vec![3; 32]));
+  assert!(!program_data.options.wants_stdin);
+
+  let timed = NetworkMessage::execute_request(program().set_time_budget_ms(5_000).build().unwrap());
+  assert_eq!(round_trip(&timed).into_program_data().expect("a program").options.time_budget_ms, 5_000);
+  let tty = NetworkMessage::execute_request(program().set_tty_size(Some((80, 24))).build().unwrap());
+  assert_eq!(round_trip(&tty).into_program_data().expect("a program").options.tty_size, Some((80, 24)));
+  assert_eq!(program_data.wasm_program_bytes, [0, 97, 115, 109]);
+  assert!(NetworkMessage::TtyRedraw { request_id: [0; 16], pid: 1 }.into_program_data().is_none());
+}
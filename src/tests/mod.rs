@@ -4,7 +4,46 @@
 
 mod config;
 mod crypto_utils;
+mod delivery;
 mod discovery;
 mod executor;
+mod fabric;
+mod fabric_sim;
+mod fanout;
+mod guest;
+mod history_guard;
+mod key_rotation;
+mod keyfile;
+mod known_peers;
 mod messages;
+mod peer_registry;
+mod run;
+mod scheduler;
+mod topology;
 mod tty;
+
+/// A fresh identity key named `name`, and the self-signed identity a node presents with it.
+fn identity(name: &str) -> (ed25519_dalek::SigningKey, crate::config::IdentityData) {
+  let key = ed25519_dalek::SigningKey::generate(&mut rand::rngs::OsRng);
+  let pubkey = key.verifying_key().as_bytes().to_vec();
+  let sig = crate::config::IdentityData::sign_identity_data(&key, name, &1, &u16::MAX, "ed25519", &pubkey);
+  let id = crate::config::IdentityData {
+    human_name: name.into(),
+    generated_at_utc0_epoch_s: 1,
+    validity_s: u16::MAX,
+    encoded_public_key_fmt: "ed25519".into(),
+    encoded_public_key: pubkey,
+    signature: sig.to_vec(),
+  };
+  (key, id)
+}
+
+/// A config whose state dir is a fresh temp dir named for `tag` and whose keyfile doesn't exist
+/// there, so nothing a test does touches the real ones. Tests remove `config.state.dir` when done.
+fn temp_config(tag: &str) -> crate::config::Config {
+  let dir = std::env::temp_dir().join(format!("weverywhere-{tag}-{}", crate::crypto_utils::to_hex(&crate::discovery::random_uuid16())));
+  let mut config: crate::config::Config = toml::from_str("[identity]\nname = \"t\"\n").unwrap();
+  config.identity.keyfile = dir.join("missing.pem");
+  config.state.dir = dir;
+  config
+}
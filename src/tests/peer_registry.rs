@@ -0,0 +1,57 @@
+use crate::config::{Config, PeerMetadata};
+use crate::peer_registry::{self, PeerRecord};
+
+fn record(pubkey: &[u8], last_seen_epoch_s: u64) -> PeerRecord {
+  PeerRecord {
+    name: "node1".into(),
+    pubkey: crate::crypto_utils::to_hex(pubkey),
+    addr: "10.0.0.9:2240".parse().unwrap(),
+    trusted: true,
+    last_seen_epoch_s,
+  }
+}
+
+#[test]
+fn records_expire_after_the_ttl_unless_it_is_zero() {
+  let r = record(&[1; 32], 1_000);
+  assert!(!r.is_expired(1_500, 600));
+  assert!(r.is_expired(1_601, 600));
+  assert!(!r.is_expired(u64::MAX, 0), "a TTL of 0 keeps peers forever");
+  assert!(!r.is_expired(0, 600), "a clock behind the record doesn't expire it");
+}
+
+#[test]
+fn pinned_block_parses_back_as_a_peer_with_the_observed_key() {
+  let key = ed25519_dalek::SigningKey::generate(&mut rand::rngs::OsRng);
+  let r = record(key.verifying_key().as_bytes(), 1);
+  let block = r.to_pinned_toml().unwrap();
+  let peer: PeerMetadata = toml::from_str(block.trim_start_matches("[[peer]]\n")).unwrap();
+  assert_eq!(peer.ipv4, Some("10.0.0.9".parse().unwrap()));
+  let pinned = crate::crypto_utils::public_key_to_ed25519_vk(peer.expected_key_str().unwrap()).unwrap();
+  assert_eq!(pinned, key.verifying_key());
+}
+
+#[tokio::test]
+async fn executor_reloads_saved_peers_and_drops_forgotten_ones() {
+  let config = crate::tests::temp_config("peer-registry-reload");
+  let path = peer_registry::path(&config.state);
+  let now = crate::sys_utils::epoch_seconds_now_utc0();
+  let (alice, bob, stale) = (record(&[0xAA; 32], now), record(&[0xBB; 32], now), record(&[0xCC; 32], 1));
+  peer_registry::save(&path, &[alice.clone(), bob.clone(), stale]).await.unwrap();
+
+  // A restart knows every unexpired peer from before.
+  let executor = crate::executor::Executor::new(&config).await;
+  let mut known: Vec<Vec<u8>> = executor.observed_targets().into_iter().map(|(_, pk)| pk).collect();
+  known.sort();
+  assert_eq!(known, vec![vec![0xAA; 32], vec![0xBB; 32]]);
+
+  // `peers forget bob` edits the file; the running executor follows at its next flush and writes
+  // back only the peers it still knows.
+  peer_registry::save(&path, std::slice::from_ref(&alice)).await.unwrap();
+  executor.flush_peer_registry().await;
+  let known: Vec<Vec<u8>> = executor.observed_targets().into_iter().map(|(_, pk)| pk).collect();
+  assert_eq!(known, vec![vec![0xAA; 32]]);
+  assert_eq!(peer_registry::load(&path).await.unwrap(), vec![alice]);
+
+  let _ = std::fs::remove_dir_all(&config.state.dir);
+}
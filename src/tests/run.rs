@@ -0,0 +1,40 @@
+use crate::command::run::{IdentityGate, TargetNode};
+use crate::config::IdentityData;
+use crate::messages::{program_accepted_payload, NetworkMessage};
+use crate::tests::identity;
+
+fn stdout(pid: u64, text: &str) -> NetworkMessage {
+  NetworkMessage::BasicInsecureProgramStdout { from_pid: pid, stdout_data: text.as_bytes().to_vec() }
+}
+
+fn shown(msgs: Vec<NetworkMessage>) -> Vec<String> {
+  msgs.into_iter().map(|m| match m {
+    NetworkMessage::BasicInsecureProgramStdout { stdout_data, .. } => String::from_utf8(stdout_data).unwrap(),
+    NetworkMessage::ProgramAccepted { .. } => "accepted".into(),
+    other => panic!("unexpected reply {other:?}"),
+  }).collect()
+}
+
+// Once the target has accepted, its pid is known to anyone watching; replies carrying it only count
+// from the address that signed the acceptance.
+#[tokio::test]
+async fn the_identity_gate_only_admits_replies_from_the_accepting_address() {
+  let (key, node) = identity("node1");
+  let real: std::net::SocketAddr = "10.0.0.9:2240".parse().unwrap();
+  let spoofed: std::net::SocketAddr = "10.0.0.66:2240".parse().unwrap();
+  let target = TargetNode { addr: real, pubkey: Some(node.encoded_public_key.clone()), label: "node1".into(), peer: None };
+  let uuid = [7; 16];
+  let mut gate = IdentityGate::new(&target, uuid);
+
+  // Replies that race ahead of the acceptance are held, and only the accepting address's are shown.
+  assert!(gate.admit(real, stdout(5, "early")).await.is_empty());
+  assert!(gate.admit(spoofed, stdout(5, "forged early")).await.is_empty());
+  let signature = IdentityData::sign_payload(&key, &uuid, &program_accepted_payload(5, false)).to_bytes().to_vec();
+  let accepted = NetworkMessage::ProgramAccepted { request_uuid: uuid, from_pid: 5, node, trusts_caller: false, signature };
+  assert_eq!(shown(gate.admit(real, accepted).await), ["accepted", "early"]);
+  assert!(!gate.never_confirmed());
+
+  assert!(gate.admit(spoofed, stdout(5, "forged")).await.is_empty());
+  assert!(gate.admit(real, stdout(6, "another program")).await.is_empty());
+  assert_eq!(shown(gate.admit(real, stdout(5, "late")).await), ["late"]);
+}
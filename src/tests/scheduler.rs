@@ -0,0 +1,56 @@
+use crate::scheduler::*;
+
+/// A capacity record as the probe builds it: the load imports' CBOR spliced in under keys 6-8.
+fn record(cpu: &crate::sys_utils::CpuLoad, mem: &crate::sys_utils::MemoryLoad, pids: &[u64]) -> Vec<u8> {
+  use serde_cbor::Value;
+  let item = |bytes: Vec<u8>| serde_cbor::from_slice::<Value>(&bytes).unwrap();
+  let map = Value::Map(
+    [
+      (Value::Integer(2), Value::Integer(1)),
+      (Value::Integer(capacity_keys::CPU), item(encode_cpu_load(cpu).unwrap())),
+      (Value::Integer(capacity_keys::MEMORY), item(encode_memory_load(mem).unwrap())),
+      (Value::Integer(capacity_keys::PIDS), item(encode_pids(pids).unwrap())),
+    ]
+    .into_iter()
+    .collect(),
+  );
+  serde_cbor::to_vec(&map).unwrap()
+}
+
+fn candidate(name: &str, trusts_us: bool, we_trust: bool, cores: u32, busy_permille: u32, mem_available_bytes: u64) -> Candidate {
+  Candidate {
+    hostname: name.to_string(),
+    pubkey: name.as_bytes().to_vec(),
+    addr: "127.0.0.1:2240".parse().unwrap(),
+    trusts_us,
+    we_trust,
+    capacity: Capacity { cores, busy_permille, mem_total_bytes: 0, mem_available_bytes, running: 0 },
+  }
+}
+
+#[test]
+fn capacity_round_trips_through_the_load_imports() {
+  let cpu = crate::sys_utils::CpuLoad { cores: 8, busy_permille: 250, load1_centi: 175 };
+  let mem = crate::sys_utils::MemoryLoad { total_bytes: 16 << 30, available_bytes: 9 << 30 };
+  let cap = Capacity::from_record(&record(&cpu, &mem, &[3, 7])).unwrap();
+  assert_eq!(cap, Capacity { cores: 8, busy_permille: 250, mem_total_bytes: 16 << 30, mem_available_bytes: 9 << 30, running: 2 });
+  assert_eq!(cap.free_millicores(), 6000);
+
+  // Missing figures read as zero rather than rejecting the node; a non-map is rejected.
+  let bare = serde_cbor::to_vec(&serde_cbor::Value::Map(Default::default())).unwrap();
+  assert_eq!(Capacity::from_record(&bare), Some(Capacity::default()));
+  assert_eq!(Capacity::from_record(&[0x01]), None);
+}
+
+#[test]
+fn rank_prefers_trust_then_headroom() {
+  let ranked = rank(vec![
+    candidate("idle-stranger", false, false, 64, 0, 1 << 40),
+    candidate("busy-friend", true, true, 4, 900, 1 << 30),
+    candidate("idle-friend", true, true, 4, 100, 1 << 30),
+    candidate("trusts-us-only", true, false, 64, 0, 1 << 40),
+    candidate("idle-friend-more-ram", true, true, 4, 100, 2 << 30),
+  ]);
+  let names: Vec<&str> = ranked.iter().map(|c| c.hostname.as_str()).collect();
+  assert_eq!(names, ["idle-friend-more-ram", "idle-friend", "busy-friend", "trusts-us-only", "idle-stranger"]);
+}
@@ -0,0 +1,181 @@
+use std::net::SocketAddr;
+
+use ed25519_dalek::Signer;
+use serde_cbor::Value;
+
+use crate::discovery::{attestation_signing_bytes, build_attestation_cbor, record_keys};
+use crate::topology::{FabricWatch, MapChange, NetworkMap};
+
+const NOW: u64 = 1_760_000_000;
+
+struct Node {
+  key: ed25519_dalek::SigningKey,
+  hostname: &'static str,
+}
+
+impl Node {
+  fn new(hostname: &'static str) -> Node {
+    Node { key: ed25519_dalek::SigningKey::generate(&mut rand::rngs::OsRng), hostname }
+  }
+
+  fn pubkey(&self) -> Vec<u8> {
+    self.key.verifying_key().as_bytes().to_vec()
+  }
+
+  /// The record this node's discovery program would return when reached from `parent`.
+  fn record(&self, parent: &[u8], depth: u64, trusts_parent: bool, addr: &str, epoch_s: u64) -> Vec<u8> {
+    self.record_claiming(self.hostname, parent, depth, trusts_parent, addr, epoch_s)
+  }
+
+  /// Like [`Node::record`], but the attestation claims `hostname` under a signature over our own.
+  fn record_claiming(&self, hostname: &str, parent: &[u8], depth: u64, trusts_parent: bool, addr: &str, epoch_s: u64) -> Vec<u8> {
+    let pubkey = self.pubkey();
+    let sig = self.key.sign(&attestation_signing_bytes(self.hostname, &pubkey, epoch_s)).to_bytes();
+    let attestation = build_attestation_cbor(hostname, &pubkey, epoch_s, &sig).unwrap();
+    let map = Value::Map([
+      (Value::Integer(record_keys::ATTESTATION), Value::Bytes(attestation)),
+      (Value::Integer(record_keys::TRUSTS_CALLER), Value::Integer(trusts_parent as i128)),
+      (Value::Integer(record_keys::DEPTH), Value::Integer(depth as i128)),
+      (Value::Integer(record_keys::PARENT_PUBKEY), Value::Bytes(parent.to_vec())),
+      (Value::Integer(record_keys::NODE_ADDR), Value::Text(addr.to_string())),
+    ].into_iter().collect());
+    serde_cbor::to_vec(&map).unwrap()
+  }
+}
+
+fn from(addr: &str) -> SocketAddr {
+  addr.parse().unwrap()
+}
+
+/// us -> a (trusts us) -> b (doesn't trust a), plus a record with a forged signature and a stale one.
+fn sample() -> (NetworkMap, Vec<u8>, Node, Node) {
+  let us = Node::new("laptop");
+  let (a, b, stale) = (Node::new("fileserver"), Node::new("guest \"pi\""), Node::new("old"));
+  let forged = a.record_claiming("imposter", &us.pubkey(), 1, true, "10.0.0.66:2240", NOW);
+  let replies = vec![
+    (from("10.0.0.1:2240"), a.record(&us.pubkey(), 1, true, "10.0.0.1:2240", NOW)),
+    (from("10.0.0.1:2240"), b.record(&a.pubkey(), 2, false, "10.0.0.2:2240", NOW)),
+    (from("10.0.0.1:2240"), b.record(&a.pubkey(), 2, false, "10.0.0.2:2240", NOW)),
+    (from("10.0.0.66:2240"), forged),
+    (from("10.0.0.3:2240"), stale.record(&us.pubkey(), 1, true, "10.0.0.3:2240", NOW - 3600)),
+  ];
+  let map = NetworkMap::assemble("laptop", &us.pubkey(), &replies, NOW);
+  (map, us.pubkey(), a, b)
+}
+
+#[test]
+fn assemble_verifies_dedups_and_flags_records() {
+  let (map, us, a, b) = sample();
+  assert_eq!(map.nodes.len(), 3, "a, b (once) and the stale node; the forgery is dropped");
+  assert_eq!(map.warnings.len(), 2, "one bad signature, one stale timestamp: {:?}", map.warnings);
+  let edges = map.edges();
+  assert!(edges.iter().any(|e| e.from == us && e.to == a.pubkey() && e.trusted));
+  assert!(edges.iter().any(|e| e.from == a.pubkey() && e.to == b.pubkey() && !e.trusted));
+  assert!(map.render_tree().contains("fileserver"));
+}
+
+#[test]
+fn json_lists_every_node_edge_and_warning() {
+  let (map, us, a, b) = sample();
+  let doc = map.render_json();
+  assert_eq!(doc["you"]["pubkey"], crate::crypto_utils::to_hex(&us));
+  let nodes = doc["nodes"].as_array().unwrap();
+  assert_eq!(nodes.len(), 3);
+  let b_json = nodes.iter().find(|n| n["pubkey"] == crate::crypto_utils::to_hex(&b.pubkey())).unwrap();
+  assert_eq!(b_json["hostname"], "guest \"pi\"");
+  assert_eq!(b_json["short_id"], crate::crypto_utils::short_id(&b.pubkey()));
+  assert_eq!(b_json["address"], "10.0.0.2:2240");
+  assert_eq!(b_json["depth"], 2);
+  assert_eq!(b_json["parent_pubkey"], crate::crypto_utils::to_hex(&a.pubkey()));
+  assert_eq!(b_json["attestation"]["signature_valid"], true);
+  let old = nodes.iter().find(|n| n["hostname"] == "old").unwrap();
+  assert_eq!(old["attestation"]["timestamp_valid"], false);
+  assert_eq!(doc["edges"].as_array().unwrap().len(), 3);
+  assert_eq!(doc["warnings"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn dot_and_mermaid_escape_hostnames_and_mark_untrusted_links() {
+  let (map, _, a, b) = sample();
+  let (a_hex, b_hex) = (crate::crypto_utils::to_hex(&a.pubkey()), crate::crypto_utils::to_hex(&b.pubkey()));
+
+  let dot = map.render_dot();
+  assert!(dot.starts_with("digraph netmap {"));
+  assert!(dot.contains("guest \\\"pi\\\""), "quotes in a hostname stay inside the label:\n{dot}");
+  assert!(dot.contains(&format!("\"{a_hex}\" -> \"{b_hex}\" [label=\"untrusted\", style=dashed];")));
+  assert_eq!(dot.matches("// warning:").count(), 2);
+
+  let mermaid = map.render_mermaid();
+  assert!(mermaid.starts_with("flowchart LR\n"));
+  assert!(mermaid.contains("guest #quot;pi#quot;"));
+  assert!(mermaid.contains(&format!("n{a_hex} -.->|untrusted| n{b_hex}")));
+  assert!(mermaid.contains("class ") && mermaid.contains(" flagged"), "the stale node is flagged:\n{mermaid}");
+}
+
+#[test]
+fn watch_reports_joins_leaves_moves_trust_and_key_changes() {
+  let us = Node::new("laptop");
+  let (a, b, c) = (Node::new("fileserver"), Node::new("printer"), Node::new("nas"));
+  let round = |replies: Vec<(SocketAddr, Vec<u8>)>| NetworkMap::assemble("laptop", &us.pubkey(), &replies, NOW);
+  let mut watch = FabricWatch::default();
+
+  let first = watch.update(round(vec![
+    (from("10.0.0.1:2240"), a.record(&us.pubkey(), 1, true, "10.0.0.1:2240", NOW)),
+    (from("10.0.0.2:2240"), b.record(&us.pubkey(), 1, true, "10.0.0.2:2240", NOW)),
+  ]));
+  assert_eq!(first.len(), 2);
+  assert!(first.iter().all(|c| matches!(c, MapChange::Joined(_))));
+  assert!(watch.update(round(vec![
+    (from("10.0.0.1:2240"), a.record(&us.pubkey(), 1, true, "10.0.0.1:2240", NOW)),
+    (from("10.0.0.2:2240"), b.record(&us.pubkey(), 1, true, "10.0.0.2:2240", NOW)),
+  ])).is_empty(), "an unchanged fabric reports nothing");
+
+  // a moves and stops trusting us, b leaves, c joins.
+  let changes = watch.update(round(vec![
+    (from("10.0.0.9:2240"), a.record(&us.pubkey(), 1, false, "10.0.0.9:2240", NOW)),
+    (from("10.0.0.3:2240"), c.record(&us.pubkey(), 1, true, "10.0.0.3:2240", NOW)),
+  ]));
+  assert!(changes.contains(&MapChange::AddressChanged { node: watch.current.nodes[&a.pubkey()].clone(), from: "10.0.0.1:2240".into() }));
+  assert!(changes.iter().any(|ch| matches!(ch, MapChange::TrustChanged { node, from: true } if node.pubkey == a.pubkey())));
+  assert!(changes.iter().any(|ch| matches!(ch, MapChange::Left(n) if n.pubkey == b.pubkey())));
+  assert!(changes.iter().any(|ch| matches!(ch, MapChange::Joined(n) if n.pubkey == c.pubkey())));
+  assert!(!changes.iter().any(MapChange::is_alarming));
+
+  // Someone else answers as "printer" after the real one left: a key change, not a join.
+  let imposter = Node::new("printer");
+  let changes = watch.update(round(vec![
+    (from("10.0.0.9:2240"), a.record(&us.pubkey(), 1, false, "10.0.0.9:2240", NOW)),
+    (from("10.0.0.3:2240"), c.record(&us.pubkey(), 1, true, "10.0.0.3:2240", NOW)),
+    (from("10.0.0.2:2240"), imposter.record(&us.pubkey(), 1, true, "10.0.0.2:2240", NOW)),
+  ]));
+  assert_eq!(changes.len(), 1, "{changes:?}");
+  assert_eq!(changes[0], MapChange::KeyChanged { node: watch.current.nodes[&imposter.pubkey()].clone(), previous: b.pubkey() });
+  assert!(changes[0].is_alarming());
+  assert!(changes[0].describe().contains("possible impersonation"));
+  assert_eq!(changes[0].to_json()["event"], "key_changed");
+  assert_eq!(watch.rounds, 4);
+}
+
+// Two machines left with the same stock hostname are both legitimate: answering in turns is not a
+// key change. Only a key never seen under the name is.
+#[test]
+fn watch_only_alarms_on_a_key_never_seen_under_a_hostname() {
+  let us = Node::new("laptop");
+  let (pi1, pi2, stranger) = (Node::new("raspberrypi"), Node::new("raspberrypi"), Node::new("raspberrypi"));
+  let round = |replies: Vec<(SocketAddr, Vec<u8>)>| NetworkMap::assemble("laptop", &us.pubkey(), &replies, NOW);
+  let one = |node: &Node, addr: &str| round(vec![(from(addr), node.record(&us.pubkey(), 1, true, addr, NOW))]);
+  let mut watch = FabricWatch::default();
+
+  watch.update(round(vec![
+    (from("10.0.0.1:2240"), pi1.record(&us.pubkey(), 1, true, "10.0.0.1:2240", NOW)),
+    (from("10.0.0.2:2240"), pi2.record(&us.pubkey(), 1, true, "10.0.0.2:2240", NOW)),
+  ]));
+  for _ in 0..2 {
+    for (node, addr) in [(&pi1, "10.0.0.1:2240"), (&pi2, "10.0.0.2:2240")] {
+      let changes = watch.update(one(node, addr));
+      assert!(!changes.iter().any(MapChange::is_alarming), "{changes:?}");
+    }
+  }
+  let changes = watch.update(one(&stranger, "10.0.0.3:2240"));
+  assert_eq!(changes, [MapChange::KeyChanged { node: watch.current.nodes[&stranger.pubkey()].clone(), previous: pi2.pubkey() }]);
+}
@@ -16,3 +16,319 @@ fn encodes_named_and_resize_events() {
   assert_eq!(encode_event(&TtyEvent::Resize(80, 24)), vec![event_kind::RESIZE, 80, 0, 24, 0]);
   assert_eq!(encode_event(&TtyEvent::Resize(258, 1)), vec![event_kind::RESIZE, 2, 1, 1, 0]);
 }
+
+// Protocol 1 guests must see exactly what they always did: modified keys as the bare key, and events
+// they have no tag for as nothing (the host then reports no input) rather than bytes they'd misread.
+#[test]
+fn protocol_1_drops_what_it_cannot_express() {
+  use crate::tty::{encode_event_for, PROTOCOL_V1};
+  let alt_x = TtyEvent::Modified(crate::tty::modifiers::ALT, Box::new(TtyEvent::Char('x')));
+  assert_eq!(encode_event(&alt_x), vec![event_kind::CHAR, b'x']);
+  assert_eq!(encode_event(&TtyEvent::Tab), Vec::<u8>::new());
+  assert_eq!(encode_event(&TtyEvent::Paste("hi".into())), Vec::<u8>::new());
+  assert_eq!(encode_event_for(&TtyEvent::Enter, PROTOCOL_V1, 64), vec![event_kind::ENTER]);
+}
+
+#[test]
+fn protocol_2_carries_version_modifiers_and_every_event() {
+  use crate::tty::{encode_event_v2, modifiers, mouse_action, mouse_button, MouseEvent, PROTOCOL_V2};
+  assert_eq!(encode_event_v2(&TtyEvent::Char('a')), vec![PROTOCOL_V2, event_kind::CHAR, 0, b'a']);
+  assert_eq!(encode_event_v2(&TtyEvent::Resize(80, 24)), vec![PROTOCOL_V2, event_kind::RESIZE, 0, 80, 0, 24, 0]);
+  assert_eq!(encode_event_v2(&TtyEvent::CtrlC), vec![PROTOCOL_V2, event_kind::CTRL_C, modifiers::CTRL]);
+  let ctrl_alt_left = TtyEvent::Modified(modifiers::CTRL | modifiers::ALT, Box::new(TtyEvent::Left));
+  assert_eq!(encode_event_v2(&ctrl_alt_left), vec![PROTOCOL_V2, event_kind::LEFT, modifiers::CTRL | modifiers::ALT]);
+  assert_eq!(encode_event_v2(&TtyEvent::F(12)), vec![PROTOCOL_V2, event_kind::FUNCTION, 0, 12]);
+  assert_eq!(encode_event_v2(&TtyEvent::BackTab), vec![PROTOCOL_V2, event_kind::BACK_TAB, 0]);
+  assert_eq!(encode_event_v2(&TtyEvent::Paste("hé".into())), vec![PROTOCOL_V2, event_kind::PASTE, 0, b'h', 0xC3, 0xA9]);
+  let click = MouseEvent { action: mouse_action::DOWN, button: mouse_button::LEFT, col: 300, row: 2, modifiers: modifiers::SHIFT };
+  assert_eq!(encode_event_v2(&TtyEvent::Mouse(click)),
+    vec![PROTOCOL_V2, event_kind::MOUSE, modifiers::SHIFT, mouse_action::DOWN, mouse_button::LEFT, 44, 1, 2, 0]);
+}
+
+// A paste longer than the guest's buffer is cut on a character boundary, never mid-character.
+#[test]
+fn long_pastes_are_cut_on_a_character_boundary() {
+  use crate::tty::{encode_event_for, PROTOCOL_V2};
+  let bytes = encode_event_for(&TtyEvent::Paste("aé".into()), PROTOCOL_V2, 5);
+  assert_eq!(bytes, vec![PROTOCOL_V2, event_kind::PASTE, 0, b'a']);
+  assert_eq!(encode_event_for(&TtyEvent::Paste("aé".into()), PROTOCOL_V2, 64).len(), 6);
+}
+
+// Crossterm events become ours: Shift folds into characters and Shift+Tab, Ctrl+C stays its own
+// event, and any other modifier wraps the key.
+#[test]
+fn maps_modifiers_function_keys_and_mouse() {
+  use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEventKind};
+  use crate::tty::{map_event, modifiers, mouse_action, mouse_button, MouseEvent};
+  let key = |code, mods| map_event(Event::Key(KeyEvent::new(code, mods)));
+  assert_eq!(key(KeyCode::Char('A'), KeyModifiers::SHIFT), Some(TtyEvent::Char('A')));
+  assert_eq!(key(KeyCode::Char('c'), KeyModifiers::CONTROL), Some(TtyEvent::CtrlC));
+  assert_eq!(key(KeyCode::Char('a'), KeyModifiers::CONTROL), Some(TtyEvent::Modified(modifiers::CTRL, Box::new(TtyEvent::Char('a')))));
+  assert_eq!(key(KeyCode::BackTab, KeyModifiers::SHIFT), Some(TtyEvent::BackTab));
+  assert_eq!(key(KeyCode::F(5), KeyModifiers::ALT), Some(TtyEvent::Modified(modifiers::ALT, Box::new(TtyEvent::F(5)))));
+  assert_eq!(key(KeyCode::F(13), KeyModifiers::NONE), None);
+  let scroll = crossterm::event::MouseEvent { kind: MouseEventKind::ScrollDown, column: 3, row: 4, modifiers: KeyModifiers::NONE };
+  assert_eq!(map_event(Event::Mouse(scroll)), Some(TtyEvent::Mouse(MouseEvent { action: mouse_action::SCROLL_DOWN, button: mouse_button::NONE, col: 3, row: 4, modifiers: 0 })));
+  let drag = crossterm::event::MouseEvent { kind: MouseEventKind::Drag(MouseButton::Right), column: 0, row: 0, modifiers: KeyModifiers::CONTROL };
+  assert_eq!(map_event(Event::Mouse(drag)).map(|e| crate::tty::encode_event_v2(&e)[2]), Some(modifiers::CTRL));
+}
+
+#[test]
+fn colours_cover_ansi_palette_and_rgb() {
+  use crate::tty::{color, RGB_FLAG};
+  use crossterm::style::Color;
+  assert_eq!(color(9), Color::Red);
+  assert_eq!(color(208), Color::AnsiValue(208));
+  assert_eq!(color(RGB_FLAG | 0x12_34_56), Color::Rgb { r: 0x12, g: 0x34, b: 0x56 });
+  assert_eq!(color(-1), Color::Reset);
+  assert_eq!(color(0x0200_0000), Color::Reset);
+}
+
+/// A [`crate::tty::Terminal`] that writes down what it is asked to do.
+#[derive(Default)]
+pub(crate) struct Recorder {
+  pub calls: std::sync::Mutex<Vec<String>>,
+}
+
+impl crate::tty::Terminal for Recorder {
+  fn print(&self, s: &str) {
+    self.calls.lock().unwrap().push(format!("print {s}"));
+  }
+  fn move_to(&self, col: u16, row: u16) {
+    self.calls.lock().unwrap().push(format!("move {col},{row}"));
+  }
+  fn clear(&self) {
+    self.calls.lock().unwrap().push("clear".into());
+  }
+  fn style(&self, fg: i32, bg: i32, attrs: i32) {
+    self.calls.lock().unwrap().push(format!("style {fg},{bg},{attrs}"));
+  }
+  fn set_modes(&self, wanted: u32) {
+    self.calls.lock().unwrap().push(format!("modes {wanted}"));
+  }
+  fn flush(&self) {
+    self.calls.lock().unwrap().push("flush".into());
+  }
+  fn size(&self) -> (u16, u16) {
+    (80, 24)
+  }
+}
+
+// Forwarded input is read back exactly as it was sent, modifiers and all.
+#[test]
+fn protocol_2_events_decode_back() {
+  use crate::tty::{decode_event_v2, encode_event_v2, modifiers, mouse_action, mouse_button, MouseEvent};
+  let events = [
+    TtyEvent::Char('é'),
+    TtyEvent::CtrlC,
+    TtyEvent::CtrlD,
+    TtyEvent::Resize(300, 2),
+    TtyEvent::F(7),
+    TtyEvent::Paste("two\nlines".into()),
+    TtyEvent::Modified(modifiers::CTRL | modifiers::ALT, Box::new(TtyEvent::Left)),
+    TtyEvent::Mouse(MouseEvent { action: mouse_action::DRAG, button: mouse_button::RIGHT, col: 7, row: 513, modifiers: modifiers::SHIFT }),
+  ];
+  for ev in events {
+    assert_eq!(decode_event_v2(&encode_event_v2(&ev)), Some(ev));
+  }
+  assert_eq!(decode_event_v2(&encode_event(&TtyEvent::Enter)), None, "protocol 1 bytes aren't protocol 2");
+  assert_eq!(decode_event_v2(&[crate::tty::PROTOCOL_V2, event_kind::RESIZE, 0, 80]), None);
+}
+
+// A remote terminal's drawing replays in order on the user's terminal, split into batches that each
+// fit a datagram, and a forwarded resize changes the size the program sees.
+#[tokio::test]
+async fn remote_terminals_stream_draw_ops_and_take_forwarded_input() {
+  use crate::tty::{apply_draw_ops, remote, DRAW_BATCH_BYTES};
+  let (handle, input, mut batches) = remote((100, 30));
+  handle.clear();
+  handle.move_to(3, 4);
+  handle.style(1, crate::tty::RGB_FLAG | 0x102030, crate::tty::attrs::BOLD);
+  handle.print("hé");
+  handle.flush();
+  let shown = Recorder::default();
+  assert!(apply_draw_ops(&batches.recv().await.unwrap(), &shown));
+  assert_eq!(*shown.calls.lock().unwrap(), ["clear", "move 3,4", &format!("style 1,{},1", crate::tty::RGB_FLAG | 0x102030), "print hé", "flush"]);
+
+  let long = "x".repeat(DRAW_BATCH_BYTES * 2);
+  handle.print(&long);
+  handle.move_to(0, 0);
+  handle.flush();
+  let shown = Recorder::default();
+  let mut count = 0;
+  while let Ok(batch) = batches.try_recv() {
+    assert!(batch.len() <= DRAW_BATCH_BYTES);
+    assert!(apply_draw_ops(&batch, &shown));
+    count += 1;
+  }
+  assert_eq!(count, 3);
+  let calls = shown.calls.lock().unwrap().clone();
+  let printed: String = calls.iter().filter_map(|c| c.strip_prefix("print ")).collect();
+  assert_eq!(printed, long);
+  assert!(calls.iter().any(|c| c == "move 0,0"));
+  assert!(!apply_draw_ops(&[crate::tty::draw_op::PRINT, 9, 0, 0, 0, b'x'], &Recorder::default()), "a cut-short op is refused");
+
+  assert_eq!(handle.size(), (100, 30));
+  input.feed(TtyEvent::Resize(120, 40));
+  input.feed(TtyEvent::Char('q'));
+  assert_eq!(handle.size(), (120, 40));
+  let tick = std::time::Duration::from_millis(100);
+  assert_eq!(handle.next_event(tick).await, Some(TtyEvent::Resize(120, 40)));
+  assert_eq!(handle.next_event(tick).await, Some(TtyEvent::Char('q')));
+}
+
+// A client that misses a draw batch draws nothing more until a redraw, and the frame it gets leaves
+// its screen just as one that saw every batch, so drawing carries on from there.
+#[tokio::test]
+async fn a_missed_draw_batch_is_repaired_by_a_full_frame() {
+  use crate::tty::headless::headless;
+  use crate::tty::{apply_draw_ops, remote, DrawSequence};
+  let (handle, input, mut batches) = remote((20, 5));
+  let (everything, _, everything_view) = headless((20, 5), None).unwrap();
+  let (client, _, client_view) = headless((20, 5), None).unwrap();
+  let mut draws = DrawSequence::default();
+  let mut seq = 0;
+  // Hands this flush's batches to both screens, the client losing them if `lose`.
+  let mut deliver = |lose: bool| {
+    while let Ok(ops) = batches.try_recv() {
+      assert!(apply_draw_ops(&ops, &**everything));
+      if !lose && draws.admit(seq, &ops) {
+        assert!(apply_draw_ops(&ops, &**client));
+      }
+      seq += 1;
+    }
+    draws.awaiting_frame()
+  };
+
+  handle.style(2, -1, crate::tty::attrs::BOLD);
+  handle.move_to(1, 1);
+  handle.print("first");
+  handle.flush();
+  assert!(!deliver(false));
+  handle.move_to(1, 2);
+  handle.print("lost");
+  handle.flush();
+  assert!(!deliver(true), "a gap shows only once a later batch arrives");
+  handle.style(-1, 4, 0);
+  handle.move_to(1, 3);
+  handle.print("after");
+  handle.flush();
+  assert!(deliver(false));
+  assert_eq!(client_view.screen().text(), "\n first\n\n\n\n", "nothing is drawn past the gap");
+
+  input.redraw();
+  assert!(!deliver(false));
+  assert_eq!(client_view.screen(), everything_view.screen());
+
+  handle.print("!");
+  handle.flush();
+  assert!(!deliver(false));
+  let screen = client_view.screen();
+  assert_eq!(screen, everything_view.screen());
+  assert_eq!(screen.row_text(3), " after!");
+  assert_eq!(screen.cell(6, 3).unwrap().bg, 4, "the pen survives the frame");
+  assert!(!draws.admit(0, &[]), "an old batch is never drawn");
+}
+
+// What a headless terminal shows only changes at a flush, and draws the way a raw-mode terminal
+// would: `\r\n` for a new line, wrapping at the right edge and scrolling at the bottom.
+#[test]
+fn headless_screens_draw_wrap_and_scroll() {
+  use crate::tty::headless::headless;
+  let (handle, _input, view) = headless((5, 3), None).unwrap();
+  handle.print("hello");
+  assert_eq!(view.screen().text(), "\n\n\n", "nothing shows before a flush");
+  handle.flush();
+  assert_eq!(view.screen().row_text(0), "hello");
+
+  handle.print("!\r\nab");
+  handle.style(2, -1, crate::tty::attrs::BOLD);
+  handle.print("c");
+  handle.flush();
+  let screen = view.screen();
+  assert_eq!(screen.text(), "hello\n!\nabc\n");
+  assert_eq!(screen.cursor(), (3, 2));
+  assert_eq!(screen.cell(2, 2).unwrap().fg, 2);
+  assert_eq!(screen.cell(2, 2).unwrap().attrs, crate::tty::attrs::BOLD);
+  assert_eq!(screen.cell(1, 2).unwrap().fg, -1);
+
+  handle.print("\r\nend");
+  handle.flush();
+  assert_eq!(view.screen().text(), "!\nabc\nend\n", "the top row scrolled off");
+
+  handle.clear();
+  handle.move_to(9, 9);
+  handle.print("z");
+  handle.flush();
+  assert_eq!(view.screen().text(), "\n\n    z\n", "moves are clamped to the screen");
+}
+
+#[tokio::test]
+async fn headless_resizes_follow_input() {
+  use crate::tty::headless::headless;
+  let (handle, input, view) = headless((4, 2), None).unwrap();
+  handle.print("abcd");
+  handle.flush();
+  input.send(TtyEvent::Resize(2, 3)).unwrap();
+  let tick = std::time::Duration::from_millis(100);
+  assert_eq!(handle.next_event(tick).await, Some(TtyEvent::Resize(2, 3)));
+  assert_eq!(handle.size(), (2, 3));
+  assert_eq!(view.screen().text(), "ab\n\n\n");
+}
+
+#[test]
+fn scripts_parse_into_events_and_waits() {
+  use crate::tty::headless::{parse_script, Step};
+  use crate::tty::{modifiers, mouse_action, mouse_button, MouseEvent};
+  let script = "# a comment\n\ntype a b\nkey enter\nkey ctrl+c\nkey alt+shift+left\nkey f5\nkey ctrl++\n\
+                paste two words\nresize 100x30\nmouse down left 10 5\nwait 250\n";
+  let steps = parse_script(script).unwrap();
+  let event = |ev| Step::Event(ev);
+  assert_eq!(
+    steps,
+    [
+      event(TtyEvent::Char('a')),
+      event(TtyEvent::Char(' ')),
+      event(TtyEvent::Char('b')),
+      event(TtyEvent::Enter),
+      event(TtyEvent::CtrlC),
+      event(TtyEvent::Modified(modifiers::ALT | modifiers::SHIFT, Box::new(TtyEvent::Left))),
+      event(TtyEvent::F(5)),
+      event(TtyEvent::Modified(modifiers::CTRL, Box::new(TtyEvent::Char('+')))),
+      event(TtyEvent::Paste("two words".into())),
+      event(TtyEvent::Resize(100, 30)),
+      event(TtyEvent::Mouse(MouseEvent { action: mouse_action::DOWN, button: mouse_button::LEFT, col: 10, row: 5, modifiers: 0 })),
+      Step::Wait(std::time::Duration::from_millis(250)),
+    ]
+  );
+
+  for (script, line) in [("key f13", "line 1"), ("type ok\nresize 80", "line 2"), ("\n\nwait soon", "line 3"), ("jump", "line 1"), ("mouse down left 1", "line 1")] {
+    let err = parse_script(script).unwrap_err();
+    assert!(err.starts_with(line), "{script:?}: {err}");
+  }
+}
+
+// A recorded session is a valid asciicast: a header, then output at each flush and what was typed.
+#[tokio::test]
+async fn headless_sessions_record_asciicasts() {
+  use crate::tty::headless::headless;
+  let path = std::env::temp_dir().join(format!("weverywhere-tty-cast-{}.cast", std::process::id()));
+  let (handle, input, _view) = headless((20, 5), Some(&path)).unwrap();
+  handle.move_to(1, 0);
+  handle.print("hi");
+  handle.flush();
+  input.send(TtyEvent::Char('x')).unwrap();
+  input.send(TtyEvent::Resize(30, 6)).unwrap();
+  let tick = std::time::Duration::from_millis(100);
+  while handle.next_event(tick).await.is_some() {}
+  drop(handle);
+
+  let text = std::fs::read_to_string(&path).unwrap();
+  let _ = std::fs::remove_file(&path);
+  let lines: Vec<serde_json::Value> = text.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+  assert_eq!(lines[0]["version"], 2);
+  assert_eq!(lines[0]["width"], 20);
+  assert_eq!(lines[0]["height"], 5);
+  let events: Vec<(&str, &str)> = lines[1..].iter().map(|e| (e[1].as_str().unwrap(), e[2].as_str().unwrap())).collect();
+  assert_eq!(events, [("o", "\x1b[1;2Hhi"), ("i", "x"), ("r", "30x6")]);
+}
@@ -0,0 +1,540 @@
+//! The fabric map `netmap` assembles from discovery records, and its renderings.
+//!
+//! Every node that answers the discovery program returns one record (see
+//! [`discovery::record_keys`]): a signed attestation of its hostname and key, its caller's key, and
+//! whether it trusts that caller. A relaying daemon signs the onward request with its own identity,
+//! so "trusts its caller" is really "trusts its parent in the tree", i.e. the trust flag of the edge
+//! parent -> node. [`NetworkMap::assemble`] verifies and deduplicates the records; the `render_*`
+//! methods turn the result into the ASCII tree or a JSON / Graphviz DOT / Mermaid document.
+//! [`FabricWatch`] diffs consecutive maps for `netmap --watch`.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::net::SocketAddr;
+
+use crate::*;
+
+/// One discovery record, decoded but not yet verified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+  pub attestation: Vec<u8>,
+  /// Key of the caller that sent the node the program (empty if not reported).
+  pub parent: Vec<u8>,
+  pub trusts_caller: bool,
+  /// Hops from the origin, if reported.
+  pub depth: Option<u64>,
+  /// The node's own address as it reported it.
+  pub node_addr: Option<String>,
+}
+
+/// Decode a per-node record CBOR map; `None` if it has no attestation.
+pub fn parse_record(bytes: &[u8]) -> Option<Record> {
+  use serde_cbor::Value;
+  let map = match serde_cbor::from_slice::<Value>(bytes).ok()? {
+    Value::Map(m) => m,
+    _ => return None,
+  };
+  let attestation = match map.get(&Value::Integer(discovery::record_keys::ATTESTATION)) {
+    Some(Value::Bytes(b)) => b.clone(),
+    _ => return None,
+  };
+  let parent = match map.get(&Value::Integer(discovery::record_keys::PARENT_PUBKEY)) {
+    Some(Value::Bytes(b)) => b.clone(),
+    _ => Vec::new(),
+  };
+  let trusts_caller = matches!(
+    map.get(&Value::Integer(discovery::record_keys::TRUSTS_CALLER)),
+    Some(Value::Integer(1))
+  );
+  let depth = match map.get(&Value::Integer(discovery::record_keys::DEPTH)) {
+    Some(Value::Integer(d)) => u64::try_from(*d).ok(),
+    _ => None,
+  };
+  // Accept the address as either a text or byte string; an empty value means "unknown".
+  let node_addr = match map.get(&Value::Integer(discovery::record_keys::NODE_ADDR)) {
+    Some(Value::Text(s)) if !s.is_empty() => Some(s.clone()),
+    Some(Value::Bytes(b)) if !b.is_empty() => Some(String::from_utf8_lossy(b).into_owned()),
+    _ => None,
+  };
+  Some(Record { attestation, parent, trusts_caller, depth, node_addr })
+}
+
+/// One verified node in the map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapNode {
+  pub pubkey: Vec<u8>,
+  pub hostname: String,
+  pub parent: Vec<u8>,
+  pub trusts_caller: bool,
+  pub depth: Option<u64>,
+  /// The node's OWN address as it reported it (record key 5). Preferred for display over
+  /// `responder`, which for a relayed record is the intermediate daemon that forwarded it up.
+  pub node_addr: Option<String>,
+  pub responder: SocketAddr,
+  pub sig_valid: bool,
+  pub time_ok: bool,
+}
+
+impl MapNode {
+  /// The address to show: self-reported if known, else where the record came from.
+  pub fn addr(&self) -> String {
+    self.node_addr.clone().unwrap_or_else(|| self.responder.to_string())
+  }
+}
+
+/// A parent -> child link; `trusted` is whether the child trusts the parent that reached it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edge {
+  pub from: Vec<u8>,
+  pub to: Vec<u8>,
+  pub trusted: bool,
+}
+
+/// The verified, deduplicated fabric as seen from one discovery round.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkMap {
+  /// Our own name and key (the root).
+  pub you: String,
+  pub our_pubkey: Vec<u8>,
+  /// The fabric the map was taken on (`[network].fabric`; empty = the default fabric).
+  pub fabric: String,
+  pub nodes: HashMap<Vec<u8>, MapNode>,
+  /// Records dropped or flagged while assembling (bad signatures, stale timestamps).
+  pub warnings: Vec<String>,
+}
+
+impl NetworkMap {
+  /// Verify each collected `(responder, record)` and build the map. A record whose attestation
+  /// doesn't verify is left out with a warning; one whose timestamp is outside the request window
+  /// is kept but flagged. A node is only taken once (its first record), keyed by its pubkey.
+  pub fn assemble(you: &str, our_pubkey: &[u8], replies: &[(SocketAddr, Vec<u8>)], now_epoch_s: u64) -> NetworkMap {
+    let mut map = NetworkMap { you: you.to_string(), our_pubkey: our_pubkey.to_vec(), ..Default::default() };
+    for (responder, cbor) in replies {
+      let Some(record) = parse_record(cbor) else { continue };
+      match discovery::verify_attestation_cbor(&record.attestation) {
+        Ok(vn) => {
+          let time_ok = discovery::attestation_time_ok(vn.epoch_s, now_epoch_s);
+          if !time_ok {
+            map.warnings.push(format!("{} ({}) attestation timestamp is outside the +/-30s window", vn.hostname, responder));
+          }
+          map.nodes.entry(vn.pubkey.clone()).or_insert(MapNode {
+            pubkey: vn.pubkey,
+            hostname: vn.hostname,
+            parent: record.parent,
+            trusts_caller: record.trusts_caller,
+            depth: record.depth,
+            node_addr: record.node_addr,
+            responder: *responder,
+            sig_valid: true,
+            time_ok,
+          });
+        }
+        Err(e) => {
+          map.warnings.push(format!("invalid signature from {} - {} (node not trusted for identity)", responder, e));
+        }
+      }
+    }
+    map
+  }
+
+  /// Nodes sorted by hostname, then key, for stable output.
+  pub fn sorted_nodes(&self) -> Vec<&MapNode> {
+    let mut nodes: Vec<&MapNode> = self.nodes.values().collect();
+    nodes.sort_by(|a, b| a.hostname.cmp(&b.hostname).then_with(|| a.pubkey.cmp(&b.pubkey)));
+    nodes
+  }
+
+  /// True if `pubkey` is us or a node in the map.
+  fn knows(&self, pubkey: &[u8]) -> bool {
+    (!self.our_pubkey.is_empty() && pubkey == self.our_pubkey.as_slice()) || self.nodes.contains_key(pubkey)
+  }
+
+  /// Whether a node hangs directly off the root: its parent is us, or someone we never heard from.
+  fn is_root(&self, node: &MapNode) -> bool {
+    node.parent == self.our_pubkey || !self.nodes.contains_key(&node.parent)
+  }
+
+  /// Every link whose parent is us or a node in the map, in [`Self::sorted_nodes`] order.
+  pub fn edges(&self) -> Vec<Edge> {
+    self.sorted_nodes().into_iter()
+      .filter(|n| self.knows(&n.parent))
+      .map(|n| Edge { from: n.parent.clone(), to: n.pubkey.clone(), trusted: n.trusts_caller })
+      .collect()
+  }
+
+  /// The human-readable tree `netmap` prints by default.
+  pub fn render_tree(&self) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out);
+    let _ = writeln!(out, "weverywhere network map");
+    let _ = writeln!(out, "  you = {}", self.you);
+    let _ = writeln!(out, "  fabric = {}", if self.fabric.is_empty() { "(default)" } else { &self.fabric });
+    let _ = writeln!(out, "  legend:  <3 = node trusts you    x = node does NOT trust you    (!) = unverified/expired");
+    let _ = writeln!(out, "  nodes show as  hostname (short-id) @ addr;  short-id matches the chat name and its full");
+    let _ = writeln!(out, "  public key is listed below the tree (a short-id can collide - always verify the full key)");
+    let _ = writeln!(out);
+
+    if self.nodes.is_empty() {
+      let _ = writeln!(out, "(no servers responded)");
+      let _ = writeln!(out, "  - is a daemon running and reachable? try:  weverywhere netmap --local");
+      let _ = writeln!(out, "  - are peers configured, and did the discovery program get built?");
+      let _ = writeln!(out);
+      return out;
+    }
+
+    // children[parent_pubkey] = [child pubkeys], sorted for stable output.
+    let mut children: HashMap<Vec<u8>, Vec<Vec<u8>>> = HashMap::new();
+    for node in self.sorted_nodes() {
+      children.entry(node.parent.clone()).or_default().push(node.pubkey.clone());
+    }
+
+    if self.our_pubkey.is_empty() {
+      let _ = writeln!(out, "(you) {}", self.you);
+    } else {
+      let _ = writeln!(out, "(you) {} ({})", self.you, crypto_utils::short_id(&self.our_pubkey));
+    }
+    let roots: Vec<Vec<u8>> = self.sorted_nodes().into_iter()
+      .filter(|n| self.is_root(n))
+      .map(|n| n.pubkey.clone())
+      .collect();
+
+    // First pass: build every row's tree portion so we can align the trust column into a single column
+    // regardless of how deep (and therefore how wide) each branch is.
+    let mut printed: HashSet<Vec<u8>> = HashSet::new();
+    let mut rows: Vec<Row> = Vec::new();
+    let root_count = roots.len();
+    for (i, pk) in roots.iter().enumerate() {
+      collect_rows(pk, &self.nodes, &children, "", i + 1 == root_count, &mut printed, &mut rows);
+    }
+
+    // Second pass: pad each tree portion to the widest one, then print the aligned trust column.
+    let tree_width = rows.iter().map(|r| r.tree.chars().count()).max().unwrap_or(0);
+    for row in &rows {
+      let pad = tree_width - row.tree.chars().count();
+      let _ = writeln!(out, "{}{:pad$}   [{}]{}", row.tree, "", row.trust_mark, row.warn, pad = pad);
+    }
+
+    // Full public keys, so a short-id (as shown in the tree and in chat) can be verified against the
+    // unforgeable full ed25519 key. A hostname or short-id can be claimed by anyone; only the full key
+    // is unique. Include ourselves so a user can confirm their own chat short-id too.
+    let mut ids: Vec<(Vec<u8>, String, bool)> = Vec::new();
+    if !self.our_pubkey.is_empty() {
+      ids.push((self.our_pubkey.clone(), self.you.clone(), true));
+    }
+    for (pk, node) in self.nodes.iter() {
+      ids.push((pk.clone(), node.hostname.clone(), false));
+    }
+    ids.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+
+    // Flag short-id collisions (distinct full keys sharing a short-id) - a hint of possible impersonation.
+    let mut short_counts: HashMap<String, usize> = HashMap::new();
+    for (pk, _, _) in &ids {
+      *short_counts.entry(crypto_utils::short_id(pk)).or_default() += 1;
+    }
+
+    let _ = writeln!(out);
+    let _ = writeln!(out, "public keys (verify a chat/netmap short-id against the full key here):");
+    for (pk, hostname, is_you) in &ids {
+      let short = crypto_utils::short_id(pk);
+      let you_mark = if *is_you { " (you)" } else { "" };
+      let collision = if short_counts[&short] > 1 { "   (!) SHARED SHORT-ID - verify full key" } else { "" };
+      let _ = writeln!(out, "  {}  {}  {}{}{}", short, crypto_utils::to_hex(pk), hostname, you_mark, collision);
+    }
+    let _ = writeln!(out);
+    out
+  }
+
+  /// The map as one JSON document: `you`, the `fabric` (null for the default one), every `nodes`
+  /// entry, every `edges` link and the `warnings`. Keys are lowercase hex.
+  pub fn render_json(&self) -> serde_json::Value {
+    use serde_json::json;
+    let hex = |pk: &[u8]| if pk.is_empty() { serde_json::Value::Null } else { json!(crypto_utils::to_hex(pk)) };
+    let nodes: Vec<serde_json::Value> = self.sorted_nodes().into_iter().map(|n| json!({
+      "hostname": n.hostname,
+      "pubkey": crypto_utils::to_hex(&n.pubkey),
+      "short_id": crypto_utils::short_id(&n.pubkey),
+      "address": n.addr(),
+      "responder": n.responder.to_string(),
+      "depth": n.depth,
+      "parent_pubkey": hex(&n.parent),
+      "trusts_parent": n.trusts_caller,
+      "attestation": { "signature_valid": n.sig_valid, "timestamp_valid": n.time_ok },
+    })).collect();
+    let edges: Vec<serde_json::Value> = self.edges().into_iter().map(|e| json!({
+      "from": crypto_utils::to_hex(&e.from),
+      "to": crypto_utils::to_hex(&e.to),
+      "trusted": e.trusted,
+    })).collect();
+    json!({
+      "you": {
+        "hostname": self.you,
+        "pubkey": hex(&self.our_pubkey),
+        "short_id": if self.our_pubkey.is_empty() { serde_json::Value::Null } else { json!(crypto_utils::short_id(&self.our_pubkey)) },
+      },
+      "fabric": if self.fabric.is_empty() { serde_json::Value::Null } else { json!(self.fabric) },
+      "nodes": nodes,
+      "edges": edges,
+      "warnings": self.warnings,
+    })
+  }
+
+  /// The map as a Graphviz digraph. Trusted links are solid, untrusted ones dashed; flagged nodes
+  /// are drawn red; warnings become comments.
+  pub fn render_dot(&self) -> String {
+    let mut out = String::from("digraph netmap {\n  rankdir=LR;\n  node [shape=box];\n");
+    for w in self.warnings.iter() {
+      let _ = writeln!(out, "  // warning: {}", w.replace('\n', " "));
+    }
+    let _ = writeln!(out, "  \"{}\" [label=\"{}\", shape=doubleoctagon];", self.root_id(), dot_escape(&format!("(you) {}", self.you)));
+    for n in self.sorted_nodes() {
+      let flagged = if n.sig_valid && n.time_ok { "" } else { ", color=red" };
+      let label = format!("{} ({})\n{}", n.hostname, crypto_utils::short_id(&n.pubkey), n.addr());
+      let _ = writeln!(out, "  \"{}\" [label=\"{}\"{}];", crypto_utils::to_hex(&n.pubkey), dot_escape(&label), flagged);
+    }
+    for e in self.edges() {
+      let style = if e.trusted { "label=\"trusted\"" } else { "label=\"untrusted\", style=dashed" };
+      let _ = writeln!(out, "  \"{}\" -> \"{}\" [{}];", self.node_id(&e.from), crypto_utils::to_hex(&e.to), style);
+    }
+    out.push_str("}\n");
+    out
+  }
+
+  /// The map as a Mermaid flowchart. Trusted links are solid, untrusted ones dotted; flagged nodes
+  /// get the `flagged` class; warnings become `%%` comments.
+  pub fn render_mermaid(&self) -> String {
+    let mut out = String::from("flowchart LR\n");
+    for w in self.warnings.iter() {
+      let _ = writeln!(out, "  %% warning: {}", w.replace('\n', " "));
+    }
+    let _ = writeln!(out, "  n{}[\"{}\"]", self.root_id(), mermaid_escape(&format!("(you) {}", self.you)));
+    let mut flagged = Vec::new();
+    for n in self.sorted_nodes() {
+      let id = crypto_utils::to_hex(&n.pubkey);
+      let label = format!("{} ({})<br/>{}", mermaid_escape(&n.hostname), crypto_utils::short_id(&n.pubkey), mermaid_escape(&n.addr()));
+      let _ = writeln!(out, "  n{}[\"{}\"]", id, label);
+      if !(n.sig_valid && n.time_ok) {
+        flagged.push(format!("n{id}"));
+      }
+    }
+    for e in self.edges() {
+      let arrow = if e.trusted { "-->|trusted|" } else { "-.->|untrusted|" };
+      let _ = writeln!(out, "  n{} {} n{}", self.node_id(&e.from), arrow, crypto_utils::to_hex(&e.to));
+    }
+    if !flagged.is_empty() {
+      let _ = writeln!(out, "  classDef flagged stroke:#c00,stroke-width:2px");
+      let _ = writeln!(out, "  class {} flagged", flagged.join(","));
+    }
+    out
+  }
+
+  /// Graph id of the root. Not our key: a local daemon sharing our identity is a node of its own.
+  fn root_id(&self) -> String {
+    "you".to_string()
+  }
+
+  /// Graph id of an edge's parent end: a link from our key always starts at the root.
+  fn node_id(&self, pubkey: &[u8]) -> String {
+    if pubkey == self.our_pubkey.as_slice() { self.root_id() } else { crypto_utils::to_hex(pubkey) }
+  }
+}
+
+/// One difference between two consecutive discovery rounds, as reported by `netmap --watch`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MapChange {
+  Joined(MapNode),
+  Left(MapNode),
+  AddressChanged { node: MapNode, from: String },
+  /// The node now trusts (or stopped trusting) the parent that reached it.
+  TrustChanged { node: MapNode, from: bool },
+  /// A hostname we already knew answered with a different key - possibly an impersonation, since
+  /// anyone can claim a hostname. `previous` is the key it had; `node` carries the new one.
+  KeyChanged { node: MapNode, previous: Vec<u8> },
+}
+
+impl MapChange {
+  pub fn node(&self) -> &MapNode {
+    match self {
+      MapChange::Joined(node) | MapChange::Left(node) => node,
+      MapChange::AddressChanged { node, .. } | MapChange::TrustChanged { node, .. } | MapChange::KeyChanged { node, .. } => node,
+    }
+  }
+
+  /// Whether this change should stand out: only a key change does.
+  pub fn is_alarming(&self) -> bool {
+    matches!(self, MapChange::KeyChanged { .. })
+  }
+
+  /// One human-readable event line (no timestamp).
+  pub fn describe(&self) -> String {
+    let n = self.node();
+    let who = format!("{} ({})", n.hostname, crypto_utils::short_id(&n.pubkey));
+    let trust = |t: bool| if t { "trusts its parent" } else { "does NOT trust its parent" };
+    match self {
+      MapChange::Joined(_) => format!("+ joined   {} @ {} ({})", who, n.addr(), trust(n.trusts_caller)),
+      MapChange::Left(_) => format!("- left     {} @ {}", who, n.addr()),
+      MapChange::AddressChanged { from, .. } => format!("~ moved    {} {} -> {}", who, from, n.addr()),
+      MapChange::TrustChanged { .. } => format!("~ trust    {} now {}", who, trust(n.trusts_caller)),
+      MapChange::KeyChanged { previous, .. } => format!(
+        "! KEY CHANGED {} @ {}: was {} now {} - possible impersonation, verify the full key",
+        n.hostname, n.addr(), crypto_utils::to_hex(previous), crypto_utils::to_hex(&n.pubkey)
+      ),
+    }
+  }
+
+  /// The change as one JSON object, for `--watch --format json`.
+  pub fn to_json(&self) -> serde_json::Value {
+    use serde_json::json;
+    let n = self.node();
+    let mut event = json!({
+      "hostname": n.hostname,
+      "pubkey": crypto_utils::to_hex(&n.pubkey),
+      "address": n.addr(),
+      "trusts_parent": n.trusts_caller,
+    });
+    let (kind, extra) = match self {
+      MapChange::Joined(_) => ("joined", json!({})),
+      MapChange::Left(_) => ("left", json!({})),
+      MapChange::AddressChanged { from, .. } => ("address_changed", json!({ "previous_address": from })),
+      MapChange::TrustChanged { from, .. } => ("trust_changed", json!({ "previous_trusts_parent": from })),
+      MapChange::KeyChanged { previous, .. } => ("key_changed", json!({ "previous_pubkey": crypto_utils::to_hex(previous), "possible_impersonation": true })),
+    };
+    event["event"] = json!(kind);
+    if let (Some(event), serde_json::Value::Object(extra)) = (event.as_object_mut(), extra) {
+      event.extend(extra);
+    }
+    event
+  }
+}
+
+/// The live model behind `netmap --watch`: the latest round's map, plus every key seen under each
+/// hostname during the session, so a key change is caught even if the old key left rounds ago.
+#[derive(Debug, Clone, Default)]
+pub struct FabricWatch {
+  pub current: NetworkMap,
+  pub rounds: u64,
+  /// Per hostname, the keys seen under it, most recently seen last. Several are normal: two stock
+  /// machines can share a hostname.
+  known_keys: HashMap<String, Vec<Vec<u8>>>,
+}
+
+impl FabricWatch {
+  /// Take one round's map and return what changed since the previous round, ordered by hostname
+  /// (on the first round, every node "joins"). Nodes are matched by key: a key never seen before
+  /// under a hostname we already knew is reported as [`MapChange::KeyChanged`] (against the key last
+  /// seen there) instead of a join, and that key's departure in the same round is folded into it. A
+  /// key already seen under the hostname simply rejoins.
+  pub fn update(&mut self, next: NetworkMap) -> Vec<MapChange> {
+    let mut changes = Vec::new();
+    let mut replaced: HashSet<Vec<u8>> = HashSet::new();
+    for node in next.sorted_nodes() {
+      match self.current.nodes.get(&node.pubkey) {
+        Some(old) => {
+          if old.addr() != node.addr() {
+            changes.push(MapChange::AddressChanged { node: node.clone(), from: old.addr() });
+          }
+          if old.trusts_caller != node.trusts_caller {
+            changes.push(MapChange::TrustChanged { node: node.clone(), from: old.trusts_caller });
+          }
+        }
+        None => match self.known_keys.get(&node.hostname) {
+          Some(seen) if !seen.contains(&node.pubkey) && let Some(previous) = seen.last() => {
+            replaced.insert(previous.clone());
+            changes.push(MapChange::KeyChanged { node: node.clone(), previous: previous.clone() });
+          }
+          _ => changes.push(MapChange::Joined(node.clone())),
+        },
+      }
+    }
+    for old in self.current.sorted_nodes() {
+      if !next.nodes.contains_key(&old.pubkey) && !replaced.contains(&old.pubkey) {
+        changes.push(MapChange::Left(old.clone()));
+      }
+    }
+    changes.sort_by(|a, b| a.node().hostname.cmp(&b.node().hostname));
+    for node in next.sorted_nodes() {
+      let seen = self.known_keys.entry(node.hostname.clone()).or_default();
+      seen.retain(|key| *key != node.pubkey);
+      seen.push(node.pubkey.clone());
+    }
+    self.current = next;
+    self.rounds += 1;
+    changes
+  }
+}
+
+/// A DOT double-quoted string body. Hostnames come from the network, so nothing may break out of
+/// the quotes.
+fn dot_escape(s: &str) -> String {
+  let mut out = String::with_capacity(s.len());
+  for c in s.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      c if c.is_control() => {}
+      c => out.push(c),
+    }
+  }
+  out
+}
+
+/// A Mermaid quoted-label body: quotes and markup characters as entity codes, controls dropped.
+fn mermaid_escape(s: &str) -> String {
+  let mut out = String::with_capacity(s.len());
+  for c in s.chars() {
+    match c {
+      '"' => out.push_str("#quot;"),
+      '<' => out.push_str("#lt;"),
+      '>' => out.push_str("#gt;"),
+      '#' => out.push_str("#35;"),
+      c if c.is_control() => {}
+      c => out.push(c),
+    }
+  }
+  out
+}
+
+/// One rendered tree line, split so the trust column can be aligned across the whole tree.
+struct Row {
+  /// The tree portion: prefix + branch + `hostname (short-id) @ addr`.
+  tree: String,
+  /// Trust indicator (`<3` / `x`) shown in the aligned column.
+  trust_mark: &'static str,
+  /// Trailing warning marker (` (!)`) for unverified/expired records, or empty.
+  warn: &'static str,
+}
+
+/// Recursively build one node's row and its children's rows with box-drawing indentation, guarding
+/// against cycles via `printed` (a node reached by two paths is only shown once). Rows are collected
+/// rather than printed so the caller can align the trust column across the whole tree.
+fn collect_rows(
+  pk: &[u8],
+  nodes: &HashMap<Vec<u8>, MapNode>,
+  children: &HashMap<Vec<u8>, Vec<Vec<u8>>>,
+  prefix: &str,
+  is_last: bool,
+  printed: &mut HashSet<Vec<u8>>,
+  rows: &mut Vec<Row>,
+) {
+  let node = match nodes.get(pk) { Some(n) => n, None => return };
+  if !printed.insert(pk.to_vec()) {
+    return; // already printed via another path
+  }
+  let branch = if is_last { "`--" } else { "|--" };
+  let trust_mark = if node.trusts_caller { "<3" } else { "x" };
+  let warn = if node.sig_valid && node.time_ok { "" } else { " (!)" };
+  rows.push(Row {
+    tree: format!("{}{} {} ({}) @ {}", prefix, branch, node.hostname, crypto_utils::short_id(pk), node.addr()),
+    trust_mark,
+    warn,
+  });
+
+  let child_prefix = format!("{}{}", prefix, if is_last { "    " } else { "|   " });
+  if let Some(kids) = children.get(pk) {
+    let kids: Vec<&Vec<u8>> = kids.iter().filter(|k| !printed.contains(*k)).collect();
+    let n = kids.len();
+    for (i, child) in kids.into_iter().enumerate() {
+      collect_rows(child, nodes, children, &child_prefix, i + 1 == n, printed, rows);
+    }
+  }
+}
@@ -0,0 +1,140 @@
+//! The datagram layer the daemon serves on and the clients talk to it through.
+//!
+//! The serve loop (`command::serve::serve_endpoint`) only ever sends and receives whole datagrams,
+//! asks which of our addresses faces a caller, and opens a fresh ephemeral socket for relaying. That
+//! is all [`Datagram`] asks of a transport, so the same loop runs on a real UDP socket in production
+//! and on an in-memory fabric in tests (see `src/tests/fabric_sim.rs`). The client side (`run`'s
+//! reply readers, netmap's record collector, chat's history backfill) reads replies through an
+//! [`Endpoint`] too, so the tests drive it against the same simulated fabric; only the multicast
+//! socket setup stays UDP-specific.
+
+use crate::*;
+
+/// One datagram socket as the serve loop sees it. Implemented for tokio's UDP socket; tests
+/// implement it over an in-memory fabric with simulated loss, latency and partitions.
+pub trait Datagram: Send + Sync {
+  fn poll_send_to(
+    &self,
+    cx: &mut core::task::Context<'_>,
+    buf: &[u8],
+    addr: std::net::SocketAddr,
+  ) -> core::task::Poll<std::io::Result<usize>>;
+
+  fn poll_recv_from(
+    &self,
+    cx: &mut core::task::Context<'_>,
+    buf: &mut tokio::io::ReadBuf<'_>,
+  ) -> core::task::Poll<std::io::Result<std::net::SocketAddr>>;
+
+  /// This host's own IP as `peer` would reach it (see [`net_utils::local_addr_facing`]).
+  fn local_ip_facing(&self, peer: std::net::IpAddr) -> Option<std::net::IpAddr>;
+
+  /// A new endpoint on an ephemeral port, of the same family as `peer`, for conversations this
+  /// host starts on its own behalf (the discovery relay).
+  fn open_ephemeral(&self, peer: std::net::SocketAddr) -> std::io::Result<Endpoint>;
+}
+
+impl Datagram for tokio::net::UdpSocket {
+  fn poll_send_to(
+    &self,
+    cx: &mut core::task::Context<'_>,
+    buf: &[u8],
+    addr: std::net::SocketAddr,
+  ) -> core::task::Poll<std::io::Result<usize>> {
+    tokio::net::UdpSocket::poll_send_to(self, cx, buf, addr)
+  }
+
+  fn poll_recv_from(
+    &self,
+    cx: &mut core::task::Context<'_>,
+    buf: &mut tokio::io::ReadBuf<'_>,
+  ) -> core::task::Poll<std::io::Result<std::net::SocketAddr>> {
+    tokio::net::UdpSocket::poll_recv_from(self, cx, buf)
+  }
+
+  fn local_ip_facing(&self, peer: std::net::IpAddr) -> Option<std::net::IpAddr> {
+    net_utils::local_addr_facing(peer)
+  }
+
+  fn open_ephemeral(&self, peer: std::net::SocketAddr) -> std::io::Result<Endpoint> {
+    Endpoint::udp_ephemeral(peer)
+  }
+}
+
+/// A shareable handle to a [`Datagram`] socket. Clones refer to the same socket.
+///
+/// An endpoint sends on one fabric (see [`Endpoint::scoped`]): whatever is sent through it is put
+/// on that fabric, so replies and relayed requests stay on the fabric the request arrived on.
+/// Receiving is unaffected; the serve loop decodes each datagram's fabric itself.
+#[derive(Clone)]
+pub struct Endpoint {
+  inner: std::sync::Arc<dyn Datagram>,
+  fabric: messages::FabricId,
+}
+
+impl Endpoint {
+  pub fn new(inner: std::sync::Arc<dyn Datagram>) -> Endpoint {
+    Endpoint { inner, fabric: messages::FabricId::default() }
+  }
+
+  pub fn udp(socket: tokio::net::UdpSocket) -> Endpoint {
+    Endpoint::new(std::sync::Arc::new(socket))
+  }
+
+  /// A UDP socket on an ephemeral port, of the same family as `peer`: what a client sends a request
+  /// from and reads the replies on.
+  pub fn udp_ephemeral(peer: std::net::SocketAddr) -> std::io::Result<Endpoint> {
+    let bind: std::net::SocketAddr = if peer.is_ipv4() {
+      (std::net::Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+      (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let sock = std::net::UdpSocket::bind(bind)?;
+    sock.set_nonblocking(true)?;
+    Ok(Endpoint::udp(tokio::net::UdpSocket::from_std(sock)?))
+  }
+
+  /// The same socket, sending on `fabric`.
+  pub fn scoped(&self, fabric: messages::FabricId) -> Endpoint {
+    Endpoint { inner: self.inner.clone(), fabric }
+  }
+
+  pub fn fabric(&self) -> messages::FabricId {
+    self.fabric
+  }
+
+  pub async fn send_to(&self, buf: &[u8], addr: std::net::SocketAddr) -> std::io::Result<usize> {
+    std::future::poll_fn(|cx| self.poll_send_to(cx, buf, addr)).await
+  }
+
+  /// Sends `buf` (an encoded message) on this endpoint's fabric. The returned length is `buf`'s, so
+  /// callers tracking partial writes never see the envelope.
+  pub fn poll_send_to(
+    &self,
+    cx: &mut core::task::Context<'_>,
+    buf: &[u8],
+    addr: std::net::SocketAddr,
+  ) -> core::task::Poll<std::io::Result<usize>> {
+    if self.fabric.is_default() {
+      return self.inner.poll_send_to(cx, buf, addr);
+    }
+    let scoped = self.fabric.scope(buf.to_vec()).map_err(|e| std::io::Error::other(e.to_string()))?;
+    self.inner.poll_send_to(cx, &scoped, addr).map_ok(|_| buf.len())
+  }
+
+  pub async fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, std::net::SocketAddr)> {
+    std::future::poll_fn(|cx| {
+      let mut read_buf = tokio::io::ReadBuf::new(&mut *buf);
+      self.inner.poll_recv_from(cx, &mut read_buf).map_ok(|from| (read_buf.filled().len(), from))
+    }).await
+  }
+
+  pub fn local_ip_facing(&self, peer: std::net::IpAddr) -> Option<std::net::IpAddr> {
+    self.inner.local_ip_facing(peer)
+  }
+
+  /// A fresh ephemeral endpoint (see [`Datagram::open_ephemeral`]) on this endpoint's fabric.
+  pub fn open_ephemeral(&self, peer: std::net::SocketAddr) -> std::io::Result<Endpoint> {
+    Ok(self.inner.open_ephemeral(peer)?.scoped(self.fabric))
+  }
+}
@@ -0,0 +1,145 @@
+//! Recording a terminal session as an asciicast (format version 2), which `asciinema play` and the
+//! asciinema web player replay. The file is a JSON header line followed by one
+//! `[seconds, code, data]` line per event: `"o"` for what was drawn at each flush, as the escape
+//! sequences a real terminal would have been sent, `"i"` for what was typed and `"r"` for a resize
+//! (`"COLSxROWS"`).
+
+use std::io::Write;
+
+use crossterm::{cursor, terminal, QueueableCommand};
+
+use super::{modifiers, Terminal, TtyEvent};
+
+/// An asciicast being written.
+pub struct Cast {
+  out: std::sync::Mutex<std::io::BufWriter<std::fs::File>>,
+  started: std::time::Instant,
+}
+
+impl Cast {
+  /// Start recording a `size` (cols, rows) terminal at `path`, replacing any file there.
+  pub fn create(path: &std::path::Path, size: (u16, u16)) -> std::io::Result<Cast> {
+    let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let header = serde_json::json!({
+      "version": 2,
+      "width": size.0,
+      "height": size.1,
+      "timestamp": crate::sys_utils::epoch_seconds_now_utc0(),
+      "env": { "TERM": "xterm-256color" },
+    });
+    writeln!(out, "{header}")?;
+    out.flush()?;
+    Ok(Cast { out: std::sync::Mutex::new(out), started: std::time::Instant::now() })
+  }
+
+  /// Append an event, stamped with the time since the recording started. Each is written through, so
+  /// a session that ends abruptly still leaves a playable file.
+  fn event(&self, code: &str, data: &str) {
+    if let Ok(mut out) = self.out.lock() {
+      let line = serde_json::json!([self.started.elapsed().as_secs_f64(), code, data]);
+      let _ = writeln!(out, "{line}");
+      let _ = out.flush();
+    }
+  }
+}
+
+/// A terminal that records into a [`Cast`] everything drawn on it and every event it hands out.
+pub struct Recording {
+  inner: Box<dyn Terminal>,
+  cast: Cast,
+  /// Escape sequences and text queued since the last flush.
+  pending: std::sync::Mutex<Vec<u8>>,
+}
+
+impl Recording {
+  pub fn new(inner: Box<dyn Terminal>, cast: Cast) -> Self {
+    Recording { inner, cast, pending: std::sync::Mutex::new(Vec::new()) }
+  }
+
+  fn record(&self, queue: impl FnOnce(&mut Vec<u8>) -> std::io::Result<()>) {
+    if let Ok(mut pending) = self.pending.lock() {
+      let _ = queue(&mut pending);
+    }
+  }
+}
+
+impl Terminal for Recording {
+  fn print(&self, s: &str) {
+    self.inner.print(s);
+    self.record(|o| o.write_all(s.as_bytes()));
+  }
+  fn move_to(&self, col: u16, row: u16) {
+    self.inner.move_to(col, row);
+    self.record(|o| o.queue(cursor::MoveTo(col, row)).map(drop));
+  }
+  fn clear(&self) {
+    self.inner.clear();
+    self.record(|o| o.queue(terminal::Clear(terminal::ClearType::All)).map(drop));
+  }
+  fn style(&self, fg: i32, bg: i32, attrs: i32) {
+    self.inner.style(fg, bg, attrs);
+    self.record(|o| super::queue_style(o, fg, bg, attrs));
+  }
+  fn set_modes(&self, wanted: u32) {
+    // Mouse reporting, paste and the alternate screen don't change what a replay shows.
+    self.inner.set_modes(wanted);
+  }
+  fn flush(&self) {
+    self.inner.flush();
+    let drawn = match self.pending.lock() {
+      Ok(mut pending) => std::mem::take(&mut *pending),
+      Err(_) => return,
+    };
+    if !drawn.is_empty() {
+      self.cast.event("o", &String::from_utf8_lossy(&drawn));
+    }
+  }
+  fn size(&self) -> (u16, u16) {
+    self.inner.size()
+  }
+  fn note_input(&self, ev: &TtyEvent) {
+    self.inner.note_input(ev);
+    match ev {
+      TtyEvent::Resize(cols, rows) => self.cast.event("r", &format!("{cols}x{rows}")),
+      ev => {
+        if let Some(text) = typed(ev) {
+          self.cast.event("i", &text);
+        }
+      }
+    }
+  }
+}
+
+/// What a plain terminal would have sent a program for `ev`, for an `"i"` event; `None` for events
+/// with no such bytes, like the mouse.
+pub fn typed(ev: &TtyEvent) -> Option<String> {
+  let text = match ev {
+    TtyEvent::Char(c) => return Some(c.to_string()),
+    TtyEvent::Paste(text) => return Some(text.clone()),
+    TtyEvent::Modified(modifiers::CTRL, key) => match key.as_ref() {
+      TtyEvent::Char(c) if c.is_ascii_alphabetic() => return Some(((c.to_ascii_lowercase() as u8 - b'a' + 1) as char).to_string()),
+      key => return typed(key),
+    },
+    TtyEvent::Modified(mods, key) if mods & modifiers::ALT != 0 => return typed(key).map(|text| format!("\x1b{text}")),
+    TtyEvent::Modified(_, key) => return typed(key),
+    TtyEvent::Enter => "\r",
+    TtyEvent::Backspace => "\x7f",
+    TtyEvent::Tab => "\t",
+    TtyEvent::BackTab => "\x1b[Z",
+    TtyEvent::Esc => "\x1b",
+    TtyEvent::CtrlC => "\x03",
+    TtyEvent::CtrlD => "\x04",
+    TtyEvent::Up => "\x1b[A",
+    TtyEvent::Down => "\x1b[B",
+    TtyEvent::Right => "\x1b[C",
+    TtyEvent::Left => "\x1b[D",
+    TtyEvent::Home => "\x1b[H",
+    TtyEvent::End => "\x1b[F",
+    TtyEvent::PageUp => "\x1b[5~",
+    TtyEvent::PageDown => "\x1b[6~",
+    TtyEvent::Delete => "\x1b[3~",
+    TtyEvent::Insert => "\x1b[2~",
+    TtyEvent::F(_) | TtyEvent::Mouse(_) | TtyEvent::Resize(..) => return None,
+  };
+  Some(text.to_string())
+}
@@ -0,0 +1,336 @@
+//! A terminal with no terminal behind it: output is drawn into an in-memory grid of cells, and input
+//! comes from whoever holds the sending side - a test, or a script (`run-local --tty-script`).
+//!
+//! A script has one step per line; blank lines and lines starting with `#` are skipped:
+//!
+//! ```text
+//! type hello world        each character, spaces included, as a key press
+//! key enter               a named key: enter, backspace, tab, backtab, esc, up, down, left, right,
+//!                         home, end, pageup, pagedown, delete, insert, f1-f12, or a single character;
+//!                         prefix ctrl+, alt+ and/or shift+ to hold them (key ctrl+c, key alt+left)
+//! paste some text         a bracketed paste
+//! resize 100x30           the terminal changes size
+//! mouse down left 10 5    a mouse action (down, up, drag, move, scroll-up, scroll-down,
+//!                         scroll-left, scroll-right), button (none, left, right, middle), col, row
+//! wait 250                pause this many milliseconds before the next step
+//! ```
+
+use std::sync::Arc;
+
+use super::{modifiers, mouse_action, mouse_button, MouseEvent, Terminal, TtyEvent, TtyHandle};
+
+/// One character cell and how it is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+  pub ch: char,
+  /// Colours as given to `host::tty_style` (-1 is the terminal default).
+  pub fg: i32,
+  pub bg: i32,
+  /// [`super::attrs`] bits.
+  pub attrs: i32,
+}
+
+impl Default for Cell {
+  fn default() -> Self {
+    Cell { ch: ' ', fg: -1, bg: -1, attrs: 0 }
+  }
+}
+
+/// A grid of cells with a cursor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screen {
+  cols: u16,
+  rows: u16,
+  cells: Vec<Cell>,
+  cursor: (u16, u16),
+}
+
+impl Screen {
+  pub fn new(cols: u16, rows: u16) -> Screen {
+    Screen { cols, rows, cells: vec![Cell::default(); cols as usize * rows as usize], cursor: (0, 0) }
+  }
+
+  /// (cols, rows).
+  pub fn size(&self) -> (u16, u16) {
+    (self.cols, self.rows)
+  }
+
+  /// The 0-based (col, row) the next character goes to.
+  pub fn cursor(&self) -> (u16, u16) {
+    self.cursor
+  }
+
+  pub fn cell(&self, col: u16, row: u16) -> Option<&Cell> {
+    (col < self.cols && row < self.rows).then(|| &self.cells[row as usize * self.cols as usize + col as usize])
+  }
+
+  /// The characters of `row`, without trailing blanks.
+  pub fn row_text(&self, row: u16) -> String {
+    let text: String = (0..self.cols).filter_map(|col| self.cell(col, row)).map(|c| c.ch).collect();
+    text.trim_end().to_string()
+  }
+
+  /// Every row's [`row_text`](Self::row_text), one line each - what `--tty-dump` writes.
+  pub fn text(&self) -> String {
+    (0..self.rows).map(|row| self.row_text(row) + "\n").collect()
+  }
+
+  /// Change size, keeping what fits and clamping the cursor.
+  fn resize(&mut self, cols: u16, rows: u16) {
+    let mut resized = Screen::new(cols, rows);
+    for row in 0..rows.min(self.rows) {
+      for col in 0..cols.min(self.cols) {
+        resized.cells[row as usize * cols as usize + col as usize] = self.cells[row as usize * self.cols as usize + col as usize];
+      }
+    }
+    resized.cursor = (self.cursor.0.min(cols.saturating_sub(1)), self.cursor.1.min(rows.saturating_sub(1)));
+    *self = resized;
+  }
+
+  /// Move the cursor down a row, scrolling everything up a row from the bottom one.
+  fn line_feed(&mut self) {
+    if self.cursor.1 + 1 < self.rows {
+      self.cursor.1 += 1;
+    } else if self.rows > 0 {
+      self.cells.drain(..self.cols as usize);
+      self.cells.extend(std::iter::repeat_n(Cell::default(), self.cols as usize));
+    }
+  }
+
+  /// Draw text at the cursor as a terminal in raw mode does: `\r` returns to column 0, `\n` only moves
+  /// down, and a character past the last column wraps onto the next row.
+  fn print(&mut self, s: &str, pen: Cell) {
+    for ch in s.chars() {
+      match ch {
+        '\r' => self.cursor.0 = 0,
+        '\n' => self.line_feed(),
+        ch if ch.is_control() => {}
+        ch => {
+          if self.cursor.0 >= self.cols {
+            self.cursor.0 = 0;
+            self.line_feed();
+          }
+          if let Some(cell) = self.cells.get_mut(self.cursor.1 as usize * self.cols as usize + self.cursor.0 as usize) {
+            *cell = Cell { ch, ..pen };
+          }
+          self.cursor.0 += 1;
+        }
+      }
+    }
+  }
+}
+
+/// What a headless terminal draws into, and what it shows as of its last flush. A [`super::remote`]
+/// terminal keeps one as a mirror of what it has sent, to repaint the user's screen from.
+pub(super) struct HeadlessTerminal {
+  drawing: std::sync::Mutex<(Screen, Cell)>,
+  shown: Arc<std::sync::Mutex<Screen>>,
+  /// The [`super::modes`] last asked for.
+  modes: std::sync::atomic::AtomicU32,
+}
+
+impl HeadlessTerminal {
+  pub(super) fn new(size: (u16, u16)) -> HeadlessTerminal {
+    HeadlessTerminal {
+      drawing: std::sync::Mutex::new((Screen::new(size.0, size.1), Cell::default())),
+      shown: Arc::new(std::sync::Mutex::new(Screen::new(size.0, size.1))),
+      modes: std::sync::atomic::AtomicU32::new(0),
+    }
+  }
+
+  /// The screen drawn so far (flushed or not), the pen the next text is drawn with, and the modes.
+  pub(super) fn drawn(&self) -> (Screen, Cell, u32) {
+    let (screen, pen) = self.drawing.lock().map(|d| d.clone()).unwrap_or_else(|e| e.into_inner().clone());
+    (screen, pen, self.modes.load(std::sync::atomic::Ordering::Relaxed))
+  }
+
+  fn draw(&self, f: impl FnOnce(&mut Screen, &mut Cell)) {
+    if let Ok(mut drawing) = self.drawing.lock() {
+      let (screen, pen) = &mut *drawing;
+      f(screen, pen);
+    }
+  }
+}
+
+impl Terminal for HeadlessTerminal {
+  fn print(&self, s: &str) {
+    self.draw(|screen, pen| screen.print(s, *pen));
+  }
+  fn move_to(&self, col: u16, row: u16) {
+    self.draw(|screen, _| screen.cursor = (col.min(screen.cols.saturating_sub(1)), row.min(screen.rows.saturating_sub(1))));
+  }
+  fn clear(&self) {
+    self.draw(|screen, _| screen.cells.fill(Cell::default()));
+  }
+  fn style(&self, fg: i32, bg: i32, attrs: i32) {
+    self.draw(|_, pen| *pen = Cell { ch: ' ', fg, bg, attrs });
+  }
+  fn set_modes(&self, wanted: u32) {
+    self.modes.store(wanted, std::sync::atomic::Ordering::Relaxed);
+  }
+  fn flush(&self) {
+    if let (Ok(drawing), Ok(mut shown)) = (self.drawing.lock(), self.shown.lock()) {
+      *shown = drawing.0.clone();
+    }
+  }
+  fn size(&self) -> (u16, u16) {
+    self.drawing.lock().map(|d| d.0.size()).unwrap_or((80, 24))
+  }
+  fn note_input(&self, ev: &TtyEvent) {
+    if let TtyEvent::Resize(cols, rows) = *ev {
+      self.draw(|screen, _| screen.resize(cols, rows));
+      if let Ok(mut shown) = self.shown.lock() {
+        shown.resize(cols, rows);
+      }
+    }
+  }
+}
+
+/// Read access to a headless terminal's screen.
+#[derive(Clone)]
+pub struct View(Arc<std::sync::Mutex<Screen>>);
+
+impl View {
+  /// The screen as of the program's last flush.
+  pub fn screen(&self) -> Screen {
+    self.0.lock().map(|s| s.clone()).unwrap_or_else(|e| e.into_inner().clone())
+  }
+}
+
+/// A headless terminal of `size` (cols, rows), recorded as an asciicast at `record` when given.
+/// Returns the handle for the executor, the sender its input events go in by, and a [`View`] of its
+/// screen.
+pub fn headless(size: (u16, u16), record: Option<&std::path::Path>) -> std::io::Result<(Arc<TtyHandle>, tokio::sync::mpsc::UnboundedSender<TtyEvent>, View)> {
+  let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+  let terminal = HeadlessTerminal::new(size);
+  let shown = terminal.shown.clone();
+  let terminal: Box<dyn Terminal> = match record {
+    Some(path) => Box::new(super::cast::Recording::new(Box::new(terminal), super::cast::Cast::create(path, size)?)),
+    None => Box::new(terminal),
+  };
+  Ok((Arc::new(TtyHandle::new(rx, terminal)), tx, View(shown)))
+}
+
+/// One step of an input script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+  Event(TtyEvent),
+  Wait(std::time::Duration),
+}
+
+/// Parse an input script (see the module docs). Errors name the line.
+pub fn parse_script(text: &str) -> Result<Vec<Step>, String> {
+  let mut steps = Vec::new();
+  for (n, line) in text.lines().enumerate() {
+    let trimmed = line.trim_start();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+      continue;
+    }
+    let (command, rest) = trimmed.split_once(' ').unwrap_or((trimmed, ""));
+    let bad = |what: &str| format!("line {}: {what}: {line:?}", n + 1);
+    match command {
+      "type" => steps.extend(rest.chars().map(|c| Step::Event(TtyEvent::Char(c)))),
+      "key" => steps.push(Step::Event(parse_key(rest.trim()).ok_or_else(|| bad("unknown key"))?)),
+      "paste" => steps.push(Step::Event(TtyEvent::Paste(rest.to_string()))),
+      "resize" => {
+        let size = rest.trim().split_once('x').and_then(|(c, r)| Some((c.parse().ok()?, r.parse().ok()?)));
+        let (cols, rows) = size.ok_or_else(|| bad("expected resize COLSxROWS"))?;
+        steps.push(Step::Event(TtyEvent::Resize(cols, rows)));
+      }
+      "mouse" => steps.push(Step::Event(parse_mouse(rest).ok_or_else(|| bad("expected mouse ACTION BUTTON COL ROW"))?)),
+      "wait" => {
+        let ms: u64 = rest.trim().parse().map_err(|_| bad("expected wait MILLISECONDS"))?;
+        steps.push(Step::Wait(std::time::Duration::from_millis(ms)));
+      }
+      _ => return Err(bad("unknown step")),
+    }
+  }
+  Ok(steps)
+}
+
+/// A key as `key` names it: `enter`, `f5`, `x`, `ctrl+c`, `alt+shift+left`, ...
+fn parse_key(name: &str) -> Option<TtyEvent> {
+  let mut parts: Vec<&str> = name.split('+').collect();
+  // `key +` and `key ctrl++` name the plus key itself.
+  if name.ends_with('+') {
+    parts.truncate(parts.len().saturating_sub(2));
+    parts.push("+");
+  }
+  let (key, mods) = parts.split_last()?;
+  let mut bits = 0;
+  for m in mods {
+    bits |= match m.to_ascii_lowercase().as_str() {
+      "ctrl" => modifiers::CTRL,
+      "alt" => modifiers::ALT,
+      "shift" => modifiers::SHIFT,
+      _ => return None,
+    };
+  }
+  let mut chars = key.chars();
+  let key = match (chars.next(), chars.next()) {
+    (Some(c), None) => TtyEvent::Char(c),
+    _ => match key.to_ascii_lowercase().as_str() {
+      "enter" => TtyEvent::Enter,
+      "backspace" => TtyEvent::Backspace,
+      "tab" => TtyEvent::Tab,
+      "backtab" => TtyEvent::BackTab,
+      "esc" => TtyEvent::Esc,
+      "space" => TtyEvent::Char(' '),
+      "up" => TtyEvent::Up,
+      "down" => TtyEvent::Down,
+      "left" => TtyEvent::Left,
+      "right" => TtyEvent::Right,
+      "home" => TtyEvent::Home,
+      "end" => TtyEvent::End,
+      "pageup" => TtyEvent::PageUp,
+      "pagedown" => TtyEvent::PageDown,
+      "delete" => TtyEvent::Delete,
+      "insert" => TtyEvent::Insert,
+      f => TtyEvent::F(f.strip_prefix('f')?.parse().ok().filter(|n| (1..=12).contains(n))?),
+    },
+  };
+  Some(match (bits, key) {
+    (0, key) => key,
+    // As the real terminal reports them (see `super::map_event`).
+    (modifiers::CTRL, TtyEvent::Char('c')) => TtyEvent::CtrlC,
+    (modifiers::CTRL, TtyEvent::Char('d')) => TtyEvent::CtrlD,
+    (bits, key) => TtyEvent::Modified(bits, Box::new(key)),
+  })
+}
+
+fn parse_mouse(rest: &str) -> Option<TtyEvent> {
+  let [action, button, col, row] = rest.split_whitespace().collect::<Vec<_>>()[..] else { return None };
+  let action = match action {
+    "down" => mouse_action::DOWN,
+    "up" => mouse_action::UP,
+    "drag" => mouse_action::DRAG,
+    "move" => mouse_action::MOVE,
+    "scroll-up" => mouse_action::SCROLL_UP,
+    "scroll-down" => mouse_action::SCROLL_DOWN,
+    "scroll-left" => mouse_action::SCROLL_LEFT,
+    "scroll-right" => mouse_action::SCROLL_RIGHT,
+    _ => return None,
+  };
+  let button = match button {
+    "none" => mouse_button::NONE,
+    "left" => mouse_button::LEFT,
+    "right" => mouse_button::RIGHT,
+    "middle" => mouse_button::MIDDLE,
+    _ => return None,
+  };
+  Some(TtyEvent::Mouse(MouseEvent { action, button, col: col.parse().ok()?, row: row.parse().ok()?, modifiers: 0 }))
+}
+
+/// Send `steps`' events to `input` in order, pausing at each wait.
+pub async fn play(steps: Vec<Step>, input: &tokio::sync::mpsc::UnboundedSender<TtyEvent>) {
+  for step in steps {
+    match step {
+      Step::Event(ev) => {
+        if input.send(ev).is_err() {
+          return;
+        }
+      }
+      Step::Wait(pause) => tokio::time::sleep(pause).await,
+    }
+  }
+}
@@ -0,0 +1,919 @@
+//! A cross-platform interactive terminal driver, exposed to WASI programs through the `host::tty_*`
+//! imports. It is the host-side half of "a program can be a real UI": crossterm gives us raw mode,
+//! structured key events, and styled/positioned output on Windows (incl. legacy consoles via Virtual
+//! Terminal), macOS, and Linux with no C dependencies.
+//!
+//! A launcher (e.g. the `chat` command) calls [`attach`] once, holds the returned [`TtyGuard`] for
+//! the session (its Drop restores the terminal even if the guest traps), and hands the [`TtyHandle`]
+//! to the executor. The executor's `host::tty_*` callbacks then drive the terminal on the guest's
+//! behalf. Output is structured (move / clear / style / print) rather than raw ANSI, so it renders
+//! identically across shells; input arrives as decoded events, not raw termios bytes.
+//!
+//! A program run with `run --tty` on another node gets a [`remote`] terminal instead: its output is
+//! recorded as [`draw_op`]s and streamed back to the user, whose input is forwarded to it. Tests and
+//! `run-local --tty-script` use a [`headless`] one, and any session can be recorded as an asciicast
+//! ([`cast`]).
+
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crossterm::{cursor, event, style, terminal, QueueableCommand};
+
+pub mod cast;
+pub mod headless;
+
+/// A single decoded terminal input event. Encoded for the guest by [`encode_event`] (protocol 1) or
+/// [`encode_event_v2`] (protocol 2, see [`PROTOCOL_V2`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TtyEvent {
+  Char(char),
+  Enter,
+  Backspace,
+  Left,
+  Right,
+  Up,
+  Down,
+  CtrlC,
+  CtrlD,
+  Esc,
+  Resize(u16, u16),
+  Tab,
+  /// Shift+Tab.
+  BackTab,
+  Home,
+  End,
+  PageUp,
+  PageDown,
+  Delete,
+  Insert,
+  /// F1-F12 (as 1-12).
+  F(u8),
+  /// `event` with Alt, Ctrl and/or Shift held ([`modifiers`] bits). Shift on a character is already in
+  /// the character, so a plain `Char('A')` is never wrapped for it.
+  Modified(u8, Box<TtyEvent>),
+  /// Text pasted while bracketed paste is on ([`modes::PASTE`]), in one piece.
+  Paste(String),
+  /// A click, release, drag or scroll while mouse reporting is on ([`modes::MOUSE`]).
+  Mouse(MouseEvent),
+}
+
+/// Mouse activity at a 0-based (col, row), with the modifiers held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+  /// A [`mouse_action`] tag.
+  pub action: u8,
+  /// A [`mouse_button`] tag; [`mouse_button::NONE`] for scrolls and plain moves.
+  pub button: u8,
+  pub col: u16,
+  pub row: u16,
+  /// [`modifiers`] bits.
+  pub modifiers: u8,
+}
+
+/// Event kind tags in the wire encoding handed to the guest via `host::tty_next_event`. Mirror any
+/// change in the guest decoder (example-programs/chat.c).
+pub mod event_kind {
+  pub const CHAR: u8 = 1; // followed by the character's UTF-8 bytes
+  pub const ENTER: u8 = 2;
+  pub const BACKSPACE: u8 = 3;
+  pub const LEFT: u8 = 4;
+  pub const RIGHT: u8 = 5;
+  pub const UP: u8 = 6;
+  pub const DOWN: u8 = 7;
+  pub const CTRL_C: u8 = 8;
+  pub const CTRL_D: u8 = 9;
+  pub const ESC: u8 = 10;
+  pub const RESIZE: u8 = 11; // followed by cols(u16 LE), rows(u16 LE)
+  // Protocol 2 only:
+  pub const TAB: u8 = 12;
+  pub const BACK_TAB: u8 = 13;
+  pub const HOME: u8 = 14;
+  pub const END: u8 = 15;
+  pub const PAGE_UP: u8 = 16;
+  pub const PAGE_DOWN: u8 = 17;
+  pub const DELETE: u8 = 18;
+  pub const INSERT: u8 = 19;
+  pub const FUNCTION: u8 = 20; // followed by the key number, 1-12 (u8)
+  pub const PASTE: u8 = 21; // followed by the pasted UTF-8 text
+  pub const MOUSE: u8 = 22; // followed by action(u8), button(u8), col(u16 LE), row(u16 LE)
+}
+
+/// Modifier bits of a protocol 2 event.
+pub mod modifiers {
+  pub const SHIFT: u8 = 1;
+  pub const ALT: u8 = 2;
+  pub const CTRL: u8 = 4;
+}
+
+/// [`MouseEvent::action`] tags.
+pub mod mouse_action {
+  pub const DOWN: u8 = 1;
+  pub const UP: u8 = 2;
+  pub const DRAG: u8 = 3;
+  pub const MOVE: u8 = 4;
+  pub const SCROLL_UP: u8 = 5;
+  pub const SCROLL_DOWN: u8 = 6;
+  pub const SCROLL_LEFT: u8 = 7;
+  pub const SCROLL_RIGHT: u8 = 8;
+}
+
+/// [`MouseEvent::button`] tags.
+pub mod mouse_button {
+  pub const NONE: u8 = 0;
+  pub const LEFT: u8 = 1;
+  pub const RIGHT: u8 = 2;
+  pub const MIDDLE: u8 = 3;
+}
+
+/// Terminal modes for `host::tty_modes` (see [`Terminal::set_modes`]).
+pub mod modes {
+  /// Report clicks, drags and the scroll wheel as [`super::TtyEvent::Mouse`]. Off by default: while
+  /// it is on, the terminal's own text selection usually needs Shift held.
+  pub const MOUSE: u32 = 1;
+  /// Bracketed paste: pasted text arrives as one [`super::TtyEvent::Paste`] instead of keystrokes.
+  pub const PASTE: u32 = 2;
+  /// Draw on the alternate screen, leaving the shell's scrollback untouched. On by default.
+  pub const ALTERNATE_SCREEN: u32 = 4;
+  /// What [`super::attach`] starts with.
+  pub const DEFAULT: u32 = ALTERNATE_SCREEN;
+}
+
+/// The original encoding, which every guest gets until it asks for another with `host::tty_protocol`.
+pub const PROTOCOL_V1: u8 = 1;
+/// Protocol 2: every event is `[PROTOCOL_V2][kind][modifiers][payload...]`, covering every
+/// [`TtyEvent`]. The leading byte lets a decoder tell which layout it is reading.
+pub const PROTOCOL_V2: u8 = 2;
+/// The newest protocol this host speaks.
+pub const PROTOCOL_LATEST: u8 = PROTOCOL_V2;
+
+/// Encode an event as `[kind][payload...]` for the guest. Char carries UTF-8; Resize carries
+/// cols,rows as little-endian u16s; everything else is a single tag byte. This is protocol 1: a
+/// modified key encodes as the key alone (as it always has), and events protocol 1 has no tag for
+/// encode as nothing, which the host reports as no input.
+pub fn encode_event(ev: &TtyEvent) -> Vec<u8> {
+  let mut out = Vec::with_capacity(6);
+  match ev {
+    TtyEvent::Char(c) => {
+      out.push(event_kind::CHAR);
+      let mut b = [0u8; 4];
+      out.extend_from_slice(c.encode_utf8(&mut b).as_bytes());
+    }
+    TtyEvent::Enter => out.push(event_kind::ENTER),
+    TtyEvent::Backspace => out.push(event_kind::BACKSPACE),
+    TtyEvent::Left => out.push(event_kind::LEFT),
+    TtyEvent::Right => out.push(event_kind::RIGHT),
+    TtyEvent::Up => out.push(event_kind::UP),
+    TtyEvent::Down => out.push(event_kind::DOWN),
+    TtyEvent::CtrlC => out.push(event_kind::CTRL_C),
+    TtyEvent::CtrlD => out.push(event_kind::CTRL_D),
+    TtyEvent::Esc => out.push(event_kind::ESC),
+    TtyEvent::Resize(c, r) => {
+      out.push(event_kind::RESIZE);
+      out.extend_from_slice(&c.to_le_bytes());
+      out.extend_from_slice(&r.to_le_bytes());
+    }
+    TtyEvent::Modified(_, inner) => return encode_event(inner),
+    _ => {}
+  }
+  out
+}
+
+/// Encode an event in protocol 2: `[PROTOCOL_V2][kind][modifiers][payload...]`. Payloads are as in
+/// protocol 1, plus FUNCTION's key number, PASTE's text and MOUSE's action, button, col and row.
+/// Ctrl+C and Ctrl+D keep their own kinds (with the CTRL bit set).
+pub fn encode_event_v2(ev: &TtyEvent) -> Vec<u8> {
+  let (mods, ev) = match ev {
+    TtyEvent::Modified(mods, inner) => (*mods, inner.as_ref()),
+    TtyEvent::CtrlC | TtyEvent::CtrlD => (modifiers::CTRL, ev),
+    TtyEvent::Mouse(m) => (m.modifiers, ev),
+    _ => (0, ev),
+  };
+  let kind = match ev {
+    TtyEvent::Tab => event_kind::TAB,
+    TtyEvent::BackTab => event_kind::BACK_TAB,
+    TtyEvent::Home => event_kind::HOME,
+    TtyEvent::End => event_kind::END,
+    TtyEvent::PageUp => event_kind::PAGE_UP,
+    TtyEvent::PageDown => event_kind::PAGE_DOWN,
+    TtyEvent::Delete => event_kind::DELETE,
+    TtyEvent::Insert => event_kind::INSERT,
+    TtyEvent::F(_) => event_kind::FUNCTION,
+    TtyEvent::Paste(_) => event_kind::PASTE,
+    TtyEvent::Mouse(_) => event_kind::MOUSE,
+    // A wrapped wrapper; flatten it rather than nest modifier bytes.
+    TtyEvent::Modified(inner_mods, inner) => return encode_event_v2(&TtyEvent::Modified(mods | inner_mods, inner.clone())),
+    legacy => {
+      let v1 = encode_event(legacy);
+      let mut out = vec![PROTOCOL_V2, v1[0], mods];
+      out.extend_from_slice(&v1[1..]);
+      return out;
+    }
+  };
+  let mut out = vec![PROTOCOL_V2, kind, mods];
+  match ev {
+    TtyEvent::F(n) => out.push(*n),
+    TtyEvent::Paste(text) => out.extend_from_slice(text.as_bytes()),
+    TtyEvent::Mouse(m) => {
+      out.push(m.action);
+      out.push(m.button);
+      out.extend_from_slice(&m.col.to_le_bytes());
+      out.extend_from_slice(&m.row.to_le_bytes());
+    }
+    _ => {}
+  }
+  out
+}
+
+/// Encode `ev` in `protocol` for a guest buffer of `cap` bytes. A paste too long for the buffer is
+/// cut at a character boundary, so the guest never sees half a character.
+pub fn encode_event_for(ev: &TtyEvent, protocol: u8, cap: usize) -> Vec<u8> {
+  if protocol < PROTOCOL_V2 {
+    return encode_event(ev);
+  }
+  let mut out = encode_event_v2(ev);
+  if let TtyEvent::Paste(text) = ev && out.len() > cap {
+    let header = out.len() - text.len();
+    let mut end = cap.saturating_sub(header).min(text.len());
+    while !text.is_char_boundary(end) {
+      end -= 1;
+    }
+    out.truncate(header + end);
+  }
+  out
+}
+
+/// Decode a protocol 2 event (as made by [`encode_event_v2`]); `None` if `bytes` isn't one. This is
+/// how input forwarded from a remote terminal (`run --tty`) is read back.
+pub fn decode_event_v2(bytes: &[u8]) -> Option<TtyEvent> {
+  let [PROTOCOL_V2, kind, mods, payload @ ..] = bytes else { return None };
+  let u16_at = |i: usize| payload.get(i..i + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+  let ev = match *kind {
+    event_kind::CHAR => {
+      let mut chars = std::str::from_utf8(payload).ok()?.chars();
+      let c = chars.next()?;
+      if chars.next().is_some() {
+        return None;
+      }
+      TtyEvent::Char(c)
+    }
+    event_kind::ENTER => TtyEvent::Enter,
+    event_kind::BACKSPACE => TtyEvent::Backspace,
+    event_kind::LEFT => TtyEvent::Left,
+    event_kind::RIGHT => TtyEvent::Right,
+    event_kind::UP => TtyEvent::Up,
+    event_kind::DOWN => TtyEvent::Down,
+    event_kind::CTRL_C if *mods == modifiers::CTRL => return Some(TtyEvent::CtrlC),
+    event_kind::CTRL_D if *mods == modifiers::CTRL => return Some(TtyEvent::CtrlD),
+    event_kind::ESC => TtyEvent::Esc,
+    event_kind::RESIZE => TtyEvent::Resize(u16_at(0)?, u16_at(2)?),
+    event_kind::TAB => TtyEvent::Tab,
+    event_kind::BACK_TAB => TtyEvent::BackTab,
+    event_kind::HOME => TtyEvent::Home,
+    event_kind::END => TtyEvent::End,
+    event_kind::PAGE_UP => TtyEvent::PageUp,
+    event_kind::PAGE_DOWN => TtyEvent::PageDown,
+    event_kind::DELETE => TtyEvent::Delete,
+    event_kind::INSERT => TtyEvent::Insert,
+    event_kind::FUNCTION => TtyEvent::F(*payload.first()?),
+    event_kind::PASTE => return Some(TtyEvent::Paste(String::from_utf8(payload.to_vec()).ok()?)),
+    event_kind::MOUSE => return Some(TtyEvent::Mouse(MouseEvent {
+      action: *payload.first()?,
+      button: *payload.get(1)?,
+      col: u16_at(2)?,
+      row: u16_at(4)?,
+      modifiers: *mods,
+    })),
+    _ => return None,
+  };
+  Some(if *mods == 0 { ev } else { TtyEvent::Modified(*mods, Box::new(ev)) })
+}
+
+/// Where a [`TtyHandle`]'s output goes: the local terminal ([`attach`]) or, for a program run with
+/// `run --tty` on another node, a stream of draw messages back to the user ([`remote`]).
+pub trait Terminal: Send + Sync {
+  /// Queue text at the cursor (call [`flush`](Self::flush) to present it).
+  fn print(&self, s: &str);
+  /// Move the cursor to a 0-based (col, row).
+  fn move_to(&self, col: u16, row: u16);
+  /// Clear the whole screen.
+  fn clear(&self);
+  /// Set foreground/background colour (see [`color`]) and attributes, every bit of `attrs` that is
+  /// set ([`attrs`]); 0 is plain text.
+  fn style(&self, fg: i32, bg: i32, attrs: i32);
+  /// Switch mouse reporting, bracketed paste and the alternate screen on or off ([`modes`] bits).
+  fn set_modes(&self, wanted: u32);
+  /// Present everything queued since the last flush.
+  fn flush(&self);
+  /// Current (cols, rows).
+  fn size(&self) -> (u16, u16);
+  /// Called with each event as it is handed to the program, for terminals that follow the input (a
+  /// headless screen taking a resize, a recording).
+  fn note_input(&self, _ev: &TtyEvent) {}
+}
+
+/// Handle to a terminal, shared into the executor: input events arrive on a channel, and output goes
+/// to its [`Terminal`] (which the handle derefs to).
+pub struct TtyHandle {
+  rx: tokio::sync::Mutex<tokio::sync::mpsc::UnboundedReceiver<TtyEvent>>,
+  terminal: Box<dyn Terminal>,
+}
+
+impl TtyHandle {
+  /// A handle reading input from `rx` and drawing on `terminal`.
+  pub fn new(rx: tokio::sync::mpsc::UnboundedReceiver<TtyEvent>, terminal: Box<dyn Terminal>) -> Self {
+    Self { rx: tokio::sync::Mutex::new(rx), terminal }
+  }
+
+  /// Await the next input event, up to `timeout`. `None` means the timeout elapsed (or the input
+  /// side ended) - the guest should treat that as "no input this tick" and loop.
+  pub async fn next_event(&self, timeout: std::time::Duration) -> Option<TtyEvent> {
+    let mut rx = self.rx.lock().await;
+    let ev = tokio::time::timeout(timeout, rx.recv()).await.ok().flatten();
+    if let Some(ev) = &ev {
+      self.terminal.note_input(ev);
+    }
+    ev
+  }
+}
+
+impl std::ops::Deref for TtyHandle {
+  type Target = dyn Terminal;
+  fn deref(&self) -> &Self::Target {
+    self.terminal.as_ref()
+  }
+}
+
+/// The local terminal, through a buffered, mutex-guarded stdout.
+struct CrosstermTerminal {
+  out: std::sync::Mutex<std::io::BufWriter<std::io::Stdout>>,
+  /// The [`modes`] in effect.
+  modes: std::sync::Mutex<u32>,
+}
+
+impl Terminal for CrosstermTerminal {
+  fn print(&self, s: &str) {
+    if let Ok(mut o) = self.out.lock() {
+      let _ = o.queue(style::Print(s));
+    }
+  }
+  fn move_to(&self, col: u16, row: u16) {
+    if let Ok(mut o) = self.out.lock() {
+      let _ = o.queue(cursor::MoveTo(col, row));
+    }
+  }
+  fn clear(&self) {
+    if let Ok(mut o) = self.out.lock() {
+      let _ = o.queue(terminal::Clear(terminal::ClearType::All));
+    }
+  }
+  fn style(&self, fg: i32, bg: i32, attrs: i32) {
+    if let Ok(mut o) = self.out.lock() {
+      let _ = queue_style(&mut *o, fg, bg, attrs);
+    }
+  }
+  fn set_modes(&self, wanted: u32) {
+    if let (Ok(mut o), Ok(mut current)) = (self.out.lock(), self.modes.lock()) {
+      let changed = wanted ^ *current;
+      if changed & modes::ALTERNATE_SCREEN != 0 {
+        let _ = if wanted & modes::ALTERNATE_SCREEN != 0 { o.queue(terminal::EnterAlternateScreen) } else { o.queue(terminal::LeaveAlternateScreen) };
+      }
+      if changed & modes::MOUSE != 0 {
+        let _ = if wanted & modes::MOUSE != 0 { o.queue(event::EnableMouseCapture) } else { o.queue(event::DisableMouseCapture) };
+      }
+      if changed & modes::PASTE != 0 {
+        let _ = if wanted & modes::PASTE != 0 { o.queue(event::EnableBracketedPaste) } else { o.queue(event::DisableBracketedPaste) };
+      }
+      let _ = o.flush();
+      *current = wanted & (modes::MOUSE | modes::PASTE | modes::ALTERNATE_SCREEN);
+    }
+  }
+  fn flush(&self) {
+    if let Ok(mut o) = self.out.lock() {
+      let _ = o.flush();
+    }
+  }
+  /// (80, 24) if the size can't be determined.
+  fn size(&self) -> (u16, u16) {
+    terminal::size().unwrap_or((80, 24))
+  }
+}
+
+/// Draw op tags in the compact encoding a remote terminal streams back (`TtyDraw` messages). Each op
+/// is its tag followed by its arguments, integers little-endian.
+pub mod draw_op {
+  pub const PRINT: u8 = 1; // followed by len(u32), then that many bytes of UTF-8
+  pub const MOVE_TO: u8 = 2; // followed by col(u16), row(u16)
+  pub const CLEAR: u8 = 3;
+  pub const STYLE: u8 = 4; // followed by fg(i32), bg(i32), attrs(i32)
+  pub const MODES: u8 = 5; // followed by the modes bits (u32)
+  /// Starts a batch that repaints the whole screen (it and the rest of its flush), sent when the user
+  /// asks with `TtyRedraw` after missing a batch.
+  pub const FRAME: u8 = 6;
+}
+
+/// Largest batch of draw ops sent as one message; a flush with more queued is split across several.
+pub const DRAW_BATCH_BYTES: usize = 16 * 1024;
+
+/// Draw ops as they are queued, in the [`draw_op`] encoding.
+#[derive(Default)]
+struct DrawOps(std::sync::Mutex<Vec<u8>>);
+
+impl DrawOps {
+  fn push(&self, op: &[u8]) {
+    if let Ok(mut ops) = self.0.lock() {
+      ops.extend_from_slice(op);
+    }
+  }
+  fn take(&self) -> Vec<u8> {
+    self.0.lock().map(|mut ops| std::mem::take(&mut *ops)).unwrap_or_default()
+  }
+  fn print(&self, s: &str) {
+    // Long text goes as several ops, so no single op outgrows a batch.
+    let mut rest = s;
+    while !rest.is_empty() {
+      let mut end = rest.len().min(DRAW_BATCH_BYTES - 5);
+      while !rest.is_char_boundary(end) {
+        end -= 1;
+      }
+      let mut op = vec![draw_op::PRINT];
+      op.extend_from_slice(&(end as u32).to_le_bytes());
+      op.extend_from_slice(&rest.as_bytes()[..end]);
+      self.push(&op);
+      rest = &rest[end..];
+    }
+  }
+  fn move_to(&self, col: u16, row: u16) {
+    let [c0, c1] = col.to_le_bytes();
+    let [r0, r1] = row.to_le_bytes();
+    self.push(&[draw_op::MOVE_TO, c0, c1, r0, r1]);
+  }
+  fn clear(&self) {
+    self.push(&[draw_op::CLEAR]);
+  }
+  fn style(&self, fg: i32, bg: i32, attrs: i32) {
+    let mut op = vec![draw_op::STYLE];
+    for v in [fg, bg, attrs] {
+      op.extend_from_slice(&v.to_le_bytes());
+    }
+    self.push(&op);
+  }
+  fn modes(&self, wanted: u32) {
+    let mut op = vec![draw_op::MODES];
+    op.extend_from_slice(&wanted.to_le_bytes());
+    self.push(&op);
+  }
+}
+
+/// What a remote terminal has sent: the channel its batches go out on, and a headless mirror of the
+/// screen they draw, to repaint the user's screen from when a batch goes missing.
+struct Sent {
+  batches: tokio::sync::mpsc::UnboundedSender<Vec<u8>>,
+  mirror: headless::HeadlessTerminal,
+}
+
+impl Sent {
+  /// Draw `ops` on the mirror and send them.
+  fn send(&self, ops: &[u8]) {
+    apply_draw_ops(ops, &self.mirror);
+    self.send_batches(ops);
+  }
+
+  /// Send a [`draw_op::FRAME`] repainting the screen as the mirror has it: every cell that isn't
+  /// blank, then the cursor, pen and modes.
+  fn send_frame(&self) {
+    let (screen, pen, modes) = self.mirror.drawn();
+    let ops = DrawOps::default();
+    ops.push(&[draw_op::FRAME]);
+    ops.modes(modes);
+    ops.style(-1, -1, 0);
+    ops.clear();
+    let (cols, rows) = screen.size();
+    let blank = headless::Cell::default();
+    for row in 0..rows {
+      let cells: Vec<headless::Cell> = (0..cols).filter_map(|col| screen.cell(col, row).copied()).collect();
+      let Some(last) = cells.iter().rposition(|cell| *cell != blank) else { continue };
+      ops.move_to(0, row);
+      for run in cells[..=last].chunk_by(|a, b| (a.fg, a.bg, a.attrs) == (b.fg, b.bg, b.attrs)) {
+        ops.style(run[0].fg, run[0].bg, run[0].attrs);
+        ops.print(&run.iter().map(|cell| cell.ch).collect::<String>());
+      }
+    }
+    let (col, row) = screen.cursor();
+    ops.move_to(col, row);
+    ops.style(pen.fg, pen.bg, pen.attrs);
+    self.send_batches(&ops.take());
+  }
+
+  /// Send `ops` in batches of at most [`DRAW_BATCH_BYTES`], cut at op boundaries only.
+  fn send_batches(&self, ops: &[u8]) {
+    let mut at = 0;
+    while at < ops.len() {
+      let start = at;
+      while at < ops.len() {
+        let Some(len) = draw_op_len(&ops[at..]) else { return };
+        if at > start && at + len - start > DRAW_BATCH_BYTES {
+          break;
+        }
+        at += len;
+      }
+      let _ = self.batches.send(ops[start..at].to_vec());
+    }
+  }
+}
+
+/// A terminal on another node, drawn through draw op batches (see [`draw_op`]) that [`flush`]
+/// hands to whoever relays them to the user.
+///
+/// [`flush`]: Terminal::flush
+struct RemoteTerminal {
+  pending: DrawOps,
+  /// Locked for a whole flush or frame, so batches go out in the order the mirror drew them.
+  sent: Arc<std::sync::Mutex<Sent>>,
+  size: Arc<std::sync::Mutex<(u16, u16)>>,
+}
+
+impl Terminal for RemoteTerminal {
+  fn print(&self, s: &str) {
+    self.pending.print(s);
+  }
+  fn move_to(&self, col: u16, row: u16) {
+    self.pending.move_to(col, row);
+  }
+  fn clear(&self) {
+    self.pending.clear();
+  }
+  fn style(&self, fg: i32, bg: i32, attrs: i32) {
+    self.pending.style(fg, bg, attrs);
+  }
+  fn set_modes(&self, wanted: u32) {
+    self.pending.modes(wanted);
+    self.flush();
+  }
+  fn flush(&self) {
+    if let Ok(sent) = self.sent.lock() {
+      sent.send(&self.pending.take());
+    }
+  }
+  fn size(&self) -> (u16, u16) {
+    self.size.lock().map(|s| *s).unwrap_or((80, 24))
+  }
+}
+
+/// Length of the op at the start of `ops`, tag included; `None` if it is unknown or cut short.
+fn draw_op_len(ops: &[u8]) -> Option<usize> {
+  let len = match *ops.first()? {
+    draw_op::PRINT => 5 + u32::from_le_bytes(ops.get(1..5)?.try_into().ok()?) as usize,
+    draw_op::MOVE_TO => 5,
+    draw_op::CLEAR => 1,
+    draw_op::STYLE => 13,
+    draw_op::MODES => 5,
+    draw_op::FRAME => 1,
+    _ => return None,
+  };
+  (ops.len() >= len).then_some(len)
+}
+
+/// Replay a batch of draw ops (as streamed by a remote terminal) on `terminal`, then flush it.
+/// Stops at the first op it can't read, returning `false`.
+pub fn apply_draw_ops(ops: &[u8], terminal: &dyn Terminal) -> bool {
+  let i32_at = |op: &[u8], i: usize| i32::from_le_bytes([op[i], op[i + 1], op[i + 2], op[i + 3]]);
+  let mut rest = ops;
+  let mut ok = true;
+  while !rest.is_empty() {
+    let Some(len) = draw_op_len(rest) else {
+      ok = false;
+      break;
+    };
+    let op = &rest[..len];
+    match op[0] {
+      draw_op::PRINT => match std::str::from_utf8(&op[5..]) {
+        Ok(text) => terminal.print(text),
+        Err(_) => {
+          ok = false;
+          break;
+        }
+      },
+      draw_op::MOVE_TO => terminal.move_to(u16::from_le_bytes([op[1], op[2]]), u16::from_le_bytes([op[3], op[4]])),
+      draw_op::CLEAR => terminal.clear(),
+      draw_op::STYLE => terminal.style(i32_at(op, 1), i32_at(op, 5), i32_at(op, 9)),
+      draw_op::MODES => terminal.set_modes(i32_at(op, 1) as u32),
+      _ => {} // FRAME only marks the batch
+    }
+    rest = &rest[len..];
+  }
+  terminal.flush();
+  ok
+}
+
+/// The input side of a [`remote`] terminal: events forwarded from the user's terminal go in here.
+#[derive(Clone)]
+pub struct RemoteInput {
+  events: tokio::sync::mpsc::UnboundedSender<TtyEvent>,
+  size: Arc<std::sync::Mutex<(u16, u16)>>,
+  sent: Arc<std::sync::Mutex<Sent>>,
+}
+
+impl RemoteInput {
+  /// Deliver `ev` to the program; a resize also changes what [`Terminal::size`] reports, and the
+  /// size of the mirror a redraw is painted from.
+  pub fn feed(&self, ev: TtyEvent) {
+    if let TtyEvent::Resize(cols, rows) = ev {
+      if let Ok(mut size) = self.size.lock() {
+        *size = (cols, rows);
+      }
+      if let Ok(sent) = self.sent.lock() {
+        sent.mirror.note_input(&ev);
+      }
+    }
+    let _ = self.events.send(ev);
+  }
+
+  /// Send the user a [`draw_op::FRAME`] repainting the whole screen, after everything already sent.
+  pub fn redraw(&self) {
+    if let Ok(sent) = self.sent.lock() {
+      sent.send_frame();
+    }
+  }
+}
+
+/// Orders the draw batches a `run --tty` client receives: each is drawn once and in sequence, and
+/// after a gap (a batch lost, or overtaken by a later one) nothing more is drawn until a
+/// [`draw_op::FRAME`] repaints the screen.
+#[derive(Debug, Default)]
+pub struct DrawSequence {
+  next_seq: u64,
+  awaiting_frame: bool,
+}
+
+impl DrawSequence {
+  /// Whether to draw batch `seq` (holding `ops`) now.
+  pub fn admit(&mut self, seq: u64, ops: &[u8]) -> bool {
+    if seq < self.next_seq {
+      return false;
+    }
+    if ops.first() == Some(&draw_op::FRAME) || (seq == self.next_seq && !self.awaiting_frame) {
+      self.next_seq = seq + 1;
+      self.awaiting_frame = false;
+      return true;
+    }
+    self.awaiting_frame = true;
+    false
+  }
+
+  /// Whether a batch went missing and the screen stays as it is until a frame repaints it (ask for
+  /// one with `TtyRedraw`).
+  pub fn awaiting_frame(&self) -> bool {
+    self.awaiting_frame
+  }
+}
+
+/// A terminal for a program whose user is on another node, starting at `size`. Returns the handle
+/// for the executor, the input side to feed forwarded events into, and the draw op batches to relay
+/// back to the user (see [`apply_draw_ops`]).
+pub fn remote(size: (u16, u16)) -> (Arc<TtyHandle>, RemoteInput, tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>) {
+  let (events, rx) = tokio::sync::mpsc::unbounded_channel();
+  let (batches, batches_rx) = tokio::sync::mpsc::unbounded_channel();
+  let sent = Arc::new(std::sync::Mutex::new(Sent { batches, mirror: headless::HeadlessTerminal::new(size) }));
+  let size = Arc::new(std::sync::Mutex::new(size));
+  let terminal = RemoteTerminal { pending: DrawOps::default(), sent: sent.clone(), size: size.clone() };
+  (Arc::new(TtyHandle::new(rx, Box::new(terminal))), RemoteInput { events, size, sent }, batches_rx)
+}
+
+/// Attribute bits of `host::tty_style`; any combination applies.
+pub mod attrs {
+  pub const BOLD: i32 = 1;
+  pub const UNDERLINE: i32 = 2;
+  pub const REVERSE: i32 = 4;
+  pub const ITALIC: i32 = 8;
+  pub const DIM: i32 = 16;
+  pub const STRIKETHROUGH: i32 = 32;
+  pub const BLINK: i32 = 64;
+}
+
+const ATTRIBUTES: [(i32, style::Attribute); 7] = [
+  (attrs::BOLD, style::Attribute::Bold),
+  (attrs::UNDERLINE, style::Attribute::Underlined),
+  (attrs::REVERSE, style::Attribute::Reverse),
+  (attrs::ITALIC, style::Attribute::Italic),
+  (attrs::DIM, style::Attribute::Dim),
+  (attrs::STRIKETHROUGH, style::Attribute::CrossedOut),
+  (attrs::BLINK, style::Attribute::SlowBlink),
+];
+
+/// Queue the escape sequences for [`Terminal::style`] on `o`.
+fn queue_style(o: &mut impl Write, fg: i32, bg: i32, attrs: i32) -> std::io::Result<()> {
+  // SGR reset clears colours too, so it goes first.
+  o.queue(style::SetAttribute(style::Attribute::Reset))?;
+  o.queue(style::SetForegroundColor(color(fg)))?;
+  o.queue(style::SetBackgroundColor(color(bg)))?;
+  for (bit, attr) in ATTRIBUTES {
+    if attrs & bit != 0 {
+      o.queue(style::SetAttribute(attr))?;
+    }
+  }
+  Ok(())
+}
+
+/// Flag marking a `host::tty_style` colour as RGB: `RGB_FLAG | 0xRRGGBB`.
+pub const RGB_FLAG: i32 = 0x0100_0000;
+
+/// Map a `host::tty_style` colour to a crossterm colour: 0-15 are the ANSI colours, 16-255 the rest
+/// of the 256-colour palette, `RGB_FLAG | 0xRRGGBB` a 24-bit colour, and anything else (-1 by
+/// convention) the terminal's default.
+pub fn color(c: i32) -> style::Color {
+  match c {
+    0..=15 => ansi_color(c),
+    16..=255 => style::Color::AnsiValue(c as u8),
+    _ if c & !0x00ff_ffff == RGB_FLAG => style::Color::Rgb { r: (c >> 16) as u8, g: (c >> 8) as u8, b: c as u8 },
+    _ => style::Color::Reset,
+  }
+}
+
+/// Map an ANSI colour index (0-15) to a crossterm colour; anything else resets to the default.
+fn ansi_color(c: i32) -> style::Color {
+  match c {
+    0 => style::Color::Black,
+    1 => style::Color::DarkRed,
+    2 => style::Color::DarkGreen,
+    3 => style::Color::DarkYellow,
+    4 => style::Color::DarkBlue,
+    5 => style::Color::DarkMagenta,
+    6 => style::Color::DarkCyan,
+    7 => style::Color::Grey,
+    8 => style::Color::DarkGrey,
+    9 => style::Color::Red,
+    10 => style::Color::Green,
+    11 => style::Color::Yellow,
+    12 => style::Color::Blue,
+    13 => style::Color::Magenta,
+    14 => style::Color::Cyan,
+    15 => style::Color::White,
+    _ => style::Color::Reset,
+  }
+}
+
+/// Restores the terminal (mouse reporting and bracketed paste off, plain text, leave alternate screen,
+/// show cursor, disable raw mode) and stops the input
+/// thread when dropped - even on a panic or a guest trap, so the user's shell is never left wedged.
+pub struct TtyGuard {
+  stop: Arc<AtomicBool>,
+  reader: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Drop for TtyGuard {
+  fn drop(&mut self) {
+    self.stop.store(true, Ordering::SeqCst);
+    if let Some(h) = self.reader.take() {
+      let _ = h.join();
+    }
+    let mut out = std::io::stdout();
+    let _ = out.queue(event::DisableMouseCapture);
+    let _ = out.queue(event::DisableBracketedPaste);
+    let _ = out.queue(style::SetAttribute(style::Attribute::Reset));
+    let _ = out.queue(cursor::Show);
+    let _ = out.queue(terminal::LeaveAlternateScreen);
+    let _ = out.flush();
+    let _ = terminal::disable_raw_mode();
+  }
+}
+
+/// Attach to the controlling terminal: enable raw mode, switch to the alternate screen, and spawn a
+/// blocking thread that decodes crossterm events onto a channel. Returns the shared [`TtyHandle`] and
+/// a [`TtyGuard`] the caller must keep alive for the session. Fails if there is no usable terminal
+/// (e.g. stdin/stdout is a pipe), in which case the caller should fall back to non-interactive output.
+pub fn attach() -> std::io::Result<(Arc<TtyHandle>, TtyGuard)> {
+  attach_recorded(None)
+}
+
+/// [`attach`], also recording the session as an asciicast at `record` when given (see [`cast`]).
+pub fn attach_recorded(record: Option<&std::path::Path>) -> std::io::Result<(Arc<TtyHandle>, TtyGuard)> {
+  let cast = match record {
+    Some(path) => Some(cast::Cast::create(path, terminal::size().unwrap_or((80, 24)))?),
+    None => None,
+  };
+  terminal::enable_raw_mode()?;
+  {
+    let mut out = std::io::stdout();
+    out.queue(terminal::EnterAlternateScreen)?;
+    out.queue(cursor::Hide)?;
+    out.flush()?;
+  }
+
+  let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+  let stop = Arc::new(AtomicBool::new(false));
+  let reader = {
+    let stop = stop.clone();
+    std::thread::spawn(move || {
+      // Poll so we can notice the stop flag instead of blocking forever in read().
+      let tick = std::time::Duration::from_millis(50);
+      while !stop.load(Ordering::SeqCst) {
+        match event::poll(tick) {
+          Ok(true) => match event::read() {
+            Ok(ev) => {
+              if let Some(mapped) = map_event(ev) {
+                if tx.send(mapped).is_err() {
+                  break; // receiver gone
+                }
+              }
+            }
+            Err(_) => break,
+          },
+          Ok(false) => {}   // no event this tick
+          Err(_) => break,  // input error
+        }
+      }
+    })
+  };
+
+  let terminal = CrosstermTerminal {
+    out: std::sync::Mutex::new(std::io::BufWriter::new(std::io::stdout())),
+    modes: std::sync::Mutex::new(modes::DEFAULT),
+  };
+  let terminal: Box<dyn Terminal> = match cast {
+    Some(cast) => Box::new(cast::Recording::new(Box::new(terminal), cast)),
+    None => Box::new(terminal),
+  };
+  let handle = Arc::new(TtyHandle::new(rx, terminal));
+  Ok((handle, TtyGuard { stop, reader: Some(reader) }))
+}
+
+/// Translate a crossterm event into a [`TtyEvent`], dropping events we don't model (focus changes,
+/// key releases, keys with no tag such as Caps Lock or F13+).
+pub fn map_event(ev: event::Event) -> Option<TtyEvent> {
+  use event::{Event, KeyCode, KeyEvent};
+  match ev {
+    Event::Resize(c, r) => Some(TtyEvent::Resize(c, r)),
+    Event::Paste(text) => Some(TtyEvent::Paste(text)),
+    Event::Mouse(m) => map_mouse(m),
+    Event::Key(KeyEvent { code, modifiers, kind, .. }) => {
+      // On Windows key events fire for both press and release; only act on presses.
+      if kind != event::KeyEventKind::Press && kind != event::KeyEventKind::Repeat {
+        return None;
+      }
+      let mut mods = modifier_bits(modifiers);
+      let key = match code {
+        KeyCode::Char('c') if mods == modifiers::CTRL => return Some(TtyEvent::CtrlC),
+        KeyCode::Char('d') if mods == modifiers::CTRL => return Some(TtyEvent::CtrlD),
+        KeyCode::Char(c) => {
+          // Shift is already in the character's case (or its shifted symbol).
+          mods &= !modifiers::SHIFT;
+          TtyEvent::Char(c)
+        }
+        KeyCode::Enter => TtyEvent::Enter,
+        KeyCode::Backspace => TtyEvent::Backspace,
+        KeyCode::Left => TtyEvent::Left,
+        KeyCode::Right => TtyEvent::Right,
+        KeyCode::Up => TtyEvent::Up,
+        KeyCode::Down => TtyEvent::Down,
+        KeyCode::Esc => TtyEvent::Esc,
+        KeyCode::Tab => TtyEvent::Tab,
+        KeyCode::BackTab => {
+          mods &= !modifiers::SHIFT;
+          TtyEvent::BackTab
+        }
+        KeyCode::Home => TtyEvent::Home,
+        KeyCode::End => TtyEvent::End,
+        KeyCode::PageUp => TtyEvent::PageUp,
+        KeyCode::PageDown => TtyEvent::PageDown,
+        KeyCode::Delete => TtyEvent::Delete,
+        KeyCode::Insert => TtyEvent::Insert,
+        KeyCode::F(n @ 1..=12) => TtyEvent::F(n),
+        _ => return None,
+      };
+      Some(if mods == 0 { key } else { TtyEvent::Modified(mods, Box::new(key)) })
+    }
+    _ => None,
+  }
+}
+
+fn modifier_bits(m: event::KeyModifiers) -> u8 {
+  use event::KeyModifiers;
+  let mut bits = 0;
+  for (flag, bit) in [(KeyModifiers::SHIFT, modifiers::SHIFT), (KeyModifiers::ALT, modifiers::ALT), (KeyModifiers::CONTROL, modifiers::CTRL)] {
+    if m.contains(flag) {
+      bits |= bit;
+    }
+  }
+  bits
+}
+
+fn map_mouse(m: event::MouseEvent) -> Option<TtyEvent> {
+  use event::{MouseButton, MouseEventKind};
+  let button = |b: MouseButton| match b {
+    MouseButton::Left => mouse_button::LEFT,
+    MouseButton::Right => mouse_button::RIGHT,
+    MouseButton::Middle => mouse_button::MIDDLE,
+  };
+  let (action, button) = match m.kind {
+    MouseEventKind::Down(b) => (mouse_action::DOWN, button(b)),
+    MouseEventKind::Up(b) => (mouse_action::UP, button(b)),
+    MouseEventKind::Drag(b) => (mouse_action::DRAG, button(b)),
+    MouseEventKind::Moved => (mouse_action::MOVE, mouse_button::NONE),
+    MouseEventKind::ScrollUp => (mouse_action::SCROLL_UP, mouse_button::NONE),
+    MouseEventKind::ScrollDown => (mouse_action::SCROLL_DOWN, mouse_button::NONE),
+    MouseEventKind::ScrollLeft => (mouse_action::SCROLL_LEFT, mouse_button::NONE),
+    MouseEventKind::ScrollRight => (mouse_action::SCROLL_RIGHT, mouse_button::NONE),
+  };
+  Some(TtyEvent::Mouse(MouseEvent { action, button, col: m.column, row: m.row, modifiers: modifier_bits(m.modifiers) }))
+}